@@ -0,0 +1,217 @@
+//! Components for sharing an AES engine and for the AES syscall driver.
+//!
+//! The mux virtualizes the hardware engine, so that kernel users (802.15.4
+//! link-layer security through a `MuxAES128CCM`, for instance) and the
+//! userspace driver can use it concurrently. ECB mode is only available once
+//! `enable_ecb()` was called on the mux, which requires the hardware to
+//! implement it.
+//!
+//! The driver component gives the driver its own virtual engine, and an
+//! AES-GCM layer on another one. CCM is available to processes when a CCM
+//! implementation is passed with `with_ccm()`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let aes_mux = components::aes::AesMuxComponent::new(&peripherals.aes)
+//!     .finalize(components::aes_mux_component_helper!(sam4l::aes::Aes));
+//! aes_mux.enable_ecb();
+//!
+//! let ccm_aes = components::aes::VirtualAesComponent::new(aes_mux)
+//!     .finalize(components::virtual_aes_component_helper!(sam4l::aes::Aes));
+//!
+//! let aes = components::aes::AesDriverComponent::new(board_kernel, aes_mux)
+//!     .with_ccm(user_ccm)
+//!     .finalize(components::aes_driver_component_helper!(sam4l::aes::Aes));
+//! ```
+
+use capsules::aes::AesDriver;
+use capsules::aes_gcm::AesGcm;
+use capsules::virtual_aes::{MuxAES128, VirtualAES128};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, CCMClient, AES128, AES128CBC, AES128CCM, AES128GCM,
+};
+use kernel::static_init_half;
+
+/// The size of the kernel buffers of the driver and of its GCM layer, which
+/// bounds the length of CCM and GCM messages.
+pub const AES_DRIVER_BUF_LEN: usize = 256;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! aes_mux_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::virtual_aes::MuxAES128;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<MuxAES128<'static, $A>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct AesMuxComponent<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> {
+    aes: &'static A,
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> AesMuxComponent<A> {
+    pub fn new(aes: &'static A) -> AesMuxComponent<A> {
+        AesMuxComponent { aes }
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> Component for AesMuxComponent<A> {
+    type StaticInput = &'static mut MaybeUninit<MuxAES128<'static, A>>;
+    type Output = &'static MuxAES128<'static, A>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let aes_mux = static_init_half!(s, MuxAES128<'static, A>, MuxAES128::new(self.aes));
+        self.aes.set_client(aes_mux);
+
+        aes_mux
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! virtual_aes_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::virtual_aes::VirtualAES128;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualAES128<'static, $A>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct VirtualAesComponent<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> {
+    aes_mux: &'static MuxAES128<'static, A>,
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> VirtualAesComponent<A> {
+    pub fn new(aes_mux: &'static MuxAES128<'static, A>) -> VirtualAesComponent<A> {
+        VirtualAesComponent { aes_mux }
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> Component for VirtualAesComponent<A> {
+    type StaticInput = &'static mut MaybeUninit<VirtualAES128<'static, A>>;
+    type Output = &'static VirtualAES128<'static, A>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let aes = static_init_half!(
+            s,
+            VirtualAES128<'static, A>,
+            VirtualAES128::new(self.aes_mux)
+        );
+        aes.setup();
+
+        aes
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! aes_driver_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::aes::AesDriver;
+        use capsules::aes_gcm::AesGcm;
+        use capsules::virtual_aes::VirtualAES128;
+        use core::mem::MaybeUninit;
+        use $crate::aes::AES_DRIVER_BUF_LEN;
+        static mut BUF1: MaybeUninit<VirtualAES128<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualAES128<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<AesGcm<'static, VirtualAES128<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<AesDriver<VirtualAES128<'static, $A>>> = MaybeUninit::uninit();
+        static mut GCM_BUF: [u8; AES_DRIVER_BUF_LEN] = [0; AES_DRIVER_BUF_LEN];
+        static mut DRIVER_BUF: [u8; AES_DRIVER_BUF_LEN] = [0; AES_DRIVER_BUF_LEN];
+        (
+            &mut BUF1,
+            &mut BUF2,
+            &mut BUF3,
+            &mut BUF4,
+            &mut GCM_BUF,
+            &mut DRIVER_BUF,
+        )
+    };};
+}
+
+pub struct AesDriverComponent<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> {
+    board_kernel: &'static kernel::Kernel,
+    aes_mux: &'static MuxAES128<'static, A>,
+    ccm: Option<&'static dyn AES128CCM<'static>>,
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> AesDriverComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        aes_mux: &'static MuxAES128<'static, A>,
+    ) -> AesDriverComponent<A> {
+        AesDriverComponent {
+            board_kernel,
+            aes_mux,
+            ccm: None,
+        }
+    }
+
+    /// Gives processes access to CCM through `ccm`, which should not be
+    /// used by other clients.
+    pub fn with_ccm(mut self, ccm: &'static dyn AES128CCM<'static>) -> AesDriverComponent<A> {
+        self.ccm = Some(ccm);
+        self
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC> Component for AesDriverComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualAES128<'static, A>>,
+        &'static mut MaybeUninit<VirtualAES128<'static, A>>,
+        &'static mut MaybeUninit<AesGcm<'static, VirtualAES128<'static, A>>>,
+        &'static mut MaybeUninit<AesDriver<VirtualAES128<'static, A>>>,
+        &'static mut [u8; AES_DRIVER_BUF_LEN],
+        &'static mut [u8; AES_DRIVER_BUF_LEN],
+    );
+    type Output = &'static AesDriver<VirtualAES128<'static, A>>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver_aes = static_init_half!(
+            s.0,
+            VirtualAES128<'static, A>,
+            VirtualAES128::new(self.aes_mux)
+        );
+        driver_aes.setup();
+
+        let gcm_aes = static_init_half!(
+            s.1,
+            VirtualAES128<'static, A>,
+            VirtualAES128::new(self.aes_mux)
+        );
+        gcm_aes.setup();
+
+        let gcm = static_init_half!(
+            s.2,
+            AesGcm<'static, VirtualAES128<'static, A>>,
+            AesGcm::new(gcm_aes, s.4)
+        );
+        gcm_aes.set_client(gcm);
+
+        let aes = static_init_half!(
+            s.3,
+            AesDriver<VirtualAES128<'static, A>>,
+            AesDriver::new(driver_aes, s.5, self.board_kernel.create_grant(&grant_cap))
+        );
+        driver_aes.set_client(aes);
+        AES128GCM::set_client(gcm, aes);
+        aes.set_gcm(gcm);
+        if let Some(ccm) = self.ccm {
+            ccm.set_client(aes as &dyn CCMClient);
+            aes.set_ccm(ccm);
+        }
+
+        aes
+    }
+}
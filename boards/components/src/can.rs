@@ -0,0 +1,96 @@
+//! Components for CAN controllers and the CAN syscall driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let can_mux = components::can::CanMuxComponent::new(&base_peripherals.can1, 500_000)
+//!     .finalize(());
+//! let can = components::can::CanDriverComponent::new(
+//!     board_kernel,
+//!     can_mux,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules::can::CanDriver;
+use capsules::virtual_can::{CanDevice, MuxCan};
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::can;
+use kernel::static_init;
+
+pub struct CanMuxComponent {
+    can: &'static dyn can::Can<'static>,
+    bitrate: u32,
+}
+
+impl CanMuxComponent {
+    pub fn new(can: &'static dyn can::Can<'static>, bitrate: u32) -> CanMuxComponent {
+        CanMuxComponent { can, bitrate }
+    }
+}
+
+impl Component for CanMuxComponent {
+    type StaticInput = ();
+    type Output = &'static MuxCan<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let can_mux = static_init!(MuxCan<'static>, MuxCan::new(self.can));
+        self.can.set_client(can_mux);
+        if can_mux.set_bitrate(self.bitrate) != kernel::ReturnCode::SUCCESS {
+            panic!("unsupported CAN bit rate {}", self.bitrate);
+        }
+        can_mux
+    }
+}
+
+pub struct CanDriverComponent {
+    board_kernel: &'static kernel::Kernel,
+    can_mux: &'static MuxCan<'static>,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl CanDriverComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        can_mux: &'static MuxCan<'static>,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> CanDriverComponent {
+        CanDriverComponent {
+            board_kernel,
+            can_mux,
+            deferred_caller,
+        }
+    }
+}
+
+impl Component for CanDriverComponent {
+    type StaticInput = ();
+    type Output = &'static CanDriver<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let can_device = static_init!(CanDevice<'static>, CanDevice::new(self.can_mux));
+        can_device.add_to_mux();
+        let can_driver = static_init!(
+            CanDriver<'static>,
+            CanDriver::new(
+                can_device,
+                self.board_kernel.create_grant(&grant_cap),
+                self.deferred_caller,
+            )
+        );
+        can_driver.initialize_callback_handle(
+            self.deferred_caller
+                .register(can_driver)
+                .expect("no deferred call slot available for CAN driver"),
+        );
+        can::Can::set_client(can_device, can_driver);
+
+        can_driver
+    }
+}
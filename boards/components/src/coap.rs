@@ -0,0 +1,171 @@
+//! Component for the CoAP endpoint and its userspace driver.
+//!
+//! The endpoint binds the CoAP port through the UDP port table set up by
+//! `UDPMuxComponent`. Its message IDs and tokens are seeded from `rng`,
+//! whose client the endpoint becomes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) = ...;
+//! let coap_driver = components::coap::CoapComponent::new(
+//!     board_kernel,
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//!     coap_rng,
+//! )
+//! .finalize(components::coap_component_helper!(sam4l::ast::Ast));
+//! ```
+//!
+//! Like `UDPDriverComponent`, the endpoint sends through the UDP mux of the
+//! 6LoWPAN stack unless the type of another IPv6 sender follows the alarm in
+//! the helper.
+
+use capsules::net::coap::endpoint::{CoapEndpoint, COAP_PORT, MAX_MESSAGE_LEN};
+use capsules::net::coap::CoapDriver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::{create_capability, static_init, static_init_half};
+
+static mut TX_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+static mut REQUEST_BUF: [u8; MAX_MESSAGE_LEN] = [0; MAX_MESSAGE_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_component_helper {
+    ($A:ty, $T:ty $(,)?) => {{
+        use capsules::net::coap::endpoint::CoapEndpoint;
+        use capsules::net::coap::CoapDriver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<UDPSendStruct<'static, $T>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<CoapEndpoint<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+    ($A:ty $(,)?) => {{
+        $crate::coap_component_helper!(
+            $A,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                'static,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        )
+    };};
+}
+
+pub struct CoapComponent<A: Alarm<'static> + 'static, T: IP6Sender<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    udp_send_mux: &'static MuxUdpSender<'static, T>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Rng<'static>,
+}
+
+impl<A: Alarm<'static> + 'static, T: IP6Sender<'static>> CoapComponent<A, T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        udp_send_mux: &'static MuxUdpSender<'static, T>,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Rng<'static>,
+    ) -> CoapComponent<A, T> {
+        CoapComponent {
+            board_kernel,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+            rng,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static, T: IP6Sender<'static>> Component for CoapComponent<A, T> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UDPSendStruct<'static, T>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<CoapEndpoint<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static CoapDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let udp_send = static_init_half!(
+            static_buffer.0,
+            UDPSendStruct<'static, T>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+        self.udp_recv_mux.add_client(udp_recv);
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .expect("no UDP socket available for CoAP");
+        match self.port_table.bind(socket, COAP_PORT, net_cap) {
+            Ok((send_binding, recv_binding)) => {
+                udp_send.set_binding(send_binding);
+                udp_recv.set_binding(recv_binding);
+            }
+            Err(_socket) => panic!("CoAP port already bound"),
+        }
+
+        let coap_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let endpoint = static_init_half!(
+            static_buffer.2,
+            CoapEndpoint<'static, VirtualMuxAlarm<'static, A>>,
+            CoapEndpoint::new(udp_send, coap_alarm, net_cap, &mut TX_BUF, &mut REQUEST_BUF)
+        );
+        udp_send.set_client(endpoint);
+        udp_recv.set_client(endpoint);
+        coap_alarm.set_alarm_client(endpoint);
+        self.rng.set_client(endpoint);
+        endpoint.set_rng(self.rng);
+
+        let coap_driver = static_init_half!(
+            static_buffer.3,
+            CoapDriver<'static, VirtualMuxAlarm<'static, A>>,
+            CoapDriver::new(endpoint, self.board_kernel.create_grant(&grant_cap))
+        );
+        endpoint.set_client(coap_driver);
+        endpoint.set_resources(coap_driver);
+
+        coap_driver
+    }
+}
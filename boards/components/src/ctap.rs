@@ -1,7 +1,13 @@
-//! Component for CTAP HID over USB support.
+//! Components for CTAP HID over USB support and the FIDO2 authenticator.
 //!
-//! This provides a component for using the CTAP driver. This allows for
-//! Client to Authenticator Protool Authentication
+//! `CtapComponent` provides the CTAPHID transport, which assembles CTAP
+//! messages from USB HID reports, and the CTAP driver, which lets a process
+//! answer them. This allows for Client to Authenticator Protocol
+//! Authentication.
+//!
+//! `FidoAuthenticatorComponent` adds the in-kernel FIDO2 authenticator,
+//! which then answers the CTAP2 requests and passes the other requests to
+//! the driver.
 //!
 //! Usage
 //! -----
@@ -11,117 +17,287 @@
 //!     "FIDO Key",      // Product
 //!     "Serial No. 5",  // Serial number
 //! ];
-//!     let ctap_send_buffer = static_init!([u8; 64], [0; 64]);
-//!     let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
-//!
-//!     let (ctap, ctap_driver) = components::ctap::CtapComponent::new(
+//!     let (ctap, ctap_transport, ctap_driver) = components::ctap::CtapComponent::new(
 //!         &earlgrey::usbdev::USB,
+//!         mux_alarm,
 //!         0x1337, // My important company
 //!         0x0DEC, // My device name
 //!         strings,
 //!         board_kernel,
-//!         ctap_send_buffer,
-//!         ctap_recv_buffer,
 //!     )
-//!     .finalize(components::usb_ctap_component_helper!(lowrisc::usbdev::Usb));
+//!     .finalize(components::usb_ctap_component_helper!(
+//!         lowrisc::usbdev::Usb,
+//!         lowrisc::timer::RvTimer
+//!     ));
+//!
+//!     let authenticator = components::ctap::FidoAuthenticatorComponent::new(
+//!         ctap_transport,
+//!         mux_alarm,
+//!         fido_rng,
+//!         nonvolatile_storage,
+//!         0x50000, // Storage address of the credentials
+//!         [0; 16], // AAGUID
+//!         &gpio_port[BUTTON_PIN], // Presence button
+//!         ActivationMode::ActiveLow,
+//!     )
+//!     .with_fallback(ctap_driver)
+//!     .finalize(components::fido_authenticator_component_helper!(
+//!         lowrisc::timer::RvTimer
+//!     ));
+//!     authenticator.load();
 //!
 //!     ctap.enable();
 //!     ctap.attach();
+//!     ctap_transport.start();
 //! ```
 
+use capsules::ctap::CtapDriver;
+use capsules::ctaphid::{self, CtapHidTransport, Transport};
+use capsules::fido2::credentials::{RECORD_LEN, STORAGE_LEN};
+use capsules::fido2::Authenticator;
+use capsules::usb::ctap::CtapHid;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil;
+use kernel::hil::gpio::{ActivationMode, FloatingState, InterruptPin};
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
 use kernel::static_init_half;
 
+/// The size of the CTAP message buffer, which bounds the length of requests
+/// and responses.
+pub const CTAP_MESSAGE_BUF_LEN: usize = 1024;
+
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! usb_ctap_component_helper {
-    ($U:ty $(,)?) => {{
+    ($U:ty, $A:ty $(,)?) => {{
+        use capsules::ctap::CtapDriver;
+        use capsules::ctaphid::CtapHidTransport;
+        use capsules::usb::ctap::CtapHid;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
-        static mut BUF1: MaybeUninit<capsules::usb::ctap::CtapHid<'static, $U>> =
-            MaybeUninit::uninit();
-        static mut BUF2: MaybeUninit<
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, $U>>,
+        use $crate::ctap::CTAP_MESSAGE_BUF_LEN;
+        static mut BUF1: MaybeUninit<CtapHid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<
+            CtapHidTransport<'static, CtapHid<'static, $U>, VirtualMuxAlarm<'static, $A>>,
         > = MaybeUninit::uninit();
-        (&mut BUF1, &mut BUF2)
+        static mut BUF4: MaybeUninit<CtapDriver<'static>> = MaybeUninit::uninit();
+        static mut MESSAGE_BUF: [u8; CTAP_MESSAGE_BUF_LEN] = [0; CTAP_MESSAGE_BUF_LEN];
+        static mut SEND_BUF: [u8; 64] = [0; 64];
+        static mut RECV_BUF: [u8; 64] = [0; 64];
+        (
+            &mut BUF1,
+            &mut BUF2,
+            &mut BUF3,
+            &mut BUF4,
+            &mut MESSAGE_BUF,
+            &mut SEND_BUF,
+            &mut RECV_BUF,
+        )
     };};
 }
 
-pub struct CtapComponent<U: 'static + hil::usb::UsbController<'static>> {
+pub struct CtapComponent<U: 'static + hil::usb::UsbController<'static>, A: 'static + Alarm<'static>>
+{
     usb: &'static U,
+    mux_alarm: &'static MuxAlarm<'static, A>,
     vendor_id: u16,
     product_id: u16,
     strings: &'static [&'static str; 3],
     board_kernel: &'static kernel::Kernel,
-    send_buffer: &'static mut [u8; 64],
-    recv_buffer: &'static mut [u8; 64],
 }
 
-impl<U: 'static + hil::usb::UsbController<'static>> CtapComponent<U> {
+impl<U: 'static + hil::usb::UsbController<'static>, A: 'static + Alarm<'static>>
+    CtapComponent<U, A>
+{
     pub fn new(
         usb: &'static U,
+        mux_alarm: &'static MuxAlarm<'static, A>,
         vendor_id: u16,
         product_id: u16,
         strings: &'static [&'static str; 3],
         board_kernel: &'static kernel::Kernel,
-        send_buffer: &'static mut [u8; 64],
-        recv_buffer: &'static mut [u8; 64],
-    ) -> CtapComponent<U> {
+    ) -> CtapComponent<U, A> {
         CtapComponent {
             usb,
+            mux_alarm,
             vendor_id,
             product_id,
             strings,
             board_kernel,
-            send_buffer,
-            recv_buffer,
         }
     }
 }
 
-impl<U: 'static + hil::usb::UsbController<'static>> Component for CtapComponent<U> {
+impl<U: 'static + hil::usb::UsbController<'static>, A: 'static + Alarm<'static>> Component
+    for CtapComponent<U, A>
+{
     type StaticInput = (
-        &'static mut MaybeUninit<capsules::usb::ctap::CtapHid<'static, U>>,
+        &'static mut MaybeUninit<CtapHid<'static, U>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
         &'static mut MaybeUninit<
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
+            CtapHidTransport<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
         >,
+        &'static mut MaybeUninit<CtapDriver<'static>>,
+        &'static mut [u8; CTAP_MESSAGE_BUF_LEN],
+        &'static mut [u8; 64],
+        &'static mut [u8; 64],
     );
     type Output = (
-        &'static capsules::usb::ctap::CtapHid<'static, U>,
-        &'static capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
+        &'static CtapHid<'static, U>,
+        &'static CtapHidTransport<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
+        &'static CtapDriver<'static>,
     );
 
     unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
         let ctap = static_init_half!(
             s.0,
-            capsules::usb::ctap::CtapHid<'static, U>,
-            capsules::usb::ctap::CtapHid::new(
-                self.usb,
-                self.vendor_id,
-                self.product_id,
-                self.strings
-            )
+            CtapHid<'static, U>,
+            CtapHid::new(self.usb, self.vendor_id, self.product_id, self.strings)
         );
         self.usb.set_client(ctap);
 
+        let ctap_alarm = static_init_half!(
+            s.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        let transport = static_init_half!(
+            s.2,
+            CtapHidTransport<'static, CtapHid<'static, U>, VirtualMuxAlarm<'static, A>>,
+            CtapHidTransport::new(ctap, ctap_alarm, s.4, s.5, s.6)
+        );
+        ctap.set_client(transport);
+        ctap_alarm.set_alarm_client(transport);
+
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
 
         let ctap_driver = static_init_half!(
+            s.3,
+            CtapDriver<'static>,
+            CtapDriver::new(transport, self.board_kernel.create_grant(&grant_cap))
+        );
+        transport.set_client(ctap_driver);
+
+        (ctap, transport, ctap_driver)
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! fido_authenticator_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::fido2::credentials::{RECORD_LEN, STORAGE_LEN};
+        use capsules::fido2::Authenticator;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Authenticator<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut TABLE_BUF: [u8; STORAGE_LEN] = [0; STORAGE_LEN];
+        static mut RECORD_BUF: [u8; RECORD_LEN] = [0; RECORD_LEN];
+        (&mut BUF1, &mut BUF2, &mut TABLE_BUF, &mut RECORD_BUF)
+    };};
+}
+
+pub struct FidoAuthenticatorComponent<A: 'static + Alarm<'static>> {
+    transport: &'static dyn Transport<'static>,
+    mux_alarm: &'static MuxAlarm<'static, A>,
+    rng: &'static dyn Rng<'static>,
+    storage: &'static dyn NonvolatileStorage<'static>,
+    storage_address: usize,
+    aaguid: [u8; 16],
+    presence: &'static dyn InterruptPin<'static>,
+    presence_mode: ActivationMode,
+    fallback: Option<&'static dyn ctaphid::Client<'static>>,
+}
+
+impl<A: 'static + Alarm<'static>> FidoAuthenticatorComponent<A> {
+    /// The authenticator takes `rng` and `storage` for itself. The
+    /// credentials take `capsules::fido2::credentials::STORAGE_LEN` bytes
+    /// from `storage_address`. The user confirms presence with the button
+    /// on `presence`, active in `presence_mode`.
+    pub fn new(
+        transport: &'static dyn Transport<'static>,
+        mux_alarm: &'static MuxAlarm<'static, A>,
+        rng: &'static dyn Rng<'static>,
+        storage: &'static dyn NonvolatileStorage<'static>,
+        storage_address: usize,
+        aaguid: [u8; 16],
+        presence: &'static dyn InterruptPin<'static>,
+        presence_mode: ActivationMode,
+    ) -> FidoAuthenticatorComponent<A> {
+        FidoAuthenticatorComponent {
+            transport,
+            mux_alarm,
+            rng,
+            storage,
+            storage_address,
+            aaguid,
+            presence,
+            presence_mode,
+            fallback: None,
+        }
+    }
+
+    /// Passes the requests the authenticator does not handle to `client`.
+    pub fn with_fallback(mut self, client: &'static dyn ctaphid::Client<'static>) -> Self {
+        self.fallback = Some(client);
+        self
+    }
+}
+
+impl<A: 'static + Alarm<'static>> Component for FidoAuthenticatorComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Authenticator<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut [u8; STORAGE_LEN],
+        &'static mut [u8; RECORD_LEN],
+    );
+    type Output = &'static Authenticator<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let alarm = static_init_half!(
+            s.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.mux_alarm)
+        );
+        let authenticator = static_init_half!(
             s.1,
-            capsules::ctap::CtapDriver<'static, capsules::usb::ctap::CtapHid<'static, U>>,
-            capsules::ctap::CtapDriver::new(
-                Some(ctap),
-                self.send_buffer,
-                self.recv_buffer,
-                self.board_kernel.create_grant(&grant_cap),
+            Authenticator<'static, VirtualMuxAlarm<'static, A>>,
+            Authenticator::new(
+                self.transport,
+                alarm,
+                self.rng,
+                self.storage,
+                self.storage_address,
+                self.aaguid,
+                self.presence,
+                self.presence_mode,
+                s.2,
+                s.3
             )
         );
+        alarm.set_alarm_client(authenticator);
+        self.rng.set_client(authenticator);
+        self.storage.set_client(authenticator);
+        self.presence.make_input();
+        self.presence.set_floating_state(match self.presence_mode {
+            ActivationMode::ActiveLow => FloatingState::PullUp,
+            ActivationMode::ActiveHigh => FloatingState::PullDown,
+        });
+        self.presence.set_client(authenticator);
+        if let Some(fallback) = self.fallback {
+            authenticator.set_fallback(fallback);
+        }
+        self.transport.set_client(authenticator);
 
-        ctap.set_client(ctap_driver);
-
-        (ctap, ctap_driver)
+        authenticator
     }
 }
@@ -0,0 +1,144 @@
+//! Components for the HMAC_DRBG random number generator.
+//!
+//! `DrbgComponent` seeds the generator from an entropy source, which it then
+//! owns. Each user of the generator, such as the RNG syscall driver built by
+//! `RngDriverComponent`, gets its own `DrbgUser`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let drbg = components::drbg::DrbgComponent::new(
+//!     &base_peripherals.trng,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::drbg_component_helper!());
+//! let rng = components::drbg::RngDriverComponent::new(board_kernel, drbg)
+//!     .finalize(components::drbg_user_component_helper!());
+//! let entropy_user = components::drbg::DrbgUserComponent::new(drbg)
+//!     .finalize(components::drbg_user_component_helper!());
+//! ```
+
+use capsules::drbg::{DrbgUser, HmacDrbg};
+use capsules::rng::RngDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::entropy::Entropy32;
+use kernel::hil::rng::Rng;
+use kernel::{static_init, static_init_half};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! drbg_component_helper {
+    () => {{
+        use capsules::drbg::HmacDrbg;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<HmacDrbg<'static>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct DrbgComponent {
+    trng: &'static dyn Entropy32<'static>,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl DrbgComponent {
+    pub fn new(
+        trng: &'static dyn Entropy32<'static>,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> DrbgComponent {
+        DrbgComponent {
+            trng,
+            deferred_caller,
+        }
+    }
+}
+
+impl Component for DrbgComponent {
+    type StaticInput = &'static mut MaybeUninit<HmacDrbg<'static>>;
+    type Output = &'static HmacDrbg<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let drbg = static_init_half!(
+            s,
+            HmacDrbg<'static>,
+            HmacDrbg::new(self.trng, self.deferred_caller)
+        );
+        drbg.initialize_callback_handle(
+            self.deferred_caller
+                .register(drbg)
+                .expect("no deferred call slot available for the DRBG"),
+        );
+        self.trng.set_client(drbg);
+
+        drbg
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! drbg_user_component_helper {
+    () => {{
+        use capsules::drbg::DrbgUser;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<DrbgUser<'static>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct DrbgUserComponent {
+    drbg: &'static HmacDrbg<'static>,
+}
+
+impl DrbgUserComponent {
+    pub fn new(drbg: &'static HmacDrbg<'static>) -> DrbgUserComponent {
+        DrbgUserComponent { drbg }
+    }
+}
+
+impl Component for DrbgUserComponent {
+    type StaticInput = &'static mut MaybeUninit<DrbgUser<'static>>;
+    type Output = &'static DrbgUser<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let user = static_init_half!(s, DrbgUser<'static>, DrbgUser::new(self.drbg));
+        user.setup();
+
+        user
+    }
+}
+
+pub struct RngDriverComponent {
+    board_kernel: &'static kernel::Kernel,
+    drbg: &'static HmacDrbg<'static>,
+}
+
+impl RngDriverComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        drbg: &'static HmacDrbg<'static>,
+    ) -> RngDriverComponent {
+        RngDriverComponent { board_kernel, drbg }
+    }
+}
+
+impl Component for RngDriverComponent {
+    type StaticInput = &'static mut MaybeUninit<DrbgUser<'static>>;
+    type Output = &'static RngDriver<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let user = DrbgUserComponent::new(self.drbg).finalize(s);
+        let rng = static_init!(
+            RngDriver<'static>,
+            RngDriver::new(user, self.board_kernel.create_grant(&grant_cap))
+        );
+        user.set_client(rng);
+
+        rng
+    }
+}
@@ -155,6 +155,7 @@ impl<
         awake_mac.set_transmit_client(mac_device);
         awake_mac.set_receive_client(mac_device);
         awake_mac.set_config_client(mac_device);
+        awake_mac.set_energy_detect_client(mac_device);
 
         let mux_mac = static_init!(
             capsules::ieee802154::virtual_mac::MuxMac<'static>,
@@ -162,6 +163,7 @@ impl<
         );
         mac_device.set_transmit_client(mux_mac);
         mac_device.set_receive_client(mux_mac);
+        mac_device.set_energy_detect_client(mux_mac);
 
         let userspace_mac = static_init!(
             capsules::ieee802154::virtual_mac::MacUser<'static>,
@@ -0,0 +1,107 @@
+//! Component for IEEE 802.15.4 MAC layer management.
+//!
+//! Sets up scans, beacons and association on its own user of the `MuxMac`
+//! returned by `Ieee802154Component`, along with their userspace driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (radio_driver, mux_mac) = components::ieee802154::Ieee802154Component::new(...);
+//! let mlme_driver = components::ieee802154_mlme::MlmeComponent::new(
+//!     board_kernel,
+//!     mux_mac,
+//!     mux_alarm,
+//! )
+//! .finalize(components::mlme_component_helper!(nrf52::rtc::Rtc<'static>));
+//! ```
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::mlme::{Mlme, MlmeDriver};
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! mlme_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::ieee802154::mlme::{Mlme, MlmeDriver};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<Mlme<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<MlmeDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+// The buffer MAC commands and beacons are sent from.
+static mut MLME_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+pub struct MlmeComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static MuxMac<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> MlmeComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static MuxMac<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> MlmeComponent<A> {
+        MlmeComponent {
+            board_kernel,
+            mux_mac,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for MlmeComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Mlme<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<MlmeDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static MlmeDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let mlme_mac = static_init!(MacUser<'static>, MacUser::new(self.mux_mac));
+        self.mux_mac.add_user(mlme_mac);
+
+        let mlme_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let mlme = static_init_half!(
+            static_buffer.1,
+            Mlme<'static, VirtualMuxAlarm<'static, A>>,
+            Mlme::new(mlme_mac, mlme_alarm, &mut MLME_BUF)
+        );
+        mlme_mac.set_transmit_client(mlme);
+        mlme_mac.set_receive_client(mlme);
+        mlme_mac.set_energy_detect_client(mlme);
+        mlme_alarm.set_alarm_client(mlme);
+
+        let mlme_driver = static_init_half!(
+            static_buffer.2,
+            MlmeDriver<'static, VirtualMuxAlarm<'static, A>>,
+            MlmeDriver::new(mlme, self.board_kernel.create_grant(&grant_cap))
+        );
+        mlme.set_client(mlme_driver);
+
+        mlme_driver
+    }
+}
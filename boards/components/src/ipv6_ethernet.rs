@@ -0,0 +1,125 @@
+//! Component for running the IPv6 stack over an Ethernet MAC.
+//!
+//! The component sets up an `IP6Ethernet` adapter on top of the MAC, with
+//! the link-local address derived from the MAC address. The adapter is both
+//! the `IP6Sender` and the `IP6Receiver` of the stack above it.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ip6_ethernet = components::ipv6_ethernet::IP6EthernetComponent::new(ethmac0, mux_alarm)
+//!     .finalize(components::ipv6_ethernet_component_helper!(
+//!         litex_vexriscv::timer::LiteXAlarm<
+//!             'static,
+//!             'static,
+//!             socc::SoCRegisterFmt,
+//!             socc::ClockFrequency,
+//!         >
+//!     ));
+//! ```
+
+use capsules::net::frag_utils::BITMAP_SIZE;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_ethernet::IP6Ethernet;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::IpVisibilityCapability;
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::{self, Ethernet};
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+/// Largest transport payload: the IPv6 minimum MTU less the IPv6 and UDP
+/// headers.
+pub const MAX_PAYLOAD_LEN: usize = 1280 - 40 - 8;
+
+static mut TX_BUF: [u8; ethernet::MAX_FRAME_LEN] = [0; ethernet::MAX_FRAME_LEN];
+static mut IP6_PAYLOAD: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+// Reassembled packets are limited by the size of the fragment bitmap
+static mut REASSEMBLY_BUF: [u8; BITMAP_SIZE * 8 * 8] = [0; BITMAP_SIZE * 8 * 8];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ipv6_ethernet_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::ipv6::ipv6_ethernet::IP6Ethernet;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<IP6Ethernet<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct IP6EthernetComponent<A: Alarm<'static> + 'static> {
+    ethernet: &'static dyn Ethernet<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> IP6EthernetComponent<A> {
+    pub fn new(
+        ethernet: &'static dyn Ethernet<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> IP6EthernetComponent<A> {
+        IP6EthernetComponent {
+            ethernet,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for IP6EthernetComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let ip6_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        let ip6_packet = static_init!(
+            IP6Packet<'static>,
+            IP6Packet::new(IPPayload::new(
+                TransportHeader::UDP(UDPHeader::new()),
+                &mut IP6_PAYLOAD
+            ))
+        );
+
+        let ip6_ethernet = static_init_half!(
+            static_buffer.1,
+            IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>,
+            IP6Ethernet::new(
+                self.ethernet,
+                ip6_alarm,
+                ip6_packet,
+                &mut TX_BUF,
+                &mut REASSEMBLY_BUF,
+                ip_vis
+            )
+        );
+        self.ethernet.set_client(ip6_ethernet);
+        ip6_alarm.set_alarm_client(ip6_ethernet);
+        ip6_ethernet.set_addr(IPAddr::generate_from_ethernet_mac(
+            self.ethernet.mac_address(),
+        ));
+
+        ip6_ethernet
+    }
+}
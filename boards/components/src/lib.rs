@@ -2,17 +2,21 @@
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod aes;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
 pub mod bus;
 pub mod button;
+pub mod can;
 pub mod cdc;
+pub mod coap;
 pub mod console;
 pub mod crc;
 pub mod ctap;
 pub mod debug_queue;
 pub mod debug_writer;
+pub mod drbg;
 pub mod ft6x06;
 pub mod fxos8700;
 pub mod gpio;
@@ -21,6 +25,8 @@ pub mod hmac;
 pub mod humidity;
 pub mod i2c;
 pub mod ieee802154;
+pub mod ieee802154_mlme;
+pub mod ipv6_ethernet;
 pub mod isl29035;
 pub mod l3gd20;
 pub mod led;
@@ -34,13 +40,19 @@ pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod ping;
 pub mod process_console;
+pub mod public_key_crypto;
+pub mod pwm;
 pub mod rng;
+pub mod rpl;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sha;
 pub mod sht3x;
 pub mod si7021;
+pub mod sixlowpan_nd;
 pub mod sound_pressure;
 pub mod spi;
 pub mod st77xx;
@@ -51,3 +63,9 @@ pub mod text_screen;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod udp_mux_ethernet;
+pub mod usb_cdc_ecm;
+pub mod usb_composite;
+pub mod usb_dfu;
+pub mod usb_hid;
+pub mod usb_msc;
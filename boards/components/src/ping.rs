@@ -0,0 +1,88 @@
+//! Component for the ICMPv6 echo (ping) userspace driver.
+//!
+//! The driver sends its Echo Requests through the ICMPv6 responder set up by
+//! `UDPMuxComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) = ...;
+//! let ping_driver =
+//!     components::ping::PingComponent::new(board_kernel, icmp_responder, mux_alarm)
+//!         .finalize(components::ping_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules::net::icmpv6::driver::PingDriver;
+use capsules::net::icmpv6::icmpv6_responder::ICMP6EchoSender;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ping_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::icmpv6::driver::PingDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<PingDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct PingComponent<A: Alarm<'static> + 'static> {
+    board_kernel: &'static kernel::Kernel,
+    echo_sender: &'static dyn ICMP6EchoSender<'static>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> PingComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        echo_sender: &'static dyn ICMP6EchoSender<'static>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> PingComponent<A> {
+        PingComponent {
+            board_kernel,
+            echo_sender,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for PingComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<PingDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static PingDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let ping_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let ping_driver = static_init_half!(
+            static_buffer.1,
+            PingDriver<'static, VirtualMuxAlarm<'static, A>>,
+            PingDriver::new(
+                self.echo_sender,
+                ping_alarm,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        ping_alarm.set_alarm_client(ping_driver);
+        self.echo_sender.set_echo_client(ping_driver);
+
+        ping_driver
+    }
+}
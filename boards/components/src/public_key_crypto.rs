@@ -0,0 +1,193 @@
+//! Components for the software signature engines and the public key crypto
+//! syscall driver.
+//!
+//! A software engine computes one algorithm. It can generate keys when it is
+//! given a random number generator with `with_rng()`, which then delivers
+//! its randomness to the engine only.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ecdsa = components::public_key_crypto::PublicKeySoftwareComponent::new(
+//!     dynamic_deferred_caller,
+//! )
+//! .with_rng(entropy_to_random)
+//! .finalize(components::public_key_software_component_helper!(
+//!     capsules::public_key_crypto::software::EcdsaP256
+//! ));
+//! let ed25519 = components::public_key_crypto::PublicKeySoftwareComponent::new(
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::public_key_software_component_helper!(
+//!     capsules::public_key_crypto::software::Ed25519
+//! ));
+//!
+//! let public_key_crypto =
+//!     components::public_key_crypto::PublicKeyDriverComponent::new(board_kernel)
+//!         .with_engine(Algorithm::EcdsaP256, ecdsa)
+//!         .with_engine(Algorithm::Ed25519, ed25519)
+//!         .finalize(components::public_key_driver_component_helper!());
+//! ```
+
+use capsules::public_key_crypto::driver::{
+    Algorithm, SignatureEngine, PRIVATE_KEY_BUF_LEN, PUBLIC_KEY_BUF_LEN, SIGNATURE_BUF_LEN,
+};
+use capsules::public_key_crypto::software::{PublicKeySoftware, SignatureAlgorithm};
+use capsules::public_key_crypto::PublicKeyDriver;
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::rng::Rng;
+use kernel::static_init_half;
+
+/// The size of the kernel message buffer of the driver, which bounds the
+/// length of the messages processes sign and verify.
+pub const PUBLIC_KEY_MESSAGE_BUF_LEN: usize = 256;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! public_key_software_component_helper {
+    ($S:ty $(,)?) => {{
+        use capsules::public_key_crypto::software::PublicKeySoftware;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<PublicKeySoftware<'static, $S>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct PublicKeySoftwareComponent<S: 'static + SignatureAlgorithm> {
+    deferred_caller: &'static DynamicDeferredCall,
+    rng: Option<&'static dyn Rng<'static>>,
+    _algorithm: core::marker::PhantomData<S>,
+}
+
+impl<S: 'static + SignatureAlgorithm> PublicKeySoftwareComponent<S> {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> PublicKeySoftwareComponent<S> {
+        PublicKeySoftwareComponent {
+            deferred_caller,
+            rng: None,
+            _algorithm: core::marker::PhantomData,
+        }
+    }
+
+    /// Lets the engine generate keys with randomness from `rng`.
+    pub fn with_rng(mut self, rng: &'static dyn Rng<'static>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+}
+
+impl<S: 'static + SignatureAlgorithm> Component for PublicKeySoftwareComponent<S> {
+    type StaticInput = &'static mut MaybeUninit<PublicKeySoftware<'static, S>>;
+    type Output = &'static PublicKeySoftware<'static, S>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let engine = static_init_half!(
+            s,
+            PublicKeySoftware<'static, S>,
+            PublicKeySoftware::new(self.deferred_caller)
+        );
+        engine.initialize_callback_handle(
+            self.deferred_caller
+                .register(engine)
+                .expect("no deferred call slot available for public key software"),
+        );
+        if let Some(rng) = self.rng {
+            engine.set_rng(rng);
+            rng.set_client(engine);
+        }
+
+        engine
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! public_key_driver_component_helper {
+    () => {{
+        use capsules::public_key_crypto::driver::{
+            PRIVATE_KEY_BUF_LEN, PUBLIC_KEY_BUF_LEN, SIGNATURE_BUF_LEN,
+        };
+        use capsules::public_key_crypto::PublicKeyDriver;
+        use core::mem::MaybeUninit;
+        use $crate::public_key_crypto::PUBLIC_KEY_MESSAGE_BUF_LEN;
+        static mut BUF1: MaybeUninit<PublicKeyDriver<'static>> = MaybeUninit::uninit();
+        static mut MESSAGE_BUF: [u8; PUBLIC_KEY_MESSAGE_BUF_LEN] = [0; PUBLIC_KEY_MESSAGE_BUF_LEN];
+        static mut SIGNATURE_BUF: [u8; SIGNATURE_BUF_LEN] = [0; SIGNATURE_BUF_LEN];
+        static mut PRIVATE_KEY_BUF: [u8; PRIVATE_KEY_BUF_LEN] = [0; PRIVATE_KEY_BUF_LEN];
+        static mut PUBLIC_KEY_BUF: [u8; PUBLIC_KEY_BUF_LEN] = [0; PUBLIC_KEY_BUF_LEN];
+        (
+            &mut BUF1,
+            &mut MESSAGE_BUF,
+            &mut SIGNATURE_BUF,
+            &mut PRIVATE_KEY_BUF,
+            &mut PUBLIC_KEY_BUF,
+        )
+    };};
+}
+
+pub struct PublicKeyDriverComponent {
+    board_kernel: &'static kernel::Kernel,
+    engines: [Option<&'static dyn SignatureEngine<'static>>; 2],
+}
+
+impl PublicKeyDriverComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel) -> PublicKeyDriverComponent {
+        PublicKeyDriverComponent {
+            board_kernel,
+            engines: [None, None],
+        }
+    }
+
+    /// Makes `algorithm`, computed by `engine`, available to processes.
+    pub fn with_engine(
+        mut self,
+        algorithm: Algorithm,
+        engine: &'static dyn SignatureEngine<'static>,
+    ) -> Self {
+        self.engines[algorithm as usize] = Some(engine);
+        self
+    }
+}
+
+impl Component for PublicKeyDriverComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<PublicKeyDriver<'static>>,
+        &'static mut [u8; PUBLIC_KEY_MESSAGE_BUF_LEN],
+        &'static mut [u8; SIGNATURE_BUF_LEN],
+        &'static mut [u8; PRIVATE_KEY_BUF_LEN],
+        &'static mut [u8; PUBLIC_KEY_BUF_LEN],
+    );
+    type Output = &'static PublicKeyDriver<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let driver = static_init_half!(
+            s.0,
+            PublicKeyDriver<'static>,
+            PublicKeyDriver::new(
+                s.1,
+                s.2,
+                s.3,
+                s.4,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        for (algorithm, engine) in [Algorithm::EcdsaP256, Algorithm::Ed25519]
+            .iter()
+            .zip(self.engines.iter())
+        {
+            if let Some(engine) = engine {
+                engine.set_sign_client(driver);
+                engine.set_verify_client(driver);
+                engine.set_key_generate_client(driver);
+                driver.set_engine(*algorithm, *engine);
+            }
+        }
+
+        driver
+    }
+}
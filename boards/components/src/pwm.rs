@@ -0,0 +1,150 @@
+//! Components for PWM outputs and the PWM syscall driver.
+//!
+//! The driver helper takes the channels exposed to userspace in order, each
+//! with the name processes can look it up by.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mux_pwm = components::pwm::PwmMuxComponent::new(&base_peripherals.pwm0)
+//!     .finalize(components::pwm_mux_component_helper!(nrf52840::pwm::Pwm));
+//!
+//! let pwm = components::pwm::PwmDriverComponent::new(board_kernel).finalize(
+//!     components::pwm_driver_component_helper!(
+//!         "led1" => components::pwm::PwmPinComponent::new(mux_pwm, Pinmux::new(LED1_PIN as u32))
+//!             .finalize(components::pwm_pin_component_helper!(nrf52840::pwm::Pwm)),
+//!         "led2" => components::pwm::PwmPinComponent::new(mux_pwm, Pinmux::new(LED2_PIN as u32))
+//!             .finalize(components::pwm_pin_component_helper!(nrf52840::pwm::Pwm)),
+//!     ),
+//! );
+//! ```
+
+use capsules::pwm::Pwm;
+use capsules::virtual_pwm::{MuxPwm, PwmPinUser};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::pwm;
+use kernel::static_init_half;
+
+#[macro_export]
+macro_rules! pwm_mux_component_helper {
+    ($P:ty $(,)?) => {{
+        use capsules::virtual_pwm::MuxPwm;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<MuxPwm<'static, $P>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! pwm_pin_component_helper {
+    ($P:ty $(,)?) => {{
+        use capsules::virtual_pwm::PwmPinUser;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<PwmPinUser<'static, $P>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! pwm_driver_component_helper {
+    ($($N:expr => $P:expr),+ $(,)?) => {{
+        use capsules::pwm::Pwm;
+        use core::mem::MaybeUninit;
+        use kernel::count_expressions;
+        use kernel::static_init;
+        const NUM_CHANNELS: usize = count_expressions!($($P),+);
+
+        let channels = static_init!(
+            [(&'static str, &'static dyn kernel::hil::pwm::PwmPin); NUM_CHANNELS],
+            [
+                $(($N, $P),)*
+            ]
+        );
+        static mut BUF: MaybeUninit<Pwm<'static>> = MaybeUninit::uninit();
+        (&mut BUF, channels)
+    };};
+}
+
+pub struct PwmMuxComponent<P: 'static + pwm::Pwm> {
+    pwm: &'static P,
+}
+
+impl<P: 'static + pwm::Pwm> PwmMuxComponent<P> {
+    pub fn new(pwm: &'static P) -> Self {
+        PwmMuxComponent { pwm: pwm }
+    }
+}
+
+impl<P: 'static + pwm::Pwm> Component for PwmMuxComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<MuxPwm<'static, P>>;
+    type Output = &'static MuxPwm<'static, P>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        static_init_half!(static_buffer, MuxPwm<'static, P>, MuxPwm::new(self.pwm))
+    }
+}
+
+pub struct PwmPinComponent<P: 'static + pwm::Pwm> {
+    pwm_mux: &'static MuxPwm<'static, P>,
+    pin: P::Pin,
+}
+
+impl<P: 'static + pwm::Pwm> PwmPinComponent<P> {
+    pub fn new(mux: &'static MuxPwm<'static, P>, pin: P::Pin) -> Self {
+        PwmPinComponent {
+            pwm_mux: mux,
+            pin: pin,
+        }
+    }
+}
+
+impl<P: 'static + pwm::Pwm> Component for PwmPinComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<PwmPinUser<'static, P>>;
+    type Output = &'static PwmPinUser<'static, P>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let pwm_pin = static_init_half!(
+            static_buffer,
+            PwmPinUser<'static, P>,
+            PwmPinUser::new(self.pwm_mux, self.pin)
+        );
+
+        pwm_pin.add_to_mux();
+
+        pwm_pin
+    }
+}
+
+pub struct PwmDriverComponent {
+    board_kernel: &'static kernel::Kernel,
+}
+
+impl PwmDriverComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel) -> PwmDriverComponent {
+        PwmDriverComponent {
+            board_kernel: board_kernel,
+        }
+    }
+}
+
+impl Component for PwmDriverComponent {
+    type StaticInput = (
+        &'static mut MaybeUninit<Pwm<'static>>,
+        &'static [(&'static str, &'static dyn kernel::hil::pwm::PwmPin)],
+    );
+    type Output = &'static Pwm<'static>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_pwm = self.board_kernel.create_grant(&grant_cap);
+
+        static_init_half!(
+            static_buffer.0,
+            Pwm<'static>,
+            Pwm::new(static_buffer.1, grant_pwm)
+        )
+    }
+}
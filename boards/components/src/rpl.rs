@@ -0,0 +1,123 @@
+//! Component for RPL routing in non-storing mode.
+//!
+//! Sets up an RPL node on top of the ICMPv6 responder of `UDPMuxComponent`,
+//! and starts looking for a DODAG to join. The node configures the source
+//! address and the gateway of the stack, so it replaces
+//! `SixlowpanNdComponent`. The node joins the DODAG as a leaf, and times
+//! its DODAG Information Solicitations with a Trickle timer seeded from the
+//! MAC address.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) = ...;
+//! let rpl_node = components::rpl::RplComponent::new(
+//!     icmp_responder,
+//!     mux_alarm,
+//!     src_mac_from_serial_num,
+//! )
+//! .finalize(components::rpl_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules::net::icmpv6::icmpv6_responder::{ICMP6MessageSender, ICMP6Responder};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::rpl::trickle::Trickle;
+use capsules::net::rpl::RplNode;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! rpl_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::rpl::trickle::Trickle;
+        use capsules::net::rpl::RplNode;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Trickle<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3)
+    };};
+}
+
+pub struct RplComponent<A: Alarm<'static> + 'static> {
+    icmp_responder: &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    mac_addr: MacAddress,
+}
+
+impl<A: Alarm<'static> + 'static> RplComponent<A> {
+    pub fn new(
+        icmp_responder: &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        mac_addr: MacAddress,
+    ) -> RplComponent<A> {
+        RplComponent {
+            icmp_responder,
+            alarm_mux,
+            mac_addr,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for RplComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Trickle<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<RplNode<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static RplNode<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let rpl_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let dis_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        // Neighbors have different MAC addresses, so their DIS messages are
+        // sent at different times.
+        let seed = match self.mac_addr {
+            MacAddress::Short(short) => short as u32,
+            MacAddress::Long(long) => u32::from_be_bytes([long[4], long[5], long[6], long[7]]),
+        };
+        let dis_timer = static_init_half!(
+            static_buffer.2,
+            Trickle<'static, VirtualMuxAlarm<'static, A>>,
+            Trickle::new(dis_alarm, seed)
+        );
+        dis_alarm.set_alarm_client(dis_timer);
+
+        // The responder forwards the source address and gateway to the IP
+        // sender it sits on.
+        let rpl_node = static_init_half!(
+            static_buffer.3,
+            RplNode<'static, VirtualMuxAlarm<'static, A>>,
+            RplNode::new(
+                self.icmp_responder,
+                self.icmp_responder,
+                rpl_alarm,
+                dis_timer,
+                self.mac_addr
+            )
+        );
+        rpl_alarm.set_alarm_client(rpl_node);
+        dis_timer.set_client(rpl_node);
+        self.icmp_responder.set_rpl_client(rpl_node);
+        rpl_node.start();
+
+        rpl_node
+    }
+}
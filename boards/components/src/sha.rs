@@ -0,0 +1,71 @@
+//! Component for the software SHA-2 engine.
+//!
+//! The engine implements the `Digest` interface, so it can be used in place
+//! of a hashing peripheral, for instance below the HMAC components. The type
+//! passed to the helper macro is the digest type, which also selects the
+//! default hash (`[u8; 32]` for SHA-256, `[u8; 48]` for SHA-384 and
+//! `[u8; 64]` for SHA-512).
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sha = components::sha::ShaSoftwareComponent::new(dynamic_deferred_caller)
+//!     .finalize(components::sha_software_component_helper!([u8; 32]));
+//!
+//! let mux_hmac = components::hmac::HmacMuxComponent::new(sha).finalize(
+//!     components::hmac_mux_component_helper!(capsules::sha::ShaSoftware<'static, [u8; 32]>, [u8; 32]),
+//! );
+//! ```
+
+use capsules::sha::ShaSoftware;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::component::Component;
+use kernel::hil::digest;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sha_software_component_helper {
+    ($T:ty $(,)?) => {{
+        use capsules::sha::ShaSoftware;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<ShaSoftware<'static, $T>> = MaybeUninit::uninit();
+        &mut BUF1
+    };};
+}
+
+pub struct ShaSoftwareComponent<T: 'static + digest::DigestType> {
+    deferred_caller: &'static DynamicDeferredCall,
+    phantom: PhantomData<&'static T>,
+}
+
+impl<T: 'static + digest::DigestType> ShaSoftwareComponent<T> {
+    pub fn new(deferred_caller: &'static DynamicDeferredCall) -> ShaSoftwareComponent<T> {
+        ShaSoftwareComponent {
+            deferred_caller,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: 'static + digest::DigestType> Component for ShaSoftwareComponent<T> {
+    type StaticInput = &'static mut MaybeUninit<ShaSoftware<'static, T>>;
+    type Output = &'static ShaSoftware<'static, T>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let sha = static_init_half!(
+            s,
+            ShaSoftware<'static, T>,
+            ShaSoftware::new(self.deferred_caller)
+        );
+        sha.initialize_callback_handle(
+            self.deferred_caller
+                .register(sha)
+                .expect("no deferred call slot available for SHA"),
+        );
+
+        sha
+    }
+}
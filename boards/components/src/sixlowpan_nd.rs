@@ -0,0 +1,94 @@
+//! Component for 6LoWPAN Neighbor Discovery.
+//!
+//! Sets up the RFC 6775 host on top of the ICMPv6 responder of
+//! `UDPMuxComponent`, and starts looking for routers. Once a router is
+//! found, the address configured from its prefix becomes the source address
+//! of the stack and unicast packets are sent to the router.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) = ...;
+//! let sixlowpan_nd = components::sixlowpan_nd::SixlowpanNdComponent::new(
+//!     icmp_responder,
+//!     mux_alarm,
+//!     src_mac_from_serial_num,
+//! )
+//! .finalize(components::sixlowpan_nd_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules::net::icmpv6::icmpv6_responder::{ICMP6MessageSender, ICMP6Responder};
+use capsules::net::icmpv6::sixlowpan_nd::SixlowpanNd;
+use capsules::net::ieee802154::MacAddress;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::component::Component;
+use kernel::hil::time::Alarm;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sixlowpan_nd_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::icmpv6::sixlowpan_nd::SixlowpanNd;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<SixlowpanNd<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1)
+    };};
+}
+
+pub struct SixlowpanNdComponent<A: Alarm<'static> + 'static> {
+    icmp_responder: &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    mac_addr: MacAddress,
+}
+
+impl<A: Alarm<'static> + 'static> SixlowpanNdComponent<A> {
+    pub fn new(
+        icmp_responder: &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        mac_addr: MacAddress,
+    ) -> SixlowpanNdComponent<A> {
+        SixlowpanNdComponent {
+            icmp_responder,
+            alarm_mux,
+            mac_addr,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for SixlowpanNdComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SixlowpanNd<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static SixlowpanNd<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let nd_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        // The responder forwards the source address and gateway to the IP
+        // sender it sits on.
+        let sixlowpan_nd = static_init_half!(
+            static_buffer.1,
+            SixlowpanNd<'static, VirtualMuxAlarm<'static, A>>,
+            SixlowpanNd::new(
+                self.icmp_responder,
+                self.icmp_responder,
+                nd_alarm,
+                self.mac_addr
+            )
+        );
+        nd_alarm.set_alarm_client(sixlowpan_nd);
+        self.icmp_responder.set_message_client(sixlowpan_nd);
+        sixlowpan_nd.start();
+
+        sixlowpan_nd
+    }
+}
@@ -14,12 +14,23 @@
 //!        local_ip_ifaces,
 //!        PAYLOAD_LEN,
 //!     )
-//!     .finalize();
+//!     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
+//! ```
+//!
+//! The driver sends through the UDP mux of the 6LoWPAN stack by default.
+//! Over another IPv6 sender, such as `IP6Ethernet`, its type is passed to the
+//! helper after the alarm:
+//!
+//! ```rust
+//!     .finalize(components::udp_driver_component_helper!(
+//!         nrf52840::rtc::Rtc,
+//!         IP6Ethernet<'static, VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>>
+//!     ));
 //! ```
 
 use capsules;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
 use capsules::net::network_capabilities::{
     AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
@@ -27,13 +38,11 @@ use capsules::net::udp::udp_port_table::UdpPortManager;
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
-use capsules::virtual_alarm::VirtualMuxAlarm;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
 use kernel::component::Component;
-use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init, static_init_half};
 
 const UDP_HDR_SIZE: usize = 8;
@@ -44,38 +53,35 @@ static mut DRIVER_BUF: [u8; MAX_PAYLOAD_LEN - UDP_HDR_SIZE] = [0; MAX_PAYLOAD_LE
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! udp_driver_component_helper {
-    ($A:ty $(,)?) => {{
+    ($A:ty, $T:ty $(,)?) => {{
         use capsules::net::udp::udp_send::UDPSendStruct;
         use core::mem::MaybeUninit;
-        static mut BUF0: MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<
-                    'static,
-                    VirtualMuxAlarm<'static, $A>,
-                >,
-            >,
-        > = MaybeUninit::uninit();
+        static mut BUF0: MaybeUninit<UDPSendStruct<'static, $T>> = MaybeUninit::uninit();
         (&mut BUF0,)
     };};
+    ($A:ty $(,)?) => {{
+        $crate::udp_driver_component_helper!(
+            $A,
+            capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                'static,
+                capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+            >
+        )
+    };};
 }
 
-pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
+pub struct UDPDriverComponent<T: IP6Sender<'static> + 'static> {
     board_kernel: &'static kernel::Kernel,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, T>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
     interface_list: &'static [IPAddr],
 }
 
-impl<A: Alarm<'static>> UDPDriverComponent<A> {
+impl<T: IP6Sender<'static>> UDPDriverComponent<T> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, T>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
         interface_list: &'static [IPAddr],
@@ -90,15 +96,8 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
     }
 }
 
-impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
-    type StaticInput = (
-        &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
-        >,
-    );
+impl<T: IP6Sender<'static>> Component for UDPDriverComponent<T> {
+    type StaticInput = (&'static mut MaybeUninit<UDPSendStruct<'static, T>>,);
     type Output = &'static capsules::net::udp::UDPDriver<'static>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -111,10 +110,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         );
         let udp_send = static_init_half!(
             static_buffer.0,
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
+            UDPSendStruct<'static, T>,
             UDPSendStruct::new(self.udp_send_mux, udp_vis)
         );
 
@@ -147,6 +143,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
 
         let udp_driver_rcvr = static_init!(UDPReceiver<'static>, UDPReceiver::new());
         self.udp_recv_mux.set_driver(udp_driver);
+        udp_driver.set_multicast_groups(self.udp_recv_mux);
         self.udp_recv_mux.add_client(udp_driver_rcvr);
         udp_driver
     }
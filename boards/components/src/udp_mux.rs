@@ -2,12 +2,14 @@
 //!
 //! This provides one Component, UDPMuxComponent. This component
 //! exposes a MuxUdpSender that other components can implement
-//! UDPSenders on top of to use the UDP/6Lowpan stack.
+//! UDPSenders on top of to use the UDP/6Lowpan stack. It also sets up the
+//! ICMPv6 responder, which answers Echo Requests and reports closed UDP ports,
+//! and which the ping driver sends Echo Requests through.
 //!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_mux, udp_recv, udp_port_table, icmp_responder) = UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
@@ -25,13 +27,16 @@
 
 use capsules;
 use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6_responder::ICMP6Responder;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
 use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
@@ -50,18 +55,22 @@ use kernel::{static_init, static_init_half};
 // The UDP stack requires several packet buffers:
 //
 //   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
-//   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
+//   2. SIXLOWPAN_RX_BUFS: Buffers to hold full IP packets after they are decompressed by 6LoWPAN,
+//      one for each packet which can be reassembled at the same time
 //   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   4. ICMP_BUF: Buffer the ICMPv6 responder crafts its messages in.
 //
 //   Additionally, every capsule using the stack needs an additional buffer to craft packets for
 //   tx which can then be passed to the MuxUdpSender for tx.
 
 static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
-static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut SIXLOWPAN_RX_BUFS: [[u8; 1280]; 2] = [[0x00; 1280]; 2];
 
 pub const MAX_PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userspace apps or capsules
 const UDP_HDR_SIZE: usize = 8;
 static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN - UDP_HDR_SIZE] = [0; MAX_PAYLOAD_LEN - UDP_HDR_SIZE];
+const ICMP_HDR_SIZE: usize = 8;
+static mut ICMP_BUF: [u8; MAX_PAYLOAD_LEN - ICMP_HDR_SIZE] = [0; MAX_PAYLOAD_LEN - ICMP_HDR_SIZE];
 
 // Rather than require a data structure with 65535 slots (number of UDP ports), we
 // use a structure that can hold up to 16 port bindings. Any given capsule can bind
@@ -107,8 +116,17 @@ macro_rules! udp_mux_component_helper {
                 >,
             >,
         > = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<
+            capsules::net::icmpv6::icmpv6_responder::ICMP6Responder<
+                'static,
+                VirtualMuxAlarm<'static, $A>,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<sixlowpan_state::RxState<'static>> = MaybeUninit::uninit();
         (
-            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5,
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8,
         )
     };};
 }
@@ -166,11 +184,15 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                 capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
             >,
         >,
+        &'static mut MaybeUninit<ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<sixlowpan_state::RxState<'static>>,
     );
     type Output = (
         &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -196,6 +218,12 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             IpVisibilityCapability::new(&create_cap)
         );
 
+        // 6LoWPAN sets alarms to time out reassemblies, so it needs its own
+        let sixlowpan_alarm = static_init_half!(
+            static_buffer.7,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
         let sixlowpan = static_init_half!(
             static_buffer.2,
             sixlowpan_state::Sixlowpan<
@@ -210,18 +238,25 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                     id: 0,
                     compress: false,
                 },
-                ipsender_virtual_alarm, // OK to reuse bc only used to get time, not set alarms
+                sixlowpan_alarm,
             )
         );
+        sixlowpan_alarm.set_alarm_client(sixlowpan);
 
         let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
         let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
         let default_rx_state = static_init_half!(
             static_buffer.3,
             sixlowpan_state::RxState<'static>,
-            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUFS[0])
         );
         sixlowpan_state.add_rx_state(default_rx_state);
+        let second_rx_state = static_init_half!(
+            static_buffer.8,
+            sixlowpan_state::RxState<'static>,
+            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUFS[1])
+        );
+        sixlowpan_state.add_rx_state(second_rx_state);
         udp_mac.set_receive_client(sixlowpan);
 
         let tr_hdr = TransportHeader::UDP(UDPHeader::new());
@@ -267,6 +302,27 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         sixlowpan_state.set_rx_client(ip_receive);
         let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
         ip_receive.set_client(udp_recv_mux);
+        udp_recv_mux.set_multicast_groups(ip_receive);
+
+        // The ICMPv6 responder sits between the UDP layer and the IP sender,
+        // so it can send its messages whenever the UDP layer is not sending.
+        let icmp_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let icmp_responder = static_init_half!(
+            static_buffer.6,
+            ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+            ICMP6Responder::new(
+                ip_send,
+                ipsender_virtual_alarm, // Only used to get time, not set alarms
+                &mut ICMP_BUF,
+                icmp_net_cap,
+            )
+        );
+        ip_send.set_client(icmp_responder);
+        ip_receive.set_icmp_client(icmp_responder);
+        udp_recv_mux.set_error_reporter(icmp_responder);
 
         let udp_send_mux = static_init_half!(
             static_buffer.5,
@@ -274,9 +330,9 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                 'static,
                 capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
             >,
-            MuxUdpSender::new(ip_send)
+            MuxUdpSender::new(icmp_responder)
         );
-        ip_send.set_client(udp_send_mux);
+        icmp_responder.set_client(udp_send_mux);
 
         let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
         let udp_port_table = static_init!(
@@ -284,6 +340,6 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder)
     }
 }
@@ -0,0 +1,147 @@
+//! Component to initialize the UDP stack over Ethernet.
+//!
+//! This provides one Component, UDPMuxEthernetComponent, the counterpart of
+//! `UDPMuxComponent` for a link which carries IPv6 packets directly, such as
+//! an Ethernet MAC or a USB network interface. It builds the UDP mux, the
+//! ICMPv6 responder and the UDP port table on top of the `IP6Ethernet`
+//! adapter set up by `IP6EthernetComponent`, and returns them like
+//! `UDPMuxComponent` does, so the UDP driver, CoAP and ping components can be
+//! set up the same way. A board runs them over either this component or
+//! `UDPMuxComponent`, since each of them can only be set up once.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_mux, udp_recv, udp_port_table, icmp_responder) =
+//!        UDPMuxEthernetComponent::new(ip6_ethernet, mux_alarm)
+//!            .finalize(components::udp_mux_ethernet_component_helper!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules::net::icmpv6::icmpv6_responder::ICMP6Responder;
+use capsules::net::ipv6::ipv6_ethernet::IP6Ethernet;
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+const ICMP_HDR_SIZE: usize = 8;
+static mut ICMP_BUF: [u8; super::udp_mux::MAX_PAYLOAD_LEN - ICMP_HDR_SIZE] =
+    [0; super::udp_mux::MAX_PAYLOAD_LEN - ICMP_HDR_SIZE];
+
+static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! udp_mux_ethernet_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::net::icmpv6::icmpv6_responder::ICMP6Responder;
+        use capsules::net::ipv6::ipv6_ethernet::IP6Ethernet;
+        use capsules::net::udp::udp_send::MuxUdpSender;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<ICMP6Responder<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            MuxUdpSender<'static, IP6Ethernet<'static, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UDPMuxEthernetComponent<A: Alarm<'static> + 'static> {
+    ip6_ethernet: &'static IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: Alarm<'static> + 'static> UDPMuxEthernetComponent<A> {
+    pub fn new(
+        ip6_ethernet: &'static IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            ip6_ethernet,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: Alarm<'static> + 'static> Component for UDPMuxEthernetComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<
+            MuxUdpSender<'static, IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>>,
+        >,
+    );
+    type Output = (
+        &'static MuxUdpSender<'static, IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+        &'static ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        IP6Receiver::set_client(self.ip6_ethernet, udp_recv_mux);
+        udp_recv_mux.set_multicast_groups(self.ip6_ethernet);
+
+        // The ICMPv6 responder sits between the UDP layer and the IP sender,
+        // so it can send its messages whenever the UDP layer is not sending.
+        let icmp_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let icmp_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let icmp_responder = static_init_half!(
+            static_buffer.1,
+            ICMP6Responder<'static, VirtualMuxAlarm<'static, A>>,
+            ICMP6Responder::new(
+                self.ip6_ethernet,
+                icmp_alarm, // Only used to get time, not set alarms
+                &mut ICMP_BUF,
+                icmp_net_cap,
+            )
+        );
+        IP6Sender::set_client(self.ip6_ethernet, icmp_responder);
+        self.ip6_ethernet.set_icmp_client(icmp_responder);
+        udp_recv_mux.set_error_reporter(icmp_responder);
+
+        let udp_send_mux = static_init_half!(
+            static_buffer.2,
+            MuxUdpSender<'static, IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>>,
+            MuxUdpSender::new(icmp_responder)
+        );
+        icmp_responder.set_client(udp_send_mux);
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = static_init!(
+            UdpPortManager,
+            UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
+        );
+
+        (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder)
+    }
+}
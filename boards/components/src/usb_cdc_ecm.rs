@@ -0,0 +1,105 @@
+//! Component for a USB network interface, which presents the board as an
+//! Ethernet adapter to the host.
+//!
+//! The component returns the adapter, which is an Ethernet MAC the IPv6
+//! stack can run over with `IP6EthernetComponent`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 4] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Network",      // Product
+//!     "Serial No. 5", // Serial number
+//!     "02A1B2C3D4E5", // Host MAC address
+//! ];
+//!
+//! let ecm = components::usb_cdc_ecm::UsbCdcEcmComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::cdc_ecm::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//!     MacAddress::new([0x02, 0xa1, 0xb2, 0xc3, 0xd4, 0xe6]),
+//! )
+//! .finalize(components::usb_cdc_ecm_component_helper!(nrf52::usbd::Usbd));
+//!
+//! ecm.enable();
+//! ecm.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::cdc_ecm::{CdcEcm, RX_BUFFER_LEN};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::ethernet::MacAddress;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_cdc_ecm_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::cdc_ecm::{CdcEcm, RX_BUFFER_LEN};
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CdcEcm<'static, $U>> = MaybeUninit::uninit();
+        static mut RX_BUF: [u8; RX_BUFFER_LEN] = [0; RX_BUFFER_LEN];
+        (&mut BUF, &mut RX_BUF)
+    };};
+}
+
+pub struct UsbCdcEcmComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 4],
+    mac_address: MacAddress,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCdcEcmComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 4],
+        mac_address: MacAddress,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            mac_address,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCdcEcmComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<CdcEcm<'static, U>>,
+        &'static mut [u8; RX_BUFFER_LEN],
+    );
+    type Output = &'static CdcEcm<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let ecm = static_init_half!(
+            s.0,
+            CdcEcm<'static, U>,
+            CdcEcm::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.mac_address,
+                s.1,
+            )
+        );
+        self.usb.set_client(ecm);
+
+        ecm
+    }
+}
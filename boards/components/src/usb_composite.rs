@@ -0,0 +1,147 @@
+//! Components for a USB composite device.
+//!
+//! `UsbCompositeComponent` provides the device, which shares the USB
+//! controller between several class drivers. `CompositeFunctionComponent`
+//! provides a function of the device, which is the USB controller to give to
+//! one class driver. The interfaces of the functions follow the order in
+//! which the functions are created.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let composite = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::composite::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//! )
+//! .finalize(components::usb_composite_component_helper!(nrf52::usbd::Usbd));
+//!
+//! let msc_usb = components::usb_composite::CompositeFunctionComponent::new(composite)
+//!     .finalize(components::composite_function_component_helper!(nrf52::usbd::Usbd));
+//!
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     msc_usb,
+//!     capsules::usb::msc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//!     msc_storage,
+//! )
+//! .finalize(components::usb_msc_component_helper!(
+//!     capsules::usb::composite::CompositeFunction<'static, nrf52::usbd::Usbd>
+//! ));
+//!
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::composite::{CompositeDevice, CompositeFunction, CONFIGURATION_BUFFER_LEN};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_composite_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::composite::{CompositeDevice, CONFIGURATION_BUFFER_LEN};
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CompositeDevice<'static, $U>> = MaybeUninit::uninit();
+        static mut CONFIGURATION_BUF: [u8; CONFIGURATION_BUFFER_LEN] =
+            [0; CONFIGURATION_BUFFER_LEN];
+        (&mut BUF, &mut CONFIGURATION_BUF)
+    };};
+}
+
+pub struct UsbCompositeComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbCompositeComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbCompositeComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<CompositeDevice<'static, U>>,
+        &'static mut [u8; CONFIGURATION_BUFFER_LEN],
+    );
+    type Output = &'static CompositeDevice<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let composite = static_init_half!(
+            s.0,
+            CompositeDevice<'static, U>,
+            CompositeDevice::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                s.1,
+            )
+        );
+        self.usb.set_client(composite);
+
+        composite
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! composite_function_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::composite::CompositeFunction;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<CompositeFunction<'static, $U>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct CompositeFunctionComponent<U: 'static + hil::usb::UsbController<'static>> {
+    composite: &'static CompositeDevice<'static, U>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> CompositeFunctionComponent<U> {
+    pub fn new(composite: &'static CompositeDevice<'static, U>) -> Self {
+        Self { composite }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for CompositeFunctionComponent<U> {
+    type StaticInput = &'static mut MaybeUninit<CompositeFunction<'static, U>>;
+    type Output = &'static CompositeFunction<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let function = static_init_half!(
+            s,
+            CompositeFunction<'static, U>,
+            CompositeFunction::new(self.composite)
+        );
+        function.setup();
+
+        function
+    }
+}
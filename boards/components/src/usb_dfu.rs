@@ -0,0 +1,145 @@
+//! Component for the USB Device Firmware Upgrade class.
+//!
+//! The component takes the targets the host can download to, and makes the
+//! device the client of their storage.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 5] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Updater",      // Product
+//!     "Serial No. 5", // Serial number
+//!     "Applications", // First target
+//!     "Kernel",       // Second target
+//! ];
+//! let targets = static_init!(
+//!     [capsules::usb::dfu::Target<'static>; 2],
+//!     [
+//!         capsules::usb::dfu::Target {
+//!             storage: internal_flash,
+//!             address: 0x30000,
+//!             length: 0xd0000,
+//!             image: capsules::usb::dfu::Image::Apps(app_flash),
+//!         },
+//!         capsules::usb::dfu::Target {
+//!             storage: external_flash,
+//!             address: 0x00000,
+//!             length: 0x30000,
+//!             image: capsules::usb::dfu::Image::Kernel,
+//!         },
+//!     ]
+//! );
+//!
+//! let dfu = components::usb_dfu::UsbDfuComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::dfu::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//!     targets,
+//!     capsules::usb::dfu::Mode::Runtime,
+//!     board_kernel,
+//!     None,
+//! )
+//! .finalize(components::usb_dfu_component_helper!(nrf52::usbd::Usbd));
+//!
+//! dfu.enable();
+//! dfu.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::dfu::{Dfu, Mode, Target, TRANSFER_SIZE};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_dfu_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::dfu::{Dfu, TRANSFER_SIZE};
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<Dfu<'static, $U, $crate::usb_dfu::Capability>> =
+            MaybeUninit::uninit();
+        static mut BLOCK_BUF: [u8; TRANSFER_SIZE] = [0; TRANSFER_SIZE];
+        (&mut BUF, &mut BLOCK_BUF)
+    };};
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct UsbDfuComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str],
+    targets: &'static [Target<'static>],
+    mode: Mode,
+    board_kernel: &'static kernel::Kernel,
+    reset: Option<&'static (dyn Fn() + 'static)>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbDfuComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str],
+        targets: &'static [Target<'static>],
+        mode: Mode,
+        board_kernel: &'static kernel::Kernel,
+        reset: Option<&'static (dyn Fn() + 'static)>,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            targets,
+            mode,
+            board_kernel,
+            reset,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbDfuComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<Dfu<'static, U, Capability>>,
+        &'static mut [u8; TRANSFER_SIZE],
+    );
+    type Output = &'static Dfu<'static, U, Capability>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let dfu = static_init_half!(
+            s.0,
+            Dfu<'static, U, Capability>,
+            Dfu::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.targets,
+                self.mode,
+                s.1,
+                self.board_kernel,
+                Capability,
+                self.reset,
+            )
+        );
+        self.usb.set_client(dfu);
+        for target in self.targets {
+            target.storage.set_client(dfu);
+        }
+
+        dfu
+    }
+}
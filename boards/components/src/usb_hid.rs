@@ -0,0 +1,119 @@
+//! Component for a USB HID device, such as a keyboard or a mouse, and its
+//! syscall driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Macro Pad",    // Product
+//!     "Serial No. 5", // Serial number
+//! ];
+//!
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::hid::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//!     &capsules::usb::hid::KEYBOARD,
+//!     board_kernel,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::hid_driver::HidDriver;
+use capsules::usb::hid::{Hid, HidDescriptors, MAX_REPORT_LEN};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::hil::usb_hid::ReportDevice;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_hid_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::hid_driver::HidDriver;
+        use capsules::usb::hid::{Hid, MAX_REPORT_LEN};
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<Hid<'static, $U>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<HidDriver<'static>> = MaybeUninit::uninit();
+        static mut REPORT_BUF: [u8; MAX_REPORT_LEN] = [0; MAX_REPORT_LEN];
+        (&mut BUF1, &mut BUF2, &mut REPORT_BUF)
+    };};
+}
+
+pub struct UsbHidComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    descriptors: &'static HidDescriptors,
+    board_kernel: &'static kernel::Kernel,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbHidComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        descriptors: &'static HidDescriptors,
+        board_kernel: &'static kernel::Kernel,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            descriptors,
+            board_kernel,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbHidComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<Hid<'static, U>>,
+        &'static mut MaybeUninit<HidDriver<'static>>,
+        &'static mut [u8; MAX_REPORT_LEN],
+    );
+    type Output = (&'static Hid<'static, U>, &'static HidDriver<'static>);
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let hid = static_init_half!(
+            s.0,
+            Hid<'static, U>,
+            Hid::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.descriptors,
+            )
+        );
+        self.usb.set_client(hid);
+
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let hid_driver = static_init_half!(
+            s.1,
+            HidDriver<'static>,
+            HidDriver::new(hid, s.2, self.board_kernel.create_grant(&grant_cap))
+        );
+        hid.set_client(hid_driver);
+
+        (hid, hid_driver)
+    }
+}
@@ -0,0 +1,160 @@
+//! Components for the USB Mass Storage Class device.
+//!
+//! `UsbMscComponent` provides the device, which lets a host use a
+//! `BlockStorage` medium as a USB drive. `NonvolatileBlocksComponent`
+//! provides such a medium from a region of nonvolatile storage.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! static STRINGS: &'static [&str; 3] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Log Drive",    // Product
+//!     "Serial No. 5", // Serial number
+//! ];
+//! let msc_storage = components::usb_msc::NonvolatileBlocksComponent::new(
+//!     nonvolatile_storage,
+//!     0x40000, // Start address of the drive
+//!     0x1f000, // Length of the drive
+//! )
+//! .finalize(components::nonvolatile_blocks_component_helper!());
+//!
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     &nrf52::usbd::USBD,
+//!     capsules::usb::msc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x2341,
+//!     0x005a,
+//!     STRINGS,
+//!     msc_storage,
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52::usbd::Usbd));
+//!
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::usb::msc::{BlockStorage, MassStorage, NonvolatileBlocks, BLOCK_SIZE};
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage;
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! usb_msc_component_helper {
+    ($U:ty $(,)?) => {{
+        use capsules::usb::msc::{MassStorage, BLOCK_SIZE};
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<MassStorage<'static, $U>> = MaybeUninit::uninit();
+        static mut BLOCK_BUF: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+        (&mut BUF, &mut BLOCK_BUF)
+    };};
+}
+
+pub struct UsbMscComponent<U: 'static + hil::usb::UsbController<'static>> {
+    usb: &'static U,
+    max_ctrl_packet_size: u8,
+    vendor_id: u16,
+    product_id: u16,
+    strings: &'static [&'static str; 3],
+    storage: &'static dyn BlockStorage<'static>,
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> UsbMscComponent<U> {
+    pub fn new(
+        usb: &'static U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'static dyn BlockStorage<'static>,
+    ) -> Self {
+        Self {
+            usb,
+            max_ctrl_packet_size,
+            vendor_id,
+            product_id,
+            strings,
+            storage,
+        }
+    }
+}
+
+impl<U: 'static + hil::usb::UsbController<'static>> Component for UsbMscComponent<U> {
+    type StaticInput = (
+        &'static mut MaybeUninit<MassStorage<'static, U>>,
+        &'static mut [u8; BLOCK_SIZE],
+    );
+    type Output = &'static MassStorage<'static, U>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let msc = static_init_half!(
+            s.0,
+            MassStorage<'static, U>,
+            MassStorage::new(
+                self.usb,
+                self.max_ctrl_packet_size,
+                self.vendor_id,
+                self.product_id,
+                self.strings,
+                self.storage,
+                s.1,
+            )
+        );
+        self.usb.set_client(msc);
+        self.storage.set_client(msc);
+
+        msc
+    }
+}
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! nonvolatile_blocks_component_helper {
+    () => {{
+        use capsules::usb::msc::NonvolatileBlocks;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<NonvolatileBlocks<'static>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+pub struct NonvolatileBlocksComponent {
+    storage: &'static dyn NonvolatileStorage<'static>,
+    address: usize,
+    length: usize,
+}
+
+impl NonvolatileBlocksComponent {
+    /// The medium takes `storage` for itself, and uses `length` bytes from
+    /// `address`, rounded down to whole blocks.
+    pub fn new(
+        storage: &'static dyn NonvolatileStorage<'static>,
+        address: usize,
+        length: usize,
+    ) -> Self {
+        Self {
+            storage,
+            address,
+            length,
+        }
+    }
+}
+
+impl Component for NonvolatileBlocksComponent {
+    type StaticInput = &'static mut MaybeUninit<NonvolatileBlocks<'static>>;
+    type Output = &'static NonvolatileBlocks<'static>;
+
+    unsafe fn finalize(self, s: Self::StaticInput) -> Self::Output {
+        let blocks = static_init_half!(
+            s,
+            NonvolatileBlocks<'static>,
+            NonvolatileBlocks::new(self.storage, self.address, self.length)
+        );
+        self.storage.set_client(blocks);
+
+        blocks
+    }
+}
@@ -13,7 +13,8 @@ mod imix_components;
 use capsules::alarm::AlarmDriver;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::virtual_aes_ccm::MuxAES128CCM;
+use capsules::virtual_aes::VirtualAES128;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
 use capsules::virtual_alarm::VirtualMuxAlarm;
 use capsules::virtual_i2c::MuxI2C;
 use capsules::virtual_spi::VirtualSpiMasterDevice;
@@ -25,7 +26,7 @@ use kernel::hil::i2c::I2CMaster;
 use kernel::hil::radio;
 #[allow(unused_imports)]
 use kernel::hil::radio::{RadioConfig, RadioData};
-use kernel::hil::symmetric_encryption::AES128;
+use kernel::hil::symmetric_encryption::{AES128, AES128_BLOCK_SIZE};
 //use kernel::hil::time::Alarm;
 use kernel::hil::led::LedHigh;
 use kernel::hil::Controller;
@@ -119,6 +120,7 @@ struct Imix {
         &'static capsules::led::LedDriver<'static, LedHigh<'static, sam4l::gpio::GPIOPin<'static>>>,
     button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin<'static>>,
     rng: &'static capsules::rng::RngDriver<'static>,
+    aes: &'static capsules::aes::AesDriver<VirtualAES128<'static, sam4l::aes::Aes<'static>>>,
     analog_comparator: &'static capsules::analog_comparator::AnalogComparator<
         'static,
         sam4l::acifc::Acifc<'static>,
@@ -131,6 +133,10 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -175,9 +181,11 @@ impl kernel::Platform for Imix {
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules::aes::DRIVER_NUM => f(Some(self.aes)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -448,17 +456,38 @@ pub unsafe fn reset_handler() {
     let serial_num_bottom_16 = (serial_num.get_lower_64() & 0x0000_0000_0000_ffff) as u16;
     let src_mac_from_serial_num: MacAddress = MacAddress::Short(serial_num_bottom_16);
 
+    // The AES engine is shared between 802.15.4 link-layer security and the
+    // userspace driver.
+    let aes_hw_mux = components::aes::AesMuxComponent::new(&peripherals.aes)
+        .finalize(components::aes_mux_component_helper!(sam4l::aes::Aes));
+    aes_hw_mux.enable_ecb();
+    let ccm_aes = components::aes::VirtualAesComponent::new(aes_hw_mux)
+        .finalize(components::virtual_aes_component_helper!(sam4l::aes::Aes));
+
     let aes_mux = static_init!(
-        MuxAES128CCM<'static, sam4l::aes::Aes>,
-        MuxAES128CCM::new(&peripherals.aes, dynamic_deferred_caller)
+        MuxAES128CCM<'static, VirtualAES128<'static, sam4l::aes::Aes>>,
+        MuxAES128CCM::new(ccm_aes, dynamic_deferred_caller)
     );
-    peripherals.aes.set_client(aes_mux);
+    ccm_aes.set_client(aes_mux);
     aes_mux.initialize_callback_handle(
         dynamic_deferred_caller
             .register(aes_mux)
             .expect("no deferred call slot available for ccm mux"),
     );
 
+    let user_ccm_buf = static_init!(
+        [u8; 3 * AES128_BLOCK_SIZE + components::aes::AES_DRIVER_BUF_LEN],
+        [0; 3 * AES128_BLOCK_SIZE + components::aes::AES_DRIVER_BUF_LEN]
+    );
+    let user_ccm = static_init!(
+        VirtualAES128CCM<'static, VirtualAES128<'static, sam4l::aes::Aes>>,
+        VirtualAES128CCM::new(aes_mux, user_ccm_buf)
+    );
+    user_ccm.setup();
+    let aes = components::aes::AesDriverComponent::new(board_kernel, aes_hw_mux)
+        .with_ccm(user_ccm)
+        .finalize(components::aes_driver_component_helper!(sam4l::aes::Aes));
+
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
     let (radio_driver, mux_mac) = components::ieee802154::Ieee802154Component::new(
@@ -471,11 +500,38 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::ieee802154_component_helper!(
         capsules::rf233::RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
-        sam4l::aes::Aes<'static>
+        VirtualAES128<'static, sam4l::aes::Aes<'static>>
     ));
 
     let usb_driver = UsbComponent::new(board_kernel, &peripherals.usbc).finalize(());
 
+    // To make the board a USB keyboard instead, which processes drive through
+    // `capsules::hid_driver::DRIVER_NUM` once `hid_driver` is added to
+    // `Imix`, replace the USB driver above with:
+    //
+    // let strings = static_init!(
+    //     [&str; 3],
+    //     [
+    //         "University of California", // Manufacturer
+    //         "imix - TockOS",            // Product
+    //         "serial0001",               // Serial number
+    //     ]
+    // );
+    // let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+    //     &peripherals.usbc,
+    //     capsules::usb::hid::MAX_CTRL_PACKET_SIZE_SAM4L,
+    //     0x6667,
+    //     0xabce,
+    //     strings,
+    //     &capsules::usb::hid::KEYBOARD,
+    //     board_kernel,
+    // )
+    // .finalize(components::usb_hid_component_helper!(
+    //     sam4l::usbc::Usbc<'static>
+    // ));
+    // kernel::hil::usb::Client::enable(hid);
+    // kernel::hil::usb::Client::attach(hid);
+
     // Kernel storage region, allocated with the storage_volume!
     // macro in common/utils.rs
     extern "C" {
@@ -511,17 +567,18 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num, //comment out for dual rx test only
-        //MacAddress::Short(49138), //comment in for dual rx test only
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num, //comment out for dual rx test only
+            //MacAddress::Short(49138), //comment in for dual rx test only
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -533,6 +590,18 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    let ping_driver = components::ping::PingComponent::new(board_kernel, icmp_responder, mux_alarm)
+        .finalize(components::ping_component_helper!(sam4l::ast::Ast));
+
+    // Look for a 6LoWPAN border router, and configure an address from its
+    // prefix
+    let _sixlowpan_nd = components::sixlowpan_nd::SixlowpanNdComponent::new(
+        icmp_responder,
+        mux_alarm,
+        src_mac_from_serial_num,
+    )
+    .finalize(components::sixlowpan_nd_component_helper!(sam4l::ast::Ast));
+
     let imix = Imix {
         pconsole,
         console,
@@ -545,6 +614,7 @@ pub unsafe fn reset_handler() {
         led,
         button,
         rng,
+        aes,
         analog_comparator,
         crc,
         spi: spi_syscalls,
@@ -552,6 +622,7 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        ping_driver,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
@@ -85,6 +85,10 @@ pub unsafe fn run(
         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
         VirtualMuxAlarm::new(mux_alarm)
     );
+    let sixlo_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
     let sixlowpan = static_init!(
         Sixlowpan<
             'static,
@@ -98,9 +102,10 @@ pub unsafe fn run(
                 id: 0,
                 compress: false,
             },
-            ipsender_virtual_alarm
+            sixlo_alarm
         )
     );
+    sixlo_alarm.set_alarm_client(sixlowpan);
 
     let sixlowpan_state = sixlowpan as &dyn SixlowpanState;
     let sixlowpan_tx = TxState::new(sixlowpan_state);
@@ -11,6 +11,7 @@ use kernel::capabilities;
 use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::common::StaticRef;
 use kernel::component::Component;
+use kernel::hil::ethernet::MacAddress;
 use kernel::hil::time::{Alarm, Frequency, Timer};
 use kernel::Chip;
 use kernel::InterruptService;
@@ -71,6 +72,10 @@ impl InterruptService<()> for LiteXArtyInterruptablePeripherals {
 
 const NUM_PROCS: usize = 4;
 
+/// MAC address of the Ethernet interface, the default address of the LiteX
+/// BIOS.
+const ETHMAC0_ADDRESS: [u8; 6] = [0x10, 0xe2, 0xd5, 0x00, 0x00, 0x00];
+
 // Actual memory for holding the active process structures. Need an
 // empty list at least.
 static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
@@ -353,6 +358,7 @@ pub unsafe fn reset_handler() {
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
             ethmac0_rxbuf0,
+            MacAddress::new(ETHMAC0_ADDRESS),
         )
     );
 
@@ -10,6 +10,7 @@ use kernel::capabilities;
 use kernel::common::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
 use kernel::common::StaticRef;
 use kernel::component::Component;
+use kernel::hil::ethernet::MacAddress;
 use kernel::hil::time::{Alarm, Timer};
 use kernel::Chip;
 use kernel::InterruptService;
@@ -71,6 +72,10 @@ impl InterruptService<()> for LiteXSimInterruptablePeripherals {
 
 const NUM_PROCS: usize = 4;
 
+/// MAC address of the Ethernet interface, the default address of the LiteX
+/// BIOS.
+const ETHMAC0_ADDRESS: [u8; 6] = [0x10, 0xe2, 0xd5, 0x00, 0x00, 0x00];
+
 // Actual memory for holding the active process structures. Need an
 // empty list at least.
 static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; NUM_PROCS] =
@@ -317,12 +322,25 @@ pub unsafe fn reset_handler() {
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
             ethmac0_rxbuf0,
+            MacAddress::new(ETHMAC0_ADDRESS),
         )
     );
 
     // Initialize the ETHMAC controller
     ethmac0.initialize();
 
+    // IPv6 over the ETHMAC, answering Neighbor Discovery for the
+    // link-local address derived from the MAC address
+    let _ip6_ethernet = components::ipv6_ethernet::IP6EthernetComponent::new(ethmac0, mux_alarm)
+        .finalize(components::ipv6_ethernet_component_helper!(
+            litex_vexriscv::timer::LiteXAlarm<
+                'static,
+                'static,
+                socc::SoCRegisterFmt,
+                socc::ClockFrequency,
+            >
+        ));
+
     // ---------- INITIALIZE CHIP, ENABLE INTERRUPTS ----------
 
     let interrupt_service = static_init!(
@@ -149,6 +149,10 @@ pub struct Platform {
         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
     ieee802154_radio: &'static capsules::ieee802154::RadioDriver<'static>,
+    mlme_driver: &'static capsules::ieee802154::mlme::MlmeDriver<
+        'static,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     button: &'static capsules::button::Button<'static, nrf52840::gpio::GPIOPin<'static>>,
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
@@ -173,6 +177,15 @@ pub struct Platform {
     >,
     nonvolatile_storage: &'static capsules::nonvolatile_storage_driver::NonvolatileStorage<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    public_key_crypto: &'static capsules::public_key_crypto::PublicKeyDriver<'static>,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
+    ping_driver: &'static capsules::net::icmpv6::PingDriver<
+        'static,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
 }
 
 impl kernel::Platform for Platform {
@@ -189,10 +202,14 @@ impl kernel::Platform for Platform {
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_radio)),
+            capsules::ieee802154::mlme::DRIVER_NUM => f(Some(self.mlme_driver)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
             capsules::analog_comparator::DRIVER_NUM => f(Some(self.analog_comparator)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.ping_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
+            capsules::public_key_crypto::DRIVER_NUM => f(Some(self.public_key_crypto)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -338,7 +355,7 @@ pub unsafe fn reset_handler() {
     .finalize(());
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 3], Default::default());
+        static_init!([DynamicDeferredCallClientState; 6], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -390,6 +407,10 @@ pub unsafe fn reset_handler() {
         nrf52840::aes::AesECB<'static>
     ));
 
+    let mlme_driver =
+        components::ieee802154_mlme::MlmeComponent::new(board_kernel, mux_mac, mux_alarm)
+            .finalize(components::mlme_component_helper!(nrf52840::rtc::Rtc));
+
     let local_ip_ifaces = static_init!(
         [IPAddr; 3],
         [
@@ -407,16 +428,17 @@ pub unsafe fn reset_handler() {
         ]
     );
 
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            DST_MAC_ADDR,
+            src_mac_from_serial_num,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -428,11 +450,69 @@ pub unsafe fn reset_handler() {
     )
     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
 
+    let ping_driver = components::ping::PingComponent::new(board_kernel, icmp_responder, mux_alarm)
+        .finalize(components::ping_component_helper!(nrf52840::rtc::Rtc));
+
+    let drbg =
+        components::drbg::DrbgComponent::new(&base_peripherals.trng, dynamic_deferred_caller)
+            .finalize(components::drbg_component_helper!());
+    let coap_rng = components::drbg::DrbgUserComponent::new(drbg)
+        .finalize(components::drbg_user_component_helper!());
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+        coap_rng,
+    )
+    .finalize(components::coap_component_helper!(nrf52840::rtc::Rtc));
+
+    // Look for a 6LoWPAN border router, and configure an address from its
+    // prefix
+    let _sixlowpan_nd = components::sixlowpan_nd::SixlowpanNdComponent::new(
+        icmp_responder,
+        mux_alarm,
+        src_mac_from_serial_num,
+    )
+    .finalize(components::sixlowpan_nd_component_helper!(
+        nrf52840::rtc::Rtc
+    ));
+
     let temp =
         components::temperature::TemperatureComponent::new(board_kernel, &base_peripherals.temp)
             .finalize(());
 
-    let rng = components::rng::RngComponent::new(board_kernel, &base_peripherals.trng).finalize(());
+    let rng = components::drbg::RngDriverComponent::new(board_kernel, drbg)
+        .finalize(components::drbg_user_component_helper!());
+
+    let ecdsa_p256_rng = components::drbg::DrbgUserComponent::new(drbg)
+        .finalize(components::drbg_user_component_helper!());
+    let ecdsa_p256 =
+        components::public_key_crypto::PublicKeySoftwareComponent::new(dynamic_deferred_caller)
+            .with_rng(ecdsa_p256_rng)
+            .finalize(components::public_key_software_component_helper!(
+                capsules::public_key_crypto::software::EcdsaP256
+            ));
+    let ed25519_rng = components::drbg::DrbgUserComponent::new(drbg)
+        .finalize(components::drbg_user_component_helper!());
+    let ed25519 =
+        components::public_key_crypto::PublicKeySoftwareComponent::new(dynamic_deferred_caller)
+            .with_rng(ed25519_rng)
+            .finalize(components::public_key_software_component_helper!(
+                capsules::public_key_crypto::software::Ed25519
+            ));
+    let public_key_crypto =
+        components::public_key_crypto::PublicKeyDriverComponent::new(board_kernel)
+            .with_engine(
+                capsules::public_key_crypto::driver::Algorithm::EcdsaP256,
+                ecdsa_p256,
+            )
+            .with_engine(
+                capsules::public_key_crypto::driver::Algorithm::Ed25519,
+                ed25519,
+            )
+            .finalize(components::public_key_driver_component_helper!());
 
     // SPI
     let mux_spi = components::spi::SpiMuxComponent::new(&base_peripherals.spim0)
@@ -509,22 +589,320 @@ pub unsafe fn reset_handler() {
     //     ]
     // );
 
-    // let ctap_send_buffer = static_init!([u8; 64], [0; 64]);
-    // let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
-
-    // let (ctap, _ctap_driver) = components::ctap::CtapComponent::new(
-    //     &peripherals.usbd,
+    // let (ctap, ctap_transport, ctap_driver) = components::ctap::CtapComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     mux_alarm,
     //     0x1915, // Nordic Semiconductor
     //     0x503a, // lowRISC generic FS USB
     //     strings,
     //     board_kernel,
-    //     ctap_send_buffer,
-    //     ctap_recv_buffer,
     // )
-    // .finalize(components::usb_ctap_component_helper!(nrf52840::usbd::Usbd));
+    // .finalize(components::usb_ctap_component_helper!(
+    //     nrf52840::usbd::Usbd,
+    //     nrf52840::rtc::Rtc
+    // ));
+
+    // // To answer CTAP2 requests in the kernel, with the credentials at the
+    // // end of the kernel region of the external flash, and Button 4 (which
+    // // must then be removed from the button driver) to confirm presence.
+    // let fido_rng = components::drbg::DrbgUserComponent::new(drbg)
+    //     .finalize(components::drbg_user_component_helper!());
+    // let authenticator = components::ctap::FidoAuthenticatorComponent::new(
+    //     ctap_transport,
+    //     mux_alarm,
+    //     fido_rng,
+    //     nonvolatile_storage,
+    //     0x5f000, // Storage address of the credentials
+    //     [0; 16], // AAGUID
+    //     &gpio_port[BUTTON4_PIN],
+    //     kernel::hil::gpio::ActivationMode::ActiveLow,
+    // )
+    // .with_fallback(ctap_driver)
+    // .finalize(components::fido_authenticator_component_helper!(
+    //     nrf52840::rtc::Rtc
+    // ));
+    // authenticator.load();
 
     // ctap.enable();
     // ctap.attach();
+    // ctap_transport.start();
+
+    //--------------------------------------------------------------------------
+    // USB MASS STORAGE EXAMPLE
+    //--------------------------------------------------------------------------
+    // Uncomment to experiment with this. The drive uses the start of the kernel
+    // region of the external flash, which nothing else must use.
+
+    // let strings = static_init!(
+    //     [&str; 3],
+    //     [
+    //         "Nordic Semiconductor", // Manufacturer
+    //         "nRF52840dk - TockOS",  // Product
+    //         "serial0001",           // Serial number
+    //     ]
+    // );
+
+    // let msc_storage = components::usb_msc::NonvolatileBlocksComponent::new(
+    //     nonvolatile_storage,
+    //     0x00000, // Start address of the drive
+    //     0x40000, // Length of the drive
+    // )
+    // .finalize(components::nonvolatile_blocks_component_helper!());
+
+    // let msc = components::usb_msc::UsbMscComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     capsules::usb::msc::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     msc_storage,
+    // )
+    // .finalize(components::usb_msc_component_helper!(nrf52840::usbd::Usbd));
+
+    // msc.enable();
+    // msc.attach();
+
+    //--------------------------------------------------------------------------
+    // USB COMPOSITE EXAMPLE
+    //--------------------------------------------------------------------------
+    // Uncomment to experiment with this. The board is both the security key of
+    // the CTAP example and the drive of the mass storage example, so those
+    // must stay commented out.
+
+    // let strings = static_init!(
+    //     [&str; 3],
+    //     [
+    //         "Nordic Semiconductor", // Manufacturer
+    //         "nRF52840dk - TockOS",  // Product
+    //         "serial0001",           // Serial number
+    //     ]
+    // );
+
+    // let composite = components::usb_composite::UsbCompositeComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     capsules::usb::composite::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    // )
+    // .finalize(components::usb_composite_component_helper!(
+    //     nrf52840::usbd::Usbd
+    // ));
+
+    // let ctap_usb = components::usb_composite::CompositeFunctionComponent::new(composite)
+    //     .finalize(components::composite_function_component_helper!(
+    //         nrf52840::usbd::Usbd
+    //     ));
+    // let (_ctap, ctap_transport, _ctap_driver) = components::ctap::CtapComponent::new(
+    //     ctap_usb,
+    //     mux_alarm,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     board_kernel,
+    // )
+    // .finalize(components::usb_ctap_component_helper!(
+    //     capsules::usb::composite::CompositeFunction<'static, nrf52840::usbd::Usbd>,
+    //     nrf52840::rtc::Rtc
+    // ));
+
+    // let msc_usb = components::usb_composite::CompositeFunctionComponent::new(composite)
+    //     .finalize(components::composite_function_component_helper!(
+    //         nrf52840::usbd::Usbd
+    //     ));
+    // let msc_storage = components::usb_msc::NonvolatileBlocksComponent::new(
+    //     nonvolatile_storage,
+    //     0x00000, // Start address of the drive
+    //     0x40000, // Length of the drive
+    // )
+    // .finalize(components::nonvolatile_blocks_component_helper!());
+    // let _msc = components::usb_msc::UsbMscComponent::new(
+    //     msc_usb,
+    //     capsules::usb::msc::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     msc_storage,
+    // )
+    // .finalize(components::usb_msc_component_helper!(
+    //     capsules::usb::composite::CompositeFunction<'static, nrf52840::usbd::Usbd>
+    // ));
+
+    // composite.enable();
+    // composite.attach();
+    // ctap_transport.start();
+
+    //--------------------------------------------------------------------------
+    // USB DFU EXAMPLE
+    //--------------------------------------------------------------------------
+    // Uncomment to experiment with this. The host can replace the applications
+    // in the internal flash with `dfu-util -a 0 -D apps.tbf`, or stage a kernel
+    // at the start of the kernel region of the external flash, which nothing
+    // else must use, with `dfu-util -a 1 -D tock.bin`.
+
+    // let strings = static_init!(
+    //     [&str; 5],
+    //     [
+    //         "Nordic Semiconductor", // Manufacturer
+    //         "nRF52840dk - TockOS",  // Product
+    //         "serial0001",           // Serial number
+    //         "Applications",         // Alternate setting 0
+    //         "Kernel",               // Alternate setting 1
+    //     ]
+    // );
+
+    // let nvmc_page = static_init!(nrf52840::nvmc::NrfPage, nrf52840::nvmc::NrfPage::default());
+    // let internal_flash = static_init!(
+    //     capsules::nonvolatile_to_pages::NonvolatileToPages<'static, nrf52840::nvmc::Nvmc>,
+    //     capsules::nonvolatile_to_pages::NonvolatileToPages::new(
+    //         &base_peripherals.nvmc,
+    //         nvmc_page
+    //     )
+    // );
+    // kernel::hil::flash::HasClient::set_client(&base_peripherals.nvmc, internal_flash);
+
+    // let app_flash = core::slice::from_raw_parts(
+    //     &_sapps as *const u8,
+    //     &_eapps as *const u8 as usize - &_sapps as *const u8 as usize,
+    // );
+    // let dfu_targets = static_init!(
+    //     [capsules::usb::dfu::Target<'static>; 2],
+    //     [
+    //         capsules::usb::dfu::Target {
+    //             storage: internal_flash,
+    //             address: app_flash.as_ptr() as usize,
+    //             length: app_flash.len(),
+    //             image: capsules::usb::dfu::Image::Apps(app_flash),
+    //         },
+    //         capsules::usb::dfu::Target {
+    //             storage: nonvolatile_storage,
+    //             address: 0x00000,
+    //             length: 0x30000,
+    //             image: capsules::usb::dfu::Image::Kernel,
+    //         },
+    //     ]
+    // );
+
+    // let dfu = components::usb_dfu::UsbDfuComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     capsules::usb::dfu::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     dfu_targets,
+    //     capsules::usb::dfu::Mode::Runtime,
+    //     board_kernel,
+    //     Some(&|| cortexm4::scb::reset()),
+    // )
+    // .finalize(components::usb_dfu_component_helper!(nrf52840::usbd::Usbd));
+
+    // dfu.enable();
+    // dfu.attach();
+
+    //--------------------------------------------------------------------------
+    // USB HID EXAMPLE
+    //--------------------------------------------------------------------------
+    // Uncomment to experiment with this. Processes type on the keyboard
+    // through `capsules::hid_driver::DRIVER_NUM` once `hid_driver` is added to
+    // `Platform`. `capsules::usb::hid::MOUSE` makes the board a mouse instead.
+
+    // let strings = static_init!(
+    //     [&str; 3],
+    //     [
+    //         "Nordic Semiconductor", // Manufacturer
+    //         "nRF52840dk - TockOS",  // Product
+    //         "serial0001",           // Serial number
+    //     ]
+    // );
+
+    // let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     capsules::usb::hid::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     &capsules::usb::hid::KEYBOARD,
+    //     board_kernel,
+    // )
+    // .finalize(components::usb_hid_component_helper!(nrf52840::usbd::Usbd));
+
+    // hid.enable();
+    // hid.attach();
+
+    //--------------------------------------------------------------------------
+    // USB NETWORK EXAMPLE
+    //--------------------------------------------------------------------------
+    // Uncomment to experiment with this. The board appears to Linux and macOS
+    // hosts as a USB Ethernet adapter, and processes use UDP and CoAP over it
+    // instead of 802.15.4: remove the setup of the UDP mux, UDP driver, ping
+    // and CoAP drivers above, which this replaces. The host reaches the board
+    // at its link-local address, fe80::a1:b2ff:fec3:d4e6.
+
+    // let strings = static_init!(
+    //     [&str; 4],
+    //     [
+    //         "Nordic Semiconductor", // Manufacturer
+    //         "nRF52840dk - TockOS",  // Product
+    //         "serial0001",           // Serial number
+    //         "02A1B2C3D4E5",         // Host MAC address
+    //     ]
+    // );
+    // let mac_address = kernel::hil::ethernet::MacAddress::new([0x02, 0xa1, 0xb2, 0xc3, 0xd4, 0xe6]);
+
+    // let ecm = components::usb_cdc_ecm::UsbCdcEcmComponent::new(
+    //     &nrf52840_peripherals.usbd,
+    //     capsules::usb::cdc_ecm::MAX_CTRL_PACKET_SIZE_NRF52840,
+    //     0x1915, // Nordic Semiconductor
+    //     0x503a, // lowRISC generic FS USB
+    //     strings,
+    //     mac_address,
+    // )
+    // .finalize(components::usb_cdc_ecm_component_helper!(nrf52840::usbd::Usbd));
+
+    // let ip6_ethernet = components::ipv6_ethernet::IP6EthernetComponent::new(ecm, mux_alarm)
+    //     .finalize(components::ipv6_ethernet_component_helper!(nrf52840::rtc::Rtc));
+    // let (udp_send_mux, udp_recv_mux, udp_port_table, icmp_responder) =
+    //     components::udp_mux_ethernet::UDPMuxEthernetComponent::new(ip6_ethernet, mux_alarm)
+    //         .finalize(components::udp_mux_ethernet_component_helper!(nrf52840::rtc::Rtc));
+
+    // let local_ip_ifaces = static_init!(
+    //     [IPAddr; 1],
+    //     [IPAddr::generate_from_ethernet_mac(mac_address)]
+    // );
+    // let udp_driver = components::udp_driver::UDPDriverComponent::new(
+    //     board_kernel,
+    //     udp_send_mux,
+    //     udp_recv_mux,
+    //     udp_port_table,
+    //     local_ip_ifaces,
+    // )
+    // .finalize(components::udp_driver_component_helper!(
+    //     nrf52840::rtc::Rtc,
+    //     capsules::net::ipv6::ipv6_ethernet::IP6Ethernet<
+    //         'static,
+    //         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+    //     >
+    // ));
+    // let ping_driver = components::ping::PingComponent::new(board_kernel, icmp_responder, mux_alarm)
+    //     .finalize(components::ping_component_helper!(nrf52840::rtc::Rtc));
+    // let coap_driver = components::coap::CoapComponent::new(
+    //     board_kernel,
+    //     udp_send_mux,
+    //     udp_recv_mux,
+    //     udp_port_table,
+    //     mux_alarm,
+    //     coap_rng,
+    // )
+    // .finalize(components::coap_component_helper!(
+    //     nrf52840::rtc::Rtc,
+    //     capsules::net::ipv6::ipv6_ethernet::IP6Ethernet<
+    //         'static,
+    //         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc>,
+    //     >
+    // ));
+
+    // ecm.enable();
+    // ecm.attach();
 
     let platform = Platform {
         button,
@@ -540,6 +918,10 @@ pub unsafe fn reset_handler() {
         analog_comparator,
         nonvolatile_storage,
         udp_driver,
+        ping_driver,
+        coap_driver,
+        mlme_driver,
+        public_key_crypto,
         ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_capability),
     };
 
@@ -0,0 +1,781 @@
+//! Provides userspace access to AES encryption and decryption.
+//!
+//! The driver supports the ECB, CBC and CTR block cipher modes through a
+//! (virtualized) `AES128` engine, and the CCM and GCM authenticated modes
+//! through optional `AES128CCM` and `AES128GCM` implementations.
+//!
+//! Keys can be given to each operation through an allowed buffer, or stored
+//! in the kernel and referenced by a handle, so that the process never has
+//! to hold the key bytes. Stored keys are either imported by a process, in
+//! which case only that process can use or delete them, or provisioned by
+//! the board with `provision_key()`, in which case every process can use
+//! them. The slots of a process which is gone are erased and reused when a
+//! key needs to be stored.
+//!
+//! Data is moved through a kernel buffer: the block cipher modes process
+//! messages of any length in chunks of the buffer size, while a CCM or GCM
+//! operation must fit whole in the buffer, along with its additional data
+//! and tag.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let aes_buf = static_init!([u8; 256], [0; 256]);
+//! let aes = static_init!(
+//!     capsules::aes::AesDriver<VirtualAES128<'static, sam4l::aes::Aes>>,
+//!     capsules::aes::AesDriver::new(
+//!         virtual_aes,
+//!         aes_buf,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! virtual_aes.set_client(aes);
+//! aes.set_gcm(gcm);
+//! gcm.set_client(aes);
+//! ```
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::Aes as usize;
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB, AES128GCM, AES128_BLOCK_SIZE,
+    AES128_KEY_SIZE, CCM_NONCE_LENGTH, GCM_IV_LENGTH, GCM_TAG_LENGTH,
+};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// The number of keys that can be stored in the kernel.
+pub const NUM_KEY_SLOTS: usize = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+    Ccm,
+    Gcm,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum KeyOwner {
+    Board,
+    App(AppId),
+}
+
+struct KeySlot {
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    owner: Cell<Option<KeyOwner>>,
+}
+
+impl KeySlot {
+    fn usable_by(&self, appid: AppId) -> bool {
+        match self.owner.get() {
+            Some(KeyOwner::Board) => true,
+            Some(KeyOwner::App(owner)) => owner == appid,
+            None => false,
+        }
+    }
+}
+
+pub struct AesDriver<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB> {
+    aes: &'static A,
+    ccm: OptionalCell<&'static dyn AES128CCM<'static>>,
+    gcm: OptionalCell<&'static dyn AES128GCM<'static>>,
+
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    buffer: TakeCell<'static, [u8]>,
+    keys: [KeySlot; NUM_KEY_SLOTS],
+
+    /// The mode of the current operation.
+    mode: Cell<Mode>,
+    /// The length of the current message, and how much of it is done.
+    length: Cell<usize>,
+    position: Cell<usize>,
+    /// The length of the chunk in the kernel buffer or, for CCM and GCM,
+    /// of the output.
+    chunk: Cell<usize>,
+    /// The length of the additional data of a CCM or GCM operation.
+    aad_len: Cell<usize>,
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB> AesDriver<A> {
+    pub fn new(aes: &'static A, buffer: &'static mut [u8], grant: Grant<App>) -> AesDriver<A> {
+        AesDriver {
+            aes: aes,
+            ccm: OptionalCell::empty(),
+            gcm: OptionalCell::empty(),
+            apps: grant,
+            appid: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            keys: Default::default(),
+            mode: Cell::new(Mode::Ctr),
+            length: Cell::new(0),
+            position: Cell::new(0),
+            chunk: Cell::new(0),
+            aad_len: Cell::new(0),
+        }
+    }
+
+    /// Enables the CCM mode, which uses `ccm`. The driver must be set as
+    /// the client of `ccm`.
+    pub fn set_ccm(&self, ccm: &'static dyn AES128CCM<'static>) {
+        self.ccm.set(ccm);
+    }
+
+    /// Enables the GCM mode, which uses `gcm`. The driver must be set as
+    /// the client of `gcm`.
+    pub fn set_gcm(&self, gcm: &'static dyn AES128GCM<'static>) {
+        self.gcm.set(gcm);
+    }
+
+    /// Stores a key that every process can use, and returns its handle.
+    pub fn provision_key(&self, key: &[u8]) -> Result<usize, ReturnCode> {
+        self.store_key(key, KeyOwner::Board)
+    }
+
+    /// Stores `key` in a free slot. Slots of processes that are gone are
+    /// erased and freed first.
+    fn store_key(&self, key: &[u8], owner: KeyOwner) -> Result<usize, ReturnCode> {
+        if key.len() < AES128_KEY_SIZE {
+            return Err(ReturnCode::EINVAL);
+        }
+        for slot in self.keys.iter() {
+            if let Some(KeyOwner::App(appid)) = slot.owner.get() {
+                if self.apps.enter(appid, |_, _| ()).is_err() {
+                    slot.key.set([0; AES128_KEY_SIZE]);
+                    slot.owner.set(None);
+                }
+            }
+        }
+        let handle = self
+            .keys
+            .iter()
+            .position(|slot| slot.owner.get().is_none())
+            .ok_or(ReturnCode::ENOMEM)?;
+        let mut k = [0; AES128_KEY_SIZE];
+        k.copy_from_slice(&key[..AES128_KEY_SIZE]);
+        self.keys[handle].key.set(k);
+        self.keys[handle].owner.set(Some(owner));
+        Ok(handle)
+    }
+
+    /// Returns the key of the operation, from the selected handle or else
+    /// from the allowed key buffer.
+    fn key(&self, app: &App, appid: AppId) -> Result<[u8; AES128_KEY_SIZE], ReturnCode> {
+        match app.key_handle {
+            Some(handle) => self
+                .keys
+                .get(handle)
+                .filter(|slot| slot.usable_by(appid))
+                .map(|slot| slot.key.get())
+                .ok_or(ReturnCode::EINVAL),
+            None => {
+                let mut key = [0; AES128_KEY_SIZE];
+                match app.key {
+                    Some(ref slice) if slice.len() >= AES128_KEY_SIZE => {
+                        key.copy_from_slice(&slice.as_ref()[..AES128_KEY_SIZE]);
+                        Ok(key)
+                    }
+                    _ => Err(ReturnCode::ERESERVE),
+                }
+            }
+        }
+    }
+
+    /// Returns the first `len` bytes of the allowed IV buffer.
+    fn iv<'b>(&self, app: &'b App, len: usize) -> Result<&'b [u8], ReturnCode> {
+        match app.iv {
+            Some(ref slice) if slice.len() >= len => Ok(&slice.as_ref()[..len]),
+            _ => Err(ReturnCode::ERESERVE),
+        }
+    }
+
+    /// Starts an operation on the first `len` bytes of the source buffer of
+    /// `app`.
+    fn start(&self, app: &App, appid: AppId, encrypting: bool, len: usize) -> ReturnCode {
+        let mode = match app.mode {
+            Some(mode) => mode,
+            None => return ReturnCode::ERESERVE,
+        };
+        let key = match self.key(app, appid) {
+            Ok(key) => key,
+            Err(e) => return e,
+        };
+        if app.source.as_ref().map_or(0, |s| s.len()) < len {
+            return ReturnCode::ESIZE;
+        }
+        self.mode.set(mode);
+        self.length.set(len);
+        self.position.set(0);
+
+        match mode {
+            Mode::Ecb | Mode::Cbc | Mode::Ctr => {
+                if len == 0 || (mode != Mode::Ctr && len % AES128_BLOCK_SIZE != 0) {
+                    return ReturnCode::EINVAL;
+                }
+                if app.dest.as_ref().map_or(0, |d| d.len()) < len {
+                    return ReturnCode::ESIZE;
+                }
+                let res = self.aes.set_key(&key);
+                if res != ReturnCode::SUCCESS {
+                    return res;
+                }
+                if mode != Mode::Ecb {
+                    let res = self
+                        .iv(app, AES128_BLOCK_SIZE)
+                        .map_or_else(|e| e, |iv| self.aes.set_iv(iv));
+                    if res != ReturnCode::SUCCESS {
+                        return res;
+                    }
+                }
+                match mode {
+                    Mode::Ecb => self.aes.set_mode_aes128ecb(encrypting),
+                    Mode::Cbc => self.aes.set_mode_aes128cbc(encrypting),
+                    _ => self.aes.set_mode_aes128ctr(encrypting),
+                }
+                self.aes.start_message();
+                self.crypt_chunk(app)
+            }
+            Mode::Ccm | Mode::Gcm => self.start_aead(app, &key, encrypting, len),
+        }
+    }
+
+    /// Starts a CCM or GCM operation, which is done in one pass over the
+    /// kernel buffer, laid out as `[ aad | message | tag ]`.
+    fn start_aead(
+        &self,
+        app: &App,
+        key: &[u8; AES128_KEY_SIZE],
+        encrypting: bool,
+        len: usize,
+    ) -> ReturnCode {
+        let mode = self.mode.get();
+        let (tag_len, nonce_len) = if mode == Mode::Ccm {
+            (app.tag_len, CCM_NONCE_LENGTH)
+        } else {
+            (GCM_TAG_LENGTH, GCM_IV_LENGTH)
+        };
+        // The tag follows the message in the input when decrypting, and in
+        // the output when encrypting
+        let m_len = if encrypting {
+            len
+        } else if len >= tag_len {
+            len - tag_len
+        } else {
+            return ReturnCode::EINVAL;
+        };
+        let out_len = if encrypting { len + tag_len } else { m_len };
+        if app.dest.as_ref().map_or(0, |d| d.len()) < out_len {
+            return ReturnCode::ESIZE;
+        }
+        let nonce = match self.iv(app, nonce_len) {
+            Ok(nonce) => nonce,
+            Err(e) => return e,
+        };
+        let aad = app.aad.as_ref().map_or(&[][..], |a| a.as_ref());
+        let source = app.source.as_ref().map_or(&[][..], |s| s.as_ref());
+        let a_len = aad.len();
+
+        let buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        if a_len + m_len + tag_len > buf.len() {
+            self.buffer.replace(buf);
+            return ReturnCode::ESIZE;
+        }
+        buf[..a_len].copy_from_slice(aad);
+        buf[a_len..a_len + len].copy_from_slice(&source[..len]);
+        self.aad_len.set(a_len);
+        self.chunk.set(out_len);
+
+        let (res, buf) = if mode == Mode::Ccm {
+            match self.ccm.map(|ccm| *ccm) {
+                Some(ccm) => {
+                    let mut res = ccm.set_key(key);
+                    if res == ReturnCode::SUCCESS {
+                        res = ccm.set_nonce(nonce);
+                    }
+                    if res == ReturnCode::SUCCESS {
+                        ccm.crypt(buf, 0, a_len, m_len, tag_len, true, encrypting)
+                    } else {
+                        (res, Some(buf))
+                    }
+                }
+                None => (ReturnCode::ENOSUPPORT, Some(buf)),
+            }
+        } else {
+            match self.gcm.map(|gcm| *gcm) {
+                Some(gcm) => {
+                    let mut res = gcm.set_key(key);
+                    if res == ReturnCode::SUCCESS {
+                        res = gcm.set_iv(nonce);
+                    }
+                    if res == ReturnCode::SUCCESS {
+                        gcm.crypt(buf, 0, a_len, m_len, encrypting)
+                    } else {
+                        (res, Some(buf))
+                    }
+                }
+                None => (ReturnCode::ENOSUPPORT, Some(buf)),
+            }
+        };
+        buf.map(|buf| {
+            buf.iter_mut().for_each(|b| *b = 0);
+            self.buffer.replace(buf);
+        });
+        res
+    }
+
+    /// Copies the next chunk of the message to the kernel buffer and
+    /// encrypts or decrypts it.
+    fn crypt_chunk(&self, app: &App) -> ReturnCode {
+        let source = match app.source {
+            Some(ref source) => source.as_ref(),
+            None => return ReturnCode::ERESERVE,
+        };
+        let buf = match self.buffer.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let position = self.position.get();
+        let max_chunk = buf.len() - buf.len() % AES128_BLOCK_SIZE;
+        let chunk = cmp::min(self.length.get() - position, max_chunk);
+        // Only the last chunk of a CTR message can be a partial block, and
+        // its padding is discarded
+        let padded = (chunk + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+        buf[..chunk].copy_from_slice(&source[position..position + chunk]);
+        buf[chunk..padded].iter_mut().for_each(|b| *b = 0);
+        self.chunk.set(chunk);
+        match self.aes.crypt(None, buf, 0, padded) {
+            None => ReturnCode::SUCCESS,
+            Some((res, _, buf)) => {
+                self.buffer.replace(buf);
+                res
+            }
+        }
+    }
+
+    /// Ends the current operation, and notifies its process.
+    fn finish(&self, res: ReturnCode, len: usize, tag_is_valid: bool) {
+        self.buffer.map(|buf| buf.iter_mut().for_each(|b| *b = 0));
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.callback
+                    .map(|cb| cb.schedule(usize::from(res), len, tag_is_valid as usize));
+            });
+        });
+        self.check_queue();
+    }
+
+    fn check_queue(&self) {
+        for appiter in self.apps.iter() {
+            if self.appid.is_some() {
+                break;
+            }
+            appiter.enter(|app, _| {
+                let appid = app.appid();
+                if let Some((encrypting, len)) = app.pending.take() {
+                    self.appid.set(appid);
+                    let res = self.start(app, appid, encrypting, len);
+                    if res != ReturnCode::SUCCESS {
+                        self.appid.clear();
+                        app.callback.map(|cb| cb.schedule(usize::from(res), 0, 0));
+                    }
+                }
+            });
+        }
+    }
+
+    /// Copies `len` bytes of the kernel buffer from `offset` to the
+    /// destination buffer of the current process, at the current position.
+    fn copy_out(&self, buf: &[u8], offset: usize, len: usize) -> bool {
+        self.appid.map_or(false, |appid| {
+            self.apps
+                .enter(*appid, |app, _| match app.dest {
+                    Some(ref mut dest) if dest.len() >= self.position.get() + len => {
+                        let position = self.position.get();
+                        dest.as_mut()[position..position + len]
+                            .copy_from_slice(&buf[offset..offset + len]);
+                        true
+                    }
+                    _ => false,
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    /// Ends a CCM or GCM operation. When encrypting, the tag is copied out
+    /// along with the ciphertext. When decrypting, the plaintext is only
+    /// copied out if the tag is valid.
+    fn aead_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        let out_len = self.chunk.get();
+        let res = if res != ReturnCode::SUCCESS {
+            res
+        } else if !tag_is_valid {
+            ReturnCode::FAIL
+        } else if self.copy_out(buf, self.aad_len.get(), out_len) {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::ESIZE
+        };
+        self.buffer.replace(buf);
+        let len = if res == ReturnCode::SUCCESS {
+            out_len
+        } else {
+            0
+        };
+        self.finish(res, len, tag_is_valid);
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB>
+    symmetric_encryption::Client<'static> for AesDriver<A>
+{
+    fn crypt_done(&'static self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+        let chunk = self.chunk.get();
+        let copied = self.copy_out(dest, 0, chunk);
+        self.buffer.replace(dest);
+        if !copied {
+            self.finish(ReturnCode::ESIZE, 0, false);
+            return;
+        }
+        self.position.set(self.position.get() + chunk);
+        if self.position.get() == self.length.get() {
+            self.finish(ReturnCode::SUCCESS, self.length.get(), false);
+            return;
+        }
+        let res = self.appid.map_or(ReturnCode::FAIL, |appid| {
+            self.apps
+                .enter(*appid, |app, _| self.crypt_chunk(app))
+                .unwrap_or_else(|err| err.into())
+        });
+        if res != ReturnCode::SUCCESS {
+            self.finish(res, 0, false);
+        }
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB>
+    symmetric_encryption::CCMClient for AesDriver<A>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.aead_done(buf, res, tag_is_valid);
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB>
+    symmetric_encryption::GCMClient for AesDriver<A>
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.aead_done(buf, res, tag_is_valid);
+    }
+}
+
+impl<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB> Driver for AesDriver<A> {
+    /// Specify memory regions to be used.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The key, when no key handle is selected, or the key to import.
+    /// - `1`: The IV of CBC, the initial counter block of CTR, or the nonce
+    ///        of CCM (13 bytes) and GCM (12 bytes).
+    /// - `2`: The input message. When decrypting with CCM or GCM, the tag
+    ///        follows the ciphertext.
+    /// - `3`: The output message. When encrypting with CCM or GCM, the tag
+    ///        follows the ciphertext.
+    /// - `4`: The additional authenticated data of CCM and GCM.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.key = slice,
+                    1 => app.iv = slice,
+                    2 => app.source = slice,
+                    3 => app.dest = slice,
+                    4 => app.aad = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Subscribe to AES events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: ReturnCode, length: usize,
+    ///        tag_is_valid: bool)`, where `length` is the number of bytes
+    ///        written to the output buffer.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback.insert(callback);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Configure and run AES operations.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Set the mode: `0` for ECB, `1` for CBC, `2` for CTR, `3` for
+    ///        CCM and `4` for GCM. For CCM, `data2` is the tag length.
+    /// - `2`: Import the key in the key buffer into the kernel, and return
+    ///        its handle. Only this process can use the key.
+    /// - `3`: Select the key with handle `data1` for the next operations,
+    ///        or the key in the key buffer if `data1` is `usize::MAX`.
+    /// - `4`: Delete the key with handle `data1`, which this process must
+    ///        have imported.
+    /// - `5`: Encrypt (`data1` is `1`) or decrypt (`data1` is `0`) the
+    ///        first `data2` bytes of the input buffer.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            // set mode
+            1 => {
+                let mode = match data1 {
+                    0 => Mode::Ecb,
+                    1 => Mode::Cbc,
+                    2 => Mode::Ctr,
+                    3 if self.ccm.is_some() => Mode::Ccm,
+                    4 if self.gcm.is_some() => Mode::Gcm,
+                    _ => return ReturnCode::ENOSUPPORT,
+                };
+                if mode == Mode::Ccm && !(data2 >= 4 && data2 <= 16 && data2 % 2 == 0) {
+                    return ReturnCode::EINVAL;
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        if self.appid.map_or(false, |id| *id == appid) {
+                            return ReturnCode::EBUSY;
+                        }
+                        app.mode = Some(mode);
+                        app.tag_len = data2;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            // import key
+            2 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let key = app.key.as_ref().map_or(&[][..], |k| k.as_ref());
+                    match self.store_key(key, KeyOwner::App(appid)) {
+                        Ok(handle) => ReturnCode::SuccessWithValue { value: handle },
+                        Err(e) => e,
+                    }
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // select key
+            3 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if data1 == usize::MAX {
+                        app.key_handle = None;
+                    } else if self
+                        .keys
+                        .get(data1)
+                        .map_or(false, |slot| slot.usable_by(appid))
+                    {
+                        app.key_handle = Some(data1);
+                    } else {
+                        return ReturnCode::EINVAL;
+                    }
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            // delete key
+            4 => match self.keys.get(data1) {
+                Some(slot) if slot.owner.get() == Some(KeyOwner::App(appid)) => {
+                    if self.appid.map_or(false, |id| *id == appid) {
+                        return ReturnCode::EBUSY;
+                    }
+                    slot.key.set([0; AES128_KEY_SIZE]);
+                    slot.owner.set(None);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            },
+
+            // crypt
+            5 => {
+                let encrypting = data1 != 0;
+                // Release the engine if the process that owned it is gone
+                let owner_exists = self.appid.map_or(false, |owner| {
+                    self.apps.enter(*owner, |_, _| true).unwrap_or(false)
+                });
+                if !owner_exists {
+                    self.appid.clear();
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        if self.appid.is_none() {
+                            self.appid.set(appid);
+                            let res = self.start(app, appid, encrypting, data2);
+                            if res != ReturnCode::SUCCESS {
+                                self.appid.clear();
+                            }
+                            res
+                        } else if self.appid.map_or(false, |id| *id == appid)
+                            || app.pending.is_some()
+                        {
+                            ReturnCode::EBUSY
+                        } else {
+                            app.pending = Some((encrypting, data2));
+                            ReturnCode::SUCCESS
+                        }
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl Default for KeySlot {
+    fn default() -> KeySlot {
+        KeySlot {
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            owner: Cell::new(None),
+        }
+    }
+}
+
+pub struct App {
+    callback: OptionalCell<Callback>,
+    pending: Option<(bool, usize)>,
+    mode: Option<Mode>,
+    tag_len: usize,
+    key_handle: Option<usize>,
+    key: Option<AppSlice<Shared, u8>>,
+    iv: Option<AppSlice<Shared, u8>>,
+    source: Option<AppSlice<Shared, u8>>,
+    dest: Option<AppSlice<Shared, u8>>,
+    aad: Option<AppSlice<Shared, u8>>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: OptionalCell::empty(),
+            pending: None,
+            mode: None,
+            tag_len: 0,
+            key_handle: None,
+            key: None,
+            iv: None,
+            source: None,
+            dest: None,
+            aad: None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::mock::MockKernel;
+
+    struct NoAes;
+
+    impl AES128<'static> for NoAes {
+        fn enable(&self) {}
+        fn disable(&self) {}
+        fn set_client(&'static self, _client: &'static dyn symmetric_encryption::Client<'static>) {}
+        fn set_key(&self, _key: &[u8]) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn set_iv(&self, _iv: &[u8]) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn start_message(&self) {}
+        fn crypt(
+            &'static self,
+            source: Option<&'static mut [u8]>,
+            dest: &'static mut [u8],
+            _start_index: usize,
+            _stop_index: usize,
+        ) -> Option<(ReturnCode, Option<&'static mut [u8]>, &'static mut [u8])> {
+            Some((ReturnCode::ENOSUPPORT, source, dest))
+        }
+    }
+
+    impl AES128Ctr for NoAes {
+        fn set_mode_aes128ctr(&self, _encrypting: bool) {}
+    }
+
+    impl AES128CBC for NoAes {
+        fn set_mode_aes128cbc(&self, _encrypting: bool) {}
+    }
+
+    impl AES128ECB for NoAes {
+        fn set_mode_aes128ecb(&self, _encrypting: bool) {}
+    }
+
+    #[test]
+    fn test_key_slots_of_exited_processes_freed() {
+        let kernel = MockKernel::new(2);
+        let driver = AesDriver::new(
+            &NoAes,
+            std::boxed::Box::leak(std::boxed::Box::new([0; 64])),
+            kernel.create_grant(),
+        );
+        let (app0, app1) = (kernel.process(0), kernel.process(1));
+        for app in [app0, app1].iter() {
+            let key = app.allow_buffer(std::boxed::Box::leak(std::boxed::Box::new([0x5a; 16])));
+            assert_eq!(driver.allow(app.appid(), 0, Some(key)), ReturnCode::SUCCESS);
+        }
+
+        for handle in 0..NUM_KEY_SLOTS {
+            assert_eq!(
+                driver.command(2, 0, 0, app0.appid()),
+                ReturnCode::SuccessWithValue { value: handle }
+            );
+        }
+        assert_eq!(driver.command(2, 0, 0, app1.appid()), ReturnCode::ENOMEM);
+
+        // The keys of the process are erased once another one needs a slot
+        app0.terminate();
+        assert_eq!(
+            driver.command(2, 0, 0, app1.appid()),
+            ReturnCode::SuccessWithValue { value: 0 }
+        );
+        assert!(driver.keys[0].usable_by(app1.appid()));
+        for slot in driver.keys[1..].iter() {
+            assert_eq!(slot.key.get(), [0; AES128_KEY_SIZE]);
+            assert!(slot.owner.get().is_none());
+        }
+    }
+}
@@ -0,0 +1,643 @@
+//! Implements AES-GCM encryption/decryption/authentication using an
+//! underlying AES-CTR implementation.
+//!
+//! NIST SP 800-38D. The message is encrypted with AES-CTR starting from the
+//! counter block `inc32(J0)`, where `J0 = IV | 0^31 | 1` for the 96-bit IVs
+//! supported here, and the tag is `E(K, J0) XOR GHASH(H, A, C)`, where the
+//! hash subkey `H` is `E(K, 0^128)`.
+//!
+//! Both `E(K, J0)` and the keystream of the message come from a single CTR
+//! pass over a zero block followed by the message, which is copied to
+//! `crypt_buf` for the purpose:
+//!
+//! ```text
+//! crypt_buf: [ 0^128 | -------- PData/CData -------- | padding ]
+//! aes_ctr:    \______________________________________________/  from J0
+//! ```
+//!
+//! `H` is computed with a one-block CTR pass from the zero counter block,
+//! the first time the key is used. The GHASH universal hash is computed in
+//! software, in constant time, so that all the engine needs from the
+//! hardware is AES-CTR.
+//!
+//! At decryption, the plaintext is only written back to the client's buffer
+//! if the tag is valid.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let gcm_buf = static_init!([u8; 144], [0; 144]);
+//! let gcm = static_init!(
+//!     AesGcm<'static, VirtualAES128<'static, sam4l::aes::Aes>>,
+//!     AesGcm::new(virtual_aes, gcm_buf)
+//! );
+//! virtual_aes.set_client(gcm);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128_BLOCK_SIZE, AES128_KEY_SIZE, GCM_IV_LENGTH, GCM_TAG_LENGTH,
+};
+use kernel::ReturnCode;
+
+/// Multiplies `x` and `y` in GF(2^128), with the bit order of GCM, in
+/// constant time.
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut z = 0;
+    let mut v = y;
+    for i in (0..128).rev() {
+        let bit = (x >> i) & 1;
+        z ^= v & 0u128.wrapping_sub(bit);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+/// The GHASH function of GCM.
+pub struct Ghash {
+    h: u128,
+    y: u128,
+}
+
+impl Ghash {
+    pub fn new(h: &[u8; AES128_BLOCK_SIZE]) -> Ghash {
+        Ghash {
+            h: u128::from_be_bytes(*h),
+            y: 0,
+        }
+    }
+
+    /// Hashes `data`, padded with zeros to a whole number of blocks.
+    pub fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(AES128_BLOCK_SIZE) {
+            let mut block = [0; AES128_BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.y = gf128_mul(self.y ^ u128::from_be_bytes(block), self.h);
+        }
+    }
+
+    /// Hashes the lengths of the additional data and the ciphertext, in
+    /// bytes, and returns the hash.
+    pub fn finish(&mut self, a_len: usize, c_len: usize) -> [u8; AES128_BLOCK_SIZE] {
+        let lengths = ((a_len as u128 * 8) << 64) | (c_len as u128 * 8);
+        self.y = gf128_mul(self.y ^ lengths, self.h);
+        self.y.to_be_bytes()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum GCMState {
+    Idle,
+    HashKey,
+    Crypt,
+}
+
+pub struct AesGcm<'a, A: AES128<'a> + AES128Ctr> {
+    aes: &'a A,
+    crypt_buf: TakeCell<'a, [u8]>,
+    client: OptionalCell<&'a dyn symmetric_encryption::GCMClient>,
+
+    state: Cell<GCMState>,
+    encrypting: Cell<bool>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    iv: Cell<[u8; GCM_IV_LENGTH]>,
+    /// The hash subkey of `key`, once computed.
+    hash_key: OptionalCell<[u8; AES128_BLOCK_SIZE]>,
+
+    buf: TakeCell<'static, [u8]>,
+    pos: Cell<(usize, usize, usize)>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr> AesGcm<'a, A> {
+    pub fn new(aes: &'a A, crypt_buf: &'a mut [u8]) -> AesGcm<'a, A> {
+        AesGcm {
+            aes: aes,
+            crypt_buf: TakeCell::new(crypt_buf),
+            client: OptionalCell::empty(),
+            state: Cell::new(GCMState::Idle),
+            encrypting: Cell::new(false),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            iv: Cell::new([0; GCM_IV_LENGTH]),
+            hash_key: OptionalCell::empty(),
+            buf: TakeCell::empty(),
+            pos: Cell::new((0, 0, 0)),
+        }
+    }
+
+    /// Runs AES-CTR over the first `len` bytes of `crypt_buf`, from the
+    /// counter block `counter`.
+    fn start_ctr(&self, counter: &[u8; AES128_BLOCK_SIZE], len: usize) -> ReturnCode {
+        let res = self.aes.set_key(&self.key.get());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let res = self.aes.set_iv(counter);
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        self.aes.set_mode_aes128ctr(true);
+        self.aes.start_message();
+        let crypt_buf = match self.crypt_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::FAIL,
+        };
+        match self.aes.crypt(None, crypt_buf, 0, len) {
+            None => ReturnCode::SUCCESS,
+            Some((res, _, crypt_buf)) => {
+                self.crypt_buf.replace(crypt_buf);
+                res
+            }
+        }
+    }
+
+    fn start_hash_key(&self) -> ReturnCode {
+        self.crypt_buf.map(|cbuf| {
+            cbuf[..AES128_BLOCK_SIZE].iter_mut().for_each(|b| *b = 0);
+        });
+        let res = self.start_ctr(&[0; AES128_BLOCK_SIZE], AES128_BLOCK_SIZE);
+        if res == ReturnCode::SUCCESS {
+            self.state.set(GCMState::HashKey);
+        }
+        res
+    }
+
+    fn start_crypt(&self) -> ReturnCode {
+        let (_, m_off, m_len) = self.pos.get();
+        let padded_len = (m_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+        let copied = self.crypt_buf.map(|cbuf| {
+            self.buf.map(|buf| {
+                let (block, rest) = cbuf.split_at_mut(AES128_BLOCK_SIZE);
+                block.iter_mut().for_each(|b| *b = 0);
+                rest[..m_len].copy_from_slice(&buf[m_off..m_off + m_len]);
+                rest[m_len..padded_len].iter_mut().for_each(|b| *b = 0);
+            })
+        });
+        if copied.is_none() {
+            return ReturnCode::FAIL;
+        }
+
+        let mut j0 = [0; AES128_BLOCK_SIZE];
+        j0[..GCM_IV_LENGTH].copy_from_slice(&self.iv.get());
+        j0[AES128_BLOCK_SIZE - 1] = 1;
+        let res = self.start_ctr(&j0, AES128_BLOCK_SIZE + padded_len);
+        if res == ReturnCode::SUCCESS {
+            self.state.set(GCMState::Crypt);
+        }
+        res
+    }
+
+    /// Computes the tag, checks it or appends it to the message, and
+    /// returns whether it is valid.
+    fn end_crypt(&self, cbuf: &mut [u8], buf: &mut [u8]) -> bool {
+        let (a_off, m_off, m_len) = self.pos.get();
+        let m_end = m_off + m_len;
+        let mut ghash = Ghash::new(&self.hash_key.unwrap_or([0; AES128_BLOCK_SIZE]));
+        ghash.update_padded(&buf[a_off..m_off]);
+        if self.encrypting.get() {
+            ghash.update_padded(&cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]);
+        } else {
+            ghash.update_padded(&buf[m_off..m_end]);
+        }
+        let mut tag = ghash.finish(m_off - a_off, m_len);
+        tag.iter_mut()
+            .zip(cbuf[..AES128_BLOCK_SIZE].iter())
+            .for_each(|(t, k)| *t ^= *k);
+
+        let valid = if self.encrypting.get() {
+            buf[m_end..m_end + GCM_TAG_LENGTH].copy_from_slice(&tag);
+            true
+        } else {
+            // Compare every byte, so that the time taken does not depend on
+            // where the first difference is
+            buf[m_end..m_end + GCM_TAG_LENGTH]
+                .iter()
+                .zip(tag.iter())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+        };
+        if valid {
+            buf[m_off..m_end].copy_from_slice(&cbuf[AES128_BLOCK_SIZE..AES128_BLOCK_SIZE + m_len]);
+        }
+        // Do not leave plaintext or keystream behind
+        cbuf.iter_mut().for_each(|b| *b = 0);
+        valid
+    }
+
+    fn finish(&self, res: ReturnCode, tag_is_valid: bool) {
+        self.state.set(GCMState::Idle);
+        self.buf.take().map(|buf| {
+            self.client
+                .map(move |client| client.crypt_done(buf, res, tag_is_valid));
+        });
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr> symmetric_encryption::AES128GCM<'a> for AesGcm<'a, A> {
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::GCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        if self.state.get() != GCMState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        if new_key != self.key.get() || self.hash_key.is_none() {
+            self.key.set(new_key);
+            self.hash_key.clear();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+        if iv.len() != GCM_IV_LENGTH {
+            return ReturnCode::EINVAL;
+        }
+        if self.state.get() != GCMState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let mut new_iv = [0; GCM_IV_LENGTH];
+        new_iv.copy_from_slice(iv);
+        self.iv.set(new_iv);
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.state.get() != GCMState::Idle {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if !(a_off <= m_off && m_off + m_len + GCM_TAG_LENGTH <= buf.len()) {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        let padded_len = (m_len + AES128_BLOCK_SIZE - 1) / AES128_BLOCK_SIZE * AES128_BLOCK_SIZE;
+        if self
+            .crypt_buf
+            .map_or(true, |cbuf| cbuf.len() < AES128_BLOCK_SIZE + padded_len)
+        {
+            return (ReturnCode::ESIZE, Some(buf));
+        }
+
+        self.buf.replace(buf);
+        self.pos.set((a_off, m_off, m_len));
+        self.encrypting.set(encrypting);
+        let res = if self.hash_key.is_none() {
+            self.start_hash_key()
+        } else {
+            self.start_crypt()
+        };
+        if res != ReturnCode::SUCCESS {
+            return (res, self.buf.take());
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr> symmetric_encryption::Client<'a> for AesGcm<'a, A> {
+    fn crypt_done(&'a self, _: Option<&'a mut [u8]>, crypt_buf: &'a mut [u8]) {
+        match self.state.get() {
+            GCMState::Idle => {
+                self.crypt_buf.replace(crypt_buf);
+            }
+            GCMState::HashKey => {
+                let mut hash_key = [0; AES128_BLOCK_SIZE];
+                hash_key.copy_from_slice(&crypt_buf[..AES128_BLOCK_SIZE]);
+                self.hash_key.set(hash_key);
+                self.crypt_buf.replace(crypt_buf);
+                let res = self.start_crypt();
+                if res != ReturnCode::SUCCESS {
+                    self.finish(res, false);
+                }
+            }
+            GCMState::Crypt => {
+                let valid = self.buf.map_or(false, |buf| self.end_crypt(crypt_buf, buf));
+                self.crypt_buf.replace(crypt_buf);
+                self.finish(ReturnCode::SUCCESS, valid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::hex::hex;
+    use kernel::hil::symmetric_encryption::{GCMClient, AES128GCM};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    fn xtime(x: u8) -> u8 {
+        (x << 1) ^ (if x & 0x80 != 0 { 0x1b } else { 0 })
+    }
+
+    fn sbox(x: u8) -> u8 {
+        // The multiplicative inverse is x^254
+        let mul = |mut a: u8, mut b: u8| {
+            let mut p = 0;
+            while b != 0 {
+                if b & 1 != 0 {
+                    p ^= a;
+                }
+                a = xtime(a);
+                b >>= 1;
+            }
+            p
+        };
+        let mut inv = 1;
+        for _ in 0..254 {
+            inv = mul(inv, x);
+        }
+        inv ^ inv.rotate_left(1)
+            ^ inv.rotate_left(2)
+            ^ inv.rotate_left(3)
+            ^ inv.rotate_left(4)
+            ^ 0x63
+    }
+
+    /// A plain AES-128 block encryption, to check against the NIST vectors.
+    fn aes128_encrypt(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+        let mut round_keys = [[0u8; 16]; 11];
+        round_keys[0] = *key;
+        let mut rcon = 1;
+        for r in 1..11 {
+            let prev = round_keys[r - 1];
+            let mut t = [
+                sbox(prev[13]) ^ rcon,
+                sbox(prev[14]),
+                sbox(prev[15]),
+                sbox(prev[12]),
+            ];
+            rcon = xtime(rcon);
+            for i in 0..16 {
+                round_keys[r][i] = prev[i] ^ t[i % 4];
+                t[i % 4] = round_keys[r][i];
+            }
+        }
+
+        let mut s = *block;
+        s.iter_mut()
+            .zip(round_keys[0].iter())
+            .for_each(|(s, k)| *s ^= k);
+        for (r, round_key) in round_keys.iter().enumerate().skip(1) {
+            let mut t = [0; 16];
+            for c in 0..4 {
+                for row in 0..4 {
+                    t[4 * c + row] = sbox(s[4 * ((c + row) % 4) + row]);
+                }
+            }
+            if r != 10 {
+                for c in 0..4 {
+                    let col = [t[4 * c], t[4 * c + 1], t[4 * c + 2], t[4 * c + 3]];
+                    let all = col[0] ^ col[1] ^ col[2] ^ col[3];
+                    for row in 0..4 {
+                        t[4 * c + row] ^= all ^ xtime(col[row] ^ col[(row + 1) % 4]);
+                    }
+                }
+            }
+            s.iter_mut()
+                .zip(t.iter().zip(round_key.iter()))
+                .for_each(|(s, (t, k))| *s = t ^ k);
+        }
+        s
+    }
+
+    /// An AES-CTR engine, which completes operations when `complete()` is
+    /// called.
+    struct CtrEngine<'a> {
+        client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+        key: Cell<[u8; 16]>,
+        counter: Cell<[u8; 16]>,
+        dest: TakeCell<'a, [u8]>,
+    }
+
+    impl CtrEngine<'_> {
+        fn complete(&self) {
+            let dest = self.dest.take().unwrap();
+            self.client.map(move |client| client.crypt_done(None, dest));
+        }
+    }
+
+    impl<'a> AES128<'a> for CtrEngine<'a> {
+        fn enable(&self) {}
+        fn disable(&self) {}
+        fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+            self.client.set(client);
+        }
+        fn set_key(&self, key: &[u8]) -> ReturnCode {
+            let mut k = [0; 16];
+            k.copy_from_slice(key);
+            self.key.set(k);
+            ReturnCode::SUCCESS
+        }
+        fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+            let mut v = [0; 16];
+            v.copy_from_slice(iv);
+            self.counter.set(v);
+            ReturnCode::SUCCESS
+        }
+        fn start_message(&self) {}
+        fn crypt(
+            &'a self,
+            _source: Option<&'a mut [u8]>,
+            dest: &'a mut [u8],
+            start_index: usize,
+            stop_index: usize,
+        ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+            for block in dest[start_index..stop_index].chunks_mut(16) {
+                let counter = self.counter.get();
+                let keystream = aes128_encrypt(&self.key.get(), &counter);
+                block
+                    .iter_mut()
+                    .zip(keystream.iter())
+                    .for_each(|(b, k)| *b ^= k);
+                self.counter
+                    .set(u128::from_be_bytes(counter).wrapping_add(1).to_be_bytes());
+            }
+            self.dest.replace(dest);
+            None
+        }
+    }
+
+    impl AES128Ctr for CtrEngine<'_> {
+        fn set_mode_aes128ctr(&self, _encrypting: bool) {}
+    }
+
+    struct TestClient {
+        done: TakeCell<'static, [u8]>,
+        result: Cell<Option<(ReturnCode, bool)>>,
+    }
+
+    impl GCMClient for TestClient {
+        fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+            self.done.replace(buf);
+            self.result.set(Some((res, tag_is_valid)));
+        }
+    }
+
+    type Gcm = AesGcm<'static, CtrEngine<'static>>;
+
+    fn setup() -> (
+        &'static CtrEngine<'static>,
+        &'static Gcm,
+        &'static TestClient,
+    ) {
+        let engine: &'static CtrEngine = Box::leak(Box::new(CtrEngine {
+            client: OptionalCell::empty(),
+            key: Cell::new([0; 16]),
+            counter: Cell::new([0; 16]),
+            dest: TakeCell::empty(),
+        }));
+        let crypt_buf: &'static mut [u8] = Box::leak(Box::new([0; 96]));
+        let gcm: &'static Gcm = Box::leak(Box::new(AesGcm::new(engine, crypt_buf)));
+        engine.set_client(gcm);
+        let client: &'static TestClient = Box::leak(Box::new(TestClient {
+            done: TakeCell::empty(),
+            result: Cell::new(None),
+        }));
+        gcm.set_client(client);
+        (engine, gcm, client)
+    }
+
+    /// Runs an operation on `a | m | tag` and returns the result and the
+    /// buffer.
+    fn run(
+        engine: &CtrEngine,
+        gcm: &Gcm,
+        client: &TestClient,
+        data: Vec<u8>,
+        a_len: usize,
+        encrypting: bool,
+    ) -> ((ReturnCode, bool), Vec<u8>) {
+        let m_len = data.len() - a_len - GCM_TAG_LENGTH;
+        let buf: &'static mut [u8] = Box::leak(data.into_boxed_slice());
+        let (res, buf) = gcm.crypt(buf, 0, a_len, m_len, encrypting);
+        assert_eq!(res, ReturnCode::SUCCESS);
+        assert!(buf.is_none());
+        while client.result.get().is_none() {
+            engine.complete();
+        }
+        let buf = client.done.take().unwrap();
+        (client.result.take().unwrap(), buf.to_vec())
+    }
+
+    #[test]
+    fn aes_block() {
+        // FIPS-197 appendix B
+        let key = hex("2b7e151628aed2a6abf7158809cf4f3c");
+        let block = hex("3243f6a8885a308d313198a2e0370734");
+        let mut k = [0; 16];
+        let mut b = [0; 16];
+        k.copy_from_slice(&key);
+        b.copy_from_slice(&block);
+        assert_eq!(
+            aes128_encrypt(&k, &b).to_vec(),
+            hex("3925841d02dc09fbdc118597196a0b32")
+        );
+    }
+
+    #[test]
+    fn ghash() {
+        // GCM test case 2: the hash of the ciphertext, without AAD
+        let mut h = [0; 16];
+        h.copy_from_slice(&hex("66e94bd4ef8a2c3b884cfa59ca342b2e"));
+        let mut ghash = Ghash::new(&h);
+        ghash.update_padded(&hex("0388dace60b6a392f328c2b971b2fe78"));
+        assert_eq!(
+            ghash.finish(0, 16).to_vec(),
+            hex("f38cbb1ad69223dcc3457ae5b6b0f885")
+        );
+    }
+
+    #[test]
+    fn nist_vectors() {
+        let (engine, gcm, client) = setup();
+
+        // GCM test case 2
+        assert_eq!(gcm.set_key(&[0; 16]), ReturnCode::SUCCESS);
+        assert_eq!(gcm.set_iv(&[0; 12]), ReturnCode::SUCCESS);
+        let (res, out) = run(engine, gcm, client, std::vec![0; 32], 0, true);
+        assert_eq!(res, (ReturnCode::SUCCESS, true));
+        assert_eq!(
+            out,
+            hex("0388dace60b6a392f328c2b971b2fe78ab6e47d42cec13bdf53a67b21257bddf")
+        );
+
+        // GCM test case 4, with a message that is not a whole number of
+        // blocks
+        let aad = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plaintext = hex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        let ciphertext = hex(
+            "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+             21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091",
+        );
+        let tag = hex("5bc94fbc3221a5db94fae95ae7121a47");
+        assert_eq!(
+            gcm.set_key(&hex("feffe9928665731c6d6a8f9467308308")),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            gcm.set_iv(&hex("cafebabefacedbaddecaf888")),
+            ReturnCode::SUCCESS
+        );
+        let mut data = aad.clone();
+        data.extend_from_slice(&plaintext);
+        data.extend_from_slice(&[0; 16]);
+        let (res, out) = run(engine, gcm, client, data, aad.len(), true);
+        assert_eq!(res, (ReturnCode::SUCCESS, true));
+        let mut expected = aad.clone();
+        expected.extend_from_slice(&ciphertext);
+        expected.extend_from_slice(&tag);
+        assert_eq!(out, expected);
+
+        // Decryption restores the plaintext
+        let (res, out) = run(engine, gcm, client, expected.clone(), aad.len(), false);
+        assert_eq!(res, (ReturnCode::SUCCESS, true));
+        assert_eq!(&out[aad.len()..aad.len() + plaintext.len()], &plaintext[..]);
+
+        // A modified AAD byte invalidates the tag, and the plaintext is not
+        // released
+        let mut tampered = expected.clone();
+        tampered[3] ^= 1;
+        let (res, out) = run(engine, gcm, client, tampered.clone(), aad.len(), false);
+        assert_eq!(res, (ReturnCode::SUCCESS, false));
+        assert_eq!(out, tampered);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let (_engine, gcm, _client) = setup();
+        assert_eq!(gcm.set_key(&[0; 15]), ReturnCode::EINVAL);
+        assert_eq!(gcm.set_iv(&[0; 16]), ReturnCode::EINVAL);
+
+        // No room for the tag
+        let buf: &'static mut [u8] = Box::leak(Box::new([0; 32]));
+        let (res, buf) = gcm.crypt(buf, 0, 0, 17, true);
+        assert_eq!(res, ReturnCode::EINVAL);
+
+        // Longer than the internal buffer
+        let buf = buf.unwrap();
+        let big: &'static mut [u8] = Box::leak(Box::new([0; 112]));
+        let (res, _) = gcm.crypt(big, 0, 0, 96, true);
+        assert_eq!(res, ReturnCode::ESIZE);
+        assert_eq!(buf.len(), 32);
+    }
+}
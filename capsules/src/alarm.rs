@@ -1,143 +1,283 @@
 //! Tock syscall driver capsule for Alarms, which issue callbacks when
 //! a point in time has been reached.
+//!
+//! Each process has a fixed number of independent alarms, set by the board
+//! when creating the driver (`DEFAULT_ALARMS_PER_PROCESS` with `new`). They are
+//! allocated in the process's grant region the first time the process uses
+//! the driver. Alarm 0 is the one used by the original single-alarm commands
+//! (`3` to `6`); the other alarms are handed out with command `7` so that
+//! independent libraries in a process do not step on each other.
+//!
+//! Alarms can be one-shot or periodic. Periodic alarms are re-armed relative
+//! to their previous expiration rather than to the time the callback ran, so
+//! they do not drift.
+//!
+//! The driver also extends the hardware counter to 64 bits by counting its
+//! wraparounds, and reports the current time in ticks or microseconds. While
+//! a process that asked for the 64-bit time exists, the driver keeps the
+//! underlying alarm armed at least every half counter period so that no
+//! wraparound goes unnoticed. Wraparounds are not counted while no such
+//! process exists, so the 64-bit time is only monotonic as long as one does.
 
 use core::cell::Cell;
-use kernel::hil::time::{self, Alarm, Frequency, Ticks, Ticks32};
-use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
+use kernel::{AppId, Callback, Driver, DynamicGrant, Grant, ReturnCode};
 
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Alarm as usize;
 
+/// Number of alarms per process when the driver is created with `new`.
+pub const DEFAULT_ALARMS_PER_PROCESS: usize = 4;
+
 #[derive(Copy, Clone, Debug)]
 enum Expiration {
     Disabled,
     Enabled { reference: u32, dt: u32 },
 }
 
-#[derive(Copy, Clone)]
-pub struct AlarmData {
+#[derive(Copy, Clone, Debug)]
+struct UserAlarm {
+    allocated: bool,
     expiration: Expiration,
+    // Re-arm with the same `dt` after every expiration.
+    periodic: bool,
+}
+
+impl Default for UserAlarm {
+    fn default() -> UserAlarm {
+        UserAlarm {
+            allocated: false,
+            expiration: Expiration::Disabled,
+            periodic: false,
+        }
+    }
+}
+
+pub struct AlarmData {
     callback: Option<Callback>,
+    alarms: Option<DynamicGrant<[UserAlarm]>>,
+    // Upper 32 bits of the last 64-bit time read by the process.
+    latched_high: u32,
+    // The process read the 64-bit time, so wraparounds must be counted.
+    uses_64bit_time: bool,
 }
 
 impl Default for AlarmData {
     fn default() -> AlarmData {
         AlarmData {
-            expiration: Expiration::Disabled,
             callback: None,
+            alarms: None,
+            latched_high: 0,
+            uses_64bit_time: false,
         }
     }
 }
 
+/// Arithmetic on tick values modulo the width of the underlying counter,
+/// which may be narrower than 32 bits.
+#[derive(Copy, Clone)]
+struct TickMask(u32);
+
+impl TickMask {
+    fn elapsed(self, reference: u32, now: u32) -> u32 {
+        now.wrapping_sub(reference) & self.0
+    }
+
+    fn end(self, reference: u32, dt: u32) -> u32 {
+        reference.wrapping_add(dt) & self.0
+    }
+
+    /// Ticks until the alarm fires, 0 if it already expired.
+    fn remaining(self, reference: u32, dt: u32, now: u32) -> u32 {
+        dt.saturating_sub(self.elapsed(reference, now))
+    }
+
+    /// Advance a periodic alarm that expired to its next expiration in the
+    /// future, skipping periods that were missed entirely.
+    fn next_period(self, reference: u32, dt: u32, now: u32) -> u32 {
+        if dt == 0 {
+            return now;
+        }
+        let elapsed = self.elapsed(reference, now);
+        let periods = elapsed / dt;
+        reference.wrapping_add(periods.wrapping_mul(dt)) & self.0
+    }
+
+    /// Half of the counter period: the longest the driver may go without
+    /// reading the counter while tracking wraparounds.
+    fn half_period(self) -> u32 {
+        (self.0 >> 1).max(1)
+    }
+}
+
+/// Convert a tick count to microseconds without overflowing for large counts.
+fn ticks_to_us(ticks: u64, frequency: u32) -> u64 {
+    let frequency = frequency as u64;
+    (ticks / frequency) * 1_000_000 + (ticks % frequency) * 1_000_000 / frequency
+}
+
+/// Run `f` on the alarms of a process, or fail with `ENOMEM` if they
+/// could not be allocated.
+fn with_alarms<F>(app: &mut AlarmData, f: F) -> ReturnCode
+where
+    F: FnOnce(&mut [UserAlarm]) -> ReturnCode,
+{
+    app.alarms.as_mut().map_or(ReturnCode::ENOMEM, |alarms| {
+        alarms
+            .enter(|mut alarms| f(&mut *alarms))
+            .unwrap_or_else(|err| err.into())
+    })
+}
+
+/// Run `f` on the allocated alarm `handle` of a process.
+fn with_handle<F>(app: &mut AlarmData, handle: usize, f: F) -> ReturnCode
+where
+    F: FnOnce(&mut UserAlarm) -> ReturnCode,
+{
+    with_alarms(app, |alarms| match alarms.get_mut(handle) {
+        Some(alarm) if alarm.allocated || handle == 0 => f(alarm),
+        _ => ReturnCode::EINVAL,
+    })
+}
+
+/// Split a 64-bit value into the low word returned to the process and
+/// the high word latched for command `14`.
+fn latch(app: &mut AlarmData, value: u64) -> ReturnCode {
+    app.latched_high = (value >> 32) as u32;
+    ReturnCode::SuccessWithValue {
+        value: value as u32 as usize,
+    }
+}
+
 pub struct AlarmDriver<'a, A: Alarm<'a>> {
     alarm: &'a A,
     num_armed: Cell<usize>,
     app_alarms: Grant<AlarmData>,
-    next_alarm: Cell<Expiration>,
+    alarms_per_process: usize,
+    // Wraparound tracking for the 64-bit time.
+    wraps: Cell<u32>,
+    last_now: Cell<u32>,
+    track_wraps: Cell<bool>,
 }
 
 impl<'a, A: Alarm<'a>> AlarmDriver<'a, A> {
     pub const fn new(alarm: &'a A, grant: Grant<AlarmData>) -> AlarmDriver<'a, A> {
+        AlarmDriver::new_with_alarms(alarm, grant, DEFAULT_ALARMS_PER_PROCESS)
+    }
+
+    /// Create a driver giving each process `alarms_per_process` alarms (at
+    /// least one).
+    pub const fn new_with_alarms(
+        alarm: &'a A,
+        grant: Grant<AlarmData>,
+        alarms_per_process: usize,
+    ) -> AlarmDriver<'a, A> {
         AlarmDriver {
             alarm: alarm,
             num_armed: Cell::new(0),
             app_alarms: grant,
-            next_alarm: Cell::new(Expiration::Disabled),
+            alarms_per_process: if alarms_per_process == 0 {
+                1
+            } else {
+                alarms_per_process
+            },
+            wraps: Cell::new(0),
+            last_now: Cell::new(0),
+            track_wraps: Cell::new(false),
         }
     }
 
-    // This logic is tricky because it needs to handle the case when the
-    // underlying alarm is wider than 32 bits.
+    fn mask(&self) -> TickMask {
+        TickMask(A::Ticks::max_value().into_u32())
+    }
+
+    /// Read the counter, noting a wraparound since the last read.
+    fn now(&self) -> u32 {
+        self.track(self.alarm.now())
+    }
+
+    fn track(&self, now: A::Ticks) -> u32 {
+        let now = now.into_u32() & self.mask().0;
+        if now < self.last_now.get() {
+            self.wraps.set(self.wraps.get().wrapping_add(1));
+        }
+        self.last_now.set(now);
+        now
+    }
+
+    fn now_64(&self) -> u64 {
+        let now = self.now();
+        (self.wraps.get() as u64) * (self.mask().0 as u64 + 1) + now as u64
+    }
+
+    fn arm(&self, alarm: &mut UserAlarm, reference: u32, dt: u32, periodic: bool) -> ReturnCode {
+        if let Expiration::Disabled = alarm.expiration {
+            self.num_armed.set(self.num_armed.get() + 1);
+        }
+        let mask = self.mask();
+        let (reference, dt) = (reference & mask.0, dt & mask.0);
+        alarm.expiration = Expiration::Enabled { reference, dt };
+        alarm.periodic = periodic;
+        ReturnCode::SuccessWithValue {
+            value: mask.end(reference, dt) as usize,
+        }
+    }
+
+    fn disarm(&self, alarm: &mut UserAlarm) -> ReturnCode {
+        match alarm.expiration {
+            // Request to stop when already stopped
+            Expiration::Disabled => ReturnCode::EALREADY,
+            Expiration::Enabled { .. } => {
+                alarm.expiration = Expiration::Disabled;
+                self.num_armed.set(self.num_armed.get() - 1);
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    /// Program the underlying alarm for the earliest armed process alarm.
+    ///
+    /// This also recounts the armed alarms and whether wraparounds need to be
+    /// tracked, as processes that exited since no longer have any.
     fn reset_active_alarm(&self) {
-        let mut earliest_alarm = Expiration::Disabled;
-        let mut earliest_end: A::Ticks = A::Ticks::from(0);
-        // Scale now down to a u32 since that is the width of the alarm;
-        // otherwise for larger widths (e.g., u64) now can be outside of
-        // the range of what an alarm can be set to.
-        let now = self.alarm.now();
-        let now_lower_bits = A::Ticks::from(now.into_u32());
-        // Find the first alarm to fire and store it in earliest_alarm,
-        // its counter value at earliest_end. In the case that there
-        // are multiple alarms in the past, just store one of them
-        // and resolve ordering later, when we fire.
-        for alarm in self.app_alarms.iter() {
-            alarm.enter(|alarm, _| match alarm.expiration {
-                Expiration::Enabled { reference, dt } => {
-                    // Do this because `reference` shadowed below
-                    let current_reference = reference;
-                    let current_reference_ticks = A::Ticks::from(current_reference);
-                    let current_dt = dt;
-                    let current_dt_ticks = A::Ticks::from(current_dt);
-                    let current_end_ticks = current_reference_ticks.wrapping_add(current_dt_ticks);
-
-                    earliest_alarm = match earliest_alarm {
-                        Expiration::Disabled => {
-                            earliest_end = current_end_ticks;
-                            alarm.expiration
-                        }
-                        Expiration::Enabled { reference, dt } => {
-                            // There are two cases when current might be
-                            // an earlier alarm.  The first is if it
-                            // fires inside the interval (reference,
-                            // reference+dt) of the existing earliest.
-                            // The second is if now is not within the
-                            // interval: this means that it has
-                            // passed. It could be the earliest has passed
-                            // too, but at this point we don't need to track
-                            // which is earlier: the key point is that
-                            // the alarm must fire immediately, and then when
-                            // we handle the alarm callback the userspace
-                            // callbacks will all be pushed onto processes.
-                            // Because there is at most a single callback per
-                            // process and they must go through the scheduler
-                            // we don't care about the order in which we push
-                            // their callbacks, as their order of execution is
-                            // determined by the scheduler not push order. -pal
-                            let temp_earliest_reference = A::Ticks::from(reference);
-                            let temp_earliest_dt = A::Ticks::from(dt);
-                            let temp_earliest_end =
-                                temp_earliest_reference.wrapping_add(temp_earliest_dt);
-
-                            if current_end_ticks
-                                .within_range(temp_earliest_reference, temp_earliest_end)
-                            {
-                                earliest_end = current_end_ticks;
-                                alarm.expiration
-                            } else if !now_lower_bits
-                                .within_range(temp_earliest_reference, temp_earliest_end)
-                            {
-                                earliest_end = temp_earliest_end;
-                                alarm.expiration
-                            } else {
-                                earliest_alarm
+        let mask = self.mask();
+        let now_full = self.alarm.now();
+        let now = self.track(now_full);
+
+        let mut earliest: Option<u32> = None;
+        let mut num_armed = 0;
+        let mut track_wraps = false;
+        for cntr in self.app_alarms.iter() {
+            cntr.enter(|app, _| {
+                track_wraps |= app.uses_64bit_time;
+                app.alarms.as_mut().map(|alarms| {
+                    let _ = alarms.enter(|alarms| {
+                        for alarm in alarms.iter() {
+                            if let Expiration::Enabled { reference, dt } = alarm.expiration {
+                                let remaining = mask.remaining(reference, dt, now);
+                                earliest = Some(earliest.map_or(remaining, |e| e.min(remaining)));
+                                num_armed += 1;
                             }
                         }
-                    }
-                }
-                Expiration::Disabled => {}
+                    });
+                });
             });
         }
-        self.next_alarm.set(earliest_alarm);
-        match earliest_alarm {
-            Expiration::Disabled => {
+        self.num_armed.set(num_armed);
+        self.track_wraps.set(track_wraps);
+
+        if self.track_wraps.get() {
+            let guard = mask.half_period();
+            earliest = Some(earliest.map_or(guard, |e| e.min(guard)));
+        }
+
+        match earliest {
+            None => {
                 self.alarm.disarm();
             }
-            Expiration::Enabled { reference, dt } => {
-                // This logic handles when the underlying Alarm is wider than
-                // 32 bits; it sets the reference to include the high bits of now
-                let mut high_bits = now.wrapping_sub(now_lower_bits);
-                // If lower bits have wrapped around from reference, this means the
-                // reference's high bits are actually one less; if we don't subtract
-                // one then the alarm will incorrectly be set 1<<32 higher than it should.
-                // This uses the invariant that reference <= now.
-                if now_lower_bits.into_u32() < reference {
-                    // Build 1<<32 in a way that just overflows to 0 if we are 32 bits
-                    let bit33 = A::Ticks::from(0xffffffff).wrapping_add(A::Ticks::from(0x1));
-                    high_bits = high_bits.wrapping_sub(bit33);
-                }
-                let real_reference = high_bits.wrapping_add(A::Ticks::from(reference));
-                self.alarm.set_alarm(real_reference, A::Ticks::from(dt));
+            Some(remaining) => {
+                self.alarm.set_alarm(now_full, A::Ticks::from(remaining));
             }
         }
     }
@@ -167,84 +307,118 @@ impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
     ///
     /// ### `command_num`
     ///
-    /// - `0`: Driver check.
+    /// - `0`: Driver check, returns the number of alarms per process.
     /// - `1`: Return the clock frequency in Hz.
     /// - `2`: Read the the current clock value
-    /// - `3`: Stop the alarm if it is outstanding
-    /// - `4`: Set an alarm to fire at a given clock value `time`.
-    /// - `5`: Set an alarm to fire at a given clock value `time` relative to `now` (EXPERIMENTAL).
+    /// - `3`: Stop alarm 0 if it is outstanding
+    /// - `4`: Set alarm 0 to fire at a given clock value `time`.
+    /// - `5`: Set alarm 0 to fire at a given clock value `time` relative to `now` (EXPERIMENTAL).
+    /// - `6`: Set alarm 0 to fire `dt` ticks after the clock value `reference`.
+    /// - `7`: Allocate an alarm, returns its handle.
+    /// - `8`: Stop and free the alarm `handle`.
+    /// - `9`: Set the alarm `handle` to fire once, `dt` ticks from now.
+    /// - `10`: Set the alarm `handle` to fire every `dt` ticks, starting `dt`
+    ///   ticks from now.
+    /// - `11`: Stop the alarm `handle`.
+    /// - `12`: Read the 64-bit clock value, returns the lower 32 bits.
+    /// - `13`: Read the 64-bit time in microseconds, returns the lower 32
+    ///   bits.
+    /// - `14`: Return the upper 32 bits of the last value read with `12` or
+    ///   `13`.
     fn command(&self, cmd_type: usize, data: usize, data2: usize, caller_id: AppId) -> ReturnCode {
-        // Returns the error code to return to the user and whether we need to
-        // reset which is the next active alarm. We _don't_ reset if
-        //   - we're disabling the underlying alarm anyway,
-        //   - the underlying alarm is currently disabled and we're enabling the first alarm, or
-        //   - on an error (i.e. no change to the alarms).
         self.app_alarms
-            .enter(caller_id, |td, _alloc| {
-                // helper function to rearm alarm
-                let mut rearm = |reference: usize, dt: usize| {
-                    if let Expiration::Disabled = td.expiration {
-                        self.num_armed.set(self.num_armed.get() + 1);
-                    }
-                    td.expiration = Expiration::Enabled {
-                        reference: reference as u32,
-                        dt: dt as u32,
-                    };
-                    (
-                        ReturnCode::SuccessWithValue {
-                            value: reference.wrapping_add(dt),
-                        },
-                        true,
-                    )
+            .enter(caller_id, |td, allocator| {
+                if td.alarms.is_none() {
+                    td.alarms = allocator
+                        .alloc_n_with(self.alarms_per_process, |_| UserAlarm::default())
+                        .ok();
+                }
+                let now = self.now();
+                let arm = |td: &mut AlarmData, handle, reference, dt, periodic| {
+                    let rcode =
+                        with_handle(td, handle, |alarm| self.arm(alarm, reference, dt, periodic));
+                    let reset = rcode != ReturnCode::EINVAL && rcode != ReturnCode::ENOMEM;
+                    (rcode, reset)
                 };
-                let now = self.alarm.now();
+                let disarm = |td: &mut AlarmData, handle| {
+                    let rcode = with_handle(td, handle, |alarm| self.disarm(alarm));
+                    (rcode, rcode == ReturnCode::SUCCESS)
+                };
+                // Returns the error code to return to the user and whether we
+                // need to reset which is the next active alarm.
                 let (return_code, reset) = match cmd_type {
-                    0 /* check if present */ => (ReturnCode::SuccessWithValue { value: 1 }, false),
+                    0 /* check if present */ => (ReturnCode::SuccessWithValue {
+                        value: self.alarms_per_process,
+                    }, false),
                     1 /* Get clock frequency */ => {
                         let freq = <A::Frequency>::frequency() as usize;
                         (ReturnCode::SuccessWithValue { value: freq }, false)
                     },
                     2 /* capture time */ => {
-                        (ReturnCode::SuccessWithValue { value: now.into_u32() as usize },
-                         false)
-                    },
-                    3 /* Stop */ => {
-                        match td.expiration {
-                            Expiration::Disabled => {
-                                // Request to stop when already stopped
-                                (ReturnCode::EALREADY, false)
-                            },
-                            _ => {
-                                td.expiration = Expiration::Disabled;
-                                let new_num_armed = self.num_armed.get() - 1;
-                                self.num_armed.set(new_num_armed);
-                                (ReturnCode::SUCCESS, true)
-                            }
-                        }
+                        (ReturnCode::SuccessWithValue { value: now as usize }, false)
                     },
+                    3 /* Stop */ => disarm(td, 0),
                     4 /* Set absolute expiration */ => {
-                        let reference = now.into_u32() as usize;
-                        let future_time = data;
-                        let dt = future_time.wrapping_sub(reference);
-                        // if previously unarmed, but now will become armed
-                        rearm(reference, dt)
-                    },
-                    5 /* Set relative expiration */ => {
-                        let reference = now.into_u32() as usize;
-                        let dt = data;
-                        // if previously unarmed, but now will become armed
-                        rearm(reference, dt)
+                        let dt = (data as u32).wrapping_sub(now);
+                        arm(td, 0, now, dt, false)
                     },
+                    5 /* Set relative expiration */ => arm(td, 0, now, data as u32, false),
                     6 /* Set absolute expiration with reference point */ => {
                         // Taking a reference timestamp from userspace
                         // prevents wraparound bugs; future versions of
                         // libtock will use only this call and deprecate
                         // command #4; for now it is added as an additional
                         // comamnd for backwards compatibility. -pal
-                        let reference = data;
-                        let dt = data2;
-                        rearm(reference, dt)
+                        arm(td, 0, data as u32, data2 as u32, false)
                     }
+                    7 /* Allocate an alarm */ => (with_alarms(td, |alarms| {
+                        alarms
+                            .iter_mut()
+                            .enumerate()
+                            .skip(1)
+                            .find(|(_, alarm)| !alarm.allocated)
+                            .map_or(ReturnCode::ENOMEM, |(handle, alarm)| {
+                                *alarm = UserAlarm::default();
+                                alarm.allocated = true;
+                                ReturnCode::SuccessWithValue { value: handle }
+                            })
+                    }), false),
+                    8 /* Free an alarm */ => {
+                        if data == 0 {
+                            (ReturnCode::EINVAL, false)
+                        } else {
+                            let mut reset = false;
+                            let rcode = with_handle(td, data, |alarm| {
+                                reset = self.disarm(alarm) == ReturnCode::SUCCESS;
+                                alarm.allocated = false;
+                                ReturnCode::SUCCESS
+                            });
+                            (rcode, reset)
+                        }
+                    }
+                    9 /* Set a one-shot alarm */ => arm(td, data, now, data2 as u32, false),
+                    10 /* Set a periodic alarm */ => {
+                        if data2 == 0 {
+                            (ReturnCode::EINVAL, false)
+                        } else {
+                            arm(td, data, now, data2 as u32, true)
+                        }
+                    }
+                    11 /* Stop an alarm */ => disarm(td, data),
+                    12 /* 64-bit ticks */ => {
+                        td.uses_64bit_time = true;
+                        let reset = !self.track_wraps.replace(true);
+                        (latch(td, self.now_64()), reset)
+                    }
+                    13 /* 64-bit microseconds */ => {
+                        td.uses_64bit_time = true;
+                        let reset = !self.track_wraps.replace(true);
+                        let us = ticks_to_us(self.now_64(), <A::Frequency>::frequency());
+                        (latch(td, us), reset)
+                    }
+                    14 /* Upper word of the last 64-bit read */ => (ReturnCode::SuccessWithValue {
+                        value: td.latched_high as usize,
+                    }, false),
                     _ => (ReturnCode::ENOSUPPORT, false)
                 };
                 if reset {
@@ -258,35 +432,222 @@ impl<'a, A: Alarm<'a>> Driver for AlarmDriver<'a, A> {
 
 impl<'a, A: Alarm<'a>> time::AlarmClient for AlarmDriver<'a, A> {
     fn alarm(&self) {
-        let now: Ticks32 = Ticks32::from(self.alarm.now().into_u32());
-        self.app_alarms.each(|alarm| {
-            if let Expiration::Enabled { reference, dt } = alarm.expiration {
-                // Now is not within reference, reference + ticks; this timer
-                // as passed (since reference must be in the past)
-                if !now.within_range(
-                    Ticks32::from(reference),
-                    Ticks32::from(reference.wrapping_add(dt)),
-                ) {
-                    alarm.expiration = Expiration::Disabled;
-                    self.num_armed.set(self.num_armed.get() - 1);
-                    alarm.callback.map(|mut cb| {
-                        cb.schedule(
-                            now.into_u32() as usize,
-                            reference.wrapping_add(dt) as usize,
-                            0,
-                        )
+        let mask = self.mask();
+        let now = self.now();
+        for cntr in self.app_alarms.iter() {
+            cntr.enter(|app, _| {
+                let callback = app.callback;
+                app.alarms.as_mut().map(|alarms| {
+                    let _ = alarms.enter(|mut alarms| {
+                        for (handle, alarm) in alarms.iter_mut().enumerate() {
+                            if let Expiration::Enabled { reference, dt } = alarm.expiration {
+                                // Now is not within reference, reference + dt;
+                                // this alarm has passed (since reference must
+                                // be in the past)
+                                if mask.remaining(reference, dt, now) > 0 {
+                                    continue;
+                                }
+                                let end = mask.end(reference, dt);
+                                if alarm.periodic {
+                                    let reference = mask.next_period(reference, dt, now);
+                                    alarm.expiration = Expiration::Enabled { reference, dt };
+                                } else {
+                                    alarm.expiration = Expiration::Disabled;
+                                    self.num_armed.set(self.num_armed.get() - 1);
+                                }
+                                callback
+                                    .map(|mut cb| cb.schedule(now as usize, end as usize, handle));
+                            }
+                        }
                     });
-                }
-            }
-        });
+                });
+            });
+        }
 
-        // If there are no armed alarms left, skip checking and just disable.
-        // Otherwise, check all the alarms and find the next one, rescheduling
-        // the underlying alarm.
-        if self.num_armed.get() == 0 {
+        // If there are no armed alarms left and no need to watch for
+        // wraparounds, skip checking and just disable. Otherwise, check all
+        // the alarms and find the next one, rescheduling the underlying
+        // alarm.
+        if self.num_armed.get() == 0 && !self.track_wraps.get() {
             self.alarm.disarm();
         } else {
             self.reset_active_alarm();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ticks_to_us, TickMask};
+
+    #[test]
+    fn test_remaining_wraps_at_counter_width() {
+        let mask = TickMask(0x00FF_FFFF);
+        // Set 0x10 ticks before a 24-bit wraparound, expiring after it.
+        assert_eq!(mask.end(0x00FF_FFF0, 0x20), 0x10);
+        assert_eq!(mask.remaining(0x00FF_FFF0, 0x20, 0x00FF_FFF8), 0x18);
+        assert_eq!(mask.remaining(0x00FF_FFF0, 0x20, 0x05), 0x0B);
+        assert_eq!(mask.remaining(0x00FF_FFF0, 0x20, 0x10), 0);
+        assert_eq!(mask.remaining(0x00FF_FFF0, 0x20, 0x100), 0);
+    }
+
+    #[test]
+    fn test_periodic_rearm_does_not_drift() {
+        let mask = TickMask(0xFFFF_FFFF);
+        // Serviced 3 ticks late: the next period still starts at 1100.
+        assert_eq!(mask.next_period(1000, 100, 1103), 1100);
+        // Two periods missed entirely are skipped.
+        assert_eq!(mask.next_period(1000, 100, 1250), 1200);
+        // Across a wraparound.
+        assert_eq!(mask.next_period(0xFFFF_FFC0, 0x80, 0x50), 0x40);
+    }
+
+    #[test]
+    fn test_ticks_to_us() {
+        assert_eq!(ticks_to_us(32768, 32768), 1_000_000);
+        assert_eq!(ticks_to_us(1, 16_000_000), 0);
+        assert_eq!(ticks_to_us(16, 16_000_000), 1);
+        // Does not overflow where ticks * 1_000_000 would.
+        let ticks = u64::MAX / 1000;
+        assert_eq!(ticks_to_us(ticks, 1_000_000), ticks);
+    }
+
+    #[cfg(feature = "process_mock")]
+    mod driver {
+        extern crate std;
+
+        use super::super::{AlarmDriver, DRIVER_NUM};
+        use core::cell::Cell;
+        use kernel::hil::time::{self, AlarmClient, Freq1KHz, Ticks, Ticks32};
+        use kernel::mock::MockKernel;
+        use kernel::{Driver, ReturnCode};
+        use std::boxed::Box;
+
+        #[derive(Default)]
+        struct TestAlarm {
+            now: Cell<u32>,
+            alarm: Cell<Option<u32>>,
+        }
+
+        impl time::Time for TestAlarm {
+            type Frequency = Freq1KHz;
+            type Ticks = Ticks32;
+
+            fn now(&self) -> Ticks32 {
+                self.now.get().into()
+            }
+        }
+
+        impl<'a> time::Alarm<'a> for TestAlarm {
+            fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+            fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+                self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+            }
+
+            fn get_alarm(&self) -> Ticks32 {
+                self.alarm.get().unwrap_or(0).into()
+            }
+
+            fn disarm(&self) -> ReturnCode {
+                self.alarm.set(None);
+                ReturnCode::SUCCESS
+            }
+
+            fn is_armed(&self) -> bool {
+                self.alarm.get().is_some()
+            }
+
+            fn minimum_dt(&self) -> Ticks32 {
+                1.into()
+            }
+        }
+
+        fn setup(
+            kernel: &'static MockKernel,
+        ) -> (&'static AlarmDriver<'static, TestAlarm>, &'static TestAlarm) {
+            let alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm::default()));
+            let driver = Box::leak(Box::new(AlarmDriver::new(alarm, kernel.create_grant())));
+            (driver, alarm)
+        }
+
+        // Advance the clock to the underlying alarm and fire it.
+        fn fire(driver: &AlarmDriver<'static, TestAlarm>, alarm: &TestAlarm) {
+            alarm.now.set(alarm.alarm.get().unwrap());
+            driver.alarm();
+        }
+
+        #[test]
+        fn test_periodic_and_oneshot_alarms() {
+            let kernel = MockKernel::new(1);
+            let (driver, alarm) = setup(kernel);
+            let app = kernel.process(0);
+            let id = app.appid();
+
+            assert_eq!(
+                driver.subscribe(0, Some(app.callback(DRIVER_NUM, 0)), id),
+                ReturnCode::SUCCESS
+            );
+            assert_eq!(
+                driver.command(7, 0, 0, id),
+                ReturnCode::SuccessWithValue { value: 1 }
+            );
+            assert_eq!(
+                driver.command(10, 1, 100, id),
+                ReturnCode::SuccessWithValue { value: 100 }
+            );
+            assert_eq!(
+                driver.command(5, 150, 0, id),
+                ReturnCode::SuccessWithValue { value: 150 }
+            );
+            assert_eq!(alarm.alarm.get(), Some(100));
+
+            fire(driver, alarm);
+            assert_eq!(app.take_callbacks(), [(0, 100, 100, 1)]);
+            assert_eq!(alarm.alarm.get(), Some(150));
+
+            fire(driver, alarm);
+            assert_eq!(app.take_callbacks(), [(0, 150, 150, 0)]);
+            assert_eq!(alarm.alarm.get(), Some(200));
+
+            // Serviced late, the periodic alarm keeps its period.
+            alarm.now.set(230);
+            driver.alarm();
+            assert_eq!(app.take_callbacks(), [(0, 230, 200, 1)]);
+            assert_eq!(alarm.alarm.get(), Some(300));
+
+            assert_eq!(driver.command(11, 1, 0, id), ReturnCode::SUCCESS);
+            assert_eq!(alarm.alarm.get(), None);
+        }
+
+        #[test]
+        fn test_wraparounds_tracked_while_needed() {
+            let kernel = MockKernel::new(1);
+            let (driver, alarm) = setup(kernel);
+            let a = kernel.process(0);
+
+            alarm.now.set(0xFFFF_FF00);
+            assert_eq!(
+                driver.command(12, 0, 0, a.appid()),
+                ReturnCode::SuccessWithValue { value: 0xFFFF_FF00 }
+            );
+            assert_eq!(alarm.alarm.get(), Some(0x7FFF_FEFF));
+            // The guard alarm fires after the counter wrapped around.
+            fire(driver, alarm);
+            alarm.now.set(0x9000_0000);
+            assert_eq!(
+                driver.command(12, 0, 0, a.appid()),
+                ReturnCode::SuccessWithValue { value: 0x9000_0000 }
+            );
+            assert_eq!(
+                driver.command(14, 0, 0, a.appid()),
+                ReturnCode::SuccessWithValue { value: 1 }
+            );
+
+            // Once the process exited, the guard alarm is not set again.
+            a.terminate();
+            fire(driver, alarm);
+            assert_eq!(alarm.alarm.get(), None);
+        }
+    }
+}
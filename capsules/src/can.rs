@@ -0,0 +1,548 @@
+//! Provides userspace access to a CAN bus.
+//!
+//! Processes join the bus, install an acceptance filter and then send and
+//! receive classic CAN frames. The capsule is usually given a `CanDevice` from
+//! `virtual_can`, so the controller can be shared with kernel clients.
+//!
+//! Each process has at most one acceptance filter; a process without a filter
+//! receives nothing. The filters of all processes that joined the bus are
+//! installed in the underlying controller, falling back to accepting every
+//! frame in hardware if there are too many of them. Frames are still only
+//! delivered to the processes whose filter they match.
+//!
+//! Each process can have one frame outstanding. Frames of different processes
+//! are transmitted one after the other.
+//!
+//! The bus is left once no process is on it anymore, and the filters of
+//! processes that exited are removed. As there is no notification when a
+//! process exits, this is checked on every command and, from a deferred call,
+//! after every event of the controller.
+//!
+//! Frame format
+//! ------------
+//!
+//! Frames are exchanged through allowed buffers in a 13 byte format:
+//!
+//! - Bytes 0-3: the identifier word, little endian. Bits 0-28 hold the
+//!   identifier, bit 30 is set for remote frames and bit 31 for frames with
+//!   an extended (29-bit) identifier.
+//! - Byte 4: the data length code, between 0 and 8.
+//! - Bytes 5-12: the payload.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//!
+//! let can_device = static_init!(
+//!     capsules::virtual_can::CanDevice<'static>,
+//!     capsules::virtual_can::CanDevice::new(can_mux)
+//! );
+//! can_device.add_to_mux();
+//! let can = static_init!(
+//!     capsules::can::CanDriver<'static>,
+//!     capsules::can::CanDriver::new(
+//!         can_device,
+//!         board_kernel.create_grant(&grant_cap),
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! can.initialize_callback_handle(dynamic_deferred_caller.register(can).unwrap());
+//! hil::can::Can::set_client(can_device, can);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::can::{self, ErrorState, Filter, Frame, Id};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Can as usize;
+
+/// Size of an encoded frame in an allowed buffer.
+pub const FRAME_LEN: usize = 13;
+
+const ID_MASK: u32 = 0x1FFF_FFFF;
+const ID_REMOTE: u32 = 1 << 30;
+const ID_EXTENDED: u32 = 1 << 31;
+
+/// Largest number of process filters installed in the controller.
+const MAX_FILTERS: usize = 8;
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    state_callback: Option<Callback>,
+    rx_buffer: Option<AppSlice<Shared, u8>>,
+    tx_buffer: Option<AppSlice<Shared, u8>>,
+    joined: bool,
+    filter: Option<Filter>,
+    pending_tx: Option<Frame>,
+}
+
+pub struct CanDriver<'a> {
+    can: &'a dyn can::Can<'a>,
+    apps: Grant<App>,
+    inflight: OptionalCell<AppId>,
+    // The filters installed in the controller and how many of them are used,
+    // or `None` when off the bus.
+    installed: Cell<Option<([Filter; MAX_FILTERS], usize)>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+fn decode_id(word: u32) -> Id {
+    if word & ID_EXTENDED != 0 {
+        Id::Extended(word & ID_MASK)
+    } else {
+        Id::Standard((word & ID_MASK) as u16)
+    }
+}
+
+fn encode_id(id: Id, remote: bool) -> u32 {
+    let mut word = id.raw();
+    if id.is_extended() {
+        word |= ID_EXTENDED;
+    }
+    if remote {
+        word |= ID_REMOTE;
+    }
+    word
+}
+
+fn decode_frame(buf: &[u8]) -> Option<Frame> {
+    if buf.len() < FRAME_LEN {
+        return None;
+    }
+    let word = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let id = decode_id(word);
+    let dlc = buf[4];
+    if word & ID_REMOTE != 0 {
+        Frame::new_remote(id, dlc)
+    } else if dlc <= 8 {
+        Frame::new(id, &buf[5..5 + dlc as usize])
+    } else {
+        None
+    }
+}
+
+fn encode_frame(frame: &Frame, buf: &mut [u8]) {
+    buf[0..4].copy_from_slice(&encode_id(frame.id, frame.remote).to_le_bytes());
+    buf[4] = frame.dlc;
+    buf[5..FRAME_LEN].copy_from_slice(&frame.data);
+}
+
+impl<'a> CanDriver<'a> {
+    pub fn new(
+        can: &'a dyn can::Can<'a>,
+        grant: Grant<App>,
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> CanDriver<'a> {
+        CanDriver {
+            can: can,
+            apps: grant,
+            inflight: OptionalCell::empty(),
+            installed: Cell::new(None),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Install the filters of all processes on the bus, and join or leave the
+    /// bus depending on whether any process is on it. The controller is only
+    /// reconfigured if that changed since the last call, for instance because
+    /// a process exited.
+    fn update(&self) -> ReturnCode {
+        let hw_count = cmp::min(self.can.filter_count(), MAX_FILTERS);
+        let mut filters = [Filter::any(Id::Standard(0)); MAX_FILTERS];
+        let mut count = 0;
+        let mut overflow = false;
+        let mut joined = false;
+
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if !app.joined {
+                    return;
+                }
+                joined = true;
+                app.filter.map(|filter| {
+                    if filters[..count].contains(&filter) {
+                        return;
+                    }
+                    if count < hw_count {
+                        filters[count] = filter;
+                        count += 1;
+                    } else {
+                        overflow = true;
+                    }
+                });
+            });
+        }
+
+        if !joined {
+            self.installed.set(None);
+            return if self.can.is_enabled() {
+                self.can.disable()
+            } else {
+                ReturnCode::SUCCESS
+            };
+        }
+
+        if overflow {
+            // Accept every frame, `frame_received` filters per process.
+            filters = [Filter::any(Id::Standard(0)); MAX_FILTERS];
+            filters[1] = Filter::any(Id::Extended(0));
+            count = cmp::min(hw_count, 2);
+        }
+        if self.installed.get() == Some((filters, count)) && self.can.is_enabled() {
+            return ReturnCode::SUCCESS;
+        }
+        let rcode = self.can.set_filters(&filters[..count]);
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        self.installed.set(Some((filters, count)));
+        if self.can.is_enabled() {
+            ReturnCode::SUCCESS
+        } else {
+            self.can.enable()
+        }
+    }
+
+    /// Check from a deferred call whether processes left the bus by exiting.
+    fn schedule_update(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Start the next pending transmission if the controller is idle.
+    fn do_next_tx(&self) {
+        while self.inflight.is_none() {
+            let next = self.apps.iter().find_map(|cntr| {
+                cntr.enter(|app, _| app.pending_tx.take().map(|frame| (app.appid(), frame)))
+            });
+            let (appid, frame) = match next {
+                Some(next) => next,
+                None => return,
+            };
+            let rcode = self.can.send(&frame);
+            if rcode == ReturnCode::SUCCESS {
+                self.inflight.set(appid);
+            } else {
+                let _ = self.apps.enter(appid, |app, _| {
+                    app.tx_callback
+                        .map(|mut cb| cb.schedule(usize::from(rcode), 0, 0));
+                });
+            }
+        }
+    }
+
+    fn send(&self, appid: AppId) -> ReturnCode {
+        let rcode = self
+            .apps
+            .enter(appid, |app, _| {
+                if !app.joined {
+                    return ReturnCode::EOFF;
+                }
+                if app.pending_tx.is_some() || self.inflight.contains(&appid) {
+                    return ReturnCode::EBUSY;
+                }
+                let frame = app
+                    .tx_buffer
+                    .as_ref()
+                    .map_or(None, |buf| decode_frame(buf.as_ref()));
+                match frame {
+                    Some(frame) => {
+                        app.pending_tx = Some(frame);
+                        ReturnCode::SUCCESS
+                    }
+                    None => ReturnCode::EINVAL,
+                }
+            })
+            .unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS {
+            self.do_next_tx();
+        }
+        rcode
+    }
+}
+
+impl can::Client for CanDriver<'_> {
+    fn transmit_complete(&self, result: ReturnCode) {
+        self.inflight.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.tx_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+            });
+        });
+        self.do_next_tx();
+        self.schedule_update();
+    }
+
+    fn frame_received(&self, frame: &Frame) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                let wanted = app.joined && app.filter.map_or(false, |f| f.matches(frame.id));
+                if !wanted {
+                    return;
+                }
+                let delivered = app.rx_buffer.as_mut().map_or(false, |buf| {
+                    if buf.len() < FRAME_LEN {
+                        return false;
+                    }
+                    encode_frame(frame, buf.as_mut());
+                    true
+                });
+                if delivered {
+                    app.rx_callback.map(|mut cb| {
+                        cb.schedule(
+                            encode_id(frame.id, frame.remote) as usize,
+                            frame.dlc as usize,
+                            0,
+                        )
+                    });
+                }
+            });
+        }
+        self.schedule_update();
+    }
+
+    fn error_state_changed(&self, state: ErrorState) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.joined {
+                    app.state_callback
+                        .map(|mut cb| cb.schedule(state as usize, 0, 0));
+                }
+            });
+        }
+        self.schedule_update();
+    }
+}
+
+impl DynamicDeferredCallClient for CanDriver<'_> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.update();
+    }
+}
+
+impl Driver for CanDriver<'_> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Receive buffer, at least `FRAME_LEN` bytes. Each received frame
+    ///   overwrites the previous one.
+    /// - `1`: Transmit buffer holding the frame to send.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.rx_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.tx_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A frame was received into the receive buffer. The arguments are
+    ///   the identifier word and the data length code.
+    /// - `1`: The outstanding transmission finished. The argument is the
+    ///   result as a `ReturnCode`.
+    /// - `2`: The error state of the controller changed: 0 for error-active,
+    ///   1 for error-passive and 2 for bus-off.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match subscribe_num {
+                    0 => app.rx_callback = callback,
+                    1 => app.tx_callback = callback,
+                    2 => app.state_callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Control the CAN bus.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Join the bus.
+    /// - `2`: Leave the bus. A frame not yet handed to the controller is
+    ///   dropped.
+    /// - `3`: Send the frame in the transmit buffer.
+    /// - `4`: Set the acceptance filter. `arg1` is an identifier word, `arg2`
+    ///   the mask of identifier bits that must match.
+    /// - `5`: Remove the acceptance filter.
+    /// - `6`: Return the error state of the controller.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        // Commands changing the processes on the bus report the result of
+        // updating the controller themselves.
+        if let 0 | 3 | 6 = command_num {
+            self.update();
+        }
+
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 | 2 => {
+                let rcode = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.joined = command_num == 1;
+                        if !app.joined {
+                            app.pending_tx = None;
+                        }
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into());
+                if rcode == ReturnCode::SUCCESS {
+                    self.update()
+                } else {
+                    rcode
+                }
+            }
+
+            3 => self.send(appid),
+
+            4 | 5 => {
+                let filter = if command_num == 4 {
+                    let id = decode_id(arg1 as u32);
+                    if !id.is_valid() {
+                        return ReturnCode::EINVAL;
+                    }
+                    Some(Filter {
+                        id: id,
+                        mask: arg2 as u32 & ID_MASK,
+                    })
+                } else {
+                    None
+                };
+                let rcode = self
+                    .apps
+                    .enter(appid, |app, _| {
+                        app.filter = filter;
+                        app.joined
+                    })
+                    .map_err(ReturnCode::from);
+                match rcode {
+                    Ok(true) => self.update(),
+                    Ok(false) => ReturnCode::SUCCESS,
+                    Err(err) => err,
+                }
+            }
+
+            6 => ReturnCode::SuccessWithValue {
+                value: self.can.error_state() as usize,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::CanDriver;
+    use crate::can_loopback::CanLoopback;
+    use kernel::common::dynamic_deferred_call::{
+        DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+        DynamicDeferredCallClientState,
+    };
+    use kernel::hil::can::{Can, ErrorState};
+    use kernel::mock::MockKernel;
+    use kernel::{Driver, ReturnCode};
+    use std::boxed::Box;
+
+    fn setup() -> (
+        &'static MockKernel,
+        &'static CanDriver<'static>,
+        &'static CanLoopback<'static>,
+        &'static DynamicDeferredCall,
+        DeferredCallHandle,
+    ) {
+        let kernel = MockKernel::new(2);
+        let states: &'static [DynamicDeferredCallClientState] =
+            Box::leak(Box::new([DynamicDeferredCallClientState::default()]));
+        let ddc = Box::leak(Box::new(DynamicDeferredCall::new(states)));
+        let loopback = Box::leak(Box::new(CanLoopback::new(ddc)));
+        let driver = Box::leak(Box::new(CanDriver::new(
+            loopback,
+            kernel.create_grant(),
+            ddc,
+        )));
+        let handle = ddc.register(driver).unwrap();
+        driver.initialize_callback_handle(handle);
+        loopback.set_client(driver);
+        (kernel, driver, loopback, ddc, handle)
+    }
+
+    #[test]
+    fn test_bus_left_when_processes_exit() {
+        let (kernel, driver, loopback, ddc, handle) = setup();
+        let (a, b) = (kernel.process(0), kernel.process(1));
+
+        for (app, id) in [(a, 0x100), (b, 0x200)].iter() {
+            assert_eq!(driver.command(1, 0, 0, app.appid()), ReturnCode::SUCCESS);
+            assert_eq!(
+                driver.command(4, *id, 0x7FF, app.appid()),
+                ReturnCode::SUCCESS
+            );
+        }
+        assert!(loopback.is_enabled());
+        assert_eq!(loopback.hardware_filters(), 2);
+
+        // The filter of an exited process is removed on the next command.
+        a.terminate();
+        assert_eq!(driver.command(0, 0, 0, b.appid()), ReturnCode::SUCCESS);
+        assert_eq!(loopback.hardware_filters(), 1);
+        assert!(loopback.is_enabled());
+
+        // Once the last process exited, the next event of the controller
+        // takes it off the bus.
+        b.terminate();
+        loopback.set_error_state(ErrorState::Passive);
+        assert!(ddc.has_pending());
+        driver.call(handle);
+        assert!(!loopback.is_enabled());
+    }
+}
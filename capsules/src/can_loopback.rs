@@ -0,0 +1,210 @@
+//! Software CAN controller in loopback mode.
+//!
+//! `CanLoopback` implements `hil::can::Can` without any hardware: every frame
+//! sent is acknowledged and received back by the same controller, subject to
+//! its acceptance filters. It is useful to exercise CAN clients and userspace
+//! applications on boards without a transceiver, and as a stand-in controller
+//! in host-side unit tests.
+//!
+//! Transmissions complete from a deferred call, as they would from an
+//! interrupt on real hardware. Tests which do not run a deferred call loop can
+//! call `service()` directly instead. `set_error_state()` simulates the
+//! controller changing its fault confinement state.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//!
+//! let can_loopback = static_init!(
+//!     capsules::can_loopback::CanLoopback<'static>,
+//!     capsules::can_loopback::CanLoopback::new(dynamic_deferred_caller)
+//! );
+//! can_loopback.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(can_loopback).unwrap()
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::can::{self, ErrorState, Filter, Frame};
+use kernel::ReturnCode;
+
+/// Number of acceptance filters of the loopback controller.
+pub const FILTER_COUNT: usize = 4;
+
+/// Fastest classic CAN bit rate.
+const MAX_BITRATE: u32 = 1_000_000;
+
+pub struct CanLoopback<'a> {
+    client: OptionalCell<&'a dyn can::Client>,
+    enabled: Cell<bool>,
+    bitrate: Cell<u32>,
+    filters: Cell<[Option<Filter>; FILTER_COUNT]>,
+    state: Cell<ErrorState>,
+    tx: OptionalCell<Frame>,
+    dropped: Cell<usize>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> CanLoopback<'a> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> CanLoopback<'a> {
+        CanLoopback {
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            bitrate: Cell::new(125_000),
+            filters: Cell::new([None; FILTER_COUNT]),
+            state: Cell::new(ErrorState::Active),
+            tx: OptionalCell::empty(),
+            dropped: Cell::new(0),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Complete the outstanding transmission, if any, and receive the frame
+    /// back.
+    pub fn service(&self) {
+        self.tx.take().map(|frame| {
+            let accepted = self
+                .filters
+                .get()
+                .iter()
+                .filter_map(|filter| *filter)
+                .any(|filter| filter.matches(frame.id));
+            if accepted {
+                self.client.map(|client| client.frame_received(&frame));
+            } else {
+                self.dropped.set(self.dropped.get() + 1);
+            }
+            self.client
+                .map(|client| client.transmit_complete(ReturnCode::SUCCESS));
+        });
+    }
+
+    /// Simulate a change of the fault confinement state. Going bus-off fails
+    /// the outstanding transmission.
+    pub fn set_error_state(&self, state: ErrorState) {
+        if self.state.replace(state) == state {
+            return;
+        }
+        self.client.map(|client| client.error_state_changed(state));
+        if state == ErrorState::BusOff {
+            self.tx.take().map(|_| {
+                self.client
+                    .map(|client| client.transmit_complete(ReturnCode::FAIL));
+            });
+        }
+    }
+
+    /// Number of filters currently programmed.
+    pub fn hardware_filters(&self) -> usize {
+        self.filters
+            .get()
+            .iter()
+            .filter(|filter| filter.is_some())
+            .count()
+    }
+
+    /// Number of looped back frames discarded by the acceptance filters.
+    pub fn dropped(&self) -> usize {
+        self.dropped.get()
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate.get()
+    }
+}
+
+impl<'a> can::Can<'a> for CanLoopback<'a> {
+    fn set_client(&self, client: &'a dyn can::Client) {
+        self.client.set(client);
+    }
+
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode {
+        if self.enabled.get() {
+            ReturnCode::EBUSY
+        } else if bitrate == 0 || bitrate > MAX_BITRATE {
+            ReturnCode::EINVAL
+        } else {
+            self.bitrate.set(bitrate);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.enabled.set(true);
+        // Joining the bus resets the error counters.
+        self.state.set(ErrorState::Active);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        self.enabled.set(false);
+        self.tx.take().map(|_| {
+            self.client
+                .map(|client| client.transmit_complete(ReturnCode::ECANCEL));
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn send(&self, frame: &Frame) -> ReturnCode {
+        if !frame.is_valid() {
+            ReturnCode::EINVAL
+        } else if !self.enabled.get() || self.state.get() == ErrorState::BusOff {
+            ReturnCode::EOFF
+        } else if self.tx.is_some() {
+            ReturnCode::EBUSY
+        } else {
+            self.tx.set(*frame);
+            self.handle.map(|handle| self.deferred_caller.set(*handle));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn filter_count(&self) -> usize {
+        FILTER_COUNT
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode {
+        if filters.len() > FILTER_COUNT {
+            return ReturnCode::ESIZE;
+        }
+        let mut new = [None; FILTER_COUNT];
+        for (slot, filter) in new.iter_mut().zip(filters.iter()) {
+            *slot = Some(*filter);
+        }
+        self.filters.set(new);
+        ReturnCode::SUCCESS
+    }
+
+    fn error_state(&self) -> ErrorState {
+        self.state.get()
+    }
+}
+
+impl DynamicDeferredCallClient for CanLoopback<'_> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.service();
+    }
+}
@@ -1,5 +1,14 @@
-//! Provides userspace with access CTAP devices over any transport
-//! layer (USB HID, BLE, NFC). Currently only USB HID is supported.
+//! Provides userspace with access to CTAP messages over any transport
+//! layer (USB HID, BLE, NFC). Currently only USB HID is supported, through
+//! `ctaphid::CtapHidTransport`.
+//!
+//! The transport assembles the CTAPHID packets, so the process sees whole
+//! requests and sends whole responses. The driver can be the client of the
+//! transport, to let a process implement the authenticator, or the fallback
+//! client of the in-kernel `fido2::Authenticator`, to let a process handle
+//! the commands the authenticator does not, such as `CTAPHID_MSG`.
+//!
+//! Only one process at a time can listen for requests.
 //!
 //! Setup
 //! -----
@@ -8,191 +17,145 @@
 //! `hil::usb_hid::UsbHid` trait.
 //!
 //! ```rust
-//!     let ctap_send_buffer = static_init!([u8; 64], [0; 64]);
-//!     let ctap_recv_buffer = static_init!([u8; 64], [0; 64]);
-//!
-//!     let (ctap, ctap_driver) = components::ctap::CtapComponent::new(
+//!     let (ctap, ctap_transport, ctap_driver) = components::ctap::CtapComponent::new(
 //!         &earlgrey::usbdev::USB,
+//!         mux_alarm,
 //!         0x1337, // My important company
 //!         0x0DEC, // My device name
 //!         strings,
 //!         board_kernel,
-//!         ctap_send_buffer,
-//!         ctap_recv_buffer,
 //!     )
-//!     .finalize(components::usb_ctap_component_helper!(lowrisc::usbdev::Usb));
+//!     .finalize(components::usb_ctap_component_helper!(
+//!         lowrisc::usbdev::Usb,
+//!         lowrisc::timer::RvTimer
+//!     ));
 //!
 //!     ctap.enable();
 //!     ctap.attach();
+//!     ctap_transport.start();
 //! ```
-//!
 
+use crate::ctaphid::{self, KeepaliveStatus};
 use core::cell::Cell;
-use core::marker::PhantomData;
 use kernel::common::cells::{OptionalCell, TakeCell};
-use kernel::hil::usb_hid;
 use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 /// Syscall driver number.
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::CtapHid as usize;
 
+/// The callback event of a received request.
+const EVENT_REQUEST: usize = 0;
+/// The callback event of a request cancelled by the host.
+const EVENT_CANCEL: usize = 2;
+
 pub struct App {
     callback: OptionalCell<Callback>,
-    recv_buf: Option<AppSlice<Shared, u8>>,
-    send_buf: Option<AppSlice<Shared, u8>>,
-    can_receive: Cell<bool>,
+    request_buf: Option<AppSlice<Shared, u8>>,
+    response_buf: Option<AppSlice<Shared, u8>>,
 }
 
 impl Default for App {
     fn default() -> App {
         App {
             callback: OptionalCell::empty(),
-            recv_buf: None,
-            send_buf: None,
-            can_receive: Cell::new(false),
+            request_buf: None,
+            response_buf: None,
         }
     }
 }
 
-pub struct CtapDriver<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> {
-    usb: Option<&'a U>,
+pub struct CtapDriver<'a> {
+    transport: &'a dyn ctaphid::Transport<'a>,
 
     app: Grant<App>,
     appid: OptionalCell<AppId>,
-    phantom: PhantomData<&'a U>,
 
-    send_buffer: TakeCell<'static, [u8; 64]>,
-    recv_buffer: TakeCell<'static, [u8; 64]>,
+    /// The request being processed by the process.
+    message: TakeCell<'static, [u8]>,
+    cancelled: Cell<bool>,
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> CtapDriver<'a, U> {
-    pub fn new(
-        usb: Option<&'a U>,
-        send_buffer: &'static mut [u8; 64],
-        recv_buffer: &'static mut [u8; 64],
-        grant: Grant<App>,
-    ) -> CtapDriver<'a, U> {
+impl<'a> CtapDriver<'a> {
+    pub fn new(transport: &'a dyn ctaphid::Transport<'a>, grant: Grant<App>) -> CtapDriver<'a> {
         CtapDriver {
-            usb: usb,
+            transport,
             app: grant,
             appid: OptionalCell::empty(),
-            phantom: PhantomData,
-            send_buffer: TakeCell::new(send_buffer),
-            recv_buffer: TakeCell::new(recv_buffer),
-        }
-    }
-}
-
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> usb_hid::UsbHid<'a, [u8; 64]> for CtapDriver<'a, U> {
-    fn send_buffer(
-        &'a self,
-        send: &'static mut [u8; 64],
-    ) -> Result<usize, (ReturnCode, &'static mut [u8; 64])> {
-        if let Some(usb) = self.usb {
-            usb.send_buffer(send)
-        } else {
-            Err((ReturnCode::ENOSUPPORT, send))
-        }
-    }
-
-    fn send_cancel(&'a self) -> Result<&'static mut [u8; 64], ReturnCode> {
-        if let Some(usb) = self.usb {
-            usb.send_cancel()
-        } else {
-            Err(ReturnCode::ENOSUPPORT)
-        }
-    }
-
-    fn receive_buffer(
-        &'a self,
-        recv: &'static mut [u8; 64],
-    ) -> Result<(), (ReturnCode, &'static mut [u8; 64])> {
-        if let Some(usb) = self.usb {
-            usb.receive_buffer(recv)
-        } else {
-            Err((ReturnCode::ENODEVICE, recv))
+            message: TakeCell::empty(),
+            cancelled: Cell::new(false),
         }
     }
 
-    fn receive_cancel(&'a self) -> Result<&'static mut [u8; 64], ReturnCode> {
-        if let Some(usb) = self.usb {
-            usb.receive_cancel()
-        } else {
-            Err(ReturnCode::ENOSUPPORT)
-        }
+    /// Sends `status` as a CTAPHID error response, when the listening process
+    /// cannot.
+    fn respond_error(&self, status: u8) {
+        self.message.take().map(|message| {
+            message[0] = status;
+            self.transport.respond(ctaphid::CMD_ERROR, message, 1);
+        });
     }
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> usb_hid::Client<'a, [u8; 64]> for CtapDriver<'a, U> {
-    fn packet_received(
+impl<'a> ctaphid::Client<'a> for CtapDriver<'a> {
+    fn request(
         &'a self,
-        _result: ReturnCode,
-        buffer: &'static mut [u8; 64],
-        _endpoint: usize,
-    ) {
-        self.appid.map(|id| {
+        _channel: u32,
+        command: u8,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), &'static mut [u8]> {
+        let delivered = self.appid.map_or(false, |id| {
             self.app
                 .enter(*id, |app, _| {
-                    match app.recv_buf.as_mut() {
-                        Some(dest) => {
-                            dest.as_mut().copy_from_slice(buffer.as_ref());
+                    let copied = app.request_buf.as_mut().map_or(false, |dest| {
+                        if dest.len() < len {
+                            return false;
                         }
-                        None => {}
-                    };
-
-                    app.callback.map(|cb| {
-                        cb.schedule(0, 0, 0);
-                        // Set that we can't receive until the app says we can
-                        app.can_receive.set(false);
+                        dest.as_mut()[..len].copy_from_slice(&message[..len]);
+                        true
                     });
+                    if copied {
+                        app.callback
+                            .map(|cb| cb.schedule(EVENT_REQUEST, len, command as usize));
+                    }
+                    copied
                 })
-                .map_err(|err| {
-                    if err == kernel::procs::Error::NoSuchApp
-                        || err == kernel::procs::Error::InactiveApp
-                    {}
-                })
+                .unwrap_or(false)
         });
 
-        self.recv_buffer.replace(buffer);
+        if delivered {
+            self.cancelled.set(false);
+            self.message.replace(message);
+            Ok(())
+        } else {
+            Err(message)
+        }
     }
 
-    fn packet_transmitted(
-        &'a self,
-        _result: ReturnCode,
-        buffer: &'static mut [u8; 64],
-        _endpoint: usize,
-    ) {
-        self.appid.map(|id| {
+    fn cancel(&'a self) {
+        self.cancelled.set(true);
+        let notified = self.appid.map_or(false, |id| {
             self.app
                 .enter(*id, |app, _| {
-                    app.callback.map(|cb| {
-                        cb.schedule(1, 0, 0);
-                    });
-                })
-                .map_err(|err| {
-                    if err == kernel::procs::Error::NoSuchApp
-                        || err == kernel::procs::Error::InactiveApp
-                    {}
+                    app.callback.map(|cb| cb.schedule(EVENT_CANCEL, 0, 0));
                 })
+                .is_ok()
         });
-
-        // Save our send buffer so we can use it later
-        self.send_buffer.replace(buffer);
-    }
-
-    fn can_receive(&'a self) -> bool {
-        self.appid
-            .map(|id| {
-                self.app
-                    .enter(*id, |app, _| app.can_receive.get())
-                    .unwrap_or(false)
-            })
-            .unwrap_or(false)
+        if !notified {
+            // The process is gone and will never respond
+            self.respond_error(ctaphid::ERR_OTHER);
+        }
     }
 }
 
-impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> Driver for CtapDriver<'a, U> {
+impl Driver for CtapDriver<'_> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The buffer requests are copied to.
+    /// - `1`: The buffer responses are copied from.
     fn allow(
         &self,
         appid: AppId,
@@ -200,25 +163,22 @@ impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> Driver for CtapDriver<'a, U> {
         slice: Option<AppSlice<Shared, u8>>,
     ) -> ReturnCode {
         match allow_num {
-            // Pass buffer for the recvieved data to be stored in
             0 => self
                 .app
                 .enter(appid, |app, _| {
-                    app.recv_buf = slice;
+                    app.request_buf = slice;
                     ReturnCode::SUCCESS
                 })
-                .unwrap_or(ReturnCode::FAIL),
+                .unwrap_or_else(|err| err.into()),
 
-            // Pass buffer for the sent data to be stored in
             1 => self
                 .app
                 .enter(appid, |app, _| {
-                    app.send_buf = slice;
+                    app.response_buf = slice;
                     ReturnCode::SUCCESS
                 })
-                .unwrap_or(ReturnCode::FAIL),
+                .unwrap_or_else(|err| err.into()),
 
-            // default
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -227,10 +187,10 @@ impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> Driver for CtapDriver<'a, U> {
     ///
     /// ### `subscribe_num`
     ///
-    /// - `0`: Subscribe to interrupts from Ctap events.
-    ///        The callback signature is `fn(direction: u32)`
-    ///        `fn(0)` indicates a packet was recieved
-    ///        `fn(1)` indicates a packet was transmitted
+    /// - `0`: Subscribe to requests.
+    ///        The callback signature is `fn(event, len, command)`
+    ///        `fn(0, len, command)` indicates a request was received
+    ///        `fn(2, 0, 0)` indicates the host cancelled the request
     fn subscribe(
         &self,
         subscribe_num: usize,
@@ -238,191 +198,77 @@ impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>> Driver for CtapDriver<'a, U> {
         appid: AppId,
     ) -> ReturnCode {
         match subscribe_num {
-            0 => {
-                // set callback
-                self.app
-                    .enter(appid, |app, _| {
-                        app.callback.insert(callback);
-                        ReturnCode::SUCCESS
-                    })
-                    .unwrap_or(ReturnCode::FAIL)
-            }
+            0 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.callback.insert(callback);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
 
-            // default
             _ => ReturnCode::ENOSUPPORT,
         }
     }
 
-    fn command(
-        &self,
-        command_num: usize,
-        _data1: usize,
-        _data2: usize,
-        appid: AppId,
-    ) -> ReturnCode {
-        let can_access = self.appid.map_or(true, |owning_app| {
-            if owning_app == &appid {
-                // We own the Ctap device
-                true
-            } else {
-                false
-            }
-        });
-
-        if !can_access {
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Listen for requests. The first process to do so owns the
+    ///        driver.
+    /// - `2`: Respond to the request with command `data1`, with the first
+    ///        `data2` bytes of the response buffer.
+    /// - `3`: Report in keep-alive packets that the user must be present
+    ///        if `data1` is 1, or only that the request is processed.
+    fn command(&self, command_num: usize, data1: usize, data2: usize, appid: AppId) -> ReturnCode {
+        if command_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+        let owner = self.appid.map_or(true, |owning_app| owning_app == &appid);
+        if !owner {
             return ReturnCode::EBUSY;
         }
 
         match command_num {
-            // Send data
-            0 => self
-                .app
-                .enter(appid, |app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        match app.send_buf.as_ref() {
-                            Some(d) => {
-                                self.send_buffer.take().map(|buf| {
-                                    let data = d.as_ref();
-
-                                    // Copy the data into the static buffer
-                                    buf.copy_from_slice(&data[0..]);
+            1 => {
+                self.appid.set(appid);
+                ReturnCode::SUCCESS
+            }
 
-                                    let _ = usb.send_buffer(buf);
-                                });
-                            }
-                            None => {
-                                return ReturnCode::ERESERVE;
-                            }
-                        };
-                        ReturnCode::SUCCESS
-                    } else {
-                        ReturnCode::ENOSUPPORT
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Allow receive
-            1 => self
-                .app
-                .enter(appid, |app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        app.can_receive.set(true);
-                        if let Some(buf) = self.recv_buffer.take() {
-                            match usb.receive_buffer(buf) {
-                                Ok(_) => ReturnCode::SUCCESS,
-                                Err((err, buffer)) => {
-                                    self.recv_buffer.replace(buffer);
-                                    err
+            2 => {
+                if self.message.is_none() {
+                    return ReturnCode::EOFF;
+                }
+                self.app
+                    .enter(appid, |app, _| match app.response_buf.as_ref() {
+                        Some(src) if src.len() >= data2 => {
+                            self.message.take().map_or(ReturnCode::FAIL, |message| {
+                                if data2 > message.len() {
+                                    self.message.replace(message);
+                                    return ReturnCode::ESIZE;
                                 }
-                            }
-                        } else {
-                            ReturnCode::EBUSY
+                                message[..data2].copy_from_slice(&src.as_ref()[..data2]);
+                                self.transport.respond(data1 as u8, message, data2);
+                                ReturnCode::SUCCESS
+                            })
                         }
-                    } else {
-                        ReturnCode::ENOSUPPORT
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Cancel send
-            2 => self
-                .app
-                .enter(appid, |_app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        match usb.receive_cancel() {
-                            Ok(buf) => {
-                                self.recv_buffer.replace(buf);
-                                ReturnCode::ENOSUPPORT
-                            }
-                            Err(err) => err,
-                        }
-                    } else {
-                        ReturnCode::ENOSUPPORT
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Cancel receive
-            3 => self
-                .app
-                .enter(appid, |_app, _| {
-                    self.appid.set(appid);
-                    if let Some(usb) = self.usb {
-                        match usb.receive_cancel() {
-                            Ok(buf) => {
-                                self.recv_buffer.replace(buf);
-                                ReturnCode::ENOSUPPORT
-                            }
-                            Err(err) => err,
-                        }
-                    } else {
-                        ReturnCode::ENOSUPPORT
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
-            // Send or receive
-            // This command has two parts.
-            //    Part 1: Receive
-            //            This will allow receives, the same as the Allow
-            //            receive command above. If data is ready to receive
-            //            the `packet_received()` callback will be called.
-            //            When this happens the client callback will be
-            //            scheduled and no send event will occur.
-            //    Part 2: Send
-            //            If no receive occurs we will be left in a start where
-            //            future recieves will be allowed. This is the same
-            //            outcome as calling the Allow receive command.
-            //            As well as that we will then send the data in the
-            //            send buffer.
-            4 => self
-                .app
-                .enter(appid, |app, _| {
-                    if let Some(usb) = self.usb {
-                        if app.can_receive.get() {
-                            // We are already receiving
-                            ReturnCode::EBUSY
-                        } else {
-                            app.can_receive.set(true);
-                            if let Some(buf) = self.recv_buffer.take() {
-                                match usb.receive_buffer(buf) {
-                                    Ok(_) => ReturnCode::SUCCESS,
-                                    Err((err, buffer)) => {
-                                        self.recv_buffer.replace(buffer);
-                                        return err;
-                                    }
-                                }
-                            } else {
-                                return ReturnCode::EBUSY;
-                            };
-
-                            if !app.can_receive.get() {
-                                // The call to receive_buffer() collected a pending packet.
-                            } else {
-                                match app.send_buf.as_ref() {
-                                    Some(d) => {
-                                        self.send_buffer.take().map(|buf| {
-                                            let data = d.as_ref();
-
-                                            // Copy the data into the static buffer
-                                            buf.copy_from_slice(&data[0..]);
+                        Some(_) => ReturnCode::ESIZE,
+                        None => ReturnCode::ERESERVE,
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
 
-                                            let _ = usb.send_buffer(buf);
-                                        });
-                                    }
-                                    None => {
-                                        return ReturnCode::ERESERVE;
-                                    }
-                                };
-                            }
-                            ReturnCode::SUCCESS
-                        }
-                    } else {
-                        ReturnCode::ENOSUPPORT
-                    }
-                })
-                .unwrap_or_else(|err| err.into()),
+            3 => {
+                if self.message.is_none() || self.cancelled.get() {
+                    return ReturnCode::EOFF;
+                }
+                self.transport.set_keepalive_status(if data1 == 1 {
+                    KeepaliveStatus::UserPresenceNeeded
+                } else {
+                    KeepaliveStatus::Processing
+                });
+                ReturnCode::SUCCESS
+            }
 
-            // default
             _ => ReturnCode::ENOSUPPORT,
         }
     }
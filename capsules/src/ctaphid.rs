@@ -0,0 +1,934 @@
+//! CTAPHID message framing and channel management, over a USB HID interface.
+//!
+//! `CtapHidTransport` implements the CTAPHID protocol of the FIDO Client to
+//! Authenticator Protocol (section 8.1) on top of a `UsbHid` device with
+//! 64-byte reports, such as `usb::ctap::CtapHid`. It allocates channels
+//! with `CTAPHID_INIT`, reassembles requests from their initialization and
+//! continuation packets, and splits responses into packets, so its client
+//! only sees whole messages.
+//!
+//! One transaction runs at a time. Requests on other channels meanwhile get
+//! `ERR_CHANNEL_BUSY`, and a request whose packets stop arriving for
+//! `MESSAGE_TIMEOUT_MS` is dropped with `ERR_MSG_TIMEOUT`. The transport
+//! answers `CTAPHID_PING` and `CTAPHID_WINK` itself, and passes
+//! `CTAPHID_CBOR`, `CTAPHID_MSG` and vendor commands to its `Client`, which
+//! answers with `Transport::respond()`. Until it does, the transport sends
+//! `CTAPHID_KEEPALIVE` packets every `KEEPALIVE_INTERVAL_MS`, and forwards
+//! `CTAPHID_CANCEL` from the host to the client.
+//!
+//! The largest request is bounded by the message buffer given to `new()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let transport = static_init!(
+//!     CtapHidTransport<'static, CtapHid<'static, nrf52840::usbd::Usbd>, VirtualMuxAlarm<'static, Rtc>>,
+//!     CtapHidTransport::new(ctap, ctap_alarm, message_buf, send_report, recv_report)
+//! );
+//! ctap.set_client(transport);
+//! ctap_alarm.set_alarm_client(transport);
+//! transport.set_client(ctap_driver);
+//!
+//! ctap.enable();
+//! ctap.attach();
+//! transport.start();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time::{self, Alarm};
+use kernel::hil::usb_hid;
+use kernel::ReturnCode;
+
+pub const REPORT_LEN: usize = 64;
+
+/// The channel used to allocate channels.
+pub const BROADCAST_CID: u32 = 0xffff_ffff;
+
+pub const CMD_PING: u8 = 0x01;
+pub const CMD_MSG: u8 = 0x03;
+pub const CMD_LOCK: u8 = 0x04;
+pub const CMD_INIT: u8 = 0x06;
+pub const CMD_WINK: u8 = 0x08;
+pub const CMD_CBOR: u8 = 0x10;
+pub const CMD_CANCEL: u8 = 0x11;
+pub const CMD_KEEPALIVE: u8 = 0x3b;
+pub const CMD_ERROR: u8 = 0x3f;
+/// Commands from this one up to 0x7f are vendor specific.
+pub const CMD_VENDOR_FIRST: u8 = 0x40;
+
+pub const ERR_INVALID_CMD: u8 = 0x01;
+pub const ERR_INVALID_PAR: u8 = 0x02;
+pub const ERR_INVALID_LEN: u8 = 0x03;
+pub const ERR_INVALID_SEQ: u8 = 0x04;
+pub const ERR_MSG_TIMEOUT: u8 = 0x05;
+pub const ERR_CHANNEL_BUSY: u8 = 0x06;
+pub const ERR_INVALID_CHANNEL: u8 = 0x0b;
+pub const ERR_OTHER: u8 = 0x7f;
+
+const CAPABILITY_WINK: u8 = 0x01;
+const CAPABILITY_CBOR: u8 = 0x04;
+
+const PROTOCOL_VERSION: u8 = 2;
+
+/// Longest time between two packets of a request.
+pub const MESSAGE_TIMEOUT_MS: u32 = 500;
+/// Interval of the keep-alive packets sent while a request is processed.
+pub const KEEPALIVE_INTERVAL_MS: u32 = 100;
+
+const INIT_HEADER_LEN: usize = 7;
+const CONT_HEADER_LEN: usize = 5;
+
+/// The status reported by keep-alive packets.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KeepaliveStatus {
+    Processing = 1,
+    UserPresenceNeeded = 2,
+}
+
+/// The interface of the transport to the client processing requests.
+pub trait Transport<'a> {
+    fn set_client(&'a self, client: &'a dyn Client<'a>);
+
+    /// Sends the response to the request being processed, the first `len`
+    /// bytes of `message`, and takes back the message buffer.
+    fn respond(&'a self, command: u8, message: &'static mut [u8], len: usize);
+
+    /// Sets the status of the next keep-alive packets.
+    fn set_keepalive_status(&self, status: KeepaliveStatus);
+}
+
+pub trait Client<'a> {
+    /// Called with a request, in the first `len` bytes of `message`. The
+    /// client returns the buffer if it does not handle `command`, and
+    /// otherwise keeps it until it calls `respond()`.
+    fn request(
+        &'a self,
+        channel: u32,
+        command: u8,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), &'static mut [u8]>;
+
+    /// The host cancelled the request being processed. The client should
+    /// still respond, with an error if it did not complete.
+    fn cancel(&'a self);
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    Idle,
+    Receiving {
+        cid: u32,
+        command: u8,
+        len: usize,
+        received: usize,
+        seq: u8,
+    },
+    /// The client holds the message buffer.
+    Processing {
+        cid: u32,
+    },
+    Sending {
+        cid: u32,
+        command: u8,
+        len: usize,
+        sent: usize,
+        /// The sequence number of the next continuation packet, once the
+        /// initialization packet was sent.
+        seq: Option<u8>,
+    },
+}
+
+pub struct CtapHidTransport<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: Alarm<'a>> {
+    usb: &'a U,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn Client<'a>>,
+
+    state: Cell<State>,
+    next_cid: Cell<u32>,
+    /// Set when the host aborted the request being processed, whose response
+    /// is then dropped.
+    discard: Cell<bool>,
+    keepalive_status: Cell<KeepaliveStatus>,
+    keepalive_pending: Cell<bool>,
+
+    message: TakeCell<'static, [u8]>,
+    message_len: usize,
+    send_report: TakeCell<'static, [u8; 64]>,
+    recv_report: TakeCell<'static, [u8; 64]>,
+    /// A received report, processed once the report being sent is done.
+    held_report: TakeCell<'static, [u8; 64]>,
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: Alarm<'a>> CtapHidTransport<'a, U, A> {
+    pub fn new(
+        usb: &'a U,
+        alarm: &'a A,
+        message: &'static mut [u8],
+        send_report: &'static mut [u8; 64],
+        recv_report: &'static mut [u8; 64],
+    ) -> CtapHidTransport<'a, U, A> {
+        CtapHidTransport {
+            usb,
+            alarm,
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Idle),
+            next_cid: Cell::new(1),
+            discard: Cell::new(false),
+            keepalive_status: Cell::new(KeepaliveStatus::Processing),
+            keepalive_pending: Cell::new(false),
+            message_len: message.len(),
+            message: TakeCell::new(message),
+            send_report: TakeCell::new(send_report),
+            recv_report: TakeCell::new(recv_report),
+            held_report: TakeCell::empty(),
+        }
+    }
+
+    /// The size of the largest message.
+    pub fn max_message_len(&self) -> usize {
+        self.message_len
+    }
+
+    /// Starts receiving reports. The USB interface must be enabled.
+    pub fn start(&'a self) -> ReturnCode {
+        match self.recv_report.take() {
+            Some(report) => self.receive(report),
+            None => ReturnCode::EALREADY,
+        }
+    }
+
+    fn receive(&'a self, report: &'static mut [u8; 64]) -> ReturnCode {
+        match self.usb.receive_buffer(report) {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err((e, report)) => {
+                self.recv_report.replace(report);
+                e
+            }
+        }
+    }
+
+    /// Sends a packet on `cid` with the header of an initialization packet,
+    /// if no other packet is being sent.
+    fn send_init(&self, cid: u32, command: u8, payload: &[u8]) {
+        self.send_report.take().map(|report| {
+            *report = [0; REPORT_LEN];
+            report[..4].copy_from_slice(&cid.to_be_bytes());
+            report[4] = 0x80 | command;
+            report[5..7].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+            report[7..7 + payload.len()].copy_from_slice(payload);
+            if let Err((_, report)) = self.usb.send_buffer(report) {
+                self.send_report.replace(report);
+            }
+        });
+    }
+
+    fn send_error(&self, cid: u32, error: u8) {
+        self.send_init(cid, CMD_ERROR, &[error]);
+    }
+
+    /// Drops the request being received.
+    fn abort(&self) {
+        self.state.set(State::Idle);
+        self.alarm.disarm();
+    }
+
+    fn process(&'a self, report: &[u8; 64]) {
+        let mut cid = [0; 4];
+        cid.copy_from_slice(&report[..4]);
+        let cid = u32::from_be_bytes(cid);
+        if report[4] & 0x80 == 0 {
+            self.continuation(cid, report);
+            return;
+        }
+        let command = report[4] & 0x7f;
+        let len = u16::from_be_bytes([report[5], report[6]]) as usize;
+
+        let allocated = cid != 0 && cid < self.next_cid.get();
+        if command == CMD_INIT {
+            self.init(cid, len, &report[INIT_HEADER_LEN..INIT_HEADER_LEN + 8]);
+            return;
+        }
+        if !allocated {
+            self.send_error(cid, ERR_INVALID_CHANNEL);
+            return;
+        }
+
+        match self.state.get() {
+            State::Idle => {}
+            State::Receiving { cid: current, .. } if current == cid => {
+                self.abort();
+                if command != CMD_CANCEL {
+                    self.send_error(cid, ERR_INVALID_SEQ);
+                }
+                return;
+            }
+            State::Processing { cid: current } if current == cid && command == CMD_CANCEL => {
+                self.client.map(|client| client.cancel());
+                return;
+            }
+            _ => {
+                if command != CMD_CANCEL {
+                    self.send_error(cid, ERR_CHANNEL_BUSY);
+                }
+                return;
+            }
+        }
+        if command == CMD_CANCEL {
+            return;
+        }
+        if len > self.message_len {
+            self.send_error(cid, ERR_INVALID_LEN);
+            return;
+        }
+
+        let received = len.min(REPORT_LEN - INIT_HEADER_LEN);
+        self.message.map(|message| {
+            message[..received]
+                .copy_from_slice(&report[INIT_HEADER_LEN..INIT_HEADER_LEN + received])
+        });
+        if received == len {
+            self.dispatch(cid, command, len);
+        } else {
+            self.state.set(State::Receiving {
+                cid,
+                command,
+                len,
+                received,
+                seq: 0,
+            });
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(MESSAGE_TIMEOUT_MS));
+        }
+    }
+
+    fn init(&'a self, cid: u32, len: usize, nonce: &[u8]) {
+        if len != 8 {
+            self.send_error(cid, ERR_INVALID_LEN);
+            return;
+        }
+        let new_cid = if cid == BROADCAST_CID {
+            let new_cid = self.next_cid.get();
+            self.next_cid.set(if new_cid + 1 == BROADCAST_CID {
+                1
+            } else {
+                new_cid + 1
+            });
+            new_cid
+        } else if cid != 0 && cid < self.next_cid.get() {
+            // Resynchronize the channel, dropping its transaction
+            match self.state.get() {
+                State::Receiving { cid: current, .. } if current == cid => self.abort(),
+                State::Processing { cid: current } if current == cid => {
+                    self.discard.set(true);
+                    self.client.map(|client| client.cancel());
+                }
+                _ => {}
+            }
+            cid
+        } else {
+            self.send_error(cid, ERR_INVALID_CHANNEL);
+            return;
+        };
+
+        let mut response = [0; 17];
+        response[..8].copy_from_slice(nonce);
+        response[8..12].copy_from_slice(&new_cid.to_be_bytes());
+        response[12] = PROTOCOL_VERSION;
+        response[13..16].copy_from_slice(&[1, 0, 0]);
+        response[16] = CAPABILITY_WINK | CAPABILITY_CBOR;
+        self.send_init(cid, CMD_INIT, &response);
+    }
+
+    fn continuation(&'a self, cid: u32, report: &[u8; 64]) {
+        // Continuation packets outside of a transaction are ignored
+        if let State::Receiving {
+            cid: current,
+            command,
+            len,
+            received,
+            seq,
+        } = self.state.get()
+        {
+            if current != cid {
+                return;
+            }
+            if report[4] != seq {
+                self.abort();
+                self.send_error(cid, ERR_INVALID_SEQ);
+                return;
+            }
+            let n = (len - received).min(REPORT_LEN - CONT_HEADER_LEN);
+            self.message.map(|message| {
+                message[received..received + n]
+                    .copy_from_slice(&report[CONT_HEADER_LEN..CONT_HEADER_LEN + n])
+            });
+            if received + n == len {
+                self.alarm.disarm();
+                self.dispatch(cid, command, len);
+            } else {
+                self.state.set(State::Receiving {
+                    cid,
+                    command,
+                    len,
+                    received: received + n,
+                    seq: seq + 1,
+                });
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_ms(MESSAGE_TIMEOUT_MS));
+            }
+        }
+    }
+
+    /// Handles a complete request.
+    fn dispatch(&'a self, cid: u32, command: u8, len: usize) {
+        match command {
+            CMD_PING | CMD_WINK => {
+                let len = if command == CMD_PING { len } else { 0 };
+                self.state.set(State::Sending {
+                    cid,
+                    command,
+                    len,
+                    sent: 0,
+                    seq: None,
+                });
+                self.pump();
+            }
+            CMD_CBOR | CMD_MSG | CMD_VENDOR_FIRST..=0x7f if self.client.is_some() => {
+                let message = match self.message.take() {
+                    Some(message) => message,
+                    None => {
+                        self.state.set(State::Idle);
+                        self.send_error(cid, ERR_OTHER);
+                        return;
+                    }
+                };
+                self.state.set(State::Processing { cid });
+                self.discard.set(false);
+                self.keepalive_status.set(KeepaliveStatus::Processing);
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_ms(KEEPALIVE_INTERVAL_MS));
+                let res = self
+                    .client
+                    .map(move |client| client.request(cid, command, message, len));
+                if let Some(Err(message)) = res {
+                    self.message.replace(message);
+                    self.abort();
+                    self.send_error(cid, ERR_INVALID_CMD);
+                }
+            }
+            _ => {
+                self.state.set(State::Idle);
+                self.send_error(cid, ERR_INVALID_CMD);
+            }
+        }
+    }
+
+    /// Sends the next packet of the response, or a keep-alive packet, unless
+    /// a packet is being sent.
+    fn pump(&self) {
+        match self.state.get() {
+            State::Sending {
+                cid,
+                command,
+                len,
+                sent,
+                seq,
+            } => {
+                let report = match self.send_report.take() {
+                    Some(report) => report,
+                    None => return,
+                };
+                *report = [0; REPORT_LEN];
+                report[..4].copy_from_slice(&cid.to_be_bytes());
+                let (header_len, next_seq) = match seq {
+                    None => {
+                        report[4] = 0x80 | command;
+                        report[5..7].copy_from_slice(&(len as u16).to_be_bytes());
+                        (INIT_HEADER_LEN, 0)
+                    }
+                    Some(seq) => {
+                        report[4] = seq;
+                        (CONT_HEADER_LEN, seq + 1)
+                    }
+                };
+                let n = (len - sent).min(REPORT_LEN - header_len);
+                self.message.map(|message| {
+                    report[header_len..header_len + n].copy_from_slice(&message[sent..sent + n])
+                });
+                self.state.set(State::Sending {
+                    cid,
+                    command,
+                    len,
+                    sent: sent + n,
+                    seq: Some(next_seq),
+                });
+                if let Err((_, report)) = self.usb.send_buffer(report) {
+                    // The response cannot be sent; drop it
+                    self.send_report.replace(report);
+                    self.state.set(State::Idle);
+                }
+            }
+            State::Processing { cid } if self.keepalive_pending.get() => {
+                if self.send_report.is_some() {
+                    self.keepalive_pending.set(false);
+                    self.send_init(cid, CMD_KEEPALIVE, &[self.keepalive_status.get() as u8]);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: Alarm<'a>> Transport<'a>
+    for CtapHidTransport<'a, U, A>
+{
+    fn set_client(&'a self, client: &'a dyn Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn respond(&'a self, command: u8, message: &'static mut [u8], len: usize) {
+        let len = len.min(message.len());
+        self.message.replace(message);
+        if let State::Processing { cid } = self.state.get() {
+            self.alarm.disarm();
+            self.keepalive_pending.set(false);
+            if self.discard.take() {
+                self.state.set(State::Idle);
+            } else {
+                self.state.set(State::Sending {
+                    cid,
+                    command,
+                    len,
+                    sent: 0,
+                    seq: None,
+                });
+                self.pump();
+            }
+        }
+    }
+
+    fn set_keepalive_status(&self, status: KeepaliveStatus) {
+        self.keepalive_status.set(status);
+    }
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: Alarm<'a>> usb_hid::Client<'a, [u8; 64]>
+    for CtapHidTransport<'a, U, A>
+{
+    fn packet_received(&'a self, result: ReturnCode, report: &'static mut [u8; 64], _: usize) {
+        if result != ReturnCode::SUCCESS {
+            self.receive(report);
+            return;
+        }
+        if self.send_report.is_none() {
+            // Wait until the reply can be sent
+            self.held_report.replace(report);
+            return;
+        }
+        self.process(report);
+        self.receive(report);
+    }
+
+    fn packet_transmitted(&'a self, _result: ReturnCode, report: &'static mut [u8; 64], _: usize) {
+        self.send_report.replace(report);
+        if let State::Sending {
+            len,
+            sent,
+            seq: Some(_),
+            ..
+        } = self.state.get()
+        {
+            if sent == len {
+                self.state.set(State::Idle);
+            }
+        }
+        self.pump();
+        if self.send_report.is_some() {
+            self.held_report.take().map(|report| {
+                self.process(report);
+                self.receive(report);
+            });
+        }
+    }
+
+    fn can_receive(&'a self) -> bool {
+        true
+    }
+}
+
+impl<'a, U: usb_hid::UsbHid<'a, [u8; 64]>, A: Alarm<'a>> time::AlarmClient
+    for CtapHidTransport<'a, U, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Receiving { cid, .. } => {
+                self.state.set(State::Idle);
+                // The error is dropped if a report is waiting to be answered
+                if self.held_report.is_none() {
+                    self.send_error(cid, ERR_MSG_TIMEOUT);
+                }
+            }
+            State::Processing { .. } => {
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_ms(KEEPALIVE_INTERVAL_MS));
+                self.keepalive_pending.set(true);
+                self.pump();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use core::cell::RefCell;
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks, Ticks32};
+    use kernel::hil::usb_hid::{Client as _, UsbHid};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestAlarm {
+        alarm: Cell<Option<u32>>,
+    }
+
+    impl time::Time for TestAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0.into()
+        }
+    }
+
+    impl<'a> time::Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.alarm.get().unwrap_or(0).into()
+        }
+
+        fn disarm(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+
+        fn is_armed(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// A HID interface whose reports are exchanged by the test.
+    struct TestHid {
+        sent: RefCell<Vec<[u8; 64]>>,
+        in_flight: TakeCell<'static, [u8; 64]>,
+        receiving: TakeCell<'static, [u8; 64]>,
+    }
+
+    impl<'a> UsbHid<'a, [u8; 64]> for TestHid {
+        fn send_buffer(
+            &'a self,
+            send: &'static mut [u8; 64],
+        ) -> Result<usize, (ReturnCode, &'static mut [u8; 64])> {
+            self.sent.borrow_mut().push(*send);
+            self.in_flight.replace(send);
+            Ok(REPORT_LEN)
+        }
+
+        fn send_cancel(&'a self) -> Result<&'static mut [u8; 64], ReturnCode> {
+            Err(ReturnCode::ENOSUPPORT)
+        }
+
+        fn receive_buffer(
+            &'a self,
+            recv: &'static mut [u8; 64],
+        ) -> Result<(), (ReturnCode, &'static mut [u8; 64])> {
+            self.receiving.replace(recv);
+            Ok(())
+        }
+
+        fn receive_cancel(&'a self) -> Result<&'static mut [u8; 64], ReturnCode> {
+            Err(ReturnCode::ENOSUPPORT)
+        }
+    }
+
+    /// A client that keeps the requests until the test answers them.
+    struct TestClient {
+        requests: RefCell<Vec<(u8, Vec<u8>)>>,
+        message: TakeCell<'static, [u8]>,
+        cancelled: Cell<bool>,
+    }
+
+    impl<'a> Client<'a> for TestClient {
+        fn request(
+            &'a self,
+            _channel: u32,
+            command: u8,
+            message: &'static mut [u8],
+            len: usize,
+        ) -> Result<(), &'static mut [u8]> {
+            if command == CMD_MSG {
+                return Err(message);
+            }
+            self.requests
+                .borrow_mut()
+                .push((command, message[..len].to_vec()));
+            self.message.replace(message);
+            Ok(())
+        }
+
+        fn cancel(&'a self) {
+            self.cancelled.set(true);
+        }
+    }
+
+    type TestTransport<'a> = CtapHidTransport<'a, TestHid, TestAlarm>;
+
+    fn setup() -> (
+        &'static TestHid,
+        &'static TestAlarm,
+        &'static TestClient,
+        &'static TestTransport<'static>,
+    ) {
+        let hid: &'static TestHid = Box::leak(Box::new(TestHid {
+            sent: RefCell::new(Vec::new()),
+            in_flight: TakeCell::empty(),
+            receiving: TakeCell::empty(),
+        }));
+        let alarm = Box::leak(Box::new(TestAlarm {
+            alarm: Cell::new(None),
+        }));
+        let client: &'static TestClient = Box::leak(Box::new(TestClient {
+            requests: RefCell::new(Vec::new()),
+            message: TakeCell::empty(),
+            cancelled: Cell::new(false),
+        }));
+        let transport = Box::leak(Box::new(CtapHidTransport::new(
+            hid,
+            alarm,
+            Box::leak(Box::new([0; 256])),
+            Box::leak(Box::new([0; 64])),
+            Box::leak(Box::new([0; 64])),
+        )));
+        transport.set_client(client);
+        assert_eq!(transport.start(), ReturnCode::SUCCESS);
+        (hid, alarm, client, transport)
+    }
+
+    fn packet(header: &[u8], payload: &[u8]) -> [u8; 64] {
+        let mut report = [0; 64];
+        report[..header.len()].copy_from_slice(header);
+        report[header.len()..header.len() + payload.len()].copy_from_slice(payload);
+        report
+    }
+
+    fn init_packet(cid: u32, command: u8, len: usize, payload: &[u8]) -> [u8; 64] {
+        let mut header = [0; 7];
+        header[..4].copy_from_slice(&cid.to_be_bytes());
+        header[4] = 0x80 | command;
+        header[5..].copy_from_slice(&(len as u16).to_be_bytes());
+        packet(&header, payload)
+    }
+
+    fn cont_packet(cid: u32, seq: u8, payload: &[u8]) -> [u8; 64] {
+        let mut header = [0; 5];
+        header[..4].copy_from_slice(&cid.to_be_bytes());
+        header[4] = seq;
+        packet(&header, payload)
+    }
+
+    fn receive(hid: &TestHid, transport: &'static TestTransport<'static>, report: [u8; 64]) {
+        let buffer = hid.receiving.take().expect("no receive buffer");
+        *buffer = report;
+        transport.packet_received(ReturnCode::SUCCESS, buffer, 0);
+    }
+
+    /// Completes the transmission of the reports until the link is idle,
+    /// and returns them.
+    fn transmit(hid: &TestHid, transport: &'static TestTransport<'static>) -> Vec<[u8; 64]> {
+        while let Some(buffer) = hid.in_flight.take() {
+            transport.packet_transmitted(ReturnCode::SUCCESS, buffer, 0);
+        }
+        hid.sent.replace(Vec::new())
+    }
+
+    fn allocate(hid: &TestHid, transport: &'static TestTransport<'static>) -> u32 {
+        let nonce = [1, 2, 3, 4, 5, 6, 7, 8];
+        receive(
+            hid,
+            transport,
+            init_packet(BROADCAST_CID, CMD_INIT, 8, &nonce),
+        );
+        let sent = transmit(hid, transport);
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            &sent[0][..7],
+            &init_packet(BROADCAST_CID, CMD_INIT, 17, &[])[..7]
+        );
+        assert_eq!(&sent[0][7..15], &nonce);
+        assert_eq!(&sent[0][19..24], &[PROTOCOL_VERSION, 1, 0, 0, 0x05]);
+        let mut cid = [0; 4];
+        cid.copy_from_slice(&sent[0][15..19]);
+        u32::from_be_bytes(cid)
+    }
+
+    fn error(cid: u32, error: u8) -> [u8; 64] {
+        init_packet(cid, CMD_ERROR, 1, &[error])
+    }
+
+    #[test]
+    fn ping() {
+        let (hid, _, _, transport) = setup();
+        let cid = allocate(hid, transport);
+        assert_eq!(cid, 1);
+        assert_eq!(allocate(hid, transport), 2);
+
+        // A ping of 100 bytes takes an initialization and a continuation
+        // packet both ways
+        let data: Vec<u8> = (0..100).collect();
+        receive(hid, transport, init_packet(cid, CMD_PING, 100, &data[..57]));
+        assert!(hid.sent.borrow().is_empty());
+        receive(hid, transport, cont_packet(cid, 0, &data[57..]));
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![
+                init_packet(cid, CMD_PING, 100, &data[..57]),
+                cont_packet(cid, 0, &data[57..])
+            ]
+        );
+
+        receive(hid, transport, init_packet(cid, CMD_WINK, 0, &[]));
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![init_packet(cid, CMD_WINK, 0, &[])]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let (hid, alarm, _, transport) = setup();
+        let cid = allocate(hid, transport);
+        let exchange = |report| {
+            receive(hid, transport, report);
+            transmit(hid, transport)
+        };
+
+        // Unallocated channels, unknown commands and oversized requests
+        assert_eq!(
+            exchange(init_packet(7, CMD_PING, 0, &[])),
+            std::vec![error(7, ERR_INVALID_CHANNEL)]
+        );
+        assert_eq!(
+            exchange(init_packet(cid, CMD_LOCK, 0, &[])),
+            std::vec![error(cid, ERR_INVALID_CMD)]
+        );
+        assert_eq!(
+            exchange(init_packet(cid, CMD_PING, 257, &[])),
+            std::vec![error(cid, ERR_INVALID_LEN)]
+        );
+        assert_eq!(
+            exchange(init_packet(cid, CMD_MSG, 0, &[])),
+            std::vec![error(cid, ERR_INVALID_CMD)]
+        );
+
+        // Other channels are busy during a transaction, and a wrong sequence
+        // number aborts it
+        let other = allocate(hid, transport);
+        assert!(exchange(init_packet(cid, CMD_PING, 100, &[0; 57])).is_empty());
+        assert_eq!(
+            exchange(init_packet(other, CMD_PING, 0, &[])),
+            std::vec![error(other, ERR_CHANNEL_BUSY)]
+        );
+        assert_eq!(
+            exchange(cont_packet(cid, 1, &[0; 43])),
+            std::vec![error(cid, ERR_INVALID_SEQ)]
+        );
+        assert!(exchange(cont_packet(cid, 0, &[0; 43])).is_empty());
+
+        // The transaction times out without its next packet
+        assert!(exchange(init_packet(cid, CMD_PING, 100, &[0; 57])).is_empty());
+        assert_eq!(alarm.alarm.get(), Some(MESSAGE_TIMEOUT_MS));
+        transport.alarm();
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![error(cid, ERR_MSG_TIMEOUT)]
+        );
+        assert!(exchange(cont_packet(cid, 0, &[0; 43])).is_empty());
+
+        // INIT on the channel resynchronizes it
+        assert!(exchange(init_packet(cid, CMD_PING, 100, &[0; 57])).is_empty());
+        let sent = exchange(init_packet(cid, CMD_INIT, 8, &[0; 8]));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(&sent[0][..7], &init_packet(cid, CMD_INIT, 17, &[])[..7]);
+        assert_eq!(alarm.alarm.get(), None);
+        assert!(exchange(cont_packet(cid, 0, &[0; 43])).is_empty());
+    }
+
+    #[test]
+    fn requests() {
+        let (hid, alarm, client, transport) = setup();
+        let cid = allocate(hid, transport);
+
+        let request: Vec<u8> = (0..70).collect();
+        receive(
+            hid,
+            transport,
+            init_packet(cid, CMD_CBOR, 70, &request[..57]),
+        );
+        receive(hid, transport, cont_packet(cid, 0, &request[57..]));
+        assert_eq!(client.requests.take(), std::vec![(CMD_CBOR, request)]);
+
+        // Keep-alive packets are sent until the response
+        assert_eq!(alarm.alarm.get(), Some(KEEPALIVE_INTERVAL_MS));
+        transport.alarm();
+        transport.set_keepalive_status(KeepaliveStatus::UserPresenceNeeded);
+        transport.alarm();
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![
+                init_packet(cid, CMD_KEEPALIVE, 1, &[1]),
+                init_packet(cid, CMD_KEEPALIVE, 1, &[2])
+            ]
+        );
+
+        // Requests meanwhile are refused, and a cancellation goes to the
+        // client
+        receive(hid, transport, init_packet(cid, CMD_CBOR, 1, &[0]));
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![error(cid, ERR_CHANNEL_BUSY)]
+        );
+        receive(hid, transport, init_packet(cid, CMD_CANCEL, 0, &[]));
+        assert!(client.cancelled.get());
+
+        // A report received while a packet is sent is processed after it
+        let message = client.message.take().unwrap();
+        message[0] = 0x2d;
+        transport.respond(CMD_CBOR, message, 1);
+        assert_eq!(alarm.alarm.get(), None);
+        receive(hid, transport, init_packet(cid, CMD_WINK, 0, &[]));
+        assert_eq!(hid.sent.borrow().len(), 1);
+        assert!(hid.receiving.is_none());
+        assert_eq!(
+            transmit(hid, transport),
+            std::vec![
+                init_packet(cid, CMD_CBOR, 1, &[0x2d]),
+                init_packet(cid, CMD_WINK, 0, &[])
+            ]
+        );
+        assert!(hid.receiving.is_some());
+    }
+}
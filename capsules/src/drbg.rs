@@ -0,0 +1,714 @@
+//! HMAC_DRBG deterministic random bit generator, seeded from an entropy
+//! source.
+//!
+//! `HmacDrbg` implements the HMAC_DRBG of NIST SP 800-90A (section 10.1.2)
+//! with SHA-256, at a security strength of 256 bits. It is instantiated from
+//! 256 bits of entropy, a 128-bit nonce drawn from the same source and an
+//! optional personalization string, and reseeded with another 256 bits of
+//! entropy. At the assumed `MIN_ENTROPY_PER_WORD` bits per word, this takes
+//! 32 words of the source for the entropy and 16 for the nonce, which are
+//! conditioned down with HMAC-SHA256 (SP 800-90B section 3.1.5.1.1). The
+//! generator is reseeded:
+//!
+//! - after `reseed_interval` generate requests (`DEFAULT_RESEED_INTERVAL`
+//!   by default, see `set_reseed_interval()`),
+//! - when `reseed()` was called, for instance once the board knows an event
+//!   could have exposed the state,
+//! - before every request, if prediction resistance is enabled with
+//!   `set_prediction_resistance()`.
+//!
+//! The raw entropy is checked by the continuous health tests of NIST SP
+//! 800-90B (section 4.4), the Repetition Count Test and the Adaptive
+//! Proportion Test, with cutoffs for an assumed min-entropy of
+//! `MIN_ENTROPY_PER_WORD` bits per 32-bit word. Before the first seed, and
+//! after a failure, the tests run over `STARTUP_WORDS` words which are then
+//! discarded. When a test fails, the collected entropy is discarded, the
+//! generator is uninstantiated, and the waiting requests complete with
+//! `FAIL`; the next request starts over with the startup tests.
+//!
+//! Each `DrbgUser` is a `hil::rng::Rng` with its own client, so the RNG
+//! syscall driver and kernel capsules can share the generator. Randomness is
+//! delivered from deferred calls, in generate requests of at most
+//! `WORDS_PER_REQUEST` words; a client returning `Continue::More` is served
+//! again by a new request.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::drbg::{DrbgUser, HmacDrbg};
+//! # use kernel::static_init;
+//! let drbg = static_init!(
+//!     HmacDrbg<'static>,
+//!     HmacDrbg::new(&nrf52840::trng::TRNG, dynamic_deferred_caller)
+//! );
+//! drbg.initialize_callback_handle(dynamic_deferred_caller.register(drbg).unwrap());
+//! nrf52840::trng::TRNG.set_client(drbg);
+//!
+//! let rng = static_init!(DrbgUser<'static>, DrbgUser::new(drbg));
+//! rng.setup();
+//! ```
+
+use crate::sha::hmac_sha256;
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::entropy::{self, Entropy32};
+use kernel::hil::rng::{self, Rng};
+use kernel::ReturnCode;
+
+/// Generate requests between two reseeds, unless `set_reseed_interval()` is
+/// called.
+pub const DEFAULT_RESEED_INTERVAL: u32 = 1 << 16;
+
+/// Largest number of words produced by one generate request, which is well
+/// below the 2^19 bits allowed by SP 800-90A.
+pub const WORDS_PER_REQUEST: usize = 256;
+
+/// The min-entropy of a word of the source, in bits, assumed by the health
+/// tests and by the amount of entropy collected for a seed.
+pub const MIN_ENTROPY_PER_WORD: usize = 8;
+
+/// Number of words checked by the health tests before the entropy is used.
+pub const STARTUP_WORDS: usize = 1024;
+
+/// Repetition Count Test cutoff, `1 + ceil(20 / H)` for a false positive
+/// probability of 2^-20.
+const REPETITION_CUTOFF: usize = 1 + (20 + MIN_ENTROPY_PER_WORD - 1) / MIN_ENTROPY_PER_WORD;
+
+/// Adaptive Proportion Test window, for non-binary samples.
+const PROPORTION_WINDOW: usize = 512;
+
+/// Adaptive Proportion Test cutoff, `1 + CRITBINOM(512, 2^-8, 1 - 2^-20)`.
+const PROPORTION_CUTOFF: usize = 13;
+
+/// Security strength of the generator, in bits.
+const SECURITY_STRENGTH: usize = 256;
+
+const SEED_LEN: usize = 32;
+const NONCE_LEN: usize = 16;
+
+/// Bytes of the source conditioned into the entropy input and the nonce,
+/// which hold `SECURITY_STRENGTH` and `SECURITY_STRENGTH / 2` bits of
+/// min-entropy.
+const SEED_ENTROPY_LEN: usize = SECURITY_STRENGTH / MIN_ENTROPY_PER_WORD * 4;
+const NONCE_ENTROPY_LEN: usize = SECURITY_STRENGTH / 2 / MIN_ENTROPY_PER_WORD * 4;
+const ENTROPY_LEN: usize = SEED_ENTROPY_LEN + NONCE_ENTROPY_LEN;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    /// No seed was derived, or the health tests failed.
+    Uninstantiated,
+    Ready,
+}
+
+/// The continuous health tests of SP 800-90B, over 32-bit words.
+struct HealthTests {
+    last: Cell<u32>,
+    repetitions: Cell<usize>,
+    reference: Cell<u32>,
+    window: Cell<usize>,
+    matches: Cell<usize>,
+}
+
+impl HealthTests {
+    fn new() -> HealthTests {
+        HealthTests {
+            last: Cell::new(0),
+            repetitions: Cell::new(0),
+            reference: Cell::new(0),
+            window: Cell::new(0),
+            matches: Cell::new(0),
+        }
+    }
+
+    fn reset(&self) {
+        self.repetitions.set(0);
+        self.window.set(0);
+    }
+
+    /// Runs both tests on the next word. Returns `false` on a failure.
+    fn check(&self, word: u32) -> bool {
+        // Repetition Count Test
+        if self.repetitions.get() > 0 && word == self.last.get() {
+            self.repetitions.set(self.repetitions.get() + 1);
+        } else {
+            self.last.set(word);
+            self.repetitions.set(1);
+        }
+
+        // Adaptive Proportion Test
+        if self.window.get() == 0 {
+            self.reference.set(word);
+            self.matches.set(1);
+        } else if word == self.reference.get() {
+            self.matches.set(self.matches.get() + 1);
+        }
+        self.window.set((self.window.get() + 1) % PROPORTION_WINDOW);
+
+        self.repetitions.get() < REPETITION_CUTOFF && self.matches.get() < PROPORTION_CUTOFF
+    }
+}
+
+pub struct HmacDrbg<'a> {
+    egen: &'a dyn Entropy32<'a>,
+    users: List<'a, DrbgUser<'a>>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+
+    key: Cell<[u8; 32]>,
+    value: Cell<[u8; 32]>,
+    state: Cell<State>,
+    reseed_counter: Cell<u32>,
+    reseed_interval: Cell<u32>,
+    reseed_requested: Cell<bool>,
+    prediction_resistance: Cell<bool>,
+    /// Whether the state was reseeded since the last generate request.
+    fresh: Cell<bool>,
+    personalization: Cell<&'a [u8]>,
+
+    health: HealthTests,
+    /// Words left to check before the entropy is used.
+    startup_words: Cell<usize>,
+    collecting: Cell<bool>,
+    /// The entropy collected for the next seed.
+    seed: Cell<[u8; ENTROPY_LEN]>,
+    seed_len: Cell<usize>,
+    /// Set when the entropy source failed, until the waiting users are told.
+    failed: Cell<bool>,
+}
+
+impl<'a> HmacDrbg<'a> {
+    pub fn new(egen: &'a dyn Entropy32<'a>, deferred_caller: &'a DynamicDeferredCall) -> Self {
+        HmacDrbg {
+            egen,
+            users: List::new(),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            key: Cell::new([0; 32]),
+            value: Cell::new([0; 32]),
+            state: Cell::new(State::Uninstantiated),
+            reseed_counter: Cell::new(0),
+            reseed_interval: Cell::new(DEFAULT_RESEED_INTERVAL),
+            reseed_requested: Cell::new(false),
+            prediction_resistance: Cell::new(false),
+            fresh: Cell::new(false),
+            personalization: Cell::new(&[]),
+            health: HealthTests::new(),
+            startup_words: Cell::new(STARTUP_WORDS),
+            collecting: Cell::new(false),
+            seed: Cell::new([0; ENTROPY_LEN]),
+            seed_len: Cell::new(0),
+            failed: Cell::new(false),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Sets the personalization string mixed into the seed, such as a
+    /// device identifier. It takes effect at the next instantiation.
+    pub fn set_personalization(&self, personalization: &'a [u8]) {
+        self.personalization.set(personalization);
+    }
+
+    /// Sets the number of generate requests between two reseeds, at least 1.
+    pub fn set_reseed_interval(&self, interval: u32) {
+        self.reseed_interval.set(interval.max(1));
+    }
+
+    /// When enabled, every generate request is preceded by a reseed.
+    pub fn set_prediction_resistance(&self, enabled: bool) {
+        self.prediction_resistance.set(enabled);
+    }
+
+    /// Reseeds the generator before the next generate request.
+    pub fn reseed(&self) {
+        self.reseed_requested.set(true);
+    }
+
+    fn needs_seed(&self) -> bool {
+        self.state.get() != State::Ready
+            || self.reseed_counter.get() > self.reseed_interval.get()
+            || self.reseed_requested.get()
+            || (self.prediction_resistance.get() && !self.fresh.get())
+    }
+
+    fn schedule(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    /// Starts serving the users with pending requests.
+    fn request(&self) {
+        if self.needs_seed() {
+            self.collect();
+        } else {
+            self.schedule();
+        }
+    }
+
+    /// Starts collecting entropy for a seed.
+    fn collect(&self) {
+        if self.collecting.get() {
+            return;
+        }
+        self.seed_len.set(0);
+        self.collecting.set(true);
+        if self.egen.get() != ReturnCode::SUCCESS {
+            self.fail();
+        }
+    }
+
+    /// Discards the entropy and the state, and reports the failure to the
+    /// waiting users.
+    fn fail(&self) {
+        self.collecting.set(false);
+        self.seed.set([0; ENTROPY_LEN]);
+        self.seed_len.set(0);
+        self.key.set([0; 32]);
+        self.value.set([0; 32]);
+        self.state.set(State::Uninstantiated);
+        self.health.reset();
+        self.startup_words.set(STARTUP_WORDS);
+        self.failed.set(true);
+        self.schedule();
+    }
+
+    /// The number of entropy bytes needed for the next seed.
+    fn seed_needed(&self) -> usize {
+        match self.state.get() {
+            State::Uninstantiated => ENTROPY_LEN,
+            State::Ready => SEED_ENTROPY_LEN,
+        }
+    }
+
+    /// HMAC_DRBG_Update of SP 800-90A 10.1.2.2, with the concatenation of
+    /// `provided` as provided data.
+    fn update(&self, provided: &[&[u8]]) {
+        let mut key = self.key.get();
+        let mut value = self.value.get();
+        for &round in [0u8, 1].iter() {
+            if round == 1 && provided.iter().all(|p| p.is_empty()) {
+                break;
+            }
+            let mut parts: [&[u8]; 4] = [&value, &[round], &[], &[]];
+            for (part, p) in parts[2..].iter_mut().zip(provided.iter()) {
+                *part = p;
+            }
+            key = hmac_sha256(&key, &parts);
+            value = hmac_sha256(&key, &[&value]);
+        }
+        self.key.set(key);
+        self.value.set(value);
+    }
+
+    /// Derives the state from the collected entropy: instantiates the
+    /// generator, or reseeds it.
+    fn seeded(&self) {
+        let raw = self.seed.get();
+        let mut seed = [0; SEED_LEN + NONCE_LEN];
+        seed[..SEED_LEN].copy_from_slice(&condition(&raw[..SEED_ENTROPY_LEN]));
+        match self.state.get() {
+            State::Uninstantiated => {
+                let mut nonce = condition(&raw[SEED_ENTROPY_LEN..]);
+                seed[SEED_LEN..].copy_from_slice(&nonce[..NONCE_LEN]);
+                nonce.iter_mut().for_each(|b| *b = 0);
+                self.key.set([0; 32]);
+                self.value.set([1; 32]);
+                self.update(&[&seed, self.personalization.get()]);
+            }
+            State::Ready => self.update(&[&seed[..SEED_LEN]]),
+        }
+        seed.iter_mut().for_each(|b| *b = 0);
+        self.seed.set([0; ENTROPY_LEN]);
+        self.seed_len.set(0);
+        self.collecting.set(false);
+        self.state.set(State::Ready);
+        self.reseed_counter.set(1);
+        self.reseed_requested.set(false);
+        self.fresh.set(true);
+        self.schedule();
+    }
+}
+
+/// Compresses raw entropy into 32 bytes with HMAC-SHA256, one of the vetted
+/// conditioning functions of SP 800-90B.
+fn condition(raw: &[u8]) -> [u8; 32] {
+    hmac_sha256(&[0; 32], &[raw])
+}
+
+/// The output of a generate request.
+struct Generator<'a, 'b> {
+    drbg: &'b HmacDrbg<'a>,
+    block: [u8; 32],
+    offset: usize,
+    words: usize,
+}
+
+impl Iterator for Generator<'_, '_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        if self.words == WORDS_PER_REQUEST {
+            return None;
+        }
+        if self.offset == self.block.len() {
+            self.block = hmac_sha256(&self.drbg.key.get(), &[&self.drbg.value.get()]);
+            self.drbg.value.set(self.block);
+            self.offset = 0;
+        }
+        let mut word = [0; 4];
+        word.copy_from_slice(&self.block[self.offset..self.offset + 4]);
+        self.offset += 4;
+        self.words += 1;
+        Some(u32::from_le_bytes(word))
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for HmacDrbg<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.failed.take() {
+            for user in self.users.iter() {
+                if user.pending.take() {
+                    user.client.map(|client| {
+                        client.randomness_available(&mut core::iter::empty(), ReturnCode::FAIL)
+                    });
+                }
+            }
+            return;
+        }
+
+        // Serve each waiting user with one generate request
+        for user in self.users.iter() {
+            if !user.pending.get() {
+                continue;
+            }
+            if self.needs_seed() {
+                self.collect();
+                return;
+            }
+            let mut generator = Generator {
+                drbg: self,
+                block: [0; 32],
+                offset: 32,
+                words: 0,
+            };
+            let more = user.client.map_or(rng::Continue::Done, |client| {
+                client.randomness_available(&mut generator, ReturnCode::SUCCESS)
+            });
+            generator.block.iter_mut().for_each(|b| *b = 0);
+            self.update(&[]);
+            self.reseed_counter.set(self.reseed_counter.get() + 1);
+            self.fresh.set(false);
+            if more == rng::Continue::Done {
+                user.pending.set(false);
+            }
+        }
+        if self.users.iter().any(|user| user.pending.get()) {
+            self.request();
+        }
+    }
+}
+
+impl entropy::Client32 for HmacDrbg<'_> {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> entropy::Continue {
+        if !self.collecting.get() {
+            return entropy::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            self.fail();
+            return entropy::Continue::Done;
+        }
+
+        let mut seed = self.seed.get();
+        let mut seed_len = self.seed_len.get();
+        let needed = self.seed_needed();
+        for word in entropy {
+            if !self.health.check(word) {
+                seed.iter_mut().for_each(|b| *b = 0);
+                self.fail();
+                return entropy::Continue::Done;
+            }
+            if self.startup_words.get() > 0 {
+                self.startup_words.set(self.startup_words.get() - 1);
+                continue;
+            }
+            seed[seed_len..seed_len + 4].copy_from_slice(&word.to_le_bytes());
+            seed_len += 4;
+            if seed_len == needed {
+                break;
+            }
+        }
+        self.seed.set(seed);
+        self.seed_len.set(seed_len);
+        if seed_len == needed {
+            self.seeded();
+            entropy::Continue::Done
+        } else {
+            entropy::Continue::More
+        }
+    }
+}
+
+/// A client of the DRBG.
+pub struct DrbgUser<'a> {
+    drbg: &'a HmacDrbg<'a>,
+    next: ListLink<'a, DrbgUser<'a>>,
+    client: OptionalCell<&'a dyn rng::Client>,
+    pending: Cell<bool>,
+}
+
+impl<'a> DrbgUser<'a> {
+    pub fn new(drbg: &'a HmacDrbg<'a>) -> DrbgUser<'a> {
+        DrbgUser {
+            drbg,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            pending: Cell::new(false),
+        }
+    }
+
+    pub fn setup(&'a self) {
+        self.drbg.users.push_head(self);
+    }
+}
+
+impl<'a> Rng<'a> for DrbgUser<'a> {
+    fn get(&self) -> ReturnCode {
+        if self.client.is_none() {
+            return ReturnCode::EOFF;
+        }
+        if !self.pending.get() {
+            self.pending.set(true);
+            self.drbg.request();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.pending.set(false);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'a self, client: &'a dyn rng::Client) {
+        self.client.set(client);
+    }
+}
+
+impl<'a> ListNode<'a, DrbgUser<'a>> for DrbgUser<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, DrbgUser<'a>> {
+        &self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::hex::hex;
+    use kernel::common::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// An entropy source whose words are delivered by the test.
+    struct Source {
+        requests: Cell<usize>,
+        state: Cell<u32>,
+    }
+
+    impl Source {
+        fn next_word(&self) -> u32 {
+            let x = self
+                .state
+                .get()
+                .wrapping_mul(1103515245)
+                .wrapping_add(12345);
+            self.state.set(x);
+            x
+        }
+
+        /// Delivers words to `drbg` until it has enough, `per_call` at a
+        /// time.
+        fn deliver(&self, drbg: &HmacDrbg, per_call: usize) {
+            loop {
+                let words: Vec<u32> = (0..per_call).map(|_| self.next_word()).collect();
+                let res = entropy::Client32::entropy_available(
+                    drbg,
+                    &mut words.into_iter(),
+                    ReturnCode::SUCCESS,
+                );
+                if res == entropy::Continue::Done {
+                    return;
+                }
+            }
+        }
+    }
+
+    impl<'a> Entropy32<'a> for Source {
+        fn get(&self) -> ReturnCode {
+            self.requests.set(self.requests.get() + 1);
+            ReturnCode::SUCCESS
+        }
+        fn cancel(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn set_client(&'a self, _: &'a dyn entropy::Client32) {}
+    }
+
+    /// Takes `words` words of each request.
+    struct Client {
+        words: usize,
+        received: Cell<Option<(Vec<u32>, ReturnCode)>>,
+    }
+
+    impl rng::Client for Client {
+        fn randomness_available(
+            &self,
+            randomness: &mut dyn Iterator<Item = u32>,
+            error: ReturnCode,
+        ) -> rng::Continue {
+            self.received
+                .set(Some((randomness.take(self.words).collect(), error)));
+            rng::Continue::Done
+        }
+    }
+
+    fn setup() -> (
+        &'static HmacDrbg<'static>,
+        &'static Source,
+        &'static DrbgUser<'static>,
+        &'static Client,
+        DeferredCallHandle,
+    ) {
+        let deferred_caller: &'static DynamicDeferredCall =
+            Box::leak(Box::new(DynamicDeferredCall::new(Box::leak(Box::new(
+                <[DynamicDeferredCallClientState; 1]>::default(),
+            )))));
+        let source: &'static Source = Box::leak(Box::new(Source {
+            requests: Cell::new(0),
+            state: Cell::new(1),
+        }));
+        let drbg: &'static HmacDrbg = Box::leak(Box::new(HmacDrbg::new(source, deferred_caller)));
+        let handle = deferred_caller.register(drbg).unwrap();
+        drbg.initialize_callback_handle(handle);
+        let user: &'static DrbgUser = Box::leak(Box::new(DrbgUser::new(drbg)));
+        user.setup();
+        let client: &'static Client = Box::leak(Box::new(Client {
+            words: 8,
+            received: Cell::new(None),
+        }));
+        user.set_client(client);
+        (drbg, source, user, client, handle)
+    }
+
+    fn words(bytes: &[u8]) -> Vec<u32> {
+        bytes
+            .chunks(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect()
+    }
+
+    /// The expected outputs were computed with an independent implementation
+    /// of SP 800-90A 10.1.2 and of the conditioning, from the same entropy.
+    #[test]
+    fn generate_and_reseed() {
+        let (drbg, source, user, client, handle) = setup();
+        drbg.set_personalization(b"Tock");
+        drbg.set_reseed_interval(2);
+
+        let expected = [
+            "da768f7cdfec26f29f491dc5e82f7f970f750c123facb3486f8de304420af359",
+            "42243596ae7b38ea947a55005c6da8fbd540de07ba50d95af847615a9d2b876c",
+            "90ad24eb2aa8a2f19a9a856845e4023eeadc9d73abd92829249e1072edcf4999",
+        ];
+        for (i, expected) in expected.iter().enumerate() {
+            assert_eq!(user.get(), ReturnCode::SUCCESS);
+            // Instantiation, and the reseed after two requests, collect
+            // entropy before the deferred call is scheduled. The words are
+            // delivered by four so that none are left over.
+            if i == 0 || i == 2 {
+                assert_eq!(source.requests.get(), 1 + i / 2);
+                source.deliver(drbg, 4);
+            }
+            assert!(client.received.take().is_none());
+            DynamicDeferredCallClient::call(drbg, handle);
+            assert_eq!(
+                client.received.take(),
+                Some((words(&hex(expected)), ReturnCode::SUCCESS))
+            );
+        }
+        assert_eq!(source.requests.get(), 2);
+    }
+
+    #[test]
+    fn prediction_resistance() {
+        let (drbg, source, user, client, handle) = setup();
+        drbg.set_prediction_resistance(true);
+        for i in 1..4 {
+            assert_eq!(user.get(), ReturnCode::SUCCESS);
+            assert_eq!(source.requests.get(), i);
+            source.deliver(drbg, 16);
+            DynamicDeferredCallClient::call(drbg, handle);
+            assert_eq!(client.received.take().unwrap().1, ReturnCode::SUCCESS);
+        }
+
+        // An explicit reseed request collects entropy once
+        drbg.set_prediction_resistance(false);
+        drbg.reseed();
+        assert_eq!(user.get(), ReturnCode::SUCCESS);
+        assert_eq!(source.requests.get(), 4);
+        source.deliver(drbg, 16);
+        DynamicDeferredCallClient::call(drbg, handle);
+        assert_eq!(user.get(), ReturnCode::SUCCESS);
+        DynamicDeferredCallClient::call(drbg, handle);
+        assert_eq!(source.requests.get(), 4);
+        assert_eq!(client.received.take().unwrap().1, ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn health_tests() {
+        let (drbg, source, user, client, handle) = setup();
+        assert_eq!(user.get(), ReturnCode::SUCCESS);
+
+        // A word repeated REPETITION_CUTOFF times fails the Repetition
+        // Count Test
+        let stuck = [7; REPETITION_CUTOFF];
+        assert_eq!(
+            entropy::Client32::entropy_available(
+                drbg,
+                &mut stuck.iter().cloned(),
+                ReturnCode::SUCCESS
+            ),
+            entropy::Continue::Done
+        );
+        DynamicDeferredCallClient::call(drbg, handle);
+        assert_eq!(client.received.take(), Some((Vec::new(), ReturnCode::FAIL)));
+
+        // A word which comes back too often within a window fails the
+        // Adaptive Proportion Test
+        assert_eq!(user.get(), ReturnCode::SUCCESS);
+        let mut biased =
+            (0..PROPORTION_WINDOW as u32).map(|i| if i % 8 == 0 { 5 } else { source.next_word() });
+        assert_eq!(
+            entropy::Client32::entropy_available(drbg, &mut biased, ReturnCode::SUCCESS),
+            entropy::Continue::Done
+        );
+        DynamicDeferredCallClient::call(drbg, handle);
+        assert_eq!(client.received.take(), Some((Vec::new(), ReturnCode::FAIL)));
+
+        // The generator starts over once the source recovers
+        assert_eq!(user.get(), ReturnCode::SUCCESS);
+        source.deliver(drbg, 64);
+        DynamicDeferredCallClient::call(drbg, handle);
+        assert_eq!(client.received.take().unwrap().1, ReturnCode::SUCCESS);
+    }
+}
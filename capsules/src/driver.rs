@@ -16,6 +16,7 @@ pub enum NUM {
     Adc                   = 0x00005,
     Dac                   = 0x00006,
     AnalogComparator      = 0x00007,
+    Pwm                   = 0x00010,
 
     // Kernel
     Ipc                   = 0x10000,
@@ -26,17 +27,24 @@ pub enum NUM {
     I2cMaster             = 0x20003,
     UsbUser               = 0x20005,
     I2cMasterSlave        = 0x20006,
+    Can                   = 0x20007,
+    UsbHid                = 0x20008,
 
     // Radio
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Ping                  = 0x30003,
+    Ieee802154Mlme        = 0x30004,
+    Coap                  = 0x30005,
 
     // Cryptography
+    Aes                   = 0x40000,
     Rng                   = 0x40001,
     Crc                   = 0x40002,
     Hmac                  = 0x40003,
     CtapHid               = 0x40004,
+    PublicKeyCrypto       = 0x40005,
 
     // Storage
     AppFlash              = 0x50000,
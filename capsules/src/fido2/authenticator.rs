@@ -0,0 +1,1538 @@
+//! A FIDO2 authenticator, answering the CTAP2 requests of a `ctaphid`
+//! transport.
+//!
+//! The authenticator implements `authenticatorGetInfo`,
+//! `authenticatorMakeCredential` and `authenticatorGetAssertion` with ES256
+//! (ECDSA over P-256 with SHA-256) credentials. Every credential is
+//! resident: it is stored, with its private key, in the nonvolatile
+//! storage given to `new()`, in the layout of `fido2::credentials`. New
+//! credentials carry a "packed" self attestation.
+//!
+//! The user confirms presence by pressing the button given to `new()`,
+//! within `PRESENCE_TIMEOUT_MS`. Only GetAssertion requests with the "up"
+//! option set to false proceed without it. No PIN or user verification is
+//! supported.
+//!
+//! `CTAPHID_MSG` and vendor commands, which the authenticator does not
+//! handle, go to the client set with `set_fallback()`, such as the CTAP
+//! syscall driver.
+//!
+//! The storage is read with `load()` at boot, and the authenticator answers
+//! requests after that completes.
+
+use super::cbor::{Item, Reader, Writer};
+use super::credentials::{self, CREDENTIAL_ID_LEN, MAX_USER_ID_LEN, RECORD_LEN};
+use crate::ctaphid::{self, KeepaliveStatus, Transport, CMD_CBOR};
+use crate::public_key_crypto::p256;
+use crate::sha::Sha256Context;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::gpio::{self, ActivationMode, ActivationState, InterruptEdge};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::time::{self, Alarm};
+use kernel::ReturnCode;
+
+pub const COMMAND_MAKE_CREDENTIAL: u8 = 0x01;
+pub const COMMAND_GET_ASSERTION: u8 = 0x02;
+pub const COMMAND_GET_INFO: u8 = 0x04;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_INVALID_COMMAND: u8 = 0x01;
+pub const STATUS_INVALID_LENGTH: u8 = 0x03;
+pub const STATUS_CBOR_UNEXPECTED_TYPE: u8 = 0x11;
+pub const STATUS_INVALID_CBOR: u8 = 0x12;
+pub const STATUS_MISSING_PARAMETER: u8 = 0x14;
+pub const STATUS_CREDENTIAL_EXCLUDED: u8 = 0x19;
+pub const STATUS_UNSUPPORTED_ALGORITHM: u8 = 0x26;
+pub const STATUS_KEY_STORE_FULL: u8 = 0x28;
+pub const STATUS_UNSUPPORTED_OPTION: u8 = 0x2b;
+pub const STATUS_INVALID_OPTION: u8 = 0x2c;
+pub const STATUS_KEEPALIVE_CANCEL: u8 = 0x2d;
+pub const STATUS_NO_CREDENTIALS: u8 = 0x2e;
+pub const STATUS_USER_ACTION_TIMEOUT: u8 = 0x2f;
+pub const STATUS_PIN_NOT_SET: u8 = 0x35;
+pub const STATUS_OTHER: u8 = 0x7f;
+
+/// How long the user has to confirm presence.
+pub const PRESENCE_TIMEOUT_MS: u32 = 30_000;
+
+/// COSE algorithm identifier of ES256.
+const ALG_ES256: i64 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// The length of authenticator data without attested credential data.
+const AUTH_DATA_LEN: usize = 37;
+/// The length of the COSE encoding of a P-256 public key.
+const COSE_KEY_LEN: usize = 77;
+const ATTESTED_AUTH_DATA_LEN: usize = AUTH_DATA_LEN + 16 + 2 + CREDENTIAL_ID_LEN + COSE_KEY_LEN;
+
+/// The length of the random data of a new credential: the private key and
+/// the credential ID.
+const RANDOM_LEN: usize = 32 + CREDENTIAL_ID_LEN;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    /// Reading the credentials from storage.
+    Loading,
+    /// Writing an empty table to unformatted storage.
+    Formatting,
+    Idle,
+    /// Waiting for the user to confirm presence.
+    Presence,
+    /// Collecting randomness for a new credential.
+    Random,
+    /// Writing a credential or the signature counter.
+    Storing,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Operation {
+    MakeCredential,
+    GetAssertion,
+}
+
+type Status = u8;
+
+fn unexpected(item: Option<Item>) -> Status {
+    match item {
+        Some(_) => STATUS_CBOR_UNEXPECTED_TYPE,
+        None => STATUS_INVALID_CBOR,
+    }
+}
+
+fn read_int(r: &mut Reader) -> Result<i64, Status> {
+    let item = r.next();
+    item.and_then(|i| i.as_int())
+        .ok_or_else(|| unexpected(item))
+}
+
+fn read_bytes<'b>(r: &mut Reader<'b>) -> Result<&'b [u8], Status> {
+    match r.next() {
+        Some(Item::Bytes(b)) => Ok(b),
+        item => Err(unexpected(item)),
+    }
+}
+
+fn read_text<'b>(r: &mut Reader<'b>) -> Result<&'b [u8], Status> {
+    match r.next() {
+        Some(Item::Text(t)) => Ok(t),
+        item => Err(unexpected(item)),
+    }
+}
+
+fn read_bool(r: &mut Reader) -> Result<bool, Status> {
+    match r.next() {
+        Some(Item::Bool(b)) => Ok(b),
+        item => Err(unexpected(item)),
+    }
+}
+
+fn read_array(r: &mut Reader) -> Result<usize, Status> {
+    match r.next() {
+        Some(Item::Array(n)) => Ok(n),
+        item => Err(unexpected(item)),
+    }
+}
+
+fn read_map(r: &mut Reader) -> Result<usize, Status> {
+    match r.next() {
+        Some(Item::Map(n)) => Ok(n),
+        item => Err(unexpected(item)),
+    }
+}
+
+fn skip(r: &mut Reader) -> Result<(), Status> {
+    r.skip().ok_or(STATUS_INVALID_CBOR)
+}
+
+/// Reads a PublicKeyCredentialDescriptor and returns its ID.
+fn read_credential_descriptor<'b>(r: &mut Reader<'b>) -> Result<&'b [u8], Status> {
+    let mut id = None;
+    for _ in 0..read_map(r)? {
+        if read_text(r)? == b"id" {
+            id = Some(read_bytes(r)?);
+        } else {
+            skip(r)?;
+        }
+    }
+    id.ok_or(STATUS_MISSING_PARAMETER)
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut ctx = Sha256Context::new();
+    ctx.update(data);
+    ctx.finish(&mut hash);
+    hash
+}
+
+/// Encodes an ECDSA signature, given as `r | s`, in the DER encoding of
+/// WebAuthn attestation and assertion signatures. Returns its length.
+pub fn der_signature(signature: &[u8], der: &mut [u8; 72]) -> usize {
+    let mut pos = 2;
+    for half in signature[..64].chunks(32) {
+        let start = half.iter().position(|b| *b != 0).unwrap_or(31);
+        let pad = (half[start] & 0x80 != 0) as usize;
+        let len = 32 - start + pad;
+        der[pos] = 0x02;
+        der[pos + 1] = len as u8;
+        der[pos + 2] = 0;
+        der[pos + 2 + pad..pos + 2 + len].copy_from_slice(&half[start..]);
+        pos += 2 + len;
+    }
+    der[0] = 0x30;
+    der[1] = (pos - 2) as u8;
+    pos
+}
+
+pub struct Authenticator<'a, A: Alarm<'a>> {
+    transport: &'a dyn Transport<'a>,
+    alarm: &'a A,
+    rng: &'a dyn Rng<'a>,
+    storage: &'a dyn NonvolatileStorage<'a>,
+    storage_address: usize,
+    aaguid: [u8; 16],
+    presence: &'a dyn gpio::InterruptPin<'a>,
+    presence_mode: ActivationMode,
+    fallback: OptionalCell<&'a dyn ctaphid::Client<'a>>,
+
+    state: Cell<State>,
+    operation: Cell<Operation>,
+    /// Whether the fallback client processes the current request.
+    forwarded: Cell<bool>,
+    message: TakeCell<'static, [u8]>,
+
+    client_data_hash: Cell<[u8; 32]>,
+    rp_id_hash: Cell<[u8; 32]>,
+    user_id: Cell<[u8; MAX_USER_ID_LEN]>,
+    user_id_len: Cell<usize>,
+    /// Whether the request needs the user to be present.
+    user_presence: Cell<bool>,
+    /// The record of the credential being created or used.
+    credential: Cell<usize>,
+    random: Cell<[u8; RANDOM_LEN]>,
+    random_len: Cell<usize>,
+
+    /// The credentials, unless they are being loaded.
+    table: TakeCell<'a, [u8]>,
+    record: TakeCell<'a, [u8]>,
+    /// The part of the table being written.
+    write_offset: Cell<usize>,
+    write_len: Cell<usize>,
+}
+
+impl<'a, A: Alarm<'a>> Authenticator<'a, A> {
+    /// `presence` is the button, active in `presence_mode`, with which the
+    /// user confirms presence. It must be configured as an input, with this
+    /// authenticator as its client. `table` must hold
+    /// `credentials::STORAGE_LEN` bytes, and `record` must hold
+    /// `credentials::RECORD_LEN` bytes.
+    pub fn new(
+        transport: &'a dyn Transport<'a>,
+        alarm: &'a A,
+        rng: &'a dyn Rng<'a>,
+        storage: &'a dyn NonvolatileStorage<'a>,
+        storage_address: usize,
+        aaguid: [u8; 16],
+        presence: &'a dyn gpio::InterruptPin<'a>,
+        presence_mode: ActivationMode,
+        table: &'a mut [u8],
+        record: &'a mut [u8],
+    ) -> Authenticator<'a, A> {
+        Authenticator {
+            transport,
+            alarm,
+            rng,
+            storage,
+            storage_address,
+            aaguid,
+            presence,
+            presence_mode,
+            fallback: OptionalCell::empty(),
+            state: Cell::new(State::Loading),
+            operation: Cell::new(Operation::GetAssertion),
+            forwarded: Cell::new(false),
+            message: TakeCell::empty(),
+            client_data_hash: Cell::new([0; 32]),
+            rp_id_hash: Cell::new([0; 32]),
+            user_id: Cell::new([0; MAX_USER_ID_LEN]),
+            user_id_len: Cell::new(0),
+            user_presence: Cell::new(true),
+            credential: Cell::new(0),
+            random: Cell::new([0; RANDOM_LEN]),
+            random_len: Cell::new(0),
+            table: TakeCell::new(table),
+            record: TakeCell::new(record),
+            write_offset: Cell::new(0),
+            write_len: Cell::new(0),
+        }
+    }
+
+    /// Passes the requests the authenticator does not handle to `client`.
+    pub fn set_fallback(&self, client: &'a dyn ctaphid::Client<'a>) {
+        self.fallback.set(client);
+    }
+
+    /// Reads the credentials from storage.
+    pub fn load(&self) -> ReturnCode {
+        self.table.take().map_or(ReturnCode::EALREADY, |table| {
+            self.state.set(State::Loading);
+            self.storage
+                .read(table, self.storage_address, credentials::STORAGE_LEN)
+        })
+    }
+
+    /// Ends the request with `status` and no other response data.
+    fn finish(&self, status: Status) {
+        self.state.set(State::Idle);
+        self.reply(status);
+    }
+
+    fn reply(&self, status: Status) {
+        self.message.take().map(|message| {
+            message[0] = status;
+            self.transport.respond(CMD_CBOR, message, 1);
+        });
+    }
+
+    fn get_info(&self, message: &mut [u8]) -> Option<usize> {
+        let max_message_len = message.len();
+        let mut w = Writer::new(&mut message[1..]);
+        w.map(4);
+        w.int(1);
+        w.array(1);
+        w.text("FIDO_2_0");
+        w.int(3);
+        w.bytes(&self.aaguid);
+        w.int(4);
+        w.map(3);
+        w.text("rk");
+        w.bool(true);
+        w.text("up");
+        w.bool(true);
+        w.text("plat");
+        w.bool(false);
+        w.int(5);
+        w.int(max_message_len as i64);
+        w.len()
+    }
+
+    fn parse_rp(&self, r: &mut Reader) -> Result<bool, Status> {
+        let mut found = false;
+        for _ in 0..read_map(r)? {
+            if read_text(r)? == b"id" {
+                self.rp_id_hash.set(sha256(read_text(r)?));
+                found = true;
+            } else {
+                skip(r)?;
+            }
+        }
+        Ok(found)
+    }
+
+    fn parse_user(&self, r: &mut Reader) -> Result<(), Status> {
+        for _ in 0..read_map(r)? {
+            if read_text(r)? == b"id" {
+                let id = read_bytes(r)?;
+                if id.len() > MAX_USER_ID_LEN {
+                    return Err(STATUS_INVALID_LENGTH);
+                }
+                let mut user_id = [0; MAX_USER_ID_LEN];
+                user_id[..id.len()].copy_from_slice(id);
+                self.user_id.set(user_id);
+                self.user_id_len.set(id.len());
+            } else {
+                skip(r)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_options(&self, r: &mut Reader, up_allowed: bool) -> Result<(), Status> {
+        for _ in 0..read_map(r)? {
+            let key = read_text(r)?;
+            let value = read_bool(r)?;
+            match key {
+                b"uv" if value => return Err(STATUS_UNSUPPORTED_OPTION),
+                b"up" if !value => {
+                    if up_allowed {
+                        self.user_presence.set(false);
+                    } else {
+                        return Err(STATUS_INVALID_OPTION);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_client_data_hash(&self, r: &mut Reader) -> Result<(), Status> {
+        let hash = read_bytes(r)?;
+        if hash.len() != 32 {
+            return Err(STATUS_INVALID_LENGTH);
+        }
+        let mut client_data_hash = [0; 32];
+        client_data_hash.copy_from_slice(hash);
+        self.client_data_hash.set(client_data_hash);
+        Ok(())
+    }
+
+    fn parse_make_credential(&self, table: &[u8], request: &[u8]) -> Result<(), Status> {
+        let mut r = Reader::new(request);
+        // The parameters are in increasing order of their keys, so the
+        // relying party is known before the exclude list
+        let (mut client_data_hash, mut rp, mut user, mut params) = (false, false, false, false);
+        let mut es256 = false;
+        let mut excluded = false;
+        self.user_id_len.set(0);
+        for _ in 0..read_map(&mut r)? {
+            match read_int(&mut r)? {
+                1 => {
+                    self.parse_client_data_hash(&mut r)?;
+                    client_data_hash = true;
+                }
+                2 => rp = self.parse_rp(&mut r)?,
+                3 => {
+                    self.parse_user(&mut r)?;
+                    user = true;
+                }
+                4 => {
+                    for _ in 0..read_array(&mut r)? {
+                        let (mut alg, mut public_key) = (None, false);
+                        for _ in 0..read_map(&mut r)? {
+                            match read_text(&mut r)? {
+                                b"alg" => alg = Some(read_int(&mut r)?),
+                                b"type" => public_key = read_text(&mut r)? == b"public-key",
+                                _ => skip(&mut r)?,
+                            }
+                        }
+                        es256 |= public_key && alg == Some(ALG_ES256);
+                    }
+                    params = true;
+                }
+                5 => {
+                    let rp_id_hash = self.rp_id_hash.get();
+                    for _ in 0..read_array(&mut r)? {
+                        let id = read_credential_descriptor(&mut r)?;
+                        excluded |= rp
+                            && credentials::find(table, &rp_id_hash, |c| c.credential_id() == id)
+                                .is_some();
+                    }
+                }
+                7 => self.parse_options(&mut r, false)?,
+                8 => return Err(STATUS_PIN_NOT_SET),
+                _ => skip(&mut r)?,
+            }
+        }
+        if !(client_data_hash && rp && user && params) {
+            return Err(STATUS_MISSING_PARAMETER);
+        }
+        if !es256 {
+            return Err(STATUS_UNSUPPORTED_ALGORITHM);
+        }
+        if excluded {
+            return Err(STATUS_CREDENTIAL_EXCLUDED);
+        }
+        let user_id = self.user_id.get();
+        credentials::find_slot(
+            table,
+            &self.rp_id_hash.get(),
+            &user_id[..self.user_id_len.get()],
+        )
+        .ok_or(STATUS_KEY_STORE_FULL)?;
+        Ok(())
+    }
+
+    fn parse_get_assertion(&self, table: &[u8], request: &[u8]) -> Result<(), Status> {
+        let mut r = Reader::new(request);
+        let (mut rp, mut client_data_hash) = (false, false);
+        let mut allowed = None;
+        for _ in 0..read_map(&mut r)? {
+            match read_int(&mut r)? {
+                1 => {
+                    self.rp_id_hash.set(sha256(read_text(&mut r)?));
+                    rp = true;
+                }
+                2 => {
+                    self.parse_client_data_hash(&mut r)?;
+                    client_data_hash = true;
+                }
+                3 => {
+                    let rp_id_hash = self.rp_id_hash.get();
+                    let mut found = None;
+                    for _ in 0..read_array(&mut r)? {
+                        let id = read_credential_descriptor(&mut r)?;
+                        if rp && found.is_none() {
+                            found =
+                                credentials::find(table, &rp_id_hash, |c| c.credential_id() == id);
+                        }
+                    }
+                    allowed = Some(found);
+                }
+                5 => self.parse_options(&mut r, true)?,
+                6 => return Err(STATUS_PIN_NOT_SET),
+                _ => skip(&mut r)?,
+            }
+        }
+        if !(rp && client_data_hash) {
+            return Err(STATUS_MISSING_PARAMETER);
+        }
+        // Without an allow list, the first resident credential is used
+        let credential = match allowed {
+            Some(found) => found,
+            None => credentials::find(table, &self.rp_id_hash.get(), |_| true),
+        };
+        self.credential
+            .set(credential.ok_or(STATUS_NO_CREDENTIALS)?);
+        Ok(())
+    }
+
+    fn process(&self, message: &'static mut [u8], len: usize) {
+        self.message.replace(message);
+        if len == 0 {
+            self.reply(STATUS_INVALID_LENGTH);
+            return;
+        }
+        if self.state.get() != State::Idle {
+            // The credentials are not loaded yet
+            self.reply(STATUS_OTHER);
+            return;
+        }
+
+        let command = self.message.map_or(0, |message| message[0]);
+        self.user_presence.set(true);
+        let parsed = match command {
+            COMMAND_GET_INFO => {
+                let info = self.message.map(|message| self.get_info(message));
+                match info {
+                    Some(Some(info_len)) => {
+                        self.message.take().map(|message| {
+                            message[0] = STATUS_OK;
+                            self.transport.respond(CMD_CBOR, message, 1 + info_len);
+                        });
+                    }
+                    _ => self.finish(STATUS_OTHER),
+                }
+                return;
+            }
+            COMMAND_MAKE_CREDENTIAL => {
+                self.operation.set(Operation::MakeCredential);
+                self.table.map_or(Err(STATUS_OTHER), |table| {
+                    self.message.map_or(Err(STATUS_OTHER), |message| {
+                        self.parse_make_credential(table, &message[1..len])
+                    })
+                })
+            }
+            COMMAND_GET_ASSERTION => {
+                self.operation.set(Operation::GetAssertion);
+                self.table.map_or(Err(STATUS_OTHER), |table| {
+                    self.message.map_or(Err(STATUS_OTHER), |message| {
+                        self.parse_get_assertion(table, &message[1..len])
+                    })
+                })
+            }
+            _ => Err(STATUS_INVALID_COMMAND),
+        };
+
+        match parsed {
+            Err(status) => self.finish(status),
+            Ok(()) if self.user_presence.get() => {
+                self.state.set(State::Presence);
+                self.transport
+                    .set_keepalive_status(KeepaliveStatus::UserPresenceNeeded);
+                self.presence.enable_interrupts(InterruptEdge::EitherEdge);
+                self.alarm
+                    .set_alarm(self.alarm.now(), A::ticks_from_ms(PRESENCE_TIMEOUT_MS));
+            }
+            Ok(()) => self.proceed(),
+        }
+    }
+
+    fn stop_presence(&self) {
+        self.alarm.disarm();
+        self.presence.disable_interrupts();
+        self.transport
+            .set_keepalive_status(KeepaliveStatus::Processing);
+    }
+
+    /// Continues the request once the user is present.
+    fn proceed(&self) {
+        match self.operation.get() {
+            Operation::MakeCredential => {
+                self.state.set(State::Random);
+                self.random_len.set(0);
+                if self.rng.get() != ReturnCode::SUCCESS {
+                    self.finish(STATUS_OTHER);
+                }
+            }
+            Operation::GetAssertion => {
+                let res = self.record.take().map(|record| {
+                    self.table.map(|table| {
+                        credentials::write_header(table, record, credentials::counter(table) + 1)
+                    });
+                    self.store(record, 0, credentials::HEADER_LEN)
+                });
+                if res != Some(ReturnCode::SUCCESS) {
+                    self.finish(STATUS_OTHER);
+                }
+            }
+        }
+    }
+
+    /// Writes `data` to the table at `offset`.
+    fn store(&self, data: &'a mut [u8], offset: usize, len: usize) -> ReturnCode {
+        self.state.set(State::Storing);
+        self.write_offset.set(offset);
+        self.write_len.set(len);
+        self.storage.write(data, self.storage_address + offset, len)
+    }
+
+    fn create_credential(&self) {
+        let random = self.random.get();
+        self.random.set([0; RANDOM_LEN]);
+        let user_id = self.user_id.get();
+        let user_id = &user_id[..self.user_id_len.get()];
+        let rp_id_hash = self.rp_id_hash.get();
+
+        let slot = self
+            .table
+            .map(|table| credentials::find_slot(table, &rp_id_hash, user_id));
+        match (slot, self.record.take()) {
+            (Some(Some(slot)), Some(record)) => {
+                credentials::write_record(
+                    record,
+                    &rp_id_hash,
+                    &random[32..],
+                    &random[..32],
+                    user_id,
+                );
+                self.credential.set(slot);
+                if self.store(record, credentials::record_offset(slot), RECORD_LEN)
+                    != ReturnCode::SUCCESS
+                {
+                    self.finish(STATUS_OTHER);
+                }
+            }
+            (Some(None), record) => {
+                record.map(|record| self.record.replace(record));
+                self.finish(STATUS_KEY_STORE_FULL);
+            }
+            (_, record) => {
+                record.map(|record| self.record.replace(record));
+                self.finish(STATUS_OTHER);
+            }
+        }
+    }
+
+    /// Writes the authenticator data to `auth_data`, up to the attested
+    /// credential data.
+    fn auth_data(&self, auth_data: &mut [u8], flags: u8, counter: u32) {
+        auth_data[..32].copy_from_slice(&self.rp_id_hash.get());
+        auth_data[32] = flags;
+        auth_data[33..37].copy_from_slice(&counter.to_be_bytes());
+    }
+
+    fn make_credential_response(&self, table: &[u8], message: &mut [u8]) -> Option<usize> {
+        let credential = credentials::record(table, self.credential.get());
+        let mut public_key = [0; 64];
+        if !p256::public_key(credential.private_key(), &mut public_key) {
+            return None;
+        }
+
+        let mut flags = FLAG_ATTESTED_CREDENTIAL;
+        if self.user_presence.get() {
+            flags |= FLAG_USER_PRESENT;
+        }
+        let mut signed = [0; ATTESTED_AUTH_DATA_LEN + 32];
+        self.auth_data(&mut signed, flags, credentials::counter(table));
+        let mut pos = AUTH_DATA_LEN;
+        signed[pos..pos + 16].copy_from_slice(&self.aaguid);
+        signed[pos + 16..pos + 18].copy_from_slice(&(CREDENTIAL_ID_LEN as u16).to_be_bytes());
+        pos += 18;
+        signed[pos..pos + CREDENTIAL_ID_LEN].copy_from_slice(credential.credential_id());
+        pos += CREDENTIAL_ID_LEN;
+        let mut cose = Writer::new(&mut signed[pos..ATTESTED_AUTH_DATA_LEN]);
+        cose.map(5);
+        cose.int(1); // kty: EC2
+        cose.int(2);
+        cose.int(3); // alg: ES256
+        cose.int(ALG_ES256);
+        cose.int(-1); // crv: P-256
+        cose.int(1);
+        cose.int(-2);
+        cose.bytes(&public_key[..32]);
+        cose.int(-3);
+        cose.bytes(&public_key[32..]);
+        if cose.len() != Some(COSE_KEY_LEN) {
+            return None;
+        }
+        signed[ATTESTED_AUTH_DATA_LEN..].copy_from_slice(&self.client_data_hash.get());
+
+        let mut signature = [0; 64];
+        p256::sign(credential.private_key(), &signed, &mut signature);
+        let mut der = [0; 72];
+        let der_len = der_signature(&signature, &mut der);
+
+        let mut w = Writer::new(&mut message[1..]);
+        w.map(3);
+        w.int(1);
+        w.text("packed");
+        w.int(2);
+        w.bytes(&signed[..ATTESTED_AUTH_DATA_LEN]);
+        w.int(3);
+        w.map(2);
+        w.text("alg");
+        w.int(ALG_ES256);
+        w.text("sig");
+        w.bytes(&der[..der_len]);
+        w.len()
+    }
+
+    fn get_assertion_response(&self, table: &[u8], message: &mut [u8]) -> Option<usize> {
+        let credential = credentials::record(table, self.credential.get());
+        let flags = if self.user_presence.get() {
+            FLAG_USER_PRESENT
+        } else {
+            0
+        };
+        let mut signed = [0; AUTH_DATA_LEN + 32];
+        self.auth_data(&mut signed, flags, credentials::counter(table));
+        signed[AUTH_DATA_LEN..].copy_from_slice(&self.client_data_hash.get());
+
+        let mut signature = [0; 64];
+        if !p256::sign(credential.private_key(), &signed, &mut signature) {
+            return None;
+        }
+        let mut der = [0; 72];
+        let der_len = der_signature(&signature, &mut der);
+
+        let mut w = Writer::new(&mut message[1..]);
+        w.map(4);
+        w.int(1);
+        w.map(2);
+        w.text("id");
+        w.bytes(credential.credential_id());
+        w.text("type");
+        w.text("public-key");
+        w.int(2);
+        w.bytes(&signed[..AUTH_DATA_LEN]);
+        w.int(3);
+        w.bytes(&der[..der_len]);
+        w.int(4);
+        w.map(1);
+        w.text("id");
+        w.bytes(credential.user_id());
+        w.len()
+    }
+
+    /// Answers the request once the credential or the counter is stored.
+    fn respond(&self) {
+        self.state.set(State::Idle);
+        let len = self.table.map(|table| {
+            self.message.map(|message| match self.operation.get() {
+                Operation::MakeCredential => self.make_credential_response(table, message),
+                Operation::GetAssertion => self.get_assertion_response(table, message),
+            })
+        });
+        match len {
+            Some(Some(Some(len))) => {
+                self.message.take().map(|message| {
+                    message[0] = STATUS_OK;
+                    self.transport.respond(CMD_CBOR, message, 1 + len);
+                });
+            }
+            _ => self.finish(STATUS_OTHER),
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> ctaphid::Client<'a> for Authenticator<'a, A> {
+    fn request(
+        &'a self,
+        channel: u32,
+        command: u8,
+        message: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), &'static mut [u8]> {
+        if command != CMD_CBOR {
+            let res = match self.fallback.map(|client| *client) {
+                Some(client) => client.request(channel, command, message, len),
+                None => Err(message),
+            };
+            self.forwarded.set(res.is_ok());
+            return res;
+        }
+        self.forwarded.set(false);
+        self.process(message, len);
+        Ok(())
+    }
+
+    fn cancel(&'a self) {
+        if self.forwarded.get() {
+            self.fallback.map(|client| client.cancel());
+        } else if self.state.get() == State::Presence {
+            self.stop_presence();
+            self.finish(STATUS_KEEPALIVE_CANCEL);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> gpio::Client for Authenticator<'a, A> {
+    fn fired(&self) {
+        let pressed = self.presence.read_activation(self.presence_mode) == ActivationState::Active;
+        if self.state.get() == State::Presence && pressed {
+            self.stop_presence();
+            self.proceed();
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> time::AlarmClient for Authenticator<'a, A> {
+    fn alarm(&self) {
+        if self.state.get() == State::Presence {
+            self.stop_presence();
+            self.finish(STATUS_USER_ACTION_TIMEOUT);
+        }
+    }
+}
+
+impl<'a, A: Alarm<'a>> rng::Client for Authenticator<'a, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if self.state.get() != State::Random {
+            return rng::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            self.finish(STATUS_OTHER);
+            return rng::Continue::Done;
+        }
+
+        let mut random = self.random.get();
+        let mut len = self.random_len.get();
+        for word in randomness.take((RANDOM_LEN - len) / 4) {
+            random[len..len + 4].copy_from_slice(&word.to_le_bytes());
+            len += 4;
+        }
+        self.random.set(random);
+        self.random_len.set(len);
+        if len < RANDOM_LEN {
+            return rng::Continue::More;
+        }
+        if !p256::is_valid_private_key(&random[..32]) {
+            self.random_len.set(0);
+            return rng::Continue::More;
+        }
+        self.create_credential();
+        rng::Continue::Done
+    }
+}
+
+impl<'a, A: Alarm<'a>> NonvolatileStorageClient<'a> for Authenticator<'a, A> {
+    fn read_done(&self, table: &'a mut [u8], _length: usize) {
+        if credentials::is_formatted(table) {
+            self.table.replace(table);
+            self.state.set(State::Idle);
+        } else {
+            credentials::format(table);
+            self.state.set(State::Formatting);
+            self.storage
+                .write(table, self.storage_address, credentials::STORAGE_LEN);
+        }
+    }
+
+    fn write_done(&self, buffer: &'a mut [u8], _length: usize) {
+        match self.state.get() {
+            State::Formatting => {
+                self.table.replace(buffer);
+                self.state.set(State::Idle);
+            }
+            State::Storing => {
+                let (offset, len) = (self.write_offset.get(), self.write_len.get());
+                self.table
+                    .map(|table| table[offset..offset + len].copy_from_slice(&buffer[..len]));
+                self.record.replace(buffer);
+                self.respond();
+            }
+            _ => {
+                self.record.replace(buffer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::ctaphid::Client as _;
+    use core::cell::RefCell;
+    use kernel::hil::gpio::Client as _;
+    use kernel::hil::rng::Client as _;
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks, Ticks32};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestAlarm {
+        alarm: Cell<Option<u32>>,
+    }
+
+    impl time::Time for TestAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0.into()
+        }
+    }
+
+    impl<'a> time::Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.alarm.get().unwrap_or(0).into()
+        }
+
+        fn disarm(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+
+        fn is_armed(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// A transport that keeps the responses, and the message buffer between
+    /// requests.
+    struct TestTransport {
+        responses: RefCell<Vec<(u8, Vec<u8>)>>,
+        message: TakeCell<'static, [u8]>,
+    }
+
+    impl<'a> Transport<'a> for TestTransport {
+        fn set_client(&'a self, _client: &'a dyn ctaphid::Client<'a>) {}
+
+        fn respond(&'a self, command: u8, message: &'static mut [u8], len: usize) {
+            self.responses
+                .borrow_mut()
+                .push((command, message[..len].to_vec()));
+            self.message.replace(message);
+        }
+
+        fn set_keepalive_status(&self, _status: KeepaliveStatus) {}
+    }
+
+    struct TestRng {
+        requests: Cell<usize>,
+    }
+
+    impl<'a> Rng<'a> for TestRng {
+        fn get(&self) -> ReturnCode {
+            self.requests.set(self.requests.get() + 1);
+            ReturnCode::SUCCESS
+        }
+
+        fn cancel(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+
+        fn set_client(&'a self, _client: &'a dyn rng::Client) {}
+    }
+
+    /// An active high button, which the test presses with `Test::press()`.
+    struct TestButton {
+        pressed: Cell<bool>,
+        interrupts: Cell<bool>,
+    }
+
+    impl gpio::Configure for TestButton {
+        fn configuration(&self) -> gpio::Configuration {
+            gpio::Configuration::Input
+        }
+
+        fn make_output(&self) -> gpio::Configuration {
+            gpio::Configuration::Input
+        }
+
+        fn disable_output(&self) -> gpio::Configuration {
+            gpio::Configuration::Input
+        }
+
+        fn make_input(&self) -> gpio::Configuration {
+            gpio::Configuration::Input
+        }
+
+        fn disable_input(&self) -> gpio::Configuration {
+            gpio::Configuration::Input
+        }
+
+        fn deactivate_to_low_power(&self) {}
+
+        fn set_floating_state(&self, _state: gpio::FloatingState) {}
+
+        fn floating_state(&self) -> gpio::FloatingState {
+            gpio::FloatingState::PullDown
+        }
+    }
+
+    impl gpio::Output for TestButton {
+        fn set(&self) {}
+
+        fn clear(&self) {}
+
+        fn toggle(&self) -> bool {
+            false
+        }
+    }
+
+    impl gpio::Input for TestButton {
+        fn read(&self) -> bool {
+            self.pressed.get()
+        }
+    }
+
+    impl<'a> gpio::Interrupt<'a> for TestButton {
+        fn set_client(&self, _client: &'a dyn gpio::Client) {}
+
+        fn enable_interrupts(&self, _mode: InterruptEdge) {
+            self.interrupts.set(true);
+        }
+
+        fn disable_interrupts(&self) {
+            self.interrupts.set(false);
+        }
+
+        fn is_pending(&self) -> bool {
+            false
+        }
+    }
+
+    impl gpio::Pin for TestButton {}
+    impl<'a> gpio::InterruptPin<'a> for TestButton {}
+
+    /// Storage whose operations complete when the test calls `complete()`.
+    struct TestStorage {
+        memory: RefCell<Vec<u8>>,
+        pending: TakeCell<'static, [u8]>,
+        operation: Cell<(bool, usize, usize)>,
+    }
+
+    impl TestStorage {
+        fn complete(&self, client: &dyn NonvolatileStorageClient<'static>) {
+            let buffer = self.pending.take().expect("no pending operation");
+            let (write, address, len) = self.operation.get();
+            let mut memory = self.memory.borrow_mut();
+            if write {
+                memory[address..address + len].copy_from_slice(&buffer[..len]);
+                drop(memory);
+                client.write_done(buffer, len);
+            } else {
+                buffer[..len].copy_from_slice(&memory[address..address + len]);
+                drop(memory);
+                client.read_done(buffer, len);
+            }
+        }
+    }
+
+    impl NonvolatileStorage<'static> for TestStorage {
+        fn set_client(&self, _client: &'static dyn NonvolatileStorageClient<'static>) {}
+
+        fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+            self.pending.replace(buffer);
+            self.operation.set((false, address, length));
+            ReturnCode::SUCCESS
+        }
+
+        fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+            self.pending.replace(buffer);
+            self.operation.set((true, address, length));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    const STORAGE_ADDRESS: usize = 0x100;
+
+    struct Test {
+        transport: &'static TestTransport,
+        alarm: &'static TestAlarm,
+        rng: &'static TestRng,
+        button: &'static TestButton,
+        storage: &'static TestStorage,
+        authenticator: &'static Authenticator<'static, TestAlarm>,
+    }
+
+    impl Test {
+        fn new(storage: &'static TestStorage) -> Test {
+            let transport: &'static TestTransport = Box::leak(Box::new(TestTransport {
+                responses: RefCell::new(Vec::new()),
+                message: TakeCell::new(Box::leak(std::vec![0; 1024].into_boxed_slice())),
+            }));
+            let rng: &'static TestRng = Box::leak(Box::new(TestRng {
+                requests: Cell::new(0),
+            }));
+            let alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm {
+                alarm: Cell::new(None),
+            }));
+            let button: &'static TestButton = Box::leak(Box::new(TestButton {
+                pressed: Cell::new(false),
+                interrupts: Cell::new(false),
+            }));
+            let authenticator = Box::leak(Box::new(Authenticator::new(
+                transport,
+                alarm,
+                rng,
+                storage,
+                STORAGE_ADDRESS,
+                [0xaa; 16],
+                button,
+                ActivationMode::ActiveHigh,
+                Box::leak(std::vec![0; credentials::STORAGE_LEN].into_boxed_slice()),
+                Box::leak(std::vec![0; RECORD_LEN].into_boxed_slice()),
+            )));
+            assert_eq!(authenticator.load(), ReturnCode::SUCCESS);
+            storage.complete(authenticator);
+            Test {
+                transport,
+                alarm,
+                rng,
+                button,
+                storage,
+                authenticator,
+            }
+        }
+
+        /// Sends a CTAP2 request, and returns the response if there is one.
+        fn request(&self, command: u8, parameters: &[u8]) -> Option<(u8, Vec<u8>)> {
+            let message = self.transport.message.take().unwrap();
+            message[0] = command;
+            message[1..1 + parameters.len()].copy_from_slice(parameters);
+            assert!(self
+                .authenticator
+                .request(1, CMD_CBOR, message, 1 + parameters.len())
+                .is_ok());
+            self.response()
+        }
+
+        fn response(&self) -> Option<(u8, Vec<u8>)> {
+            let (command, response) = self.transport.responses.borrow_mut().pop()?;
+            assert_eq!(command, CMD_CBOR);
+            Some((response[0], response[1..].to_vec()))
+        }
+
+        /// Presses the presence button, which the authenticator must be
+        /// waiting for, and releases it.
+        fn press(&self) {
+            assert!(self.button.interrupts.get());
+            self.button.pressed.set(true);
+            self.authenticator.fired();
+            self.button.pressed.set(false);
+            assert!(!self.button.interrupts.get());
+        }
+
+        /// Delivers `words` of randomness.
+        fn random(&self, words: &[u32]) {
+            assert_eq!(self.rng.requests.take(), 1);
+            let mut iter = words.iter().cloned();
+            self.authenticator
+                .randomness_available(&mut iter, ReturnCode::SUCCESS);
+        }
+    }
+
+    fn erased_storage() -> &'static TestStorage {
+        Box::leak(Box::new(TestStorage {
+            memory: RefCell::new(std::vec![0xff; STORAGE_ADDRESS + credentials::STORAGE_LEN]),
+            pending: TakeCell::empty(),
+            operation: Cell::new((false, 0, 0)),
+        }))
+    }
+
+    fn make_credential(rp_id: &str, user_id: &[u8], alg: i64, exclude: Option<&[u8]>) -> Vec<u8> {
+        let mut buf = [0; 256];
+        let mut w = Writer::new(&mut buf);
+        w.map(if exclude.is_some() { 5 } else { 4 });
+        w.int(1);
+        w.bytes(&[0x11; 32]);
+        w.int(2);
+        w.map(2);
+        w.text("id");
+        w.text(rp_id);
+        w.text("name");
+        w.text("Example");
+        w.int(3);
+        w.map(2);
+        w.text("id");
+        w.bytes(user_id);
+        w.text("name");
+        w.text("user");
+        w.int(4);
+        w.array(1);
+        w.map(2);
+        w.text("alg");
+        w.int(alg);
+        w.text("type");
+        w.text("public-key");
+        if let Some(id) = exclude {
+            w.int(5);
+            w.array(1);
+            w.map(2);
+            w.text("id");
+            w.bytes(id);
+            w.text("type");
+            w.text("public-key");
+        }
+        let len = w.len().unwrap();
+        buf[..len].to_vec()
+    }
+
+    fn get_assertion(rp_id: &str, allow: Option<&[u8]>) -> Vec<u8> {
+        let mut buf = [0; 128];
+        let mut w = Writer::new(&mut buf);
+        w.map(if allow.is_some() { 3 } else { 2 });
+        w.int(1);
+        w.text(rp_id);
+        w.int(2);
+        w.bytes(&[0x22; 32]);
+        if let Some(id) = allow {
+            w.int(3);
+            w.array(1);
+            w.map(2);
+            w.text("id");
+            w.bytes(id);
+            w.text("type");
+            w.text("public-key");
+        }
+        let len = w.len().unwrap();
+        buf[..len].to_vec()
+    }
+
+    /// Decodes a DER signature to `r | s`.
+    fn from_der(der: &[u8]) -> [u8; 64] {
+        assert_eq!(der[0], 0x30);
+        assert_eq!(der[1] as usize, der.len() - 2);
+        let mut signature = [0; 64];
+        let mut pos = 2;
+        for i in 0..2 {
+            assert_eq!(der[pos], 0x02);
+            let len = der[pos + 1] as usize;
+            let int = &der[pos + 2..pos + 2 + len];
+            let int = if int.len() == 33 { &int[1..] } else { int };
+            signature[i * 32 + 32 - int.len()..(i + 1) * 32].copy_from_slice(int);
+            pos += 2 + len;
+        }
+        signature
+    }
+
+    /// The COSE public key of attested credential data.
+    fn cose_public_key(cose: &[u8]) -> [u8; 64] {
+        let mut r = Reader::new(cose);
+        assert_eq!(r.next(), Some(Item::Map(5)));
+        let mut public_key = [0; 64];
+        for _ in 0..5 {
+            match (read_int(&mut r).unwrap(), r.next().unwrap()) {
+                (1, kty) => assert_eq!(kty, Item::Unsigned(2)),
+                (3, alg) => assert_eq!(alg.as_int(), Some(ALG_ES256)),
+                (-1, crv) => assert_eq!(crv, Item::Unsigned(1)),
+                (-2, Item::Bytes(x)) => public_key[..32].copy_from_slice(x),
+                (-3, Item::Bytes(y)) => public_key[32..].copy_from_slice(y),
+                (key, _) => panic!("unexpected key {}", key),
+            }
+        }
+        assert!(r.is_empty());
+        public_key
+    }
+
+    /// Checks a MakeCredential response and returns the credential ID and
+    /// public key.
+    fn check_attestation(response: &[u8], rp_id: &str, counter: u32) -> (Vec<u8>, [u8; 64]) {
+        let mut r = Reader::new(response);
+        assert_eq!(r.next(), Some(Item::Map(3)));
+        assert_eq!(r.next(), Some(Item::Unsigned(1)));
+        assert_eq!(r.next(), Some(Item::Text(b"packed")));
+        assert_eq!(r.next(), Some(Item::Unsigned(2)));
+        let auth_data = read_bytes(&mut r).unwrap();
+        assert_eq!(r.next(), Some(Item::Unsigned(3)));
+        assert_eq!(r.next(), Some(Item::Map(2)));
+        assert_eq!(r.next(), Some(Item::Text(b"alg")));
+        assert_eq!(read_int(&mut r), Ok(ALG_ES256));
+        assert_eq!(r.next(), Some(Item::Text(b"sig")));
+        let signature = from_der(read_bytes(&mut r).unwrap());
+        assert!(r.is_empty());
+
+        assert_eq!(auth_data.len(), ATTESTED_AUTH_DATA_LEN);
+        assert_eq!(&auth_data[..32], &sha256(rp_id.as_bytes()));
+        assert_eq!(auth_data[32], FLAG_USER_PRESENT | FLAG_ATTESTED_CREDENTIAL);
+        assert_eq!(&auth_data[33..37], &counter.to_be_bytes());
+        assert_eq!(&auth_data[37..53], &[0xaa; 16]);
+        assert_eq!(&auth_data[53..55], &[0, CREDENTIAL_ID_LEN as u8]);
+        let credential_id = auth_data[55..71].to_vec();
+        let public_key = cose_public_key(&auth_data[71..]);
+
+        // Self attestation is signed by the credential
+        let mut signed = auth_data.to_vec();
+        signed.extend_from_slice(&[0x11; 32]);
+        assert_eq!(p256::verify(&public_key, &signed, &signature), Some(true));
+        (credential_id, public_key)
+    }
+
+    /// Checks a GetAssertion response by the credential.
+    fn check_assertion(
+        response: &[u8],
+        rp_id: &str,
+        counter: u32,
+        credential_id: &[u8],
+        user_id: &[u8],
+        public_key: &[u8; 64],
+    ) {
+        let mut r = Reader::new(response);
+        assert_eq!(r.next(), Some(Item::Map(4)));
+        assert_eq!(r.next(), Some(Item::Unsigned(1)));
+        assert_eq!(r.next(), Some(Item::Map(2)));
+        assert_eq!(r.next(), Some(Item::Text(b"id")));
+        assert_eq!(read_bytes(&mut r), Ok(credential_id));
+        assert_eq!(r.next(), Some(Item::Text(b"type")));
+        assert_eq!(r.next(), Some(Item::Text(b"public-key")));
+        assert_eq!(r.next(), Some(Item::Unsigned(2)));
+        let auth_data = read_bytes(&mut r).unwrap();
+        assert_eq!(r.next(), Some(Item::Unsigned(3)));
+        let signature = from_der(read_bytes(&mut r).unwrap());
+        assert_eq!(r.next(), Some(Item::Unsigned(4)));
+        assert_eq!(r.next(), Some(Item::Map(1)));
+        assert_eq!(r.next(), Some(Item::Text(b"id")));
+        assert_eq!(read_bytes(&mut r), Ok(user_id));
+        assert!(r.is_empty());
+
+        assert_eq!(auth_data.len(), AUTH_DATA_LEN);
+        assert_eq!(&auth_data[..32], &sha256(rp_id.as_bytes()));
+        assert_eq!(auth_data[32], FLAG_USER_PRESENT);
+        assert_eq!(&auth_data[33..37], &counter.to_be_bytes());
+        let mut signed = auth_data.to_vec();
+        signed.extend_from_slice(&[0x22; 32]);
+        assert_eq!(p256::verify(public_key, &signed, &signature), Some(true));
+    }
+
+    #[test]
+    fn get_info() {
+        let test = Test::new(erased_storage());
+        // The erased storage was formatted
+        assert!(test.storage.pending.is_some());
+        test.storage.complete(test.authenticator);
+        assert!(credentials::is_formatted(
+            &test.storage.memory.borrow()[STORAGE_ADDRESS..]
+        ));
+
+        let (status, info) = test.request(COMMAND_GET_INFO, &[]).unwrap();
+        assert_eq!(status, STATUS_OK);
+        let mut r = Reader::new(&info);
+        assert_eq!(r.next(), Some(Item::Map(4)));
+        assert_eq!(r.next(), Some(Item::Unsigned(1)));
+        assert_eq!(r.next(), Some(Item::Array(1)));
+        assert_eq!(r.next(), Some(Item::Text(b"FIDO_2_0")));
+        assert_eq!(r.next(), Some(Item::Unsigned(3)));
+        assert_eq!(r.next(), Some(Item::Bytes(&[0xaa; 16])));
+        assert_eq!(r.next(), Some(Item::Unsigned(4)));
+        assert_eq!(r.skip(), Some(()));
+        assert_eq!(r.next(), Some(Item::Unsigned(5)));
+        assert_eq!(r.next(), Some(Item::Unsigned(1024)));
+        assert!(r.is_empty());
+
+        assert_eq!(
+            test.request(0x07, &[]),
+            Some((STATUS_INVALID_COMMAND, Vec::new()))
+        );
+    }
+
+    #[test]
+    fn credentials() {
+        let storage = erased_storage();
+        let test = Test::new(storage);
+        storage.complete(test.authenticator);
+
+        // A new credential is stored before it is returned
+        let user_id = [1, 2, 3];
+        assert_eq!(
+            test.request(
+                COMMAND_MAKE_CREDENTIAL,
+                &make_credential("example.com", &user_id, ALG_ES256, None)
+            ),
+            None
+        );
+        test.press();
+        let words: Vec<u32> = (1..=12).collect();
+        test.random(&words);
+        assert_eq!(test.response(), None);
+        storage.complete(test.authenticator);
+        let (status, response) = test.response().unwrap();
+        assert_eq!(status, STATUS_OK);
+        let (credential_id, public_key) = check_attestation(&response, "example.com", 0);
+        assert_eq!(&credential_id[..4], &[9, 0, 0, 0]);
+
+        // Assertions increment the stored counter
+        for counter in 1..3 {
+            let allow = if counter == 1 {
+                None
+            } else {
+                Some(&credential_id[..])
+            };
+            assert_eq!(
+                test.request(COMMAND_GET_ASSERTION, &get_assertion("example.com", allow)),
+                None
+            );
+            test.press();
+            storage.complete(test.authenticator);
+            let (status, response) = test.response().unwrap();
+            assert_eq!(status, STATUS_OK);
+            check_assertion(
+                &response,
+                "example.com",
+                counter,
+                &credential_id,
+                &user_id,
+                &public_key,
+            );
+        }
+
+        // The credentials persist
+        let test = Test::new(storage);
+        assert!(storage.pending.is_none());
+        test.request(COMMAND_GET_ASSERTION, &get_assertion("example.com", None));
+        test.press();
+        storage.complete(test.authenticator);
+        let (status, response) = test.response().unwrap();
+        assert_eq!(status, STATUS_OK);
+        check_assertion(
+            &response,
+            "example.com",
+            3,
+            &credential_id,
+            &user_id,
+            &public_key,
+        );
+
+        // Errors
+        assert_eq!(
+            test.request(COMMAND_GET_ASSERTION, &get_assertion("example.org", None)),
+            Some((STATUS_NO_CREDENTIALS, Vec::new()))
+        );
+        assert_eq!(
+            test.request(
+                COMMAND_GET_ASSERTION,
+                &get_assertion("example.com", Some(&[0; 16]))
+            ),
+            Some((STATUS_NO_CREDENTIALS, Vec::new()))
+        );
+        assert_eq!(
+            test.request(
+                COMMAND_MAKE_CREDENTIAL,
+                &make_credential("example.com", &[4], ALG_ES256, Some(&credential_id))
+            ),
+            Some((STATUS_CREDENTIAL_EXCLUDED, Vec::new()))
+        );
+        assert_eq!(
+            test.request(
+                COMMAND_MAKE_CREDENTIAL,
+                &make_credential("example.com", &[4], -8, None)
+            ),
+            Some((STATUS_UNSUPPORTED_ALGORITHM, Vec::new()))
+        );
+        let request = get_assertion("example.com", None);
+        assert_eq!(
+            test.request(COMMAND_GET_ASSERTION, &request[..request.len() - 1]),
+            Some((STATUS_INVALID_CBOR, Vec::new()))
+        );
+        assert_eq!(
+            test.request(COMMAND_GET_ASSERTION, &[0xa1, 0x01, 0x60]),
+            Some((STATUS_MISSING_PARAMETER, Vec::new()))
+        );
+        assert_eq!(
+            test.request(COMMAND_GET_ASSERTION, &[0xa1, 0x01, 0x01]),
+            Some((STATUS_CBOR_UNEXPECTED_TYPE, Vec::new()))
+        );
+    }
+
+    #[test]
+    fn presence() {
+        let storage = erased_storage();
+        let test = Test::new(storage);
+        storage.complete(test.authenticator);
+        test.request(
+            COMMAND_MAKE_CREDENTIAL,
+            &make_credential("example.com", &[1], ALG_ES256, None),
+        );
+        test.press();
+        let words: Vec<u32> = (1..=12).collect();
+        test.random(&words);
+        storage.complete(test.authenticator);
+        assert_eq!(test.response().map(|(status, _)| status), Some(STATUS_OK));
+
+        // Without a press, the request times out
+        assert_eq!(
+            test.request(COMMAND_GET_ASSERTION, &get_assertion("example.com", None)),
+            None
+        );
+        assert!(test.button.interrupts.get());
+        assert_eq!(test.alarm.alarm.get(), Some(PRESENCE_TIMEOUT_MS));
+        test.authenticator.fired();
+        assert_eq!(test.response(), None);
+        test.authenticator.alarm();
+        assert_eq!(
+            test.response(),
+            Some((STATUS_USER_ACTION_TIMEOUT, Vec::new()))
+        );
+        assert!(!test.button.interrupts.get());
+
+        // Unless the platform does not ask for presence
+        let mut request = [0; 128];
+        let mut w = Writer::new(&mut request);
+        w.map(3);
+        w.int(1);
+        w.text("example.com");
+        w.int(2);
+        w.bytes(&[0x22; 32]);
+        w.int(5);
+        w.map(1);
+        w.text("up");
+        w.bool(false);
+        let len = w.len().unwrap();
+        assert_eq!(test.request(COMMAND_GET_ASSERTION, &request[..len]), None);
+        assert!(!test.button.interrupts.get());
+        storage.complete(test.authenticator);
+        let (status, response) = test.response().unwrap();
+        assert_eq!(status, STATUS_OK);
+        let mut r = Reader::new(&response);
+        assert_eq!(r.next(), Some(Item::Map(4)));
+        assert_eq!(r.next(), Some(Item::Unsigned(1)));
+        assert_eq!(r.skip(), Some(()));
+        assert_eq!(r.next(), Some(Item::Unsigned(2)));
+        let auth_data = read_bytes(&mut r).unwrap();
+        assert_eq!(auth_data[32], 0);
+    }
+
+    #[test]
+    fn der() {
+        let mut der = [0; 72];
+        let mut signature = [0; 64];
+        signature[31] = 0x7f;
+        signature[32] = 0x80;
+        let len = der_signature(&signature, &mut der);
+        assert_eq!(&der[..6], &[0x30, 0x26, 0x02, 0x01, 0x7f, 0x02]);
+        assert_eq!(&der[6..8], &[0x21, 0x00]);
+        assert_eq!(len, 40);
+        assert_eq!(from_der(&der[..len])[..], signature[..]);
+    }
+}
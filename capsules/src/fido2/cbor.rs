@@ -0,0 +1,271 @@
+//! Minimal CBOR (RFC 7049) encoding and decoding for CTAP2 messages.
+//!
+//! `Reader` decodes one data item header at a time and can skip whole
+//! items, without recursion. It only accepts definite lengths, which is
+//! what the CTAP2 canonical encoding allows. `Writer` encodes into a
+//! buffer and remembers if it ran out of space, so the encoder can be
+//! called without checking every item.
+
+pub const MAJOR_UNSIGNED: u8 = 0;
+pub const MAJOR_NEGATIVE: u8 = 1;
+pub const MAJOR_BYTES: u8 = 2;
+pub const MAJOR_TEXT: u8 = 3;
+pub const MAJOR_ARRAY: u8 = 4;
+pub const MAJOR_MAP: u8 = 5;
+pub const MAJOR_TAG: u8 = 6;
+pub const MAJOR_SIMPLE: u8 = 7;
+
+const SIMPLE_FALSE: u64 = 20;
+const SIMPLE_TRUE: u64 = 21;
+const SIMPLE_NULL: u64 = 22;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Item<'b> {
+    Unsigned(u64),
+    /// The value is `-1 - n`, for `n` up to `u64::MAX`.
+    Negative(u64),
+    Bytes(&'b [u8]),
+    Text(&'b [u8]),
+    /// The number of items in the array.
+    Array(usize),
+    /// The number of key and value pairs in the map.
+    Map(usize),
+    Tag(u64),
+    Bool(bool),
+    Null,
+    /// Other simple values and floating point numbers.
+    Simple,
+}
+
+impl<'b> Item<'b> {
+    /// The value of an integer item, if it fits in an `i64`.
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Item::Unsigned(n) if n <= i64::MAX as u64 => Some(n as i64),
+            Item::Negative(n) if n <= i64::MAX as u64 => Some(-1 - n as i64),
+            _ => None,
+        }
+    }
+}
+
+pub struct Reader<'b> {
+    buf: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> Reader<'b> {
+    pub fn new(buf: &'b [u8]) -> Reader<'b> {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Whether all the input was read.
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'b [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let slice = self.buf.get(self.pos..end)?;
+        self.pos = end;
+        Some(slice)
+    }
+
+    /// Reads the header of the next item, and its contents if it is a byte
+    /// or text string. Returns `None` if the input is malformed.
+    pub fn next(&mut self) -> Option<Item<'b>> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let info = initial & 0x1f;
+        let value = match info {
+            0..=23 => info as u64,
+            24..=27 => {
+                let bytes = self.take(1 << (info - 24))?;
+                bytes.iter().fold(0, |v, b| (v << 8) | *b as u64)
+            }
+            // Reserved values and indefinite lengths
+            _ => return None,
+        };
+        Some(match major {
+            MAJOR_UNSIGNED => Item::Unsigned(value),
+            MAJOR_NEGATIVE => Item::Negative(value),
+            MAJOR_BYTES => Item::Bytes(self.take(value as usize)?),
+            MAJOR_TEXT => Item::Text(self.take(value as usize)?),
+            MAJOR_ARRAY => Item::Array(value as usize),
+            MAJOR_MAP => Item::Map(value as usize),
+            MAJOR_TAG => Item::Tag(value),
+            _ => match value {
+                SIMPLE_FALSE => Item::Bool(false),
+                SIMPLE_TRUE => Item::Bool(true),
+                SIMPLE_NULL => Item::Null,
+                _ => Item::Simple,
+            },
+        })
+    }
+
+    /// Skips the next item, with the items it contains.
+    pub fn skip(&mut self) -> Option<()> {
+        let mut remaining: usize = 1;
+        while remaining > 0 {
+            remaining -= 1;
+            match self.next()? {
+                Item::Array(n) => remaining = remaining.checked_add(n)?,
+                Item::Map(n) => remaining = remaining.checked_add(n.checked_mul(2)?)?,
+                Item::Tag(_) => remaining += 1,
+                _ => {}
+            }
+            // Every item takes at least a byte
+            if remaining > self.buf.len() - self.pos {
+                return None;
+            }
+        }
+        Some(())
+    }
+}
+
+pub struct Writer<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+    overflow: bool,
+}
+
+impl<'b> Writer<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Writer<'b> {
+        Writer {
+            buf,
+            pos: 0,
+            overflow: false,
+        }
+    }
+
+    /// The length of the encoding, or `None` if it did not fit.
+    pub fn len(&self) -> Option<usize> {
+        if self.overflow {
+            None
+        } else {
+            Some(self.pos)
+        }
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        match self.buf.get_mut(self.pos..self.pos + data.len()) {
+            Some(dest) if !self.overflow => {
+                dest.copy_from_slice(data);
+                self.pos += data.len();
+            }
+            _ => self.overflow = true,
+        }
+    }
+
+    fn head(&mut self, major: u8, value: u64) {
+        let major = major << 5;
+        if value < 24 {
+            self.put(&[major | value as u8]);
+        } else if value <= 0xff {
+            self.put(&[major | 24, value as u8]);
+        } else if value <= 0xffff {
+            self.put(&[major | 25]);
+            self.put(&(value as u16).to_be_bytes());
+        } else if value <= 0xffff_ffff {
+            self.put(&[major | 26]);
+            self.put(&(value as u32).to_be_bytes());
+        } else {
+            self.put(&[major | 27]);
+            self.put(&value.to_be_bytes());
+        }
+    }
+
+    pub fn int(&mut self, value: i64) {
+        if value < 0 {
+            self.head(MAJOR_NEGATIVE, (-1 - value) as u64);
+        } else {
+            self.head(MAJOR_UNSIGNED, value as u64);
+        }
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.head(MAJOR_BYTES, data.len() as u64);
+        self.put(data);
+    }
+
+    pub fn text(&mut self, text: &str) {
+        self.head(MAJOR_TEXT, text.len() as u64);
+        self.put(text.as_bytes());
+    }
+
+    /// Starts an array of `n` items, which are written next.
+    pub fn array(&mut self, n: usize) {
+        self.head(MAJOR_ARRAY, n as u64);
+    }
+
+    /// Starts a map of `n` key and value pairs, which are written next.
+    pub fn map(&mut self, n: usize) {
+        self.head(MAJOR_MAP, n as u64);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        let simple = if value { SIMPLE_TRUE } else { SIMPLE_FALSE };
+        self.head(MAJOR_SIMPLE, simple);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0; 64];
+        let mut w = Writer::new(&mut buf);
+        w.map(3);
+        w.int(1);
+        w.array(2);
+        w.int(-7);
+        w.int(-257);
+        w.int(24);
+        w.bytes(&[0xaa; 3]);
+        w.text("up");
+        w.bool(true);
+        let len = w.len().unwrap();
+        assert_eq!(
+            &buf[..len],
+            &[
+                0xa3, 0x01, 0x82, 0x26, 0x39, 0x01, 0x00, 0x18, 0x18, 0x43, 0xaa, 0xaa, 0xaa, 0x62,
+                b'u', b'p', 0xf5
+            ]
+        );
+
+        let mut r = Reader::new(&buf[..len]);
+        assert_eq!(r.next(), Some(Item::Map(3)));
+        assert_eq!(r.next().and_then(|i| i.as_int()), Some(1));
+        assert_eq!(r.next(), Some(Item::Array(2)));
+        assert_eq!(r.next().and_then(|i| i.as_int()), Some(-7));
+        assert_eq!(r.next().and_then(|i| i.as_int()), Some(-257));
+        assert_eq!(r.next(), Some(Item::Unsigned(24)));
+        assert_eq!(r.next(), Some(Item::Bytes(&[0xaa; 3])));
+        assert_eq!(r.next(), Some(Item::Text(b"up")));
+        assert_eq!(r.next(), Some(Item::Bool(true)));
+        assert!(r.is_empty());
+
+        let mut r = Reader::new(&buf[..len]);
+        assert_eq!(r.skip(), Some(()));
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        // Truncated strings, indefinite lengths and oversized containers
+        assert_eq!(Reader::new(&[0x43, 0x00]).next(), None);
+        assert_eq!(Reader::new(&[0x5f]).next(), None);
+        assert_eq!(Reader::new(&[0x1b, 0x00]).next(), None);
+        assert_eq!(
+            Reader::new(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).skip(),
+            None
+        );
+        assert_eq!(Reader::new(&[0xa2, 0x01, 0x02, 0x03]).skip(), None);
+
+        let mut buf = [0; 4];
+        let mut w = Writer::new(&mut buf);
+        w.bytes(&[0; 4]);
+        assert_eq!(w.len(), None);
+    }
+}
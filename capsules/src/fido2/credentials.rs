@@ -0,0 +1,131 @@
+//! The layout of the credentials in nonvolatile storage.
+//!
+//! The storage holds a header, with the signature counter, followed by
+//! `MAX_CREDENTIALS` fixed-size records. The authenticator keeps a copy of
+//! the whole table in RAM, and writes a record, or the header, back when it
+//! changes.
+//!
+//! ```text
+//! header: magic (4) | signature counter (4, big endian) | reserved (8)
+//! record: flags (1) | rpIdHash (32) | credential ID (16) | private key (32)
+//!         | user ID length (1) | user ID (64) | reserved (14)
+//! ```
+//!
+//! Erased storage is not formatted; the authenticator then formats it with
+//! an empty table.
+
+pub const MAX_CREDENTIALS: usize = 16;
+pub const CREDENTIAL_ID_LEN: usize = 16;
+pub const MAX_USER_ID_LEN: usize = 64;
+
+pub const HEADER_LEN: usize = 16;
+pub const RECORD_LEN: usize = 160;
+/// The number of bytes of storage used by the authenticator.
+pub const STORAGE_LEN: usize = HEADER_LEN + MAX_CREDENTIALS * RECORD_LEN;
+
+const MAGIC: [u8; 4] = *b"FID2";
+
+const FLAG_VALID: u8 = 0x01;
+
+const RP_ID_HASH: usize = 1;
+const CREDENTIAL_ID: usize = RP_ID_HASH + 32;
+const PRIVATE_KEY: usize = CREDENTIAL_ID + CREDENTIAL_ID_LEN;
+const USER_ID_LEN: usize = PRIVATE_KEY + 32;
+const USER_ID: usize = USER_ID_LEN + 1;
+
+pub fn is_formatted(table: &[u8]) -> bool {
+    table[..4] == MAGIC
+}
+
+/// Clears `table`, making it an empty table with a zero counter.
+pub fn format(table: &mut [u8]) {
+    for b in table[..STORAGE_LEN].iter_mut() {
+        *b = 0;
+    }
+    table[..4].copy_from_slice(&MAGIC);
+}
+
+pub fn counter(table: &[u8]) -> u32 {
+    let mut counter = [0; 4];
+    counter.copy_from_slice(&table[4..8]);
+    u32::from_be_bytes(counter)
+}
+
+/// Writes the header of `table` with `counter` to `header`.
+pub fn write_header(table: &[u8], header: &mut [u8], counter: u32) {
+    header[..HEADER_LEN].copy_from_slice(&table[..HEADER_LEN]);
+    header[4..8].copy_from_slice(&counter.to_be_bytes());
+}
+
+/// The offset of record `index` in the table.
+pub fn record_offset(index: usize) -> usize {
+    HEADER_LEN + index * RECORD_LEN
+}
+
+/// A view of a record of the table.
+#[derive(Copy, Clone)]
+pub struct Record<'b>(&'b [u8]);
+
+impl<'b> Record<'b> {
+    pub fn is_valid(&self) -> bool {
+        self.0[0] & FLAG_VALID != 0
+    }
+
+    pub fn rp_id_hash(&self) -> &'b [u8] {
+        &self.0[RP_ID_HASH..RP_ID_HASH + 32]
+    }
+
+    pub fn credential_id(&self) -> &'b [u8] {
+        &self.0[CREDENTIAL_ID..CREDENTIAL_ID + CREDENTIAL_ID_LEN]
+    }
+
+    pub fn private_key(&self) -> &'b [u8] {
+        &self.0[PRIVATE_KEY..PRIVATE_KEY + 32]
+    }
+
+    pub fn user_id(&self) -> &'b [u8] {
+        let len = (self.0[USER_ID_LEN] as usize).min(MAX_USER_ID_LEN);
+        &self.0[USER_ID..USER_ID + len]
+    }
+}
+
+pub fn record(table: &[u8], index: usize) -> Record {
+    let offset = record_offset(index);
+    Record(&table[offset..offset + RECORD_LEN])
+}
+
+/// Finds the valid credential of the relying party with `rp_id_hash` whose
+/// `select` returns `true`.
+pub fn find<F: Fn(Record) -> bool>(table: &[u8], rp_id_hash: &[u8], select: F) -> Option<usize> {
+    (0..MAX_CREDENTIALS).find(|i| {
+        let r = record(table, *i);
+        r.is_valid() && r.rp_id_hash() == rp_id_hash && select(r)
+    })
+}
+
+/// Finds the record a new credential is stored in: the credential of the
+/// same user at the same relying party, which it replaces, or else a free
+/// record.
+pub fn find_slot(table: &[u8], rp_id_hash: &[u8], user_id: &[u8]) -> Option<usize> {
+    find(table, rp_id_hash, |r| r.user_id() == user_id)
+        .or_else(|| (0..MAX_CREDENTIALS).find(|i| !record(table, *i).is_valid()))
+}
+
+/// Encodes a credential to `record`.
+pub fn write_record(
+    record: &mut [u8],
+    rp_id_hash: &[u8],
+    credential_id: &[u8],
+    private_key: &[u8],
+    user_id: &[u8],
+) {
+    for b in record[..RECORD_LEN].iter_mut() {
+        *b = 0;
+    }
+    record[0] = FLAG_VALID;
+    record[RP_ID_HASH..RP_ID_HASH + 32].copy_from_slice(rp_id_hash);
+    record[CREDENTIAL_ID..CREDENTIAL_ID + CREDENTIAL_ID_LEN].copy_from_slice(credential_id);
+    record[PRIVATE_KEY..PRIVATE_KEY + 32].copy_from_slice(private_key);
+    record[USER_ID_LEN] = user_id.len() as u8;
+    record[USER_ID..USER_ID + user_id.len()].copy_from_slice(user_id);
+}
@@ -0,0 +1,11 @@
+//! A FIDO2 authenticator in the kernel.
+//!
+//! `authenticator::Authenticator` answers CTAP2 requests from a `ctaphid`
+//! transport, with credentials kept in nonvolatile storage in the layout of
+//! `credentials`. `cbor` encodes and decodes the CTAP2 messages.
+
+pub mod authenticator;
+pub mod cbor;
+pub mod credentials;
+
+pub use self::authenticator::Authenticator;
@@ -0,0 +1,247 @@
+//! Provides userspace access to a HID device, such as a USB keyboard or
+//! mouse.
+//!
+//! Processes send the input reports of the device, for instance the keys
+//! which are pressed, and can be notified of the output reports the host
+//! sets, for instance the state of the LEDs of a keyboard. Reports of several
+//! processes are sent in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let report_buffer = static_init!([u8; 64], [0; 64]);
+//! let hid_driver = static_init!(
+//!     capsules::hid_driver::HidDriver<'static>,
+//!     capsules::hid_driver::HidDriver::new(
+//!         hid,
+//!         report_buffer,
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! hid.set_client(hid_driver);
+//! ```
+
+use core::cmp;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::usb_hid::{Protocol, ReportClient, ReportDevice};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::UsbHid as usize;
+
+#[derive(Default)]
+pub struct App {
+    sent_callback: Option<Callback>,
+    output_callback: Option<Callback>,
+    report: Option<AppSlice<Shared, u8>>,
+    output: Option<AppSlice<Shared, u8>>,
+    /// The length of the report waiting to be sent.
+    pending: Option<usize>,
+}
+
+pub struct HidDriver<'a> {
+    device: &'a dyn ReportDevice<'a>,
+    apps: Grant<App>,
+    /// The process whose report is sent.
+    sending_app: OptionalCell<AppId>,
+    buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a> HidDriver<'a> {
+    /// `buffer` must hold the input reports of the device.
+    pub fn new(
+        device: &'a dyn ReportDevice<'a>,
+        buffer: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> HidDriver<'a> {
+        HidDriver {
+            device,
+            apps: grant,
+            sending_app: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+        }
+    }
+
+    /// Sends the first `len` bytes of the report buffer of `app`.
+    fn send(&self, app: &mut App, len: usize) -> ReturnCode {
+        let report = match app.report.as_ref() {
+            Some(report) if report.len() >= len => report,
+            Some(_) => return ReturnCode::ESIZE,
+            None => return ReturnCode::ERESERVE,
+        };
+        self.buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            if len > buffer.len() {
+                self.buffer.replace(buffer);
+                return ReturnCode::ESIZE;
+            }
+            buffer[..len].copy_from_slice(&report.as_ref()[..len]);
+            match self.device.send_report(buffer, len) {
+                Ok(()) => ReturnCode::SUCCESS,
+                Err((rc, buffer)) => {
+                    self.buffer.replace(buffer);
+                    rc
+                }
+            }
+        })
+    }
+
+    /// Sends the report of the next process which waits.
+    fn send_next(&self) {
+        for cntr in self.apps.iter() {
+            let started = cntr.enter(|app, _| {
+                app.pending.take().map_or(false, |len| {
+                    let rc = self.send(app, len);
+                    if rc == ReturnCode::SUCCESS {
+                        self.sending_app.set(app.appid());
+                        true
+                    } else {
+                        app.sent_callback
+                            .map(|mut cb| cb.schedule(isize::from(rc) as usize, 0, 0));
+                        false
+                    }
+                })
+            });
+            if started {
+                break;
+            }
+        }
+    }
+}
+
+impl ReportClient for HidDriver<'_> {
+    fn report_sent(&self, result: ReturnCode, report: &'static mut [u8]) {
+        self.buffer.replace(report);
+        self.sending_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.sent_callback
+                    .map(|mut cb| cb.schedule(isize::from(result) as usize, 0, 0));
+            });
+        });
+        self.send_next();
+    }
+
+    fn output_report(&self, report: &[u8]) {
+        self.apps.each(|app| {
+            if let Some(output) = app.output.as_mut() {
+                let len = cmp::min(output.len(), report.len());
+                output.as_mut()[..len].copy_from_slice(&report[..len]);
+            }
+            let first = report.first().map_or(0, |b| *b as usize);
+            app.output_callback
+                .map(|mut cb| cb.schedule(report.len(), first, 0));
+        });
+    }
+}
+
+impl Driver for HidDriver<'_> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The input report to send.
+    /// - `1`: The buffer output reports are copied to.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.report = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.output = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Subscribe to HidDriver events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to sent reports.
+    ///        The callback signature is `fn(result, 0, 0)`
+    /// - `1`: Subscribe to output reports.
+    ///        The callback signature is `fn(len, first_byte, 0)`
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.sent_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.output_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send the first `data1` bytes of the report buffer as an input
+    ///        report.
+    /// - `2`: Get the protocol the host selected: 0 for the boot protocol,
+    ///        1 for the report protocol.
+    fn command(&self, command_num: usize, data1: usize, _data2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    if app.pending.is_some() || self.sending_app.contains(&appid) {
+                        return ReturnCode::EBUSY;
+                    }
+                    if self.sending_app.is_some() {
+                        // Wait for the reports of the other processes
+                        app.pending = Some(data1);
+                        return ReturnCode::SUCCESS;
+                    }
+                    let rc = self.send(app, data1);
+                    if rc == ReturnCode::SUCCESS {
+                        self.sending_app.set(appid);
+                    }
+                    rc
+                })
+                .unwrap_or_else(|err| err.into()),
+
+            2 => ReturnCode::SuccessWithValue {
+                value: match self.device.protocol() {
+                    Protocol::Boot => 0,
+                    Protocol::Report => 1,
+                },
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
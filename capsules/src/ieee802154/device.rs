@@ -12,7 +12,7 @@
 //! procedure in hardware, as opposed to requiring a software implementation.
 
 use crate::ieee802154::framer::Frame;
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ieee802154::{FrameType, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use kernel::ReturnCode;
 
 pub trait MacDevice<'a> {
@@ -20,6 +20,8 @@ pub trait MacDevice<'a> {
     fn set_transmit_client(&self, client: &'a dyn TxClient);
     /// Sets the receive client of this MAC device
     fn set_receive_client(&self, client: &'a dyn RxClient);
+    /// Sets the energy detection client of this MAC device
+    fn set_energy_detect_client(&self, client: &'a dyn EnergyDetectClient);
 
     /// The short 16-bit address of the MAC device
     fn get_address(&self) -> u16;
@@ -27,6 +29,8 @@ pub trait MacDevice<'a> {
     fn get_address_long(&self) -> [u8; 8];
     /// The 16-bit PAN ID of the MAC device
     fn get_pan(&self) -> u16;
+    /// The 802.15.4 channel of the MAC device
+    fn get_channel(&self) -> u8;
 
     /// Set the short 16-bit address of the MAC device
     fn set_address(&self, addr: u16);
@@ -34,6 +38,9 @@ pub trait MacDevice<'a> {
     fn set_address_long(&self, addr: [u8; 8]);
     /// Set the 16-bit PAN ID of the MAC device
     fn set_pan(&self, id: u16);
+    /// Set the 802.15.4 channel of the MAC device. Fails if the radio does
+    /// not support the channel.
+    fn set_channel(&self, chan: u8) -> ReturnCode;
 
     /// This method must be called after one or more calls to `set_*`. If
     /// `set_*` is called without calling `config_commit`, there is no guarantee
@@ -44,6 +51,10 @@ pub trait MacDevice<'a> {
     /// Returns if the MAC device is currently on.
     fn is_on(&self) -> bool;
 
+    /// Measures the energy on the current channel, as done by energy detection
+    /// scans. The level is reported to the energy detection client.
+    fn energy_detect(&self) -> ReturnCode;
+
     /// Prepares a mutable buffer slice as an 802.15.4 frame by writing the appropriate
     /// header bytes into the buffer. This needs to be done before adding the
     /// payload because the length of the header is not fixed.
@@ -69,6 +80,24 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares an unsecured frame of another type than data, such as a
+    /// beacon or MAC command frame. The addressing fields that are `None` are
+    /// left out of the header: for example, beacons have no destination, and
+    /// beacon requests no source. Acknowledgement is requested for frames sent
+    /// to a unicast address.
+    ///
+    /// Returns either a Frame that is ready to have payload appended to it, or
+    /// the mutable buffer if the frame cannot be prepared for any reason
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst_pan: Option<PanID>,
+        dst_addr: Option<MacAddress>,
+        src_pan: Option<PanID>,
+        src_addr: Option<MacAddress>,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
@@ -112,3 +141,12 @@ pub trait RxClient {
     /// - `data_len`: Length of the data payload
     fn receive<'a>(&self, buf: &'a [u8], header: Header<'a>, data_offset: usize, data_len: usize);
 }
+
+/// Trait to be implemented by users of the IEEE 802.15.4 device that measure
+/// the energy on channels, as done by energy detection scans.
+pub trait EnergyDetectClient {
+    /// Reports the energy measured on the current channel, as an ED level
+    /// from 0 to 255. `result` is `ReturnCode::SUCCESS` if the measurement
+    /// completed.
+    fn energy_detect_done(&self, level: u8, result: ReturnCode);
+}
@@ -23,6 +23,7 @@
 //! xmac.set_transmit_client(mac_device);
 //! xmac.set_receive_client(mac_device, &mut MAC_RX_BUF);
 //! xmac.set_config_client(mac_device);
+//! xmac.set_energy_detect_client(mac_device);
 //! ```
 //!
 //! The `mac_device` device is now set up. Users of the MAC device can now
@@ -67,12 +68,12 @@
 
 //
 // TODO: Encryption/decryption
-// TODO: Sending beacon frames
-// TODO: Channel scanning
+// TODO: Securing beacon and MAC command frames
 //
 
-use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
+use crate::ieee802154::device::{EnergyDetectClient, MacDevice, RxClient, TxClient};
 use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::BROADCAST_ADDR;
 use crate::net::ieee802154::{
     FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
 };
@@ -314,6 +315,8 @@ pub struct Framer<'a, M: Mac, A: AES128CCM<'a>> {
     /// `None`, except when transitioning between states.
     rx_state: MapCell<RxState>,
     rx_client: OptionalCell<&'a dyn RxClient>,
+
+    ed_client: OptionalCell<&'a dyn EnergyDetectClient>,
 }
 
 impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
@@ -328,6 +331,7 @@ impl<'a, M: Mac, A: AES128CCM<'a>> Framer<'a, M, A> {
             tx_client: OptionalCell::empty(),
             rx_state: MapCell::new(RxState::Idle),
             rx_client: OptionalCell::empty(),
+            ed_client: OptionalCell::empty(),
         }
     }
 
@@ -656,6 +660,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.rx_client.set(client);
     }
 
+    fn set_energy_detect_client(&self, client: &'a dyn EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
     fn get_address(&self) -> u16 {
         self.mac.get_address()
     }
@@ -668,6 +676,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.mac.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.mac.get_channel()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mac.set_address(addr)
     }
@@ -680,6 +692,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.mac.set_pan(id)
     }
 
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.mac.set_channel(chan)
+    }
+
     fn config_commit(&self) {
         self.mac.config_commit()
     }
@@ -688,6 +704,10 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         self.mac.is_on()
     }
 
+    fn energy_detect(&self) -> ReturnCode {
+        self.mac.energy_detect()
+    }
+
     fn prepare_data_frame(
         &self,
         buf: &'static mut [u8],
@@ -734,8 +754,9 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
             /* TODO: determine this by looking at queue, and also set it in
              * hardware so that ACKs set this flag to the right value. */
             frame_pending: false,
-            // Unicast data frames request acknowledgement
-            ack_requested: true,
+            // Unicast data frames request acknowledgement, broadcast frames
+            // cannot be acknowledged
+            ack_requested: dst_addr != MacAddress::Short(BROADCAST_ADDR),
             version: FrameVersion::V2006,
             seq: Some(self.data_sequence.get()),
             dst_pan: Some(dst_pan),
@@ -765,6 +786,48 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         }
     }
 
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst_pan: Option<PanID>,
+        dst_addr: Option<MacAddress>,
+        src_pan: Option<PanID>,
+        src_addr: Option<MacAddress>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let header = Header {
+            frame_type: frame_type,
+            frame_pending: false,
+            ack_requested: dst_addr.map_or(false, |addr| addr != MacAddress::Short(BROADCAST_ADDR)),
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: dst_pan,
+            dst_addr: dst_addr,
+            src_pan: src_pan,
+            src_addr: src_addr,
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => Ok(Frame {
+                buf: buf,
+                info: FrameInfo {
+                    frame_type: frame_type,
+                    mac_payload_offset: mac_payload_offset,
+                    data_offset: data_offset,
+                    data_len: 0,
+                    mic_len: 0,
+                    security_params: None,
+                },
+            }),
+            None => Err(buf),
+        }
+    }
+
     fn transmit(&self, frame: Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         let Frame { buf, info } = frame;
         let state = match self.tx_state.take() {
@@ -841,6 +904,13 @@ impl<'a, M: Mac, A: AES128CCM<'a>> radio::ConfigClient for Framer<'a, M, A> {
     }
 }
 
+impl<'a, M: Mac, A: AES128CCM<'a>> radio::EnergyDetectClient for Framer<'a, M, A> {
+    fn energy_detect_done(&self, level: u8, result: ReturnCode) {
+        self.ed_client
+            .map(|client| client.energy_detect_done(level, result));
+    }
+}
+
 impl<'a, M: Mac, A: AES128CCM<'a>> CCMClient for Framer<'a, M, A> {
     fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
         let mut tx_waiting = false;
@@ -15,6 +15,9 @@ use kernel::debug;
 use kernel::hil::radio;
 use kernel::ReturnCode;
 
+/// The short address of frames sent to every device in range
+pub const BROADCAST_ADDR: u16 = 0xffff;
+
 pub trait Mac {
     /// Initializes the layer; may require a buffer to temporarily retaining frames to be
     /// transmitted
@@ -28,6 +31,8 @@ pub trait Mac {
     fn set_receive_client(&self, client: &'static dyn radio::RxClient);
     /// Sets the buffer for packet reception
     fn set_receive_buffer(&self, buffer: &'static mut [u8]);
+    /// Sets the notified client for energy detections
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient);
 
     /// The short 16-bit address of the radio
     fn get_address(&self) -> u16;
@@ -35,6 +40,8 @@ pub trait Mac {
     fn get_address_long(&self) -> [u8; 8];
     /// The 16-bit PAN id of the radio
     fn get_pan(&self) -> u16;
+    /// The 802.15.4 channel of the radio
+    fn get_channel(&self) -> u8;
 
     /// Sets the short 16-bit address of the radio
     fn set_address(&self, addr: u16);
@@ -42,6 +49,8 @@ pub trait Mac {
     fn set_address_long(&self, addr: [u8; 8]);
     /// Sets the 16-bit PAN id of the radio
     fn set_pan(&self, id: u16);
+    /// Sets the 802.15.4 channel of the radio
+    fn set_channel(&self, chan: u8) -> ReturnCode;
 
     /// Must be called after one or more calls to `set_*`. If
     /// `set_*` is called without calling `config_commit`, there is no guarantee
@@ -53,6 +62,10 @@ pub trait Mac {
     /// Indicates whether or not the MAC protocol is active and can send frames
     fn is_on(&self) -> bool;
 
+    /// Measures the energy on the current channel, reported to the energy
+    /// detection client
+    fn energy_detect(&self) -> ReturnCode;
+
     /// Transmits complete MAC frames, which must be prepared by an ieee802154::device::MacDevice
     /// before being passed to the Mac layer. Returns the frame buffer in case of an error.
     fn transmit(
@@ -122,10 +135,26 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
         self.radio.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.radio.get_channel()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.radio.set_channel(chan)
+    }
+
     fn config_commit(&self) {
         self.radio.config_commit()
     }
 
+    fn energy_detect(&self) -> ReturnCode {
+        self.radio.energy_detect()
+    }
+
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient) {
+        self.radio.set_energy_detect_client(client)
+    }
+
     fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
         self.tx_client.set(client);
     }
@@ -163,15 +192,18 @@ impl<R: radio::Radio> radio::RxClient for AwakeMac<'_, R> {
         crc_valid: bool,
         result: ReturnCode,
     ) {
-        // Filter packets by destination because radio is in promiscuous mode
+        // Filter packets by destination because radio is in promiscuous mode.
+        // Broadcast frames and frames without a destination, such as beacons,
+        // are left to the layers above.
         let mut addr_match = false;
         if let Some((_, (header, _))) = Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
-            if let Some(dst_addr) = header.dst_addr {
-                addr_match = match dst_addr {
-                    MacAddress::Short(addr) => addr == self.radio.get_address(),
-                    MacAddress::Long(long_addr) => long_addr == self.radio.get_address_long(),
-                };
-            }
+            addr_match = match header.dst_addr {
+                Some(MacAddress::Short(addr)) => {
+                    addr == self.radio.get_address() || addr == BROADCAST_ADDR
+                }
+                Some(MacAddress::Long(long_addr)) => long_addr == self.radio.get_address_long(),
+                None => true,
+            };
         }
 
         if addr_match {
@@ -0,0 +1,1277 @@
+//! IEEE 802.15.4 MAC layer management (MLME): scans, beacons and association.
+//!
+//! `Mlme` lets nodes discover and join PANs instead of being statically
+//! configured. It runs on a `MacDevice`, usually a `MacUser` of the `MuxMac`,
+//! and provides:
+//!
+//! - Energy detection scans, which measure the peak energy on each channel of
+//!   a channel mask, to pick a quiet channel for a new PAN.
+//! - Active scans, which send a Beacon Request on each channel of a mask and
+//!   collect the PAN descriptors of the beacons heard in answer.
+//! - The coordinator role: once a PAN is started, Beacon Requests are
+//!   answered with beacons, and Association Requests with a newly allocated
+//!   short address, which the device fetches with a Data Request.
+//! - Association of a device with a coordinator found by an active scan, and
+//!   disassociation.
+//!
+//! Only nonbeacon-enabled PANs are supported, so coordinators do not send
+//! periodic beacons, and MAC commands are sent unsecured. Each scan channel
+//! is listened to for `(2^duration + 1)` base superframe durations, where
+//! `duration` is the scan duration exponent from 0 to 14.
+//!
+//! `MlmeDriver` exposes scans and association to userspace.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mlme = static_init!(
+//!     capsules::ieee802154::mlme::Mlme<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::mlme::Mlme::new(mlme_mac, mlme_alarm, &mut MLME_BUF)
+//! );
+//! mlme_mac.set_transmit_client(mlme);
+//! mlme_mac.set_receive_client(mlme);
+//! mlme_mac.set_energy_detect_client(mlme);
+//! mlme_alarm.set_alarm_client(mlme);
+//! mlme.set_client(client);
+//! mlme.active_scan(mlme::ALL_CHANNELS, 3);
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: The buffer scan results are written to. Energy detection scans
+//!   write the ED level of each channel from `FIRST_CHANNEL`, one byte each.
+//!   Active scans write `PAN_DESCRIPTOR_LEN` bytes per PAN found: the
+//!   channel, the coordinator address mode (2 for short, 3 for extended), the
+//!   PAN ID, the Superframe Specification, and the coordinator address, all
+//!   little-endian.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called when a scan completes, with the status and the number of
+//!   results.
+//! - `1`: Called when an association attempt completes, with the status
+//!   (`SUCCESS`, `ENOACK` if the coordinator did not answer, `ENOMEM` if the
+//!   PAN is at capacity, `FAIL` if access was denied) and the short address.
+//! - `2`: Called when the node leaves its PAN.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Energy detection scan of the channels in mask `arg1`, with scan
+//!   duration exponent `arg2`.
+//! - `2`: Active scan of the channels in mask `arg1`, with scan duration
+//!   exponent `arg2`.
+//! - `3`: Associate with the PAN at index `arg1` of the last active scan,
+//!   with capability information `arg2`.
+//! - `4`: Disassociate from the PAN.
+//! - `5`: Start a PAN with ID `arg1` on channel `arg2`, as its coordinator,
+//!   using the current short address.
+//! - `6`: Permit (`arg1` non-zero) or deny new associations.
+
+use crate::ieee802154::device::{self, MacDevice};
+use crate::net::ieee802154::{FrameType, Header, MacAddress, PanID};
+use crate::net::stream::{decode_u16, decode_u8, encode_u16, encode_u8, SResult};
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ieee802154Mlme as usize;
+
+/// The lowest 2.4 GHz channel
+pub const FIRST_CHANNEL: u8 = 11;
+/// The number of 2.4 GHz channels
+pub const NUM_CHANNELS: usize = 16;
+/// Channel mask of all 2.4 GHz channels, where bit `n` selects channel `n`
+pub const ALL_CHANNELS: u32 = 0x07ff_f800;
+/// Largest scan duration exponent
+pub const MAX_SCAN_DURATION: u8 = 14;
+
+/// Number of PANs an active scan can report
+pub const MAX_PAN_DESCRIPTORS: usize = 8;
+/// Number of devices a coordinator can associate
+pub const MAX_DEVICES: usize = 8;
+/// Size of a PAN descriptor reported to userspace
+pub const PAN_DESCRIPTOR_LEN: usize = 14;
+
+/// The short address of a PAN coordinator which is not associated
+pub const NO_SHORT_ADDR: u16 = 0xffff;
+/// The short address telling an associated device to use its extended
+/// address
+pub const USE_EXTENDED_ADDR: u16 = 0xfffe;
+/// The PAN ID of frames sent to every PAN
+pub const BROADCAST_PAN: PanID = 0xffff;
+
+/// aBaseSuperframeDuration, 960 symbols of 16 us
+const BASE_SUPERFRAME_DURATION_US: u32 = 15_360;
+/// macResponseWaitTime, 32 aBaseSuperframeDuration, after which a device
+/// polls the coordinator for the Association Response
+const RESPONSE_WAIT_MS: u32 = 492;
+/// How long a device waits for the Association Response after polling.
+/// macMaxFrameTotalWaitTime is shorter, but coordinators answering from
+/// software need more.
+const FRAME_WAIT_MS: u32 = 100;
+
+/// Superframe Specification of a nonbeacon-enabled PAN: beacon order,
+/// superframe order and final CAP slot 15
+const SUPERFRAME_NONBEACON: u16 = 0x0fff;
+const SUPERFRAME_PAN_COORDINATOR: u16 = 1 << 14;
+const SUPERFRAME_ASSOCIATION_PERMIT: u16 = 1 << 15;
+
+/// MAC command frame identifiers
+pub mod command_id {
+    pub const ASSOCIATION_REQUEST: u8 = 0x01;
+    pub const ASSOCIATION_RESPONSE: u8 = 0x02;
+    pub const DISASSOCIATION_NOTIFICATION: u8 = 0x03;
+    pub const DATA_REQUEST: u8 = 0x04;
+    pub const BEACON_REQUEST: u8 = 0x07;
+}
+
+/// Bits of the capability information of an Association Request
+pub mod capability {
+    pub const DEVICE_TYPE_FFD: u8 = 1 << 1;
+    pub const MAINS_POWERED: u8 = 1 << 2;
+    pub const RX_ON_WHEN_IDLE: u8 = 1 << 3;
+    pub const ALLOCATE_ADDRESS: u8 = 1 << 7;
+}
+
+/// Reasons of a Disassociation Notification
+pub mod disassociation_reason {
+    pub const COORDINATOR_REQUEST: u8 = 0x01;
+    pub const DEVICE_REQUEST: u8 = 0x02;
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AssociationStatus {
+    Success = 0x00,
+    PanAtCapacity = 0x01,
+    AccessDenied = 0x02,
+}
+
+impl AssociationStatus {
+    pub fn from_u8(status: u8) -> Option<AssociationStatus> {
+        match status {
+            0x00 => Some(AssociationStatus::Success),
+            0x01 => Some(AssociationStatus::PanAtCapacity),
+            0x02 => Some(AssociationStatus::AccessDenied),
+            _ => None,
+        }
+    }
+
+    fn result(self) -> ReturnCode {
+        match self {
+            AssociationStatus::Success => ReturnCode::SUCCESS,
+            AssociationStatus::PanAtCapacity => ReturnCode::ENOMEM,
+            AssociationStatus::AccessDenied => ReturnCode::FAIL,
+        }
+    }
+}
+
+/// The MAC commands used to associate with a PAN.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    AssociationRequest { capability: u8 },
+    AssociationResponse { short_addr: u16, status: u8 },
+    DisassociationNotification { reason: u8 },
+    DataRequest,
+    BeaconRequest,
+}
+
+impl Command {
+    pub fn decode(buf: &[u8]) -> SResult<Command> {
+        let (off, id) = dec_try!(buf; decode_u8);
+        match id {
+            command_id::ASSOCIATION_REQUEST => {
+                let (off, capability) = dec_try!(buf, off; decode_u8);
+                stream_done!(off, Command::AssociationRequest { capability });
+            }
+            command_id::ASSOCIATION_RESPONSE => {
+                let (off, short_addr_be) = dec_try!(buf, off; decode_u16);
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                stream_done!(
+                    off,
+                    Command::AssociationResponse {
+                        short_addr: u16::from_be(short_addr_be),
+                        status,
+                    }
+                );
+            }
+            command_id::DISASSOCIATION_NOTIFICATION => {
+                let (off, reason) = dec_try!(buf, off; decode_u8);
+                stream_done!(off, Command::DisassociationNotification { reason });
+            }
+            command_id::DATA_REQUEST => stream_done!(off, Command::DataRequest),
+            command_id::BEACON_REQUEST => stream_done!(off, Command::BeaconRequest),
+            _ => stream_err!(()),
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let off = match *self {
+            Command::AssociationRequest { capability } => {
+                let off = enc_consume!(buf; encode_u8, command_id::ASSOCIATION_REQUEST);
+                enc_consume!(buf, off; encode_u8, capability)
+            }
+            Command::AssociationResponse { short_addr, status } => {
+                let off = enc_consume!(buf; encode_u8, command_id::ASSOCIATION_RESPONSE);
+                let off = enc_consume!(buf, off; encode_u16, short_addr.to_be());
+                enc_consume!(buf, off; encode_u8, status)
+            }
+            Command::DisassociationNotification { reason } => {
+                let off = enc_consume!(buf; encode_u8, command_id::DISASSOCIATION_NOTIFICATION);
+                enc_consume!(buf, off; encode_u8, reason)
+            }
+            Command::DataRequest => enc_consume!(buf; encode_u8, command_id::DATA_REQUEST),
+            Command::BeaconRequest => enc_consume!(buf; encode_u8, command_id::BEACON_REQUEST),
+        };
+        stream_done!(off);
+    }
+}
+
+/// Encodes the payload of a beacon of a nonbeacon-enabled PAN, without GTS
+/// or pending addresses.
+pub fn encode_beacon(buf: &mut [u8], association_permit: bool) -> SResult {
+    let mut superframe_spec = SUPERFRAME_NONBEACON | SUPERFRAME_PAN_COORDINATOR;
+    if association_permit {
+        superframe_spec |= SUPERFRAME_ASSOCIATION_PERMIT;
+    }
+    let off = enc_consume!(buf; encode_u16, superframe_spec.to_be());
+    // GTS and pending address specifications
+    let off = enc_consume!(buf, off; encode_u8, 0);
+    let off = enc_consume!(buf, off; encode_u8, 0);
+    stream_done!(off);
+}
+
+/// Decodes the Superframe Specification of a beacon payload.
+pub fn decode_beacon(buf: &[u8]) -> SResult<u16> {
+    stream_len_cond!(buf, 4);
+    let (off, superframe_spec_be) = dec_try!(buf; decode_u16);
+    stream_done!(off, u16::from_be(superframe_spec_be));
+}
+
+/// How long each channel is scanned for, in milliseconds.
+pub fn scan_duration_ms(exponent: u8) -> u32 {
+    let superframes = (1 << exponent.min(MAX_SCAN_DURATION)) + 1;
+    (superframes * BASE_SUPERFRAME_DURATION_US + 999) / 1000
+}
+
+/// A PAN heard during an active scan.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PanDescriptor {
+    pub channel: u8,
+    pub pan: PanID,
+    pub coord_addr: MacAddress,
+    pub superframe_spec: u16,
+}
+
+impl PanDescriptor {
+    const EMPTY: PanDescriptor = PanDescriptor {
+        channel: 0,
+        pan: 0,
+        coord_addr: MacAddress::Short(0),
+        superframe_spec: 0,
+    };
+
+    pub fn association_permit(&self) -> bool {
+        self.superframe_spec & SUPERFRAME_ASSOCIATION_PERMIT != 0
+    }
+
+    pub fn pan_coordinator(&self) -> bool {
+        self.superframe_spec & SUPERFRAME_PAN_COORDINATOR != 0
+    }
+
+    /// Encodes the descriptor in the format reported to userspace.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        if buf.len() < PAN_DESCRIPTOR_LEN {
+            return 0;
+        }
+        let mut addr = [0; 8];
+        let mode = match self.coord_addr {
+            MacAddress::Short(short_addr) => {
+                addr[..2].copy_from_slice(&short_addr.to_le_bytes());
+                2
+            }
+            MacAddress::Long(long_addr) => {
+                addr = long_addr;
+                3
+            }
+        };
+        buf[0] = self.channel;
+        buf[1] = mode;
+        buf[2..4].copy_from_slice(&self.pan.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.superframe_spec.to_le_bytes());
+        buf[6..14].copy_from_slice(&addr);
+        PAN_DESCRIPTOR_LEN
+    }
+}
+
+/// A device associated with a coordinator.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Device {
+    pub ext_addr: [u8; 8],
+    pub short_addr: u16,
+    /// The device has yet to fetch its Association Response.
+    pub response_pending: bool,
+}
+
+/// The devices associated with a coordinator.
+pub type DeviceTable = [Option<Device>; MAX_DEVICES];
+
+/// Picks the short address of the device with extended address `ext_addr`
+/// asking to join the PAN of a coordinator with short address `coord_addr`.
+/// Devices that associate again keep their address; new ones get the one
+/// after the coordinator's that matches their slot in `devices`. The entry
+/// of an accepted device is marked as waiting for its Association Response.
+pub fn allocate_address(
+    devices: &mut DeviceTable,
+    coord_addr: u16,
+    ext_addr: [u8; 8],
+    capability: u8,
+    permit: bool,
+) -> (u16, AssociationStatus) {
+    if let Some(device) = devices
+        .iter_mut()
+        .flatten()
+        .find(|device| device.ext_addr == ext_addr)
+    {
+        device.response_pending = true;
+        return (device.short_addr, AssociationStatus::Success);
+    }
+    if !permit {
+        return (NO_SHORT_ADDR, AssociationStatus::AccessDenied);
+    }
+    match devices.iter().position(|device| device.is_none()) {
+        Some(slot) => {
+            let short_addr = coord_addr.wrapping_add(1 + slot as u16);
+            let short_addr = if capability & capability::ALLOCATE_ADDRESS == 0
+                || short_addr >= USE_EXTENDED_ADDR
+            {
+                USE_EXTENDED_ADDR
+            } else {
+                short_addr
+            };
+            devices[slot] = Some(Device {
+                ext_addr: ext_addr,
+                short_addr: short_addr,
+                response_pending: true,
+            });
+            (short_addr, AssociationStatus::Success)
+        }
+        None => (NO_SHORT_ADDR, AssociationStatus::PanAtCapacity),
+    }
+}
+
+pub trait MlmeClient {
+    /// An energy detection scan is over. `energies[i]` is the peak ED level
+    /// measured on channel `FIRST_CHANNEL + i`, or 0 if it was not scanned.
+    fn energy_scan_done(&self, energies: &[u8; NUM_CHANNELS], result: ReturnCode);
+    /// An active scan is over, and heard the beacons of `pans`.
+    fn active_scan_done(&self, pans: &[PanDescriptor], result: ReturnCode);
+    /// An association attempt is over. On success, the node now uses
+    /// `short_addr`, or its extended address if it is `USE_EXTENDED_ADDR`.
+    fn associate_done(&self, short_addr: u16, result: ReturnCode);
+    /// The node left its PAN, on its own or because its coordinator asked it
+    /// to. Users of the link, such as 6LoWPAN, should drop their state.
+    fn disassociated(&self);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    Idle,
+    /// Measuring the energy on the current scan channel
+    EnergyScan,
+    /// Listening for beacons on the current scan channel
+    ActiveScan,
+    /// Sending the Association Request
+    RequestingAssociation,
+    /// Waiting before polling the coordinator
+    AwaitingResponse,
+    /// Polled the coordinator, waiting for the Association Response
+    Polling,
+    /// Sending the Disassociation Notification
+    Disassociating,
+}
+
+pub struct Mlme<'a, A: time::Alarm<'a>> {
+    mac: &'a dyn MacDevice<'a>,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn MlmeClient>,
+    tx_buf: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+
+    // Scans
+    scan_channels: Cell<u32>,
+    scan_channel: Cell<u8>,
+    scan_duration: Cell<u8>,
+    scan_expired: Cell<bool>,
+    measuring: Cell<bool>,
+    saved_channel: Cell<u8>,
+    energies: Cell<[u8; NUM_CHANNELS]>,
+    pans: Cell<[PanDescriptor; MAX_PAN_DESCRIPTORS]>,
+    num_pans: Cell<usize>,
+
+    // Device role
+    coordinator: OptionalCell<(PanID, MacAddress)>,
+    associated: Cell<bool>,
+
+    // Coordinator role
+    coordinating: Cell<bool>,
+    association_permit: Cell<bool>,
+    devices: Cell<DeviceTable>,
+    /// The device whose last Association Request was rejected, and why.
+    rejected: OptionalCell<([u8; 8], AssociationStatus)>,
+}
+
+impl<'a, A: time::Alarm<'a>> Mlme<'a, A> {
+    pub fn new(mac: &'a dyn MacDevice<'a>, alarm: &'a A, tx_buf: &'static mut [u8]) -> Mlme<'a, A> {
+        Mlme {
+            mac: mac,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            tx_buf: TakeCell::new(tx_buf),
+            state: Cell::new(State::Idle),
+            scan_channels: Cell::new(0),
+            scan_channel: Cell::new(0),
+            scan_duration: Cell::new(0),
+            scan_expired: Cell::new(false),
+            measuring: Cell::new(false),
+            saved_channel: Cell::new(0),
+            energies: Cell::new([0; NUM_CHANNELS]),
+            pans: Cell::new([PanDescriptor::EMPTY; MAX_PAN_DESCRIPTORS]),
+            num_pans: Cell::new(0),
+            coordinator: OptionalCell::empty(),
+            associated: Cell::new(false),
+            coordinating: Cell::new(false),
+            association_permit: Cell::new(false),
+            devices: Cell::new([None; MAX_DEVICES]),
+            rejected: OptionalCell::empty(),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MlmeClient) {
+        self.client.set(client);
+    }
+
+    /// The PANs heard by the last active scan.
+    pub fn pan_descriptor(&self, index: usize) -> Option<PanDescriptor> {
+        if index < self.num_pans.get() {
+            Some(self.pans.get()[index])
+        } else {
+            None
+        }
+    }
+
+    pub fn is_associated(&self) -> bool {
+        self.associated.get()
+    }
+
+    /// Measures the energy on the channels of `channels`, each for the scan
+    /// duration of exponent `duration`.
+    pub fn energy_scan(&self, channels: u32, duration: u8) -> ReturnCode {
+        self.start_scan(State::EnergyScan, channels, duration)
+    }
+
+    /// Looks for PANs on the channels of `channels`, each for the scan
+    /// duration of exponent `duration`.
+    pub fn active_scan(&self, channels: u32, duration: u8) -> ReturnCode {
+        self.start_scan(State::ActiveScan, channels, duration)
+    }
+
+    /// Starts a PAN as its coordinator, which lets devices associate.
+    pub fn start_pan(&self, pan: PanID, channel: u8) -> ReturnCode {
+        if self.state.get() != State::Idle || self.associated.get() {
+            return ReturnCode::EBUSY;
+        }
+        let result = self.mac.set_channel(channel);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.mac.set_pan(pan);
+        self.mac.config_commit();
+        self.coordinating.set(true);
+        self.association_permit.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    /// Sets whether a coordinator accepts new devices.
+    pub fn set_association_permit(&self, permit: bool) {
+        self.association_permit.set(permit);
+    }
+
+    /// Asks the coordinator `coord_addr` of PAN `pan` on `channel` to join
+    /// its PAN, with the capability information `capability`.
+    pub fn associate(
+        &self,
+        channel: u8,
+        pan: PanID,
+        coord_addr: MacAddress,
+        capability: u8,
+    ) -> ReturnCode {
+        if self.state.get() != State::Idle || self.coordinating.get() {
+            return ReturnCode::EBUSY;
+        }
+        if self.associated.get() {
+            return ReturnCode::EALREADY;
+        }
+        let result = self.mac.set_channel(channel);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.mac.set_pan(pan);
+        self.mac.config_commit();
+
+        let result = self.send_command(
+            Some(pan),
+            Some(coord_addr),
+            BROADCAST_PAN,
+            Command::AssociationRequest { capability },
+        );
+        if result == ReturnCode::SUCCESS {
+            self.coordinator.set((pan, coord_addr));
+            self.state.set(State::RequestingAssociation);
+        }
+        result
+    }
+
+    /// Tells the coordinator that the node leaves its PAN.
+    pub fn disassociate(&self) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        let (pan, coord_addr) = match self.coordinator.map(|coordinator| *coordinator) {
+            Some(coordinator) if self.associated.get() => coordinator,
+            _ => return ReturnCode::EINVAL,
+        };
+        let result = self.send_command(
+            Some(pan),
+            Some(coord_addr),
+            pan,
+            Command::DisassociationNotification {
+                reason: disassociation_reason::DEVICE_REQUEST,
+            },
+        );
+        if result == ReturnCode::SUCCESS {
+            self.state.set(State::Disassociating);
+        }
+        result
+    }
+
+    fn start_scan(&self, state: State, channels: u32, duration: u8) -> ReturnCode {
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if channels & ALL_CHANNELS == 0 || duration > MAX_SCAN_DURATION {
+            return ReturnCode::EINVAL;
+        }
+        self.state.set(state);
+        self.scan_channels.set(channels & ALL_CHANNELS);
+        self.scan_duration.set(duration);
+        self.saved_channel.set(self.mac.get_channel());
+        self.energies.set([0; NUM_CHANNELS]);
+        self.num_pans.set(0);
+
+        let result = self.scan_next_channel();
+        if result != ReturnCode::SUCCESS {
+            self.end_scan();
+        }
+        result
+    }
+
+    /// Starts scanning the lowest channel left in the mask.
+    fn scan_next_channel(&self) -> ReturnCode {
+        let remaining = self.scan_channels.get();
+        let channel = remaining.trailing_zeros() as u8;
+        self.scan_channels.set(remaining & !(1 << channel));
+        self.scan_channel.set(channel);
+
+        let result = self.mac.set_channel(channel);
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.mac.config_commit();
+        self.scan_expired.set(false);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            A::ticks_from_ms(scan_duration_ms(self.scan_duration.get())),
+        );
+        match self.state.get() {
+            State::EnergyScan => self.measure(),
+            State::ActiveScan => self.send_command(
+                Some(BROADCAST_PAN),
+                Some(MacAddress::Short(NO_SHORT_ADDR)),
+                BROADCAST_PAN,
+                Command::BeaconRequest,
+            ),
+            _ => ReturnCode::SUCCESS,
+        }
+    }
+
+    fn measure(&self) -> ReturnCode {
+        let result = self.mac.energy_detect();
+        self.measuring.set(result == ReturnCode::SUCCESS);
+        result
+    }
+
+    /// Moves on once the scan of the current channel is over.
+    fn scan_channel_done(&self) {
+        if self.scan_channels.get() == 0 {
+            self.finish_scan(ReturnCode::SUCCESS);
+        } else {
+            let result = self.scan_next_channel();
+            if result != ReturnCode::SUCCESS {
+                self.finish_scan(result);
+            }
+        }
+    }
+
+    fn end_scan(&self) {
+        self.alarm.disarm();
+        self.state.set(State::Idle);
+        self.mac.set_channel(self.saved_channel.get());
+        self.mac.config_commit();
+    }
+
+    fn finish_scan(&self, result: ReturnCode) {
+        let state = self.state.get();
+        self.end_scan();
+        self.client.map(|client| match state {
+            State::EnergyScan => client.energy_scan_done(&self.energies.get(), result),
+            _ => {
+                let pans = self.pans.get();
+                client.active_scan_done(&pans[..self.num_pans.get()], result)
+            }
+        });
+    }
+
+    fn finish_association(&self, short_addr: u16, result: ReturnCode) {
+        self.alarm.disarm();
+        self.state.set(State::Idle);
+        if result == ReturnCode::SUCCESS {
+            self.associated.set(true);
+            self.mac.set_address(short_addr);
+            self.mac.config_commit();
+        } else {
+            self.coordinator.clear();
+        }
+        self.client
+            .map(|client| client.associate_done(short_addr, result));
+    }
+
+    fn leave(&self) {
+        self.associated.set(false);
+        self.coordinator.clear();
+        self.mac.set_address(NO_SHORT_ADDR);
+        self.mac.config_commit();
+        self.client.map(|client| client.disassociated());
+    }
+
+    /// The address the node sends MAC commands from: the extended address,
+    /// unless it has a short address and is the coordinator.
+    fn own_address(&self) -> MacAddress {
+        let short_addr = self.mac.get_address();
+        if self.coordinating.get() && short_addr < USE_EXTENDED_ADDR {
+            MacAddress::Short(short_addr)
+        } else {
+            MacAddress::Long(self.mac.get_address_long())
+        }
+    }
+
+    fn send_command(
+        &self,
+        dst_pan: Option<PanID>,
+        dst_addr: Option<MacAddress>,
+        src_pan: PanID,
+        command: Command,
+    ) -> ReturnCode {
+        // Beacon Requests carry no source
+        let src_addr = match command {
+            Command::BeaconRequest => None,
+            _ => Some(self.own_address()),
+        };
+        let src_pan = src_addr.map(|_| src_pan);
+        let mut payload = [0; 4];
+        let len = match command.encode(&mut payload).done() {
+            Some((len, ())) => len,
+            None => return ReturnCode::FAIL,
+        };
+        self.send(
+            FrameType::MACCommand,
+            dst_pan,
+            dst_addr,
+            src_pan,
+            src_addr,
+            &payload[..len],
+        )
+    }
+
+    fn send_beacon(&self) -> ReturnCode {
+        let mut payload = [0; 4];
+        let len = match encode_beacon(&mut payload, self.association_permit.get()).done() {
+            Some((len, ())) => len,
+            None => return ReturnCode::FAIL,
+        };
+        self.send(
+            FrameType::Beacon,
+            None,
+            None,
+            Some(self.mac.get_pan()),
+            Some(self.own_address()),
+            &payload[..len],
+        )
+    }
+
+    fn send(
+        &self,
+        frame_type: FrameType,
+        dst_pan: Option<PanID>,
+        dst_addr: Option<MacAddress>,
+        src_pan: Option<PanID>,
+        src_addr: Option<MacAddress>,
+        payload: &[u8],
+    ) -> ReturnCode {
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let mut frame = match self
+            .mac
+            .prepare_frame(buf, frame_type, dst_pan, dst_addr, src_pan, src_addr)
+        {
+            Ok(frame) => frame,
+            Err(buf) => {
+                self.tx_buf.replace(buf);
+                return ReturnCode::FAIL;
+            }
+        };
+        let result = frame.append_payload(payload);
+        if result != ReturnCode::SUCCESS {
+            self.tx_buf.replace(frame.into_buf());
+            return result;
+        }
+        let (result, buf) = self.mac.transmit(frame);
+        buf.map(|buf| self.tx_buf.replace(buf));
+        result
+    }
+
+    fn receive_beacon(&self, header: &Header, payload: &[u8]) {
+        if self.state.get() != State::ActiveScan {
+            return;
+        }
+        let (pan, coord_addr) = match (header.src_pan, header.src_addr) {
+            (Some(pan), Some(coord_addr)) => (pan, coord_addr),
+            _ => return,
+        };
+        let superframe_spec = match decode_beacon(payload).done() {
+            Some((_, superframe_spec)) => superframe_spec,
+            None => return,
+        };
+        let descriptor = PanDescriptor {
+            channel: self.scan_channel.get(),
+            pan: pan,
+            coord_addr: coord_addr,
+            superframe_spec: superframe_spec,
+        };
+        let mut pans = self.pans.get();
+        let num_pans = self.num_pans.get();
+        let known = pans[..num_pans].iter().any(|known| {
+            known.channel == descriptor.channel
+                && known.pan == descriptor.pan
+                && known.coord_addr == descriptor.coord_addr
+        });
+        if !known && num_pans < MAX_PAN_DESCRIPTORS {
+            pans[num_pans] = descriptor;
+            self.pans.set(pans);
+            self.num_pans.set(num_pans + 1);
+        }
+    }
+
+    /// Sends the Association Response the device `ext_addr` polls for, if
+    /// any, and forgets it once sent.
+    fn send_association_response(&self, ext_addr: [u8; 8]) {
+        let mut devices = self.devices.get();
+        let device = devices
+            .iter_mut()
+            .flatten()
+            .find(|device| device.ext_addr == ext_addr && device.response_pending);
+        let (short_addr, status) = match device {
+            Some(ref device) => (device.short_addr, AssociationStatus::Success),
+            None => match self.rejected.map(|rejected| *rejected) {
+                Some((ext, status)) if ext == ext_addr => (NO_SHORT_ADDR, status),
+                _ => return,
+            },
+        };
+        let pan = self.mac.get_pan();
+        let result = self.send_command(
+            Some(pan),
+            Some(MacAddress::Long(ext_addr)),
+            pan,
+            Command::AssociationResponse {
+                short_addr,
+                status: status as u8,
+            },
+        );
+        if result != ReturnCode::SUCCESS {
+            return;
+        }
+        match device {
+            Some(device) => {
+                device.response_pending = false;
+                self.devices.set(devices);
+            }
+            None => self.rejected.clear(),
+        }
+    }
+
+    fn receive_command(&self, header: &Header, command: Command) {
+        match command {
+            Command::BeaconRequest => {
+                if self.coordinating.get() {
+                    let _ = self.send_beacon();
+                }
+            }
+            Command::AssociationRequest { capability } => {
+                if !self.coordinating.get() {
+                    return;
+                }
+                if let Some(MacAddress::Long(ext_addr)) = header.src_addr {
+                    let mut devices = self.devices.get();
+                    let (_, status) = allocate_address(
+                        &mut devices,
+                        self.mac.get_address(),
+                        ext_addr,
+                        capability,
+                        self.association_permit.get(),
+                    );
+                    self.devices.set(devices);
+                    // Responses are kept until the device polls for them
+                    if status != AssociationStatus::Success {
+                        self.rejected.set((ext_addr, status));
+                    } else if self.rejected.map_or(false, |(ext, _)| *ext == ext_addr) {
+                        self.rejected.clear();
+                    }
+                }
+            }
+            Command::DataRequest => {
+                if let Some(MacAddress::Long(ext_addr)) = header.src_addr {
+                    self.send_association_response(ext_addr);
+                }
+            }
+            Command::AssociationResponse { short_addr, status } => match self.state.get() {
+                State::AwaitingResponse | State::Polling => {
+                    let from_coordinator = self.coordinator.map_or(false, |(_, coord_addr)| {
+                        header.src_addr == Some(*coord_addr)
+                    });
+                    if !from_coordinator {
+                        return;
+                    }
+                    let result =
+                        AssociationStatus::from_u8(status).map_or(ReturnCode::FAIL, |s| s.result());
+                    self.finish_association(short_addr, result);
+                }
+                _ => {}
+            },
+            Command::DisassociationNotification { .. } => {
+                if self.coordinating.get() {
+                    if let Some(MacAddress::Long(ext_addr)) = header.src_addr {
+                        let mut devices = self.devices.get();
+                        for device in devices.iter_mut() {
+                            if device.map_or(false, |device| device.ext_addr == ext_addr) {
+                                *device = None;
+                            }
+                        }
+                        self.devices.set(devices);
+                    }
+                } else if self.associated.get() && self.state.get() == State::Idle {
+                    let from_coordinator = self.coordinator.map_or(false, |(_, coord_addr)| {
+                        header.src_addr == Some(*coord_addr)
+                    });
+                    if from_coordinator {
+                        self.leave();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> device::TxClient for Mlme<'a, A> {
+    fn send_done(&self, spi_buf: &'static mut [u8], _acked: bool, result: ReturnCode) {
+        self.tx_buf.replace(spi_buf);
+        // Radios do not all report acknowledgements, so the coordinator is
+        // assumed to have heard frames sent successfully.
+        match self.state.get() {
+            State::RequestingAssociation => {
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::AwaitingResponse);
+                    self.alarm
+                        .set_alarm(self.alarm.now(), A::ticks_from_ms(RESPONSE_WAIT_MS));
+                } else {
+                    self.finish_association(NO_SHORT_ADDR, result);
+                }
+            }
+            State::Polling => {
+                if result == ReturnCode::SUCCESS {
+                    self.alarm
+                        .set_alarm(self.alarm.now(), A::ticks_from_ms(FRAME_WAIT_MS));
+                } else {
+                    self.finish_association(NO_SHORT_ADDR, result);
+                }
+            }
+            State::Disassociating => {
+                self.state.set(State::Idle);
+                self.leave();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> device::RxClient for Mlme<'a, A> {
+    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+        let payload = &buf[data_offset..data_offset + data_len];
+        match header.frame_type {
+            FrameType::Beacon => self.receive_beacon(&header, payload),
+            FrameType::MACCommand => {
+                if let Some((_, command)) = Command::decode(payload).done() {
+                    self.receive_command(&header, command);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> device::EnergyDetectClient for Mlme<'a, A> {
+    fn energy_detect_done(&self, level: u8, result: ReturnCode) {
+        if self.state.get() != State::EnergyScan {
+            return;
+        }
+        self.measuring.set(false);
+        if result == ReturnCode::SUCCESS {
+            let mut energies = self.energies.get();
+            let index = (self.scan_channel.get() - FIRST_CHANNEL) as usize;
+            energies[index] = energies[index].max(level);
+            self.energies.set(energies);
+        }
+        if self.scan_expired.get() {
+            self.scan_channel_done();
+        } else {
+            // Keep the peak energy until the end of the scan duration. If no
+            // measurement can be started, the alarm ends the channel.
+            let _ = self.measure();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Mlme<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::EnergyScan => {
+                if self.measuring.get() {
+                    self.scan_expired.set(true);
+                } else {
+                    self.scan_channel_done();
+                }
+            }
+            State::ActiveScan => self.scan_channel_done(),
+            State::AwaitingResponse => {
+                let result = match self.coordinator.map(|coordinator| *coordinator) {
+                    Some((pan, coord_addr)) => {
+                        self.send_command(Some(pan), Some(coord_addr), pan, Command::DataRequest)
+                    }
+                    None => ReturnCode::FAIL,
+                };
+                if result == ReturnCode::SUCCESS {
+                    self.state.set(State::Polling);
+                } else {
+                    self.finish_association(NO_SHORT_ADDR, result);
+                }
+            }
+            State::Polling => self.finish_association(NO_SHORT_ADDR, ReturnCode::ENOACK),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct App {
+    scan_callback: Option<Callback>,
+    associate_callback: Option<Callback>,
+    disassociate_callback: Option<Callback>,
+    results: Option<AppSlice<Shared, u8>>,
+}
+
+/// Userspace interface to the MLME. One scan or association is outstanding
+/// at a time across all processes.
+pub struct MlmeDriver<'a, A: time::Alarm<'a>> {
+    mlme: &'a Mlme<'a, A>,
+    apps: Grant<App>,
+    current_app: OptionalCell<AppId>,
+}
+
+impl<'a, A: time::Alarm<'a>> MlmeDriver<'a, A> {
+    pub fn new(mlme: &'a Mlme<'a, A>, grant: Grant<App>) -> MlmeDriver<'a, A> {
+        MlmeDriver {
+            mlme: mlme,
+            apps: grant,
+            current_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Runs `operation` on behalf of `appid`, which gets its completion.
+    fn start(&self, appid: AppId, operation: impl FnOnce() -> ReturnCode) -> ReturnCode {
+        if self.current_app.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let result = operation();
+        if result == ReturnCode::SUCCESS {
+            self.current_app.set(appid);
+        }
+        result
+    }
+
+    /// Writes the results of a scan to the buffer of the process which
+    /// started it, and notifies it.
+    fn scan_done(&self, result: ReturnCode, write: impl Fn(&mut [u8]) -> usize) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                let count = app
+                    .results
+                    .as_mut()
+                    .map_or(0, |results| write(results.as_mut()));
+                app.scan_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), count, 0));
+            });
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> MlmeClient for MlmeDriver<'a, A> {
+    fn energy_scan_done(&self, energies: &[u8; NUM_CHANNELS], result: ReturnCode) {
+        self.scan_done(result, |buf| {
+            let len = buf.len().min(NUM_CHANNELS);
+            buf[..len].copy_from_slice(&energies[..len]);
+            len
+        });
+    }
+
+    fn active_scan_done(&self, pans: &[PanDescriptor], result: ReturnCode) {
+        self.scan_done(result, |buf| {
+            let mut count = 0;
+            for (chunk, pan) in buf.chunks_mut(PAN_DESCRIPTOR_LEN).zip(pans.iter()) {
+                if pan.encode(chunk) > 0 {
+                    count += 1;
+                }
+            }
+            count
+        });
+    }
+
+    fn associate_done(&self, short_addr: u16, result: ReturnCode) {
+        self.current_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.associate_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), short_addr as usize, 0));
+            });
+        });
+    }
+
+    fn disassociated(&self) {
+        self.apps.each(|app| {
+            app.disassociate_callback.map(|mut cb| cb.schedule(0, 0, 0));
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for MlmeDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The buffer scan results are written to.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.results = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Scan completion callback.
+    /// - `1`: Association completion callback.
+    /// - `2`: Disassociation callback.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(app_id, |app, _| match subscribe_num {
+                0 => {
+                    app.scan_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                1 => {
+                    app.associate_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                2 => {
+                    app.disassociate_callback = callback;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOSUPPORT,
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// MAC management.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Energy detection scan of channel mask `arg1` with duration
+    ///        exponent `arg2`.
+    /// - `2`: Active scan of channel mask `arg1` with duration exponent
+    ///        `arg2`.
+    /// - `3`: Associate with the PAN `arg1` of the last active scan, with
+    ///        capability information `arg2`.
+    /// - `4`: Disassociate.
+    /// - `5`: Start PAN `arg1` on channel `arg2` as its coordinator.
+    /// - `6`: Permit new associations if `arg1` is non-zero.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.start(appid, || {
+                self.mlme.energy_scan(arg1 as u32, arg2.min(0xff) as u8)
+            }),
+            2 => self.start(appid, || {
+                self.mlme.active_scan(arg1 as u32, arg2.min(0xff) as u8)
+            }),
+            3 => match self.mlme.pan_descriptor(arg1) {
+                Some(pan) => self.start(appid, || {
+                    self.mlme
+                        .associate(pan.channel, pan.pan, pan.coord_addr, arg2 as u8)
+                }),
+                None => ReturnCode::EINVAL,
+            },
+            4 => self.mlme.disassociate(),
+            5 => self.mlme.start_pan(arg1 as PanID, arg2 as u8),
+            6 => {
+                self.mlme.set_association_permit(arg1 != 0);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_round_trip() {
+        let mut buf = [0; 4];
+        let response = Command::AssociationResponse {
+            short_addr: 0x1234,
+            status: AssociationStatus::Success as u8,
+        };
+        assert_eq!(response.encode(&mut buf).done(), Some((4, ())));
+        assert_eq!(buf, [command_id::ASSOCIATION_RESPONSE, 0x34, 0x12, 0]);
+        assert_eq!(Command::decode(&buf).done(), Some((4, response)));
+        assert_eq!(
+            Command::decode(&[command_id::BEACON_REQUEST]).done(),
+            Some((1, Command::BeaconRequest))
+        );
+        assert!(Command::decode(&[command_id::ASSOCIATION_REQUEST]).is_needed());
+        assert!(Command::decode(&[0x09]).is_err());
+    }
+
+    #[test]
+    fn beacon_payload() {
+        let mut buf = [0; 4];
+        assert_eq!(encode_beacon(&mut buf, true).done(), Some((4, ())));
+        assert_eq!(buf, [0xff, 0xcf, 0, 0]);
+        let (_, superframe_spec) = decode_beacon(&buf).done().unwrap();
+        let pan = PanDescriptor {
+            superframe_spec,
+            ..PanDescriptor::EMPTY
+        };
+        assert!(pan.association_permit() && pan.pan_coordinator());
+        assert!(decode_beacon(&buf[..2]).is_needed());
+    }
+
+    #[test]
+    fn address_allocation() {
+        let mut devices = [None; MAX_DEVICES];
+        let ext = [1; 8];
+        let allocate = capability::ALLOCATE_ADDRESS;
+        assert_eq!(
+            allocate_address(&mut devices, 0, ext, allocate, true),
+            (1, AssociationStatus::Success)
+        );
+        // Associating again keeps the address, even once the PAN is closed
+        assert_eq!(
+            allocate_address(&mut devices, 0, ext, allocate, false),
+            (1, AssociationStatus::Success)
+        );
+        assert_eq!(
+            allocate_address(&mut devices, 0, [2; 8], allocate, false),
+            (NO_SHORT_ADDR, AssociationStatus::AccessDenied)
+        );
+        assert_eq!(
+            allocate_address(&mut devices, 0, [2; 8], 0, true),
+            (USE_EXTENDED_ADDR, AssociationStatus::Success)
+        );
+        // Each device waits for its own response
+        assert_eq!(
+            devices[..2],
+            [
+                Some(Device {
+                    ext_addr: ext,
+                    short_addr: 1,
+                    response_pending: true,
+                }),
+                Some(Device {
+                    ext_addr: [2; 8],
+                    short_addr: USE_EXTENDED_ADDR,
+                    response_pending: true,
+                }),
+            ]
+        );
+        for i in 3..=MAX_DEVICES as u8 {
+            allocate_address(&mut devices, 0, [i; 8], allocate, true);
+        }
+        assert_eq!(
+            allocate_address(&mut devices, 0, [0xff; 8], allocate, true),
+            (NO_SHORT_ADDR, AssociationStatus::PanAtCapacity)
+        );
+    }
+
+    #[test]
+    fn scan_durations() {
+        assert_eq!(scan_duration_ms(0), 31);
+        assert_eq!(scan_duration_ms(3), 139);
+        assert_eq!(scan_duration_ms(MAX_SCAN_DURATION), 251_674);
+    }
+}
@@ -3,6 +3,7 @@
 pub mod device;
 pub mod framer;
 pub mod mac;
+pub mod mlme;
 pub mod virtual_mac;
 pub mod xmac;
 
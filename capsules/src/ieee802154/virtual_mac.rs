@@ -6,7 +6,8 @@
 //! subsequently 6LoWPAN-encoded and fragmented IP packets. This capsule allows
 //! that to happen by providing a mechanism for sequencing transmission attempts,
 //! Every radio frame received is provided to all listening clients so that each
-//! client can perform its own frame filtering logic.
+//! client can perform its own frame filtering logic. Energy detections are
+//! reported to the user which requested them.
 //!
 //! Usage
 //! -----
@@ -20,6 +21,7 @@
 //!     capsules::ieee802154::virtual_mac::MuxMac::new(&'static mac_device));
 //! mac_device.set_transmit_client(mux_mac);
 //! mac_device.set_receive_client(mux_mac);
+//! mac_device.set_energy_detect_client(mux_mac);
 //!
 //! // Everything that uses the virtualized MAC device must create one of these.
 //! let virtual_mac = static_init!(
@@ -29,7 +31,7 @@
 //! ```
 
 use crate::ieee802154::{device, framer};
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ieee802154::{FrameType, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use core::cell::Cell;
 use kernel::common::cells::{MapCell, OptionalCell};
 use kernel::common::{List, ListLink, ListNode};
@@ -61,6 +63,16 @@ impl device::RxClient for MuxMac<'_> {
     }
 }
 
+impl device::EnergyDetectClient for MuxMac<'_> {
+    fn energy_detect_done(&self, level: u8, result: ReturnCode) {
+        let user = self.users.iter().find(|user| user.energy_detecting.get());
+        user.map(|user| {
+            user.energy_detecting.set(false);
+            user.energy_detect_done(level, result);
+        });
+    }
+}
+
 impl<'a> MuxMac<'a> {
     pub const fn new(mac: &'a dyn device::MacDevice<'a>) -> MuxMac<'a> {
         MuxMac {
@@ -193,6 +205,8 @@ pub struct MacUser<'a> {
     next: ListLink<'a, MacUser<'a>>,
     tx_client: Cell<Option<&'a dyn device::TxClient>>,
     rx_client: Cell<Option<&'a dyn device::RxClient>>,
+    ed_client: Cell<Option<&'a dyn device::EnergyDetectClient>>,
+    energy_detecting: Cell<bool>,
 }
 
 impl<'a> MacUser<'a> {
@@ -203,6 +217,8 @@ impl<'a> MacUser<'a> {
             next: ListLink::empty(),
             tx_client: Cell::new(None),
             rx_client: Cell::new(None),
+            ed_client: Cell::new(None),
+            energy_detecting: Cell::new(false),
         }
     }
 }
@@ -219,6 +235,12 @@ impl MacUser<'_> {
             .get()
             .map(move |client| client.receive(buf, header, data_offset, data_len));
     }
+
+    fn energy_detect_done(&self, level: u8, result: ReturnCode) {
+        self.ed_client
+            .get()
+            .map(move |client| client.energy_detect_done(level, result));
+    }
 }
 
 impl<'a> ListNode<'a, MacUser<'a>> for MacUser<'a> {
@@ -236,6 +258,10 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.rx_client.set(Some(client));
     }
 
+    fn set_energy_detect_client(&self, client: &'a dyn device::EnergyDetectClient) {
+        self.ed_client.set(Some(client));
+    }
+
     fn get_address(&self) -> u16 {
         self.mux.mac.get_address()
     }
@@ -248,6 +274,10 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.mux.mac.get_channel()
+    }
+
     fn set_address(&self, addr: u16) {
         self.mux.mac.set_address(addr)
     }
@@ -260,6 +290,10 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.set_pan(id)
     }
 
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.mux.mac.set_channel(chan)
+    }
+
     fn config_commit(&self) {
         self.mux.mac.config_commit()
     }
@@ -268,6 +302,23 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
         self.mux.mac.is_on()
     }
 
+    // Only one user can measure the energy at a time.
+    fn energy_detect(&self) -> ReturnCode {
+        if self
+            .mux
+            .users
+            .iter()
+            .any(|user| user.energy_detecting.get())
+        {
+            return ReturnCode::EBUSY;
+        }
+        let result = self.mux.mac.energy_detect();
+        if result == ReturnCode::SUCCESS {
+            self.energy_detecting.set(true);
+        }
+        result
+    }
+
     fn prepare_data_frame(
         &self,
         buf: &'static mut [u8],
@@ -282,6 +333,20 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst_pan: Option<PanID>,
+        dst_addr: Option<MacAddress>,
+        src_pan: Option<PanID>,
+        src_addr: Option<MacAddress>,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux
+            .mac
+            .prepare_frame(buf, frame_type, dst_pan, dst_addr, src_pan, src_addr)
+    }
+
     fn transmit(&self, frame: framer::Frame) -> (ReturnCode, Option<&'static mut [u8]>) {
         // If the muxer is idle, immediately transmit the frame, otherwise
         // attempt to queue the transmission request. However, each MAC user can
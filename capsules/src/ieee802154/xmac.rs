@@ -20,6 +20,19 @@
 //!   * Since X-MAC relies on proper sleep/wake behavior for all nodes, any
 //!     node with this implementation will not be able to communicate correctly
 //!     with non-XMAC-wrapped radios.
+//!   * The interval between wake-ups adapts to the traffic received and sent:
+//!     it is halved, down to a quarter of `SLEEP_TIME_MS`, while frames keep
+//!     coming, and doubled back when the node is idle. Wake-ups stay aligned
+//!     with the longest interval, so a node always wakes up once every
+//!     `SLEEP_TIME_MS`.
+//!   * As in ContikiMAC, the time at which each neighbor acknowledged a
+//!     preamble is remembered, and later transmissions to that neighbor wait
+//!     until just before its next wake-up to send their preambles, which
+//!     shortens the preamble trains. The phase is learned modulo
+//!     `SLEEP_TIME_MS`, so a neighbor which no longer wakes up at that phase
+//!     is found by a full preamble train, which updates it.
+//!   * `stats` reports the time the radio was on, and counts wake-ups,
+//!     preambles and frames, to measure the energy used.
 //!
 //! Usage
 //! -----
@@ -65,14 +78,12 @@
 //! xmac.set_transmit_client(mac_device);
 //! xmac.set_receive_client(mac_device);
 //! xmac.set_config_client(mac_device);
+//! xmac.set_energy_detect_client(mac_device);
 //! ```
 
 //
-// TODO: Test no-preamble transmission with randomized backoff, requires 3
-//       devices.
-// TODO: Modifying sleep time with traffic load to optimize energy usage.
 // TODO: Remove expectation that radios cancel pending sleeps when receiving a
-//       new packet (see line 652).
+//       new packet (see `receive` below).
 //
 // Author: Jean-Luc Watson
 // Date: Nov 21 2017
@@ -84,7 +95,7 @@ use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::radio;
 use kernel::hil::rng::{self, Rng};
-use kernel::hil::time::{self, Alarm, Ticks};
+use kernel::hil::time::{self, Alarm, Frequency, Ticks};
 use kernel::ReturnCode;
 
 // Time the radio will remain awake listening for packets before sleeping.
@@ -93,10 +104,15 @@ use kernel::ReturnCode;
 // we are very likely to pick up any incoming preambles, and is half as much
 // as the 20 ms lower bound in Buettner et al.
 const WAKE_TIME_MS: u32 = 10;
-// Time the radio will sleep between wakes. Configurable to any desired value
-// less than or equal to the max time the transmitter sends preambles before
-// abandoning the transmission.
+// Longest time between two wakes, used when there is no traffic. Configurable
+// to any desired value less than or equal to the max time the transmitter sends
+// preambles before abandoning the transmission.
 const SLEEP_TIME_MS: u32 = 250;
+// Number of times the time between wakes can be halved under heavy traffic.
+const MAX_SLEEP_LEVEL: u32 = 2;
+// Once this many frames are sent or received within `SLEEP_TIME_MS`, the time
+// between wakes is halved. It is doubled back after `SLEEP_TIME_MS` without any.
+const BUSY_FRAMES: u32 = 2;
 // Time the radio will continue to send preamble packets before aborting the
 // transmission and returning ENOACK. Should be at least as large as the maximum
 // sleep time for any node in the network.
@@ -111,6 +127,12 @@ const MAX_TX_BACKOFF_MS: u32 = 10;
 // any additional incoming packets before going to sleep.
 const MAX_RX_SLEEP_DELAY_MS: u32 = MAX_TX_BACKOFF_MS;
 
+// Number of neighbors whose wake-up phase is remembered.
+const MAX_NEIGHBORS: usize = 8;
+// Time before the expected wake of a neighbor at which preambles start being
+// sent to it, which covers the time between its wake and its acknowledgement.
+const PHASE_GUARD_MS: u32 = WAKE_TIME_MS;
+
 #[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq)]
 enum XMacState {
@@ -124,6 +146,34 @@ enum XMacState {
     TX_PREAMBLE, // Transmitting preambles and waiting for an ACK
     TX,          // Transmitting data packet to the destination node
     TX_DELAY,    // Backing off to send data directly without preamble
+    TX_WAIT,     // Waiting for the destination node to wake up
+}
+
+/// Duty cycle statistics, see [XMac::stats](struct.XMac.html#method.stats).
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DutyCycleStats {
+    /// Time the radio was on, in milliseconds.
+    pub radio_on_ms: u32,
+    /// Time since the statistics were reset, in milliseconds.
+    pub elapsed_ms: u32,
+    /// Scheduled wakes to listen for preambles.
+    pub wakeups: u32,
+    pub preambles_sent: u32,
+    pub frames_sent: u32,
+    pub frames_received: u32,
+    /// Transmissions which waited for the learned wake-up phase of their
+    /// destination instead of sending preambles right away.
+    pub phase_waits: u32,
+    /// Current time between wakes, in milliseconds.
+    pub sleep_interval_ms: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Neighbor<T: Ticks> {
+    addr: MacAddress,
+    // When the neighbor acknowledged a preamble, which it does soon after
+    // waking up.
+    wake: T,
 }
 
 // Information extracted for each packet from the data buffer provided to
@@ -162,6 +212,21 @@ pub struct XMac<'a, R: radio::Radio, A: Alarm<'a>> {
     tx_preamble_buf: TakeCell<'static, [u8]>,
 
     rx_pending: Cell<bool>,
+
+    // Wakes are scheduled every `SLEEP_TIME_MS >> sleep_level` after
+    // `cycle_start`, the last wake at the longest interval.
+    sleep_level: Cell<u32>,
+    cycle_start: Cell<A::Ticks>,
+    // Frames sent and received since `cycle_start`.
+    traffic: Cell<u32>,
+    neighbors: Cell<[Option<Neighbor<A::Ticks>>; MAX_NEIGHBORS]>,
+    next_neighbor: Cell<usize>,
+
+    radio_on: Cell<bool>,
+    last_update: Cell<A::Ticks>,
+    radio_on_ticks: Cell<u64>,
+    elapsed_ticks: Cell<u64>,
+    stats: Cell<DutyCycleStats>,
 }
 
 impl<'a, R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
@@ -181,13 +246,177 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
             tx_preamble_seq_num: Cell::new(0),
             tx_preamble_buf: TakeCell::empty(),
             rx_pending: Cell::new(false),
+            sleep_level: Cell::new(0),
+            cycle_start: Cell::new(A::Ticks::from(0)),
+            traffic: Cell::new(0),
+            neighbors: Cell::new([None; MAX_NEIGHBORS]),
+            next_neighbor: Cell::new(0),
+            radio_on: Cell::new(false),
+            last_update: Cell::new(A::Ticks::from(0)),
+            radio_on_ticks: Cell::new(0),
+            elapsed_ticks: Cell::new(0),
+            stats: Cell::new(DutyCycleStats::default()),
+        }
+    }
+
+    /// Duty cycle statistics since the last call to `reset_stats`.
+    pub fn stats(&self) -> DutyCycleStats {
+        self.update_radio_time();
+        let to_ms = |ticks: u64| (ticks * 1000 / A::Frequency::frequency() as u64) as u32;
+        DutyCycleStats {
+            radio_on_ms: to_ms(self.radio_on_ticks.get()),
+            elapsed_ms: to_ms(self.elapsed_ticks.get()),
+            sleep_interval_ms: SLEEP_TIME_MS >> self.sleep_level.get(),
+            ..self.stats.get()
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.update_radio_time();
+        self.radio_on_ticks.set(0);
+        self.elapsed_ticks.set(0);
+        self.stats.set(DutyCycleStats::default());
+    }
+
+    fn count(&self, counter: fn(&mut DutyCycleStats) -> &mut u32) {
+        let mut stats = self.stats.get();
+        let value = counter(&mut stats);
+        *value = value.wrapping_add(1);
+        self.stats.set(stats);
+    }
+
+    // Accounts for the time since the last update. This must happen more
+    // often than the alarm wraps around, which the wakes ensure.
+    fn update_radio_time(&self) {
+        let now = self.alarm.now();
+        let elapsed = now.wrapping_sub(self.last_update.get()).into_u32() as u64;
+        self.last_update.set(now);
+        self.elapsed_ticks.set(self.elapsed_ticks.get() + elapsed);
+        if self.radio_on.get() {
+            self.radio_on_ticks.set(self.radio_on_ticks.get() + elapsed);
+        }
+    }
+
+    fn set_radio_on(&self, on: bool) {
+        self.update_radio_time();
+        self.radio_on.set(on);
+    }
+
+    fn stop_radio(&self) {
+        self.radio.stop();
+        self.set_radio_on(false);
+    }
+
+    // Moves `cycle_start` to the last wake at the longest interval, adapting
+    // the time between wakes to the traffic seen since the previous one.
+    fn update_cycle(&self) {
+        let period = A::ticks_from_ms(SLEEP_TIME_MS);
+        let elapsed = self.alarm.now().wrapping_sub(self.cycle_start.get());
+        if elapsed < period {
+            return;
+        }
+        let cycles = elapsed.into_u32() / period.into_u32();
+        self.cycle_start.set(
+            self.cycle_start
+                .get()
+                .wrapping_add(A::Ticks::from(cycles * period.into_u32())),
+        );
+
+        let traffic = self.traffic.replace(0);
+        let level = self.sleep_level.get();
+        if traffic >= BUSY_FRAMES && level < MAX_SLEEP_LEVEL {
+            self.sleep_level.set(level + 1);
+        } else if traffic == 0 && level > 0 {
+            self.sleep_level.set(level - 1);
         }
     }
 
-    fn sleep_time(&self) -> u32 {
-        // TODO (ongoing) modify based on traffic load to efficiently schedule
-        // sleep. Currently sleeps for a constant amount of time.
-        SLEEP_TIME_MS
+    // Time from now until the next wake, which is aligned with `cycle_start`.
+    fn sleep_time(&self) -> A::Ticks {
+        self.update_cycle();
+        let interval = A::ticks_from_ms(SLEEP_TIME_MS).into_u32() >> self.sleep_level.get();
+        let elapsed = self
+            .alarm
+            .now()
+            .wrapping_sub(self.cycle_start.get())
+            .into_u32();
+        A::Ticks::from((elapsed / interval + 1) * interval - elapsed)
+    }
+
+    fn add_traffic(&self) {
+        self.traffic.set(self.traffic.get() + 1);
+    }
+
+    fn is_unicast(addr: MacAddress) -> bool {
+        addr != MacAddress::Short(0xffff)
+    }
+
+    // Remembers that `addr` acknowledged a preamble now.
+    fn learn_phase(&self, addr: MacAddress) {
+        let mut neighbors = self.neighbors.get();
+        let index = neighbors
+            .iter()
+            .position(|n| n.map_or(false, |n| n.addr == addr))
+            .or_else(|| neighbors.iter().position(|n| n.is_none()))
+            .unwrap_or_else(|| {
+                // Forget the neighbors in turn when the table is full
+                let index = self.next_neighbor.get();
+                self.next_neighbor.set((index + 1) % MAX_NEIGHBORS);
+                index
+            });
+        neighbors[index] = Some(Neighbor {
+            addr: addr,
+            wake: self.alarm.now(),
+        });
+        self.neighbors.set(neighbors);
+    }
+
+    fn forget_phase(&self, addr: MacAddress) {
+        let mut neighbors = self.neighbors.get();
+        for neighbor in neighbors.iter_mut() {
+            if neighbor.map_or(false, |n| n.addr == addr) {
+                *neighbor = None;
+            }
+        }
+        self.neighbors.set(neighbors);
+    }
+
+    // Time from now until preambles should start being sent to `addr`, if
+    // its wake-up phase is known.
+    fn wait_for_phase(&self, addr: MacAddress) -> Option<A::Ticks> {
+        let period = A::ticks_from_ms(SLEEP_TIME_MS).into_u32();
+        let guard = A::ticks_from_ms(PHASE_GUARD_MS).into_u32();
+        let wake = self
+            .neighbors
+            .get()
+            .iter()
+            .filter_map(|n| *n)
+            .find(|n| n.addr == addr)?
+            .wake;
+        let since_wake = self.alarm.now().wrapping_sub(wake).into_u32() % period;
+        let until_wake = period - since_wake;
+        if until_wake > guard {
+            Some(A::Ticks::from(until_wake - guard))
+        } else {
+            None
+        }
+    }
+
+    // Starts sending preambles, waking up the radio first if it is asleep.
+    fn start_preambles(&self) {
+        // If the radio is on, start the preamble timer and start transmitting
+        if self.radio.is_on() {
+            self.state.set(XMacState::TX_PREAMBLE);
+            self.set_timer_ms(PREAMBLE_TX_MS);
+            self.transmit_preamble();
+
+        // If the radio is currently sleeping, wake it and indicate that when
+        // ready, it should begin transmitting preambles
+        } else {
+            self.state.set(XMacState::STARTUP);
+            self.tx_preamble_pending.set(true);
+            self.radio.start();
+        }
     }
 
     fn sleep(&self) {
@@ -200,9 +429,9 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
 
             // Otherwise, don't sleep if expecting a data packet or transmitting
             } else if !self.rx_pending.get() {
-                self.radio.stop();
+                self.stop_radio();
                 self.state.set(XMacState::SLEEP);
-                self.set_timer_ms(self.sleep_time());
+                self.set_timer(self.sleep_time());
             }
         }
     }
@@ -253,6 +482,9 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
                 // If we can successfully encode the preamble, transmit.
                 Some((data_offset, _)) => {
                     result = self.radio.transmit(buf, data_offset + radio::PSDU_OFFSET);
+                    if result.0 == ReturnCode::SUCCESS {
+                        self.count(|stats| &mut stats.preambles_sent);
+                    }
                 }
                 None => {
                     self.tx_preamble_buf.replace(buf);
@@ -329,7 +561,7 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> rng::Client for XMac<'a, R, A> {
                     // the callback and randomly determine the remaining time
                     // spent backing off.
                     let ticks_remaining = self.alarm.get_alarm().wrapping_sub(self.alarm.now());
-                    let backoff = A::Ticks::from(ticks_remaining.into_u32() % random);
+                    let backoff = A::Ticks::from(random % (ticks_remaining.into_u32() + 1));
                     self.set_timer(backoff);
                 }
                 rng::Continue::Done
@@ -344,13 +576,16 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
     fn initialize(&self, mac_buf: &'static mut [u8]) -> ReturnCode {
         self.tx_preamble_buf.replace(mac_buf);
         self.state.set(XMacState::STARTUP);
+        let now = self.alarm.now();
+        self.cycle_start.set(now);
+        self.last_update.set(now);
         ReturnCode::SUCCESS
     }
 
     // Always lie and say the radio is on when sleeping, as XMAC will wake up
     // itself to send preambles if necessary.
     fn is_on(&self) -> bool {
-        if self.state.get() == XMacState::SLEEP {
+        if let XMacState::SLEEP | XMacState::TX_WAIT = self.state.get() {
             return true;
         }
         self.radio.is_on()
@@ -384,10 +619,30 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
         self.radio.get_pan()
     }
 
+    fn get_channel(&self) -> u8 {
+        self.radio.get_channel()
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        self.radio.set_channel(chan)
+    }
+
     fn config_commit(&self) {
         self.radio.config_commit()
     }
 
+    // Energy can only be measured while the radio is awake.
+    fn energy_detect(&self) -> ReturnCode {
+        if !self.radio.is_on() {
+            return ReturnCode::EOFF;
+        }
+        self.radio.energy_detect()
+    }
+
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient) {
+        self.radio.set_energy_detect_client(client)
+    }
+
     fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
         self.tx_client.set(client);
     }
@@ -440,19 +695,31 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
         }
 
         self.tx_preamble_seq_num.set(0);
+        self.add_traffic();
 
-        // If the radio is on, start the preamble timer and start transmitting
-        if self.radio.is_on() {
-            self.state.set(XMacState::TX_PREAMBLE);
-            self.set_timer_ms(PREAMBLE_TX_MS);
-            self.transmit_preamble();
-
-        // If the radio is currently sleeping, wake it and indicate that when
-        // ready, it should begin transmitting preambles
-        } else {
-            self.state.set(XMacState::STARTUP);
-            self.tx_preamble_pending.set(true);
-            self.radio.start();
+        // If the destination's wake-up phase is known, sleep until it is about
+        // to wake up, unless we are waiting for data ourselves.
+        let can_wait = match self.state.get() {
+            XMacState::SLEEP => true,
+            XMacState::AWAKE => !self.rx_pending.get() && !self.delay_sleep.get(),
+            _ => false,
+        };
+        let wait = self
+            .tx_header
+            .get()
+            .and_then(|hdr| hdr.dst_addr)
+            .filter(|addr| can_wait && Self::is_unicast(*addr))
+            .and_then(|addr| self.wait_for_phase(addr));
+        match wait {
+            Some(wait) => {
+                if self.radio.is_on() {
+                    self.stop_radio();
+                }
+                self.count(|stats| &mut stats.phase_waits);
+                self.state.set(XMacState::TX_WAIT);
+                self.set_timer(wait);
+            }
+            None => self.start_preambles(),
         }
 
         (ReturnCode::SUCCESS, None)
@@ -463,10 +730,13 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
 // indicates the next state/action to take.
 impl<'a, R: radio::Radio, A: Alarm<'a>> time::AlarmClient for XMac<'a, R, A> {
     fn alarm(&self) {
+        self.update_radio_time();
         match self.state.get() {
             XMacState::SLEEP => {
                 // If asleep, start the radio and wait for the PowerClient to
                 // indicate that the radio is ready
+                self.update_cycle();
+                self.count(|stats| &mut stats.wakeups);
                 if !self.radio.is_on() {
                     self.state.set(XMacState::STARTUP);
                     self.radio.start();
@@ -489,6 +759,9 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> time::AlarmClient for XMac<'a, R, A> {
             // any node in the network, then our destination is non-responsive;
             // return ENOACK to the client.
             XMacState::TX_PREAMBLE => {
+                if let Some(dst_addr) = self.tx_header.get().and_then(|hdr| hdr.dst_addr) {
+                    self.forget_phase(dst_addr);
+                }
                 self.call_tx_client(self.tx_payload.take().unwrap(), false, ReturnCode::ENOACK);
             }
             // After a randomized backoff period, transmit the data directly.
@@ -496,6 +769,10 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> time::AlarmClient for XMac<'a, R, A> {
                 self.state.set(XMacState::TX);
                 self.transmit_packet();
             }
+            // The destination is about to wake up
+            XMacState::TX_WAIT => {
+                self.start_preambles();
+            }
             _ => {}
         }
     }
@@ -507,6 +784,7 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> radio::PowerClient for XMac<'a, R, A> {
         // listening for incoming preambles or start transmitting preambles if
         // the radio was turned on for a transmission.
         if on {
+            self.set_radio_on(true);
             if let XMacState::STARTUP = self.state.get() {
                 if self.tx_preamble_pending.get() {
                     self.tx_preamble_pending.set(false);
@@ -527,13 +805,20 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> radio::TxClient for XMac<'a, R, A> {
         match self.state.get() {
             // Completed a data transmission to the destination node
             XMacState::TX => {
+                if result == ReturnCode::SUCCESS {
+                    self.count(|stats| &mut stats.frames_sent);
+                }
                 self.call_tx_client(buf, acked, result);
             }
             // Completed a preamble transmission
             XMacState::TX_PREAMBLE => {
                 self.tx_preamble_buf.replace(buf);
                 if acked {
-                    // Destination signals ready to receive data
+                    // Destination signals ready to receive data, which it
+                    // does soon after each wake
+                    if let Some(dst_addr) = self.tx_header.get().and_then(|hdr| hdr.dst_addr) {
+                        self.learn_phase(dst_addr);
+                    }
                     self.state.set(XMacState::TX);
                     self.transmit_packet();
                 } else {
@@ -622,6 +907,8 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
 
         if data_received {
             self.rx_pending.set(false);
+            self.add_traffic();
+            self.count(|stats| &mut stats.frames_received);
             self.call_rx_client(buf, frame_len, crc_valid, result);
         } else {
             self.radio.set_receive_buffer(buf);
@@ -635,3 +922,391 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
         }
     }
 }
+
+// Runs X-MAC against a simulated radio and a neighbor with a fixed wake-up
+// schedule, to measure preamble trains and radio-on time on the host. Time is
+// in milliseconds.
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::net::ieee802154::PanID;
+    use kernel::hil::radio::{PowerClient, RadioConfig, RadioData, RxClient, TxClient};
+    use kernel::hil::rng::Client;
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks32};
+    use std::boxed::Box;
+
+    const PAN: PanID = 0xabcd;
+    const NODE: u16 = 1;
+    const NEIGHBOR: u16 = 2;
+    // Time to send a frame, including waiting for its acknowledgement
+    const AIRTIME_MS: u32 = 2;
+    // Time after waking at which the neighbor acknowledges preambles
+    const NEIGHBOR_READY_MS: u32 = 4;
+
+    fn leak_buf() -> &'static mut [u8] {
+        Box::leak(std::vec![0; radio::MAX_BUF_SIZE].into_boxed_slice())
+    }
+
+    // Encodes a frame in `buf` and returns the length to pass to `transmit`.
+    fn encode(buf: &mut [u8], frame_type: FrameType, src: u16, dst: u16) -> usize {
+        let header = Header {
+            frame_type,
+            frame_pending: false,
+            ack_requested: true,
+            version: FrameVersion::V2006,
+            seq: Some(0),
+            dst_pan: Some(PAN),
+            dst_addr: Some(MacAddress::Short(dst)),
+            src_pan: Some(PAN),
+            src_addr: Some(MacAddress::Short(src)),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        let (len, _) = header
+            .encode(&mut buf[radio::PSDU_OFFSET..], true)
+            .done()
+            .unwrap();
+        radio::PSDU_OFFSET + len + 20
+    }
+
+    struct SimAlarm {
+        now: Cell<u32>,
+        alarm: Cell<Option<u32>>,
+    }
+
+    impl time::Time for SimAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            self.now.get().into()
+        }
+    }
+
+    impl<'a> Alarm<'a> for SimAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.alarm.get().unwrap_or(0).into()
+        }
+
+        fn disarm(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+
+        fn is_armed(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    struct SimRadio {
+        on: Cell<bool>,
+        starting: Cell<bool>,
+        tx: TakeCell<'static, [u8]>,
+    }
+
+    impl RadioConfig for SimRadio {
+        fn initialize(
+            &self,
+            _: &'static mut [u8],
+            _: &'static mut [u8],
+            _: &'static mut [u8],
+        ) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn reset(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn start(&self) -> ReturnCode {
+            self.starting.set(true);
+            ReturnCode::SUCCESS
+        }
+        fn stop(&self) -> ReturnCode {
+            self.on.set(false);
+            ReturnCode::SUCCESS
+        }
+        fn is_on(&self) -> bool {
+            self.on.get()
+        }
+        fn busy(&self) -> bool {
+            self.tx.is_some()
+        }
+        fn set_power_client(&self, _: &'static dyn PowerClient) {}
+        fn config_commit(&self) {}
+        fn set_config_client(&self, _: &'static dyn radio::ConfigClient) {}
+        fn get_address(&self) -> u16 {
+            NODE
+        }
+        fn get_address_long(&self) -> [u8; 8] {
+            [0; 8]
+        }
+        fn get_pan(&self) -> u16 {
+            PAN
+        }
+        fn get_tx_power(&self) -> i8 {
+            0
+        }
+        fn get_channel(&self) -> u8 {
+            26
+        }
+        fn set_address(&self, _: u16) {}
+        fn set_address_long(&self, _: [u8; 8]) {}
+        fn set_pan(&self, _: u16) {}
+        fn set_tx_power(&self, _: i8) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn set_channel(&self, _: u8) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn energy_detect(&self) -> ReturnCode {
+            ReturnCode::ENOSUPPORT
+        }
+        fn set_energy_detect_client(&self, _: &'static dyn radio::EnergyDetectClient) {}
+    }
+
+    impl RadioData for SimRadio {
+        fn set_transmit_client(&self, _: &'static dyn TxClient) {}
+        fn set_receive_client(&self, _: &'static dyn RxClient, _: &'static mut [u8]) {}
+        fn set_receive_buffer(&self, _: &'static mut [u8]) {}
+        fn transmit(
+            &self,
+            buf: &'static mut [u8],
+            _frame_len: usize,
+        ) -> (ReturnCode, Option<&'static mut [u8]>) {
+            self.tx.replace(buf);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    impl radio::Radio for SimRadio {}
+
+    #[derive(Default)]
+    struct SimRng {
+        requested: Cell<bool>,
+    }
+
+    impl<'a> Rng<'a> for SimRng {
+        fn get(&self) -> ReturnCode {
+            self.requested.set(true);
+            ReturnCode::SUCCESS
+        }
+        fn cancel(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn set_client(&'a self, _: &'a dyn rng::Client) {}
+    }
+
+    struct SimClient {
+        sent: Cell<Option<(bool, ReturnCode)>>,
+        buf: TakeCell<'static, [u8]>,
+        received: Cell<u32>,
+    }
+
+    impl TxClient for SimClient {
+        fn send_done(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
+            self.buf.replace(buf);
+            self.sent.set(Some((acked, result)));
+        }
+    }
+
+    impl RxClient for SimClient {
+        fn receive(&self, _: &'static mut [u8], _: usize, _: bool, _: ReturnCode) {
+            self.received.set(self.received.get() + 1);
+        }
+    }
+
+    struct Sim {
+        alarm: &'static SimAlarm,
+        radio: &'static SimRadio,
+        rng: &'static SimRng,
+        client: &'static SimClient,
+        xmac: &'static XMac<'static, SimRadio, SimAlarm>,
+        // The neighbor wakes at this time plus multiples of `SLEEP_TIME_MS`.
+        neighbor_phase: u32,
+        // Frames the neighbor will send us when it finds us awake.
+        incoming: Cell<u32>,
+    }
+
+    impl Sim {
+        fn new(neighbor_phase: u32) -> Sim {
+            let alarm: &'static SimAlarm = Box::leak(Box::new(SimAlarm {
+                now: Cell::new(0),
+                alarm: Cell::new(None),
+            }));
+            let radio: &'static SimRadio = Box::leak(Box::new(SimRadio {
+                on: Cell::new(false),
+                starting: Cell::new(false),
+                tx: TakeCell::empty(),
+            }));
+            let rng: &'static SimRng = Box::leak(Box::new(SimRng::default()));
+            let client: &'static SimClient = Box::leak(Box::new(SimClient {
+                sent: Cell::new(None),
+                buf: TakeCell::new(leak_buf()),
+                received: Cell::new(0),
+            }));
+            let xmac = Box::leak(Box::new(XMac::new(radio, alarm, rng)));
+            xmac.set_transmit_client(client);
+            xmac.set_receive_client(client);
+            xmac.initialize(leak_buf());
+            radio.start();
+            Sim {
+                alarm,
+                radio,
+                rng,
+                client,
+                xmac,
+                neighbor_phase,
+                incoming: Cell::new(0),
+            }
+        }
+
+        fn now(&self) -> u32 {
+            self.alarm.now.get()
+        }
+
+        fn neighbor_awake(&self) -> bool {
+            let since_wake = (self.now() + SLEEP_TIME_MS - self.neighbor_phase) % SLEEP_TIME_MS;
+            (NEIGHBOR_READY_MS..WAKE_TIME_MS).contains(&since_wake)
+        }
+
+        fn receive(&self, frame_type: FrameType, src: u16, dst: u16) {
+            let buf = leak_buf();
+            let len = encode(buf, frame_type, src, dst);
+            self.alarm.now.set(self.now() + AIRTIME_MS);
+            self.xmac.receive(buf, len, true, ReturnCode::SUCCESS);
+        }
+
+        fn run_until(&self, end: u32) {
+            while self.now() < end {
+                if self.radio.starting.replace(false) {
+                    self.radio.on.set(true);
+                    self.xmac.changed(true);
+                } else if let Some(buf) = self.radio.tx.take() {
+                    self.alarm.now.set(self.now() + AIRTIME_MS);
+                    let acked = match Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
+                        Some((_, (header, _))) => {
+                            header.dst_addr == Some(MacAddress::Short(NEIGHBOR))
+                                && (header.frame_type == FrameType::Data || self.neighbor_awake())
+                        }
+                        None => false,
+                    };
+                    self.xmac.send_done(buf, acked, ReturnCode::SUCCESS);
+                } else if self.rng.requested.replace(false) {
+                    self.xmac
+                        .randomness_available(&mut core::iter::once(7), ReturnCode::SUCCESS);
+                } else if self.radio.on.get() && self.incoming.get() > 0 {
+                    self.incoming.set(self.incoming.get() - 1);
+                    self.receive(FrameType::Multipurpose, NEIGHBOR, NODE);
+                    self.receive(FrameType::Data, NEIGHBOR, NODE);
+                } else {
+                    match self.alarm.alarm.get() {
+                        Some(at) if at <= end => {
+                            self.alarm.now.set(self.now().max(at));
+                            self.alarm.alarm.set(None);
+                            self.xmac.alarm();
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            self.alarm.now.set(self.now().max(end));
+        }
+
+        // Sends a frame to the neighbor at `start`, and returns when it was
+        // acknowledged.
+        fn send(&self, start: u32) -> u32 {
+            self.run_until(start);
+            let buf = self.client.buf.take().unwrap();
+            let len = encode(buf, FrameType::Data, NODE, NEIGHBOR);
+            assert_eq!(self.xmac.transmit(buf, len).0, ReturnCode::SUCCESS);
+            while self.client.sent.get().is_none() {
+                self.run_until(self.now() + 1);
+            }
+            let (acked, result) = self.client.sent.take().unwrap();
+            assert!(acked && result == ReturnCode::SUCCESS);
+            self.now()
+        }
+    }
+
+    #[test]
+    fn phase_learning_shortens_preambles() {
+        let sim = Sim::new(180);
+        sim.run_until(1000);
+        sim.xmac.reset_stats();
+
+        // The first transmission sends preambles until the neighbor wakes
+        let done = sim.send(1000);
+        assert!(done > 1180 && done < 1180 + WAKE_TIME_MS + AIRTIME_MS);
+        let first = sim.xmac.stats();
+        assert!(first.preambles_sent > 80);
+
+        // The next ones wait for its wake instead
+        sim.run_until(2000);
+        sim.xmac.reset_stats();
+        let done = sim.send(2037);
+        assert!(done > 2180 && done < 2180 + WAKE_TIME_MS + AIRTIME_MS);
+        let second = sim.xmac.stats();
+        assert!(second.preambles_sent <= 6);
+        assert_eq!((second.phase_waits, second.frames_sent), (1, 1));
+        assert!(second.radio_on_ms * 4 < first.radio_on_ms);
+    }
+
+    #[test]
+    fn sleep_interval_follows_traffic() {
+        let sim = Sim::new(0);
+        sim.run_until(2000);
+        let idle = sim.xmac.stats();
+        assert_eq!(idle.sleep_interval_ms, SLEEP_TIME_MS);
+        assert!(idle.wakeups >= 7);
+        // The radio is on for about `WAKE_TIME_MS` every `SLEEP_TIME_MS`
+        assert!(idle.radio_on_ms * 100 / idle.elapsed_ms <= 5);
+
+        // Wakes become more frequent while frames keep coming
+        for cycle in 0..4 {
+            sim.incoming.set(3);
+            sim.run_until(2000 + (cycle + 1) * SLEEP_TIME_MS);
+        }
+        let busy = sim.xmac.stats();
+        assert_eq!(busy.sleep_interval_ms, SLEEP_TIME_MS >> MAX_SLEEP_LEVEL);
+        assert_eq!(sim.client.received.get(), 12);
+
+        // And less frequent again once it stops
+        sim.run_until(5000);
+        assert_eq!(sim.xmac.stats().sleep_interval_ms, SLEEP_TIME_MS);
+    }
+
+    #[test]
+    fn backoff_without_preambles() {
+        let sim = Sim::new(180);
+        sim.run_until(1000);
+        let buf = sim.client.buf.take().unwrap();
+        let len = encode(buf, FrameType::Data, NODE, NEIGHBOR);
+        sim.xmac.transmit(buf, len);
+        sim.run_until(1050);
+
+        // Another node sends data to the neighbor, which is therefore awake,
+        // so the frame is sent after a random backoff without preambles
+        let preambles = sim.xmac.stats().preambles_sent;
+        sim.receive(FrameType::Data, 3, NEIGHBOR);
+        while sim.client.sent.get().is_none() {
+            sim.run_until(sim.now() + 1);
+        }
+        assert!(sim.now() <= 1052 + MAX_TX_BACKOFF_MS + AIRTIME_MS);
+        assert_eq!(sim.client.sent.get(), Some((true, ReturnCode::SUCCESS)));
+        assert!(sim.xmac.stats().preambles_sent <= preambles + 1);
+    }
+}
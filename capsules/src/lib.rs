@@ -9,6 +9,8 @@ pub mod net;
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod aes;
+pub mod aes_gcm;
 pub mod alarm;
 pub mod ambient_light;
 pub mod analog_comparator;
@@ -19,18 +21,24 @@ pub mod ble_advertising_driver;
 pub mod bus;
 pub mod button;
 pub mod buzzer_driver;
+pub mod can;
+pub mod can_loopback;
 pub mod console;
 pub mod crc;
 pub mod ctap;
+pub mod ctaphid;
 pub mod dac;
 pub mod debug_process_restart;
+pub mod drbg;
 pub mod driver;
+pub mod fido2;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
 pub mod gpio;
 pub mod gpio_async;
 pub mod hd44780;
+pub mod hid_driver;
 pub mod hmac;
 pub mod humidity;
 pub mod i2c_master;
@@ -59,12 +67,15 @@ pub mod panic_button;
 pub mod pca9544a;
 pub mod process_console;
 pub mod proximity;
+pub mod public_key_crypto;
+pub mod pwm;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sha;
 pub mod sht3x;
 pub mod si7021;
 pub mod sound_pressure;
@@ -78,8 +89,10 @@ pub mod touch;
 pub mod tsl2561;
 pub mod usb;
 pub mod virtual_adc;
+pub mod virtual_aes;
 pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
+pub mod virtual_can;
 pub mod virtual_digest;
 pub mod virtual_flash;
 pub mod virtual_hmac;
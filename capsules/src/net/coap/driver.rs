@@ -0,0 +1,587 @@
+//! CoAP userspace interface.
+//!
+//! Lets processes send CoAP requests and serve resources through the kernel
+//! CoAP endpoint, which handles message IDs, retransmissions, duplicates,
+//! block-wise transfers and observations. A single request is outstanding
+//! at a time across all processes, and a single observation is followed.
+//!
+//! A process registers its resources under a path, each backed by a buffer
+//! holding the representation served to GET requests. Bodies of PUT, POST
+//! and DELETE requests are delivered to the process block by block, and are
+//! answered with 2.04 (Changed) or 2.02 (Deleted). After changing a
+//! representation, the process tells the driver so that observers are
+//! notified.
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: The payload of the next request.
+//! - `1`: The buffer receiving responses, notifications and the bodies of
+//!   requests for the resources of the process.
+//! - `2`: The 16 byte IPv6 address of the server.
+//! - `3`: The path of the next request, or of the resource to register,
+//!   with segments separated by `/`.
+//! - `16 + n`: The representation of resource `n` of the process, for `n`
+//!   below `RESOURCES_PER_APP`.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called for each block of a response and for notifications. The
+//!   first argument holds the response code in its lower byte, bit 8 is set
+//!   for notifications and bit 9 if more blocks follow. The second argument
+//!   is the offset of the block, and the third the number of bytes copied
+//!   to the receive buffer.
+//! - `1`: Called once the request completes, with `SUCCESS`, `ENOACK` if the
+//!   server did not answer or `FAIL` if it reset the request.
+//! - `2`: Called for each block written to a resource of the process. The
+//!   first argument holds the resource number in its lower byte, the method
+//!   code in the next and bit 16 is set if more blocks follow. The second
+//!   argument is the offset of the block, and the third the number of bytes
+//!   copied to the receive buffer.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Send a request.
+//!   - `arg1`: The method code in the lower byte, bit 8 for a confirmable
+//!     request and bit 9 to observe the resource.
+//!   - `arg2`: The server port, or `0` for the CoAP port.
+//!   - Return: `SUCCESS` if the request was sent, `EINVAL` if no valid
+//!     address, path or method was given, `ESIZE` if the request does not
+//!     fit in a message and `EBUSY` if a request is already outstanding.
+//! - `2`: Cancel the observation made by the process.
+//! - `3`: Register resource `arg1` under the allowed path, observable if
+//!   `arg2` is `1`. Returns `EINVAL` if the number or path is invalid,
+//!   `EALREADY` if the number or path is taken and `ENOMEM` if the resource
+//!   table is full.
+//! - `4`: Unregister resource `arg1`.
+//! - `5`: Notify the observers of resource `arg1` that it changed.
+
+use crate::net::coap::endpoint::{CoapClient, CoapEndpoint, CoapResources, COAP_PORT};
+use crate::net::coap::message::{code, Message};
+use crate::net::ipv6::ip_utils::IPAddr;
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+pub const RESOURCES_PER_APP: usize = 4;
+pub const MAX_RESOURCES: usize = 8;
+pub const MAX_PATH_LEN: usize = 32;
+
+/// Allow number of the representation of the first resource.
+const REPRESENTATION_ALLOW: usize = 16;
+
+#[derive(Default)]
+pub struct App {
+    response_callback: Option<Callback>,
+    done_callback: Option<Callback>,
+    write_callback: Option<Callback>,
+    payload: Option<AppSlice<Shared, u8>>,
+    rx: Option<AppSlice<Shared, u8>>,
+    dst: Option<AppSlice<Shared, u8>>,
+    path: Option<AppSlice<Shared, u8>>,
+    representations: [Option<AppSlice<Shared, u8>>; RESOURCES_PER_APP],
+}
+
+#[derive(Copy, Clone)]
+struct Resource {
+    appid: AppId,
+    number: usize,
+    path: [u8; MAX_PATH_LEN],
+    path_len: usize,
+    observable: bool,
+}
+
+pub struct CoapDriver<'a, A: time::Alarm<'a>> {
+    endpoint: &'a CoapEndpoint<'a, A>,
+    apps: Grant<App>,
+    /// The process of the outstanding request.
+    requester: OptionalCell<AppId>,
+    /// The process following the observation.
+    observer: OptionalCell<AppId>,
+    resources: Cell<[Option<Resource>; MAX_RESOURCES]>,
+}
+
+impl<'a, A: time::Alarm<'a>> CoapDriver<'a, A> {
+    pub fn new(endpoint: &'a CoapEndpoint<'a, A>, grant: Grant<App>) -> CoapDriver<'a, A> {
+        CoapDriver {
+            endpoint: endpoint,
+            apps: grant,
+            requester: OptionalCell::empty(),
+            observer: OptionalCell::empty(),
+            resources: Cell::new([None; MAX_RESOURCES]),
+        }
+    }
+
+    fn send(&self, appid: AppId, flags: usize, port: usize) -> ReturnCode {
+        if self.endpoint.is_busy() {
+            return ReturnCode::EBUSY;
+        }
+        let mut path = [0; MAX_PATH_LEN];
+        // The grant is left before sending, as the endpoint reads the
+        // payload from it
+        let params = self.apps.enter(appid, |app, _| {
+            let dst = match app.dst {
+                Some(ref dst) if dst.len() == 16 => {
+                    let mut addr = IPAddr::new();
+                    addr.0.copy_from_slice(dst.as_ref());
+                    addr
+                }
+                _ => return Err(ReturnCode::EINVAL),
+            };
+            let path_len = match app.path {
+                Some(ref p) if p.len() <= MAX_PATH_LEN => {
+                    path[..p.len()].copy_from_slice(p.as_ref());
+                    p.len()
+                }
+                _ => return Err(ReturnCode::EINVAL),
+            };
+            let payload_len = app.payload.as_ref().map_or(0, |p| p.len());
+            Ok((dst, path_len, payload_len))
+        });
+        let (dst, path_len, payload_len) = match params {
+            Ok(Ok(params)) => params,
+            Ok(Err(err)) => return err,
+            Err(err) => return err.into(),
+        };
+
+        let method = (flags & 0xff) as u8;
+        let confirmable = flags & (1 << 8) != 0;
+        let observe = flags & (1 << 9) != 0;
+        let port = if port == 0 { COAP_PORT } else { port as u16 };
+        self.requester.set(appid);
+        let ret = self.endpoint.request(
+            dst,
+            port,
+            method,
+            &path[..path_len],
+            payload_len,
+            confirmable,
+            observe,
+        );
+        if ret == ReturnCode::SUCCESS {
+            if observe {
+                self.observer.set(appid);
+            }
+        } else {
+            self.requester.clear();
+        }
+        ret
+    }
+
+    fn register(&self, appid: AppId, number: usize, observable: bool) -> ReturnCode {
+        if number >= RESOURCES_PER_APP {
+            return ReturnCode::EINVAL;
+        }
+        let mut resource = Resource {
+            appid: appid,
+            number: number,
+            path: [0; MAX_PATH_LEN],
+            path_len: 0,
+            observable: observable,
+        };
+        let ret = self
+            .apps
+            .enter(appid, |app, _| match app.path {
+                Some(ref path) if path.len() <= MAX_PATH_LEN => {
+                    resource.path[..path.len()].copy_from_slice(path.as_ref());
+                    resource.path_len = path.len();
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            })
+            .unwrap_or_else(|err| err.into());
+        if ret != ReturnCode::SUCCESS {
+            return ret;
+        }
+
+        let mut resources = self.resources.get();
+        let taken = resources.iter().flatten().any(|r| {
+            (r.appid == appid && r.number == number)
+                || r.path[..r.path_len] == resource.path[..resource.path_len]
+        });
+        if taken {
+            return ReturnCode::EALREADY;
+        }
+        match resources.iter_mut().find(|r| r.is_none()) {
+            Some(slot) => {
+                *slot = Some(resource);
+                self.resources.set(resources);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// The handle of resource `number` of process `appid`.
+    fn handle(&self, appid: AppId, number: usize) -> Option<usize> {
+        self.resources
+            .get()
+            .iter()
+            .position(|r| r.map_or(false, |r| r.appid == appid && r.number == number))
+    }
+
+    fn unregister(&self, appid: AppId, number: usize) -> ReturnCode {
+        match self.handle(appid, number) {
+            Some(handle) => {
+                let mut resources = self.resources.get();
+                resources[handle] = None;
+                self.resources.set(resources);
+                self.endpoint.remove_observers(handle);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn resource(&self, handle: usize) -> Option<Resource> {
+        self.resources.get().get(handle).copied().flatten()
+    }
+
+    /// Copies `data` to the receive buffer of `appid` and schedules the
+    /// callback picked by `callback` with the number of bytes copied.
+    fn deliver<F>(&self, appid: AppId, data: &[u8], arg0: usize, arg1: usize, callback: F)
+    where
+        F: FnOnce(&mut App) -> Option<Callback>,
+    {
+        let _ = self.apps.enter(appid, |app, _| {
+            let len = app.rx.as_mut().map_or(0, |rx| {
+                let len = data.len().min(rx.len());
+                rx.as_mut()[..len].copy_from_slice(&data[..len]);
+                len
+            });
+            callback(app).map(|mut cb| cb.schedule(arg0, arg1, len));
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> CoapClient for CoapDriver<'a, A> {
+    fn response(&self, code: u8, offset: usize, payload: &[u8], more: bool) {
+        self.requester.map(|appid| {
+            let arg0 = code as usize | (more as usize) << 9;
+            self.deliver(*appid, payload, arg0, offset, |app| app.response_callback);
+        });
+    }
+
+    fn notification(&self, code: u8, payload: &[u8], more: bool) {
+        self.observer.map(|appid| {
+            let arg0 = code as usize | 1 << 8 | (more as usize) << 9;
+            self.deliver(*appid, payload, arg0, 0, |app| app.response_callback);
+        });
+    }
+
+    fn request_done(&self, result: ReturnCode) {
+        self.requester.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                app.done_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), 0, 0));
+            });
+        });
+    }
+
+    fn read_payload(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.requester.map_or(0, |appid| {
+            self.apps
+                .enter(*appid, |app, _| {
+                    app.payload.as_ref().map_or(0, |payload| {
+                        let payload = payload.as_ref().get(offset..).unwrap_or(&[]);
+                        let len = payload.len().min(buf.len());
+                        buf[..len].copy_from_slice(&payload[..len]);
+                        len
+                    })
+                })
+                .unwrap_or(0)
+        })
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> CoapResources for CoapDriver<'a, A> {
+    fn find(&self, request: &Message) -> Option<usize> {
+        self.resources.get().iter().position(|r| {
+            r.map_or(false, |r| {
+                request.path_matches(&r.path[..r.path_len])
+                    && self.apps.enter(r.appid, |_, _| ()).is_ok()
+            })
+        })
+    }
+
+    fn observable(&self, resource: usize) -> bool {
+        self.resource(resource).map_or(false, |r| r.observable)
+    }
+
+    fn read(&self, resource: usize, offset: usize, buf: &mut [u8]) -> (usize, usize) {
+        let resource = match self.resource(resource) {
+            Some(resource) => resource,
+            None => return (0, 0),
+        };
+        self.apps
+            .enter(resource.appid, |app, _| {
+                app.representations[resource.number]
+                    .as_ref()
+                    .map_or((0, 0), |representation| {
+                        let data = representation.as_ref().get(offset..).unwrap_or(&[]);
+                        let len = data.len().min(buf.len());
+                        buf[..len].copy_from_slice(&data[..len]);
+                        (len, representation.len())
+                    })
+            })
+            .unwrap_or((0, 0))
+    }
+
+    fn write(&self, resource: usize, method: u8, offset: usize, payload: &[u8], more: bool) -> u8 {
+        let resource = match self.resource(resource) {
+            Some(resource) => resource,
+            None => return code::NOT_FOUND,
+        };
+        let arg0 = resource.number | (method as usize) << 8 | (more as usize) << 16;
+        self.deliver(resource.appid, payload, arg0, offset, |app| {
+            app.write_callback
+        });
+        if method == code::DELETE {
+            code::DELETED
+        } else {
+            code::CHANGED
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for CoapDriver<'a, A> {
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.payload = slice,
+                    1 => app.rx = slice,
+                    2 => app.dst = slice,
+                    3 => app.path = slice,
+                    n if n >= REPRESENTATION_ALLOW
+                        && n < REPRESENTATION_ALLOW + RESOURCES_PER_APP =>
+                    {
+                        app.representations[n - REPRESENTATION_ALLOW] = slice
+                    }
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match subscribe_num {
+                    0 => app.response_callback = callback,
+                    1 => app.done_callback = callback,
+                    2 => app.write_callback = callback,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.send(appid, arg1, arg2),
+            2 => {
+                if self.observer.contains(&appid) {
+                    self.observer.clear();
+                    self.endpoint.cancel_observation();
+                }
+                ReturnCode::SUCCESS
+            }
+            3 => self.register(appid, arg1, arg2 == 1),
+            4 => self.unregister(appid, arg1),
+            5 => match self.handle(appid, arg1) {
+                Some(handle) => {
+                    self.endpoint.notify(handle);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            },
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::net::coap::message::{option, MessageType, OBSERVE_REGISTER};
+    use crate::net::coap::sim::{message, Sim, SimAlarm, PEER};
+    use kernel::mock::{MockKernel, MockProcess};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    fn setup() -> (
+        &'static MockKernel,
+        Sim,
+        &'static CoapDriver<'static, SimAlarm>,
+    ) {
+        let kernel = MockKernel::new(2);
+        let sim = Sim::new();
+        sim.seed();
+        let driver = Box::leak(Box::new(CoapDriver::new(
+            sim.endpoint,
+            kernel.create_grant(),
+        )));
+        sim.endpoint.set_client(driver);
+        sim.endpoint.set_resources(driver);
+        (kernel, sim, driver)
+    }
+
+    fn allow(
+        driver: &CoapDriver<'static, SimAlarm>,
+        process: &MockProcess,
+        allow_num: usize,
+        data: &[u8],
+    ) {
+        let buffer = Box::leak(data.to_vec().into_boxed_slice());
+        assert_eq!(
+            driver.allow(
+                process.appid(),
+                allow_num,
+                Some(process.allow_buffer(buffer))
+            ),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    fn subscribe(driver: &CoapDriver<'static, SimAlarm>, process: &MockProcess, num: usize) {
+        assert_eq!(
+            driver.subscribe(
+                num,
+                Some(process.callback(DRIVER_NUM, num)),
+                process.appid()
+            ),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    fn request(mtype: MessageType, method: u8, message_id: u16, body: &[u8]) -> Vec<u8> {
+        message(mtype, method, message_id, &[1], |w| {
+            w.uri_path(b"led").payload(body);
+        })
+    }
+
+    #[test]
+    fn request_completed_through_callbacks() {
+        let (kernel, sim, driver) = setup();
+        let (a, b) = (kernel.process(0), kernel.process(1));
+        for process in &[a, b] {
+            allow(driver, process, 2, &PEER.0);
+            allow(driver, process, 3, b"r");
+        }
+        allow(driver, a, 1, &[0; 16]);
+        subscribe(driver, a, 0);
+        subscribe(driver, a, 1);
+
+        let get = code::GET as usize | 1 << 8;
+        assert_eq!(driver.command(1, get, 0, a.appid()), ReturnCode::SUCCESS);
+        // One request is outstanding at a time
+        assert_eq!(driver.command(1, get, 0, b.appid()), ReturnCode::EBUSY);
+        let sent = sim.sent();
+        assert_eq!(sent.len(), 1);
+        let request = Message::decode(&sent[0]).unwrap();
+        assert_eq!(request.mtype, MessageType::Confirmable);
+        assert!(request.path_matches(b"r"));
+
+        sim.receive(&message(
+            MessageType::Acknowledgement,
+            code::CONTENT,
+            request.message_id,
+            request.token,
+            |w| {
+                w.payload(b"hello");
+            },
+        ));
+        assert_eq!(
+            a.take_callbacks(),
+            [
+                (0, code::CONTENT as usize, 0, 5),
+                (1, usize::from(ReturnCode::SUCCESS), 0, 0)
+            ]
+        );
+        assert_eq!(driver.command(1, get, 0, b.appid()), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn resources_served_while_registered() {
+        let (kernel, sim, driver) = setup();
+        let (a, b) = (kernel.process(0), kernel.process(1));
+        allow(driver, a, 1, &[0; 16]);
+        allow(driver, a, 3, b"led");
+        allow(driver, a, REPRESENTATION_ALLOW, b"on");
+        subscribe(driver, a, 2);
+        assert_eq!(driver.command(3, 0, 1, a.appid()), ReturnCode::SUCCESS);
+        allow(driver, b, 3, b"led");
+        assert_eq!(driver.command(3, 0, 0, b.appid()), ReturnCode::EALREADY);
+
+        sim.receive(&message(
+            MessageType::Confirmable,
+            code::GET,
+            1,
+            &[1],
+            |w| {
+                w.uint_option(option::OBSERVE, OBSERVE_REGISTER)
+                    .uri_path(b"led");
+            },
+        ));
+        let sent = sim.sent();
+        let response = Message::decode(&sent[0]).unwrap();
+        assert_eq!(response.code, code::CONTENT);
+        assert_eq!(response.payload, b"on");
+
+        sim.receive(&request(MessageType::Confirmable, code::PUT, 2, b"off"));
+        let sent = sim.sent();
+        assert_eq!(Message::decode(&sent[0]).unwrap().code, code::CHANGED);
+        assert_eq!(a.take_callbacks(), [(2, (code::PUT as usize) << 8, 0, 3)]);
+
+        assert_eq!(driver.command(5, 0, 0, a.appid()), ReturnCode::SUCCESS);
+        let sent = sim.sent();
+        assert_eq!(sent.len(), 1);
+        let notification = Message::decode(&sent[0]).unwrap();
+        assert_eq!(notification.uint_option(option::OBSERVE), Some(3));
+        assert_eq!(notification.payload, b"on");
+
+        // Unregistering drops the observers
+        assert_eq!(driver.command(4, 0, 0, a.appid()), ReturnCode::SUCCESS);
+        assert_eq!(driver.command(5, 0, 0, a.appid()), ReturnCode::EINVAL);
+        sim.receive(&request(MessageType::Confirmable, code::GET, 3, b""));
+        let sent = sim.sent();
+        assert_eq!(Message::decode(&sent[0]).unwrap().code, code::NOT_FOUND);
+    }
+
+    #[test]
+    fn resources_of_exited_processes_not_found() {
+        let (kernel, sim, driver) = setup();
+        let a = kernel.process(0);
+        allow(driver, a, 3, b"led");
+        allow(driver, a, REPRESENTATION_ALLOW, b"on");
+        assert_eq!(driver.command(3, 0, 0, a.appid()), ReturnCode::SUCCESS);
+
+        a.terminate();
+        sim.receive(&request(MessageType::Confirmable, code::GET, 1, b""));
+        let sent = sim.sent();
+        assert_eq!(Message::decode(&sent[0]).unwrap().code, code::NOT_FOUND);
+    }
+}
@@ -0,0 +1,1441 @@
+//! CoAP endpoint over UDP (RFC 7252).
+//!
+//! The endpoint is both a client, which sends one request at a time on
+//! behalf of a `CoapClient`, and a server, which answers the requests for
+//! the resources of a `CoapResources`.
+//!
+//! Client requests are confirmable or non-confirmable. A confirmable
+//! request is retransmitted with exponential backoff, starting from a
+//! random timeout between `ACK_TIMEOUT_MS` and 1.5 times that, until it is
+//! acknowledged or `MAX_RETRANSMIT` retransmissions went unanswered. Large
+//! request payloads are sent with Block1 and large responses fetched with
+//! Block2 (RFC 7959), block by block. A GET can register for notifications
+//! with the Observe option (RFC 7641); the endpoint follows one observation
+//! at a time, and forgets it on `cancel_observation`, after which further
+//! notifications are reset.
+//!
+//! The server answers confirmable requests with piggybacked responses. It
+//! remembers the message IDs of the last `MAX_RECENT` messages it received,
+//! ignoring duplicates; a duplicate of the request it last answered gets
+//! the same response again. Representations larger than a block are served
+//! with Block2, and request bodies are handed to `CoapResources` block by
+//! block. Up to `MAX_OBSERVERS` clients can observe resources, and are sent
+//! a non-confirmable notification when `notify` is called. An observer
+//! which resets a notification is removed.
+//!
+//! A single buffer is used for all transmissions. A server response which
+//! finds it busy is dropped, which the client recovers from by
+//! retransmitting, while client requests and notifications wait for it.
+//!
+//! Message IDs, tokens and retransmission timeouts are drawn from a state
+//! seeded by the `Rng` given with `set_rng()`, so that they do not repeat
+//! after a reboot. Requests are refused with `EBUSY` until the seed arrives.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let coap = static_init!(
+//!     CoapEndpoint<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     CoapEndpoint::new(udp_send, coap_alarm, net_cap, &mut TX_BUF, &mut REQUEST_BUF)
+//! );
+//! udp_send.set_client(coap);
+//! udp_recv.set_client(coap);
+//! coap_alarm.set_alarm_client(coap);
+//! rng.set_client(coap);
+//! coap.set_rng(rng);
+//! coap.set_client(client);
+//! coap.set_resources(resources);
+//! ```
+
+use crate::net::coap::message::{code, option, Block, Message, MessageType, MessageWriter};
+use crate::net::coap::message::{OBSERVE_DEREGISTER, OBSERVE_REGISTER};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng;
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+pub const COAP_PORT: u16 = 5683;
+
+/// Longest message sent or cached by the endpoint.
+pub const MAX_MESSAGE_LEN: usize = 128;
+
+/// Size exponent of the blocks sent by the endpoint, for 64 byte blocks.
+pub const BLOCK_SZX: u8 = 2;
+
+pub const ACK_TIMEOUT_MS: u32 = 2000;
+pub const MAX_RETRANSMIT: u8 = 4;
+
+/// How long a response is awaited once a request was acknowledged, or sent
+/// non-confirmable.
+pub const RESPONSE_TIMEOUT_MS: u32 = 30_000;
+
+pub const MAX_OBSERVERS: usize = 4;
+pub const MAX_RECENT: usize = 8;
+
+const TOKEN_LEN: usize = 4;
+
+/// Options understood by the server. Requests carrying other critical
+/// options are rejected.
+const KNOWN_OPTIONS: [u16; 5] = [
+    option::URI_HOST,
+    option::URI_PORT,
+    option::URI_PATH,
+    option::BLOCK2,
+    option::BLOCK1,
+];
+
+/// Observe sequence numbers are 24 bits long.
+const OBSERVE_MASK: u32 = 0x00ff_ffff;
+
+/// Whether a notification numbered `new` is more recent than one numbered
+/// `old`, given that sequence numbers wrap around (RFC 7641, 3.4).
+pub fn observe_is_newer(old: u32, new: u32) -> bool {
+    let (old, new) = (old & OBSERVE_MASK, new & OBSERVE_MASK);
+    (old < new && new - old < 1 << 23) || (old > new && old - new > 1 << 23)
+}
+
+pub trait CoapClient {
+    /// A response to the outstanding request. Block-wise responses are
+    /// delivered block by block, `payload` starting at `offset` of the
+    /// representation and `more` telling whether blocks follow.
+    fn response(&self, code: u8, offset: usize, payload: &[u8], more: bool);
+
+    /// A notification of the observed resource. Only the first block of a
+    /// large representation is delivered, with `more` set.
+    fn notification(&self, code: u8, payload: &[u8], more: bool);
+
+    /// The outstanding request completed: `SUCCESS` once the last block of
+    /// the response arrived, `ENOACK` if the server did not answer and
+    /// `FAIL` if it reset the request.
+    fn request_done(&self, result: ReturnCode);
+
+    /// Copies the request payload from `offset` into `buf`, returning the
+    /// number of bytes copied.
+    fn read_payload(&self, offset: usize, buf: &mut [u8]) -> usize;
+}
+
+pub trait CoapResources {
+    /// The resource addressed by the Uri-Path of `request`.
+    fn find(&self, request: &Message) -> Option<usize>;
+
+    fn observable(&self, resource: usize) -> bool;
+
+    /// Copies the representation of `resource` from `offset` into `buf`,
+    /// which may be empty. Returns the number of bytes copied and the
+    /// length of the representation.
+    fn read(&self, resource: usize, offset: usize, buf: &mut [u8]) -> (usize, usize);
+
+    /// A PUT, POST or DELETE of `resource`, whose body holds `payload` at
+    /// `offset`, `more` telling whether blocks follow. Returns the response
+    /// code.
+    fn write(&self, resource: usize, method: u8, offset: usize, payload: &[u8], more: bool) -> u8;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ExchangeState {
+    AwaitingAck,
+    AwaitingResponse,
+}
+
+/// The outstanding client request, whose last message is in the request
+/// buffer.
+#[derive(Copy, Clone)]
+struct Exchange {
+    dst: IPAddr,
+    port: u16,
+    token: [u8; TOKEN_LEN],
+    message_id: u16,
+    request_len: usize,
+    payload_len: usize,
+    confirmable: bool,
+    observe: bool,
+    state: ExchangeState,
+    retransmissions: u8,
+    timeout_ms: u32,
+}
+
+#[derive(Copy, Clone)]
+struct Observation {
+    server: IPAddr,
+    port: u16,
+    token: [u8; TOKEN_LEN],
+    seq: Option<u32>,
+}
+
+#[derive(Copy, Clone)]
+struct Observer {
+    resource: usize,
+    addr: IPAddr,
+    port: u16,
+    token: [u8; 8],
+    token_len: usize,
+    /// ID of the last notification, which a reset refers to.
+    message_id: u16,
+}
+
+/// A message received from a peer.
+#[derive(Copy, Clone, PartialEq)]
+struct Recent {
+    addr: IPAddr,
+    port: u16,
+    message_id: u16,
+}
+
+pub struct CoapEndpoint<'a, A: time::Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    net_cap: &'static NetworkCapability,
+    tx_buf: MapCell<LeasableBuffer<'static, u8>>,
+    request_buf: TakeCell<'static, [u8]>,
+    client: OptionalCell<&'a dyn CoapClient>,
+    resources: OptionalCell<&'a dyn CoapResources>,
+    exchange: OptionalCell<Exchange>,
+    request_pending: Cell<bool>,
+    observation: OptionalCell<Observation>,
+    observers: Cell<[Option<Observer>; MAX_OBSERVERS]>,
+    notifications_pending: Cell<u8>,
+    observe_seq: Cell<u32>,
+    recent: Cell<[Option<Recent>; MAX_RECENT]>,
+    recent_next: Cell<usize>,
+    /// The message whose response is still in the transmit buffer, with
+    /// the length of the response.
+    last_response: OptionalCell<(Recent, usize)>,
+    next_message_id: Cell<u16>,
+    next_token: Cell<u32>,
+    random: Cell<u32>,
+    seeded: Cell<bool>,
+}
+
+impl<'a, A: time::Alarm<'a>> CoapEndpoint<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        net_cap: &'static NetworkCapability,
+        tx_buf: &'static mut [u8],
+        request_buf: &'static mut [u8],
+    ) -> CoapEndpoint<'a, A> {
+        CoapEndpoint {
+            sender: sender,
+            alarm: alarm,
+            net_cap: net_cap,
+            tx_buf: MapCell::new(LeasableBuffer::new(tx_buf)),
+            request_buf: TakeCell::new(request_buf),
+            client: OptionalCell::empty(),
+            resources: OptionalCell::empty(),
+            exchange: OptionalCell::empty(),
+            request_pending: Cell::new(false),
+            observation: OptionalCell::empty(),
+            observers: Cell::new([None; MAX_OBSERVERS]),
+            notifications_pending: Cell::new(0),
+            observe_seq: Cell::new(2),
+            recent: Cell::new([None; MAX_RECENT]),
+            recent_next: Cell::new(0),
+            last_response: OptionalCell::empty(),
+            next_message_id: Cell::new(0),
+            next_token: Cell::new(0),
+            random: Cell::new(1),
+            seeded: Cell::new(false),
+        }
+    }
+
+    /// Requests the seed of the message IDs, tokens and timeouts from `rng`,
+    /// whose client the endpoint must be.
+    pub fn set_rng(&self, rng: &dyn rng::Rng) {
+        rng.get();
+    }
+
+    pub fn set_client(&self, client: &'a dyn CoapClient) {
+        self.client.set(client);
+    }
+
+    pub fn set_resources(&self, resources: &'a dyn CoapResources) {
+        self.resources.set(resources);
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.exchange.is_some()
+    }
+
+    /// Sends a request with `method` for `path` on `dst`, with a payload of
+    /// `payload_len` bytes read from the client. `observe` registers for
+    /// notifications, replacing the current observation.
+    pub fn request(
+        &self,
+        dst: IPAddr,
+        port: u16,
+        method: u8,
+        path: &[u8],
+        payload_len: usize,
+        confirmable: bool,
+        observe: bool,
+    ) -> ReturnCode {
+        if self.exchange.is_some() || !self.seeded.get() {
+            return ReturnCode::EBUSY;
+        }
+        if !code::is_request(method) {
+            return ReturnCode::EINVAL;
+        }
+        let mut exchange = Exchange {
+            dst: dst,
+            port: port,
+            token: self.new_token(),
+            message_id: self.new_message_id(),
+            request_len: 0,
+            payload_len: payload_len,
+            confirmable: confirmable,
+            observe: observe,
+            state: ExchangeState::AwaitingAck,
+            retransmissions: 0,
+            timeout_ms: 0,
+        };
+        let mtype = if confirmable {
+            MessageType::Confirmable
+        } else {
+            MessageType::NonConfirmable
+        };
+        let first_block = Block {
+            num: 0,
+            more: true,
+            szx: BLOCK_SZX,
+        };
+        let block_wise = payload_len > first_block.size();
+
+        let len = self.request_buf.map_or(Err(ReturnCode::ENOMEM), |buf| {
+            let mut writer =
+                MessageWriter::new(buf, mtype, method, exchange.message_id, &exchange.token);
+            if observe {
+                writer.uint_option(option::OBSERVE, OBSERVE_REGISTER);
+            }
+            writer.uri_path(path);
+            if block_wise {
+                writer
+                    .uint_option(option::BLOCK1, first_block.encode())
+                    .uint_option(option::SIZE1, payload_len as u32);
+            }
+            self.write_payload(&mut writer, 0, first_block.size().min(payload_len));
+            writer.finish()
+        });
+        match len {
+            Ok(len) => exchange.request_len = len,
+            Err(err) => return err,
+        }
+        self.start_exchange(exchange);
+        ReturnCode::SUCCESS
+    }
+
+    /// Forgets the current observation.
+    pub fn cancel_observation(&self) {
+        self.observation.clear();
+    }
+
+    /// Notifies the observers of `resource` that its representation
+    /// changed.
+    pub fn notify(&self, resource: usize) {
+        let mut pending = self.notifications_pending.get();
+        for (i, observer) in self.observers.get().iter().enumerate() {
+            if observer.map_or(false, |o| o.resource == resource) {
+                pending |= 1 << i;
+            }
+        }
+        self.notifications_pending.set(pending);
+        self.send_pending();
+    }
+
+    /// Removes the observers of `resource`, which is going away.
+    pub fn remove_observers(&self, resource: usize) {
+        let mut observers = self.observers.get();
+        let mut pending = self.notifications_pending.get();
+        for (i, observer) in observers.iter_mut().enumerate() {
+            if observer.map_or(false, |o| o.resource == resource) {
+                *observer = None;
+                pending &= !(1 << i);
+            }
+        }
+        self.observers.set(observers);
+        self.notifications_pending.set(pending);
+    }
+
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn new_message_id(&self) -> u16 {
+        let id = self.next_message_id.get();
+        self.next_message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn new_token(&self) -> [u8; TOKEN_LEN] {
+        let token = self.next_token.get();
+        self.next_token.set(token.wrapping_add(1));
+        token.to_be_bytes()
+    }
+
+    fn get_exchange(&self) -> Option<Exchange> {
+        self.exchange.map(|exchange| *exchange)
+    }
+
+    fn write_payload(&self, writer: &mut MessageWriter, offset: usize, len: usize) {
+        if len > 0 {
+            self.client.map(|client| {
+                writer.payload_with(len, |buf| client.read_payload(offset, buf));
+            });
+        }
+    }
+
+    /// Starts the exchange whose request was just written to the request
+    /// buffer.
+    fn start_exchange(&self, mut exchange: Exchange) {
+        exchange.retransmissions = 0;
+        if exchange.confirmable {
+            exchange.state = ExchangeState::AwaitingAck;
+            exchange.timeout_ms = ACK_TIMEOUT_MS + self.next_random() % (ACK_TIMEOUT_MS / 2);
+        } else {
+            exchange.state = ExchangeState::AwaitingResponse;
+            exchange.timeout_ms = RESPONSE_TIMEOUT_MS;
+        }
+        self.exchange.set(exchange);
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(exchange.timeout_ms));
+        self.request_pending.set(true);
+        self.send_pending();
+    }
+
+    /// Sends the next request of a block-wise transfer, numbered `number`
+    /// (Block1 or Block2) with value `block`.
+    fn continue_exchange(&self, mut exchange: Exchange, number: u16, block: Block) {
+        let mut next = [0; MAX_MESSAGE_LEN];
+        exchange.message_id = self.new_message_id();
+        let len = self.request_buf.map_or(Err(ReturnCode::ENOMEM), |buf| {
+            let previous =
+                Message::decode(&buf[..exchange.request_len]).map_err(|_| ReturnCode::FAIL)?;
+            let mut writer = MessageWriter::new(
+                &mut next,
+                previous.mtype,
+                previous.code,
+                exchange.message_id,
+                previous.token,
+            );
+            // Observe only goes with the first request, and the block
+            // options are replaced
+            writer
+                .copy_options(&previous, 0, option::OBSERVE)
+                .copy_options(&previous, option::OBSERVE + 1, option::BLOCK2)
+                .uint_option(number, block.encode())
+                .copy_options(&previous, option::SIZE2, option::SIZE1)
+                .copy_options(&previous, option::SIZE1 + 1, u16::MAX);
+            if number == option::BLOCK1 {
+                let len = block.size().min(exchange.payload_len - block.offset());
+                self.write_payload(&mut writer, block.offset(), len);
+            }
+            let len = writer.finish()?;
+            buf[..len].copy_from_slice(&next[..len]);
+            Ok(len)
+        });
+        match len {
+            Ok(len) => {
+                exchange.request_len = len;
+                self.start_exchange(exchange);
+            }
+            Err(err) => self.complete(err),
+        }
+    }
+
+    fn complete(&self, result: ReturnCode) {
+        if self.exchange.is_some() {
+            self.exchange.clear();
+            self.request_pending.set(false);
+            self.alarm.disarm();
+            self.client.map(|client| client.request_done(result));
+        }
+    }
+
+    /// Builds a message with `build` in the transmit buffer and sends it.
+    fn transmit<F>(&self, dst: IPAddr, port: u16, build: F) -> Result<usize, ReturnCode>
+    where
+        F: FnOnce(&mut [u8]) -> Result<usize, ReturnCode>,
+    {
+        let mut buf = self.tx_buf.take().ok_or(ReturnCode::EBUSY)?;
+        self.last_response.clear();
+        buf.reset();
+        let len = match build(&mut buf[..MAX_MESSAGE_LEN]) {
+            Ok(len) => len,
+            Err(err) => {
+                self.tx_buf.replace(buf);
+                return Err(err);
+            }
+        };
+        buf.slice(0..len);
+        match self.sender.send_to(dst, port, buf, self.net_cap) {
+            Ok(()) => Ok(len),
+            Err(mut buf) => {
+                buf.reset();
+                self.tx_buf.replace(buf);
+                Err(ReturnCode::FAIL)
+            }
+        }
+    }
+
+    /// Sends an empty acknowledgement or reset of `message_id`.
+    fn send_empty(&self, dst: IPAddr, port: u16, mtype: MessageType, message_id: u16) {
+        let _ = self.transmit(dst, port, |buf| {
+            MessageWriter::new(buf, mtype, code::EMPTY, message_id, &[]).finish()
+        });
+    }
+
+    /// Sends the request of the exchange, or a notification, if the
+    /// transmit buffer is free.
+    fn send_pending(&self) {
+        if self.tx_buf.is_none() {
+            return;
+        }
+        if self.request_pending.get() {
+            if let Some(exchange) = self.get_exchange() {
+                let sent = self.transmit(exchange.dst, exchange.port, |buf| {
+                    self.request_buf.map_or(Err(ReturnCode::ENOMEM), |request| {
+                        buf[..exchange.request_len]
+                            .copy_from_slice(&request[..exchange.request_len]);
+                        Ok(exchange.request_len)
+                    })
+                });
+                // A request which could not be sent is retransmitted, or
+                // times out, like a lost one
+                self.request_pending.set(false);
+                if sent.is_ok() {
+                    return;
+                }
+            }
+        }
+        let pending = self.notifications_pending.get();
+        if pending != 0 {
+            let index = pending.trailing_zeros() as usize;
+            self.notifications_pending.set(pending & !(1 << index));
+            self.send_notification(index);
+        }
+    }
+
+    fn send_notification(&self, index: usize) {
+        let mut observers = self.observers.get();
+        let mut observer = match observers[index] {
+            Some(observer) => observer,
+            None => return,
+        };
+        observer.message_id = self.new_message_id();
+        let seq = self.observe_seq.get();
+        self.observe_seq.set(seq.wrapping_add(1) & OBSERVE_MASK);
+        let sent = self.transmit(observer.addr, observer.port, |buf| {
+            let mut writer = MessageWriter::new(
+                buf,
+                MessageType::NonConfirmable,
+                code::CONTENT,
+                observer.message_id,
+                &observer.token[..observer.token_len],
+            );
+            writer.uint_option(option::OBSERVE, seq);
+            let block = Block {
+                num: 0,
+                more: false,
+                szx: BLOCK_SZX,
+            };
+            self.write_representation(&mut writer, observer.resource, block)?;
+            writer.finish()
+        });
+        if sent.is_ok() {
+            observers[index] = Some(observer);
+            self.observers.set(observers);
+        } else {
+            self.send_pending();
+        }
+    }
+
+    /// Writes the Block2 and Size2 options and the payload of a response
+    /// carrying the block `block` of the representation of `resource`.
+    fn write_representation(
+        &self,
+        writer: &mut MessageWriter,
+        resource: usize,
+        mut block: Block,
+    ) -> Result<(), ReturnCode> {
+        let resources = self.resources.map(|r| *r).ok_or(ReturnCode::FAIL)?;
+        let (_, total) = resources.read(resource, 0, &mut []);
+        let offset = block.offset();
+        if offset > total || (offset == total && offset > 0) {
+            return Err(ReturnCode::EINVAL);
+        }
+        block.more = offset + block.size() < total;
+        if block.num > 0 || block.more {
+            writer.uint_option(option::BLOCK2, block.encode());
+            if block.num == 0 {
+                writer.uint_option(option::SIZE2, total as u32);
+            }
+        }
+        writer.payload_with(block.size(), |buf| resources.read(resource, offset, buf).0);
+        Ok(())
+    }
+
+    fn is_recent(&self, recent: Recent) -> bool {
+        self.recent.get().iter().any(|r| *r == Some(recent))
+    }
+
+    fn remember(&self, recent: Recent) {
+        let mut table = self.recent.get();
+        let next = self.recent_next.get();
+        table[next] = Some(recent);
+        self.recent.set(table);
+        self.recent_next.set((next + 1) % MAX_RECENT);
+    }
+
+    /// Registers, or deregisters, the sender of a GET request as an
+    /// observer of `resource`. Returns whether it is registered.
+    fn update_observer(&self, resource: usize, src: IPAddr, port: u16, request: &Message) -> bool {
+        let mut observers = self.observers.get();
+        let same = |o: &Option<Observer>| {
+            o.map_or(false, |o| {
+                o.resource == resource && o.addr == src && o.port == port
+            })
+        };
+        let existing = observers.iter().position(same);
+        let registered = match request.uint_option(option::OBSERVE) {
+            Some(OBSERVE_REGISTER) if self.resources.map_or(false, |r| r.observable(resource)) => {
+                match existing.or_else(|| observers.iter().position(|o| o.is_none())) {
+                    Some(index) => {
+                        let mut token = [0; 8];
+                        token[..request.token.len()].copy_from_slice(request.token);
+                        observers[index] = Some(Observer {
+                            resource: resource,
+                            addr: src,
+                            port: port,
+                            token: token,
+                            token_len: request.token.len(),
+                            message_id: 0,
+                        });
+                        true
+                    }
+                    None => false,
+                }
+            }
+            Some(OBSERVE_DEREGISTER) => {
+                existing.map(|index| observers[index] = None);
+                false
+            }
+            _ => existing.is_some(),
+        };
+        self.observers.set(observers);
+        registered
+    }
+
+    fn handle_request(&self, src: IPAddr, port: u16, request: &Message) {
+        let recent = Recent {
+            addr: src,
+            port: port,
+            message_id: request.message_id,
+        };
+        let confirmable = match request.mtype {
+            MessageType::Confirmable => true,
+            MessageType::NonConfirmable => false,
+            _ => return,
+        };
+        if self.is_recent(recent) {
+            // Send the same response again, if we still have it
+            if let Some((last, len)) = self.last_response.map(|last| *last) {
+                if confirmable && last == recent {
+                    if let Some(mut buf) = self.tx_buf.take() {
+                        buf.slice(0..len);
+                        if let Err(mut buf) = self.sender.send_to(src, port, buf, self.net_cap) {
+                            buf.reset();
+                            self.tx_buf.replace(buf);
+                        }
+                    }
+                }
+            }
+            return;
+        }
+        self.remember(recent);
+
+        let (mtype, message_id) = if confirmable {
+            (MessageType::Acknowledgement, request.message_id)
+        } else {
+            (MessageType::NonConfirmable, self.new_message_id())
+        };
+        let resource = self.resources.and_then(|r| r.find(request));
+        let observing = match resource {
+            Some(resource) if request.code == code::GET => {
+                // Only the first block registers an observer
+                let first = request.block(option::BLOCK2).map_or(true, |b| b.num == 0);
+                first && self.update_observer(resource, src, port, request)
+            }
+            _ => false,
+        };
+
+        let response = |buf: &mut [u8]| {
+            let respond = |buf: &mut [u8], code| {
+                MessageWriter::new(buf, mtype, code, message_id, request.token).finish()
+            };
+            let resource = match resource {
+                _ if request.has_unknown_critical(&KNOWN_OPTIONS) => {
+                    return respond(buf, code::BAD_OPTION);
+                }
+                Some(resource) => resource,
+                None => return respond(buf, code::NOT_FOUND),
+            };
+            match request.code {
+                code::GET => {
+                    let mut writer =
+                        MessageWriter::new(buf, mtype, code::CONTENT, message_id, request.token);
+                    if observing {
+                        let seq = self.observe_seq.get();
+                        self.observe_seq.set(seq.wrapping_add(1) & OBSERVE_MASK);
+                        writer.uint_option(option::OBSERVE, seq);
+                    }
+                    // Serve the block asked for, in blocks no larger than
+                    // ours
+                    let block = request.block(option::BLOCK2).map_or(
+                        Block {
+                            num: 0,
+                            more: false,
+                            szx: BLOCK_SZX,
+                        },
+                        |b| {
+                            let szx = b.szx.min(BLOCK_SZX);
+                            Block {
+                                num: (b.offset() / (16 << szx)) as u32,
+                                more: false,
+                                szx: szx,
+                            }
+                        },
+                    );
+                    match self.write_representation(&mut writer, resource, block) {
+                        Ok(()) => writer.finish(),
+                        Err(_) => respond(buf, code::BAD_OPTION),
+                    }
+                }
+                code::PUT | code::POST | code::DELETE => {
+                    let block = request.block(option::BLOCK1);
+                    let (offset, more) = block.map_or((0, false), |b| (b.offset(), b.more));
+                    let result = self.resources.map_or(code::NOT_FOUND, |r| {
+                        r.write(resource, request.code, offset, request.payload, more)
+                    });
+                    let result = if more && code::is_success(result) {
+                        code::CONTINUE
+                    } else {
+                        result
+                    };
+                    let mut writer =
+                        MessageWriter::new(buf, mtype, result, message_id, request.token);
+                    block.map(|b| writer.uint_option(option::BLOCK1, b.encode()));
+                    writer.finish()
+                }
+                _ => respond(buf, code::METHOD_NOT_ALLOWED),
+            }
+        };
+        if let Ok(len) = self.transmit(src, port, response) {
+            if confirmable {
+                self.last_response.set((recent, len));
+            }
+        }
+    }
+
+    fn handle_reset(&self, message_id: u16) {
+        if self
+            .get_exchange()
+            .map_or(false, |exchange| exchange.message_id == message_id)
+        {
+            self.complete(ReturnCode::FAIL);
+            return;
+        }
+        // An observer which is not interested anymore
+        let mut observers = self.observers.get();
+        for observer in observers.iter_mut() {
+            if observer.map_or(false, |o| o.message_id == message_id) {
+                *observer = None;
+            }
+        }
+        self.observers.set(observers);
+    }
+
+    fn handle_response(&self, src: IPAddr, port: u16, response: &Message) {
+        let exchange = self.get_exchange().filter(|exchange| {
+            response.token == exchange.token
+                && (exchange.dst == src || exchange.dst.is_multicast())
+                && (response.mtype != MessageType::Acknowledgement
+                    || response.message_id == exchange.message_id)
+        });
+        let observation = self.observation.map(|o| *o).filter(|observation| {
+            response.token == observation.token
+                && observation.server == src
+                && observation.port == port
+        });
+        let known = exchange.is_some() || observation.is_some();
+
+        if response.mtype != MessageType::Acknowledgement {
+            let recent = Recent {
+                addr: src,
+                port: port,
+                message_id: response.message_id,
+            };
+            if response.mtype == MessageType::Confirmable {
+                // A duplicate is acknowledged again, as our acknowledgement
+                // may have been lost
+                let reply = if known || self.is_recent(recent) {
+                    MessageType::Acknowledgement
+                } else {
+                    MessageType::Reset
+                };
+                self.send_empty(src, port, reply, response.message_id);
+            }
+            if self.is_recent(recent) {
+                return;
+            }
+            self.remember(recent);
+        }
+
+        if let Some(exchange) = exchange {
+            self.handle_exchange_response(exchange, src, port, response);
+        } else if let Some(mut observation) = observation {
+            let seq = response.uint_option(option::OBSERVE);
+            if let (Some(old), Some(new)) = (observation.seq, seq) {
+                if !observe_is_newer(old, new) {
+                    return;
+                }
+            }
+            observation.seq = seq;
+            self.observation.set(observation);
+            let more = response.block(option::BLOCK2).map_or(false, |b| b.more);
+            self.client
+                .map(|client| client.notification(response.code, response.payload, more));
+            if seq.is_none() {
+                // The server ended the observation
+                self.observation.clear();
+            }
+        } else if response.mtype == MessageType::NonConfirmable {
+            self.send_empty(src, port, MessageType::Reset, response.message_id);
+        }
+    }
+
+    fn handle_exchange_response(
+        &self,
+        exchange: Exchange,
+        src: IPAddr,
+        port: u16,
+        response: &Message,
+    ) {
+        self.alarm.disarm();
+        self.request_pending.set(false);
+        if response.code == code::CONTINUE {
+            match response.block(option::BLOCK1) {
+                Some(block) if (block.num as usize + 1) * block.size() < exchange.payload_len => {
+                    // The server may ask for smaller blocks
+                    let szx = block.szx.min(BLOCK_SZX);
+                    let num = ((block.num as usize + 1) * block.size()) / (16 << szx);
+                    let next = Block {
+                        num: num as u32,
+                        more: (num + 1) * (16 << szx) < exchange.payload_len,
+                        szx: szx,
+                    };
+                    self.continue_exchange(exchange, option::BLOCK1, next);
+                }
+                _ => self.complete(ReturnCode::FAIL),
+            }
+            return;
+        }
+
+        if exchange.observe && code::is_success(response.code) {
+            if let Some(seq) = response.uint_option(option::OBSERVE) {
+                self.observation.set(Observation {
+                    server: src,
+                    port: port,
+                    token: exchange.token,
+                    seq: Some(seq),
+                });
+            }
+        }
+        let block = response.block(option::BLOCK2);
+        let (offset, more) = block.map_or((0, false), |b| (b.offset(), b.more));
+        self.client
+            .map(|client| client.response(response.code, offset, response.payload, more));
+
+        match block {
+            Some(block) if more && code::is_success(response.code) => {
+                let next = Block {
+                    num: block.num + 1,
+                    more: false,
+                    szx: block.szx,
+                };
+                // Later blocks are asked from the server which answered
+                let exchange = Exchange {
+                    dst: src,
+                    payload_len: 0,
+                    ..exchange
+                };
+                self.continue_exchange(exchange, option::BLOCK2, next);
+            }
+            _ => self.complete(ReturnCode::SUCCESS),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for CoapEndpoint<'a, A> {
+    fn alarm(&self) {
+        let mut exchange = match self.get_exchange() {
+            Some(exchange) => exchange,
+            None => return,
+        };
+        if exchange.state == ExchangeState::AwaitingAck && exchange.retransmissions < MAX_RETRANSMIT
+        {
+            exchange.retransmissions += 1;
+            exchange.timeout_ms *= 2;
+            self.exchange.set(exchange);
+            self.alarm
+                .set_alarm(self.alarm.now(), A::ticks_from_ms(exchange.timeout_ms));
+            self.request_pending.set(true);
+            self.send_pending();
+        } else {
+            self.complete(ReturnCode::ENOACK);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for CoapEndpoint<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let message = match Message::decode(payload) {
+            Ok(message) => message,
+            Err(()) => return,
+        };
+        if message.code == code::EMPTY {
+            match message.mtype {
+                // A ping
+                MessageType::Confirmable => {
+                    self.send_empty(src_addr, src_port, MessageType::Reset, message.message_id)
+                }
+                MessageType::Acknowledgement => {
+                    if let Some(mut exchange) = self.get_exchange() {
+                        if exchange.message_id == message.message_id
+                            && exchange.state == ExchangeState::AwaitingAck
+                        {
+                            // A separate response follows
+                            exchange.state = ExchangeState::AwaitingResponse;
+                            self.exchange.set(exchange);
+                            self.request_pending.set(false);
+                            self.alarm
+                                .set_alarm(self.alarm.now(), A::ticks_from_ms(RESPONSE_TIMEOUT_MS));
+                        }
+                    }
+                }
+                MessageType::Reset => self.handle_reset(message.message_id),
+                MessageType::NonConfirmable => {}
+            }
+        } else if code::is_request(message.code) {
+            self.handle_request(src_addr, src_port, &message);
+        } else if code::is_response(message.code) {
+            self.handle_response(src_addr, src_port, &message);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for CoapEndpoint<'a, A> {
+    fn send_done(&self, _result: ReturnCode, mut dgram: LeasableBuffer<'static, u8>) {
+        // The contents stay, to answer duplicates
+        dgram.reset();
+        self.tx_buf.replace(dgram);
+        self.send_pending();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> rng::Client for CoapEndpoint<'a, A> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if self.seeded.get() {
+            return rng::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            return rng::Continue::More;
+        }
+        match (randomness.next(), randomness.next(), randomness.next()) {
+            (Some(message_id), Some(token), Some(random)) => {
+                self.next_message_id.set(message_id as u16);
+                self.next_token.set(token);
+                // Xorshift gets stuck at zero
+                self.random.set(random | 1);
+                self.seeded.set(true);
+                rng::Continue::Done
+            }
+            _ => rng::Continue::More,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe_ordering() {
+        assert!(observe_is_newer(1, 2));
+        assert!(!observe_is_newer(2, 1));
+        assert!(!observe_is_newer(5, 5));
+        // Wrap around
+        assert!(observe_is_newer(OBSERVE_MASK, 0));
+        assert!(!observe_is_newer(0, 1 << 23 | 1));
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod sim_tests {
+    extern crate std;
+
+    use super::*;
+    use crate::net::coap::sim::{message, Sim, FIRST_MESSAGE_ID, FIRST_TOKEN, PEER, PEER_PORT};
+    use core::cell::RefCell;
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Response(u8, usize, Vec<u8>, bool),
+        Notification(u8, Vec<u8>, bool),
+        Done(ReturnCode),
+    }
+
+    struct TestClient {
+        body: Vec<u8>,
+        events: RefCell<Vec<Event>>,
+    }
+
+    impl TestClient {
+        fn take(&self) -> Vec<Event> {
+            self.events.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl CoapClient for TestClient {
+        fn response(&self, code: u8, offset: usize, payload: &[u8], more: bool) {
+            self.events
+                .borrow_mut()
+                .push(Event::Response(code, offset, payload.to_vec(), more));
+        }
+
+        fn notification(&self, code: u8, payload: &[u8], more: bool) {
+            self.events
+                .borrow_mut()
+                .push(Event::Notification(code, payload.to_vec(), more));
+        }
+
+        fn request_done(&self, result: ReturnCode) {
+            self.events.borrow_mut().push(Event::Done(result));
+        }
+
+        fn read_payload(&self, offset: usize, buf: &mut [u8]) -> usize {
+            let len = buf.len().min(self.body.len() - offset);
+            buf[..len].copy_from_slice(&self.body[offset..offset + len]);
+            len
+        }
+    }
+
+    /// A single observable resource at "r", which accepts every write.
+    struct TestResources {
+        representation: Vec<u8>,
+        writes: RefCell<Vec<(u8, usize, Vec<u8>, bool)>>,
+    }
+
+    impl CoapResources for TestResources {
+        fn find(&self, request: &Message) -> Option<usize> {
+            if request.path_matches(b"r") {
+                Some(0)
+            } else {
+                None
+            }
+        }
+
+        fn observable(&self, _resource: usize) -> bool {
+            true
+        }
+
+        fn read(&self, _resource: usize, offset: usize, buf: &mut [u8]) -> (usize, usize) {
+            let total = self.representation.len();
+            let len = buf.len().min(total - offset);
+            buf[..len].copy_from_slice(&self.representation[offset..offset + len]);
+            (len, total)
+        }
+
+        fn write(
+            &self,
+            _resource: usize,
+            method: u8,
+            offset: usize,
+            payload: &[u8],
+            more: bool,
+        ) -> u8 {
+            self.writes
+                .borrow_mut()
+                .push((method, offset, payload.to_vec(), more));
+            code::CHANGED
+        }
+    }
+
+    fn client_sim(body: &[u8]) -> (Sim, &'static TestClient) {
+        let sim = Sim::new();
+        sim.seed();
+        let client = Box::leak(Box::new(TestClient {
+            body: body.to_vec(),
+            events: RefCell::new(Vec::new()),
+        }));
+        sim.endpoint.set_client(client);
+        (sim, client)
+    }
+
+    fn server_sim(representation: &[u8]) -> (Sim, &'static TestResources) {
+        let sim = Sim::new();
+        sim.seed();
+        let resources = Box::leak(Box::new(TestResources {
+            representation: representation.to_vec(),
+            writes: RefCell::new(Vec::new()),
+        }));
+        sim.endpoint.set_resources(resources);
+        (sim, resources)
+    }
+
+    fn empty(mtype: MessageType, message_id: u16) -> Vec<u8> {
+        message(mtype, code::EMPTY, message_id, &[], |_| {})
+    }
+
+    #[test]
+    fn requests_wait_for_seed() {
+        let sim = Sim::new();
+        let get = || {
+            sim.endpoint
+                .request(PEER, PEER_PORT, code::GET, b"r", 0, true, false)
+        };
+        assert_eq!(get(), ReturnCode::EBUSY);
+        sim.seed();
+        assert_eq!(get(), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn confirmable_request_retransmitted_with_backoff() {
+        let (sim, client) = client_sim(&[]);
+        assert_eq!(
+            sim.endpoint
+                .request(PEER, PEER_PORT, code::GET, b"r", 0, true, false),
+            ReturnCode::SUCCESS
+        );
+        let sent = sim.sent();
+        assert_eq!(sent.len(), 1);
+        let request = Message::decode(&sent[0]).unwrap();
+        assert_eq!(request.mtype, MessageType::Confirmable);
+        assert_eq!(request.message_id, FIRST_MESSAGE_ID);
+        assert_eq!(request.token, FIRST_TOKEN);
+        assert!(request.path_matches(b"r"));
+
+        let mut timeout = sim.alarm.dt().unwrap();
+        assert!(timeout >= ACK_TIMEOUT_MS && timeout < ACK_TIMEOUT_MS * 3 / 2);
+        for _ in 0..MAX_RETRANSMIT {
+            sim.fire();
+            assert_eq!(sim.sent(), sent);
+            timeout *= 2;
+            assert_eq!(sim.alarm.dt(), Some(timeout));
+        }
+        sim.fire();
+        assert!(sim.sent().is_empty());
+        assert_eq!(client.take(), [Event::Done(ReturnCode::ENOACK)]);
+        assert!(!sim.endpoint.is_busy());
+        assert_eq!(sim.alarm.dt(), None);
+    }
+
+    #[test]
+    fn separate_response_acknowledged_once_delivered() {
+        let (sim, client) = client_sim(&[]);
+        sim.endpoint
+            .request(PEER, PEER_PORT, code::GET, b"r", 0, true, false);
+        sim.sent();
+
+        // An empty acknowledgement stops the retransmissions
+        sim.receive(&empty(MessageType::Acknowledgement, FIRST_MESSAGE_ID));
+        assert_eq!(sim.alarm.dt(), Some(RESPONSE_TIMEOUT_MS));
+
+        let response = message(
+            MessageType::Confirmable,
+            code::CONTENT,
+            0x0900,
+            &FIRST_TOKEN,
+            |w| {
+                w.payload(b"hi");
+            },
+        );
+        let ack = empty(MessageType::Acknowledgement, 0x0900);
+        sim.receive(&response);
+        assert_eq!(sim.sent(), [ack.clone()]);
+        assert_eq!(
+            client.take(),
+            [
+                Event::Response(code::CONTENT, 0, b"hi".to_vec(), false),
+                Event::Done(ReturnCode::SUCCESS)
+            ]
+        );
+
+        // The server did not get the acknowledgement
+        sim.receive(&response);
+        assert_eq!(sim.sent(), [ack]);
+        assert!(client.take().is_empty());
+    }
+
+    #[test]
+    fn duplicate_request_answered_with_same_response() {
+        let (sim, resources) = server_sim(b"");
+        let put = message(MessageType::Confirmable, code::PUT, 0x0777, &[1], |w| {
+            w.uri_path(b"r").payload(b"abc");
+        });
+        sim.receive(&put);
+        let sent = sim.sent();
+        assert_eq!(sent.len(), 1);
+        let response = Message::decode(&sent[0]).unwrap();
+        assert_eq!(response.mtype, MessageType::Acknowledgement);
+        assert_eq!(response.code, code::CHANGED);
+        assert_eq!(response.message_id, 0x0777);
+        assert_eq!(response.token, [1]);
+
+        sim.receive(&put);
+        assert_eq!(sim.sent(), sent);
+        assert_eq!(
+            *resources.writes.borrow(),
+            [(code::PUT, 0, b"abc".to_vec(), false)]
+        );
+
+        // Once the response is overwritten, duplicates are only ignored
+        sim.receive(&message(
+            MessageType::NonConfirmable,
+            code::GET,
+            0x0778,
+            &[2],
+            |w| {
+                w.uri_path(b"r");
+            },
+        ));
+        assert_eq!(sim.sent().len(), 1);
+        sim.receive(&put);
+        assert!(sim.sent().is_empty());
+        assert_eq!(resources.writes.borrow().len(), 1);
+    }
+
+    #[test]
+    fn block1_request_continued() {
+        let body: Vec<u8> = (0..150).collect();
+        let (sim, client) = client_sim(&body);
+        assert_eq!(
+            sim.endpoint
+                .request(PEER, PEER_PORT, code::PUT, b"r", body.len(), true, false),
+            ReturnCode::SUCCESS
+        );
+        let continue_block = |request: &Message, block: Block| {
+            message(
+                MessageType::Acknowledgement,
+                code::CONTINUE,
+                request.message_id,
+                request.token,
+                |w| {
+                    w.uint_option(option::BLOCK1, block.encode());
+                },
+            )
+        };
+
+        let sent = sim.sent();
+        let first = Message::decode(&sent[0]).unwrap();
+        let block = Block {
+            num: 0,
+            more: true,
+            szx: BLOCK_SZX,
+        };
+        assert_eq!(first.block(option::BLOCK1), Some(block));
+        assert_eq!(first.uint_option(option::SIZE1), Some(150));
+        assert_eq!(first.payload, &body[..64]);
+        sim.receive(&continue_block(&first, block));
+
+        // The Uri-Path is copied, Size1 only goes with the first block
+        let sent = sim.sent();
+        let second = Message::decode(&sent[0]).unwrap();
+        let block = Block { num: 1, ..block };
+        assert_eq!(second.code, code::PUT);
+        assert_eq!(second.message_id, FIRST_MESSAGE_ID + 1);
+        assert_eq!(second.token, FIRST_TOKEN);
+        assert!(second.path_matches(b"r"));
+        assert_eq!(second.block(option::BLOCK1), Some(block));
+        assert_eq!(second.uint_option(option::SIZE1), None);
+        assert_eq!(second.payload, &body[64..128]);
+        sim.receive(&continue_block(&second, block));
+
+        let sent = sim.sent();
+        let last = Message::decode(&sent[0]).unwrap();
+        let block = Block {
+            num: 2,
+            more: false,
+            ..block
+        };
+        assert_eq!(last.block(option::BLOCK1), Some(block));
+        assert_eq!(last.payload, &body[128..]);
+        sim.receive(&message(
+            MessageType::Acknowledgement,
+            code::CHANGED,
+            last.message_id,
+            last.token,
+            |w| {
+                w.uint_option(option::BLOCK1, block.encode());
+            },
+        ));
+        assert_eq!(
+            client.take(),
+            [
+                Event::Response(code::CHANGED, 0, vec![], false),
+                Event::Done(ReturnCode::SUCCESS)
+            ]
+        );
+    }
+
+    #[test]
+    fn block2_response_continued_and_observed() {
+        let (sim, client) = client_sim(&[]);
+        sim.endpoint
+            .request(PEER, PEER_PORT, code::GET, b"r", 0, true, true);
+        let sent = sim.sent();
+        let first = Message::decode(&sent[0]).unwrap();
+        assert_eq!(first.uint_option(option::OBSERVE), Some(OBSERVE_REGISTER));
+        let block = Block {
+            num: 0,
+            more: true,
+            szx: BLOCK_SZX,
+        };
+        sim.receive(&message(
+            MessageType::Acknowledgement,
+            code::CONTENT,
+            first.message_id,
+            first.token,
+            |w| {
+                w.uint_option(option::OBSERVE, 5)
+                    .uint_option(option::BLOCK2, block.encode())
+                    .uint_option(option::SIZE2, 68)
+                    .payload(&[b'a'; 64]);
+            },
+        ));
+        assert_eq!(
+            client.take(),
+            [Event::Response(code::CONTENT, 0, vec![b'a'; 64], true)]
+        );
+
+        // Observe only goes with the first request
+        let sent = sim.sent();
+        let second = Message::decode(&sent[0]).unwrap();
+        let block = Block {
+            num: 1,
+            more: false,
+            ..block
+        };
+        assert_eq!(second.code, code::GET);
+        assert_eq!(second.message_id, FIRST_MESSAGE_ID + 1);
+        assert_eq!(second.uint_option(option::OBSERVE), None);
+        assert!(second.path_matches(b"r"));
+        assert_eq!(second.block(option::BLOCK2), Some(block));
+        sim.receive(&message(
+            MessageType::Acknowledgement,
+            code::CONTENT,
+            second.message_id,
+            second.token,
+            |w| {
+                w.uint_option(option::BLOCK2, block.encode())
+                    .payload(b"tail");
+            },
+        ));
+        assert_eq!(
+            client.take(),
+            [
+                Event::Response(code::CONTENT, 64, b"tail".to_vec(), false),
+                Event::Done(ReturnCode::SUCCESS)
+            ]
+        );
+
+        let notification = |mtype, message_id, seq, payload: &[u8]| {
+            message(mtype, code::CONTENT, message_id, &FIRST_TOKEN, |w| {
+                w.uint_option(option::OBSERVE, seq).payload(payload);
+            })
+        };
+        let non = MessageType::NonConfirmable;
+        sim.receive(&notification(non, 0x0a00, 6, b"n6"));
+        assert_eq!(
+            client.take(),
+            [Event::Notification(code::CONTENT, b"n6".to_vec(), false)]
+        );
+        // Older and duplicate notifications are dropped
+        sim.receive(&notification(non, 0x0a01, 4, b"n4"));
+        sim.receive(&notification(non, 0x0a00, 6, b"n6"));
+        assert!(client.take().is_empty());
+        assert!(sim.sent().is_empty());
+
+        sim.receive(&notification(MessageType::Confirmable, 0x0a02, 7, b"n7"));
+        assert_eq!(sim.sent(), [empty(MessageType::Acknowledgement, 0x0a02)]);
+        assert_eq!(
+            client.take(),
+            [Event::Notification(code::CONTENT, b"n7".to_vec(), false)]
+        );
+
+        sim.endpoint.cancel_observation();
+        sim.receive(&notification(non, 0x0a03, 8, b"n8"));
+        assert_eq!(sim.sent(), [empty(MessageType::Reset, 0x0a03)]);
+        assert!(client.take().is_empty());
+    }
+
+    #[test]
+    fn observers_notified_until_reset() {
+        let (sim, _) = server_sim(b"temp");
+        sim.receive(&message(
+            MessageType::Confirmable,
+            code::GET,
+            0x0100,
+            &[7, 7],
+            |w| {
+                w.uint_option(option::OBSERVE, OBSERVE_REGISTER)
+                    .uri_path(b"r");
+            },
+        ));
+        let sent = sim.sent();
+        let response = Message::decode(&sent[0]).unwrap();
+        assert_eq!(response.mtype, MessageType::Acknowledgement);
+        assert_eq!(response.code, code::CONTENT);
+        assert_eq!(response.uint_option(option::OBSERVE), Some(2));
+        assert_eq!(response.payload, b"temp");
+
+        sim.endpoint.notify(0);
+        let sent = sim.sent();
+        assert_eq!(sent.len(), 1);
+        let notification = Message::decode(&sent[0]).unwrap();
+        assert_eq!(notification.mtype, MessageType::NonConfirmable);
+        assert_eq!(notification.message_id, FIRST_MESSAGE_ID);
+        assert_eq!(notification.token, [7, 7]);
+        assert_eq!(notification.uint_option(option::OBSERVE), Some(3));
+        assert_eq!(notification.payload, b"temp");
+
+        // The observer lost interest
+        sim.receive(&empty(MessageType::Reset, FIRST_MESSAGE_ID));
+        sim.endpoint.notify(0);
+        assert!(sim.sent().is_empty());
+    }
+}
@@ -0,0 +1,531 @@
+//! CoAP message encoding and decoding (RFC 7252).
+//!
+//! A message is a four byte header, a token of up to eight bytes, a sequence
+//! of options sorted by number and an optional payload behind a `0xff`
+//! marker. Option numbers are delta encoded, so options must be written in
+//! increasing order.
+//!
+//! `Message` borrows the buffer it was decoded from; its options are walked
+//! again whenever one is looked up. `MessageWriter` builds a message in
+//! place.
+//!
+//! The Block1 and Block2 options of block-wise transfers (RFC 7959) are
+//! represented by `Block`, and the Observe option (RFC 7641) is a plain
+//! unsigned integer option.
+
+use kernel::ReturnCode;
+
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 4;
+pub const MAX_TOKEN_LEN: usize = 8;
+pub const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> MessageType {
+        match bits & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// Method and response codes, written `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+
+    pub fn is_request(code: u8) -> bool {
+        code != EMPTY && code >> 5 == 0
+    }
+
+    pub fn is_response(code: u8) -> bool {
+        code >> 5 >= 2
+    }
+
+    pub fn is_success(code: u8) -> bool {
+        code >> 5 == 2
+    }
+}
+
+/// Option numbers.
+pub mod option {
+    pub const IF_MATCH: u16 = 1;
+    pub const URI_HOST: u16 = 3;
+    pub const ETAG: u16 = 4;
+    pub const IF_NONE_MATCH: u16 = 5;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const LOCATION_PATH: u16 = 8;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const LOCATION_QUERY: u16 = 20;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const PROXY_URI: u16 = 35;
+    pub const PROXY_SCHEME: u16 = 39;
+    pub const SIZE1: u16 = 60;
+}
+
+/// Values of the Observe option in requests.
+pub const OBSERVE_REGISTER: u32 = 0;
+pub const OBSERVE_DEREGISTER: u32 = 1;
+
+/// The value of a Block1 or Block2 option: the number of the block, whether
+/// more blocks follow and the size exponent, the block being
+/// `16 << szx` bytes long.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub num: u32,
+    pub more: bool,
+    pub szx: u8,
+}
+
+impl Block {
+    /// The largest size exponent, for blocks of 1024 bytes.
+    pub const MAX_SZX: u8 = 6;
+    pub const MAX_NUM: u32 = (1 << 20) - 1;
+
+    pub fn decode(value: u32) -> Option<Block> {
+        let szx = (value & 0x7) as u8;
+        if szx > Block::MAX_SZX || value >> 4 > Block::MAX_NUM {
+            return None;
+        }
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx: szx,
+        })
+    }
+
+    pub fn encode(&self) -> u32 {
+        (self.num << 4) | ((self.more as u32) << 3) | self.szx as u32
+    }
+
+    pub fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Decodes an unsigned integer option value, which is big endian without
+/// leading zeros.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |acc, b| (acc << 8) | *b as u32))
+}
+
+/// Reads the extended delta or length of an option. Returns the value and
+/// the number of bytes it took.
+fn decode_extended(nibble: u8, buf: &[u8]) -> Option<(u16, usize)> {
+    match nibble {
+        0..=12 => Some((nibble as u16, 0)),
+        13 => buf.get(0).map(|b| (*b as u16 + 13, 1)),
+        14 => {
+            if buf.len() < 2 {
+                return None;
+            }
+            let value = ((buf[0] as u16) << 8 | buf[1] as u16).checked_add(269)?;
+            Some((value, 2))
+        }
+        _ => None,
+    }
+}
+
+/// Walks the options of a message, yielding their numbers and values.
+pub struct Options<'a> {
+    buf: &'a [u8],
+    number: u16,
+}
+
+impl<'a> Options<'a> {
+    /// Decodes the option at the start of the remaining bytes, returning
+    /// it and the number of bytes it took. `None` at the payload marker or
+    /// the end of the message, `Some(Err)` if the option is malformed.
+    fn decode_next(&self) -> Option<Result<(u16, &'a [u8], usize), ()>> {
+        let buf = self.buf;
+        let first = *buf.get(0)?;
+        if first == PAYLOAD_MARKER {
+            return None;
+        }
+        let decode = || {
+            let (delta, delta_len) = decode_extended(first >> 4, &buf[1..])?;
+            let (len, len_len) = decode_extended(first & 0xf, &buf[1 + delta_len..])?;
+            let start = 1 + delta_len + len_len;
+            let end = start + len as usize;
+            let value = buf.get(start..end)?;
+            let number = self.number.checked_add(delta)?;
+            Some((number, value, end))
+        };
+        Some(decode().ok_or(()))
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        match self.decode_next() {
+            Some(Ok((number, value, len))) => {
+                self.buf = &self.buf[len..];
+                self.number = number;
+                Some((number, value))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A decoded message borrowing the buffer it came from.
+pub struct Message<'a> {
+    pub mtype: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: &'a [u8],
+    options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Decodes and checks a message, including its options.
+    pub fn decode(buf: &'a [u8]) -> Result<Message<'a>, ()> {
+        if buf.len() < HEADER_LEN || buf[0] >> 6 != VERSION {
+            return Err(());
+        }
+        let token_len = (buf[0] & 0xf) as usize;
+        if token_len > MAX_TOKEN_LEN || buf.len() < HEADER_LEN + token_len {
+            return Err(());
+        }
+        let code = buf[1];
+        let token = &buf[HEADER_LEN..HEADER_LEN + token_len];
+        let rest = &buf[HEADER_LEN + token_len..];
+
+        // Find the end of the options
+        let mut options = Options {
+            buf: rest,
+            number: 0,
+        };
+        while let Some(option) = options.decode_next() {
+            let (number, _, len) = option?;
+            options.buf = &options.buf[len..];
+            options.number = number;
+        }
+        let options_len = rest.len() - options.buf.len();
+        let payload = match options.buf.split_first() {
+            // A marker must be followed by a payload
+            Some((_, payload)) if payload.is_empty() => return Err(()),
+            Some((_, payload)) => payload,
+            None => &[],
+        };
+        // An empty message is only a header
+        if code == code::EMPTY && buf.len() != HEADER_LEN {
+            return Err(());
+        }
+
+        Ok(Message {
+            mtype: MessageType::from_bits(buf[0] >> 4),
+            code: code,
+            message_id: (buf[2] as u16) << 8 | buf[3] as u16,
+            token: token,
+            options: &rest[..options_len],
+            payload: payload,
+        })
+    }
+
+    pub fn options(&self) -> Options<'a> {
+        Options {
+            buf: self.options,
+            number: 0,
+        }
+    }
+
+    /// The value of the first option numbered `number`.
+    pub fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    /// The Block1 or Block2 option, `None` if absent or invalid.
+    pub fn block(&self, number: u16) -> Option<Block> {
+        self.uint_option(number).and_then(Block::decode)
+    }
+
+    /// Whether the Uri-Path options of the message spell out `path`, whose
+    /// segments are separated by `/`.
+    pub fn path_matches(&self, path: &[u8]) -> bool {
+        let mut segments = path.split(|b| *b == b'/').filter(|s| !s.is_empty());
+        for (_, value) in self.options().filter(|(n, _)| *n == option::URI_PATH) {
+            if segments.next() != Some(value) {
+                return false;
+            }
+        }
+        segments.next().is_none()
+    }
+
+    /// Whether the message carries an option the endpoint must understand
+    /// but does not. Critical options have odd numbers.
+    pub fn has_unknown_critical(&self, known: &[u16]) -> bool {
+        self.options()
+            .any(|(number, _)| number & 1 == 1 && !known.contains(&number))
+    }
+}
+
+/// Encodes the nibble and extended bytes of an option delta or length.
+fn encode_extended(value: u16) -> (u8, [u8; 2], usize) {
+    if value < 13 {
+        (value as u8, [0; 2], 0)
+    } else if value < 269 {
+        (13, [(value - 13) as u8, 0], 1)
+    } else {
+        let ext = value - 269;
+        (14, [(ext >> 8) as u8, ext as u8], 2)
+    }
+}
+
+/// Builds a message in a buffer. Writes past the end of the buffer, or
+/// options out of order, are recorded and reported by `finish`.
+pub struct MessageWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    last_option: u16,
+    failed: bool,
+}
+
+impl<'b> MessageWriter<'b> {
+    pub fn new(
+        buf: &'b mut [u8],
+        mtype: MessageType,
+        code: u8,
+        message_id: u16,
+        token: &[u8],
+    ) -> MessageWriter<'b> {
+        let mut writer = MessageWriter {
+            buf: buf,
+            len: 0,
+            last_option: 0,
+            failed: token.len() > MAX_TOKEN_LEN,
+        };
+        let token_len = token.len().min(MAX_TOKEN_LEN);
+        writer.put(&[
+            VERSION << 6 | (mtype as u8) << 4 | token_len as u8,
+            code,
+            (message_id >> 8) as u8,
+            message_id as u8,
+        ]);
+        writer.put(&token[..token_len]);
+        writer
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + bytes.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.failed = true,
+        }
+    }
+
+    pub fn option(&mut self, number: u16, value: &[u8]) -> &mut Self {
+        if number < self.last_option || value.len() > u16::MAX as usize {
+            self.failed = true;
+            return self;
+        }
+        let (delta_nibble, delta_ext, delta_len) = encode_extended(number - self.last_option);
+        let (len_nibble, len_ext, len_len) = encode_extended(value.len() as u16);
+        self.put(&[delta_nibble << 4 | len_nibble]);
+        self.put(&delta_ext[..delta_len]);
+        self.put(&len_ext[..len_len]);
+        self.put(value);
+        self.last_option = number;
+        self
+    }
+
+    /// Writes an unsigned integer option in the fewest bytes.
+    pub fn uint_option(&mut self, number: u16, value: u32) -> &mut Self {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..])
+    }
+
+    /// Writes a Uri-Path option for each segment of `path`.
+    pub fn uri_path(&mut self, path: &[u8]) -> &mut Self {
+        for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            self.option(option::URI_PATH, segment);
+        }
+        self
+    }
+
+    /// Copies the options of `message` numbered from `from` up to, but not
+    /// including, `to`.
+    pub fn copy_options(&mut self, message: &Message, from: u16, to: u16) -> &mut Self {
+        for (number, value) in message.options() {
+            if number >= from && number < to {
+                self.option(number, value);
+            }
+        }
+        self
+    }
+
+    pub fn payload(&mut self, payload: &[u8]) -> &mut Self {
+        if !payload.is_empty() {
+            self.put(&[PAYLOAD_MARKER]);
+            self.put(payload);
+        }
+        self
+    }
+
+    /// Lets `fill` write up to `max_len` bytes of payload in place, and
+    /// returns how many it wrote.
+    pub fn payload_with<F: FnOnce(&mut [u8]) -> usize>(
+        &mut self,
+        max_len: usize,
+        fill: F,
+    ) -> usize {
+        if self.len >= self.buf.len() {
+            self.failed = true;
+            return 0;
+        }
+        let start = self.len + 1;
+        let end = (start + max_len).min(self.buf.len());
+        let written = fill(&mut self.buf[start..end]).min(end - start);
+        if written > 0 {
+            self.buf[self.len] = PAYLOAD_MARKER;
+            self.len = start + written;
+        }
+        written
+    }
+
+    /// The length of the message, or `ESIZE` if it did not fit.
+    pub fn finish(self) -> Result<usize, ReturnCode> {
+        if self.failed {
+            Err(ReturnCode::ESIZE)
+        } else {
+            Ok(self.len)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let mut buf = [0; 64];
+        let mut writer = MessageWriter::new(
+            &mut buf,
+            MessageType::Confirmable,
+            code::GET,
+            0x1234,
+            &[7, 8],
+        );
+        writer
+            .uint_option(option::OBSERVE, OBSERVE_REGISTER)
+            .uri_path(b"/sensors/temp")
+            .uint_option(
+                option::BLOCK2,
+                Block {
+                    num: 3,
+                    more: false,
+                    szx: 2,
+                }
+                .encode(),
+            )
+            .option(option::SIZE1, &[1, 0])
+            .payload(b"hi");
+        let len = writer.finish().unwrap();
+        assert_eq!(&buf[..4], &[0x42, code::GET, 0x12, 0x34]);
+
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(msg.mtype, MessageType::Confirmable);
+        assert_eq!(msg.message_id, 0x1234);
+        assert_eq!(msg.token, &[7, 8]);
+        assert_eq!(msg.uint_option(option::OBSERVE), Some(0));
+        assert!(msg.path_matches(b"sensors/temp"));
+        assert!(!msg.path_matches(b"sensors"));
+        assert!(!msg.path_matches(b"sensors/temp/x"));
+        let block = msg.block(option::BLOCK2).unwrap();
+        assert_eq!((block.num, block.more, block.size()), (3, false, 64));
+        assert_eq!(block.offset(), 192);
+        assert_eq!(msg.uint_option(option::SIZE1), Some(256));
+        assert_eq!(msg.payload, b"hi");
+        assert!(!msg.has_unknown_critical(&[option::URI_PATH, option::BLOCK2]));
+        assert!(msg.has_unknown_critical(&[option::URI_PATH]));
+    }
+
+    #[test]
+    fn extended_options() {
+        let long = [b'a'; 20];
+        let mut buf = [0; 64];
+        let mut writer = MessageWriter::new(&mut buf, MessageType::Reset, code::EMPTY, 1, &[]);
+        writer.option(300, &long);
+        let len = writer.finish().unwrap();
+        // Delta 300 and length 20 both need extended bytes
+        assert_eq!(&buf[4..8], &[0xed, 0x00, 31, 7]);
+        // Empty messages have no options
+        assert!(Message::decode(&buf[..len]).is_err());
+        buf[1] = code::CONTENT;
+        let msg = Message::decode(&buf[..len]).unwrap();
+        assert_eq!(msg.option(300), Some(&long[..]));
+    }
+
+    #[test]
+    fn malformed_messages() {
+        // Truncated option value
+        assert!(Message::decode(&[0x40, code::GET, 0, 1, 0xb4, b'a']).is_err());
+        // Marker without payload
+        assert!(Message::decode(&[0x40, code::GET, 0, 1, 0xff]).is_err());
+        // Reserved length nibble
+        assert!(Message::decode(&[0x40, code::GET, 0, 1, 0x1f]).is_err());
+        // Token longer than the message
+        assert!(Message::decode(&[0x44, code::GET, 0, 1, 1]).is_err());
+
+        let mut buf = [0; 8];
+        let mut writer = MessageWriter::new(&mut buf, MessageType::Confirmable, 1, 1, &[]);
+        writer
+            .option(option::URI_PATH, b"x")
+            .option(option::OBSERVE, &[]);
+        assert_eq!(writer.finish(), Err(ReturnCode::ESIZE));
+    }
+}
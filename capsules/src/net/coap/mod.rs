@@ -0,0 +1,8 @@
+pub mod driver;
+pub mod endpoint;
+pub mod message;
+#[cfg(all(test, feature = "process_mock"))]
+mod sim;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
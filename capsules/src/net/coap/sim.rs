@@ -0,0 +1,236 @@
+//! A simulated alarm and UDP socket, which the unit tests of the CoAP
+//! endpoint and driver run an endpoint on.
+
+extern crate std;
+
+use crate::net::coap::endpoint::{CoapEndpoint, COAP_PORT, MAX_MESSAGE_LEN};
+use crate::net::coap::message::{MessageType, MessageWriter};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use crate::net::udp::udp_port_table::UdpPortBindingTx;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::net::udp::UDPHeader;
+use core::cell::{Cell, RefCell};
+use kernel::capabilities::UdpDriverCapability;
+use kernel::common::cells::MapCell;
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::rng;
+use kernel::hil::time::{self, Alarm, AlarmClient, Freq1KHz, Ticks, Ticks32};
+use kernel::mock::MockNetworkCapability;
+use kernel::ReturnCode;
+use std::boxed::Box;
+use std::vec::Vec;
+
+/// The node the endpoint talks to.
+pub const PEER: IPAddr = IPAddr([
+    0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xfe, 0, 0, 0x02,
+]);
+pub const PEER_PORT: u16 = 5683;
+
+/// The first message ID and token of the endpoint.
+pub const FIRST_MESSAGE_ID: u16 = 0x1234;
+pub const FIRST_TOKEN: [u8; 4] = [0xde, 0xad, 0xbe, 0xef];
+
+/// An alarm whose time stands still; tests fire it themselves.
+pub struct SimAlarm {
+    alarm: Cell<Option<u32>>,
+}
+
+impl SimAlarm {
+    /// The delay the alarm is armed with.
+    pub fn dt(&self) -> Option<u32> {
+        self.alarm.get()
+    }
+}
+
+impl time::Time for SimAlarm {
+    type Frequency = Freq1KHz;
+    type Ticks = Ticks32;
+
+    fn now(&self) -> Ticks32 {
+        0.into()
+    }
+}
+
+impl<'a> Alarm<'a> for SimAlarm {
+    fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+    fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+        self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+    }
+
+    fn get_alarm(&self) -> Ticks32 {
+        self.alarm.get().unwrap_or(0).into()
+    }
+
+    fn disarm(&self) -> ReturnCode {
+        self.alarm.set(None);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_armed(&self) -> bool {
+        self.alarm.get().is_some()
+    }
+
+    fn minimum_dt(&self) -> Ticks32 {
+        1.into()
+    }
+}
+
+/// Logs the datagrams sent through it, and holds the buffer of the last
+/// one until the test completes its transmission.
+pub struct SimSender {
+    sent: RefCell<Vec<(IPAddr, u16, Vec<u8>)>>,
+    in_flight: MapCell<LeasableBuffer<'static, u8>>,
+}
+
+impl<'a> UDPSender<'a> for SimSender {
+    fn set_client(&self, _client: &'a dyn UDPSendClient) {}
+
+    fn send_to(
+        &'a self,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        if self.in_flight.is_some() {
+            return Err(buf);
+        }
+        self.sent
+            .borrow_mut()
+            .push((dest, dst_port, buf[..].to_vec()));
+        self.in_flight.replace(buf);
+        Ok(())
+    }
+
+    fn driver_send_to(
+        &'a self,
+        _dest: IPAddr,
+        _dst_port: u16,
+        _src_port: u16,
+        buf: LeasableBuffer<'static, u8>,
+        _driver_send_cap: &dyn UdpDriverCapability,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn send(
+        &'a self,
+        _dest: IPAddr,
+        _udp_header: UDPHeader,
+        buf: LeasableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn get_binding(&self) -> Option<UdpPortBindingTx> {
+        None
+    }
+
+    fn is_bound(&self) -> bool {
+        true
+    }
+
+    fn set_binding(&self, _binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+        None
+    }
+}
+
+/// An endpoint on a simulated network.
+pub struct Sim {
+    pub endpoint: &'static CoapEndpoint<'static, SimAlarm>,
+    pub alarm: &'static SimAlarm,
+    sender: &'static SimSender,
+}
+
+impl Sim {
+    /// Creates an endpoint, which refuses requests until `seed` is called.
+    pub fn new() -> Sim {
+        let alarm: &'static SimAlarm = Box::leak(Box::new(SimAlarm {
+            alarm: Cell::new(None),
+        }));
+        let sender: &'static SimSender = Box::leak(Box::new(SimSender {
+            sent: RefCell::new(Vec::new()),
+            in_flight: MapCell::empty(),
+        }));
+        let net_cap: &'static NetworkCapability = Box::leak(Box::new(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &MockNetworkCapability,
+        )));
+        let endpoint = Box::leak(Box::new(CoapEndpoint::new(
+            sender,
+            alarm,
+            net_cap,
+            Box::leak(Box::new([0; MAX_MESSAGE_LEN])),
+            Box::leak(Box::new([0; MAX_MESSAGE_LEN])),
+        )));
+        Sim {
+            endpoint,
+            alarm,
+            sender,
+        }
+    }
+
+    /// Delivers the seed, so that the first message ID and token are
+    /// `FIRST_MESSAGE_ID` and `FIRST_TOKEN`.
+    pub fn seed(&self) {
+        let words = [FIRST_MESSAGE_ID as u32, u32::from_be_bytes(FIRST_TOKEN), 7];
+        let more = rng::Client::randomness_available(
+            self.endpoint,
+            &mut words.iter().copied(),
+            ReturnCode::SUCCESS,
+        );
+        assert!(more == rng::Continue::Done);
+    }
+
+    /// Completes the transmissions of the endpoint, and returns the
+    /// messages sent since the last call. All of them go to the peer.
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        while let Some(buf) = self.sender.in_flight.take() {
+            self.endpoint.send_done(ReturnCode::SUCCESS, buf);
+        }
+        self.sender
+            .sent
+            .borrow_mut()
+            .drain(..)
+            .map(|(dst, port, message)| {
+                assert_eq!((dst, port), (PEER, PEER_PORT));
+                message
+            })
+            .collect()
+    }
+
+    /// Delivers `message` from the peer.
+    pub fn receive(&self, message: &[u8]) {
+        self.endpoint
+            .receive(PEER, IPAddr::new(), PEER_PORT, COAP_PORT, message);
+    }
+
+    /// Fires the alarm, which must be armed.
+    pub fn fire(&self) {
+        assert!(self.alarm.is_armed());
+        self.alarm.disarm();
+        self.endpoint.alarm();
+    }
+}
+
+/// Builds a message, whose options and payload are added by `build`.
+pub fn message<F: FnOnce(&mut MessageWriter)>(
+    mtype: MessageType,
+    code: u8,
+    message_id: u16,
+    token: &[u8],
+    build: F,
+) -> Vec<u8> {
+    let mut buf = [0; MAX_MESSAGE_LEN];
+    let mut writer = MessageWriter::new(&mut buf, mtype, code, message_id, token);
+    build(&mut writer);
+    let len = writer.finish().unwrap();
+    buf[..len].to_vec()
+}
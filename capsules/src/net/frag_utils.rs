@@ -1,4 +1,6 @@
-const BITMAP_SIZE: usize = 20;
+/// Size of the bitmap in bytes, each bit of which stands for 8 bytes of a
+/// datagram.
+pub const BITMAP_SIZE: usize = 20;
 
 pub struct Bitmap {
     map: [u8; BITMAP_SIZE],
@@ -37,43 +39,55 @@ impl Bitmap {
     // must be in 8-byte groups), and thus we can store 8*8 = 64 "bytes" per
     // byte in the bitmap.
     pub fn set_bits(&mut self, start_idx: usize, end_idx: usize) -> bool {
-        if start_idx > end_idx {
+        if start_idx > end_idx || end_idx > BITMAP_SIZE * 8 {
             return false;
         }
-        let start_byte_idx = start_idx / 8;
-        let end_byte_idx = end_idx / 8;
-        let first = 0xff << (start_idx % 8);
-        let second = if end_idx % 8 == 0 {
-            0x00
-        } else {
-            0xff >> (8 - (end_idx % 8))
-        };
-        if start_byte_idx == end_byte_idx {
-            let result = (self.map[start_byte_idx] & (first & second)) == 0;
-            self.map[start_byte_idx] |= first & second;
-            result
-        } else {
-            let mut result = (self.map[start_byte_idx] & first) == 0;
-            result = result && ((self.map[end_byte_idx] & second) == 0);
-            self.map[start_byte_idx] |= first;
-            self.map[end_byte_idx] |= second;
-            // Set all bytes between start and end bytes.
-            for i in start_byte_idx + 1..end_byte_idx {
-                result = result && (self.map[i] == 0);
-                self.map[i] = 0xff;
-            }
-            result
+        let mut result = true;
+        for idx in start_idx..end_idx {
+            let bit = 1 << (idx % 8);
+            result = result && (self.map[idx / 8] & bit) == 0;
+            self.map[idx / 8] |= bit;
         }
+        result
+    }
+
+    // Returns true if all bits from start_idx (inclusive) to end_idx
+    // (exclusive) are set.
+    pub fn is_set(&self, start_idx: usize, end_idx: usize) -> bool {
+        end_idx <= BITMAP_SIZE * 8
+            && (start_idx..end_idx).all(|idx| self.map[idx / 8] & (1 << (idx % 8)) != 0)
     }
 
+    // Returns true if exactly the first `total_length` bits are set.
     pub fn is_complete(&self, total_length: usize) -> bool {
-        let mut result = true;
-        for i in 0..total_length / 8 {
-            result = result && (self.map[i] == 0xff);
+        if total_length > BITMAP_SIZE * 8 {
+            return false;
         }
-        // Check last byte.
-        let mask = 0xff >> (8 - (total_length % 8));
-        result = result && (self.map[total_length / 8] == mask);
-        result
+        let full_bytes = total_length / 8;
+        let last_bits = total_length % 8;
+        self.map[..full_bytes].iter().all(|byte| *byte == 0xff)
+            && (last_bits == 0 || self.map[full_bytes] == 0xff >> (8 - last_bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitmap_bounds() {
+        let mut bitmap = Bitmap::new();
+        assert!(bitmap.set_bits(0, 12));
+        assert!(!bitmap.is_complete(16));
+        assert!(bitmap.set_bits(12, 16));
+        assert!(bitmap.is_complete(16));
+        assert!(!bitmap.set_bits(15, 17));
+        assert!(bitmap.is_set(4, 17) && !bitmap.is_set(4, 18));
+
+        bitmap.clear();
+        assert!(!bitmap.set_bits(150, BITMAP_SIZE * 8 + 1));
+        assert!(bitmap.set_bits(0, BITMAP_SIZE * 8));
+        assert!(bitmap.is_complete(BITMAP_SIZE * 8));
+        assert!(!bitmap.is_complete(BITMAP_SIZE * 8 + 1));
     }
 }
@@ -0,0 +1,235 @@
+//! ICMPv6 echo (ping) userspace interface.
+//!
+//! Lets processes send Echo Requests and learn the round trip time of the
+//! matching Echo Replies. The identifier of each request is derived from the
+//! process, and the sequence number is chosen by the process. A single
+//! request is outstanding at a time across all processes; a request that is
+//! not answered before its timeout is reported with `ENOACK`.
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Allow
+//!
+//! - `0`: The data to send in the Echo Request.
+//! - `1`: The 16 byte IPv6 destination address.
+//!
+//! ### Subscribe
+//!
+//! - `0`: Called once a request completes, with the status (`SUCCESS`,
+//!   `ENOACK` on timeout, or the error which prevented sending), the round
+//!   trip time in microseconds and the sequence number of the request.
+//!
+//! ### Command
+//!
+//! - `0`: Driver check.
+//! - `1`: Send an Echo Request.
+//!   - `arg1`: The sequence number, of which the lower 16 bits are used.
+//!   - `arg2`: The timeout in milliseconds, or `0` for `DEFAULT_TIMEOUT_MS`.
+//!   - Return: `SUCCESS` if the request was queued, `EINVAL` if no valid
+//!     destination was allowed, `ESIZE` if the data does not fit in a
+//!     message, and `EBUSY` if a request is already outstanding.
+
+use crate::net::icmpv6::icmpv6_responder::{ICMP6EchoClient, ICMP6EchoSender};
+use crate::net::ipv6::ip_utils::IPAddr;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Frequency, Ticks};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Ping as usize;
+
+/// Timeout used when a process does not pick one.
+pub const DEFAULT_TIMEOUT_MS: u32 = 1000;
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    data: Option<AppSlice<Shared, u8>>,
+    dst: Option<AppSlice<Shared, u8>>,
+}
+
+#[derive(Copy, Clone)]
+struct Request<T: Ticks> {
+    appid: AppId,
+    dst: IPAddr,
+    id: u16,
+    seqno: u16,
+    sent_at: T,
+}
+
+pub struct PingDriver<'a, A: time::Alarm<'a>> {
+    sender: &'a dyn ICMP6EchoSender<'a>,
+    alarm: &'a A,
+    apps: Grant<App>,
+    outstanding: OptionalCell<Request<A::Ticks>>,
+}
+
+impl<'a, A: time::Alarm<'a>> PingDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn ICMP6EchoSender<'a>,
+        alarm: &'a A,
+        grant: Grant<App>,
+    ) -> PingDriver<'a, A> {
+        PingDriver {
+            sender: sender,
+            alarm: alarm,
+            apps: grant,
+            outstanding: OptionalCell::empty(),
+        }
+    }
+
+    fn send(&self, appid: AppId, seqno: u16, timeout_ms: u32) -> ReturnCode {
+        if self.outstanding.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                let dst = match app.dst {
+                    Some(ref dst) if dst.len() == 16 => {
+                        let mut addr = IPAddr::new();
+                        addr.0.copy_from_slice(dst.as_ref());
+                        addr
+                    }
+                    _ => return ReturnCode::EINVAL,
+                };
+                let request = Request {
+                    appid: appid,
+                    dst: dst,
+                    id: appid.id() as u16,
+                    seqno: seqno,
+                    sent_at: self.alarm.now(),
+                };
+                // The request is recorded first, as the sender may report
+                // its completion before returning.
+                self.outstanding.set(request);
+                let data: &[u8] = app.data.as_ref().map_or(&[], |data| data.as_ref());
+                let ret = self.sender.send_echo_request(dst, request.id, seqno, data);
+                if ret == ReturnCode::SUCCESS {
+                    self.alarm
+                        .set_alarm(request.sent_at, A::ticks_from_ms(timeout_ms));
+                } else {
+                    self.outstanding.clear();
+                }
+                ret
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Completes the outstanding request and notifies its process.
+    fn complete(&self, status: ReturnCode, rtt_us: usize) {
+        self.outstanding.take().map(|request| {
+            self.alarm.disarm();
+            let _ = self.apps.enter(request.appid, |app, _| {
+                app.callback
+                    .map(|mut cb| cb.schedule(usize::from(status), rtt_us, request.seqno as usize));
+            });
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6EchoClient for PingDriver<'a, A> {
+    fn echo_request_sent(&self, result: ReturnCode) {
+        if result != ReturnCode::SUCCESS {
+            self.complete(result, 0);
+        }
+    }
+
+    fn echo_reply(&self, src: IPAddr, id: u16, seqno: u16, _data: &[u8]) {
+        let matches = self.outstanding.map_or(false, |request| {
+            request.id == id
+                && request.seqno == seqno
+                && (request.dst == src || request.dst.is_multicast())
+        });
+        if matches {
+            let sent_at = self.outstanding.map_or(self.alarm.now(), |r| r.sent_at);
+            let elapsed = self.alarm.now().wrapping_sub(sent_at).into_u32() as u64;
+            let rtt_us = elapsed * 1_000_000 / A::Frequency::frequency() as u64;
+            self.complete(ReturnCode::SUCCESS, rtt_us as usize);
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for PingDriver<'a, A> {
+    fn alarm(&self) {
+        self.complete(ReturnCode::ENOACK, 0);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> Driver for PingDriver<'a, A> {
+    /// Setup buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The data of the Echo Requests.
+    /// - `1`: The destination address, which must be 16 bytes long.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.data = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            1 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.dst = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Request completion callback.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Ping control.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send an Echo Request with sequence number `arg1`, timing out
+    ///        after `arg2` milliseconds (`0` selects the default).
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => {
+                let timeout_ms = if arg2 == 0 {
+                    DEFAULT_TIMEOUT_MS
+                } else {
+                    arg2 as u32
+                };
+                self.send(appid, arg1 as u16, timeout_ms)
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
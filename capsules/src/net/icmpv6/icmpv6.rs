@@ -19,10 +19,39 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    Type133 {
+        unused: u32,
+    },
+    Type134 {
+        hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+    },
+    Type135 {
+        unused: u32,
+    },
+    Type136 {
+        flags: u32,
+    },
+    /// RPL control messages have no fixed header fields after the checksum;
+    /// `base` holds the first four bytes of the message base.
+    Type155 {
+        base: u32,
+    },
 }
 
 #[derive(Copy, Clone)]
@@ -31,6 +60,11 @@ pub enum ICMP6Type {
     Type3,   // Time Exceeded
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
 impl ICMP6Header {
@@ -40,6 +74,15 @@ impl ICMP6Header {
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { unused: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { unused: 0 },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: 0 },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155 { base: 0 },
         };
 
         ICMP6Header {
@@ -51,12 +94,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(ICMP6Header::new(icmp_type).get_options());
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -81,6 +119,11 @@ impl ICMP6Header {
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 { .. } => ICMP6Type::Type155,
         }
     }
 
@@ -90,6 +133,31 @@ impl ICMP6Header {
             ICMP6Type::Type3 => 3,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
+        }
+    }
+
+    /// The four bytes following the checksum, whose meaning depends on the
+    /// type of the message.
+    pub fn get_type_specific_field(&self) -> u32 {
+        match self.options {
+            ICMP6HeaderOptions::Type1 { unused }
+            | ICMP6HeaderOptions::Type3 { unused }
+            | ICMP6HeaderOptions::Type133 { unused }
+            | ICMP6HeaderOptions::Type135 { unused } => unused,
+            ICMP6HeaderOptions::Type128 { id, seqno }
+            | ICMP6HeaderOptions::Type129 { id, seqno } => (id as u32) << 16 | seqno as u32,
+            ICMP6HeaderOptions::Type134 {
+                hop_limit,
+                flags,
+                router_lifetime,
+            } => (hop_limit as u32) << 24 | (flags as u32) << 16 | router_lifetime as u32,
+            ICMP6HeaderOptions::Type136 { flags } => flags,
+            ICMP6HeaderOptions::Type155 { base } => base,
         }
     }
 
@@ -131,16 +199,7 @@ impl ICMP6Header {
         off = enc_consume!(buf, off; encode_u8, self.code);
         off = enc_consume!(buf, off; encode_u16, self.cksum);
 
-        match self.options {
-            ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-                off = enc_consume!(buf, off; encode_u32, unused);
-            }
-            ICMP6HeaderOptions::Type128 { id, seqno }
-            | ICMP6HeaderOptions::Type129 { id, seqno } => {
-                off = enc_consume!(buf, off; encode_u16, id);
-                off = enc_consume!(buf, off; encode_u16, seqno);
-            }
-        }
+        off = enc_consume!(buf, off; encode_u32, self.get_type_specific_field());
 
         stream_done!(off, off);
     }
@@ -163,6 +222,11 @@ impl ICMP6Header {
             3 => ICMP6Type::Type3,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -171,34 +235,25 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
-
-        match icmp_type {
-            ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
-            }
-            ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
-                icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
-            }
-            ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
-            }
-            ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
-                icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
-            }
-        }
+        icmp_header.set_cksum(cksum);
+
+        let (off, field) = dec_try!(buf, off; decode_u32);
+        let (id, seqno) = ((field >> 16) as u16, field as u16);
+        icmp_header.set_options(match icmp_type {
+            ICMP6Type::Type1 => ICMP6HeaderOptions::Type1 { unused: field },
+            ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: field },
+            ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id, seqno },
+            ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id, seqno },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { unused: field },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                hop_limit: (field >> 24) as u8,
+                flags: (field >> 16) as u8,
+                router_lifetime: field as u16,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { unused: field },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: field },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155 { base: field },
+        });
 
         stream_done!(off, icmp_header);
     }
@@ -0,0 +1,456 @@
+//! This file contains an ICMPv6 responder, which answers Echo Requests
+//! addressed to the node and reports packets sent to closed UDP ports with
+//! Destination Unreachable messages.
+//!
+//! The responder sits between the UDP layer and the `IP6Sender`: it passes
+//! the packets of the layer above through to the `IP6Sender`, and sends its
+//! own ICMPv6 messages whenever the `IP6Sender` is not in use by that layer.
+//! It is set as the ICMPv6 client of the `IP6Receiver`, and as the error
+//! reporter of the `MuxUdpReceiver`.
+//!
+//! The responder can also send Echo Requests on behalf of an
+//! [ICMP6EchoClient](trait.ICMP6EchoClient.html), which receives the matching
+//! Echo Replies, and other ICMPv6 messages on behalf of an
+//! [ICMP6MessageClient](trait.ICMP6MessageClient.html), which receives the
+//! messages the responder does not handle itself, such as Neighbor Discovery.
+//! RPL control messages go to a client of their own, set with
+//! `set_rpl_client()`, so that routing and Neighbor Discovery can run side
+//! by side.
+//!
+//! The responder holds a single ICMPv6 message at a time. Echo Requests
+//! arriving while a message is pending are dropped, and Destination
+//! Unreachable messages are sent at most once every `ERROR_INTERVAL_MS`, as
+//! required by RFC 4443.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let icmp_responder = static_init!(
+//!     ICMP6Responder<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     ICMP6Responder::new(ip_send, alarm, &mut ICMP_BUF, net_cap)
+//! );
+//! ip_send.set_client(icmp_responder);
+//! ip_receive.set_icmp_client(icmp_responder);
+//! udp_recv_mux.set_error_reporter(icmp_responder);
+//! ```
+
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::time::{self, Ticks};
+use kernel::ReturnCode;
+
+/// Minimum time between two Destination Unreachable messages.
+pub const ERROR_INTERVAL_MS: u32 = 500;
+
+/// Destination Unreachable code for a closed port.
+const PORT_UNREACHABLE: u8 = 4;
+
+/// Length of the ICMPv6 header, up to and including the id and sequence
+/// number of echo messages.
+const ICMP_HDR_LEN: usize = 8;
+
+/// A trait for a client of an `ICMP6EchoSender`.
+pub trait ICMP6EchoClient {
+    /// Called once the Echo Request passed to `send_echo_request()` has been
+    /// sent, or could not be sent.
+    fn echo_request_sent(&self, result: ReturnCode);
+
+    /// Called when an Echo Reply is received.
+    ///
+    /// # Arguments
+    ///
+    /// `src` - The address the reply came from
+    /// `id` - The identifier of the reply
+    /// `seqno` - The sequence number of the reply
+    /// `data` - The data of the reply, copied from the request
+    fn echo_reply(&self, src: IPAddr, id: u16, seqno: u16, data: &[u8]);
+}
+
+/// A trait that defines an interface for sending Echo Requests.
+pub trait ICMP6EchoSender<'a> {
+    fn set_echo_client(&self, client: &'a dyn ICMP6EchoClient);
+
+    /// Sends an Echo Request carrying `data` to `dst`.
+    ///
+    /// # Return Value
+    ///
+    /// Returns EBUSY if an ICMPv6 message is already pending and ESIZE if
+    /// `data` does not fit in the message buffer. On SUCCESS, the client
+    /// receives `echo_request_sent()` once the request has been sent.
+    fn send_echo_request(&self, dst: IPAddr, id: u16, seqno: u16, data: &[u8]) -> ReturnCode;
+}
+
+/// A trait for a client of an `ICMP6MessageSender`.
+pub trait ICMP6MessageClient {
+    /// Called once the message passed to `send_message()` has been sent, or
+    /// could not be sent.
+    fn message_sent(&self, result: ReturnCode);
+
+    /// Called when a message other than an echo message is received.
+    /// `body` holds the message after the ICMPv6 header.
+    fn message_received(&self, ip_header: &IP6Header, icmp_header: ICMP6Header, body: &[u8]);
+}
+
+/// A trait that defines an interface for sending arbitrary ICMPv6 messages.
+pub trait ICMP6MessageSender<'a> {
+    fn set_message_client(&self, client: &'a dyn ICMP6MessageClient);
+
+    /// Sets the client receiving RPL control messages, and notified of the
+    /// RPL control messages it sends. Without one, they go to the client set
+    /// with `set_message_client()`.
+    fn set_rpl_client(&self, client: &'a dyn ICMP6MessageClient);
+
+    /// Sends a message with header `icmp_header`, followed by `body`, to
+    /// `dst`.
+    ///
+    /// # Return Value
+    ///
+    /// Returns EBUSY if an ICMPv6 message is already pending and ESIZE if
+    /// `body` does not fit in the message buffer. On SUCCESS, the client
+    /// receives `message_sent()` once the message has been sent.
+    fn send_message(&self, dst: IPAddr, icmp_header: ICMP6Header, body: &[u8]) -> ReturnCode;
+}
+
+/// A trait implemented by the ICMPv6 layer to report packets which could not
+/// be delivered.
+pub trait ICMP6ErrorReporter {
+    /// Reports that no one listens on the destination port of the transport
+    /// packet `packet`, received with the header `ip_header`.
+    fn port_unreachable(&self, ip_header: &IP6Header, packet: &[u8]);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum MessageKind {
+    EchoRequest,
+    EchoReply,
+    Error,
+    Message,
+    RplMessage,
+}
+
+#[derive(Copy, Clone)]
+struct Message {
+    dst: IPAddr,
+    header: ICMP6Header,
+    kind: MessageKind,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Owner {
+    Idle,
+    /// The `IP6Sender` is sending a packet of the layer above.
+    Upper,
+    /// The `IP6Sender` is sending a message of the responder.
+    Responder(MessageKind),
+}
+
+pub struct ICMP6Responder<'a, A: time::Alarm<'a>> {
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    echo_client: OptionalCell<&'a dyn ICMP6EchoClient>,
+    message_client: OptionalCell<&'a dyn ICMP6MessageClient>,
+    rpl_client: OptionalCell<&'a dyn ICMP6MessageClient>,
+    owner: Cell<Owner>,
+    /// Message waiting for the `IP6Sender`; its payload is in `buffer`.
+    pending: OptionalCell<Message>,
+    buffer: TakeCell<'static, [u8]>,
+    last_error: OptionalCell<A::Ticks>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6Responder<'a, A> {
+    pub fn new(
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> ICMP6Responder<'a, A> {
+        ICMP6Responder {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            echo_client: OptionalCell::empty(),
+            message_client: OptionalCell::empty(),
+            rpl_client: OptionalCell::empty(),
+            owner: Cell::new(Owner::Idle),
+            pending: OptionalCell::empty(),
+            buffer: TakeCell::new(buffer),
+            last_error: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Whether a message of the responder is pending or being sent.
+    fn busy(&self) -> bool {
+        self.pending.is_some()
+            || match self.owner.get() {
+                Owner::Responder(_) => true,
+                _ => false,
+            }
+    }
+
+    /// Copies the parts of `data` into the message buffer and queues the
+    /// message. Returns ESIZE if `data` does not fit.
+    fn queue(
+        &self,
+        dst: IPAddr,
+        mut header: ICMP6Header,
+        kind: MessageKind,
+        data: &[&[u8]],
+    ) -> ReturnCode {
+        if self.busy() {
+            return ReturnCode::EBUSY;
+        }
+        let copied = self.buffer.map_or(None, |buffer| {
+            let mut len = 0;
+            for part in data {
+                let n = core::cmp::min(part.len(), buffer.len() - len);
+                if n < part.len() && kind != MessageKind::Error {
+                    return None;
+                }
+                buffer[len..len + n].copy_from_slice(&part[..n]);
+                len += n;
+            }
+            Some(len)
+        });
+        match copied {
+            Some(len) => {
+                header.set_len((ICMP_HDR_LEN + len) as u16);
+                self.pending.set(Message { dst, header, kind });
+                if self.owner.get() == Owner::Idle {
+                    self.send_pending()
+                } else {
+                    ReturnCode::SUCCESS
+                }
+            }
+            None => ReturnCode::ESIZE,
+        }
+    }
+
+    /// Passes the pending message to the `IP6Sender`. The `IP6Sender` copies
+    /// the payload before `send_to()` returns, so the buffer is kept.
+    fn send_pending(&self) -> ReturnCode {
+        let message = match self.pending.take() {
+            Some(message) => message,
+            None => return ReturnCode::SUCCESS,
+        };
+        let payload_len = message.header.get_len() as usize - ICMP_HDR_LEN;
+        match self.buffer.take() {
+            Some(buffer) => {
+                self.owner.set(Owner::Responder(message.kind));
+                let mut payload = LeasableBuffer::new(buffer);
+                payload.slice(..payload_len);
+                let ret = self.ip_sender.send_to(
+                    message.dst,
+                    TransportHeader::ICMP(message.header),
+                    &payload,
+                    self.net_cap,
+                );
+                self.buffer.replace(payload.take());
+                if ret != ReturnCode::SUCCESS && self.owner.get() == Owner::Responder(message.kind)
+                {
+                    self.owner.set(Owner::Idle);
+                }
+                ret
+            }
+            None => ReturnCode::FAIL,
+        }
+    }
+
+    /// Tells the client of a message sent on its behalf that it was sent.
+    fn notify_sent(&self, kind: MessageKind, result: ReturnCode) {
+        match kind {
+            MessageKind::EchoRequest => {
+                self.echo_client
+                    .map(|client| client.echo_request_sent(result));
+            }
+            MessageKind::Message => {
+                self.message_client
+                    .map(|client| client.message_sent(result));
+            }
+            MessageKind::RplMessage => {
+                self.rpl_client.map(|client| client.message_sent(result));
+            }
+            MessageKind::EchoReply | MessageKind::Error => {}
+        }
+    }
+
+    fn reply_to_echo(&self, ip_header: &IP6Header, id: u16, seqno: u16, data: &[u8]) {
+        let src = ip_header.get_src_addr();
+        if src.is_unspecified() || src.is_multicast() {
+            return;
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type129);
+        header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+        // Requests arriving while a message is pending are dropped; the
+        // sender retransmits them.
+        let _ = self.queue(src, header, MessageKind::EchoReply, &[data]);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for ICMP6Responder<'a, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.ip_sender.set_addr(src_addr);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.ip_sender.set_gateway(gateway);
+    }
+
+    /// The header is also used for the responder's own messages.
+    fn set_header(&self, ip6_header: IP6Header) {
+        self.ip_sender.set_header(ip6_header);
+    }
+
+    /// Returns EBUSY while the responder is sending one of its own messages.
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if self.owner.get() != Owner::Idle {
+            return ReturnCode::EBUSY;
+        }
+        self.owner.set(Owner::Upper);
+        let ret = self
+            .ip_sender
+            .send_to(dst, transport_header, payload, net_cap);
+        if ret != ReturnCode::SUCCESS && self.owner.get() == Owner::Upper {
+            self.owner.set(Owner::Idle);
+        }
+        ret
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6SendClient for ICMP6Responder<'a, A> {
+    fn send_done(&self, result: ReturnCode) {
+        match self.owner.replace(Owner::Idle) {
+            Owner::Upper => {
+                self.client.map(|client| client.send_done(result));
+            }
+            Owner::Responder(kind) => self.notify_sent(kind, result),
+            Owner::Idle => {}
+        }
+
+        // The layer above goes first, so its queue may delay the pending
+        // message.
+        if self.owner.get() == Owner::Idle {
+            if let Some(kind) = self.pending.map(|message| message.kind) {
+                let ret = self.send_pending();
+                if ret != ReturnCode::SUCCESS {
+                    self.notify_sent(kind, ret);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6RecvClient for ICMP6Responder<'a, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let (offset, icmp_header) = match ICMP6Header::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.reply_to_echo(&ip_header, id, seqno, &payload[offset..]);
+            }
+            ICMP6HeaderOptions::Type129 { id, seqno } => {
+                self.echo_client.map(|client| {
+                    client.echo_reply(ip_header.get_src_addr(), id, seqno, &payload[offset..])
+                });
+            }
+            options => {
+                let client = match options {
+                    ICMP6HeaderOptions::Type155 { .. } if self.rpl_client.is_some() => {
+                        &self.rpl_client
+                    }
+                    _ => &self.message_client,
+                };
+                client.map(|client| {
+                    client.message_received(&ip_header, icmp_header, &payload[offset..])
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6EchoSender<'a> for ICMP6Responder<'a, A> {
+    fn set_echo_client(&self, client: &'a dyn ICMP6EchoClient) {
+        self.echo_client.set(client);
+    }
+
+    fn send_echo_request(&self, dst: IPAddr, id: u16, seqno: u16, data: &[u8]) -> ReturnCode {
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+        self.queue(dst, header, MessageKind::EchoRequest, &[data])
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6MessageSender<'a> for ICMP6Responder<'a, A> {
+    fn set_message_client(&self, client: &'a dyn ICMP6MessageClient) {
+        self.message_client.set(client);
+    }
+
+    fn set_rpl_client(&self, client: &'a dyn ICMP6MessageClient) {
+        self.rpl_client.set(client);
+    }
+
+    fn send_message(&self, dst: IPAddr, icmp_header: ICMP6Header, body: &[u8]) -> ReturnCode {
+        let kind = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type155 { .. } if self.rpl_client.is_some() => {
+                MessageKind::RplMessage
+            }
+            _ => MessageKind::Message,
+        };
+        self.queue(dst, icmp_header, kind, &[body])
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6ErrorReporter for ICMP6Responder<'a, A> {
+    /// Sends a Destination Unreachable message holding as much of the
+    /// offending packet as fits in the buffer. No error is sent in response
+    /// to a packet sent to a multicast address or from an address which
+    /// does not identify a single node.
+    fn port_unreachable(&self, ip_header: &IP6Header, packet: &[u8]) {
+        let src = ip_header.get_src_addr();
+        if ip_header.get_dst_addr().is_multicast() || src.is_multicast() || src.is_unspecified() {
+            return;
+        }
+        let now = self.alarm.now();
+        let limited = self.last_error.map_or(false, |last| {
+            now.wrapping_sub(*last) < A::ticks_from_ms(ERROR_INTERVAL_MS)
+        });
+        if limited {
+            return;
+        }
+
+        let mut encoded_header = [0; 40];
+        if ip_header.encode(&mut encoded_header).done().is_none() {
+            return;
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type1);
+        header.set_code(PORT_UNREACHABLE);
+        if self.queue(src, header, MessageKind::Error, &[&encoded_header, packet])
+            == ReturnCode::SUCCESS
+        {
+            self.last_error.set(now);
+        }
+    }
+}
@@ -1,4 +1,11 @@
+pub mod driver;
+pub mod icmpv6_responder;
 pub mod icmpv6_send;
+pub mod ndp;
+pub mod sixlowpan_nd;
+
+pub use self::driver::PingDriver;
+pub use self::driver::DRIVER_NUM;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::icmpv6::icmpv6::ICMP6Header`)
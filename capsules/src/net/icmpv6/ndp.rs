@@ -0,0 +1,417 @@
+//! IPv6 Neighbor Discovery (RFC 4861) messages and neighbor cache.
+//!
+//! Neighbor Discovery resolves the link-layer address of an on-link IPv6
+//! neighbor: a node multicasts a Neighbor Solicitation to the
+//! solicited-node address of the target, and the target answers with a
+//! Neighbor Advertisement carrying its link-layer address. This file
+//! provides the encoding and decoding of these two messages, and a small
+//! fixed-size cache of resolved neighbors. Sending and receiving the
+//! messages is up to the link adapter using them.
+//!
+//! Router Discovery and Redirect messages are not supported. The cache does
+//! not implement Neighbor Unreachability Detection: entries are refreshed by
+//! any solicitation or advertisement from the neighbor, and the least
+//! recently used entry is replaced when the cache is full.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+pub const NEIGHBOR_SOLICITATION: u8 = 135;
+pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+
+/// Neighbor Discovery messages must be sent with this hop limit, and
+/// received ones with any other hop limit are discarded, so that they are
+/// known to originate from the link.
+pub const HOP_LIMIT: u8 = 255;
+
+/// Number of solicitations sent before resolution is given up.
+pub const MAX_MULTICAST_SOLICIT: u8 = 3;
+/// Time between solicitations, in milliseconds.
+pub const RETRANS_TIMER_MS: u32 = 1000;
+
+/// Number of neighbors the cache holds.
+pub const NEIGHBOR_CACHE_SIZE: usize = 8;
+
+const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_ADDRESS: u8 = 2;
+
+const FLAG_ROUTER: u32 = 1 << 31;
+const FLAG_SOLICITED: u32 = 1 << 30;
+const FLAG_OVERRIDE: u32 = 1 << 29;
+
+/// Length of a solicitation or advertisement without options.
+const MESSAGE_LEN: usize = 24;
+
+/// The link-nodes multicast address, ff02::1.
+pub const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+/// The solicited-node multicast address of `addr`, ff02::1:ffXX:XXXX, to
+/// which solicitations for `addr` are sent.
+pub fn solicited_node(addr: &IPAddr) -> IPAddr {
+    let mut solicited = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 0]);
+    solicited.0[13..16].copy_from_slice(&addr.0[13..16]);
+    solicited
+}
+
+/// A link-layer address carried in a Neighbor Discovery option. Ethernet
+/// uses 6 byte addresses, IEEE 802.15.4 8 byte extended addresses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LinkAddress {
+    len: usize,
+    bytes: [u8; 8],
+}
+
+impl LinkAddress {
+    /// Returns `None` if `addr` is empty or longer than 8 bytes.
+    pub fn new(addr: &[u8]) -> Option<LinkAddress> {
+        if addr.is_empty() || addr.len() > 8 {
+            return None;
+        }
+        let mut bytes = [0; 8];
+        bytes[..addr.len()].copy_from_slice(addr);
+        Some(LinkAddress {
+            len: addr.len(),
+            bytes: bytes,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Length of the option carrying this address, a multiple of 8 bytes.
+    fn option_len(&self) -> usize {
+        (2 + self.len + 7) / 8 * 8
+    }
+}
+
+/// A Neighbor Solicitation or Neighbor Advertisement.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NdMessage {
+    Solicitation {
+        /// Address being resolved.
+        target: IPAddr,
+        /// Link-layer address of the sender. Must be omitted when the
+        /// packet is sent from the unspecified address.
+        source: Option<LinkAddress>,
+    },
+    Advertisement {
+        /// Address whose link-layer address is advertised.
+        target: IPAddr,
+        /// The sender is a router.
+        router: bool,
+        /// The advertisement answers a solicitation.
+        solicited: bool,
+        /// The advertisement replaces cached link-layer addresses.
+        override_: bool,
+        /// Link-layer address of the target.
+        link_address: Option<LinkAddress>,
+    },
+}
+
+impl NdMessage {
+    pub fn target(&self) -> IPAddr {
+        match *self {
+            NdMessage::Solicitation { target, .. } => target,
+            NdMessage::Advertisement { target, .. } => target,
+        }
+    }
+
+    /// The link-layer address carried by the message, if any.
+    pub fn link_address(&self) -> Option<LinkAddress> {
+        match *self {
+            NdMessage::Solicitation { source, .. } => source,
+            NdMessage::Advertisement { link_address, .. } => link_address,
+        }
+    }
+
+    /// Length of the encoded message.
+    pub fn len(&self) -> usize {
+        MESSAGE_LEN
+            + self
+                .link_address()
+                .map_or(0, |link_address| link_address.option_len())
+    }
+
+    /// Serializes the message into `buf`, with a zero checksum. The checksum
+    /// is filled in with `compute_icmp_message_checksum()` once the IPv6
+    /// header is known.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let (msg_type, flags, option_type) = match *self {
+            NdMessage::Solicitation { .. } => {
+                (NEIGHBOR_SOLICITATION, 0, OPTION_SOURCE_LINK_ADDRESS)
+            }
+            NdMessage::Advertisement {
+                router,
+                solicited,
+                override_,
+                ..
+            } => {
+                let mut flags = 0;
+                if router {
+                    flags |= FLAG_ROUTER;
+                }
+                if solicited {
+                    flags |= FLAG_SOLICITED;
+                }
+                if override_ {
+                    flags |= FLAG_OVERRIDE;
+                }
+                (NEIGHBOR_ADVERTISEMENT, flags, OPTION_TARGET_LINK_ADDRESS)
+            }
+        };
+        stream_len_cond!(buf, self.len());
+
+        let mut off = enc_consume!(buf, 0; encode_u8, msg_type);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u16, 0);
+        off = enc_consume!(buf, off; encode_u32, flags);
+        off = enc_consume!(buf, off; encode_bytes, &self.target().0);
+        if let Some(link_address) = self.link_address() {
+            let option_len = link_address.option_len();
+            off = enc_consume!(buf, off; encode_u8, option_type);
+            off = enc_consume!(buf, off; encode_u8, (option_len / 8) as u8);
+            let end = off + option_len - 2;
+            buf[off..end].iter_mut().for_each(|byte| *byte = 0);
+            enc_consume!(buf, off; encode_bytes, link_address.as_slice());
+            off = end;
+        }
+        stream_done!(off, off);
+    }
+
+    /// Parses a solicitation or advertisement from `buf`, which holds the
+    /// whole ICMPv6 message. The checksum and hop limit are not verified.
+    pub fn decode(buf: &[u8]) -> SResult<NdMessage> {
+        stream_len_cond!(buf, MESSAGE_LEN);
+        let (off, msg_type) = dec_try!(buf, 0; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        stream_cond!(code == 0);
+        let off = off + 2;
+        let (off, flags) = dec_try!(buf, off; decode_u32);
+        let mut target = IPAddr::new();
+        let mut off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+        stream_cond!(!target.is_multicast());
+
+        let wanted_option = match msg_type {
+            NEIGHBOR_SOLICITATION => OPTION_SOURCE_LINK_ADDRESS,
+            NEIGHBOR_ADVERTISEMENT => OPTION_TARGET_LINK_ADDRESS,
+            _ => stream_err!(),
+        };
+
+        // Options are type-length-value, with the length in units of 8 bytes
+        // including the type and length. Options with a zero length are
+        // invalid, and unknown options are skipped.
+        let mut link_address = None;
+        while off < buf.len() {
+            stream_len_cond!(buf, off + 2);
+            let option_type = buf[off];
+            let option_len = buf[off + 1] as usize * 8;
+            stream_cond!(option_len != 0);
+            stream_len_cond!(buf, off + option_len);
+            if option_type == wanted_option {
+                // Link-layer addresses are padded with zeroes to the end of
+                // the option. Ethernet addresses fit in one unit, 802.15.4
+                // extended addresses take two.
+                let addr_len = if option_len == 8 { 6 } else { 8 };
+                link_address = LinkAddress::new(&buf[off + 2..off + 2 + addr_len]);
+            }
+            off += option_len;
+        }
+
+        let msg = if msg_type == NEIGHBOR_SOLICITATION {
+            NdMessage::Solicitation {
+                target: target,
+                source: link_address,
+            }
+        } else {
+            NdMessage::Advertisement {
+                target: target,
+                router: flags & FLAG_ROUTER != 0,
+                solicited: flags & FLAG_SOLICITED != 0,
+                override_: flags & FLAG_OVERRIDE != 0,
+                link_address: link_address,
+            }
+        };
+        stream_done!(off, msg);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Neighbor<L: Copy> {
+    ip: IPAddr,
+    link_address: L,
+    last_used: u32,
+}
+
+/// Fixed-size cache mapping IPv6 addresses of neighbors to their
+/// link-layer addresses of type `L`.
+pub struct NeighborCache<L: Copy> {
+    entries: [Option<Neighbor<L>>; NEIGHBOR_CACHE_SIZE],
+    clock: u32,
+}
+
+impl<L: Copy> NeighborCache<L> {
+    pub fn new() -> NeighborCache<L> {
+        NeighborCache {
+            entries: [None; NEIGHBOR_CACHE_SIZE],
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u32 {
+        self.clock = self.clock.wrapping_add(1);
+        self.clock
+    }
+
+    /// The link-layer address of `ip`, if it is known.
+    pub fn lookup(&mut self, ip: &IPAddr) -> Option<L> {
+        let now = self.tick();
+        self.entries
+            .iter_mut()
+            .filter_map(|entry| entry.as_mut())
+            .find(|neighbor| neighbor.ip == *ip)
+            .map(|neighbor| {
+                neighbor.last_used = now;
+                neighbor.link_address
+            })
+    }
+
+    /// Record the link-layer address of `ip`, replacing the least recently
+    /// used neighbor if the cache is full.
+    pub fn insert(&mut self, ip: IPAddr, link_address: L) {
+        if self.update(&ip, link_address) {
+            return;
+        }
+        let now = self.tick();
+        let slot = match self.entries.iter().position(|entry| entry.is_none()) {
+            Some(free) => free,
+            None => {
+                let mut oldest = 0;
+                for (i, entry) in self.entries.iter().enumerate() {
+                    let age = |index: usize| {
+                        self.entries[index]
+                            .map_or(0, |neighbor| now.wrapping_sub(neighbor.last_used))
+                    };
+                    if entry.is_some() && age(i) > age(oldest) {
+                        oldest = i;
+                    }
+                }
+                oldest
+            }
+        };
+        self.entries[slot] = Some(Neighbor {
+            ip: ip,
+            link_address: link_address,
+            last_used: now,
+        });
+    }
+
+    /// Change the link-layer address of `ip` if it is in the cache. Returns
+    /// whether it was.
+    pub fn update(&mut self, ip: &IPAddr, link_address: L) -> bool {
+        let now = self.tick();
+        self.entries
+            .iter_mut()
+            .filter_map(|entry| entry.as_mut())
+            .find(|neighbor| neighbor.ip == *ip)
+            .map(|neighbor| {
+                neighbor.link_address = link_address;
+                neighbor.last_used = now;
+            })
+            .is_some()
+    }
+
+    /// Drop `ip` from the cache. Returns whether it was in it.
+    pub fn remove(&mut self, ip: &IPAddr) -> bool {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.map_or(false, |neighbor| neighbor.ip == *ip))
+            .map(|entry| *entry = None)
+            .is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = [None; NEIGHBOR_CACHE_SIZE];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv6::ip_utils::compute_icmp_message_checksum;
+    use crate::net::ipv6::IP6Header;
+
+    fn addr(last: u8) -> IPAddr {
+        let mut ip = IPAddr::new();
+        ip.set_unicast_link_local();
+        ip.0[15] = last;
+        ip
+    }
+
+    #[test]
+    fn solicitation_round_trip() {
+        let msg = NdMessage::Solicitation {
+            target: addr(2),
+            source: LinkAddress::new(&[0x10, 0xe2, 0xd5, 0, 0, 1]),
+        };
+        let mut buf = [0xaa; 40];
+        let len = msg.encode(&mut buf).done().unwrap().0;
+        assert_eq!(len, 32);
+        assert_eq!(&buf[24..32], &[1, 1, 0x10, 0xe2, 0xd5, 0, 0, 1]);
+
+        let mut header = IP6Header::new();
+        header.src_addr = addr(1);
+        header.dst_addr = solicited_node(&addr(2));
+        let cksum = compute_icmp_message_checksum(&header, &buf[..len]);
+        buf[2..4].copy_from_slice(&cksum.to_be_bytes());
+        assert_eq!(compute_icmp_message_checksum(&header, &buf[..len]), 0);
+
+        assert_eq!(NdMessage::decode(&buf[..len]).done().unwrap().1, msg);
+        // Zero-length options are invalid
+        buf[25] = 0;
+        assert!(NdMessage::decode(&buf[..len]).done().is_none());
+    }
+
+    #[test]
+    fn advertisement_flags_and_solicited_node() {
+        let msg = NdMessage::Advertisement {
+            target: addr(7),
+            router: false,
+            solicited: true,
+            override_: true,
+            link_address: LinkAddress::new(&[1, 2, 3, 4, 5, 6, 7, 8]),
+        };
+        let mut buf = [0; 40];
+        let len = msg.encode(&mut buf).done().unwrap().0;
+        assert_eq!(len, 40);
+        assert_eq!(buf[4], 0x60);
+        assert_eq!(NdMessage::decode(&buf[..len]).done().unwrap().1, msg);
+
+        let mut ip = addr(0);
+        ip.0[13..16].copy_from_slice(&[0xab, 0xcd, 0xef]);
+        assert_eq!(
+            solicited_node(&ip).0,
+            [0xff, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0xff, 0xab, 0xcd, 0xef]
+        );
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = NeighborCache::new();
+        for i in 0..NEIGHBOR_CACHE_SIZE as u8 {
+            cache.insert(addr(i), i);
+        }
+        // Touch the oldest entry, so that the second oldest goes
+        assert_eq!(cache.lookup(&addr(0)), Some(0));
+        cache.insert(addr(100), 100);
+        assert_eq!(cache.lookup(&addr(1)), None);
+        assert_eq!(cache.lookup(&addr(0)), Some(0));
+        assert_eq!(cache.lookup(&addr(100)), Some(100));
+
+        assert!(cache.update(&addr(100), 42));
+        assert!(!cache.update(&addr(1), 42));
+        assert_eq!(cache.lookup(&addr(100)), Some(42));
+    }
+}
@@ -0,0 +1,813 @@
+//! Neighbor Discovery for hosts on 6LoWPAN networks (RFC 6775).
+//!
+//! 6LoWPAN-ND replaces the multicast-heavy parts of IPv6 Neighbor Discovery
+//! with exchanges between hosts and their routers:
+//!
+//! - The host multicasts Router Solicitations until a router answers with a
+//!   Router Advertisement. The advertising router becomes the default
+//!   router, and unicast packets are sent to its link-layer address.
+//! - The host forms an address from each autonomous /64 prefix of the
+//!   advertisement and its interface identifier (SLAAC), and registers it
+//!   with the default router in a Neighbor Solicitation carrying an Address
+//!   Registration Option. The router answers with a Neighbor Advertisement
+//!   holding the registration status, which doubles as duplicate address
+//!   detection.
+//! - Registrations are refreshed before their lifetime runs out, and the
+//!   address stays in use until the router rejects it. A router which stops
+//!   answering is forgotten, and the host goes back to soliciting routers.
+//!
+//! Link-local addresses are derived from the link-layer address, so they are
+//! neither registered nor resolved: the link-layer address of a neighbor is
+//! taken from the options of its messages, or from its link-local address.
+//!
+//! The registered address becomes the source address of the `IP6Sender`;
+//! while no address is registered the link-local address is used. Only one
+//! prefix is configured at a time, and the 6LoWPAN Context Option and the
+//! Authoritative Border Router Option are ignored.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sixlowpan_nd = static_init!(
+//!     SixlowpanNd<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     SixlowpanNd::new(icmp_responder, icmp_responder, nd_alarm, src_mac_addr)
+//! );
+//! nd_alarm.set_alarm_client(sixlowpan_nd);
+//! icmp_responder.set_message_client(sixlowpan_nd);
+//! sixlowpan_nd.start();
+//! ```
+
+use crate::net::icmpv6::icmpv6_responder::{ICMP6MessageClient, ICMP6MessageSender};
+use crate::net::icmpv6::ndp::{self, NeighborCache};
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::IP6Sender;
+use crate::net::ipv6::IP6Header;
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell};
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// The link-local all-routers multicast address, ff02::2.
+pub const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// Number of Router Solicitations sent `RTR_SOLICITATION_INTERVAL_MS` apart
+/// before the interval starts doubling.
+pub const MAX_RTR_SOLICITATIONS: u8 = 3;
+pub const RTR_SOLICITATION_INTERVAL_MS: u32 = 10_000;
+pub const MAX_RTR_SOLICITATION_INTERVAL_MS: u32 = 60_000;
+
+/// Number of registrations sent to a router before it is considered
+/// unreachable.
+pub const MAX_UNICAST_SOLICIT: u8 = 3;
+
+/// Lifetime requested for registrations, in minutes.
+pub const REGISTRATION_LIFETIME_MIN: u16 = 15;
+/// Time after which registrations are refreshed, three quarters of their
+/// lifetime.
+const REGISTRATION_REFRESH_MS: u32 = REGISTRATION_LIFETIME_MIN as u32 * 60_000 / 4 * 3;
+
+const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+const OPTION_TARGET_LINK_ADDRESS: u8 = 2;
+const OPTION_PREFIX_INFORMATION: u8 = 3;
+const OPTION_ADDRESS_REGISTRATION: u8 = 33;
+
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+const NA_FLAG_SOLICITED: u32 = 1 << 30;
+const NA_FLAG_OVERRIDE: u32 = 1 << 29;
+
+/// Status of an address registration, carried in the Address Registration
+/// Option of the router's Neighbor Advertisement.
+pub mod aro_status {
+    pub const SUCCESS: u8 = 0;
+    pub const DUPLICATE: u8 = 1;
+    pub const NEIGHBOR_CACHE_FULL: u8 = 2;
+}
+
+/// An on-link prefix announced in a Prefix Information Option.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Prefix {
+    pub prefix: IPAddr,
+    pub len: u8,
+    /// The prefix can be used to form addresses.
+    pub autonomous: bool,
+    /// Seconds the prefix stays valid.
+    pub valid_lifetime: u32,
+}
+
+/// The parts of a Router Advertisement a host acts upon.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RouterAdvertisement {
+    /// Seconds the router can be used as default router; zero if it must
+    /// not be.
+    pub router_lifetime: u16,
+    pub link_address: Option<MacAddress>,
+    /// The first autonomous /64 prefix of the advertisement.
+    pub prefix: Option<Prefix>,
+}
+
+/// An Address Registration Option.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressRegistration {
+    pub status: u8,
+    /// Lifetime of the registration, in minutes.
+    pub lifetime: u16,
+    pub eui64: [u8; 8],
+}
+
+/// Calls `f` with the type and the contents of each option in `buf`, after
+/// the type and length bytes. Returns `None` if an option is malformed.
+fn for_each_option<F: FnMut(u8, &[u8])>(buf: &[u8], mut f: F) -> Option<()> {
+    let mut off = 0;
+    while off < buf.len() {
+        if buf.len() < off + 2 {
+            return None;
+        }
+        let option_len = buf[off + 1] as usize * 8;
+        if option_len == 0 || buf.len() < off + option_len {
+            return None;
+        }
+        f(buf[off], &buf[off + 2..off + option_len]);
+        off += option_len;
+    }
+    Some(())
+}
+
+/// Reads an 802.15.4 address from a link-layer address option, which holds
+/// a short address in one unit of 8 bytes and an extended one in two.
+fn decode_link_address(contents: &[u8]) -> Option<MacAddress> {
+    match contents.len() {
+        6 => Some(MacAddress::Short(u16::from_be_bytes([
+            contents[0],
+            contents[1],
+        ]))),
+        14 => {
+            let mut long = [0; 8];
+            long.copy_from_slice(&contents[..8]);
+            Some(MacAddress::Long(long))
+        }
+        _ => None,
+    }
+}
+
+/// Writes a link-layer address option of type `option_type` holding `addr`
+/// into `buf`, and returns its length.
+fn encode_link_address(buf: &mut [u8], option_type: u8, addr: MacAddress) -> usize {
+    let short;
+    let (len, bytes): (usize, &[u8]) = match addr {
+        MacAddress::Short(addr) => {
+            short = addr.to_be_bytes();
+            (8, &short)
+        }
+        MacAddress::Long(ref long) => (16, long),
+    };
+    buf[..len].iter_mut().for_each(|byte| *byte = 0);
+    buf[0] = option_type;
+    buf[1] = (len / 8) as u8;
+    buf[2..2 + bytes.len()].copy_from_slice(bytes);
+    len
+}
+
+/// Writes an Address Registration Option into `buf`, and returns its length.
+fn encode_address_registration(buf: &mut [u8], registration: &AddressRegistration) -> usize {
+    buf[..16].iter_mut().for_each(|byte| *byte = 0);
+    buf[0] = OPTION_ADDRESS_REGISTRATION;
+    buf[1] = 2;
+    buf[2] = registration.status;
+    buf[6..8].copy_from_slice(&registration.lifetime.to_be_bytes());
+    buf[8..16].copy_from_slice(&registration.eui64);
+    16
+}
+
+/// The 802.15.4 address a link-local address was derived from, following
+/// RFC 6282 section 3.2.2.
+pub fn link_address_from_link_local(addr: &IPAddr) -> Option<MacAddress> {
+    if !addr.is_unicast_link_local() {
+        return None;
+    }
+    let iid = &addr.0[8..16];
+    if iid[..6] == [0, 0, 0, 0xff, 0xfe, 0] {
+        Some(MacAddress::Short(u16::from_be_bytes([iid[6], iid[7]])))
+    } else {
+        let mut long = [0; 8];
+        long.copy_from_slice(iid);
+        long[0] ^= 0b00000010;
+        Some(MacAddress::Long(long))
+    }
+}
+
+/// Parses the body of a Router Advertisement, which follows the ICMPv6
+/// header: the reachable time, the retransmission timer and the options.
+pub fn decode_router_advertisement(
+    icmp_header: &ICMP6Header,
+    body: &[u8],
+) -> Option<RouterAdvertisement> {
+    let router_lifetime = match icmp_header.get_options() {
+        ICMP6HeaderOptions::Type134 {
+            router_lifetime, ..
+        } => router_lifetime,
+        _ => return None,
+    };
+    if icmp_header.get_code() != 0 || body.len() < 8 {
+        return None;
+    }
+    let mut ra = RouterAdvertisement {
+        router_lifetime: router_lifetime,
+        link_address: None,
+        prefix: None,
+    };
+    for_each_option(&body[8..], |option_type, contents| match option_type {
+        OPTION_SOURCE_LINK_ADDRESS => ra.link_address = decode_link_address(contents),
+        OPTION_PREFIX_INFORMATION if contents.len() == 30 && ra.prefix.is_none() => {
+            let mut prefix = IPAddr::new();
+            prefix.0.copy_from_slice(&contents[14..30]);
+            let pio = Prefix {
+                prefix: prefix,
+                len: contents[0],
+                autonomous: contents[1] & PREFIX_FLAG_AUTONOMOUS != 0,
+                valid_lifetime: u32::from_be_bytes([
+                    contents[2],
+                    contents[3],
+                    contents[4],
+                    contents[5],
+                ]),
+            };
+            if pio.autonomous && pio.len == 64 && !pio.prefix.is_unicast_link_local() {
+                ra.prefix = Some(pio);
+            }
+        }
+        _ => {}
+    })?;
+    Some(ra)
+}
+
+/// Parses the body of a Neighbor Solicitation or Advertisement into the
+/// target address and the Address Registration Option, if any.
+pub fn decode_neighbor_message(body: &[u8]) -> Option<(IPAddr, Option<AddressRegistration>)> {
+    if body.len() < 16 {
+        return None;
+    }
+    let mut target = IPAddr::new();
+    target.0.copy_from_slice(&body[..16]);
+    let mut registration = None;
+    for_each_option(&body[16..], |option_type, contents| {
+        if option_type == OPTION_ADDRESS_REGISTRATION && contents.len() == 14 {
+            let mut eui64 = [0; 8];
+            eui64.copy_from_slice(&contents[6..14]);
+            registration = Some(AddressRegistration {
+                status: contents[0],
+                lifetime: u16::from_be_bytes([contents[4], contents[5]]),
+                eui64: eui64,
+            });
+        }
+    })?;
+    Some((target, registration))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Looking for a router; `count` solicitations were sent.
+    Soliciting {
+        count: u8,
+    },
+    /// Registering the configured address with the default router; `count`
+    /// registrations were sent.
+    Registering {
+        count: u8,
+    },
+    /// The address is registered, or there is a router but no prefix.
+    Registered,
+    /// Refreshing the registration of the address, which stays in use
+    /// until the router rejects it; `count` registrations were sent.
+    Refreshing {
+        count: u8,
+    },
+    /// The router reported the configured address as a duplicate.
+    Duplicate,
+}
+
+pub struct SixlowpanNd<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6MessageSender<'a>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    mac_addr: MacAddress,
+    link_local: IPAddr,
+    state: Cell<State>,
+    neighbors: MapCell<NeighborCache<MacAddress>>,
+    /// Link-local address of the default router.
+    default_router: OptionalCell<IPAddr>,
+    /// Address formed from the prefix of the default router.
+    address: OptionalCell<IPAddr>,
+}
+
+impl<'a, A: time::Alarm<'a>> SixlowpanNd<'a, A> {
+    /// `ip_sender` is the sender whose source address and gateway are kept
+    /// up to date. Messages are sent through `icmp_sender`, which should
+    /// share the same `IP6Sender`.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6MessageSender<'a>,
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        mac_addr: MacAddress,
+    ) -> SixlowpanNd<'a, A> {
+        SixlowpanNd {
+            icmp_sender: icmp_sender,
+            ip_sender: ip_sender,
+            alarm: alarm,
+            mac_addr: mac_addr,
+            link_local: IPAddr::generate_from_mac(mac_addr),
+            state: Cell::new(State::Idle),
+            neighbors: MapCell::new(NeighborCache::new()),
+            default_router: OptionalCell::empty(),
+            address: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts looking for routers, using the link-local address as source
+    /// address in the meantime.
+    pub fn start(&self) {
+        if self.state.get() == State::Idle {
+            self.ip_sender.set_addr(self.link_local);
+            self.solicit_router();
+        }
+    }
+
+    /// The registered address, if any.
+    pub fn address(&self) -> Option<IPAddr> {
+        match self.state.get() {
+            State::Registered | State::Refreshing { .. } => self.address.map(|addr| *addr),
+            _ => None,
+        }
+    }
+
+    /// The link-local address of the default router, if any.
+    pub fn default_router(&self) -> Option<IPAddr> {
+        self.default_router.map(|router| *router)
+    }
+
+    /// The link-layer address of the neighbor `addr`, if it is known.
+    pub fn lookup(&self, addr: &IPAddr) -> Option<MacAddress> {
+        self.neighbors
+            .map_or(None, |neighbors| neighbors.lookup(addr))
+            .or_else(|| link_address_from_link_local(addr))
+    }
+
+    /// The EUI-64 the configured address is registered for. Short addresses
+    /// use the interface identifier derived from them.
+    fn eui64(&self) -> [u8; 8] {
+        match self.mac_addr {
+            MacAddress::Long(long) => long,
+            MacAddress::Short(_) => {
+                let mut eui64 = [0; 8];
+                eui64.copy_from_slice(&self.link_local.0[8..16]);
+                eui64
+            }
+        }
+    }
+
+    fn arm(&self, ms: u32) {
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(ms));
+    }
+
+    /// Sends a Router Solicitation and schedules the next one.
+    fn solicit_router(&self) {
+        let count = match self.state.get() {
+            State::Soliciting { count } => count,
+            _ => 0,
+        };
+        let sent = count.saturating_add(1);
+        let mut body = [0; 16];
+        let len = encode_link_address(&mut body, OPTION_SOURCE_LINK_ADDRESS, self.mac_addr);
+        let _ = self.icmp_sender.send_message(
+            ALL_ROUTERS,
+            ICMP6Header::new(ICMP6Type::Type133),
+            &body[..len],
+        );
+
+        self.state.set(State::Soliciting { count: sent });
+        let interval = if sent < MAX_RTR_SOLICITATIONS {
+            RTR_SOLICITATION_INTERVAL_MS
+        } else {
+            let doublings = (sent - MAX_RTR_SOLICITATIONS).min(3);
+            (RTR_SOLICITATION_INTERVAL_MS << doublings).min(MAX_RTR_SOLICITATION_INTERVAL_MS)
+        };
+        self.arm(interval);
+    }
+
+    /// Sends a registration of the configured address to the default router
+    /// and schedules its retransmission. `refresh` is set when the address
+    /// is already registered.
+    fn register(&self, refresh: bool, count: u8) {
+        let (router, address) = match (self.default_router.map(|r| *r), self.address.map(|a| *a)) {
+            (Some(router), Some(address)) => (router, address),
+            _ => return,
+        };
+        let mut body = [0; 48];
+        body[..16].copy_from_slice(&address.0);
+        let mut len = 16;
+        len += encode_link_address(&mut body[len..], OPTION_SOURCE_LINK_ADDRESS, self.mac_addr);
+        len += encode_address_registration(
+            &mut body[len..],
+            &AddressRegistration {
+                status: aro_status::SUCCESS,
+                lifetime: REGISTRATION_LIFETIME_MIN,
+                eui64: self.eui64(),
+            },
+        );
+        let _ = self.icmp_sender.send_message(
+            router,
+            ICMP6Header::new(ICMP6Type::Type135),
+            &body[..len],
+        );
+        self.state.set(if refresh {
+            State::Refreshing { count: count + 1 }
+        } else {
+            State::Registering { count: count + 1 }
+        });
+        self.arm(ndp::RETRANS_TIMER_MS);
+    }
+
+    /// Drops the default router and the address configured from its prefix,
+    /// and looks for another router.
+    fn forget_router(&self) {
+        if let Some(router) = self.default_router.take() {
+            self.neighbors.map(|neighbors| neighbors.remove(&router));
+        }
+        self.address.clear();
+        self.ip_sender.set_addr(self.link_local);
+        self.state.set(State::Soliciting { count: 0 });
+        self.solicit_router();
+    }
+
+    fn receive_router_advertisement(&self, src: IPAddr, ra: RouterAdvertisement) {
+        let link_address = match ra
+            .link_address
+            .or_else(|| link_address_from_link_local(&src))
+        {
+            Some(link_address) => link_address,
+            None => return,
+        };
+        self.neighbors
+            .map(|neighbors| neighbors.insert(src, link_address));
+
+        let is_default = self.default_router.map_or(false, |router| *router == src);
+        if ra.router_lifetime == 0 {
+            if is_default {
+                self.forget_router();
+            }
+            return;
+        }
+        if !is_default && self.default_router.is_some() {
+            return;
+        }
+        self.default_router.set(src);
+        self.ip_sender.set_gateway(link_address);
+
+        match ra.prefix {
+            Some(prefix) if prefix.valid_lifetime != 0 => {
+                let mut address = self.link_local;
+                address.set_prefix(&prefix.prefix.0, prefix.len);
+                let changed = self.address.map_or(true, |current| *current != address);
+                let waiting = match self.state.get() {
+                    State::Soliciting { .. } => true,
+                    _ => false,
+                };
+                if changed || waiting {
+                    // Registrations are sent from the address they register
+                    self.address.set(address);
+                    self.ip_sender.set_addr(address);
+                    self.register(false, 0);
+                }
+            }
+            _ => {
+                if let State::Soliciting { .. } = self.state.get() {
+                    self.state.set(State::Registered);
+                    self.arm(REGISTRATION_REFRESH_MS);
+                }
+            }
+        }
+    }
+
+    fn receive_registration_status(&self, src: IPAddr, target: IPAddr, aro: AddressRegistration) {
+        let expected = match self.state.get() {
+            State::Registering { .. } | State::Refreshing { .. } => {
+                self.default_router.map_or(false, |router| *router == src)
+                    && self.address.map_or(false, |address| *address == target)
+                    && aro.eui64 == self.eui64()
+            }
+            _ => false,
+        };
+        if !expected {
+            return;
+        }
+        match aro.status {
+            aro_status::SUCCESS => {
+                self.state.set(State::Registered);
+                self.arm(REGISTRATION_REFRESH_MS);
+            }
+            aro_status::DUPLICATE => {
+                self.alarm.disarm();
+                self.address.clear();
+                self.ip_sender.set_addr(self.link_local);
+                self.state.set(State::Duplicate);
+            }
+            _ => self.forget_router(),
+        }
+    }
+
+    /// Answers a Neighbor Solicitation for one of our addresses, which
+    /// routers send to check that the host is still reachable.
+    fn answer_solicitation(&self, src: IPAddr, target: IPAddr) {
+        let ours =
+            target == self.link_local || self.address.map_or(false, |address| *address == target);
+        if !ours || src.is_unspecified() {
+            return;
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type136);
+        header.set_options(ICMP6HeaderOptions::Type136 {
+            flags: NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE,
+        });
+        let mut body = [0; 32];
+        body[..16].copy_from_slice(&target.0);
+        let len =
+            16 + encode_link_address(&mut body[16..], OPTION_TARGET_LINK_ADDRESS, self.mac_addr);
+        let _ = self.icmp_sender.send_message(src, header, &body[..len]);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6MessageClient for SixlowpanNd<'a, A> {
+    fn message_sent(&self, _result: ReturnCode) {
+        // Lost messages are retransmitted when the alarm fires.
+    }
+
+    fn message_received(&self, ip_header: &IP6Header, icmp_header: ICMP6Header, body: &[u8]) {
+        // Neighbor Discovery messages must come from the link.
+        if ip_header.get_hop_limit() != ndp::HOP_LIMIT || icmp_header.get_code() != 0 {
+            return;
+        }
+        let src = ip_header.get_src_addr();
+        match icmp_header.get_type() {
+            ICMP6Type::Type134 => {
+                let state = self.state.get();
+                if !src.is_unicast_link_local() || state == State::Idle || state == State::Duplicate
+                {
+                    return;
+                }
+                if let Some(ra) = decode_router_advertisement(&icmp_header, body) {
+                    self.receive_router_advertisement(src, ra);
+                }
+            }
+            ICMP6Type::Type135 => {
+                if let Some((target, _)) = decode_neighbor_message(body) {
+                    self.answer_solicitation(src, target);
+                }
+            }
+            ICMP6Type::Type136 => {
+                if let Some((target, Some(aro))) = decode_neighbor_message(body) {
+                    self.receive_registration_status(src, target, aro);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for SixlowpanNd<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Soliciting { .. } => self.solicit_router(),
+            State::Registering { count } if count >= MAX_UNICAST_SOLICIT => self.forget_router(),
+            State::Registering { count } => self.register(false, count),
+            State::Refreshing { count } if count >= MAX_UNICAST_SOLICIT => self.forget_router(),
+            State::Refreshing { count } => self.register(true, count),
+            State::Registered => {
+                if self.address.is_some() {
+                    self.register(true, 0);
+                } else {
+                    // Check that the router is still there
+                    self.default_router.clear();
+                    self.state.set(State::Soliciting { count: 0 });
+                    self.solicit_router();
+                }
+            }
+            State::Idle | State::Duplicate => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipv6::TransportHeader;
+    use crate::net::network_capabilities::NetworkCapability;
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks32};
+
+    struct TestAlarm;
+
+    impl time::Time for TestAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0.into()
+        }
+    }
+
+    impl<'a> time::Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+        fn set_alarm(&self, _reference: Ticks32, _dt: Ticks32) {}
+        fn get_alarm(&self) -> Ticks32 {
+            0.into()
+        }
+        fn disarm(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn is_armed(&self) -> bool {
+            false
+        }
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// Accepts every message and keeps the source address it is given.
+    struct TestSender {
+        src_addr: Cell<IPAddr>,
+        sent: Cell<usize>,
+    }
+
+    impl<'a> ICMP6MessageSender<'a> for TestSender {
+        fn set_message_client(&self, _client: &'a dyn ICMP6MessageClient) {}
+        fn set_rpl_client(&self, _client: &'a dyn ICMP6MessageClient) {}
+        fn send_message(&self, _dst: IPAddr, _header: ICMP6Header, _body: &[u8]) -> ReturnCode {
+            self.sent.set(self.sent.get() + 1);
+            ReturnCode::SUCCESS
+        }
+    }
+
+    impl<'a> IP6Sender<'a> for TestSender {
+        fn set_client(&self, _client: &'a dyn crate::net::ipv6::ipv6_send::IP6SendClient) {}
+        fn set_addr(&self, src_addr: IPAddr) {
+            self.src_addr.set(src_addr);
+        }
+        fn set_gateway(&self, _gateway: MacAddress) {}
+        fn set_header(&self, _ip6_header: IP6Header) {}
+        fn send_to(
+            &self,
+            _dst: IPAddr,
+            _transport_header: TransportHeader,
+            _payload: &LeasableBuffer<'static, u8>,
+            _net_cap: &'static NetworkCapability,
+        ) -> ReturnCode {
+            ReturnCode::ENOSUPPORT
+        }
+    }
+
+    fn router_advertisement() -> [u8; 48] {
+        let mut body = [0; 48];
+        // Reachable time and retransmission timer are left at zero.
+        // Source link-layer address option with a short address
+        body[8..16].copy_from_slice(&[1, 1, 0xbe, 0xef, 0, 0, 0, 0]);
+        // Prefix information: 2001:db8::/64, on-link and autonomous
+        body[16..24].copy_from_slice(&[3, 4, 64, 0xc0, 0, 0, 0x0e, 0x10]);
+        body[32..36].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        body
+    }
+
+    #[test]
+    fn decodes_router_advertisement() {
+        let mut header = ICMP6Header::new(ICMP6Type::Type134);
+        header.set_options(ICMP6HeaderOptions::Type134 {
+            hop_limit: 64,
+            flags: 0,
+            router_lifetime: 1800,
+        });
+        let ra = decode_router_advertisement(&header, &router_advertisement()).unwrap();
+        assert_eq!(ra.router_lifetime, 1800);
+        assert_eq!(ra.link_address, Some(MacAddress::Short(0xbeef)));
+        let prefix = ra.prefix.unwrap();
+        assert_eq!(prefix.len, 64);
+        assert_eq!(prefix.valid_lifetime, 3600);
+        assert_eq!(&prefix.prefix.0[..4], &[0x20, 0x01, 0x0d, 0xb8]);
+
+        // Prefixes which cannot be used for SLAAC are skipped
+        let mut body = router_advertisement();
+        body[19] = 0x80;
+        let ra = decode_router_advertisement(&header, &body).unwrap();
+        assert_eq!(ra.prefix, None);
+
+        // Zero-length options are invalid
+        let mut body = router_advertisement();
+        body[17] = 0;
+        assert_eq!(decode_router_advertisement(&header, &body), None);
+    }
+
+    #[test]
+    fn address_registration_round_trip() {
+        let registration = AddressRegistration {
+            status: aro_status::DUPLICATE,
+            lifetime: REGISTRATION_LIFETIME_MIN,
+            eui64: [1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let mut body = [0xaa; 48];
+        let mut target = IPAddr::new();
+        target.0[0] = 0x20;
+        target.0[15] = 1;
+        body[..16].copy_from_slice(&target.0);
+        let mut len = 16;
+        len += encode_link_address(
+            &mut body[len..],
+            OPTION_SOURCE_LINK_ADDRESS,
+            MacAddress::Long([1, 2, 3, 4, 5, 6, 7, 8]),
+        );
+        len += encode_address_registration(&mut body[len..], &registration);
+        assert_eq!(len, 48);
+        assert_eq!(&body[16..20], &[1, 2, 1, 2]);
+        assert_eq!(&body[32..40], &[33, 2, 1, 0, 0, 0, 0, 15]);
+        assert_eq!(
+            decode_neighbor_message(&body[..len]),
+            Some((target, Some(registration)))
+        );
+    }
+
+    #[test]
+    fn link_address_from_interface_identifier() {
+        let short = IPAddr::generate_from_mac(MacAddress::Short(0x1234));
+        assert_eq!(
+            link_address_from_link_local(&short),
+            Some(MacAddress::Short(0x1234))
+        );
+        let long = MacAddress::Long([0x10, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            link_address_from_link_local(&IPAddr::generate_from_mac(long)),
+            Some(long)
+        );
+        assert_eq!(link_address_from_link_local(&ALL_ROUTERS), None);
+    }
+
+    #[test]
+    fn address_kept_while_refreshing() {
+        let router = IPAddr::generate_from_mac(MacAddress::Short(0xbeef));
+        let alarm = TestAlarm;
+        let sender = TestSender {
+            src_addr: Cell::new(IPAddr::new()),
+            sent: Cell::new(0),
+        };
+        let mac_addr = MacAddress::Long([0x10, 1, 2, 3, 4, 5, 6, 7]);
+        let nd = SixlowpanNd::new(&sender, &sender, &alarm, mac_addr);
+        nd.start();
+
+        let mut ip_header = IP6Header::new();
+        ip_header.src_addr = router;
+        let mut ra = ICMP6Header::new(ICMP6Type::Type134);
+        ra.set_options(ICMP6HeaderOptions::Type134 {
+            hop_limit: 64,
+            flags: 0,
+            router_lifetime: 1800,
+        });
+        nd.message_received(&ip_header, ra, &router_advertisement());
+        let address = sender.src_addr.get();
+        assert_eq!(&address.0[..4], &[0x20, 0x01, 0x0d, 0xb8]);
+        assert_eq!(nd.address(), None);
+
+        let status = |status| {
+            let mut body = [0; 48];
+            body[..16].copy_from_slice(&address.0);
+            let len = 16
+                + encode_address_registration(
+                    &mut body[16..],
+                    &AddressRegistration {
+                        status: status,
+                        lifetime: REGISTRATION_LIFETIME_MIN,
+                        eui64: [0x10, 1, 2, 3, 4, 5, 6, 7],
+                    },
+                );
+            nd.message_received(
+                &ip_header,
+                ICMP6Header::new(ICMP6Type::Type136),
+                &body[..len],
+            );
+        };
+        status(aro_status::SUCCESS);
+        assert_eq!(nd.address(), Some(address));
+
+        // The address stays registered while the registration is refreshed
+        let sent = sender.sent.get();
+        nd.alarm();
+        assert_eq!(sender.sent.get(), sent + 1);
+        assert_eq!(nd.address(), Some(address));
+        status(aro_status::SUCCESS);
+        assert_eq!(nd.address(), Some(address));
+
+        // Until the router rejects it
+        nd.alarm();
+        status(aro_status::DUPLICATE);
+        assert_eq!(nd.address(), None);
+        assert_eq!(sender.src_addr.get(), nd.link_local);
+    }
+}
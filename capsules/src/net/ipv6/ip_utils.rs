@@ -2,10 +2,11 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::UDPHeader;
+use kernel::hil::ethernet;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum MacAddr {
@@ -74,6 +75,22 @@ impl IPAddr {
         ip_addr
     }
 
+    /// Generates an IPv6 link local address from a 48-bit Ethernet MAC
+    /// address, using the modified EUI-64 interface identifier of RFC 4291
+    /// appendix A.
+    pub fn generate_from_ethernet_mac(mac_addr: ethernet::MacAddress) -> IPAddr {
+        let mac = mac_addr.bytes();
+        let mut ip_addr = IPAddr([0; 16]);
+        ip_addr.set_unicast_link_local();
+        ip_addr.0[8] = mac[0] ^ 0b00000010;
+        ip_addr.0[9] = mac[1];
+        ip_addr.0[10] = mac[2];
+        ip_addr.0[11] = 0xff;
+        ip_addr.0[12] = 0xfe;
+        ip_addr.0[13..16].copy_from_slice(&mac[3..6]);
+        ip_addr
+    }
+
     pub fn is_unspecified(&self) -> bool {
         self.0.iter().all(|&b| b == 0)
     }
@@ -196,16 +213,9 @@ pub fn compute_icmp_checksum(
     sum += msb + lsb;
 
     // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
-    }
+    let field = icmp_header.get_type_specific_field();
+    sum += field >> 16; // upper 16 bits
+    sum += field & 0xffff; // lower 16 bits
 
     // add icmp payload
     let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
@@ -215,7 +225,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -224,6 +234,31 @@ pub fn compute_icmp_checksum(
     sum as u16
 }
 
+/// Checksum of the ICMPv6 message `msg` carried in a packet with header
+/// `ip6_header`. The checksum field of `msg` is included in the sum: it must
+/// be zero when computing the checksum of an outgoing message, and the
+/// result is zero for a received message with a valid checksum.
+pub fn compute_icmp_message_checksum(ip6_header: &IP6Header, msg: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut add = |bytes: &[u8]| {
+        for pair in bytes.chunks(2) {
+            let lsb = if pair.len() == 2 { pair[1] } else { 0 };
+            sum += (pair[0] as u32) << 8 | lsb as u32;
+        }
+    };
+    // Pseudo-header: addresses, upper-layer length and next header
+    add(&ip6_header.src_addr.0);
+    add(&ip6_header.dst_addr.0);
+    add(&(msg.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, ip6_nh::ICMP]);
+    add(msg);
+
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !sum as u16
+}
+
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
     let mut sum: u32 = 0;
 
@@ -241,7 +276,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -250,12 +285,10 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
 pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut sum: u32 = 0;
 
-    let mut i: usize = 0;
-    while i < (len as usize) {
-        let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
-        sum += msb + lsb;
-        i += 2;
+    // An odd trailing byte is padded with zero
+    for pair in buf[..len as usize].chunks(2) {
+        let lsb = if pair.len() == 2 { pair[1] as u32 } else { 0 };
+        sum += (pair[0] as u32) << 8 | lsb;
     }
 
     sum
@@ -67,10 +67,12 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_icmp_message_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
-use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
-use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
 use crate::net::tcp::TCPHeader;
 use crate::net::udp::UDPHeader;
 use kernel::common::leasable_buffer::LeasableBuffer;
@@ -78,6 +80,8 @@ use kernel::ReturnCode;
 
 pub const UDP_HDR_LEN: usize = 8;
 pub const ICMP_HDR_LEN: usize = 8;
+/// Size of a TCP header without options.
+pub const TCP_HDR_LEN: usize = 20;
 
 /// This is the struct definition for an IPv6 header. It contains (in order)
 /// the same fields as a normal IPv6 header.
@@ -264,6 +268,9 @@ impl IP6Header {
     pub fn check_transport_checksum(&self, buf: &[u8]) -> ReturnCode {
         match self.next_header {
             ip6_nh::UDP => {
+                if buf.len() < UDP_HDR_LEN {
+                    return ReturnCode::FAIL;
+                }
                 let mut udp_header: [u8; UDP_HDR_LEN] = [0; UDP_HDR_LEN];
                 udp_header.copy_from_slice(&buf[..UDP_HDR_LEN]);
                 let checksum = match UDPHeader::decode(&udp_header).done() {
@@ -281,17 +288,9 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
-                    }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
-                };
-                if checksum != 0 {
+                // The checksum of a message including its checksum field
+                // is zero
+                if buf.len() < ICMP_HDR_LEN || compute_icmp_message_checksum(&self, buf) != 0 {
                     return ReturnCode::FAIL; //Incorrect cksum
                 }
                 ReturnCode::SUCCESS
@@ -301,6 +300,330 @@ impl IP6Header {
     }
 }
 
+/// Routing type of the source routing header of RPL (RFC 6554).
+pub const ROUTING_TYPE_SOURCE_ROUTE: u8 = 3;
+
+/// Largest number of addresses of a `SourceRouteHeader`.
+pub const MAX_SOURCE_ROUTE_LEN: usize = 8;
+
+/// The source routing header RPL roots in non-storing mode use to send
+/// packets down the DODAG (RFC 6554). It lists the hops of the route after
+/// the IPv6 destination address, which is the next hop. Each node on the
+/// route swaps the destination with the next address of the list until no
+/// segments are left, and the packet is at its final destination.
+///
+/// Addresses are stored uncompressed. When encoded, the leading bytes they
+/// share with the IPv6 destination address are elided: `cmpr_i` bytes of all
+/// addresses but the last, and `cmpr_e` bytes of the last one.
+#[derive(Copy, Clone)]
+pub struct SourceRouteHeader {
+    pub next_header: u8,
+    pub segments_left: u8,
+    cmpr_i: u8,
+    cmpr_e: u8,
+    addresses: [IPAddr; MAX_SOURCE_ROUTE_LEN],
+    len: usize,
+}
+
+/// Number of leading bytes `a` and `b` share, at most 15 as a source routing
+/// header always carries at least one byte of each address.
+fn common_prefix_len(a: &IPAddr, b: &IPAddr) -> u8 {
+    a.0.iter()
+        .zip(b.0.iter())
+        .take(15)
+        .take_while(|(a, b)| a == b)
+        .count() as u8
+}
+
+impl SourceRouteHeader {
+    /// Creates a header routing a packet sent to `dst` through `route`,
+    /// which ends with the final destination. Returns `None` if `route` is
+    /// empty or longer than `MAX_SOURCE_ROUTE_LEN`.
+    pub fn new(next_header: u8, dst: &IPAddr, route: &[IPAddr]) -> Option<SourceRouteHeader> {
+        let (last, hops) = route.split_last()?;
+        if route.len() > MAX_SOURCE_ROUTE_LEN {
+            return None;
+        }
+        let mut addresses = [IPAddr::new(); MAX_SOURCE_ROUTE_LEN];
+        addresses[..route.len()].copy_from_slice(route);
+        Some(SourceRouteHeader {
+            next_header: next_header,
+            segments_left: route.len() as u8,
+            cmpr_i: hops
+                .iter()
+                .map(|hop| common_prefix_len(hop, dst))
+                .min()
+                .unwrap_or(0),
+            cmpr_e: common_prefix_len(last, dst),
+            addresses: addresses,
+            len: route.len(),
+        })
+    }
+
+    pub fn addresses(&self) -> &[IPAddr] {
+        &self.addresses[..self.len]
+    }
+
+    /// Length of the encoded header, padded to a multiple of 8 bytes.
+    pub fn get_hdr_size(&self) -> usize {
+        let addresses = (self.len - 1) * (16 - self.cmpr_i as usize) + (16 - self.cmpr_e as usize);
+        (4 + 4 + addresses + 7) / 8 * 8
+    }
+
+    /// Decodes a source routing header of a packet sent to `dst`, whose
+    /// elided address bytes are taken from.
+    pub fn decode(buf: &[u8], dst: &IPAddr) -> SResult<SourceRouteHeader> {
+        stream_len_cond!(buf, 8);
+        let (off, next_header) = dec_try!(buf, 0; decode_u8);
+        let (off, hdr_ext_len) = dec_try!(buf, off; decode_u8);
+        let (off, routing_type) = dec_try!(buf, off; decode_u8);
+        let (off, segments_left) = dec_try!(buf, off; decode_u8);
+        let (off, compression) = dec_try!(buf, off; decode_u8);
+        let (mut off, pad) = dec_try!(buf, off; decode_u8);
+        off += 2;
+        stream_cond!(routing_type == ROUTING_TYPE_SOURCE_ROUTE, ());
+
+        let hdr_size = (hdr_ext_len as usize + 1) * 8;
+        stream_len_cond!(buf, hdr_size);
+        let cmpr_i = compression >> 4;
+        let cmpr_e = compression & 0x0f;
+        let (size_i, size_e) = (16 - cmpr_i as usize, 16 - cmpr_e as usize);
+        let addresses_size = stream_from_option!(
+            (hdr_ext_len as usize * 8).checked_sub((pad >> 4) as usize + size_e),
+            ()
+        );
+        // The addresses must fill the header exactly
+        stream_cond!(addresses_size % size_i == 0, ());
+        let len = addresses_size / size_i + 1;
+        stream_cond!(len <= MAX_SOURCE_ROUTE_LEN, ());
+        stream_cond!(segments_left as usize <= len, ());
+
+        let mut header = SourceRouteHeader {
+            next_header: next_header,
+            segments_left: segments_left,
+            cmpr_i: cmpr_i,
+            cmpr_e: cmpr_e,
+            addresses: [*dst; MAX_SOURCE_ROUTE_LEN],
+            len: len,
+        };
+        for (i, address) in header.addresses[..len].iter_mut().enumerate() {
+            let size = if i == len - 1 { size_e } else { size_i };
+            address.0[16 - size..].copy_from_slice(&buf[off..off + size]);
+            off += size;
+        }
+        stream_done!(hdr_size, header);
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let hdr_size = self.get_hdr_size();
+        stream_len_cond!(buf, hdr_size);
+        let (size_i, size_e) = (16 - self.cmpr_i as usize, 16 - self.cmpr_e as usize);
+        let pad = hdr_size - 8 - (self.len - 1) * size_i - size_e;
+
+        let mut off = enc_consume!(buf, 0; encode_u8, self.next_header);
+        off = enc_consume!(buf, off; encode_u8, (hdr_size / 8 - 1) as u8);
+        off = enc_consume!(buf, off; encode_u8, ROUTING_TYPE_SOURCE_ROUTE);
+        off = enc_consume!(buf, off; encode_u8, self.segments_left);
+        off = enc_consume!(buf, off; encode_u8, self.cmpr_i << 4 | self.cmpr_e);
+        off = enc_consume!(buf, off; encode_u8, (pad as u8) << 4);
+        off = enc_consume!(buf, off; encode_u16, 0);
+        for (i, address) in self.addresses().iter().enumerate() {
+            let size = if i == self.len - 1 { size_e } else { size_i };
+            off = enc_consume!(buf, off; encode_bytes, &address.0[16 - size..]);
+        }
+        buf[off..hdr_size].iter_mut().for_each(|byte| *byte = 0);
+        stream_done!(hdr_size, hdr_size);
+    }
+
+    /// Moves the packet with header `ip6_header` one hop along the route
+    /// (RFC 6554 section 4.2): the next address of the route becomes the
+    /// destination of the packet, which must then be forwarded to it.
+    /// Returns `EALREADY` if no segments are left, in which case the packet
+    /// is at its final destination, and `EINVAL` if the route is invalid.
+    pub fn advance(&mut self, ip6_header: &mut IP6Header) -> ReturnCode {
+        if self.segments_left == 0 {
+            return ReturnCode::EALREADY;
+        }
+        let next = self.len - self.segments_left as usize;
+        let dst = ip6_header.get_dst_addr();
+        if dst.is_multicast() || self.addresses[next].is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        self.segments_left -= 1;
+        ip6_header.dst_addr = self.addresses[next];
+        self.addresses[next] = dst;
+        ReturnCode::SUCCESS
+    }
+}
+
+/// Processes the routing header at the start of `buf`, which follows
+/// `ip6_header`, for a packet addressed to this node. If the packet is at its
+/// final destination, the routing header is removed from `ip6_header`,
+/// which then describes the rest of the packet, and its length is returned.
+/// Packets with segments left would have to be forwarded to the next hop of
+/// their route, which is not supported: `None` is returned for them, and for
+/// malformed headers.
+pub fn skip_routing_header(ip6_header: &mut IP6Header, buf: &[u8]) -> Option<usize> {
+    if buf.len() < 8 {
+        return None;
+    }
+    let hdr_size = (buf[1] as usize + 1) * 8;
+    let segments_left = buf[3];
+    if hdr_size > buf.len() {
+        return None;
+    }
+    if segments_left != 0 {
+        // This node is an intermediate hop of the route
+        return None;
+    }
+    ip6_header.set_next_header(buf[0]);
+    ip6_header.set_payload_len(ip6_header.get_payload_len().saturating_sub(hdr_size as u16));
+    Some(hdr_size)
+}
+
+/// Option types of the Hop-by-Hop and Destination Options headers.
+pub mod ip6_opt {
+    pub const PAD1: u8 = 0;
+    pub const PADN: u8 = 1;
+    /// The RPL option (RFC 6553), which routers of a RPL network add to the
+    /// Hop-by-Hop Options of the packets they forward.
+    pub const RPL: u8 = 0x63;
+}
+
+/// Checks the options of the Hop-by-Hop or Destination Options header at the
+/// start of `buf`, and returns the size of the header. Unknown options are
+/// processed according to the two highest bits of their type (RFC 8200
+/// section 4.2): they are skipped for `00`, and the packet is discarded
+/// otherwise, in which case `None` is returned. The ICMP Parameter Problem
+/// messages some of these actions call for are not sent.
+fn check_options_header(buf: &[u8]) -> Option<usize> {
+    if buf.len() < 8 {
+        return None;
+    }
+    let hdr_size = (buf[1] as usize + 1) * 8;
+    if hdr_size > buf.len() {
+        return None;
+    }
+    let mut off = 2;
+    while off < hdr_size {
+        let opt_type = buf[off];
+        if opt_type == ip6_opt::PAD1 {
+            off += 1;
+            continue;
+        }
+        if off + 2 > hdr_size {
+            return None;
+        }
+        let opt_size = 2 + buf[off + 1] as usize;
+        if off + opt_size > hdr_size {
+            return None;
+        }
+        match opt_type {
+            ip6_opt::PADN | ip6_opt::RPL => {}
+            _ if opt_type >> 6 == 0 => {}
+            _ => return None,
+        }
+        off += opt_size;
+    }
+    Some(hdr_size)
+}
+
+/// Processes the extension headers at the start of `buf`, which follows
+/// `ip6_header`, for a packet addressed to this node, and returns their total
+/// size. They are removed from `ip6_header`, which then describes the rest of
+/// the packet: either the upper-layer header, or a Fragment header, after
+/// which the packet must be reassembled before going further. `None` is
+/// returned for packets which must be discarded, because they are malformed,
+/// carry an unknown option which cannot be skipped, or must be forwarded.
+pub fn skip_extension_headers(ip6_header: &mut IP6Header, buf: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &buf[offset..];
+        let hdr_size = match ip6_header.get_next_header() {
+            // Hop-by-Hop Options are only allowed right after the IPv6 header
+            ip6_nh::HOP_OPTS if offset == 0 => check_options_header(rest)?,
+            ip6_nh::HOP_OPTS => return None,
+            ip6_nh::DST_OPTS => check_options_header(rest)?,
+            ip6_nh::ROUTING => {
+                offset += skip_routing_header(ip6_header, rest)?;
+                continue;
+            }
+            _ => return Some(offset),
+        };
+        ip6_header.set_next_header(rest[0]);
+        ip6_header.set_payload_len(ip6_header.get_payload_len().saturating_sub(hdr_size as u16));
+        offset += hdr_size;
+    }
+}
+
+/// Encodes a Hop-by-Hop or Destination Options header carrying `options`,
+/// which are already encoded, padded with Pad1 or PadN to a multiple of 8
+/// bytes. Returns the size of the header.
+pub fn encode_options_header(buf: &mut [u8], next_header: u8, options: &[u8]) -> SResult<usize> {
+    let hdr_size = (2 + options.len() + 7) / 8 * 8;
+    stream_cond!(hdr_size <= 256 * 8, ());
+    stream_len_cond!(buf, hdr_size);
+    let mut off = enc_consume!(buf, 0; encode_u8, next_header);
+    off = enc_consume!(buf, off; encode_u8, (hdr_size / 8 - 1) as u8);
+    off = enc_consume!(buf, off; encode_bytes, options);
+    match hdr_size - off {
+        0 => {}
+        1 => {
+            enc_consume!(buf, off; encode_u8, ip6_opt::PAD1);
+        }
+        pad => {
+            off = enc_consume!(buf, off; encode_u8, ip6_opt::PADN);
+            off = enc_consume!(buf, off; encode_u8, (pad - 2) as u8);
+            buf[off..hdr_size].iter_mut().for_each(|byte| *byte = 0);
+        }
+    }
+    stream_done!(hdr_size, hdr_size);
+}
+
+/// Size of the Fragment header.
+pub const FRAGMENT_HDR_LEN: usize = 8;
+
+/// The Fragment header (RFC 8200 section 4.5), carried by each fragment of
+/// a packet too large for the MTU of the link. The fragmentable part of the
+/// packet, everything after the Fragment header, is split in fragments
+/// whose sizes are multiples of 8 bytes, except for the last one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FragmentHeader {
+    pub next_header: u8,
+    /// Offset of the fragment in the fragmentable part, in units of 8 bytes.
+    pub offset: u16,
+    /// Whether more fragments follow this one.
+    pub more: bool,
+    pub id: u32,
+}
+
+impl FragmentHeader {
+    pub fn decode(buf: &[u8]) -> SResult<FragmentHeader> {
+        stream_len_cond!(buf, FRAGMENT_HDR_LEN);
+        let (off, next_header) = dec_try!(buf, 0; decode_u8);
+        let (off, offset_flags) = dec_try!(buf, off + 1; decode_u16);
+        let (off, id) = dec_try!(buf, off; decode_u32);
+        stream_done!(
+            off,
+            FragmentHeader {
+                next_header: next_header,
+                offset: offset_flags >> 3,
+                more: offset_flags & 1 != 0,
+                id: id,
+            }
+        );
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_len_cond!(buf, FRAGMENT_HDR_LEN);
+        let mut off = enc_consume!(buf, 0; encode_u8, self.next_header);
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u16, self.offset << 3 | self.more as u16);
+        off = enc_consume!(buf, off; encode_u32, self.id);
+        stream_done!(off, off);
+    }
+}
+
 /// This defines the currently supported `TransportHeader` types. The contents
 /// of each header is encapsulated by the enum type. Note that this definition
 /// of `TransportHeader`s means that recursive headers are not supported.
@@ -385,30 +708,62 @@ impl<'a> IPPayload<'a> {
     /// `SResult<usize>` - The final offset into the buffer `buf` is returned
     /// wrapped in an SResult
     pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
-        let (offset, _) = match self.header {
-            TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
-            TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
-        };
+        let (offset, _) = stream_from_option!(self.encode_header(buf, offset).done(), ());
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
         stream_done!(offset, offset)
     }
 
+    fn encode_header(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        match self.header {
+            TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset),
+            TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset),
+            // TCP is not implemented
+            TransportHeader::TCP(_) => SResult::Error(()),
+        }
+    }
+
+    /// This function encodes part of the serialized `IPPayload`, as it is
+    /// carried by a fragment of the packet.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - Buffer to write the part to, which is filled up to the end of
+    /// the `IPPayload`
+    /// `start` - Offset of the part in the serialized `IPPayload`
+    ///
+    /// # Return Value
+    ///
+    /// `SResult<usize>` - The number of bytes written to `buf`
+    pub fn encode_part(&self, buf: &mut [u8], start: usize) -> SResult<usize> {
+        let mut header = [0; TCP_HDR_LEN];
+        let (hdr_len, _) = stream_from_option!(self.encode_header(&mut header, 0).done(), ());
+        let payload = &self.payload[..self.get_payload_length()];
+        let total_len = hdr_len + payload.len();
+        stream_cond!(start <= total_len, ());
+        let len = buf.len().min(total_len - start);
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            let pos = start + i;
+            *byte = if pos < hdr_len {
+                header[pos]
+            } else {
+                payload[pos - hdr_len]
+            };
+        }
+        stream_done!(len, len);
+    }
+
     fn get_payload_length(&self) -> usize {
         match self.header {
             TransportHeader::UDP(udp_header) => {
-                udp_header.get_len() as usize - udp_header.get_hdr_size()
+                (udp_header.get_len() as usize).saturating_sub(udp_header.get_hdr_size())
             }
             TransportHeader::ICMP(icmp_header) => {
-                icmp_header.get_len() as usize - icmp_header.get_hdr_size()
-            }
-            _ => {
-                unimplemented!();
+                (icmp_header.get_len() as usize).saturating_sub(icmp_header.get_hdr_size())
             }
+            TransportHeader::TCP(_) => 0,
         }
+        .min(self.payload.len())
     }
 }
 
@@ -454,7 +809,7 @@ impl<'a> IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(_) => TCP_HDR_LEN,
         };
         40 + transport_hdr_size
     }
@@ -480,9 +835,8 @@ impl<'a> IP6Packet<'a> {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
-            }
+            // TCP is not implemented, and TCP packets cannot be encoded
+            TransportHeader::TCP(_) => {}
         }
     }
 
@@ -515,8 +869,174 @@ impl<'a> IP6Packet<'a> {
     pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
         let ip6_header = self.header;
 
-        // TODO: Handle unwrap safely
-        let (off, _) = ip6_header.encode(buf).done().unwrap();
+        let (off, _) = enc_try!(buf; ip6_header; encode);
         self.payload.encode(buf, off)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> IPAddr {
+        let mut addr = IPAddr([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        addr.0[15] = last;
+        addr
+    }
+
+    #[test]
+    fn source_route_round_trip() {
+        let dst = addr(1);
+        let route = [addr(2), addr(3), addr(4)];
+        let srh = SourceRouteHeader::new(ip6_nh::UDP, &dst, &route).unwrap();
+        // 15 bytes of each address are elided: 8 bytes of header, 3 of
+        // addresses and 5 of padding
+        assert_eq!(srh.get_hdr_size(), 16);
+
+        let mut buf = [0xaa; 16];
+        assert_eq!(srh.encode(&mut buf).done(), Some((16, 16)));
+        assert_eq!(
+            buf,
+            [
+                ip6_nh::UDP,
+                1,
+                3,
+                3,
+                0xff,
+                0x50,
+                0,
+                0,
+                2,
+                3,
+                4,
+                0,
+                0,
+                0,
+                0,
+                0
+            ]
+        );
+
+        let decoded = SourceRouteHeader::decode(&buf, &dst).done().unwrap().1;
+        assert_eq!(decoded.segments_left, 3);
+        assert_eq!(decoded.addresses(), &route);
+    }
+
+    #[test]
+    fn source_route_advance() {
+        let mut header = IP6Header::new();
+        header.dst_addr = addr(1);
+        let mut route = [addr(2), addr(3)];
+        route[1].0[8] = 0x02;
+        let mut srh = SourceRouteHeader::new(ip6_nh::UDP, &header.dst_addr, &route).unwrap();
+
+        assert_eq!(srh.advance(&mut header), ReturnCode::SUCCESS);
+        assert_eq!(header.dst_addr, route[0]);
+        assert_eq!(srh.advance(&mut header), ReturnCode::SUCCESS);
+        assert_eq!(header.dst_addr, route[1]);
+        assert_eq!(srh.advance(&mut header), ReturnCode::EALREADY);
+        // The visited hops are recorded in place of the route
+        assert_eq!(srh.addresses(), &[addr(1), addr(2)]);
+    }
+
+    #[test]
+    fn routing_header_at_final_destination() {
+        let mut header = IP6Header::new();
+        header.set_next_header(ip6_nh::ROUTING);
+        header.set_payload_len(24);
+        let mut buf = [0; 16];
+        buf[..4].copy_from_slice(&[ip6_nh::UDP, 1, ROUTING_TYPE_SOURCE_ROUTE, 0]);
+        assert_eq!(skip_routing_header(&mut header, &buf), Some(16));
+        assert_eq!(header.get_next_header(), ip6_nh::UDP);
+        assert_eq!(header.get_payload_len(), 8);
+
+        // Packets with segments left are not forwarded
+        buf[3] = 1;
+        assert_eq!(skip_routing_header(&mut header, &buf), None);
+    }
+
+    #[test]
+    fn extension_header_chain() {
+        let mut buf = [0; 32];
+        // Hop-by-Hop Options with an unknown option which can be skipped
+        let hdr_len = encode_options_header(&mut buf, ip6_nh::DST_OPTS, &[0x1e, 1, 0])
+            .done()
+            .unwrap()
+            .0;
+        assert_eq!(hdr_len, 8);
+        assert_eq!(
+            buf[..8],
+            [ip6_nh::DST_OPTS, 0, 0x1e, 1, 0, ip6_opt::PADN, 1, 0]
+        );
+        // Destination Options with a single Pad1 option
+        let dst_len = encode_options_header(&mut buf[8..], ip6_nh::UDP, &[0x1e, 3, 0, 0, 0])
+            .done()
+            .unwrap()
+            .0;
+        assert_eq!(dst_len, 8);
+        assert_eq!(buf[15], ip6_opt::PAD1);
+
+        let mut header = IP6Header::new();
+        header.set_next_header(ip6_nh::HOP_OPTS);
+        header.set_payload_len(32);
+        assert_eq!(skip_extension_headers(&mut header, &buf), Some(16));
+        assert_eq!(header.get_next_header(), ip6_nh::UDP);
+        assert_eq!(header.get_payload_len(), 16);
+
+        // Hop-by-Hop Options must come first
+        header.set_next_header(ip6_nh::DST_OPTS);
+        buf[0] = ip6_nh::HOP_OPTS;
+        assert_eq!(skip_extension_headers(&mut header, &buf), None);
+
+        // Unknown options whose type asks to discard the packet
+        header.set_next_header(ip6_nh::HOP_OPTS);
+        buf[0] = ip6_nh::DST_OPTS;
+        buf[2] = 0x5e;
+        assert_eq!(skip_extension_headers(&mut header, &buf), None);
+
+        // Options overflowing the header
+        header.set_next_header(ip6_nh::HOP_OPTS);
+        buf[2] = 0x1e;
+        buf[3] = 6;
+        assert_eq!(skip_extension_headers(&mut header, &buf), None);
+
+        // Truncated header
+        header.set_next_header(ip6_nh::HOP_OPTS);
+        assert_eq!(skip_extension_headers(&mut header, &buf[..4]), None);
+    }
+
+    #[test]
+    fn fragment_header_round_trip() {
+        let fragment = FragmentHeader {
+            next_header: ip6_nh::UDP,
+            offset: 181,
+            more: true,
+            id: 0xdeadbeef,
+        };
+        let mut buf = [0; FRAGMENT_HDR_LEN];
+        assert_eq!(fragment.encode(&mut buf).done(), Some((8, 8)));
+        assert_eq!(buf, [ip6_nh::UDP, 0, 0x05, 0xa9, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(FragmentHeader::decode(&buf).done(), Some((8, fragment)));
+        assert!(FragmentHeader::decode(&buf[..7]).is_needed());
+    }
+
+    #[test]
+    fn payload_parts() {
+        let mut data = [0; 16];
+        data.iter_mut()
+            .enumerate()
+            .for_each(|(i, byte)| *byte = i as u8);
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_len(8 + 16);
+        let payload = IPPayload::new(TransportHeader::UDP(udp_header), &mut data);
+
+        let mut whole = [0; 24];
+        assert!(payload.encode(&mut whole, 0).is_done());
+        let mut part = [0; 16];
+        assert_eq!(payload.encode_part(&mut part, 0).done(), Some((16, 16)));
+        assert_eq!(part, whole[..16]);
+        assert_eq!(payload.encode_part(&mut part, 16).done(), Some((8, 8)));
+        assert_eq!(part[..8], whole[16..]);
+        assert!(payload.encode_part(&mut part, 25).is_err());
+    }
+}
@@ -0,0 +1,815 @@
+//! IPv6 over Ethernet (RFC 2464).
+//!
+//! `IP6Ethernet` implements the `IP6Sender` and `IP6Receiver` interfaces on
+//! top of an Ethernet MAC (`hil::ethernet::Ethernet`), so that the layers
+//! above IPv6 run over Ethernet as they do over 6LoWPAN. Link-layer addresses
+//! of neighbors are resolved with Neighbor Discovery (RFC 4861): the first
+//! packet to an unknown neighbor is held back while Neighbor Solicitations
+//! are sent, and goes out once the neighbor advertises its address. The node
+//! itself answers solicitations for its address.
+//!
+//! Multicast packets are sent to the Ethernet address 33:33 followed by the
+//! last 32 bits of the destination. Destinations outside the link-local
+//! prefix are sent to the router set with `set_default_router()`; without
+//! one, all destinations are assumed to be on-link. Received multicast
+//! packets are passed up for the groups joined through `MulticastGroups`.
+//!
+//! Only one packet is sent at a time: `send_to()` returns EBUSY while a
+//! packet waits for resolution or transmission. If the neighbor does not
+//! answer `ndp::MAX_MULTICAST_SOLICIT` solicitations, `send_done()` reports
+//! ENOACK.
+//!
+//! Packets larger than the MTU, 1500 bytes unless changed with `set_mtu()`,
+//! are sent in fragments (RFC 8200 section 4.5), one after the other, and
+//! `send_done()` is called once all of them are sent. Received fragments are
+//! reassembled into the buffer passed to `new()`, one packet at a time: the
+//! fragments of other packets are dropped until the packet is complete or
+//! `REASSEMBLY_TIMEOUT` seconds have passed since its first fragment.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let ip6_ethernet = static_init!(
+//!     capsules::net::ipv6::ipv6_ethernet::IP6Ethernet<'static, VirtualMuxAlarm<'static, A>>,
+//!     capsules::net::ipv6::ipv6_ethernet::IP6Ethernet::new(
+//!         ethmac0, ip6_alarm, ip6_packet, &mut TX_BUF, &mut REASSEMBLY_BUF, ip_vis
+//!     )
+//! );
+//! ethmac0.set_client(ip6_ethernet);
+//! ip6_alarm.set_alarm_client(ip6_ethernet);
+//! ip6_ethernet.set_addr(IPAddr::generate_from_ethernet_mac(ethmac0.mac_address()));
+//! ```
+
+use crate::net::frag_utils::Bitmap;
+use crate::net::icmpv6::ndp::{self, LinkAddress, NdMessage, NeighborCache};
+use crate::net::ieee802154;
+use crate::net::ipv6::ip_utils::{compute_icmp_message_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::{GroupTable, IP6Receiver, IP6RecvClient, MulticastGroups};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{skip_extension_headers, FragmentHeader, FRAGMENT_HDR_LEN};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use core::cell::Cell;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::ethernet::{self, ethertype, Ethernet, MacAddress, HEADER_LEN};
+use kernel::hil::time::{self, Ticks};
+use kernel::ReturnCode;
+
+const IP6_HEADER_LEN: usize = 40;
+
+/// The smallest MTU of an IPv6 link.
+pub const MIN_MTU: usize = 1280;
+
+/// Time after which a packet which is not completely reassembled is
+/// dropped, in seconds.
+pub const REASSEMBLY_TIMEOUT: u32 = 60;
+
+/// The Ethernet group address IPv6 multicast packets to `addr` are sent to.
+fn multicast_mac(addr: &IPAddr) -> MacAddress {
+    let mut mac = [0x33, 0x33, 0, 0, 0, 0];
+    mac[2..6].copy_from_slice(&addr.0[12..16]);
+    MacAddress::new(mac)
+}
+
+fn mac_from_link_address(link_address: LinkAddress) -> Option<MacAddress> {
+    let addr = link_address.as_slice();
+    if addr.len() != 6 {
+        return None;
+    }
+    let mut mac = [0; 6];
+    mac.copy_from_slice(addr);
+    Some(MacAddress::new(mac))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum PacketState {
+    Idle,
+    /// Waiting for the link-layer address of `next_hop`.
+    Resolving {
+        next_hop: IPAddr,
+        solicitations: u8,
+    },
+    /// Resolved, waiting for the transmit buffer.
+    Ready(MacAddress),
+    /// The packet, or one of its fragments, is being transmitted.
+    Transmitting(MacAddress),
+}
+
+/// A packet being reassembled.
+#[derive(Copy, Clone)]
+struct Reassembly<T> {
+    /// Header of the packet, with the next header of its fragmentable part.
+    header: IP6Header,
+    id: u32,
+    started: T,
+    /// End of the fragment received furthest into the packet.
+    received_len: usize,
+    /// Length of the fragmentable part, known once the last fragment is
+    /// received.
+    total_len: Option<usize>,
+}
+
+/// A Neighbor Advertisement waiting for the transmit buffer.
+#[derive(Copy, Clone)]
+struct Advertisement {
+    dst: IPAddr,
+    dst_mac: MacAddress,
+    solicited: bool,
+}
+
+pub struct IP6Ethernet<'a, A: time::Alarm<'a>> {
+    ethernet: &'a dyn Ethernet<'a>,
+    alarm: &'a A,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    tx_buf: TakeCell<'static, [u8]>,
+    mtu: Cell<usize>,
+    /// Bytes of the fragmentable part of the outgoing packet already sent.
+    fragment_offset: Cell<usize>,
+    fragment_id: Cell<u32>,
+    reassembly_buf: TakeCell<'static, [u8]>,
+    reassembly: OptionalCell<Reassembly<A::Ticks>>,
+    reassembly_map: MapCell<Bitmap>,
+    src_addr: Cell<IPAddr>,
+    default_router: OptionalCell<IPAddr>,
+    neighbors: MapCell<NeighborCache<MacAddress>>,
+    packet: Cell<PacketState>,
+    solicit: Cell<bool>,
+    advertisement: OptionalCell<Advertisement>,
+    send_client: OptionalCell<&'a dyn IP6SendClient>,
+    recv_client: OptionalCell<&'a dyn IP6RecvClient>,
+    icmp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    groups: GroupTable,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Ethernet<'a, A> {
+    pub fn new(
+        ethernet: &'a dyn Ethernet<'a>,
+        alarm: &'a A,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        tx_buf: &'static mut [u8],
+        reassembly_buf: &'static mut [u8],
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> IP6Ethernet<'a, A> {
+        IP6Ethernet {
+            ethernet: ethernet,
+            alarm: alarm,
+            ip6_packet: TakeCell::new(ip6_packet),
+            tx_buf: TakeCell::new(tx_buf),
+            mtu: Cell::new(ethernet::MAX_FRAME_LEN - HEADER_LEN),
+            fragment_offset: Cell::new(0),
+            fragment_id: Cell::new(0),
+            reassembly_buf: TakeCell::new(reassembly_buf),
+            reassembly: OptionalCell::empty(),
+            reassembly_map: MapCell::new(Bitmap::new()),
+            src_addr: Cell::new(IPAddr::new()),
+            default_router: OptionalCell::empty(),
+            neighbors: MapCell::new(NeighborCache::new()),
+            packet: Cell::new(PacketState::Idle),
+            solicit: Cell::new(false),
+            advertisement: OptionalCell::empty(),
+            send_client: OptionalCell::empty(),
+            recv_client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            groups: GroupTable::new(),
+            ip_vis: ip_vis,
+        }
+    }
+
+    /// Send packets for destinations outside the link-local prefix through
+    /// `router`, which must be on-link.
+    pub fn set_default_router(&self, router: IPAddr) {
+        self.default_router.set(router);
+    }
+
+    /// Send packets larger than `mtu` bytes in fragments. The MTU cannot be
+    /// lower than `MIN_MTU`.
+    pub fn set_mtu(&self, mtu: usize) {
+        self.mtu.set(mtu.max(MIN_MTU));
+    }
+
+    fn next_hop(&self, dst: IPAddr) -> IPAddr {
+        if dst.is_multicast() || dst.is_unicast_link_local() {
+            dst
+        } else {
+            self.default_router.unwrap_or(dst)
+        }
+    }
+
+    fn write_ethernet_header(&self, buf: &mut [u8], dst_mac: MacAddress) {
+        buf[0..6].copy_from_slice(&dst_mac.bytes());
+        buf[6..12].copy_from_slice(&self.ethernet.mac_address().bytes());
+        buf[12..14].copy_from_slice(&ethertype::IPV6.to_be_bytes());
+    }
+
+    /// Encode the next part of the outgoing packet to `buf`: the whole
+    /// packet if it fits the MTU, its next fragment otherwise. Returns the
+    /// length of the encoded packet.
+    fn encode_next_part(
+        &self,
+        ip6_packet: &IP6Packet<'static>,
+        buf: &mut [u8],
+    ) -> Result<usize, ReturnCode> {
+        let mtu = self.mtu.get().min(buf.len());
+        let total_len = ip6_packet.get_total_len() as usize;
+        let offset = self.fragment_offset.get();
+        if offset == 0 && total_len <= mtu {
+            ip6_packet.encode(buf).done().ok_or(ReturnCode::FAIL)?;
+            self.fragment_offset.set(total_len - IP6_HEADER_LEN);
+            return Ok(total_len);
+        }
+
+        // All fragments but the last carry a multiple of 8 bytes
+        let max_len = mtu.saturating_sub(IP6_HEADER_LEN + FRAGMENT_HDR_LEN) & !7;
+        if max_len == 0 {
+            return Err(ReturnCode::ESIZE);
+        }
+        if offset == 0 {
+            self.fragment_id.set(self.fragment_id.get().wrapping_add(1));
+        }
+        let fragmentable_len = total_len - IP6_HEADER_LEN;
+        let len = max_len.min(fragmentable_len - offset);
+        let fragment = FragmentHeader {
+            next_header: ip6_packet.header.get_next_header(),
+            offset: (offset / 8) as u16,
+            more: offset + len < fragmentable_len,
+            id: self.fragment_id.get(),
+        };
+        let mut header = ip6_packet.header;
+        header.set_next_header(ip6_nh::FRAGMENT);
+        header.set_payload_len((FRAGMENT_HDR_LEN + len) as u16);
+
+        let data_start = IP6_HEADER_LEN + FRAGMENT_HDR_LEN;
+        header.encode(buf);
+        fragment.encode(&mut buf[IP6_HEADER_LEN..]);
+        ip6_packet
+            .payload
+            .encode_part(&mut buf[data_start..data_start + len], offset)
+            .done()
+            .ok_or(ReturnCode::FAIL)?;
+        self.fragment_offset.set(offset + len);
+        Ok(data_start + len)
+    }
+
+    /// Send the next part of the outgoing packet in `ip6_packet` to
+    /// `dst_mac`.
+    fn transmit_packet(&self, dst_mac: MacAddress) -> ReturnCode {
+        let result = self.tx_buf.take().map_or(Err(ReturnCode::EBUSY), |buf| {
+            let encoded = self
+                .ip6_packet
+                .map_or(Err(ReturnCode::ENOMEM), |ip6_packet| {
+                    self.encode_next_part(ip6_packet, &mut buf[HEADER_LEN..])
+                });
+            let len = match encoded {
+                Ok(len) => HEADER_LEN + len,
+                Err(rcode) => {
+                    self.tx_buf.replace(buf);
+                    return Err(rcode);
+                }
+            };
+            self.write_ethernet_header(buf, dst_mac);
+            self.ethernet.transmit(buf, len).map_err(|(rcode, buf)| {
+                self.tx_buf.replace(buf);
+                rcode
+            })
+        });
+        match result {
+            Ok(()) => {
+                self.packet.set(PacketState::Transmitting(dst_mac));
+                ReturnCode::SUCCESS
+            }
+            Err(rcode) => {
+                self.packet.set(PacketState::Idle);
+                rcode
+            }
+        }
+    }
+
+    /// Send a Neighbor Discovery message in its own packet. Messages which
+    /// cannot be sent are dropped: Neighbor Discovery retransmits on its own.
+    fn send_nd(&self, msg: NdMessage, dst: IPAddr, dst_mac: MacAddress) {
+        self.tx_buf.take().map(|buf| {
+            let msg_start = HEADER_LEN + IP6_HEADER_LEN;
+            let len = msg_start + msg.len();
+            if len > buf.len() {
+                self.tx_buf.replace(buf);
+                return;
+            }
+            let mut header = IP6Header::new();
+            header.src_addr = self.src_addr.get();
+            header.dst_addr = dst;
+            header.set_next_header(ip6_nh::ICMP);
+            header.set_hop_limit(ndp::HOP_LIMIT);
+            header.set_payload_len(msg.len() as u16);
+
+            self.write_ethernet_header(buf, dst_mac);
+            header.encode(&mut buf[HEADER_LEN..]);
+            msg.encode(&mut buf[msg_start..]);
+            let cksum = compute_icmp_message_checksum(&header, &buf[msg_start..len]);
+            buf[msg_start + 2..msg_start + 4].copy_from_slice(&cksum.to_be_bytes());
+
+            if let Err((_, buf)) = self.ethernet.transmit(buf, len) {
+                self.tx_buf.replace(buf);
+            }
+        });
+    }
+
+    fn send_solicitation(&self, target: IPAddr) {
+        // Solicitations sent from the unspecified address must not carry the
+        // source link-layer address.
+        let source = if self.src_addr.get().is_unspecified() {
+            None
+        } else {
+            LinkAddress::new(&self.ethernet.mac_address().bytes())
+        };
+        let dst = ndp::solicited_node(&target);
+        self.send_nd(
+            NdMessage::Solicitation {
+                target: target,
+                source: source,
+            },
+            dst,
+            multicast_mac(&dst),
+        );
+        self.alarm
+            .set_alarm(self.alarm.now(), A::ticks_from_ms(ndp::RETRANS_TIMER_MS));
+    }
+
+    fn send_advertisement(&self, advertisement: Advertisement) {
+        self.send_nd(
+            NdMessage::Advertisement {
+                target: self.src_addr.get(),
+                router: false,
+                solicited: advertisement.solicited,
+                override_: true,
+                link_address: LinkAddress::new(&self.ethernet.mac_address().bytes()),
+            },
+            advertisement.dst,
+            advertisement.dst_mac,
+        );
+    }
+
+    /// Use the transmit buffer for whatever is waiting for it: answers to
+    /// solicitations first, then solicitations, then the outgoing packet.
+    fn send_next(&self) {
+        while self.tx_buf.is_some() {
+            if let Some(advertisement) = self.advertisement.take() {
+                self.send_advertisement(advertisement);
+            } else if self.solicit.replace(false) {
+                if let PacketState::Resolving { next_hop, .. } = self.packet.get() {
+                    self.send_solicitation(next_hop);
+                }
+            } else {
+                if let PacketState::Ready(dst_mac) = self.packet.get() {
+                    let result = self.transmit_packet(dst_mac);
+                    if result != ReturnCode::SUCCESS {
+                        self.send_done(result);
+                    }
+                }
+                return;
+            }
+        }
+    }
+
+    fn send_done(&self, result: ReturnCode) {
+        self.send_client.map(|client| client.send_done(result));
+    }
+
+    /// `ip` is reachable at `mac`. Neighbors which are not cached yet are
+    /// only added if `create` is set.
+    fn learn_neighbor(&self, ip: IPAddr, mac: MacAddress, create: bool) {
+        self.neighbors.map(|neighbors| {
+            if create {
+                neighbors.insert(ip, mac);
+            } else {
+                neighbors.update(&ip, mac);
+            }
+        });
+        if let PacketState::Resolving { next_hop, .. } = self.packet.get() {
+            if next_hop == ip && create {
+                self.alarm.disarm();
+                self.packet.set(PacketState::Ready(mac));
+                self.send_next();
+            }
+        }
+    }
+
+    fn receive_nd(&self, header: &IP6Header, msg: &[u8], src_mac: MacAddress) {
+        if header.get_hop_limit() != ndp::HOP_LIMIT
+            || compute_icmp_message_checksum(header, msg) != 0
+        {
+            return;
+        }
+        let nd = match NdMessage::decode(msg).done() {
+            Some((_, nd)) => nd,
+            None => return,
+        };
+        let link_mac = nd.link_address().and_then(mac_from_link_address);
+        let src = header.get_src_addr();
+        match nd {
+            NdMessage::Solicitation { target, .. } => {
+                if src.is_unspecified() {
+                    // Duplicate Address Detection probes carry no
+                    // link-layer address, and are answered to all nodes.
+                    if link_mac.is_some() {
+                        return;
+                    }
+                } else if let Some(mac) = link_mac {
+                    self.learn_neighbor(src, mac, true);
+                }
+                let addr = self.src_addr.get();
+                if addr.is_unspecified() || target != addr {
+                    return;
+                }
+                self.advertisement.set(if src.is_unspecified() {
+                    Advertisement {
+                        dst: ndp::ALL_NODES,
+                        dst_mac: multicast_mac(&ndp::ALL_NODES),
+                        solicited: false,
+                    }
+                } else {
+                    Advertisement {
+                        dst: src,
+                        dst_mac: link_mac.unwrap_or(src_mac),
+                        solicited: true,
+                    }
+                });
+                self.send_next();
+            }
+            NdMessage::Advertisement {
+                target,
+                solicited,
+                override_,
+                ..
+            } => {
+                if solicited && header.get_dst_addr().is_multicast() {
+                    return;
+                }
+                if let Some(mac) = link_mac {
+                    // Only advertisements answering our own solicitation
+                    // add neighbors; others may refresh cached ones.
+                    let awaited = match self.packet.get() {
+                        PacketState::Resolving { next_hop, .. } => next_hop == target,
+                        _ => false,
+                    };
+                    if awaited || override_ {
+                        self.learn_neighbor(target, mac, awaited);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Process the rest of a packet addressed to this node, which follows
+    /// `header`. Fragments of packets which are not `reassembled` yet are
+    /// added to the packet being reassembled.
+    fn receive_packet(
+        &self,
+        mut header: IP6Header,
+        payload: &[u8],
+        src_mac: MacAddress,
+        reassembled: bool,
+    ) {
+        let payload = match skip_extension_headers(&mut header, payload) {
+            Some(hdr_len) => &payload[hdr_len..],
+            None => return,
+        };
+        if header.get_next_header() == ip6_nh::FRAGMENT {
+            if !reassembled {
+                self.receive_fragment(header, payload, src_mac);
+            }
+            return;
+        }
+
+        if header.get_next_header() == ip6_nh::ICMP
+            && (payload.first() == Some(&ndp::NEIGHBOR_SOLICITATION)
+                || payload.first() == Some(&ndp::NEIGHBOR_ADVERTISEMENT))
+        {
+            // Neighbor Discovery messages are never fragmented (RFC 6980)
+            if !reassembled {
+                self.receive_nd(&header, payload, src_mac);
+            }
+            return;
+        }
+
+        if !self.groups.accepts(&header) {
+            return;
+        }
+        // UDP and ICMPv6 headers are both 8 bytes long
+        if payload.len() < 8 || header.check_transport_checksum(payload) == ReturnCode::FAIL {
+            return;
+        }
+        let client = if header.get_next_header() == ip6_nh::ICMP && self.icmp_client.is_some() {
+            &self.icmp_client
+        } else {
+            &self.recv_client
+        };
+        client.map(|client| client.receive(header, payload));
+    }
+
+    /// Add the fragment in `packet`, which starts with the Fragment header
+    /// and follows `header`, to the packet being reassembled, and process the
+    /// packet once it is complete. Duplicate fragments are ignored, while
+    /// overlapping or inconsistent ones drop the whole packet (RFC 5722).
+    fn receive_fragment(&self, mut header: IP6Header, packet: &[u8], src_mac: MacAddress) {
+        let fragment = match FragmentHeader::decode(packet).done() {
+            Some((_, fragment)) => fragment,
+            None => return,
+        };
+        let data = &packet[FRAGMENT_HDR_LEN..];
+        let start = fragment.offset as usize * 8;
+        let end = start + data.len();
+        if fragment.more && (data.is_empty() || data.len() % 8 != 0) {
+            return;
+        }
+        header.set_next_header(fragment.next_header);
+        if start == 0 && !fragment.more {
+            // An atomic fragment (RFC 6946), which is a whole packet
+            header.set_payload_len(data.len() as u16);
+            self.receive_packet(header, data, src_mac, true);
+            return;
+        }
+
+        let now = self.alarm.now();
+        let same_packet = self.reassembly.map_or(false, |reassembly| {
+            reassembly.id == fragment.id
+                && reassembly.header.get_src_addr() == header.get_src_addr()
+                && reassembly.header.get_dst_addr() == header.get_dst_addr()
+        });
+        if !same_packet {
+            let busy = self.reassembly.map_or(false, |reassembly| {
+                now.wrapping_sub(reassembly.started) < A::ticks_from_seconds(REASSEMBLY_TIMEOUT)
+            });
+            if busy {
+                return;
+            }
+            self.reassembly.set(Reassembly {
+                header: header,
+                id: fragment.id,
+                started: now,
+                received_len: 0,
+                total_len: None,
+            });
+            self.reassembly_map.map(|bitmap| bitmap.clear());
+        }
+
+        let mut reassembly = match self.reassembly.take() {
+            Some(reassembly) => reassembly,
+            None => return,
+        };
+        // The last 8-byte block of the packet may be partial
+        let blocks = (start / 8, (end + 7) / 8);
+        if !fragment.more
+            && reassembly
+                .total_len
+                .map_or(false, |total_len| total_len != end)
+        {
+            // Two different ends: the packet is discarded
+            return;
+        }
+        if blocks.0 < blocks.1
+            && self
+                .reassembly_map
+                .map_or(false, |bitmap| bitmap.is_set(blocks.0, blocks.1))
+        {
+            // A duplicate, which is ignored
+            self.reassembly.set(reassembly);
+            return;
+        }
+        if start == 0 {
+            // The next header of the first fragment is the one of the packet
+            reassembly.header.set_next_header(fragment.next_header);
+        }
+        reassembly.received_len = reassembly.received_len.max(end);
+        if !fragment.more {
+            reassembly.total_len = Some(end);
+        }
+        if reassembly
+            .total_len
+            .map_or(false, |total_len| reassembly.received_len > total_len)
+        {
+            // Data beyond the end of the packet: it is discarded
+            return;
+        }
+        let new_data = self
+            .reassembly_map
+            .map_or(false, |bitmap| bitmap.set_bits(blocks.0, blocks.1));
+        let copied = new_data
+            && self.reassembly_buf.map_or(false, |buf| {
+                if end > buf.len() {
+                    return false;
+                }
+                buf[start..end].copy_from_slice(data);
+                true
+            });
+        if !copied {
+            // An overlap, or a packet too large to be reassembled: it is
+            // discarded
+            return;
+        }
+
+        let complete = reassembly.total_len.filter(|total_len| {
+            self.reassembly_map
+                .map_or(false, |bitmap| bitmap.is_complete((total_len + 7) / 8))
+        });
+        match complete {
+            Some(total_len) => {
+                let mut header = reassembly.header;
+                header.set_payload_len(total_len as u16);
+                self.reassembly_buf.map(|buf| {
+                    self.receive_packet(header, &buf[..total_len], src_mac, true);
+                });
+            }
+            None => self.reassembly.set(reassembly),
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6Ethernet<'a, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.send_client.set(client);
+    }
+
+    /// Also joins the all-nodes and solicited-node multicast groups of the
+    /// new address, so that the MAC receives solicitations for it.
+    fn set_addr(&self, src_addr: IPAddr) {
+        let old_group = multicast_mac(&ndp::solicited_node(&self.src_addr.replace(src_addr)));
+        let new_group = multicast_mac(&ndp::solicited_node(&src_addr));
+        if old_group != new_group {
+            self.ethernet.remove_multicast(old_group);
+        }
+        self.ethernet.add_multicast(multicast_mac(&ndp::ALL_NODES));
+        self.ethernet.add_multicast(new_group);
+    }
+
+    /// Next hops are resolved with Neighbor Discovery, so the 802.15.4
+    /// gateway address is ignored.
+    fn set_gateway(&self, _gateway: ieee802154::MacAddress) {}
+
+    fn set_header(&self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> ReturnCode {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return ReturnCode::FAIL;
+        }
+        if self.packet.get() != PacketState::Idle {
+            return ReturnCode::EBUSY;
+        }
+        if !self.ethernet.link_up() {
+            return ReturnCode::EOFF;
+        }
+        let fits = self.ip6_packet.map_or(false, |ip6_packet| {
+            payload.len() <= ip6_packet.payload.payload.len()
+        });
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        self.ip6_packet.map(|ip6_packet| {
+            ip6_packet.header = IP6Header::default();
+            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.dst_addr = dst;
+            ip6_packet.set_payload(transport_header, payload);
+            ip6_packet.set_transport_checksum();
+        });
+        self.fragment_offset.set(0);
+
+        let next_hop = self.next_hop(dst);
+        let dst_mac = if dst.is_multicast() {
+            Some(multicast_mac(&dst))
+        } else {
+            self.neighbors
+                .and_then(|neighbors| neighbors.lookup(&next_hop))
+        };
+        match dst_mac {
+            Some(dst_mac) if self.tx_buf.is_some() => self.transmit_packet(dst_mac),
+            Some(dst_mac) => {
+                self.packet.set(PacketState::Ready(dst_mac));
+                ReturnCode::SUCCESS
+            }
+            None => {
+                self.packet.set(PacketState::Resolving {
+                    next_hop: next_hop,
+                    solicitations: 1,
+                });
+                self.solicit.set(true);
+                self.send_next();
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Receiver<'a> for IP6Ethernet<'a, A> {
+    fn set_client(&self, client: &'a dyn IP6RecvClient) {
+        self.recv_client.set(client);
+    }
+
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> MulticastGroups for IP6Ethernet<'a, A> {
+    fn join_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups.join_group(group)
+    }
+
+    fn leave_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups.leave_group(group)
+    }
+
+    fn is_member(&self, group: IPAddr) -> bool {
+        self.groups.is_member(group)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ethernet::Client for IP6Ethernet<'a, A> {
+    fn transmit_done(&self, frame: &'static mut [u8], result: ReturnCode) {
+        self.tx_buf.replace(frame);
+        if let PacketState::Transmitting(dst_mac) = self.packet.get() {
+            let sent_len = IP6_HEADER_LEN + self.fragment_offset.get();
+            let fragments_left = self.ip6_packet.map_or(false, |ip6_packet| {
+                sent_len < ip6_packet.get_total_len() as usize
+            });
+            if result == ReturnCode::SUCCESS && fragments_left {
+                self.packet.set(PacketState::Ready(dst_mac));
+            } else {
+                self.packet.set(PacketState::Idle);
+                self.send_done(result);
+            }
+        }
+        self.send_next();
+    }
+
+    fn frame_received(&self, frame: &[u8]) {
+        if frame.len() < HEADER_LEN || u16::from_be_bytes([frame[12], frame[13]]) != ethertype::IPV6
+        {
+            return;
+        }
+        let mut src_mac = [0; 6];
+        src_mac.copy_from_slice(&frame[6..12]);
+        let packet = &frame[HEADER_LEN..];
+
+        let header = match IP6Header::decode(packet).done() {
+            Some((_, header)) => header,
+            None => return,
+        };
+        // Frames may be padded beyond the end of the packet
+        let len = IP6_HEADER_LEN + header.get_payload_len() as usize;
+        if header.get_version() != 6 || len > packet.len() {
+            return;
+        }
+        let dst = header.get_dst_addr();
+        if dst != self.src_addr.get() && !dst.is_multicast() {
+            return;
+        }
+        self.receive_packet(
+            header,
+            &packet[IP6_HEADER_LEN..len],
+            MacAddress::new(src_mac),
+            false,
+        );
+    }
+
+    fn link_status_changed(&self, up: bool) {
+        // Neighbors may be gone once the link comes back
+        if !up {
+            self.neighbors.map(|neighbors| neighbors.clear());
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for IP6Ethernet<'a, A> {
+    fn alarm(&self) {
+        if let PacketState::Resolving {
+            next_hop,
+            solicitations,
+        } = self.packet.get()
+        {
+            if solicitations >= ndp::MAX_MULTICAST_SOLICIT {
+                self.packet.set(PacketState::Idle);
+                self.send_done(ReturnCode::ENOACK);
+            } else {
+                self.packet.set(PacketState::Resolving {
+                    next_hop: next_hop,
+                    solicitations: solicitations + 1,
+                });
+                self.solicit.set(true);
+                self.send_next();
+            }
+        }
+    }
+}
@@ -1,5 +1,7 @@
-use crate::net::ipv6::IP6Header;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::{skip_extension_headers, IP6Header};
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::debug;
 use kernel::ReturnCode;
@@ -31,6 +33,120 @@ pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// Number of multicast groups a receiver can join, besides the all-nodes
+/// groups every node belongs to.
+pub const MAX_MULTICAST_GROUPS: usize = 8;
+
+/// The interface-local all-nodes multicast address, ff01::1.
+const ALL_NODES_INTERFACE: IPAddr = IPAddr([0xff, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+/// The link-local all-nodes multicast address, ff02::1.
+const ALL_NODES_LINK: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+/// Whether `group` is one of the all-nodes groups, which every node
+/// belongs to without joining them.
+pub fn is_all_nodes(group: IPAddr) -> bool {
+    group == ALL_NODES_INTERFACE || group == ALL_NODES_LINK
+}
+
+/// Membership of the multicast groups whose packets a receiver accepts.
+///
+/// Packets sent to a multicast group are dropped unless the node joined it,
+/// except for ICMPv6 messages, which the protocols built on ICMPv6 (Neighbor
+/// Discovery, RPL) filter themselves, and packets to the all-nodes groups.
+pub trait MulticastGroups {
+    /// Joins `group`. Memberships are counted, so that the users of a group
+    /// can join and leave it independently. Returns `EINVAL` if `group` is
+    /// not a multicast address and `ENOMEM` if too many groups are joined.
+    fn join_group(&self, group: IPAddr) -> ReturnCode;
+
+    /// Leaves `group`, once per join. Returns `EINVAL` if the node is not a
+    /// member.
+    fn leave_group(&self, group: IPAddr) -> ReturnCode;
+
+    fn is_member(&self, group: IPAddr) -> bool;
+}
+
+/// A table of joined multicast groups, with the number of joins of each.
+pub struct GroupTable {
+    groups: Cell<[Option<(IPAddr, u8)>; MAX_MULTICAST_GROUPS]>,
+}
+
+impl GroupTable {
+    pub fn new() -> GroupTable {
+        GroupTable {
+            groups: Cell::new([None; MAX_MULTICAST_GROUPS]),
+        }
+    }
+
+    /// Whether a packet with header `header` is for a group the node
+    /// belongs to, or is not sent to a group at all.
+    pub fn accepts(&self, header: &IP6Header) -> bool {
+        let dst = header.get_dst_addr();
+        !dst.is_multicast() || header.get_next_header() == ip6_nh::ICMP || self.is_member(dst)
+    }
+}
+
+impl MulticastGroups for GroupTable {
+    fn join_group(&self, group: IPAddr) -> ReturnCode {
+        if !group.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        let mut groups = self.groups.get();
+        let ret = match groups
+            .iter()
+            .position(|g| g.map_or(false, |g| g.0 == group))
+        {
+            Some(index) => match groups[index] {
+                Some((_, count)) if count < u8::MAX => {
+                    groups[index] = Some((group, count + 1));
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::ENOMEM,
+            },
+            None => match groups.iter().position(|g| g.is_none()) {
+                Some(index) => {
+                    groups[index] = Some((group, 1));
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::ENOMEM,
+            },
+        };
+        self.groups.set(groups);
+        ret
+    }
+
+    fn leave_group(&self, group: IPAddr) -> ReturnCode {
+        let mut groups = self.groups.get();
+        let ret = match groups
+            .iter_mut()
+            .find(|g| g.map_or(false, |g| g.0 == group))
+        {
+            Some(entry) => {
+                *entry = entry.and_then(|(group, count)| {
+                    if count > 1 {
+                        Some((group, count - 1))
+                    } else {
+                        None
+                    }
+                });
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        };
+        self.groups.set(groups);
+        ret
+    }
+
+    fn is_member(&self, group: IPAddr) -> bool {
+        is_all_nodes(group)
+            || self
+                .groups
+                .get()
+                .iter()
+                .any(|g| g.map_or(false, |g| g.0 == group))
+    }
+}
+
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
@@ -38,34 +154,72 @@ pub trait IP6RecvClient {
 /// that are not among the local addresses of this device.
 pub trait IP6Receiver<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient);
+
+    /// Set the client receiving ICMPv6 packets. Without one, ICMPv6 packets
+    /// go to the client set with `set_client()` like all other packets.
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    icmp_client: OptionalCell<&'a dyn IP6RecvClient>,
+    groups: GroupTable,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient) {
         self.client.set(client);
     }
+
+    fn set_icmp_client(&self, client: &'a dyn IP6RecvClient) {
+        self.icmp_client.set(client);
+    }
 }
 
 impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            icmp_client: OptionalCell::empty(),
+            groups: GroupTable::new(),
         }
     }
 }
 
+impl<'a> MulticastGroups for IP6RecvStruct<'a> {
+    fn join_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups.join_group(group)
+    }
+
+    fn leave_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups.leave_group(group)
+    }
+
+    fn is_member(&self, group: IPAddr) -> bool {
+        self.groups.is_member(group)
+    }
+}
+
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
     fn receive(&self, buf: &[u8], len: usize, result: ReturnCode) {
         // TODO: Drop here?
         if len > buf.len() || result != ReturnCode::SUCCESS {
             return;
         }
-        match IP6Header::decode(buf).done() {
-            Some((offset, ip6_header)) => {
+        match IP6Header::decode(&buf[..len]).done() {
+            Some((mut offset, mut ip6_header)) => {
+                match skip_extension_headers(&mut ip6_header, &buf[offset..len]) {
+                    Some(hdr_size) => offset += hdr_size,
+                    None => return, // Dropped.
+                }
+                if !self.groups.accepts(&ip6_header) {
+                    return; // Dropped.
+                }
+                if ip6_header.get_next_header() == ip6_nh::FRAGMENT {
+                    // 6LoWPAN fragments packets itself, so IPv6 fragments
+                    // are not reassembled on this link
+                    return;
+                }
                 let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
                 if checksum_result == ReturnCode::FAIL {
                     debug!("cksum fail!: {:?}", checksum_result);
@@ -74,8 +228,13 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
                 // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
                 // are automatically assumed as fine, rather than dropped
 
-                self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                let client =
+                    if ip6_header.get_next_header() == ip6_nh::ICMP && self.icmp_client.is_some() {
+                        &self.icmp_client
+                    } else {
+                        &self.client
+                    };
+                client.map(|client| client.receive(ip6_header, &buf[offset..len]));
             }
             None => {
                 debug!("failed to decode ipv6 header");
@@ -84,3 +243,33 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_membership() {
+        let table = GroupTable::new();
+        let group = IPAddr([0xff, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x03]);
+        assert!(table.is_member(ALL_NODES_LINK));
+        assert!(!table.is_member(group));
+        assert_eq!(table.join_group(IPAddr::new()), ReturnCode::EINVAL);
+
+        // Memberships are counted
+        assert_eq!(table.join_group(group), ReturnCode::SUCCESS);
+        assert_eq!(table.join_group(group), ReturnCode::SUCCESS);
+        assert_eq!(table.leave_group(group), ReturnCode::SUCCESS);
+        assert!(table.is_member(group));
+        assert_eq!(table.leave_group(group), ReturnCode::SUCCESS);
+        assert!(!table.is_member(group));
+        assert_eq!(table.leave_group(group), ReturnCode::EINVAL);
+
+        let mut other = group;
+        for i in 0..MAX_MULTICAST_GROUPS {
+            other.0[15] = 0x10 + i as u8;
+            assert_eq!(table.join_group(other), ReturnCode::SUCCESS);
+        }
+        assert_eq!(table.join_group(group), ReturnCode::ENOMEM);
+    }
+}
@@ -29,6 +29,10 @@ use kernel::debug;
 use kernel::hil::time;
 use kernel::ReturnCode;
 
+/// The IEEE 802.15.4 short address frames to every node in range are sent
+/// to.
+const BROADCAST_SHORT_ADDR: u16 = 0xffff;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -59,7 +63,8 @@ pub trait IP6Sender<'a> {
     fn set_addr(&self, src_addr: IPAddr);
 
     /// This method sets the gateway/next hop MAC address for this `IP6Sender`
-    /// instance.
+    /// instance. Unicast packets are sent to the gateway, multicast packets
+    /// are broadcast.
     ///
     /// # Arguments
     /// `gateway` - MAC address to send the constructed packet to
@@ -70,7 +75,7 @@ pub trait IP6Sender<'a> {
     /// # Arguments
     /// `ip6_header` - New `IP6Header` that subsequent packets sent via this
     /// `IP6Sender` instance will use
-    fn set_header(&mut self, ip6_header: IP6Header);
+    fn set_header(&self, ip6_header: IP6Header);
 
     /// This method sends the provided transport header and payload to the
     /// given destination IP address
@@ -101,7 +106,6 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
     ip_vis: &'static IpVisibilityCapability,
@@ -120,7 +124,7 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         self.gateway.set(gateway);
     }
 
-    fn set_header(&mut self, ip6_header: IP6Header) {
+    fn set_header(&self, ip6_header: IP6Header) {
         self.ip6_packet
             .map(|ip6_packet| ip6_packet.header = ip6_header);
     }
@@ -135,12 +139,13 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return ReturnCode::FAIL;
         }
-        self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
+        let dst_mac_addr = if dst.is_multicast() {
+            MacAddress::Short(BROADCAST_SHORT_ADDR)
+        } else {
+            self.gateway.get()
+        };
+        self.sixlowpan
+            .init(self.src_mac_addr, dst_mac_addr, self.radio.get_pan(), None);
         self.init_packet(dst, transport_header, payload);
         let ret = self.send_next_fragment();
         ret
@@ -166,7 +171,6 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
             ip_vis: ip_vis,
@@ -1,13 +1,19 @@
 pub mod ip_utils;
+pub mod ipv6_ethernet;
 pub mod ipv6_recv;
 pub mod ipv6_send;
 
 // Reexport the exports of the [`ipv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::ipv6::ipv6::IP6Header`)
 mod ipv6;
+pub use ipv6::FragmentHeader;
 pub use ipv6::IP6Header;
 pub use ipv6::IP6Packet;
 pub use ipv6::IPPayload;
+pub use ipv6::SourceRouteHeader;
 pub use ipv6::TransportHeader;
+pub use ipv6::FRAGMENT_HDR_LEN;
 pub use ipv6::ICMP_HDR_LEN;
 pub use ipv6::UDP_HDR_LEN;
+pub use ipv6::{encode_options_header, ip6_opt, skip_extension_headers};
+pub use ipv6::{skip_routing_header, MAX_SOURCE_ROUTE_LEN, ROUTING_TYPE_SOURCE_ROUTE};
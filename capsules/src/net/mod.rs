@@ -1,5 +1,6 @@
 //! Modules for IPv6 over 6LoWPAN stack
 
+pub mod coap;
 pub mod frag_utils;
 pub mod sixlowpan;
 pub mod util;
@@ -9,6 +10,7 @@ pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
 pub mod network_capabilities;
+pub mod rpl;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
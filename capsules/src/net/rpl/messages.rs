@@ -0,0 +1,437 @@
+//! RPL control messages (RFC 6550 section 6).
+//!
+//! RPL control messages are ICMPv6 messages of type 155, whose code tells
+//! the message apart. The functions of this module work on the message
+//! after the ICMPv6 type, code and checksum, starting with the message base.
+//! Only the messages and options a node of a non-storing mode DODAG needs
+//! are supported:
+//!
+//! - DODAG Information Solicitations (DIS), sent to find DODAGs nearby.
+//! - DODAG Information Objects (DIO), which advertise a DODAG, along with
+//!   its configuration and the prefix addresses are formed from.
+//! - Destination Advertisement Objects (DAO), with which nodes report their
+//!   address and parent to the DODAG root, and their acknowledgements.
+
+use crate::net::icmpv6::sixlowpan_nd::Prefix;
+use crate::net::ipv6::ip_utils::IPAddr;
+
+/// ICMPv6 codes of the RPL control messages.
+pub mod code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+mod option {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DODAG_CONFIGURATION: u8 = 0x04;
+    pub const RPL_TARGET: u8 = 0x05;
+    pub const TRANSIT_INFORMATION: u8 = 0x06;
+    pub const PREFIX_INFORMATION: u8 = 0x08;
+}
+
+/// The link-local all-RPL-nodes multicast address, ff02::1a.
+pub const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Mode of Operation of non-storing DODAGs, in which only the root keeps
+/// routes and sends packets down with source routing headers.
+pub const MOP_NON_STORING: u8 = 1;
+
+/// Largest distance between two sequence counters which are compared.
+pub const SEQUENCE_WINDOW: u8 = 16;
+/// Initial value of sequence counters, in the linear part of the lollipop.
+pub const SEQUENCE_INITIAL: u8 = 240;
+
+const DIO_BASE_LEN: usize = 24;
+const DAO_BASE_LEN: usize = 4;
+const DAO_ACK_BASE_LEN: usize = 4;
+
+const DIO_FLAG_GROUNDED: u8 = 0x80;
+const DAO_FLAG_ACK: u8 = 0x80;
+const DAO_FLAG_DODAG_ID: u8 = 0x40;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Increments a lollipop sequence counter (RFC 6550 section 7.2), which
+/// counts up from `SEQUENCE_INITIAL` to 255 once, and then wraps from 127
+/// to 0.
+pub fn sequence_increment(seq: u8) -> u8 {
+    if seq > 127 {
+        seq.wrapping_add(1)
+    } else {
+        (seq + 1) & 127
+    }
+}
+
+/// Whether the lollipop sequence counter `a` is newer than `b`.
+pub fn sequence_newer(a: u8, b: u8) -> bool {
+    let window = SEQUENCE_WINDOW as u16;
+    match (a > 127, b > 127) {
+        (true, false) => 256 + b as u16 - a as u16 > window,
+        (false, true) => 256 + a as u16 - b as u16 <= window,
+        (true, true) => a > b,
+        (false, false) => {
+            let distance = a.wrapping_sub(b) & 127;
+            distance != 0 && distance as u16 <= window
+        }
+    }
+}
+
+/// The DODAG Configuration option, which carries the parameters shared by
+/// all nodes of a DODAG. The defaults are those of RFC 6550 section 17.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DodagConfig {
+    pub flags: u8,
+    /// The largest Trickle interval is `interval_doublings` doublings of
+    /// the smallest one.
+    pub interval_doublings: u8,
+    /// The smallest Trickle interval is 2 to the power of `interval_min`
+    /// milliseconds.
+    pub interval_min: u8,
+    /// Trickle redundancy constant; 0 disables suppression.
+    pub redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    /// Objective Code Point of the objective function of the DODAG.
+    pub ocp: u16,
+    /// Lifetime of routes, in units of `lifetime_unit` seconds.
+    pub default_lifetime: u8,
+    pub lifetime_unit: u16,
+}
+
+impl Default for DodagConfig {
+    fn default() -> DodagConfig {
+        DodagConfig {
+            flags: 0,
+            interval_doublings: 20,
+            interval_min: 3,
+            redundancy: 10,
+            max_rank_increase: 0,
+            min_hop_rank_increase: 256,
+            ocp: 0,
+            default_lifetime: 0xff,
+            lifetime_unit: 0xffff,
+        }
+    }
+}
+
+/// A DODAG Information Object.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mop: u8,
+    pub preference: u8,
+    /// Destination Advertisement Trigger Sequence Number, incremented to
+    /// have the nodes below send their DAOs again.
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+    pub config: Option<DodagConfig>,
+    /// The first autonomous prefix of the message.
+    pub prefix: Option<Prefix>,
+}
+
+/// A Destination Advertisement Object as sent in non-storing mode: it
+/// advertises a single target, reachable through `parent`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Dao {
+    pub instance_id: u8,
+    /// Whether the root should acknowledge the DAO.
+    pub expect_ack: bool,
+    pub sequence: u8,
+    pub target: IPAddr,
+    pub parent: IPAddr,
+    pub path_sequence: u8,
+    /// Lifetime of the route, in units of the lifetime unit of the DODAG.
+    pub path_lifetime: u8,
+}
+
+/// A DAO acknowledgement.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DaoAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    /// Zero or below 128 if the DAO was accepted.
+    pub status: u8,
+}
+
+/// Calls `f` with the type and the contents of each option in `buf`, after
+/// the type and length bytes. Returns `None` if an option is malformed.
+fn for_each_option<F: FnMut(u8, &[u8])>(buf: &[u8], mut f: F) -> Option<()> {
+    let mut off = 0;
+    while off < buf.len() {
+        if buf[off] == option::PAD1 {
+            off += 1;
+            continue;
+        }
+        if buf.len() < off + 2 || buf.len() < off + 2 + buf[off + 1] as usize {
+            return None;
+        }
+        let option_len = 2 + buf[off + 1] as usize;
+        f(buf[off], &buf[off + 2..off + option_len]);
+        off += option_len;
+    }
+    Some(())
+}
+
+fn decode_config(contents: &[u8]) -> DodagConfig {
+    DodagConfig {
+        flags: contents[0],
+        interval_doublings: contents[1],
+        interval_min: contents[2],
+        redundancy: contents[3],
+        max_rank_increase: u16::from_be_bytes([contents[4], contents[5]]),
+        min_hop_rank_increase: u16::from_be_bytes([contents[6], contents[7]]),
+        ocp: u16::from_be_bytes([contents[8], contents[9]]),
+        default_lifetime: contents[11],
+        lifetime_unit: u16::from_be_bytes([contents[12], contents[13]]),
+    }
+}
+
+fn decode_prefix(contents: &[u8]) -> Prefix {
+    let mut prefix = IPAddr::new();
+    prefix.0.copy_from_slice(&contents[14..30]);
+    Prefix {
+        prefix: prefix,
+        len: contents[0],
+        autonomous: contents[1] & PREFIX_FLAG_AUTONOMOUS != 0,
+        valid_lifetime: u32::from_be_bytes([contents[2], contents[3], contents[4], contents[5]]),
+    }
+}
+
+impl Dio {
+    /// Parses a DIO. Returns `None` if it is malformed.
+    pub fn decode(msg: &[u8]) -> Option<Dio> {
+        if msg.len() < DIO_BASE_LEN {
+            return None;
+        }
+        let mut dodag_id = IPAddr::new();
+        dodag_id.0.copy_from_slice(&msg[8..24]);
+        let mut dio = Dio {
+            instance_id: msg[0],
+            version: msg[1],
+            rank: u16::from_be_bytes([msg[2], msg[3]]),
+            grounded: msg[4] & DIO_FLAG_GROUNDED != 0,
+            mop: (msg[4] >> 3) & 0x07,
+            preference: msg[4] & 0x07,
+            dtsn: msg[5],
+            dodag_id: dodag_id,
+            config: None,
+            prefix: None,
+        };
+        for_each_option(
+            &msg[DIO_BASE_LEN..],
+            |option_type, contents| match option_type {
+                option::DODAG_CONFIGURATION if contents.len() == 14 => {
+                    dio.config = Some(decode_config(contents));
+                }
+                option::PREFIX_INFORMATION if contents.len() == 30 && dio.prefix.is_none() => {
+                    let prefix = decode_prefix(contents);
+                    if prefix.autonomous {
+                        dio.prefix = Some(prefix);
+                    }
+                }
+                _ => {}
+            },
+        )?;
+        Some(dio)
+    }
+
+    /// Writes the DIO into `buf`, which must hold at least 88 bytes, and
+    /// returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.instance_id;
+        buf[1] = self.version;
+        buf[2..4].copy_from_slice(&self.rank.to_be_bytes());
+        buf[4] = (if self.grounded { DIO_FLAG_GROUNDED } else { 0 })
+            | (self.mop & 0x07) << 3
+            | (self.preference & 0x07);
+        buf[5] = self.dtsn;
+        buf[6] = 0;
+        buf[7] = 0;
+        buf[8..24].copy_from_slice(&self.dodag_id.0);
+        let mut len = DIO_BASE_LEN;
+
+        if let Some(config) = self.config {
+            let option = &mut buf[len..len + 16];
+            option[0] = option::DODAG_CONFIGURATION;
+            option[1] = 14;
+            option[2] = config.flags;
+            option[3] = config.interval_doublings;
+            option[4] = config.interval_min;
+            option[5] = config.redundancy;
+            option[6..8].copy_from_slice(&config.max_rank_increase.to_be_bytes());
+            option[8..10].copy_from_slice(&config.min_hop_rank_increase.to_be_bytes());
+            option[10..12].copy_from_slice(&config.ocp.to_be_bytes());
+            option[12] = 0;
+            option[13] = config.default_lifetime;
+            option[14..16].copy_from_slice(&config.lifetime_unit.to_be_bytes());
+            len += 16;
+        }
+        if let Some(prefix) = self.prefix {
+            let option = &mut buf[len..len + 32];
+            option.iter_mut().for_each(|byte| *byte = 0);
+            option[0] = option::PREFIX_INFORMATION;
+            option[1] = 30;
+            option[2] = prefix.len;
+            option[3] = if prefix.autonomous {
+                PREFIX_FLAG_AUTONOMOUS
+            } else {
+                0
+            };
+            // The preferred lifetime is the valid lifetime
+            option[4..8].copy_from_slice(&prefix.valid_lifetime.to_be_bytes());
+            option[8..12].copy_from_slice(&prefix.valid_lifetime.to_be_bytes());
+            option[16..32].copy_from_slice(&prefix.prefix.0);
+            len += 32;
+        }
+        len
+    }
+}
+
+/// Writes a DIS without options into `buf`, and returns its length. The
+/// message is padded so that it is as long as an ICMPv6 header.
+pub fn encode_dis(buf: &mut [u8]) -> usize {
+    buf[..4].copy_from_slice(&[0, 0, option::PADN, 0]);
+    4
+}
+
+impl Dao {
+    /// Writes the DAO, with its RPL Target and Transit Information options,
+    /// into `buf`, which must hold at least 46 bytes, and returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[0] = self.instance_id;
+        buf[1] = if self.expect_ack { DAO_FLAG_ACK } else { 0 };
+        buf[2] = 0;
+        buf[3] = self.sequence;
+        let mut len = DAO_BASE_LEN;
+
+        let target = &mut buf[len..len + 20];
+        target[0] = option::RPL_TARGET;
+        target[1] = 18;
+        target[2] = 0;
+        target[3] = 128;
+        target[4..20].copy_from_slice(&self.target.0);
+        len += 20;
+
+        let transit = &mut buf[len..len + 22];
+        transit[0] = option::TRANSIT_INFORMATION;
+        transit[1] = 20;
+        transit[2] = 0;
+        transit[3] = 0;
+        transit[4] = self.path_sequence;
+        transit[5] = self.path_lifetime;
+        transit[6..22].copy_from_slice(&self.parent.0);
+        len += 22;
+        len
+    }
+}
+
+impl DaoAck {
+    /// Parses a DAO acknowledgement. Returns `None` if it is malformed.
+    pub fn decode(msg: &[u8]) -> Option<DaoAck> {
+        if msg.len() < DAO_ACK_BASE_LEN {
+            return None;
+        }
+        // The DODAG ID follows the base if the D flag is set.
+        let has_dodag_id = msg[1] & DAO_FLAG_DODAG_ID != 0;
+        if has_dodag_id && msg.len() < DAO_ACK_BASE_LEN + 16 {
+            return None;
+        }
+        Some(DaoAck {
+            instance_id: msg[0],
+            sequence: msg[2],
+            status: msg[3],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dio() -> [u8; 72] {
+        let mut msg = [0; 72];
+        // Instance 30, version 240, rank 256, grounded, non-storing, DTSN 1
+        msg[..8].copy_from_slice(&[30, 240, 1, 0, 0x88, 1, 0, 0]);
+        msg[8..24].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        // DODAG configuration with the default values
+        msg[24..40].copy_from_slice(&[4, 14, 0, 20, 3, 10, 0, 0, 1, 0, 0, 0, 0, 0xff, 0xff, 0xff]);
+        // Prefix information: 2001:db8::/64, autonomous
+        msg[40..48].copy_from_slice(&[8, 30, 64, 0x40, 0, 0, 0x0e, 0x10]);
+        msg[56..60].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8]);
+        msg
+    }
+
+    #[test]
+    fn decodes_dio() {
+        let dio = Dio::decode(&dio()).unwrap();
+        assert_eq!(dio.instance_id, 30);
+        assert_eq!(dio.version, 240);
+        assert_eq!(dio.rank, 256);
+        assert!(dio.grounded);
+        assert_eq!(dio.mop, MOP_NON_STORING);
+        assert_eq!(dio.dtsn, 1);
+        assert_eq!(dio.dodag_id.0[15], 1);
+        assert_eq!(dio.config, Some(DodagConfig::default()));
+        let prefix = dio.prefix.unwrap();
+        assert_eq!(prefix.len, 64);
+        assert_eq!(prefix.valid_lifetime, 3600);
+        assert_eq!(prefix.prefix.0[..4], [0x20, 0x01, 0x0d, 0xb8]);
+    }
+
+    #[test]
+    fn dio_round_trip() {
+        let dio = Dio::decode(&dio()).unwrap();
+        let mut buf = [0; 88];
+        let len = dio.encode(&mut buf);
+        assert_eq!(len, 72);
+        assert_eq!(Dio::decode(&buf[..len]), Some(dio));
+    }
+
+    #[test]
+    fn rejects_truncated_option() {
+        let msg = dio();
+        assert_eq!(Dio::decode(&msg[..60]), None);
+    }
+
+    #[test]
+    fn encodes_dao() {
+        let mut target = IPAddr::new();
+        target.0[15] = 2;
+        let mut parent = IPAddr::new();
+        parent.0[15] = 3;
+        let dao = Dao {
+            instance_id: 30,
+            expect_ack: true,
+            sequence: 241,
+            target: target,
+            parent: parent,
+            path_sequence: 242,
+            path_lifetime: 30,
+        };
+        let mut buf = [0; 46];
+        assert_eq!(dao.encode(&mut buf), 46);
+        assert_eq!(buf[..8], [30, 0x80, 0, 241, 5, 18, 0, 128]);
+        assert_eq!(buf[23], 2);
+        assert_eq!(buf[24..30], [6, 20, 0, 0, 242, 30]);
+        assert_eq!(buf[45], 3);
+    }
+
+    #[test]
+    fn lollipop_counters() {
+        assert_eq!(sequence_increment(SEQUENCE_INITIAL), 241);
+        assert_eq!(sequence_increment(255), 0);
+        assert_eq!(sequence_increment(127), 0);
+        assert!(sequence_newer(241, 240));
+        assert!(sequence_newer(0, 255));
+        assert!(sequence_newer(0, 127));
+        assert!(!sequence_newer(127, 0));
+        assert!(!sequence_newer(5, 5));
+        // Counters far apart in the circular region are not comparable
+        assert!(!sequence_newer(100, 10));
+    }
+}
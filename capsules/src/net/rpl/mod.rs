@@ -0,0 +1,6 @@
+pub mod messages;
+pub mod of0;
+pub mod rpl_node;
+pub mod trickle;
+
+pub use self::rpl_node::RplNode;
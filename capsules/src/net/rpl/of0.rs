@@ -0,0 +1,71 @@
+//! Objective Function Zero (RFC 6552).
+//!
+//! OF0 computes the rank of a node from the rank of its preferred parent
+//! alone, adding a fixed step per hop since no link metrics are available.
+//! The preferred parent is the candidate which yields the lowest rank; the
+//! current one is only replaced by a candidate that is better by at least
+//! `PARENT_SWITCH_THRESHOLD`, to keep the DODAG from churning.
+
+/// Objective Code Point of OF0.
+pub const OCP: u16 = 0;
+
+/// Rank of a node which is not part of a DODAG, or advertises that it
+/// cannot be used as a parent.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// Rank step of a hop over a link of unknown quality.
+pub const DEFAULT_STEP_OF_RANK: u16 = 3;
+pub const DEFAULT_RANK_FACTOR: u16 = 1;
+pub const DEFAULT_RANK_STRETCH: u16 = 0;
+
+/// Rank improvement, in multiples of `MinHopRankIncrease`, for which the
+/// preferred parent is replaced.
+pub const PARENT_SWITCH_THRESHOLD: u16 = 1;
+
+/// The amount a hop adds to the rank.
+pub fn rank_increase(min_hop_rank_increase: u16) -> u16 {
+    (DEFAULT_RANK_FACTOR * DEFAULT_STEP_OF_RANK + DEFAULT_RANK_STRETCH)
+        .saturating_mul(min_hop_rank_increase)
+}
+
+/// The rank of a node whose preferred parent has rank `parent_rank`.
+pub fn rank_through(parent_rank: u16, min_hop_rank_increase: u16) -> u16 {
+    if parent_rank == INFINITE_RANK {
+        return INFINITE_RANK;
+    }
+    parent_rank.saturating_add(rank_increase(min_hop_rank_increase))
+}
+
+/// The integer part of `rank`, which is compared to tell parents from
+/// siblings and children.
+pub fn dag_rank(rank: u16, min_hop_rank_increase: u16) -> u16 {
+    rank / min_hop_rank_increase.max(1)
+}
+
+/// Whether a candidate parent with rank `candidate` should replace the
+/// preferred parent, which has rank `current`.
+pub fn prefer(candidate: u16, current: u16, min_hop_rank_increase: u16) -> bool {
+    let threshold = PARENT_SWITCH_THRESHOLD.saturating_mul(min_hop_rank_increase);
+    candidate.saturating_add(threshold) <= current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_computation() {
+        // A child of a root with the default MinHopRankIncrease
+        assert_eq!(rank_through(256, 256), 1024);
+        assert_eq!(dag_rank(1024, 256), 4);
+        assert_eq!(rank_through(INFINITE_RANK, 256), INFINITE_RANK);
+        assert_eq!(rank_through(0xff00, 256), INFINITE_RANK);
+    }
+
+    #[test]
+    fn parent_hysteresis() {
+        assert!(prefer(256, 1024, 256));
+        assert!(!prefer(1000, 1024, 256));
+        assert!(!prefer(1024, 1024, 256));
+    }
+}
@@ -0,0 +1,790 @@
+//! A node of a non-storing mode RPL network (RFC 6550).
+//!
+//! RPL builds a Destination-Oriented DAG (DODAG) rooted at a border router,
+//! which lets nodes beyond one radio hop of the root reach it and be
+//! reached. This node joins a DODAG as a leaf:
+//!
+//! - While not part of a DODAG, it multicasts DODAG Information Solicitations
+//!   (DIS) to have nearby nodes advertise theirs. The solicitations are timed
+//!   by a Trickle timer, from `DIS_INTERVAL_MS` to `DIS_DOUBLINGS` doublings
+//!   of it, so that nodes starting together do not solicit together, and a
+//!   node skips its DIS when it heard one from a neighbor in the interval.
+//! - The first DODAG Information Object (DIO) of a non-storing DODAG using
+//!   Objective Function Zero is joined. Its senders become candidate
+//!   parents, and the one yielding the lowest rank becomes the preferred
+//!   parent, to which unicast packets are sent.
+//! - An address is formed from the prefix carried in the DIOs, and reported
+//!   to the root in a Destination Advertisement Object (DAO) along with the
+//!   preferred parent. The root routes packets down to the node from the
+//!   parents of all nodes, with source routing headers (RFC 6554). DAOs are
+//!   retransmitted until the root acknowledges them, and refreshed before
+//!   their route expires or when a parent asks for it. A parent through
+//!   which no DAO gets acknowledged is dropped.
+//! - A new DODAG version from the root discards the parents of the node,
+//!   which joins the new version from the sender of the DIO.
+//!
+//! The node takes care of the source address and the gateway of the
+//! `IP6Sender`, and is meant to be used instead of 6LoWPAN Neighbor
+//! Discovery.
+//!
+//! Known problems: the IPv6 layer does not forward packets, so the node
+//! cannot be a router. It neither advertises the DODAG nor answers DIS
+//! messages, and received packets whose source routing header has segments
+//! left are dropped. Forwarding needs the 6LoWPAN sender to send packets
+//! carrying extension headers, which it cannot encode. Only one DODAG is
+//! joined at a time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dis_timer = static_init!(
+//!     Trickle<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     Trickle::new(dis_alarm, seed)
+//! );
+//! dis_alarm.set_alarm_client(dis_timer);
+//! let rpl_node = static_init!(
+//!     RplNode<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     RplNode::new(icmp_responder, icmp_responder, rpl_alarm, dis_timer, src_mac_addr)
+//! );
+//! rpl_alarm.set_alarm_client(rpl_node);
+//! dis_timer.set_client(rpl_node);
+//! icmp_responder.set_rpl_client(rpl_node);
+//! rpl_node.start();
+//! ```
+
+use crate::net::icmpv6::icmpv6_responder::{ICMP6MessageClient, ICMP6MessageSender};
+use crate::net::icmpv6::sixlowpan_nd::link_address_from_link_local;
+use crate::net::icmpv6::sixlowpan_nd::Prefix;
+use crate::net::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::IP6Sender;
+use crate::net::ipv6::IP6Header;
+use crate::net::rpl::messages::{self, code, Dao, DaoAck, Dio, DodagConfig};
+use crate::net::rpl::of0::{self, INFINITE_RANK};
+use crate::net::rpl::trickle::{Trickle, TrickleClient};
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::debug;
+use kernel::hil::time;
+use kernel::ReturnCode;
+
+/// Number of candidate parents kept.
+pub const MAX_PARENTS: usize = 4;
+
+/// Shortest interval of the DIS timer, which doubles up to 80 s.
+pub const DIS_INTERVAL_MS: u32 = 10_000;
+pub const DIS_DOUBLINGS: u8 = 3;
+/// Number of DIS messages from neighbors after which the node does not send
+/// its own in an interval.
+pub const DIS_REDUNDANCY: u8 = 1;
+
+pub const DAO_RETRANSMIT_MS: u32 = 5_000;
+/// Number of DAOs sent without acknowledgement before the preferred parent
+/// is dropped.
+pub const MAX_DAO_TRANSMISSIONS: u8 = 4;
+/// Time after which DAOs for routes that do not expire are refreshed.
+pub const DAO_REFRESH_MAX_MS: u32 = 60 * 60 * 1000;
+
+/// Lifetime meaning that routes do not expire.
+const INFINITE_LIFETIME: u8 = 0xff;
+
+/// Longest RPL control message received or sent.
+const MAX_MESSAGE_LEN: usize = 128;
+
+#[derive(Copy, Clone, PartialEq)]
+struct Parent {
+    /// Link-local address of the parent.
+    addr: IPAddr,
+    rank: u16,
+    dtsn: u8,
+}
+
+/// The DODAG the node is part of.
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    version: u8,
+    dodag_id: IPAddr,
+    config: DodagConfig,
+    prefix: Option<Prefix>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    /// Looking for a DODAG.
+    Detached,
+    Joined,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum DaoState {
+    Idle,
+    /// Waiting for the acknowledgement of the DAO; `count` were sent.
+    Pending {
+        count: u8,
+    },
+    Acknowledged,
+}
+
+pub struct RplNode<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6MessageSender<'a>,
+    ip_sender: &'a dyn IP6Sender<'a>,
+    alarm: &'a A,
+    dis_timer: &'a Trickle<'a, A>,
+    link_local: IPAddr,
+    state: Cell<State>,
+    dodag: OptionalCell<Dodag>,
+    parents: [Cell<Option<Parent>>; MAX_PARENTS],
+    /// Link-local address of the preferred parent.
+    preferred: OptionalCell<IPAddr>,
+    rank: Cell<u16>,
+    /// Address formed from the prefix of the DODAG.
+    address: OptionalCell<IPAddr>,
+    dao: Cell<DaoState>,
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+}
+
+impl<'a, A: time::Alarm<'a>> RplNode<'a, A> {
+    /// `ip_sender` is the sender whose source address and gateway are kept
+    /// up to date. Messages are sent through `icmp_sender`, which should
+    /// share the same `IP6Sender`. `dis_timer` times the DIS messages, and
+    /// should be seeded differently than the timers of neighbors.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6MessageSender<'a>,
+        ip_sender: &'a dyn IP6Sender<'a>,
+        alarm: &'a A,
+        dis_timer: &'a Trickle<'a, A>,
+        mac_addr: MacAddress,
+    ) -> RplNode<'a, A> {
+        RplNode {
+            icmp_sender: icmp_sender,
+            ip_sender: ip_sender,
+            alarm: alarm,
+            dis_timer: dis_timer,
+            link_local: IPAddr::generate_from_mac(mac_addr),
+            state: Cell::new(State::Idle),
+            dodag: OptionalCell::empty(),
+            parents: Default::default(),
+            preferred: OptionalCell::empty(),
+            rank: Cell::new(INFINITE_RANK),
+            address: OptionalCell::empty(),
+            dao: Cell::new(DaoState::Idle),
+            dao_sequence: Cell::new(messages::SEQUENCE_INITIAL),
+            path_sequence: Cell::new(messages::SEQUENCE_INITIAL),
+        }
+    }
+
+    /// Starts looking for a DODAG, using the link-local address as source
+    /// address in the meantime.
+    pub fn start(&self) {
+        if self.state.get() == State::Idle {
+            self.ip_sender.set_addr(self.link_local);
+            self.look_for_dodag();
+        }
+    }
+
+    /// The rank of the node, `INFINITE_RANK` if it is not part of a DODAG.
+    pub fn rank(&self) -> u16 {
+        self.rank.get()
+    }
+
+    /// The address formed from the prefix of the DODAG, if any.
+    pub fn address(&self) -> Option<IPAddr> {
+        self.address.map(|addr| *addr)
+    }
+
+    /// The link-local address of the preferred parent, if any.
+    pub fn preferred_parent(&self) -> Option<IPAddr> {
+        self.preferred.map(|parent| *parent)
+    }
+
+    fn arm(&self, ms: u32) {
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(ms));
+    }
+
+    fn min_hop_rank_increase(&self) -> u16 {
+        self.dodag
+            .map_or(DodagConfig::default().min_hop_rank_increase, |dodag| {
+                dodag.config.min_hop_rank_increase
+            })
+    }
+
+    /// Sends the RPL control message `msg`, which starts with the message
+    /// base.
+    fn send(&self, dst: IPAddr, code: u8, msg: &[u8]) {
+        let mut header = ICMP6Header::new(ICMP6Type::Type155);
+        header.set_code(code);
+        header.set_options(ICMP6HeaderOptions::Type155 {
+            base: u32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]),
+        });
+        // Lost messages are sent again when their timer fires
+        let _ = self.icmp_sender.send_message(dst, header, &msg[4..]);
+    }
+
+    /// Starts soliciting DIOs.
+    fn look_for_dodag(&self) {
+        self.state.set(State::Detached);
+        self.dis_timer
+            .start(DIS_INTERVAL_MS, DIS_DOUBLINGS, DIS_REDUNDANCY);
+    }
+
+    /// Sends a new DAO, with new sequence numbers.
+    fn advertise_route(&self) {
+        self.dao_sequence
+            .set(messages::sequence_increment(self.dao_sequence.get()));
+        self.path_sequence
+            .set(messages::sequence_increment(self.path_sequence.get()));
+        self.dao.set(DaoState::Pending { count: 0 });
+        self.send_dao();
+    }
+
+    /// Sends the current DAO to the root and schedules its retransmission.
+    fn send_dao(&self) {
+        let count = match self.dao.get() {
+            DaoState::Pending { count } => count,
+            _ => return,
+        };
+        let (dodag, parent, address) = match (
+            self.dodag.map(|dodag| *dodag),
+            self.preferred.map(|parent| *parent),
+            self.address.map(|address| *address),
+        ) {
+            (Some(dodag), Some(parent), Some(address)) => (dodag, parent, address),
+            _ => {
+                // Without an address there is nothing to advertise
+                self.dao.set(DaoState::Idle);
+                return;
+            }
+        };
+        // The root knows the parent by the address it formed from the prefix
+        let mut parent_address = parent;
+        if let Some(prefix) = dodag.prefix {
+            parent_address.set_prefix(&prefix.prefix.0, prefix.len);
+        }
+        let dao = Dao {
+            instance_id: dodag.instance_id,
+            expect_ack: true,
+            sequence: self.dao_sequence.get(),
+            target: address,
+            parent: parent_address,
+            path_sequence: self.path_sequence.get(),
+            path_lifetime: dodag.config.default_lifetime,
+        };
+        let mut msg = [0; MAX_MESSAGE_LEN];
+        let len = dao.encode(&mut msg);
+        self.send(dodag.dodag_id, code::DAO, &msg[..len]);
+
+        self.dao.set(DaoState::Pending { count: count + 1 });
+        self.arm(DAO_RETRANSMIT_MS);
+    }
+
+    /// Time after which an acknowledged route is advertised again, three
+    /// quarters of its lifetime.
+    fn refresh_interval_ms(&self) -> u32 {
+        self.dodag.map_or(DAO_REFRESH_MAX_MS, |dodag| {
+            let config = dodag.config;
+            if config.default_lifetime == INFINITE_LIFETIME {
+                return DAO_REFRESH_MAX_MS;
+            }
+            let lifetime_ms = config.default_lifetime as u64 * config.lifetime_unit as u64 * 1000;
+            (lifetime_ms / 4 * 3).min(DAO_REFRESH_MAX_MS as u64) as u32
+        })
+    }
+
+    /// Leaves the DODAG and looks for another one.
+    fn detach(&self) {
+        self.dodag.clear();
+        self.parents.iter().for_each(|parent| parent.set(None));
+        self.preferred.clear();
+        self.rank.set(INFINITE_RANK);
+        self.address.clear();
+        self.ip_sender.set_addr(self.link_local);
+        self.dao.set(DaoState::Idle);
+        self.alarm.disarm();
+        self.look_for_dodag();
+    }
+
+    fn find_parent(&self, addr: &IPAddr) -> Option<&Cell<Option<Parent>>> {
+        self.parents
+            .iter()
+            .find(|parent| parent.get().map_or(false, |parent| parent.addr == *addr))
+    }
+
+    fn remove_parent(&self, addr: &IPAddr) {
+        self.find_parent(addr).map(|parent| parent.set(None));
+    }
+
+    /// Adds or updates a candidate parent. A full parent set makes room for
+    /// the new parent only if it is better than the worst one.
+    fn update_parent(&self, new: Parent) {
+        if let Some(parent) = self.find_parent(&new.addr) {
+            parent.set(Some(new));
+            return;
+        }
+        let slot = self
+            .parents
+            .iter()
+            .max_by_key(|parent| parent.get().map_or(u32::MAX, |parent| parent.rank as u32));
+        if let Some(slot) = slot {
+            if slot.get().map_or(true, |worst| new.rank < worst.rank) {
+                slot.set(Some(new));
+            }
+        }
+    }
+
+    /// Picks the preferred parent among the candidates and updates the rank
+    /// of the node.
+    fn select_parent(&self) {
+        let min_hop_rank_increase = self.min_hop_rank_increase();
+        let current = self
+            .preferred
+            .map_or(None, |addr| self.find_parent(addr))
+            .and_then(|parent| parent.get());
+        let best = self
+            .parents
+            .iter()
+            .filter_map(|parent| parent.get())
+            .min_by_key(|parent| parent.rank);
+        let selected = match (current, best) {
+            (Some(current), Some(best))
+                if !of0::prefer(best.rank, current.rank, min_hop_rank_increase) =>
+            {
+                current
+            }
+            (_, Some(best)) => best,
+            (_, None) => {
+                self.detach();
+                return;
+            }
+        };
+
+        let rank = of0::rank_through(selected.rank, min_hop_rank_increase);
+        let parent_changed = current.map_or(true, |current| current.addr != selected.addr);
+        self.rank.set(rank);
+        // Nodes no higher in the DODAG than this one cannot be parents
+        let dag_rank = of0::dag_rank(rank, min_hop_rank_increase);
+        for parent in self.parents.iter() {
+            if let Some(candidate) = parent.get() {
+                if of0::dag_rank(candidate.rank, min_hop_rank_increase) >= dag_rank {
+                    parent.set(None);
+                }
+            }
+        }
+
+        if parent_changed {
+            self.preferred.set(selected.addr);
+            if let Some(mac) = link_address_from_link_local(&selected.addr) {
+                self.ip_sender.set_gateway(mac);
+            }
+            self.advertise_route();
+        }
+    }
+
+    /// Forms the address of the node from the prefix of the DODAG, and
+    /// advertises it if it changed.
+    fn configure_address(&self, prefix: Option<Prefix>) {
+        let address = match prefix {
+            Some(prefix) if prefix.len == 64 && prefix.valid_lifetime != 0 => {
+                let mut address = self.link_local;
+                address.set_prefix(&prefix.prefix.0, prefix.len);
+                address
+            }
+            _ => return,
+        };
+        if self.address.map_or(true, |current| *current != address) {
+            self.address.set(address);
+            self.ip_sender.set_addr(address);
+            if self.preferred.is_some() {
+                self.advertise_route();
+            }
+        }
+    }
+
+    /// Joins the DODAG advertised by `dio`, or a new version of it.
+    fn join(&self, src: IPAddr, dio: &Dio, config: DodagConfig) {
+        self.parents.iter().for_each(|parent| parent.set(None));
+        self.preferred.clear();
+        self.rank.set(INFINITE_RANK);
+        self.dodag.set(Dodag {
+            instance_id: dio.instance_id,
+            version: dio.version,
+            dodag_id: dio.dodag_id,
+            config: config,
+            prefix: dio.prefix,
+        });
+        self.state.set(State::Joined);
+        self.dis_timer.stop();
+        self.alarm.disarm();
+        self.configure_address(dio.prefix);
+        self.update_parent(Parent {
+            addr: src,
+            rank: dio.rank,
+            dtsn: dio.dtsn,
+        });
+        self.select_parent();
+    }
+
+    fn receive_dio(&self, src: IPAddr, dio: Dio) {
+        if !src.is_unicast_link_local()
+            || link_address_from_link_local(&src).is_none()
+            || dio.mop != messages::MOP_NON_STORING
+        {
+            return;
+        }
+        let dodag = match self.dodag.map(|dodag| *dodag) {
+            Some(dodag) => dodag,
+            None => {
+                let config = dio.config.unwrap_or_default();
+                if config.ocp == of0::OCP && dio.rank != INFINITE_RANK {
+                    self.join(src, &dio, config);
+                }
+                return;
+            }
+        };
+        if dio.instance_id != dodag.instance_id || dio.dodag_id != dodag.dodag_id {
+            return;
+        }
+        if messages::sequence_newer(dio.version, dodag.version) {
+            // Global repair: parents from the older version are discarded
+            if dio.rank != INFINITE_RANK {
+                self.join(src, &dio, dio.config.unwrap_or(dodag.config));
+            }
+            return;
+        }
+        if dio.version != dodag.version {
+            return;
+        }
+
+        let previous = self.find_parent(&src).and_then(|parent| parent.get());
+        let min_hop_rank_increase = self.min_hop_rank_increase();
+        let is_preferred = self.preferred.map_or(false, |parent| *parent == src);
+        if dio.rank == INFINITE_RANK {
+            self.remove_parent(&src);
+        } else if is_preferred
+            || of0::dag_rank(dio.rank, min_hop_rank_increase)
+                < of0::dag_rank(self.rank.get(), min_hop_rank_increase)
+        {
+            self.update_parent(Parent {
+                addr: src,
+                rank: dio.rank,
+                dtsn: dio.dtsn,
+            });
+        }
+        self.select_parent();
+
+        if self.state.get() != State::Joined {
+            // The node detached
+            return;
+        }
+        if let Some(prefix) = dio.prefix {
+            self.dodag.map(|dodag| dodag.prefix = Some(prefix));
+            self.configure_address(Some(prefix));
+        }
+        // A new DTSN of the preferred parent asks for new DAOs
+        let still_preferred = self.preferred.map_or(false, |parent| *parent == src);
+        let dtsn_changed = previous.map_or(false, |parent| {
+            messages::sequence_newer(dio.dtsn, parent.dtsn)
+        });
+        if is_preferred && still_preferred && dtsn_changed {
+            self.advertise_route();
+        }
+    }
+
+    fn receive_dao_ack(&self, ack: DaoAck) {
+        let expected = match self.dao.get() {
+            DaoState::Pending { .. } => {
+                self.dodag
+                    .map_or(false, |dodag| dodag.instance_id == ack.instance_id)
+                    && ack.sequence == self.dao_sequence.get()
+            }
+            _ => false,
+        };
+        if !expected {
+            return;
+        }
+        if ack.status < 128 {
+            self.dao.set(DaoState::Acknowledged);
+            self.arm(self.refresh_interval_ms());
+        } else {
+            debug!("RPL: DAO rejected with status {}", ack.status);
+            self.dao.set(DaoState::Idle);
+            self.alarm.disarm();
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ICMP6MessageClient for RplNode<'a, A> {
+    fn message_sent(&self, _result: ReturnCode) {
+        // Lost messages are retransmitted when the alarm fires.
+    }
+
+    fn message_received(&self, ip_header: &IP6Header, icmp_header: ICMP6Header, body: &[u8]) {
+        let base = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type155 { base } => base,
+            _ => return,
+        };
+        let len = 4 + body.len();
+        if self.state.get() == State::Idle || len > MAX_MESSAGE_LEN {
+            return;
+        }
+        let mut msg = [0; MAX_MESSAGE_LEN];
+        msg[..4].copy_from_slice(&base.to_be_bytes());
+        msg[4..len].copy_from_slice(body);
+        let msg = &msg[..len];
+
+        let src = ip_header.get_src_addr();
+        match icmp_header.get_code() {
+            code::DIS => {
+                if self.state.get() == State::Detached {
+                    self.dis_timer.consistent();
+                }
+            }
+            code::DIO => {
+                if let Some(dio) = Dio::decode(msg) {
+                    self.receive_dio(src, dio);
+                }
+            }
+            code::DAO_ACK => {
+                if let Some(ack) = DaoAck::decode(msg) {
+                    self.receive_dao_ack(ack);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for RplNode<'a, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Joined => match self.dao.get() {
+                DaoState::Pending { count } if count >= MAX_DAO_TRANSMISSIONS => {
+                    // The preferred parent does not seem to pass DAOs on
+                    if let Some(parent) = self.preferred.take() {
+                        self.remove_parent(&parent);
+                    }
+                    self.select_parent();
+                }
+                DaoState::Pending { .. } => self.send_dao(),
+                DaoState::Acknowledged => self.advertise_route(),
+                DaoState::Idle => {}
+            },
+            State::Detached | State::Idle => {}
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TrickleClient for RplNode<'a, A> {
+    /// Multicasts a DIS.
+    fn transmit(&self) {
+        if self.state.get() == State::Detached {
+            let mut msg = [0; 4];
+            let len = messages::encode_dis(&mut msg);
+            self.send(messages::ALL_RPL_NODES, code::DIS, &msg[..len]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::net::icmpv6::icmpv6_responder::ICMP6MessageClient;
+    use crate::net::ipv6::ipv6_send::IP6SendClient;
+    use crate::net::ipv6::TransportHeader;
+    use crate::net::network_capabilities::NetworkCapability;
+    use core::cell::RefCell;
+    use kernel::common::leasable_buffer::LeasableBuffer;
+    use kernel::hil::time::{AlarmClient, Freq1KHz, Ticks, Ticks32};
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestAlarm {
+        alarm: Cell<Option<u32>>,
+    }
+
+    impl time::Time for TestAlarm {
+        type Frequency = Freq1KHz;
+        type Ticks = Ticks32;
+
+        fn now(&self) -> Ticks32 {
+            0.into()
+        }
+    }
+
+    impl<'a> time::Alarm<'a> for TestAlarm {
+        fn set_alarm_client(&'a self, _client: &'a dyn AlarmClient) {}
+
+        fn set_alarm(&self, reference: Ticks32, dt: Ticks32) {
+            self.alarm.set(Some(reference.wrapping_add(dt).into_u32()));
+        }
+
+        fn get_alarm(&self) -> Ticks32 {
+            self.alarm.get().unwrap_or(0).into()
+        }
+
+        fn disarm(&self) -> ReturnCode {
+            self.alarm.set(None);
+            ReturnCode::SUCCESS
+        }
+
+        fn is_armed(&self) -> bool {
+            self.alarm.get().is_some()
+        }
+
+        fn minimum_dt(&self) -> Ticks32 {
+            1.into()
+        }
+    }
+
+    /// Records the destination and code of the RPL messages sent.
+    struct TestSender {
+        sent: RefCell<Vec<(IPAddr, u8)>>,
+    }
+
+    impl TestSender {
+        fn take(&self) -> Vec<(IPAddr, u8)> {
+            self.sent.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl<'a> ICMP6MessageSender<'a> for TestSender {
+        fn set_message_client(&self, _client: &'a dyn ICMP6MessageClient) {}
+
+        fn set_rpl_client(&self, _client: &'a dyn ICMP6MessageClient) {}
+
+        fn send_message(&self, dst: IPAddr, icmp_header: ICMP6Header, _body: &[u8]) -> ReturnCode {
+            self.sent.borrow_mut().push((dst, icmp_header.get_code()));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    impl<'a> IP6Sender<'a> for TestSender {
+        fn set_client(&self, _client: &'a dyn IP6SendClient) {}
+
+        fn set_addr(&self, _src_addr: IPAddr) {}
+
+        fn set_gateway(&self, _gateway: MacAddress) {}
+
+        fn set_header(&self, _ip6_header: IP6Header) {}
+
+        fn send_to(
+            &self,
+            _dst: IPAddr,
+            _transport_header: TransportHeader,
+            _payload: &LeasableBuffer<'static, u8>,
+            _net_cap: &'static NetworkCapability,
+        ) -> ReturnCode {
+            ReturnCode::FAIL
+        }
+    }
+
+    struct Test {
+        node: &'static RplNode<'static, TestAlarm>,
+        dis_timer: &'static Trickle<'static, TestAlarm>,
+        dis_alarm: &'static TestAlarm,
+        sender: &'static TestSender,
+    }
+
+    impl Test {
+        fn new() -> Test {
+            let sender: &'static TestSender = Box::leak(Box::new(TestSender {
+                sent: RefCell::new(Vec::new()),
+            }));
+            let alarm = Box::leak(Box::new(TestAlarm {
+                alarm: Cell::new(None),
+            }));
+            let dis_alarm: &'static TestAlarm = Box::leak(Box::new(TestAlarm {
+                alarm: Cell::new(None),
+            }));
+            let dis_timer: &'static Trickle<'static, TestAlarm> =
+                Box::leak(Box::new(Trickle::new(dis_alarm, 7)));
+            let node = Box::leak(Box::new(RplNode::new(
+                sender,
+                sender,
+                alarm,
+                dis_timer,
+                MacAddress::Short(1),
+            )));
+            dis_timer.set_client(node);
+            Test {
+                node,
+                dis_timer,
+                dis_alarm,
+                sender,
+            }
+        }
+
+        /// Fires the DIS timer, and returns the delay it was armed with.
+        fn fire_dis_timer(&self) -> u32 {
+            let dt = self.dis_alarm.alarm.take().unwrap();
+            self.dis_timer.alarm();
+            dt
+        }
+
+        fn receive(&self, src: IPAddr, code: u8, msg: &[u8]) {
+            let mut ip_header = IP6Header::new();
+            ip_header.src_addr = src;
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+            icmp_header.set_code(code);
+            icmp_header.set_options(ICMP6HeaderOptions::Type155 {
+                base: u32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]]),
+            });
+            self.node
+                .message_received(&ip_header, icmp_header, &msg[4..]);
+        }
+    }
+
+    #[test]
+    fn solicitations_timed_by_trickle() {
+        let test = Test::new();
+        test.node.start();
+        let neighbor = IPAddr::generate_from_mac(MacAddress::Short(2));
+        let dis = [(messages::ALL_RPL_NODES, code::DIS)];
+
+        // A DIS in the second half of each interval, which doubles
+        let dt = test.fire_dis_timer();
+        assert!(dt >= DIS_INTERVAL_MS / 2 && dt < DIS_INTERVAL_MS);
+        assert_eq!(test.sender.take(), dis);
+        test.fire_dis_timer();
+        let dt = test.fire_dis_timer();
+        assert!(dt >= DIS_INTERVAL_MS && dt < 2 * DIS_INTERVAL_MS);
+        assert_eq!(test.sender.take(), dis);
+        test.fire_dis_timer();
+
+        // Unless a neighbor solicited DIOs in the interval
+        test.receive(neighbor, code::DIS, &[0; 4]);
+        test.fire_dis_timer();
+        assert!(test.sender.take().is_empty());
+        test.fire_dis_timer();
+        test.fire_dis_timer();
+        assert_eq!(test.sender.take(), dis);
+
+        // Joining a DODAG stops the solicitations
+        let mut dodag_id = neighbor;
+        dodag_id.0[0] = 0x20;
+        let dio = Dio {
+            instance_id: 30,
+            version: 240,
+            rank: 256,
+            grounded: true,
+            mop: messages::MOP_NON_STORING,
+            preference: 0,
+            dtsn: 1,
+            dodag_id: dodag_id,
+            config: Some(DodagConfig::default()),
+            prefix: None,
+        };
+        let mut msg = [0; MAX_MESSAGE_LEN];
+        let len = dio.encode(&mut msg);
+        test.receive(neighbor, code::DIO, &msg[..len]);
+        assert_eq!(test.node.preferred_parent(), Some(neighbor));
+        assert!(!test.dis_timer.is_running());
+        assert_eq!(test.dis_alarm.alarm.get(), None);
+    }
+}
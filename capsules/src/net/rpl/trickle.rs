@@ -0,0 +1,161 @@
+//! The Trickle algorithm (RFC 6206).
+//!
+//! Trickle schedules the transmissions of a node so that they are frequent
+//! while the network is changing, and rare once it is consistent. Time is
+//! split in intervals which double in length from `Imin` to `Imax`. In each
+//! interval the node transmits at a random time in the second half of the
+//! interval, unless it heard the same information `k` times before. Hearing
+//! inconsistent information brings the interval back to `Imin`.
+//!
+//! Transmission times are drawn from a pseudo-random sequence seeded by the
+//! user, which only has to differ between neighbors.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let trickle = static_init!(
+//!     Trickle<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     Trickle::new(trickle_alarm, seed)
+//! );
+//! trickle_alarm.set_alarm_client(trickle);
+//! trickle.set_client(client);
+//! trickle.start(8, 20, 10);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time;
+
+/// Longest interval, about two hours and twenty minutes, so that it fits the
+/// alarm of any clock.
+pub const MAX_INTERVAL_MS: u32 = 1 << 23;
+
+pub trait TrickleClient {
+    /// Called when the information should be transmitted.
+    fn transmit(&self);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Phase {
+    Stopped,
+    /// Waiting for the transmission time, which is `remaining_ms` before the
+    /// end of the interval.
+    Listening {
+        remaining_ms: u32,
+    },
+    /// Waiting for the end of the interval.
+    Transmitted,
+}
+
+pub struct Trickle<'a, A: time::Alarm<'a>> {
+    alarm: &'a A,
+    client: OptionalCell<&'a dyn TrickleClient>,
+    imin_ms: Cell<u32>,
+    imax_ms: Cell<u32>,
+    redundancy: Cell<u8>,
+    interval_ms: Cell<u32>,
+    counter: Cell<u8>,
+    phase: Cell<Phase>,
+    random: Cell<u32>,
+}
+
+impl<'a, A: time::Alarm<'a>> Trickle<'a, A> {
+    pub fn new(alarm: &'a A, seed: u32) -> Trickle<'a, A> {
+        Trickle {
+            alarm: alarm,
+            client: OptionalCell::empty(),
+            imin_ms: Cell::new(0),
+            imax_ms: Cell::new(0),
+            redundancy: Cell::new(0),
+            interval_ms: Cell::new(0),
+            counter: Cell::new(0),
+            phase: Cell::new(Phase::Stopped),
+            // Xorshift gets stuck at zero
+            random: Cell::new(seed | 1),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn TrickleClient) {
+        self.client.set(client);
+    }
+
+    /// Starts, or restarts, the timer with intervals from `imin_ms` to
+    /// `doublings` doublings of it, and redundancy constant `redundancy`.
+    pub fn start(&self, imin_ms: u32, doublings: u8, redundancy: u8) {
+        let imin_ms = imin_ms.max(2).min(MAX_INTERVAL_MS);
+        let imax_ms = ((imin_ms as u64) << doublings.min(32)).min(MAX_INTERVAL_MS as u64) as u32;
+        self.imin_ms.set(imin_ms);
+        self.imax_ms.set(imax_ms);
+        self.redundancy.set(redundancy);
+        self.interval_ms.set(imin_ms);
+        self.begin_interval();
+    }
+
+    pub fn stop(&self) {
+        self.phase.set(Phase::Stopped);
+        self.alarm.disarm();
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.phase.get() != Phase::Stopped
+    }
+
+    /// Records a transmission consistent with the information of the node.
+    pub fn consistent(&self) {
+        self.counter.set(self.counter.get().saturating_add(1));
+    }
+
+    /// Records an inconsistency, which resets the interval to `Imin`.
+    pub fn inconsistent(&self) {
+        if self.is_running() && self.interval_ms.get() != self.imin_ms.get() {
+            self.interval_ms.set(self.imin_ms.get());
+            self.begin_interval();
+        }
+    }
+
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn arm(&self, ms: u32) {
+        self.alarm.set_alarm(self.alarm.now(), A::ticks_from_ms(ms));
+    }
+
+    fn begin_interval(&self) {
+        let interval_ms = self.interval_ms.get();
+        let half = interval_ms / 2;
+        let transmit_ms = half + self.next_random() % half.max(1);
+        self.counter.set(0);
+        self.phase.set(Phase::Listening {
+            remaining_ms: interval_ms - transmit_ms,
+        });
+        self.arm(transmit_ms);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Trickle<'a, A> {
+    fn alarm(&self) {
+        match self.phase.get() {
+            Phase::Stopped => {}
+            Phase::Listening { remaining_ms } => {
+                self.phase.set(Phase::Transmitted);
+                self.arm(remaining_ms);
+                let redundancy = self.redundancy.get();
+                if redundancy == 0 || self.counter.get() < redundancy {
+                    self.client.map(|client| client.transmit());
+                }
+            }
+            Phase::Transmitted => {
+                let doubled = self.interval_ms.get().saturating_mul(2);
+                self.interval_ms.set(doubled.min(self.imax_ms.get()));
+                self.begin_interval();
+            }
+        }
+    }
+}
@@ -5,26 +5,41 @@
 //! and bind to UDP ports for receiving packets.
 //! Also exposes a list of interface addresses to the application (currently
 //! hard-coded).
+//!
+//! Apps can join multicast groups, and receive the datagrams sent to those
+//! groups on their bound port. Apps which allow a receive queue get the
+//! datagrams they receive appended to it, and take them out one at a time,
+//! so that bursts of datagrams are not lost while the app is busy. The
+//! groups joined by an app which is gone are left when another app joins a
+//! group.
 
 use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::{is_all_nodes, MulticastGroups};
 use crate::net::network_capabilities::NetworkCapability;
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
 use crate::net::stream::SResult;
 use crate::net::udp::udp_port_table::{PortQuery, UdpPortManager};
+use crate::net::udp::udp_queue::DatagramQueue;
 use crate::net::udp::udp_recv::UDPRecvClient;
 use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use crate::net::util::host_slice_to_u16;
 use core::cell::Cell;
 use core::{cmp, mem};
 use kernel::capabilities::UdpDriverCapability;
-use kernel::common::cells::MapCell;
+use kernel::common::cells::{MapCell, OptionalCell};
 use kernel::common::leasable_buffer::LeasableBuffer;
 use kernel::{debug, AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
 
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Udp as usize;
 
+/// Number of multicast groups each app can join.
+pub const GROUPS_PER_APP: usize = 2;
+
+/// Number of multicast group memberships kept for all apps.
+pub const MAX_MEMBERSHIPS: usize = 8;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct UDPEndpoint {
     addr: IPAddr,
@@ -68,8 +83,11 @@ pub struct App {
     app_write: Option<AppSlice<Shared, u8>>,
     app_cfg: Option<AppSlice<Shared, u8>>,
     app_rx_cfg: Option<AppSlice<Shared, u8>>,
+    app_rx_queue: Option<AppSlice<Shared, u8>>,
+    rx_queue: DatagramQueue,
     pending_tx: Option<[UDPEndpoint; 2]>,
     bound_port: Option<UDPEndpoint>,
+    groups: [Option<IPAddr>; GROUPS_PER_APP],
 }
 
 #[allow(dead_code)]
@@ -96,6 +114,13 @@ pub struct UDPDriver<'a> {
     driver_send_cap: &'static dyn UdpDriverCapability,
 
     net_cap: &'static NetworkCapability,
+
+    /// Multicast groups joined on behalf of apps
+    groups: OptionalCell<&'a dyn MulticastGroups>,
+
+    /// The groups joined by each app, which outlive the grant of an app
+    /// that exits so that they can be left.
+    memberships: [Cell<Option<(AppId, IPAddr)>>; MAX_MEMBERSHIPS],
 }
 
 impl<'a> UDPDriver<'a> {
@@ -119,9 +144,122 @@ impl<'a> UDPDriver<'a> {
             kernel_buffer: MapCell::new(kernel_buffer),
             driver_send_cap: driver_send_cap,
             net_cap: net_cap,
+            groups: OptionalCell::empty(),
+            memberships: Default::default(),
         }
     }
 
+    pub fn set_multicast_groups(&self, groups: &'a dyn MulticastGroups) {
+        self.groups.set(groups);
+    }
+
+    /// Reads the multicast group at the start of an app's config buffer.
+    fn group_from_cfg(app: &App) -> Option<IPAddr> {
+        app.app_cfg.as_ref().and_then(|cfg| {
+            let mut group = IPAddr::new();
+            group
+                .0
+                .copy_from_slice(cfg.as_ref().get(..mem::size_of::<IPAddr>())?);
+            if group.is_multicast() {
+                Some(group)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Leaves the groups joined by apps which are gone.
+    fn leave_groups_of_dead_apps(&self) {
+        for membership in self.memberships.iter() {
+            if let Some((appid, group)) = membership.get() {
+                if self.apps.enter(appid, |_, _| ()).is_err() {
+                    membership.set(None);
+                    self.groups.map(|groups| groups.leave_group(group));
+                }
+            }
+        }
+    }
+
+    fn join_group(&self, appid: AppId) -> ReturnCode {
+        self.leave_groups_of_dead_apps();
+        self.do_with_app(appid, |app| {
+            let group = match Self::group_from_cfg(app) {
+                Some(group) => group,
+                None => return ReturnCode::EINVAL,
+            };
+            if app.groups.contains(&Some(group)) {
+                return ReturnCode::EALREADY;
+            }
+            let membership = self.memberships.iter().find(|m| m.get().is_none());
+            match (app.groups.iter_mut().find(|g| g.is_none()), membership) {
+                (Some(slot), Some(membership)) => {
+                    let result = self
+                        .groups
+                        .map_or(ReturnCode::ENOSUPPORT, |groups| groups.join_group(group));
+                    if result == ReturnCode::SUCCESS {
+                        *slot = Some(group);
+                        membership.set(Some((appid, group)));
+                    }
+                    result
+                }
+                _ => ReturnCode::ENOMEM,
+            }
+        })
+    }
+
+    fn leave_group(&self, appid: AppId) -> ReturnCode {
+        self.do_with_app(appid, |app| {
+            let group = match Self::group_from_cfg(app) {
+                Some(group) => group,
+                None => return ReturnCode::EINVAL,
+            };
+            match app.groups.iter_mut().find(|g| **g == Some(group)) {
+                Some(slot) => {
+                    *slot = None;
+                    self.memberships
+                        .iter()
+                        .find(|m| m.get() == Some((appid, group)))
+                        .map(|m| m.set(None));
+                    self.groups
+                        .map_or(ReturnCode::ENOSUPPORT, |groups| groups.leave_group(group))
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    /// Takes the oldest datagram out of an app's receive queue, copying it
+    /// to the read buffer and its sender to the rx config buffer.
+    fn pop_datagram(&self, appid: AppId) -> ReturnCode {
+        self.do_with_app(appid, |app| {
+            let storage = match app.app_rx_queue {
+                Some(ref storage) => storage,
+                None => return ReturnCode::EINVAL,
+            };
+            let info = match app.app_read {
+                Some(ref mut rbuf) => app.rx_queue.pop(storage.as_ref(), rbuf.as_mut()),
+                None => app.rx_queue.pop(storage.as_ref(), &mut []),
+            };
+            match info {
+                Some(info) => {
+                    let sender_addr = UDPEndpoint {
+                        addr: info.src_addr,
+                        port: info.src_port,
+                    };
+                    app.app_rx_cfg.as_mut().map(|cfg| {
+                        if cfg.len() == 2 * mem::size_of::<UDPEndpoint>() {
+                            sender_addr.encode(cfg.as_mut(), 0);
+                        }
+                    });
+                    ReturnCode::SuccessWithValue {
+                        value: info.len | (info.dst_addr.is_multicast() as usize) << 16,
+                    }
+                }
+                None => ReturnCode::FAIL,
+            }
+        })
+    }
+
     /// Utility function to perform an action on an app in a system call.
     #[inline]
     fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
@@ -360,6 +498,10 @@ impl<'a> Driver for UDPDriver<'a> {
     /// - `3`: Rx config buffer. Used to contain source/destination addresses
     ///        and ports for receives (separate from `2` because receives may
     ///        be waiting for an incoming packet asynchronously).
+    /// - `4`: Receive queue. When allowed, received datagrams are appended
+    ///        to it, each taking 36 bytes plus its payload, instead of being
+    ///        copied to the read buffer. Datagrams which do not fit are
+    ///        dropped. Allowing a new queue empties it.
     fn allow(
         &self,
         appid: AppId,
@@ -367,7 +509,7 @@ impl<'a> Driver for UDPDriver<'a> {
         slice: Option<AppSlice<Shared, u8>>,
     ) -> ReturnCode {
         match allow_num {
-            0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
+            0 | 1 | 2 | 3 | 4 => self.do_with_app(appid, |app| {
                 let mut success = true;
                 match allow_num {
                     0 => app.app_read = slice,
@@ -383,6 +525,10 @@ impl<'a> Driver for UDPDriver<'a> {
                     },
                     2 => app.app_cfg = slice,
                     3 => app.app_rx_cfg = slice,
+                    4 => {
+                        app.app_rx_queue = slice;
+                        app.rx_queue.reset();
+                    }
                     _ => {}
                 }
                 if success {
@@ -401,7 +547,9 @@ impl<'a> Driver for UDPDriver<'a> {
     ///
     /// - `0`: Setup callback for when packet is received. If no port has
     ///        been bound, return ERESERVE to indicate that port binding is
-    ///        is a prerequisite to reception.
+    ///        is a prerequisite to reception. The callback gets the length
+    ///        of the datagram, and the number of queued datagrams if a
+    ///        receive queue is allowed.
     /// - `1`: Setup callback for when packet is transmitted. Notably,
     ///        this callback receives the result of the send_done callback
     ///        from udp_send.rs, which does not currently pass information
@@ -475,6 +623,17 @@ impl<'a> Driver for UDPDriver<'a> {
     /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: Join the multicast group whose address starts the config buffer. Datagrams
+    ///        sent to the group on the bound port are received once joined. Returns EINVAL
+    ///        if the address is not a multicast address, EALREADY if the app is a member,
+    ///        and ENOMEM if the app or the node cannot join more groups.
+    /// - `6`: Leave the multicast group whose address starts the config buffer. Returns
+    ///        EINVAL if the app is not a member.
+    /// - `7`: Take the oldest datagram out of the receive queue, copying as much of it as
+    ///        fits to the read buffer and its sender to the rx config buffer. Returns the
+    ///        length of the datagram, with bit 16 set if it was sent to a multicast group,
+    ///        EINVAL if no receive queue is allowed, and FAIL if the queue is empty or
+    ///        the app overwrote the header of a datagram in it, which empties it.
 
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
@@ -610,6 +769,9 @@ impl<'a> Driver for UDPDriver<'a> {
             4 => ReturnCode::SuccessWithValue {
                 value: self.max_tx_pyld_len,
             },
+            5 => self.join_group(appid),
+            6 => self.leave_group(appid),
+            7 => self.pop_datagram(appid),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -641,41 +803,49 @@ impl<'a> UDPRecvClient for UDPDriver<'a> {
         payload: &[u8],
     ) {
         self.apps.each(|app| {
-            if app.bound_port.is_some() {
-                let appid = app.appid();
-                self.do_with_app(app.appid(), |app| {
-                    let mut for_me = false;
-                    app.bound_port.as_ref().map(|requested_addr| {
-                        if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
-                            for_me = true;
-                        }
-                    });
-                    if for_me {
-                        let mut app_read = app.app_read.take();
-                        app_read.as_mut().map(|rbuf| {
-                            let rbuf = rbuf.as_mut();
-                            let len = payload.len();
-                            if rbuf.len() >= len {
-                                // silently ignore packets that don't fit?
-                                rbuf[..len].copy_from_slice(&payload[..len]);
-
-                                // Write address of sender into rx_cfg so it can be read by client
-                                let sender_addr = UDPEndpoint {
-                                    addr: src_addr,
-                                    port: src_port,
-                                };
-                                let cfg_len = 2 * mem::size_of::<UDPEndpoint>();
-                                self.do_with_rx_cfg_mut(appid, cfg_len, |cfg| {
-                                    sender_addr.encode(cfg, 0);
-                                    ReturnCode::SUCCESS
-                                });
-                                app.rx_callback.map(|mut cb| cb.schedule(len, 0, 0));
-                            }
-                        });
-                        app.app_read = app_read;
+            let app: &mut App = app;
+            let for_me = app.bound_port.as_ref().map_or(false, |bound| {
+                bound.port == dst_port
+                    && (bound.addr == dst_addr
+                        || (dst_addr.is_multicast()
+                            && (is_all_nodes(dst_addr) || app.groups.contains(&Some(dst_addr)))))
+            });
+            if !for_me {
+                return;
+            }
+            let len = payload.len();
+            if let Some(ref mut storage) = app.app_rx_queue {
+                if app
+                    .rx_queue
+                    .push(storage.as_mut(), src_addr, src_port, dst_addr, payload)
+                {
+                    let count = app.rx_queue.count();
+                    app.rx_callback.map(|mut cb| cb.schedule(len, count, 0));
+                }
+                return;
+            }
+            let delivered = app.app_read.as_mut().map_or(false, |rbuf| {
+                let rbuf = rbuf.as_mut();
+                if rbuf.len() >= len {
+                    // silently ignore packets that don't fit?
+                    rbuf[..len].copy_from_slice(payload);
+                    true
+                } else {
+                    false
+                }
+            });
+            if delivered {
+                // Write address of sender into rx_cfg so it can be read by client
+                let sender_addr = UDPEndpoint {
+                    addr: src_addr,
+                    port: src_port,
+                };
+                app.app_rx_cfg.as_mut().map(|cfg| {
+                    if cfg.len() == 2 * mem::size_of::<UDPEndpoint>() {
+                        sender_addr.encode(cfg.as_mut(), 0);
                     }
-                    ReturnCode::SUCCESS
                 });
+                app.rx_callback.map(|mut cb| cb.schedule(len, 0, 0));
             }
         });
     }
@@ -699,3 +869,139 @@ impl<'a> PortQuery for UDPDriver<'a> {
         port_bound
     }
 }
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::net::ipv6::ipv6_recv::GroupTable;
+    use crate::net::network_capabilities::{AddrRange, PortRange, UdpVisibilityCapability};
+    use crate::net::udp::udp_port_table::UdpPortBindingTx;
+    use crate::net::udp::UDPHeader;
+    use kernel::mock::{MockKernel, MockNetworkCapability, MockProcess};
+    use std::boxed::Box;
+
+    struct NoSender;
+
+    impl<'a> UDPSender<'a> for NoSender {
+        fn set_client(&self, _client: &'a dyn UDPSendClient) {}
+        fn send_to(
+            &'a self,
+            _dest: IPAddr,
+            _dst_port: u16,
+            buf: LeasableBuffer<'static, u8>,
+            _net_cap: &'static NetworkCapability,
+        ) -> Result<(), LeasableBuffer<'static, u8>> {
+            Err(buf)
+        }
+        fn driver_send_to(
+            &'a self,
+            _dest: IPAddr,
+            _dst_port: u16,
+            _src_port: u16,
+            buf: LeasableBuffer<'static, u8>,
+            _driver_send_cap: &dyn UdpDriverCapability,
+            _net_cap: &'static NetworkCapability,
+        ) -> Result<(), LeasableBuffer<'static, u8>> {
+            Err(buf)
+        }
+        fn send(
+            &'a self,
+            _dest: IPAddr,
+            _udp_header: UDPHeader,
+            buf: LeasableBuffer<'static, u8>,
+            _net_cap: &'static NetworkCapability,
+        ) -> Result<(), LeasableBuffer<'static, u8>> {
+            Err(buf)
+        }
+        fn get_binding(&self) -> Option<UdpPortBindingTx> {
+            None
+        }
+        fn is_bound(&self) -> bool {
+            false
+        }
+        fn set_binding(&self, _binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+            None
+        }
+    }
+
+    fn group(last: u8) -> IPAddr {
+        IPAddr([0xff, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, last])
+    }
+
+    /// Has `app` join or leave `group` through its config buffer.
+    fn command_group(
+        driver: &UDPDriver,
+        app: &MockProcess,
+        command_num: usize,
+        group: IPAddr,
+    ) -> ReturnCode {
+        let cfg = app.allow_buffer(Box::leak(Box::new(group.0)));
+        assert_eq!(driver.allow(app.appid(), 2, Some(cfg)), ReturnCode::SUCCESS);
+        driver.command(command_num, 0, 0, app.appid())
+    }
+
+    #[test]
+    fn groups_of_exited_processes_left() {
+        let kernel = MockKernel::new(2);
+        let net_cap: &'static NetworkCapability = Box::leak(Box::new(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &MockNetworkCapability,
+        )));
+        let udp_vis = Box::leak(Box::new(UdpVisibilityCapability::new(
+            &MockNetworkCapability,
+        )));
+        let port_table = Box::leak(Box::new(UdpPortManager::new(
+            &MockNetworkCapability,
+            Box::leak(Box::new([None; 4])),
+            udp_vis,
+        )));
+        let groups = Box::leak(Box::new(GroupTable::new()));
+        let driver = UDPDriver::new(
+            &NoSender,
+            kernel.create_grant(),
+            &[],
+            64,
+            port_table,
+            LeasableBuffer::new(Box::leak(Box::new([0; 64]))),
+            &MockNetworkCapability,
+            net_cap,
+        );
+        driver.set_multicast_groups(groups);
+        let (app0, app1) = (kernel.process(0), kernel.process(1));
+
+        assert_eq!(
+            command_group(&driver, app0, 5, group(1)),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            command_group(&driver, app1, 5, group(1)),
+            ReturnCode::SUCCESS
+        );
+
+        // The group stays joined for the process still running
+        app0.terminate();
+        assert_eq!(
+            command_group(&driver, app1, 5, group(2)),
+            ReturnCode::SUCCESS
+        );
+        assert!(groups.is_member(group(1)));
+
+        // A restarted process starts without groups
+        app1.restart();
+        assert_eq!(
+            command_group(&driver, app1, 5, group(3)),
+            ReturnCode::SUCCESS
+        );
+        assert!(!groups.is_member(group(1)));
+        assert!(!groups.is_member(group(2)));
+        assert_eq!(
+            command_group(&driver, app1, 6, group(3)),
+            ReturnCode::SUCCESS
+        );
+        assert!(!groups.is_member(group(3)));
+    }
+}
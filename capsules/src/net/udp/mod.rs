@@ -1,5 +1,6 @@
 pub mod driver;
 pub mod udp_port_table;
+pub mod udp_queue;
 pub mod udp_recv;
 pub mod udp_send;
 
@@ -0,0 +1,212 @@
+//! A ring of received UDP datagrams.
+//!
+//! `DatagramQueue` keeps the state of a ring stored in a byte buffer it does
+//! not own, so that the buffer can be memory shared with a process and its
+//! size picked by the process. Each datagram is stored after a header
+//! holding its length, its source address and port, and its destination
+//! address. Datagrams which do not fit in the free space of the ring are
+//! dropped and counted.
+//!
+//! As the process can write to the buffer, the headers are not trusted: a
+//! header whose length runs past the bytes in use empties the queue.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+
+/// Length of the header stored before each datagram.
+pub const ENTRY_HEADER_LEN: usize = 2 + 16 + 2 + 16;
+
+/// Where a queued datagram came from and was sent to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DatagramInfo {
+    pub src_addr: IPAddr,
+    pub src_port: u16,
+    pub dst_addr: IPAddr,
+    /// Length of the datagram, which may be longer than what was copied
+    /// out of the queue.
+    pub len: usize,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct DatagramQueue {
+    /// Offset of the oldest datagram.
+    head: usize,
+    /// Bytes used, headers included.
+    used: usize,
+    count: usize,
+    dropped: usize,
+}
+
+/// Copies `data` into `storage` from `pos`, wrapping around at its end.
+fn write_wrapped(storage: &mut [u8], pos: usize, data: &[u8]) {
+    let first = data.len().min(storage.len() - pos);
+    storage[pos..pos + first].copy_from_slice(&data[..first]);
+    storage[..data.len() - first].copy_from_slice(&data[first..]);
+}
+
+/// Fills `out` from `storage` at `pos`, wrapping around at its end.
+fn read_wrapped(storage: &[u8], pos: usize, out: &mut [u8]) {
+    let first = out.len().min(storage.len() - pos);
+    out[..first].copy_from_slice(&storage[pos..pos + first]);
+    let rest = out.len() - first;
+    out[first..].copy_from_slice(&storage[..rest]);
+}
+
+impl DatagramQueue {
+    pub fn new() -> DatagramQueue {
+        DatagramQueue::default()
+    }
+
+    /// Empties the queue, which must be done whenever its storage changes.
+    pub fn reset(&mut self) {
+        *self = DatagramQueue::new();
+    }
+
+    /// The number of queued datagrams.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The number of datagrams dropped for lack of space since the last
+    /// reset.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Appends a datagram to the ring in `storage`. Returns whether it fit.
+    pub fn push(
+        &mut self,
+        storage: &mut [u8],
+        src_addr: IPAddr,
+        src_port: u16,
+        dst_addr: IPAddr,
+        payload: &[u8],
+    ) -> bool {
+        let entry_len = ENTRY_HEADER_LEN + payload.len();
+        if payload.len() > u16::MAX as usize || self.used + entry_len > storage.len() {
+            self.dropped = self.dropped.saturating_add(1);
+            return false;
+        }
+        let mut header = [0; ENTRY_HEADER_LEN];
+        header[0..2].copy_from_slice(&(payload.len() as u16).to_be_bytes());
+        header[2..18].copy_from_slice(&src_addr.0);
+        header[18..20].copy_from_slice(&src_port.to_be_bytes());
+        header[20..36].copy_from_slice(&dst_addr.0);
+
+        let tail = (self.head + self.used) % storage.len();
+        write_wrapped(storage, tail, &header);
+        write_wrapped(storage, (tail + ENTRY_HEADER_LEN) % storage.len(), payload);
+        self.used += entry_len;
+        self.count += 1;
+        true
+    }
+
+    /// Removes the oldest datagram from the ring in `storage`, copying as
+    /// much of it as fits into `buf`. Returns `None` if the queue is empty or
+    /// its storage was overwritten, in which case the datagrams left are
+    /// dropped.
+    pub fn pop(&mut self, storage: &[u8], buf: &mut [u8]) -> Option<DatagramInfo> {
+        if self.count == 0 {
+            return None;
+        }
+        if self.used < ENTRY_HEADER_LEN || self.used > storage.len() {
+            self.drop_all();
+            return None;
+        }
+        let mut header = [0; ENTRY_HEADER_LEN];
+        read_wrapped(storage, self.head, &mut header);
+        let mut info = DatagramInfo {
+            src_addr: IPAddr::new(),
+            src_port: u16::from_be_bytes([header[18], header[19]]),
+            dst_addr: IPAddr::new(),
+            len: u16::from_be_bytes([header[0], header[1]]) as usize,
+        };
+        info.src_addr.0.copy_from_slice(&header[2..18]);
+        info.dst_addr.0.copy_from_slice(&header[20..36]);
+
+        let entry_len = ENTRY_HEADER_LEN + info.len;
+        if entry_len > self.used {
+            self.drop_all();
+            return None;
+        }
+        let copied = info.len.min(buf.len());
+        let payload_pos = (self.head + ENTRY_HEADER_LEN) % storage.len();
+        read_wrapped(storage, payload_pos, &mut buf[..copied]);
+
+        self.head = (self.head + entry_len) % storage.len();
+        self.used -= entry_len;
+        self.count -= 1;
+        if self.count == 0 {
+            // Bytes left over after a header was shortened are reclaimed
+            self.head = 0;
+            self.used = 0;
+        }
+        Some(info)
+    }
+
+    /// Empties a queue whose storage was overwritten, counting the datagrams
+    /// it held as dropped.
+    fn drop_all(&mut self) {
+        let dropped = self.dropped.saturating_add(self.count);
+        self.reset();
+        self.dropped = dropped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_wraps_around() {
+        let mut storage = [0; 2 * ENTRY_HEADER_LEN + 10];
+        let mut queue = DatagramQueue::new();
+        let src = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let dst = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xfd]);
+
+        assert!(queue.push(&mut storage, src, 5683, dst, b"abcd"));
+        assert!(queue.push(&mut storage, src, 1000, dst, b"ef"));
+        // No room left for the third
+        assert!(!queue.push(&mut storage, src, 1000, dst, b"ghijk"));
+        assert_eq!((queue.count(), queue.dropped()), (2, 1));
+
+        let mut buf = [0; 8];
+        let info = queue.pop(&storage, &mut buf).unwrap();
+        assert_eq!(
+            (info.src_addr, info.src_port, info.dst_addr),
+            (src, 5683, dst)
+        );
+        assert_eq!(&buf[..info.len], b"abcd");
+
+        // This one wraps around the end of the storage
+        assert!(queue.push(&mut storage, src, 7, dst, b"ghijk"));
+        let info = queue.pop(&storage, &mut buf).unwrap();
+        assert_eq!((info.src_port, &buf[..info.len]), (1000, &b"ef"[..]));
+        // A short buffer gets the start of the datagram
+        let info = queue.pop(&storage, &mut buf[..3]).unwrap();
+        assert_eq!((info.src_port, info.len, &buf[..3]), (7, 5, &b"ghi"[..]));
+        assert!(queue.pop(&storage, &mut buf).is_none());
+    }
+
+    #[test]
+    fn overwritten_header_empties_queue() {
+        let mut storage = [0; 64];
+        let mut queue = DatagramQueue::new();
+        let addr = IPAddr([0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        assert!(queue.push(&mut storage, addr, 1000, addr, &[7; 10]));
+
+        // The process claims the datagram is 1000 bytes long
+        storage[0..2].copy_from_slice(&1000u16.to_be_bytes());
+        let mut buf = [0; 1000];
+        assert!(queue.pop(&storage, &mut buf).is_none());
+        assert_eq!((queue.count(), queue.dropped()), (0, 1));
+
+        // A shortened header only loses the bytes it no longer covers
+        assert!(queue.push(&mut storage, addr, 1000, addr, &[7; 10]));
+        storage[0..2].copy_from_slice(&2u16.to_be_bytes());
+        let info = queue.pop(&storage, &mut buf).unwrap();
+        assert_eq!((info.len, &buf[..2]), (2, &[7, 7][..]));
+        assert!(queue.push(&mut storage, addr, 1000, addr, &[8; 20]));
+        let info = queue.pop(&storage, &mut buf).unwrap();
+        assert_eq!((info.len, &buf[..20]), (20, &[8; 20][..]));
+    }
+}
@@ -4,9 +4,14 @@
 //! appropriate capsule / app. Once again, port binding for userspace apps is managed seperately
 //! by the UDP userspace driver, which must correctly check bindings of kernel apps to ensure
 //! correctness when dispatching received packets to the appropriate client.
+//!
+//! Datagrams sent to a multicast group are delivered to every receiver bound
+//! to their port. The groups are joined through the `MulticastGroups`
+//! interface of the mux, which forwards to the IPv6 receiver filtering them.
 
+use crate::net::icmpv6::icmpv6_responder::ICMP6ErrorReporter;
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_recv::{IP6RecvClient, MulticastGroups};
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
 use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx};
@@ -14,10 +19,13 @@ use crate::net::udp::UDPHeader;
 use kernel::common::cells::{MapCell, OptionalCell};
 use kernel::common::{List, ListLink, ListNode};
 use kernel::debug;
+use kernel::ReturnCode;
 
 pub struct MuxUdpReceiver<'a> {
     rcvr_list: List<'a, UDPReceiver<'a>>,
     driver: OptionalCell<&'static UDPDriver<'static>>,
+    error_reporter: OptionalCell<&'a dyn ICMP6ErrorReporter>,
+    groups: OptionalCell<&'a dyn MulticastGroups>,
 }
 
 impl<'a> MuxUdpReceiver<'a> {
@@ -25,6 +33,8 @@ impl<'a> MuxUdpReceiver<'a> {
         MuxUdpReceiver {
             rcvr_list: List::new(),
             driver: OptionalCell::empty(),
+            error_reporter: OptionalCell::empty(),
+            groups: OptionalCell::empty(),
         }
     }
 
@@ -35,6 +45,33 @@ impl<'a> MuxUdpReceiver<'a> {
     pub fn set_driver(&self, driver_ref: &'static UDPDriver) {
         self.driver.replace(driver_ref);
     }
+
+    /// Sets the reporter told about packets sent to ports no one is bound to.
+    pub fn set_error_reporter(&self, reporter: &'a dyn ICMP6ErrorReporter) {
+        self.error_reporter.set(reporter);
+    }
+
+    /// Sets the IPv6 receiver whose multicast groups are joined through the
+    /// mux.
+    pub fn set_multicast_groups(&self, groups: &'a dyn MulticastGroups) {
+        self.groups.set(groups);
+    }
+}
+
+impl<'a> MulticastGroups for MuxUdpReceiver<'a> {
+    fn join_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups
+            .map_or(ReturnCode::ENOSUPPORT, |groups| groups.join_group(group))
+    }
+
+    fn leave_group(&self, group: IPAddr) -> ReturnCode {
+        self.groups
+            .map_or(ReturnCode::ENOSUPPORT, |groups| groups.leave_group(group))
+    }
+
+    fn is_member(&self, group: IPAddr) -> bool {
+        self.groups.map_or(false, |groups| groups.is_member(group))
+    }
 }
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
@@ -47,6 +84,8 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                     debug!("[UDP_RECV] Error: Received UDP length too long");
                     return;
                 }
+                let multicast = ip_header.get_dst_addr().is_multicast();
+                let mut delivered = false;
                 for rcvr in self.rcvr_list.iter() {
                     match rcvr.binding.take() {
                         Some(binding) => {
@@ -61,6 +100,10 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                     );
                                 });
                                 rcvr.binding.replace(binding);
+                                delivered = true;
+                                if multicast {
+                                    continue;
+                                }
                                 break;
                             }
                             rcvr.binding.replace(binding);
@@ -77,6 +120,10 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                                         &payload[offset..],
                                     );
                                     self.driver.replace(driver);
+                                    delivered = true;
+                                    if multicast {
+                                        continue;
+                                    }
                                     break;
                                 }
                                 self.driver.replace(driver);
@@ -85,6 +132,11 @@ impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
                         },
                     }
                 }
+                // Multicast packets are never answered with errors
+                if !delivered && !multicast {
+                    self.error_reporter
+                        .map(|reporter| reporter.port_unreachable(&ip_header, &payload[..len]));
+                }
             }
             None => {}
         }
@@ -63,10 +63,16 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
                         .ip_sender
                         .send_to(dest, transport_header, &buf, net_cap);
                     caller.tx_buffer.replace(buf); //Replace buffer as soon as sent.
+                    if ret != ReturnCode::SUCCESS {
+                        // Nothing is in flight for this caller, so it must
+                        // not wait at the head of the queue.
+                        self.sender_list.pop_head();
+                    }
                     ret
                 }
                 None => {
                     debug!("No buffer available to take.");
+                    self.sender_list.pop_head();
                     ReturnCode::FAIL
                 }
             }
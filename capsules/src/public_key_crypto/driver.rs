@@ -0,0 +1,616 @@
+//! Provides userspace access to public-key signatures.
+//!
+//! The driver signs and verifies messages, and generates key pairs, with
+//! the engines given to it for each `Algorithm`. Keys and signatures are
+//! passed through allowed buffers, in the encoding of the algorithm, and
+//! messages are copied to a kernel buffer, which bounds their length.
+//!
+//! One operation runs at a time; requests made while another process's
+//! operation runs return `EBUSY`. An operation whose results do not fit in
+//! the buffers allowed when it ends completes with `ESIZE`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let message_buf = static_init!([u8; 256], [0; 256]);
+//! let pk = static_init!(
+//!     PublicKeyDriver<'static>,
+//!     PublicKeyDriver::new(
+//!         message_buf,
+//!         signature_buf,
+//!         private_key_buf,
+//!         public_key_buf,
+//!         board_kernel.create_grant(&memory_allocation_cap),
+//!     )
+//! );
+//! pk.set_engine(Algorithm::EcdsaP256, ecdsa);
+//! ecdsa.set_sign_client(pk);
+//! ecdsa.set_verify_client(pk);
+//! ecdsa.set_key_generate_client(pk);
+//! ```
+
+use crate::driver;
+/// Syscall driver number.
+pub const DRIVER_NUM: usize = driver::NUM::PublicKeyCrypto as usize;
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::public_key_crypto::{
+    ClientKeyGenerate, ClientSign, ClientVerify, KeyGenerate, SignatureSign, SignatureVerify,
+};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// The length of the signature buffer the driver needs.
+pub const SIGNATURE_BUF_LEN: usize = 64;
+/// The length of the private key buffer the driver needs.
+pub const PRIVATE_KEY_BUF_LEN: usize = 32;
+/// The length of the public key buffer the driver needs.
+pub const PUBLIC_KEY_BUF_LEN: usize = 64;
+
+/// An engine which signs, verifies and generates keys for one algorithm.
+pub trait SignatureEngine<'a>: SignatureSign<'a> + SignatureVerify<'a> + KeyGenerate<'a> {}
+
+impl<'a, T: SignatureSign<'a> + SignatureVerify<'a> + KeyGenerate<'a>> SignatureEngine<'a> for T {}
+
+/// The algorithms processes can select.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Algorithm {
+    /// ECDSA over P-256 with SHA-256: 32-byte private keys, `x | y` public
+    /// keys and `r | s` signatures, all big-endian.
+    EcdsaP256 = 0,
+    /// Ed25519: 32-byte seeds and public keys, and 64-byte signatures.
+    Ed25519 = 1,
+}
+
+const NUM_ALGORITHMS: usize = 2;
+
+impl Algorithm {
+    fn from_usize(n: usize) -> Option<Algorithm> {
+        match n {
+            0 => Some(Algorithm::EcdsaP256),
+            1 => Some(Algorithm::Ed25519),
+            _ => None,
+        }
+    }
+
+    fn private_key_len(self) -> usize {
+        32
+    }
+
+    fn public_key_len(self) -> usize {
+        match self {
+            Algorithm::EcdsaP256 => 64,
+            Algorithm::Ed25519 => 32,
+        }
+    }
+
+    fn signature_len(self) -> usize {
+        64
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Operation {
+    Sign = 2,
+    Verify = 3,
+    Generate = 4,
+}
+
+pub struct PublicKeyDriver<'a> {
+    engines: [OptionalCell<&'a dyn SignatureEngine<'a>>; NUM_ALGORITHMS],
+
+    apps: Grant<App>,
+    appid: OptionalCell<AppId>,
+    operation: Cell<Option<(Algorithm, Operation)>>,
+
+    message: TakeCell<'static, [u8]>,
+    signature: TakeCell<'static, [u8]>,
+    private_key: TakeCell<'static, [u8]>,
+    public_key: TakeCell<'static, [u8]>,
+}
+
+impl<'a> PublicKeyDriver<'a> {
+    pub fn new(
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+        private_key: &'static mut [u8],
+        public_key: &'static mut [u8],
+        grant: Grant<App>,
+    ) -> PublicKeyDriver<'a> {
+        PublicKeyDriver {
+            engines: [OptionalCell::empty(), OptionalCell::empty()],
+            apps: grant,
+            appid: OptionalCell::empty(),
+            operation: Cell::new(None),
+            message: TakeCell::new(message),
+            signature: TakeCell::new(signature),
+            private_key: TakeCell::new(private_key),
+            public_key: TakeCell::new(public_key),
+        }
+    }
+
+    /// Makes `algorithm` available to processes. The driver must be set as
+    /// the sign, verify and key generation client of `engine`.
+    pub fn set_engine(&self, algorithm: Algorithm, engine: &'a dyn SignatureEngine<'a>) {
+        self.engines[algorithm as usize].set(engine);
+    }
+
+    /// Starts `operation` for the process `appid`, on the first `len` bytes
+    /// of its message buffer.
+    fn start(&self, app: &mut App, operation: Operation, len: usize) -> ReturnCode {
+        let algorithm = match app.algorithm {
+            Some(algorithm) => algorithm,
+            None => return ReturnCode::ERESERVE,
+        };
+        let engine = match self.engines[algorithm as usize].map(|engine| *engine) {
+            Some(engine) => engine,
+            None => return ReturnCode::ENOSUPPORT,
+        };
+        let slice_len =
+            |slice: &Option<AppSlice<Shared, u8>>| slice.as_ref().map_or(0, |s| s.len());
+
+        match operation {
+            Operation::Sign | Operation::Verify => {
+                let (key, key_len) = if operation == Operation::Sign {
+                    (&app.private_key, algorithm.private_key_len())
+                } else {
+                    (&app.public_key, algorithm.public_key_len())
+                };
+                if slice_len(key) < key_len || slice_len(&app.signature) < algorithm.signature_len()
+                {
+                    return ReturnCode::ERESERVE;
+                }
+                if slice_len(&app.message) < len {
+                    return ReturnCode::ESIZE;
+                }
+                let key = &key.as_ref().unwrap().as_ref()[..key_len];
+                let res = if operation == Operation::Sign {
+                    engine.set_private_key(key)
+                } else {
+                    engine.set_public_key(key)
+                };
+                if let Err(e) = res {
+                    return e;
+                }
+
+                let (message, signature) = match (self.message.take(), self.signature.take()) {
+                    (Some(message), Some(signature)) => (message, signature),
+                    (message, signature) => {
+                        self.message.put(message);
+                        self.signature.put(signature);
+                        engine.clear_private_key();
+                        return ReturnCode::EBUSY;
+                    }
+                };
+                if len > message.len() {
+                    self.message.replace(message);
+                    self.signature.replace(signature);
+                    engine.clear_private_key();
+                    return ReturnCode::ESIZE;
+                }
+                message[..len].copy_from_slice(&app.message.as_ref().unwrap().as_ref()[..len]);
+                if operation == Operation::Verify {
+                    let sig_len = algorithm.signature_len();
+                    signature[..sig_len]
+                        .copy_from_slice(&app.signature.as_ref().unwrap().as_ref()[..sig_len]);
+                }
+                let mut lease = LeasableBuffer::new(message);
+                lease.slice(..len);
+                let res = if operation == Operation::Sign {
+                    engine.sign(lease, signature)
+                } else {
+                    engine.verify(lease, signature)
+                };
+                match res {
+                    Ok(()) => {
+                        self.operation.set(Some((algorithm, operation)));
+                        ReturnCode::SUCCESS
+                    }
+                    Err((e, message, signature)) => {
+                        engine.clear_private_key();
+                        self.message.replace(message);
+                        self.signature.replace(signature);
+                        e
+                    }
+                }
+            }
+            Operation::Generate => {
+                if slice_len(&app.private_key) < algorithm.private_key_len()
+                    || slice_len(&app.public_key) < algorithm.public_key_len()
+                {
+                    return ReturnCode::ERESERVE;
+                }
+                let (private_key, public_key) =
+                    match (self.private_key.take(), self.public_key.take()) {
+                        (Some(private_key), Some(public_key)) => (private_key, public_key),
+                        (private_key, public_key) => {
+                            self.private_key.put(private_key);
+                            self.public_key.put(public_key);
+                            return ReturnCode::EBUSY;
+                        }
+                    };
+                match engine.generate(private_key, public_key) {
+                    Ok(()) => {
+                        self.operation.set(Some((algorithm, operation)));
+                        ReturnCode::SUCCESS
+                    }
+                    Err((e, private_key, public_key)) => {
+                        self.private_key.replace(private_key);
+                        self.public_key.replace(public_key);
+                        e
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ends the current operation, and notifies its process. `copy_out` is
+    /// called with the process's state to return the results, and returns
+    /// whether they fit in its buffers, which may have been replaced while
+    /// the operation ran. If they do not, the process gets `ESIZE`.
+    fn finish<F: FnOnce(&mut App, Algorithm) -> bool>(
+        &self,
+        res: ReturnCode,
+        valid: bool,
+        copy_out: F,
+    ) {
+        let operation = self.operation.take();
+        self.appid.take().map(|appid| {
+            let _ = self.apps.enter(appid, |app, _| {
+                if let Some((algorithm, operation)) = operation {
+                    let res = if res == ReturnCode::SUCCESS && !copy_out(app, algorithm) {
+                        ReturnCode::ESIZE
+                    } else {
+                        res
+                    };
+                    app.callback.map(|cb| {
+                        cb.schedule(usize::from(res), operation as usize, valid as usize)
+                    });
+                }
+            });
+        });
+    }
+}
+
+impl<'a> ClientSign<'a> for PublicKeyDriver<'a> {
+    fn sign_done(
+        &'a self,
+        result: Result<(), ReturnCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) {
+        if let Some((algorithm, _)) = self.operation.get() {
+            self.engines[algorithm as usize].map(|engine| engine.clear_private_key());
+        }
+        let res = result.err().unwrap_or(ReturnCode::SUCCESS);
+        self.finish(res, false, |app, algorithm| {
+            let len = algorithm.signature_len();
+            match app.signature {
+                Some(ref mut dest) if dest.len() >= len => {
+                    dest.as_mut()[..len].copy_from_slice(&signature[..len]);
+                    true
+                }
+                _ => false,
+            }
+        });
+        signature.iter_mut().for_each(|b| *b = 0);
+        self.message.replace(message);
+        self.signature.replace(signature);
+    }
+}
+
+impl<'a> ClientVerify<'a> for PublicKeyDriver<'a> {
+    fn verify_done(
+        &'a self,
+        result: Result<bool, ReturnCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    ) {
+        match result {
+            Ok(valid) => self.finish(ReturnCode::SUCCESS, valid, |_, _| true),
+            Err(e) => self.finish(e, false, |_, _| true),
+        }
+        self.message.replace(message);
+        self.signature.replace(signature);
+    }
+}
+
+impl<'a> ClientKeyGenerate<'a> for PublicKeyDriver<'a> {
+    fn generate_done(
+        &'a self,
+        result: Result<(), ReturnCode>,
+        private_key: &'static mut [u8],
+        public_key: &'static mut [u8],
+    ) {
+        let res = result.err().unwrap_or(ReturnCode::SUCCESS);
+        self.finish(res, false, |app, algorithm| {
+            let (private_len, public_len) =
+                (algorithm.private_key_len(), algorithm.public_key_len());
+            match (app.private_key.as_mut(), app.public_key.as_mut()) {
+                (Some(private_dest), Some(public_dest))
+                    if private_dest.len() >= private_len && public_dest.len() >= public_len =>
+                {
+                    private_dest.as_mut()[..private_len]
+                        .copy_from_slice(&private_key[..private_len]);
+                    public_dest.as_mut()[..public_len].copy_from_slice(&public_key[..public_len]);
+                    true
+                }
+                _ => false,
+            }
+        });
+        private_key.iter_mut().for_each(|b| *b = 0);
+        self.private_key.replace(private_key);
+        self.public_key.replace(public_key);
+    }
+}
+
+impl<'a> Driver for PublicKeyDriver<'a> {
+    /// Specify memory regions to be used.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The message to sign or verify.
+    /// - `1`: The signature to verify, or which receives the signature.
+    /// - `2`: The public key to verify with, or which receives the
+    ///        generated public key.
+    /// - `3`: The private key to sign with, or which receives the generated
+    ///        private key.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                match allow_num {
+                    0 => app.message = slice,
+                    1 => app.signature = slice,
+                    2 => app.public_key = slice,
+                    3 => app.private_key = slice,
+                    _ => return ReturnCode::ENOSUPPORT,
+                }
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Subscribe to public-key events.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Subscribe to the completion of operations. The callback
+    ///        signature is `fn(result: ReturnCode, command: usize,
+    ///        valid: bool)`, where `command` is the number of the command
+    ///        which started the operation and `valid` is the result of
+    ///        verifications.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        appid: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.callback.insert(callback);
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Run public-key operations.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Select the algorithm: `0` for ECDSA P-256 and `1` for Ed25519.
+    /// - `2`: Sign the first `data1` bytes of the message.
+    /// - `3`: Verify the signature of the first `data1` bytes of the
+    ///        message.
+    /// - `4`: Generate a key pair.
+    fn command(&self, command_num: usize, data1: usize, _: usize, appid: AppId) -> ReturnCode {
+        let operation = match command_num {
+            0 => return ReturnCode::SUCCESS,
+            1 => {
+                return match Algorithm::from_usize(data1) {
+                    Some(algorithm) if self.engines[algorithm as usize].is_some() => self
+                        .apps
+                        .enter(appid, |app, _| {
+                            app.algorithm = Some(algorithm);
+                            ReturnCode::SUCCESS
+                        })
+                        .unwrap_or_else(|err| err.into()),
+                    _ => ReturnCode::ENOSUPPORT,
+                };
+            }
+            2 => Operation::Sign,
+            3 => Operation::Verify,
+            4 => Operation::Generate,
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+
+        // Release the engine if the process that owned it is gone
+        let owner_exists = self.appid.map_or(false, |owner| {
+            self.apps.enter(*owner, |_, _| true).unwrap_or(false)
+        });
+        if !owner_exists {
+            self.appid.clear();
+        }
+        if self.appid.is_some() || self.operation.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        self.apps
+            .enter(appid, |app, _| {
+                self.appid.set(appid);
+                let res = self.start(app, operation, data1);
+                if res != ReturnCode::SUCCESS {
+                    self.appid.clear();
+                }
+                res
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+pub struct App {
+    callback: OptionalCell<Callback>,
+    algorithm: Option<Algorithm>,
+    message: Option<AppSlice<Shared, u8>>,
+    signature: Option<AppSlice<Shared, u8>>,
+    public_key: Option<AppSlice<Shared, u8>>,
+    private_key: Option<AppSlice<Shared, u8>>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: OptionalCell::empty(),
+            algorithm: None,
+            message: None,
+            signature: None,
+            public_key: None,
+            private_key: None,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::mock::{MockKernel, MockProcess};
+    use std::boxed::Box;
+
+    /// An engine which keeps the buffers of an operation until the test
+    /// completes it.
+    struct HeldEngine {
+        first: TakeCell<'static, [u8]>,
+        second: TakeCell<'static, [u8]>,
+    }
+
+    impl<'a> SignatureSign<'a> for HeldEngine {
+        fn set_sign_client(&'a self, _client: &'a dyn ClientSign<'a>) {}
+        fn set_private_key(&self, _key: &[u8]) -> Result<(), ReturnCode> {
+            Ok(())
+        }
+        fn clear_private_key(&self) {}
+        fn sign(
+            &'a self,
+            message: LeasableBuffer<'static, u8>,
+            signature: &'static mut [u8],
+        ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+            self.first.replace(message.take());
+            self.second.replace(signature);
+            Ok(())
+        }
+    }
+
+    impl<'a> SignatureVerify<'a> for HeldEngine {
+        fn set_verify_client(&'a self, _client: &'a dyn ClientVerify<'a>) {}
+        fn set_public_key(&self, _key: &[u8]) -> Result<(), ReturnCode> {
+            Ok(())
+        }
+        fn verify(
+            &'a self,
+            message: LeasableBuffer<'static, u8>,
+            signature: &'static mut [u8],
+        ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+            Err((ReturnCode::ENOSUPPORT, message.take(), signature))
+        }
+    }
+
+    impl<'a> KeyGenerate<'a> for HeldEngine {
+        fn set_key_generate_client(&'a self, _client: &'a dyn ClientKeyGenerate<'a>) {}
+        fn generate(
+            &'a self,
+            private_key: &'static mut [u8],
+            public_key: &'static mut [u8],
+        ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+            self.first.replace(private_key);
+            self.second.replace(public_key);
+            Ok(())
+        }
+    }
+
+    fn buffer(len: usize) -> &'static mut [u8] {
+        Box::leak(std::vec![0; len].into_boxed_slice())
+    }
+
+    fn allow(driver: &PublicKeyDriver, app: &MockProcess, allow_num: usize, len: usize) {
+        let slice = app.allow_buffer(buffer(len));
+        assert_eq!(
+            driver.allow(app.appid(), allow_num, Some(slice)),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn results_larger_than_replaced_buffers() {
+        let kernel = MockKernel::new(1);
+        let engine: &'static HeldEngine = Box::leak(Box::new(HeldEngine {
+            first: TakeCell::empty(),
+            second: TakeCell::empty(),
+        }));
+        let driver: &'static PublicKeyDriver = Box::leak(Box::new(PublicKeyDriver::new(
+            buffer(16),
+            buffer(SIGNATURE_BUF_LEN),
+            buffer(PRIVATE_KEY_BUF_LEN),
+            buffer(PUBLIC_KEY_BUF_LEN),
+            kernel.create_grant(),
+        )));
+        driver.set_engine(Algorithm::Ed25519, engine);
+        let app = kernel.process(0);
+        driver.subscribe(0, Some(app.callback(DRIVER_NUM, 0)), app.appid());
+        assert_eq!(driver.command(1, 1, 0, app.appid()), ReturnCode::SUCCESS);
+
+        // The signature buffer shrinks while the message is signed
+        allow(driver, app, 0, 4);
+        allow(driver, app, 1, 64);
+        allow(driver, app, 3, 32);
+        assert_eq!(driver.command(2, 4, 0, app.appid()), ReturnCode::SUCCESS);
+        allow(driver, app, 1, 1);
+        driver.sign_done(
+            Ok(()),
+            engine.first.take().unwrap(),
+            engine.second.take().unwrap(),
+        );
+        assert_eq!(
+            app.take_callbacks(),
+            [(0, usize::from(ReturnCode::ESIZE), 2, 0)]
+        );
+
+        // So does the public key buffer while a key pair is generated
+        allow(driver, app, 2, 32);
+        assert_eq!(driver.command(4, 0, 0, app.appid()), ReturnCode::SUCCESS);
+        allow(driver, app, 2, 1);
+        driver.generate_done(
+            Ok(()),
+            engine.first.take().unwrap(),
+            engine.second.take().unwrap(),
+        );
+        assert_eq!(
+            app.take_callbacks(),
+            [(0, usize::from(ReturnCode::ESIZE), 4, 0)]
+        );
+
+        // The driver is free again, and results that fit are copied out
+        allow(driver, app, 1, 64);
+        assert_eq!(driver.command(2, 4, 0, app.appid()), ReturnCode::SUCCESS);
+        driver.sign_done(
+            Ok(()),
+            engine.first.take().unwrap(),
+            engine.second.take().unwrap(),
+        );
+        assert_eq!(
+            app.take_callbacks(),
+            [(0, usize::from(ReturnCode::SUCCESS), 2, 0)]
+        );
+    }
+}
@@ -0,0 +1,501 @@
+//! Ed25519 signatures (RFC 8032).
+//!
+//! Points are held in extended twisted Edwards coordinates and added with
+//! the unified formulas of Hisil, Wong, Carter and Dawson, which are
+//! complete on this curve, and scalars are multiplied with a Montgomery
+//! ladder, so signing and key derivation run in constant time. Verification
+//! uses the cofactorless equation `[S]B = R + [k]A`, rejects non-canonical
+//! `S` and points, and is not constant time since it only handles public
+//! data.
+//!
+//! A private key is the 32-byte seed of RFC 8032, a public key is the
+//! 32-byte encoded point and a signature is `R | S` (64 bytes).
+
+use super::modular::{self, Limbs, Modulus, ZERO};
+use crate::sha::Sha512Context;
+
+pub const PRIVATE_KEY_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The field prime, `2^255 - 19`.
+const P: Modulus = Modulus {
+    m: [
+        0xffffffed, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff, 0xffffffff,
+        0x7fffffff,
+    ],
+    m0inv: 0x286bca1b,
+    r2: [
+        0x000005a4, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000,
+        0x00000000,
+    ],
+    r3: [
+        0x0000d658, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000, 0x00000000,
+        0x00000000,
+    ],
+};
+
+/// The order of the base point, `2^252 + 27742317777372353535851937790883648493`.
+const L: Modulus = Modulus {
+    m: [
+        0x5cf5d3ed, 0x5812631a, 0xa2f79cd6, 0x14def9de, 0x00000000, 0x00000000, 0x00000000,
+        0x10000000,
+    ],
+    m0inv: 0x12547e1b,
+    r2: [
+        0x449c0f01, 0xa40611e3, 0x68859347, 0xd00e1ba7, 0x17f5be65, 0xceec73d2, 0x7c309a3d,
+        0x0399411b,
+    ],
+    r3: [
+        0x7b83a2db, 0x2a9e4968, 0xaef7f3ec, 0x278324e6, 0x04ec5b65, 0x8065dc6c, 0x3599cec7,
+        0x0e530b77,
+    ],
+};
+
+/// The curve constant `d = -121665 / 121666`, little-endian.
+const D: [u8; 32] = [
+    0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75, 0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70, 0x00,
+    0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c, 0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c, 0x03, 0x52,
+];
+
+/// A square root of -1, little-endian.
+const SQRT_M1: [u8; 32] = [
+    0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4, 0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43, 0x2f,
+    0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b, 0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24, 0x83, 0x2b,
+];
+
+const BX: [u8; 32] = [
+    0x1a, 0xd5, 0x25, 0x8f, 0x60, 0x2d, 0x56, 0xc9, 0xb2, 0xa7, 0x25, 0x95, 0x60, 0xc7, 0x2c, 0x69,
+    0x5c, 0xdc, 0xd6, 0xfd, 0x31, 0xe2, 0xa4, 0xc0, 0xfe, 0x53, 0x6e, 0xcd, 0xd3, 0x36, 0x69, 0x21,
+];
+
+const BY: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/// A point in extended coordinates `(X : Y : Z : T)`, with `x = X / Z`,
+/// `y = Y / Z` and `xy = T / Z`, in the Montgomery domain.
+#[derive(Copy, Clone)]
+struct Point {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+    t: Limbs,
+}
+
+fn d() -> Limbs {
+    P.to_mont(&modular::from_le_bytes(&D))
+}
+
+impl Point {
+    fn identity() -> Point {
+        Point {
+            x: ZERO,
+            y: P.one(),
+            z: P.one(),
+            t: ZERO,
+        }
+    }
+
+    fn from_affine(x: &Limbs, y: &Limbs) -> Point {
+        Point {
+            x: *x,
+            y: *y,
+            z: P.one(),
+            t: P.mul(x, y),
+        }
+    }
+
+    fn base() -> Point {
+        Point::from_affine(
+            &P.to_mont(&modular::from_le_bytes(&BX)),
+            &P.to_mont(&modular::from_le_bytes(&BY)),
+        )
+    }
+
+    /// Returns `self + q`, for any two points. `d2` is `2d` in the
+    /// Montgomery domain.
+    fn add(&self, q: &Point, d2: &Limbs) -> Point {
+        let a = P.mul(&P.sub(&self.y, &self.x), &P.sub(&q.y, &q.x));
+        let b = P.mul(&P.add(&self.y, &self.x), &P.add(&q.y, &q.x));
+        let c = P.mul(&P.mul(&self.t, d2), &q.t);
+        let zz = P.mul(&self.z, &q.z);
+        let d = P.add(&zz, &zz);
+        let e = P.sub(&b, &a);
+        let f = P.sub(&d, &c);
+        let g = P.add(&d, &c);
+        let h = P.add(&b, &a);
+        Point {
+            x: P.mul(&e, &f),
+            y: P.mul(&g, &h),
+            z: P.mul(&f, &g),
+            t: P.mul(&e, &h),
+        }
+    }
+
+    fn neg(&self) -> Point {
+        Point {
+            x: P.neg(&self.x),
+            y: self.y,
+            z: self.z,
+            t: P.neg(&self.t),
+        }
+    }
+
+    fn swap(choice: u32, a: &mut Point, b: &mut Point) {
+        modular::swap(choice, &mut a.x, &mut b.x);
+        modular::swap(choice, &mut a.y, &mut b.y);
+        modular::swap(choice, &mut a.z, &mut b.z);
+        modular::swap(choice, &mut a.t, &mut b.t);
+    }
+
+    /// Returns `k * self`, for an integer `k < 2^256`.
+    fn mul(&self, k: &Limbs) -> Point {
+        let d = d();
+        let d2 = P.add(&d, &d);
+        let mut r0 = Point::identity();
+        let mut r1 = *self;
+        for i in (0..256).rev() {
+            let bit = modular::bit(k, i);
+            Point::swap(bit, &mut r0, &mut r1);
+            r1 = r0.add(&r1, &d2);
+            r0 = r0.add(&r0, &d2);
+            Point::swap(bit, &mut r0, &mut r1);
+        }
+        r0
+    }
+
+    fn encode(&self, out: &mut [u8]) {
+        let zinv = P.inv(&self.z);
+        let x = P.from_mont(&P.mul(&self.x, &zinv));
+        let y = P.from_mont(&P.mul(&self.y, &zinv));
+        modular::to_le_bytes(&y, out);
+        out[31] |= ((x[0] & 1) as u8) << 7;
+    }
+
+    /// Decodes a point, as in RFC 8032 section 5.1.3.
+    fn decode(bytes: &[u8]) -> Option<Point> {
+        let mut y = modular::from_le_bytes(bytes);
+        let sign = y[7] >> 31;
+        y[7] &= 0x7fff_ffff;
+        if modular::less_than(&y, &P.m) == 0 {
+            return None;
+        }
+        let y = P.to_mont(&y);
+        let one = P.one();
+        // x^2 = u / v
+        let yy = P.square(&y);
+        let u = P.sub(&yy, &one);
+        let v = P.add(&P.mul(&d(), &yy), &one);
+
+        // x = u v^3 (u v^7)^((p - 5) / 8)
+        let mut exponent = P.m;
+        exponent[0] -= 5;
+        for i in 0..8 {
+            exponent[i] = (exponent[i] >> 3) | exponent.get(i + 1).map_or(0, |l| l << 29);
+        }
+        let v3 = P.mul(&P.square(&v), &v);
+        let v7 = P.mul(&P.square(&v3), &v);
+        let mut x = P.mul(&P.mul(&u, &v3), &P.pow(&P.mul(&u, &v7), &exponent));
+
+        let vxx = P.mul(&v, &P.square(&x));
+        if modular::equal(&vxx, &u) == 0 {
+            if modular::equal(&vxx, &P.neg(&u)) == 0 {
+                return None;
+            }
+            x = P.mul(&x, &P.to_mont(&modular::from_le_bytes(&SQRT_M1)));
+        }
+        let x_plain = P.from_mont(&x);
+        if modular::is_zero(&x_plain) == 1 && sign == 1 {
+            return None;
+        }
+        if x_plain[0] & 1 != sign {
+            x = P.neg(&x);
+        }
+        Some(Point::from_affine(&x, &y))
+    }
+}
+
+/// Reduces a SHA-512 hash, read as a little-endian integer, modulo `L`,
+/// into the Montgomery domain.
+fn hash_to_scalar(hash: &[u8; 64]) -> Limbs {
+    L.to_mont_wide(
+        &modular::from_le_bytes(&hash[..32]),
+        &modular::from_le_bytes(&hash[32..]),
+    )
+}
+
+/// Returns the secret scalar and the prefix derived from a seed.
+fn expand(private_key: &[u8]) -> (Limbs, [u8; 32]) {
+    let mut h = [0; 64];
+    let mut ctx = Sha512Context::new();
+    ctx.update(&private_key[..PRIVATE_KEY_LEN]);
+    ctx.finish(&mut h);
+    h[0] &= 248;
+    h[31] &= 127;
+    h[31] |= 64;
+    let mut prefix = [0; 32];
+    prefix.copy_from_slice(&h[32..]);
+    let a = modular::from_le_bytes(&h[..32]);
+    h.iter_mut().for_each(|b| *b = 0);
+    (a, prefix)
+}
+
+/// Computes the public key of `private_key`.
+pub fn public_key(private_key: &[u8], public_key: &mut [u8]) {
+    let (a, _) = expand(private_key);
+    Point::base().mul(&a).encode(public_key);
+}
+
+/// Signs `message`.
+pub fn sign(private_key: &[u8], message: &[u8], signature: &mut [u8]) {
+    let (a, mut prefix) = expand(private_key);
+    let mut public = [0; PUBLIC_KEY_LEN];
+    Point::base().mul(&a).encode(&mut public);
+
+    let mut h = [0; 64];
+    let mut ctx = Sha512Context::new();
+    ctx.update(&prefix);
+    ctx.update(message);
+    ctx.finish(&mut h);
+    let r = hash_to_scalar(&h);
+    Point::base()
+        .mul(&L.from_mont(&r))
+        .encode(&mut signature[..32]);
+
+    let mut ctx = Sha512Context::new();
+    ctx.update(&signature[..32]);
+    ctx.update(&public);
+    ctx.update(message);
+    ctx.finish(&mut h);
+    let k = hash_to_scalar(&h);
+    let s = L.from_mont(&L.add(&r, &L.mul(&k, &L.to_mont(&a))));
+    modular::to_le_bytes(&s, &mut signature[32..64]);
+    prefix.iter_mut().for_each(|b| *b = 0);
+}
+
+/// Verifies the signature of `message`. Returns `None` if the public key is
+/// not a valid point.
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool> {
+    let a = Point::decode(&public_key[..PUBLIC_KEY_LEN])?;
+    let s = modular::from_le_bytes(&signature[32..64]);
+    if modular::less_than(&s, &L.m) == 0 {
+        return Some(false);
+    }
+
+    let mut h = [0; 64];
+    let mut ctx = Sha512Context::new();
+    ctx.update(&signature[..32]);
+    ctx.update(&public_key[..PUBLIC_KEY_LEN]);
+    ctx.update(message);
+    ctx.finish(&mut h);
+    let k = L.from_mont(&hash_to_scalar(&h));
+
+    let d = d();
+    let d2 = P.add(&d, &d);
+    let mut r = [0; 32];
+    Point::base()
+        .mul(&s)
+        .add(&a.neg().mul(&k), &d2)
+        .encode(&mut r);
+    Some(r[..] == signature[..32])
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::hex::hex;
+
+    /// RFC 8032 7.1, tests 1 to 3.
+    const VECTORS: [(&str, &str, &str, &str); 3] = [
+        (
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+            "",
+            concat!(
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155",
+                "5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+            ),
+        ),
+        (
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+            "72",
+            concat!(
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da",
+                "085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+            ),
+        ),
+        (
+            "c5aa8df43f9f837bedb7442f31dcb7b166d38535076f094b85ce3a2e0b4458f7",
+            "fc51cd8e6218a1a38da47ed00230f0580816ed13ba3303ac5deb911548908025",
+            "af82",
+            concat!(
+                "6291d657deec24024827e69c3abe01a30ce548a284743a445e3680d7db5ac3ac",
+                "18ff9b538d16f290ae67f760984dc6594a7c15e9716ed28dc027beceea1ec40a"
+            ),
+        ),
+    ];
+
+    #[test]
+    fn rfc8032() {
+        for &(private_key, public_key, message, signature) in VECTORS.iter() {
+            let (private_key, public_key) = (hex(private_key), hex(public_key));
+            let (message, signature) = (hex(message), hex(signature));
+
+            let mut computed = [0; PUBLIC_KEY_LEN];
+            super::public_key(&private_key, &mut computed);
+            assert_eq!(&computed[..], &public_key[..]);
+            let mut computed = [0; SIGNATURE_LEN];
+            sign(&private_key, &message, &mut computed);
+            assert_eq!(&computed[..], &signature[..]);
+
+            assert_eq!(verify(&public_key, &message, &signature), Some(true));
+            let mut modified = message.clone();
+            modified.push(0);
+            assert_eq!(verify(&public_key, &modified, &signature), Some(false));
+            let mut modified = signature.clone();
+            modified[0] ^= 0x80;
+            assert_eq!(verify(&public_key, &message, &modified), Some(false));
+        }
+    }
+
+    /// Non-canonical encodings of S, A and R.
+    #[test]
+    fn malformed() {
+        let public_key = hex(VECTORS[0].1);
+        let signature = hex(VECTORS[0].3);
+
+        // S + L is congruent to S, but not canonical
+        let mut unreduced = signature.clone();
+        unreduced[32..].copy_from_slice(&hex(
+            "4c8c7872aa064e049dbb3013fbf29380d25bf5f0595bbe24655141438e7a101b",
+        ));
+        assert_eq!(verify(&public_key, &[], &unreduced), Some(false));
+        let mut unreduced = signature.clone();
+        unreduced[32..].iter_mut().for_each(|b| *b = 0xff);
+        assert_eq!(verify(&public_key, &[], &unreduced), Some(false));
+
+        // y = p would decode to y = 0, which is on the curve
+        let mut key = [0xff; 32];
+        key[0] = 0xed;
+        key[31] = 0x7f;
+        assert_eq!(verify(&key, &[], &signature), None);
+
+        // y = 2 has no matching x
+        let mut key = [0; 32];
+        key[0] = 2;
+        assert_eq!(verify(&key, &[], &signature), None);
+
+        // An R which is not a valid encoding never matches
+        let mut invalid_r = signature.clone();
+        invalid_r[..32].iter_mut().for_each(|b| *b = 0xff);
+        assert_eq!(verify(&public_key, &[], &invalid_r), Some(false));
+    }
+
+    /// The public key of group 1 of Wycheproof's `eddsa_test.json`.
+    const WYCHEPROOF_PUBLIC_KEY: &str =
+        "7d4d0e7f6153a69b6242b522abbee685fda4420f8834b108c3bdae369ef549fa";
+
+    /// Cases of group 1 of Wycheproof's `eddsa_test.json`, labelled with
+    /// their `tcId` or `comment`: (case, message, signature, result).
+    const WYCHEPROOF: [(&str, &str, &str, bool); 9] = [
+        (
+            "tcId 1",
+            "",
+            concat!(
+                "d4fbdb52bfa726b44d1786a8c0d171c3e62ca83c9e5bbe63de0bb2483f8fd6cc",
+                "1429ab72cafc41ab56af02ff8fcc43b99bfe4c7ae940f60f38ebaa9d311c4007"
+            ),
+            true,
+        ),
+        (
+            "tcId 2",
+            "78",
+            concat!(
+                "d80737358ede548acb173ef7e0399f83392fe8125b2ce877de7975d8b726ef5b",
+                "1e76632280ee38afad12125ea44b961bf92f1178c9fa819d020869975bcbe109"
+            ),
+            true,
+        ),
+        (
+            "tcId 3",
+            "54657374",
+            concat!(
+                "7c38e026f29e14aabd059a0f2db8b0cd783040609a8be684db12f82a27774ab0",
+                "7a9155711ecfaf7f99f277bad0c6ae7e39d4eef676573336a5c51eb6f946b30d"
+            ),
+            true,
+        ),
+        (
+            "tcId 4",
+            "48656c6c6f",
+            concat!(
+                "1c1ad976cbaae3b31dee07971cf92c928ce2091a85f5899f5e11ecec90fc9f8e",
+                "93df18c5037ec9b29c07195ad284e63d548cd0a6fe358cc775bd6c1608d2c905"
+            ),
+            true,
+        ),
+        (
+            "tcId 5",
+            "313233343030",
+            concat!(
+                "657c1492402ab5ce03e2c3a7f0384d051b9cf3570f1207fc78c1bcc98c281c2b",
+                "f0cf5b3a289976458a1be6277a5055545253b45b07dcc1abd96c8b989c00f301"
+            ),
+            true,
+        ),
+        (
+            "tcId 6",
+            "000000000000000000000000",
+            concat!(
+                "d46543bfb892f84ec124dcdfc847034c19363bf3fc2fa89b1267833a14856e52",
+                "e60736918783f950b6f1dd8d40dc343247cd43ce054c2d68ef974f7ed0f3c60f"
+            ),
+            true,
+        ),
+        (
+            "special values for r and s",
+            "3f",
+            concat!(
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000000"
+            ),
+            false,
+        ),
+        (
+            "checking malleability",
+            "54657374",
+            concat!(
+                "7c38e026f29e14aabd059a0f2db8b0cd783040609a8be684db12f82a27774ab0",
+                "67654bce3832c2d76f8f6f5dafc08d9339d4eef676573336a5c51eb6f946b31d"
+            ),
+            false,
+        ),
+        (
+            "checking malleability",
+            "54657374",
+            concat!(
+                "7c38e026f29e14aabd059a0f2db8b0cd783040609a8be684db12f82a27774ab0",
+                "5439412b5395d42f462c67008eba6ca839d4eef676573336a5c51eb6f946b32d"
+            ),
+            false,
+        ),
+    ];
+
+    #[test]
+    fn wycheproof() {
+        let public_key = hex(WYCHEPROOF_PUBLIC_KEY);
+        for &(case, message, signature, valid) in WYCHEPROOF.iter() {
+            assert_eq!(
+                verify(&public_key, &hex(message), &hex(signature)),
+                Some(valid),
+                "{}",
+                case
+            );
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Public-key signatures.
+//!
+//! `software` implements the `hil::public_key_crypto` traits for ECDSA over
+//! P-256 and for Ed25519, on top of the constant-time arithmetic of `p256`
+//! and `ed25519`, and `driver` gives processes access to them.
+
+mod modular;
+
+pub mod driver;
+pub mod ed25519;
+pub mod p256;
+pub mod software;
+
+pub use self::driver::PublicKeyDriver;
+pub use self::driver::DRIVER_NUM;
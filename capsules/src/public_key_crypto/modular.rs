@@ -0,0 +1,225 @@
+//! Constant-time arithmetic modulo 256-bit odd primes.
+//!
+//! Numbers are held as eight little-endian 32-bit limbs. Field elements are
+//! kept in the Montgomery domain (`a * 2^256 mod m`), where multiplication
+//! needs no division. None of the operations branch on, or index memory
+//! with, the values they compute on; the only branches in exponentiation
+//! depend on the exponent, which is always public here.
+
+pub type Limbs = [u32; 8];
+
+pub const ZERO: Limbs = [0; 8];
+
+/// An odd modulus and its Montgomery constants.
+pub struct Modulus {
+    pub m: Limbs,
+    /// `-m^-1 mod 2^32`
+    pub m0inv: u32,
+    /// `2^512 mod m`
+    pub r2: Limbs,
+    /// `2^768 mod m`
+    pub r3: Limbs,
+}
+
+/// Returns all ones if `bit` is 1 and zero if it is 0.
+fn mask(bit: u32) -> u32 {
+    0u32.wrapping_sub(bit)
+}
+
+fn add_limbs(a: &Limbs, b: &Limbs) -> (Limbs, u32) {
+    let mut out = ZERO;
+    let mut carry = 0u64;
+    for i in 0..8 {
+        let x = a[i] as u64 + b[i] as u64 + carry;
+        out[i] = x as u32;
+        carry = x >> 32;
+    }
+    (out, carry as u32)
+}
+
+fn sub_limbs(a: &Limbs, b: &Limbs) -> (Limbs, u32) {
+    let mut out = ZERO;
+    let mut borrow = 0u64;
+    for i in 0..8 {
+        let x = (a[i] as u64).wrapping_sub(b[i] as u64 + borrow);
+        out[i] = x as u32;
+        borrow = (x >> 63) & 1;
+    }
+    (out, borrow as u32)
+}
+
+/// Returns `a` if `choice` is 1 and `b` if it is 0.
+pub fn select(choice: u32, a: &Limbs, b: &Limbs) -> Limbs {
+    let m = mask(choice);
+    let mut out = ZERO;
+    for i in 0..8 {
+        out[i] = (a[i] & m) | (b[i] & !m);
+    }
+    out
+}
+
+/// Swaps `a` and `b` if `choice` is 1.
+pub fn swap(choice: u32, a: &mut Limbs, b: &mut Limbs) {
+    let m = mask(choice);
+    for i in 0..8 {
+        let t = (a[i] ^ b[i]) & m;
+        a[i] ^= t;
+        b[i] ^= t;
+    }
+}
+
+/// Returns 1 if `a < b`.
+pub fn less_than(a: &Limbs, b: &Limbs) -> u32 {
+    sub_limbs(a, b).1
+}
+
+/// Returns 1 if `a` is zero.
+pub fn is_zero(a: &Limbs) -> u32 {
+    let x = a.iter().fold(0, |acc, l| acc | l);
+    1 ^ ((x | x.wrapping_neg()) >> 31)
+}
+
+/// Returns 1 if `a == b`.
+pub fn equal(a: &Limbs, b: &Limbs) -> u32 {
+    let mut diff = ZERO;
+    for i in 0..8 {
+        diff[i] = a[i] ^ b[i];
+    }
+    is_zero(&diff)
+}
+
+/// Returns bit `i` of `a`.
+pub fn bit(a: &Limbs, i: usize) -> u32 {
+    (a[i / 32] >> (i % 32)) & 1
+}
+
+pub fn from_be_bytes(bytes: &[u8]) -> Limbs {
+    let mut out = ZERO;
+    for (i, b) in bytes[..32].iter().rev().enumerate() {
+        out[i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    out
+}
+
+pub fn to_be_bytes(a: &Limbs, out: &mut [u8]) {
+    for (i, b) in out[..32].iter_mut().rev().enumerate() {
+        *b = (a[i / 4] >> (8 * (i % 4))) as u8;
+    }
+}
+
+pub fn from_le_bytes(bytes: &[u8]) -> Limbs {
+    let mut out = ZERO;
+    for (i, b) in bytes[..32].iter().enumerate() {
+        out[i / 4] |= (*b as u32) << (8 * (i % 4));
+    }
+    out
+}
+
+pub fn to_le_bytes(a: &Limbs, out: &mut [u8]) {
+    for (i, b) in out[..32].iter_mut().enumerate() {
+        *b = (a[i / 4] >> (8 * (i % 4))) as u8;
+    }
+}
+
+impl Modulus {
+    /// Subtracts `m` from `a` (with carry `carry` above its top limb) if the
+    /// result is not negative.
+    fn reduce_once(&self, a: &Limbs, carry: u32) -> Limbs {
+        let (diff, borrow) = sub_limbs(a, &self.m);
+        select(carry | (1 ^ borrow), &diff, a)
+    }
+
+    pub fn add(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (sum, carry) = add_limbs(a, b);
+        self.reduce_once(&sum, carry)
+    }
+
+    pub fn sub(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (diff, borrow) = sub_limbs(a, b);
+        let mut correction = self.m;
+        correction.iter_mut().for_each(|l| *l &= mask(borrow));
+        add_limbs(&diff, &correction).0
+    }
+
+    pub fn neg(&self, a: &Limbs) -> Limbs {
+        self.sub(&ZERO, a)
+    }
+
+    /// Montgomery multiplication: returns `a * b / 2^256 mod m`, for
+    /// `a < 2^256` and `b < m`.
+    pub fn mul(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let mut t = [0u32; 10];
+        for i in 0..8 {
+            let mut carry = 0u64;
+            for j in 0..8 {
+                let x = t[j] as u64 + a[j] as u64 * b[i] as u64 + carry;
+                t[j] = x as u32;
+                carry = x >> 32;
+            }
+            let x = t[8] as u64 + carry;
+            t[8] = x as u32;
+            t[9] = (x >> 32) as u32;
+
+            let q = t[0].wrapping_mul(self.m0inv);
+            let x = t[0] as u64 + q as u64 * self.m[0] as u64;
+            let mut carry = x >> 32;
+            for j in 1..8 {
+                let x = t[j] as u64 + q as u64 * self.m[j] as u64 + carry;
+                t[j - 1] = x as u32;
+                carry = x >> 32;
+            }
+            let x = t[8] as u64 + carry;
+            t[7] = x as u32;
+            t[8] = t[9] + (x >> 32) as u32;
+        }
+        let mut out = ZERO;
+        out.copy_from_slice(&t[..8]);
+        self.reduce_once(&out, t[8])
+    }
+
+    pub fn square(&self, a: &Limbs) -> Limbs {
+        self.mul(a, a)
+    }
+
+    /// Converts any `a < 2^256` to the Montgomery domain, reducing it.
+    pub fn to_mont(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &self.r2)
+    }
+
+    pub fn from_mont(&self, a: &Limbs) -> Limbs {
+        let mut one = ZERO;
+        one[0] = 1;
+        self.mul(&one, a)
+    }
+
+    /// Converts `hi * 2^256 + lo` to the Montgomery domain, reducing it.
+    pub fn to_mont_wide(&self, lo: &Limbs, hi: &Limbs) -> Limbs {
+        self.add(&self.mul(lo, &self.r2), &self.mul(hi, &self.r3))
+    }
+
+    /// One, in the Montgomery domain.
+    pub fn one(&self) -> Limbs {
+        let mut one = ZERO;
+        one[0] = 1;
+        self.to_mont(&one)
+    }
+
+    /// Returns `a^e`, for a public exponent `e`.
+    pub fn pow(&self, a: &Limbs, e: &Limbs) -> Limbs {
+        let mut out = self.one();
+        for i in (0..256).rev() {
+            out = self.square(&out);
+            if bit(e, i) == 1 {
+                out = self.mul(&out, a);
+            }
+        }
+        out
+    }
+
+    /// Returns `a^-1`, or zero if `a` is zero, since `m` is prime.
+    pub fn inv(&self, a: &Limbs) -> Limbs {
+        let mut two = ZERO;
+        two[0] = 2;
+        self.pow(a, &sub_limbs(&self.m, &two).0)
+    }
+}
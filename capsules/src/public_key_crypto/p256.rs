@@ -0,0 +1,511 @@
+//! ECDSA over NIST P-256 (secp256r1) with SHA-256.
+//!
+//! Points are added with the complete projective formulas of Renes,
+//! Costello and Batina ("Complete addition formulas for prime order
+//! elliptic curves", algorithm 4), which have no special cases for doubling
+//! or for the point at infinity, and scalars are multiplied with a
+//! Montgomery ladder, so the sequence of operations never depends on secret
+//! values. Signing nonces are derived from the key and the message as in
+//! RFC 6979, so that no randomness is needed to sign.
+//!
+//! Keys and signatures are big-endian: a private key is the 32-byte scalar,
+//! a public key is `x | y` (64 bytes, the SEC1 uncompressed encoding
+//! without its `0x04` prefix), and a signature is `r | s` (64 bytes).
+
+use super::modular::{self, Limbs, Modulus, ZERO};
+use crate::sha::{hmac_sha256, Sha256Context};
+
+pub const PRIVATE_KEY_LEN: usize = 32;
+pub const PUBLIC_KEY_LEN: usize = 64;
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The field prime, `2^256 - 2^224 + 2^192 + 2^96 - 1`.
+const P: Modulus = Modulus {
+    m: [
+        0xffffffff, 0xffffffff, 0xffffffff, 0x00000000, 0x00000000, 0x00000000, 0x00000001,
+        0xffffffff,
+    ],
+    m0inv: 0x00000001,
+    r2: [
+        0x00000003, 0x00000000, 0xffffffff, 0xfffffffb, 0xfffffffe, 0xffffffff, 0xfffffffd,
+        0x00000004,
+    ],
+    r3: [
+        0x0000000a, 0xfffffffd, 0xfffffff7, 0xffffffed, 0xfffffffc, 0x00000005, 0x00000001,
+        0x00000018,
+    ],
+};
+
+/// The order of the group.
+const N: Modulus = Modulus {
+    m: [
+        0xfc632551, 0xf3b9cac2, 0xa7179e84, 0xbce6faad, 0xffffffff, 0xffffffff, 0x00000000,
+        0xffffffff,
+    ],
+    m0inv: 0xee00bc4f,
+    r2: [
+        0xbe79eea2, 0x83244c95, 0x49bd6fa6, 0x4699799c, 0x2b6bec59, 0x2845b239, 0xf3d95620,
+        0x66e12d94,
+    ],
+    r3: [
+        0x0b65a624, 0xac8ebec9, 0x0c0555c9, 0x111f28ae, 0x6ba5e93f, 0x2543b924, 0x6407be65,
+        0x503a54e7,
+    ],
+};
+
+const B: [u8; 32] = [
+    0x5a, 0xc6, 0x35, 0xd8, 0xaa, 0x3a, 0x93, 0xe7, 0xb3, 0xeb, 0xbd, 0x55, 0x76, 0x98, 0x86, 0xbc,
+    0x65, 0x1d, 0x06, 0xb0, 0xcc, 0x53, 0xb0, 0xf6, 0x3b, 0xce, 0x3c, 0x3e, 0x27, 0xd2, 0x60, 0x4b,
+];
+
+const GX: [u8; 32] = [
+    0x6b, 0x17, 0xd1, 0xf2, 0xe1, 0x2c, 0x42, 0x47, 0xf8, 0xbc, 0xe6, 0xe5, 0x63, 0xa4, 0x40, 0xf2,
+    0x77, 0x03, 0x7d, 0x81, 0x2d, 0xeb, 0x33, 0xa0, 0xf4, 0xa1, 0x39, 0x45, 0xd8, 0x98, 0xc2, 0x96,
+];
+
+const GY: [u8; 32] = [
+    0x4f, 0xe3, 0x42, 0xe2, 0xfe, 0x1a, 0x7f, 0x9b, 0x8e, 0xe7, 0xeb, 0x4a, 0x7c, 0x0f, 0x9e, 0x16,
+    0x2b, 0xce, 0x33, 0x57, 0x6b, 0x31, 0x5e, 0xce, 0xcb, 0xb6, 0x40, 0x68, 0x37, 0xbf, 0x51, 0xf5,
+];
+
+/// A point in projective coordinates, in the Montgomery domain.
+#[derive(Copy, Clone)]
+struct Point {
+    x: Limbs,
+    y: Limbs,
+    z: Limbs,
+}
+
+impl Point {
+    fn identity() -> Point {
+        Point {
+            x: ZERO,
+            y: P.one(),
+            z: ZERO,
+        }
+    }
+
+    fn from_affine(x: &Limbs, y: &Limbs) -> Point {
+        Point {
+            x: P.to_mont(x),
+            y: P.to_mont(y),
+            z: P.one(),
+        }
+    }
+
+    fn generator() -> Point {
+        Point::from_affine(&modular::from_be_bytes(&GX), &modular::from_be_bytes(&GY))
+    }
+
+    /// Returns `self + q`, for any two points. `b` is the curve constant in
+    /// the Montgomery domain.
+    fn add(&self, q: &Point, b: &Limbs) -> Point {
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&q.x, &q.y, &q.z);
+
+        let mut t0 = P.mul(x1, x2);
+        let mut t1 = P.mul(y1, y2);
+        let mut t2 = P.mul(z1, z2);
+        let mut t3 = P.add(x1, y1);
+        let mut t4 = P.add(x2, y2);
+        t3 = P.mul(&t3, &t4);
+        t4 = P.add(&t0, &t1);
+        t3 = P.sub(&t3, &t4);
+        t4 = P.add(y1, z1);
+        let mut x3 = P.add(y2, z2);
+        t4 = P.mul(&t4, &x3);
+        x3 = P.add(&t1, &t2);
+        t4 = P.sub(&t4, &x3);
+        x3 = P.add(x1, z1);
+        let mut y3 = P.add(x2, z2);
+        x3 = P.mul(&x3, &y3);
+        y3 = P.add(&t0, &t2);
+        y3 = P.sub(&x3, &y3);
+        let mut z3 = P.mul(b, &t2);
+        x3 = P.sub(&y3, &z3);
+        z3 = P.add(&x3, &x3);
+        x3 = P.add(&x3, &z3);
+        z3 = P.sub(&t1, &x3);
+        x3 = P.add(&t1, &x3);
+        y3 = P.mul(b, &y3);
+        t1 = P.add(&t2, &t2);
+        t2 = P.add(&t1, &t2);
+        y3 = P.sub(&y3, &t2);
+        y3 = P.sub(&y3, &t0);
+        t1 = P.add(&y3, &y3);
+        y3 = P.add(&t1, &y3);
+        t1 = P.add(&t0, &t0);
+        t0 = P.add(&t1, &t0);
+        t0 = P.sub(&t0, &t2);
+        t1 = P.mul(&t4, &y3);
+        t2 = P.mul(&t0, &y3);
+        y3 = P.mul(&x3, &z3);
+        y3 = P.add(&y3, &t2);
+        x3 = P.mul(&t3, &x3);
+        x3 = P.sub(&x3, &t1);
+        z3 = P.mul(&t4, &z3);
+        t1 = P.mul(&t3, &t0);
+        z3 = P.add(&z3, &t1);
+
+        Point {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    fn swap(choice: u32, a: &mut Point, b: &mut Point) {
+        modular::swap(choice, &mut a.x, &mut b.x);
+        modular::swap(choice, &mut a.y, &mut b.y);
+        modular::swap(choice, &mut a.z, &mut b.z);
+    }
+
+    /// Returns `k * self`, for an integer `k < 2^256`.
+    fn mul(&self, k: &Limbs) -> Point {
+        let b = P.to_mont(&modular::from_be_bytes(&B));
+        let mut r0 = Point::identity();
+        let mut r1 = *self;
+        for i in (0..256).rev() {
+            let bit = modular::bit(k, i);
+            Point::swap(bit, &mut r0, &mut r1);
+            r1 = r0.add(&r1, &b);
+            r0 = r0.add(&r0, &b);
+            Point::swap(bit, &mut r0, &mut r1);
+        }
+        r0
+    }
+
+    /// Returns the affine coordinates, or `None` for the point at infinity.
+    fn to_affine(&self) -> Option<(Limbs, Limbs)> {
+        if modular::is_zero(&self.z) == 1 {
+            return None;
+        }
+        let zinv = P.inv(&self.z);
+        Some((
+            P.from_mont(&P.mul(&self.x, &zinv)),
+            P.from_mont(&P.mul(&self.y, &zinv)),
+        ))
+    }
+}
+
+/// Returns whether `(x, y)` are coordinates of a point on the curve.
+fn is_on_curve(x: &Limbs, y: &Limbs) -> bool {
+    if modular::less_than(x, &P.m) == 0 || modular::less_than(y, &P.m) == 0 {
+        return false;
+    }
+    let xm = P.to_mont(x);
+    let ym = P.to_mont(y);
+    let b = P.to_mont(&modular::from_be_bytes(&B));
+    // y^2 = x^3 - 3x + b
+    let x3 = P.mul(&P.square(&xm), &xm);
+    let three_x = P.add(&P.add(&xm, &xm), &xm);
+    let rhs = P.add(&P.sub(&x3, &three_x), &b);
+    modular::equal(&P.square(&ym), &rhs) == 1
+}
+
+/// Returns whether `d` is a valid private key, in `[1, n - 1]`.
+fn is_valid_scalar(d: &Limbs) -> bool {
+    (modular::is_zero(d) ^ 1) & modular::less_than(d, &N.m) == 1
+}
+
+/// The HMAC-DRBG of RFC 6979, section 3.2, which generates the nonces used
+/// to sign `hash` with `private_key`.
+struct NonceGenerator {
+    k: [u8; 32],
+    v: [u8; 32],
+}
+
+impl NonceGenerator {
+    fn new(private_key: &[u8], hash: &Limbs) -> NonceGenerator {
+        let mut h = [0; 32];
+        modular::to_be_bytes(&N.from_mont(&N.to_mont(hash)), &mut h);
+        let mut gen = NonceGenerator {
+            k: [0; 32],
+            v: [1; 32],
+        };
+        gen.k = hmac_sha256(&gen.k, &[&gen.v, &[0], private_key, &h]);
+        gen.v = hmac_sha256(&gen.k, &[&gen.v]);
+        gen.k = hmac_sha256(&gen.k, &[&gen.v, &[1], private_key, &h]);
+        gen.v = hmac_sha256(&gen.k, &[&gen.v]);
+        gen
+    }
+
+    /// Returns the next candidate nonce.
+    fn next(&mut self) -> Limbs {
+        loop {
+            self.v = hmac_sha256(&self.k, &[&self.v]);
+            let k = modular::from_be_bytes(&self.v);
+            self.k = hmac_sha256(&self.k, &[&self.v, &[0]]);
+            self.v = hmac_sha256(&self.k, &[&self.v]);
+            if is_valid_scalar(&k) {
+                return k;
+            }
+        }
+    }
+}
+
+fn hash(message: &[u8]) -> Limbs {
+    let mut digest = [0; 32];
+    let mut ctx = Sha256Context::new();
+    ctx.update(message);
+    ctx.finish(&mut digest);
+    modular::from_be_bytes(&digest)
+}
+
+/// Returns whether `private_key` is a valid private key.
+pub fn is_valid_private_key(private_key: &[u8]) -> bool {
+    is_valid_scalar(&modular::from_be_bytes(private_key))
+}
+
+/// Computes the public key of `private_key`. Returns `false` if the private
+/// key is not valid.
+pub fn public_key(private_key: &[u8], public_key: &mut [u8]) -> bool {
+    let d = modular::from_be_bytes(private_key);
+    if !is_valid_scalar(&d) {
+        return false;
+    }
+    match Point::generator().mul(&d).to_affine() {
+        Some((x, y)) => {
+            modular::to_be_bytes(&x, &mut public_key[..32]);
+            modular::to_be_bytes(&y, &mut public_key[32..64]);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Signs the SHA-256 hash of `message`. Returns `false` if the private key
+/// is not valid.
+pub fn sign(private_key: &[u8], message: &[u8], signature: &mut [u8]) -> bool {
+    let d = modular::from_be_bytes(private_key);
+    if !is_valid_scalar(&d) {
+        return false;
+    }
+    let h = hash(message);
+    let e = N.to_mont(&h);
+    let dm = N.to_mont(&d);
+    let mut nonces = NonceGenerator::new(&private_key[..PRIVATE_KEY_LEN], &h);
+    loop {
+        // The loop is only taken again with negligible probability
+        let k = nonces.next();
+        let x = match Point::generator().mul(&k).to_affine() {
+            Some((x, _)) => x,
+            None => continue,
+        };
+        let r = N.to_mont(&x);
+        let s = N.mul(&N.inv(&N.to_mont(&k)), &N.add(&e, &N.mul(&r, &dm)));
+        let (r, s) = (N.from_mont(&r), N.from_mont(&s));
+        if modular::is_zero(&r) == 1 || modular::is_zero(&s) == 1 {
+            continue;
+        }
+        modular::to_be_bytes(&r, &mut signature[..32]);
+        modular::to_be_bytes(&s, &mut signature[32..64]);
+        return true;
+    }
+}
+
+/// Verifies the signature of `message`. Returns `None` if the public key is
+/// not a valid point.
+pub fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool> {
+    let qx = modular::from_be_bytes(&public_key[..32]);
+    let qy = modular::from_be_bytes(&public_key[32..64]);
+    if !is_on_curve(&qx, &qy) {
+        return None;
+    }
+    let r = modular::from_be_bytes(&signature[..32]);
+    let s = modular::from_be_bytes(&signature[32..64]);
+    if !is_valid_scalar(&r) || !is_valid_scalar(&s) {
+        return Some(false);
+    }
+
+    let rm = N.to_mont(&r);
+    let w = N.inv(&N.to_mont(&s));
+    let u1 = N.from_mont(&N.mul(&N.to_mont(&hash(message)), &w));
+    let u2 = N.from_mont(&N.mul(&rm, &w));
+    let b = P.to_mont(&modular::from_be_bytes(&B));
+    let point = Point::generator()
+        .mul(&u1)
+        .add(&Point::from_affine(&qx, &qy).mul(&u2), &b);
+    Some(match point.to_affine() {
+        Some((x, _)) => modular::equal(&N.to_mont(&x), &rm) == 1,
+        None => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::hex::hex;
+    use std::vec::Vec;
+
+    const ORDER: &str = "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632551";
+    const PRIME: &str = "ffffffff00000001000000000000000000000000ffffffffffffffffffffffff";
+
+    fn sample_key() -> (Vec<u8>, Vec<u8>) {
+        let private_key = hex("c9afa9d845ba75166b5c215767b1d6934e50c3db36e89b127b8a622b120f6721");
+        let public_key = hex(concat!(
+            "60fed4ba255a9d31c961eb74c6356d68c049b8923b61fa6ce669622e60f29fb6",
+            "7903fe1008b8bc99a41ae9e95628bc64f2f1b20c2d7e9f5177a3c294d4462299"
+        ));
+        (private_key, public_key)
+    }
+
+    /// RFC 6979 A.2.5, with SHA-256 and the message "sample".
+    #[test]
+    fn rfc6979() {
+        let (private_key, public_key) = sample_key();
+        let mut computed = [0; PUBLIC_KEY_LEN];
+        assert!(super::public_key(&private_key, &mut computed));
+        assert_eq!(&computed[..], &public_key[..]);
+
+        let mut signature = [0; SIGNATURE_LEN];
+        assert!(sign(&private_key, b"sample", &mut signature));
+        assert_eq!(
+            &signature[..],
+            &hex(concat!(
+                "efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716",
+                "f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8"
+            ))[..]
+        );
+        assert_eq!(verify(&public_key, b"sample", &signature), Some(true));
+        assert_eq!(verify(&public_key, b"samplf", &signature), Some(false));
+    }
+
+    #[test]
+    fn private_keys() {
+        assert!(!is_valid_private_key(&[0; 32]));
+        assert!(!is_valid_private_key(&hex(ORDER)));
+        let mut below = hex(ORDER);
+        below[31] -= 1;
+        assert!(is_valid_private_key(&below));
+        assert!(!sign(&[0; 32], b"sample", &mut [0; SIGNATURE_LEN]));
+    }
+
+    /// Signatures whose r or s is out of range, or swapped.
+    #[test]
+    fn malformed_signatures() {
+        let (_, public_key) = sample_key();
+        let r = hex("efd48b2aacb6a8fd1140dd9cd45e81d69d2c877b56aaf991c34d0ea84eaf3716");
+        let s = hex("f7cb1c942d657c41d436c7a1b6e29f65f3e900dbb9aff4064dc4ab2f843acda8");
+        let signature = |r: &[u8], s: &[u8]| [r, s].concat();
+
+        // ECDSA signatures are malleable: (r, n - s) is valid too
+        let negated = hex("0834e36ad29a83bf2bc9385e491d6099c8fdf9d1ed67aa7ea5f51f93782857a9");
+        assert_eq!(
+            verify(&public_key, b"sample", &signature(&r, &negated)),
+            Some(true)
+        );
+
+        let zero = [0; 32];
+        let order = hex(ORDER);
+        for &(r, s) in [
+            (&zero[..], &s[..]),
+            (&r[..], &zero[..]),
+            (&order[..], &s[..]),
+            (&r[..], &order[..]),
+            (&s[..], &r[..]),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                verify(&public_key, b"sample", &signature(r, s)),
+                Some(false)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_public_keys() {
+        let (private_key, public_key) = sample_key();
+        let mut signature = [0; SIGNATURE_LEN];
+        assert!(sign(&private_key, b"sample", &mut signature));
+
+        // A point off the curve
+        let mut off_curve = public_key.clone();
+        off_curve[63] ^= 1;
+        assert_eq!(verify(&off_curve, b"sample", &signature), None);
+
+        // Coordinates which are not reduced modulo p
+        let mut unreduced = public_key.clone();
+        unreduced[..32].copy_from_slice(&hex(PRIME));
+        assert_eq!(verify(&unreduced, b"sample", &signature), None);
+
+        // The point at infinity has no affine encoding
+        assert_eq!(verify(&[0; 64], b"sample", &signature), None);
+    }
+
+    /// The public key of group 1 of Wycheproof's
+    /// `ecdsa_secp256r1_sha256_test.json`.
+    const WYCHEPROOF_PUBLIC_KEY: &str = concat!(
+        "2927b10512bae3eddcfe467828128bad2903269919f7086069c8c4df6c732838",
+        "c7787964eaac00e5921fb1498a60f4606766b3d9685001558d1a974e7341513e"
+    );
+
+    /// Cases of group 1 of Wycheproof's `ecdsa_secp256r1_sha256_test.json`,
+    /// labelled with their `tcId` or `comment`, with the DER signature split
+    /// into r and s: (case, message, r, s, result).
+    const WYCHEPROOF: [(&str, &str, &str, &str, bool); 3] = [
+        (
+            "tcId 1",
+            "313233343030",
+            "2ba3a8be6b94d5ec80a6d9d1190a436effe50d85a1eee859b8cc6af9bd5c2e18",
+            "4cd60b855d442f5b3c7b11eb6c4e0ae7525fe710fab9aa7c77a67f79e6fadd76",
+            true,
+        ),
+        (
+            "tcId 3",
+            "313233343030",
+            "2ba3a8be6b94d5ec80a6d9d1190a436effe50d85a1eee859b8cc6af9bd5c2e18",
+            "b329f479a2bbd0a5c384ee1493b1f5186a87139cac5df4087c134b49156847db",
+            true,
+        ),
+        (
+            "Edge case for Shamir multiplication",
+            "3639383139",
+            "64a1aab5000d0e804f3e2fc02bdee9be8ff312334e2ba16d11547c97711c898e",
+            "6af015971cc30be6d1a206d4e013e0997772a2f91d73286ffd683b9bb2cf4f1b",
+            true,
+        ),
+    ];
+
+    /// The r and s values of the "Signature with special case values" cases
+    /// of the same group which fit in 32 bytes: 0, 1, n - 1, n, n + 1, p and
+    /// p + 1. All of their combinations are invalid.
+    const WYCHEPROOF_SPECIAL_VALUES: [&str; 7] = [
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000001",
+        "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632550",
+        ORDER,
+        "ffffffff00000000ffffffffffffffffbce6faada7179e84f3b9cac2fc632552",
+        PRIME,
+        "ffffffff00000001000000000000000000000001000000000000000000000000",
+    ];
+
+    #[test]
+    fn wycheproof() {
+        let public_key = hex(WYCHEPROOF_PUBLIC_KEY);
+        for &(case, message, r, s, valid) in WYCHEPROOF.iter() {
+            let signature = [hex(r), hex(s)].concat();
+            assert_eq!(
+                verify(&public_key, &hex(message), &signature),
+                Some(valid),
+                "{}",
+                case
+            );
+        }
+        for r in WYCHEPROOF_SPECIAL_VALUES.iter() {
+            for s in WYCHEPROOF_SPECIAL_VALUES.iter() {
+                let signature = [hex(r), hex(s)].concat();
+                assert_eq!(
+                    verify(&public_key, &hex("313233343030"), &signature),
+                    Some(false),
+                    "r = {}, s = {}",
+                    r,
+                    s
+                );
+            }
+        }
+    }
+}
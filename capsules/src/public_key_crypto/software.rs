@@ -0,0 +1,543 @@
+//! Software implementation of the public-key signature interfaces.
+//!
+//! `PublicKeySoftware` provides `SignatureSign`, `SignatureVerify` and
+//! `KeyGenerate` for one `SignatureAlgorithm`. Signatures are computed and
+//! verified from a deferred call, so the callbacks are never issued from
+//! within the call which started the operation; the computation itself is
+//! not split, and takes a few tens of milliseconds on a Cortex-M4 for both
+//! algorithms.
+//!
+//! Key generation draws the private key from an `Rng`, which should be a
+//! cryptographically secure generator, given with `set_rng()`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ecdsa = static_init!(
+//!     PublicKeySoftware<'static, EcdsaP256>,
+//!     PublicKeySoftware::new(dynamic_deferred_caller)
+//! );
+//! ecdsa.initialize_callback_handle(dynamic_deferred_caller.register(ecdsa).unwrap());
+//! ecdsa.set_rng(rng);
+//! rng.set_client(ecdsa);
+//! ```
+
+use super::{ed25519, p256};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::public_key_crypto::{
+    ClientKeyGenerate, ClientSign, ClientVerify, KeyGenerate, SignatureSign, SignatureVerify,
+};
+use kernel::hil::rng;
+use kernel::ReturnCode;
+
+const MAX_PRIVATE_KEY_LEN: usize = 32;
+const MAX_PUBLIC_KEY_LEN: usize = 64;
+
+/// A signature algorithm, computed synchronously.
+pub trait SignatureAlgorithm {
+    const PRIVATE_KEY_LEN: usize;
+    const PUBLIC_KEY_LEN: usize;
+    const SIGNATURE_LEN: usize;
+
+    fn is_valid_private_key(private_key: &[u8]) -> bool;
+
+    fn public_key(private_key: &[u8], public_key: &mut [u8]);
+
+    fn sign(private_key: &[u8], message: &[u8], signature: &mut [u8]);
+
+    /// Returns `None` if the public key is not valid.
+    fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool>;
+}
+
+/// ECDSA over P-256, with SHA-256.
+pub struct EcdsaP256;
+
+impl SignatureAlgorithm for EcdsaP256 {
+    const PRIVATE_KEY_LEN: usize = p256::PRIVATE_KEY_LEN;
+    const PUBLIC_KEY_LEN: usize = p256::PUBLIC_KEY_LEN;
+    const SIGNATURE_LEN: usize = p256::SIGNATURE_LEN;
+
+    fn is_valid_private_key(private_key: &[u8]) -> bool {
+        p256::is_valid_private_key(private_key)
+    }
+
+    fn public_key(private_key: &[u8], public_key: &mut [u8]) {
+        p256::public_key(private_key, public_key);
+    }
+
+    fn sign(private_key: &[u8], message: &[u8], signature: &mut [u8]) {
+        p256::sign(private_key, message, signature);
+    }
+
+    fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool> {
+        p256::verify(public_key, message, signature)
+    }
+}
+
+/// Ed25519.
+pub struct Ed25519;
+
+impl SignatureAlgorithm for Ed25519 {
+    const PRIVATE_KEY_LEN: usize = ed25519::PRIVATE_KEY_LEN;
+    const PUBLIC_KEY_LEN: usize = ed25519::PUBLIC_KEY_LEN;
+    const SIGNATURE_LEN: usize = ed25519::SIGNATURE_LEN;
+
+    fn is_valid_private_key(_private_key: &[u8]) -> bool {
+        true
+    }
+
+    fn public_key(private_key: &[u8], public_key: &mut [u8]) {
+        ed25519::public_key(private_key, public_key);
+    }
+
+    fn sign(private_key: &[u8], message: &[u8], signature: &mut [u8]) {
+        ed25519::sign(private_key, message, signature);
+    }
+
+    fn verify(public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool> {
+        ed25519::verify(public_key, message, signature)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Operation {
+    Sign,
+    Verify,
+    Generate,
+}
+
+pub struct PublicKeySoftware<'a, S: SignatureAlgorithm> {
+    sign_client: OptionalCell<&'a dyn ClientSign<'a>>,
+    verify_client: OptionalCell<&'a dyn ClientVerify<'a>>,
+    generate_client: OptionalCell<&'a dyn ClientKeyGenerate<'a>>,
+    rng: OptionalCell<&'a dyn rng::Rng<'a>>,
+
+    private_key: Cell<Option<[u8; MAX_PRIVATE_KEY_LEN]>>,
+    public_key: Cell<Option<[u8; MAX_PUBLIC_KEY_LEN]>>,
+
+    operation: Cell<Option<Operation>>,
+    message: MapCell<LeasableBuffer<'static, u8>>,
+    /// The signature, or the private key being generated.
+    buffer: TakeCell<'static, [u8]>,
+    /// The public key being generated.
+    public_key_buffer: TakeCell<'static, [u8]>,
+    /// The number of bytes of the private key drawn so far.
+    generated: Cell<usize>,
+
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+    phantom: PhantomData<S>,
+}
+
+impl<'a, S: SignatureAlgorithm> PublicKeySoftware<'a, S> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> PublicKeySoftware<'a, S> {
+        PublicKeySoftware {
+            sign_client: OptionalCell::empty(),
+            verify_client: OptionalCell::empty(),
+            generate_client: OptionalCell::empty(),
+            rng: OptionalCell::empty(),
+            private_key: Cell::new(None),
+            public_key: Cell::new(None),
+            operation: Cell::new(None),
+            message: MapCell::empty(),
+            buffer: TakeCell::empty(),
+            public_key_buffer: TakeCell::empty(),
+            generated: Cell::new(0),
+            deferred_caller,
+            handle: OptionalCell::empty(),
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Sets the generator that private keys are drawn from.
+    pub fn set_rng(&self, rng: &'a dyn rng::Rng<'a>) {
+        self.rng.set(rng);
+    }
+
+    fn start(
+        &self,
+        operation: Operation,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+        if self.operation.get().is_some() {
+            return Err((ReturnCode::EBUSY, message.take(), signature));
+        }
+        let has_key = match operation {
+            Operation::Sign => self.private_key.get().is_some(),
+            _ => self.public_key.get().is_some(),
+        };
+        if !has_key {
+            return Err((ReturnCode::ERESERVE, message.take(), signature));
+        }
+        if signature.len() < S::SIGNATURE_LEN {
+            return Err((ReturnCode::EINVAL, message.take(), signature));
+        }
+        self.operation.set(Some(operation));
+        self.message.replace(message);
+        self.buffer.replace(signature);
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+        Ok(())
+    }
+
+    /// Ends key generation, handing the buffers back to the client.
+    fn generate_done(&self, result: Result<(), ReturnCode>) {
+        self.operation.set(None);
+        if let (Some(private_key), Some(public_key)) =
+            (self.buffer.take(), self.public_key_buffer.take())
+        {
+            self.generate_client
+                .map(move |client| client.generate_done(result, private_key, public_key));
+        }
+    }
+}
+
+impl<'a, S: SignatureAlgorithm> DynamicDeferredCallClient for PublicKeySoftware<'a, S> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        let operation = self.operation.get();
+        let message = self.message.take();
+        let buffer = self.buffer.take();
+        let (message, signature) = match (message, buffer) {
+            (Some(message), Some(signature)) => (message, signature),
+            _ => return,
+        };
+        self.operation.set(None);
+        match operation {
+            Some(Operation::Sign) => {
+                let mut key = self.private_key.get().unwrap_or([0; MAX_PRIVATE_KEY_LEN]);
+                S::sign(&key[..S::PRIVATE_KEY_LEN], &message[..], signature);
+                key.iter_mut().for_each(|b| *b = 0);
+                self.sign_client
+                    .map(move |client| client.sign_done(Ok(()), message.take(), signature));
+            }
+            Some(Operation::Verify) => {
+                let key = self.public_key.get().unwrap_or([0; MAX_PUBLIC_KEY_LEN]);
+                let result = S::verify(&key[..S::PUBLIC_KEY_LEN], &message[..], signature)
+                    .ok_or(ReturnCode::EINVAL);
+                self.verify_client
+                    .map(move |client| client.verify_done(result, message.take(), signature));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, S: SignatureAlgorithm> SignatureSign<'a> for PublicKeySoftware<'a, S> {
+    fn set_sign_client(&'a self, client: &'a dyn ClientSign<'a>) {
+        self.sign_client.set(client);
+    }
+
+    fn set_private_key(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        if key.len() != S::PRIVATE_KEY_LEN || !S::is_valid_private_key(key) {
+            return Err(ReturnCode::EINVAL);
+        }
+        if self.operation.get() == Some(Operation::Sign) {
+            return Err(ReturnCode::EBUSY);
+        }
+        let mut k = [0; MAX_PRIVATE_KEY_LEN];
+        k[..key.len()].copy_from_slice(key);
+        self.private_key.set(Some(k));
+        Ok(())
+    }
+
+    fn clear_private_key(&self) {
+        self.private_key.set(None);
+    }
+
+    fn sign(
+        &'a self,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+        self.start(Operation::Sign, message, signature)
+    }
+}
+
+impl<'a, S: SignatureAlgorithm> SignatureVerify<'a> for PublicKeySoftware<'a, S> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<'a>) {
+        self.verify_client.set(client);
+    }
+
+    fn set_public_key(&self, key: &[u8]) -> Result<(), ReturnCode> {
+        if key.len() != S::PUBLIC_KEY_LEN {
+            return Err(ReturnCode::EINVAL);
+        }
+        if self.operation.get() == Some(Operation::Verify) {
+            return Err(ReturnCode::EBUSY);
+        }
+        let mut k = [0; MAX_PUBLIC_KEY_LEN];
+        k[..key.len()].copy_from_slice(key);
+        self.public_key.set(Some(k));
+        Ok(())
+    }
+
+    fn verify(
+        &'a self,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+        self.start(Operation::Verify, message, signature)
+    }
+}
+
+impl<'a, S: SignatureAlgorithm> KeyGenerate<'a> for PublicKeySoftware<'a, S> {
+    fn set_key_generate_client(&'a self, client: &'a dyn ClientKeyGenerate<'a>) {
+        self.generate_client.set(client);
+    }
+
+    fn generate(
+        &'a self,
+        private_key: &'static mut [u8],
+        public_key: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])> {
+        if self.operation.get().is_some() {
+            return Err((ReturnCode::EBUSY, private_key, public_key));
+        }
+        if private_key.len() < S::PRIVATE_KEY_LEN || public_key.len() < S::PUBLIC_KEY_LEN {
+            return Err((ReturnCode::EINVAL, private_key, public_key));
+        }
+        let res = self.rng.map_or(ReturnCode::ENOSUPPORT, |rng| rng.get());
+        if res != ReturnCode::SUCCESS {
+            return Err((res, private_key, public_key));
+        }
+        self.operation.set(Some(Operation::Generate));
+        self.generated.set(0);
+        self.buffer.replace(private_key);
+        self.public_key_buffer.replace(public_key);
+        Ok(())
+    }
+}
+
+impl<'a, S: SignatureAlgorithm> rng::Client for PublicKeySoftware<'a, S> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> rng::Continue {
+        if self.operation.get() != Some(Operation::Generate) {
+            return rng::Continue::Done;
+        }
+        if error != ReturnCode::SUCCESS {
+            self.generate_done(Err(error));
+            return rng::Continue::Done;
+        }
+
+        let complete = self.buffer.map_or(false, |private_key| {
+            let mut generated = self.generated.get();
+            while generated < S::PRIVATE_KEY_LEN {
+                let word = match randomness.next() {
+                    Some(word) => word,
+                    None => break,
+                };
+                for b in word.to_le_bytes().iter() {
+                    if generated < S::PRIVATE_KEY_LEN {
+                        private_key[generated] = *b;
+                        generated += 1;
+                    }
+                }
+            }
+            // Draw a new key in the rare case it is out of range
+            if generated == S::PRIVATE_KEY_LEN
+                && !S::is_valid_private_key(&private_key[..S::PRIVATE_KEY_LEN])
+            {
+                generated = 0;
+            }
+            self.generated.set(generated);
+            generated == S::PRIVATE_KEY_LEN
+        });
+        if !complete {
+            return rng::Continue::More;
+        }
+
+        self.buffer.map(|private_key| {
+            self.public_key_buffer.map(|public_key| {
+                S::public_key(&private_key[..S::PRIVATE_KEY_LEN], public_key);
+            });
+        });
+        self.generate_done(Ok(()));
+        rng::Continue::Done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use kernel::common::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use std::boxed::Box;
+
+    struct Rng {
+        next: Cell<u32>,
+    }
+
+    impl rng::Rng<'static> for Rng {
+        fn get(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn cancel(&self) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+        fn set_client(&'static self, _: &'static dyn rng::Client) {}
+    }
+
+    struct Client {
+        signed: Cell<bool>,
+        verified: Cell<Option<Result<bool, ReturnCode>>>,
+        generated: Cell<bool>,
+        message: TakeCell<'static, [u8]>,
+        signature: TakeCell<'static, [u8]>,
+        public_key: TakeCell<'static, [u8]>,
+        private_key: TakeCell<'static, [u8]>,
+    }
+
+    impl Client {
+        fn new() -> Client {
+            Client {
+                signed: Cell::new(false),
+                verified: Cell::new(None),
+                generated: Cell::new(false),
+                message: TakeCell::empty(),
+                signature: TakeCell::empty(),
+                public_key: TakeCell::empty(),
+                private_key: TakeCell::empty(),
+            }
+        }
+    }
+
+    impl ClientSign<'static> for Client {
+        fn sign_done(
+            &self,
+            result: Result<(), ReturnCode>,
+            message: &'static mut [u8],
+            signature: &'static mut [u8],
+        ) {
+            self.signed.set(result.is_ok());
+            self.message.replace(message);
+            self.signature.replace(signature);
+        }
+    }
+
+    impl ClientVerify<'static> for Client {
+        fn verify_done(
+            &self,
+            result: Result<bool, ReturnCode>,
+            message: &'static mut [u8],
+            signature: &'static mut [u8],
+        ) {
+            self.verified.set(Some(result));
+            self.message.replace(message);
+            self.signature.replace(signature);
+        }
+    }
+
+    impl ClientKeyGenerate<'static> for Client {
+        fn generate_done(
+            &self,
+            result: Result<(), ReturnCode>,
+            private_key: &'static mut [u8],
+            public_key: &'static mut [u8],
+        ) {
+            self.generated.set(result.is_ok());
+            self.private_key.replace(private_key);
+            self.public_key.replace(public_key);
+        }
+    }
+
+    fn buffer(len: usize) -> &'static mut [u8] {
+        Box::leak(std::vec![0; len].into_boxed_slice())
+    }
+
+    /// Generates a key pair from a predictable generator, signs a message
+    /// with it and verifies the signature, through the deferred calls.
+    fn round_trip<S: SignatureAlgorithm + 'static>() {
+        let deferred_caller: &'static DynamicDeferredCall =
+            Box::leak(Box::new(DynamicDeferredCall::new(Box::leak(Box::new(
+                <[DynamicDeferredCallClientState; 1]>::default(),
+            )))));
+        let engine: &'static PublicKeySoftware<S> =
+            Box::leak(Box::new(PublicKeySoftware::new(deferred_caller)));
+        let handle = deferred_caller.register(engine).unwrap();
+        engine.initialize_callback_handle(handle);
+        let generator: &'static Rng = Box::leak(Box::new(Rng { next: Cell::new(7) }));
+        engine.set_rng(generator);
+        let client: &'static Client = Box::leak(Box::new(Client::new()));
+        engine.set_sign_client(client);
+        engine.set_verify_client(client);
+        engine.set_key_generate_client(client);
+
+        // Key generation completes once enough randomness was delivered
+        assert!(engine
+            .generate(buffer(S::PRIVATE_KEY_LEN), buffer(S::PUBLIC_KEY_LEN))
+            .is_ok());
+        let mut more = rng::Continue::More;
+        while more == rng::Continue::More {
+            let word = generator.next.get();
+            generator
+                .next
+                .set(word.wrapping_mul(1103515245).wrapping_add(12345));
+            more = rng::Client::randomness_available(
+                engine,
+                &mut core::iter::once(word),
+                ReturnCode::SUCCESS,
+            );
+        }
+        assert!(client.generated.get());
+        let private_key = client.private_key.take().unwrap();
+        let public_key = client.public_key.take().unwrap();
+        let mut expected = [0; MAX_PUBLIC_KEY_LEN];
+        S::public_key(private_key, &mut expected);
+        assert_eq!(&public_key[..], &expected[..S::PUBLIC_KEY_LEN]);
+
+        // Sign part of a buffer; the callback comes from the deferred call
+        let message = buffer(16);
+        message[..5].copy_from_slice(b"hello");
+        let mut lease = LeasableBuffer::new(message);
+        lease.slice(..5);
+        let (res, message, signature) = engine.sign(lease, buffer(S::SIGNATURE_LEN)).unwrap_err();
+        assert_eq!(res, ReturnCode::ERESERVE);
+        assert!(engine.set_private_key(private_key).is_ok());
+        let mut lease = LeasableBuffer::new(message);
+        lease.slice(..5);
+        assert!(engine.sign(lease, signature).is_ok());
+        assert!(!client.signed.get());
+        DynamicDeferredCallClient::call(engine, handle);
+        assert!(client.signed.get());
+        assert_eq!(client.message.map(|m| m.len()), Some(16));
+        let signature = client.signature.map(|s| s.to_vec()).unwrap();
+        assert_eq!(S::verify(public_key, b"hello", &signature), Some(true));
+
+        // Verification of the signature, and of a longer message
+        assert!(engine.set_public_key(public_key).is_ok());
+        for &(len, valid) in [(5, true), (6, false)].iter() {
+            let mut lease = LeasableBuffer::new(client.message.take().unwrap());
+            lease.slice(..len);
+            assert!(engine
+                .verify(lease, client.signature.take().unwrap())
+                .is_ok());
+            DynamicDeferredCallClient::call(engine, handle);
+            assert_eq!(client.verified.take(), Some(Ok(valid)));
+        }
+    }
+
+    #[test]
+    fn p256_round_trip() {
+        round_trip::<EcdsaP256>();
+    }
+
+    #[test]
+    fn ed25519_round_trip() {
+        round_trip::<Ed25519>();
+    }
+}
@@ -0,0 +1,433 @@
+//! Provides userspace access to PWM outputs on a board.
+//!
+//! The board selects which PWM pins are exposed to processes, in which order
+//! and under which name; userspace refers to them by their index in that
+//! array (their channel number), which it can look up from the name. A
+//! process claims a channel by starting it, and keeps exclusive use of it
+//! until it stops the channel or exits. Attempts by other processes to start
+//! or stop a channel that is owned by someone else fail with `EBUSY`. The
+//! output of a channel whose owner exited is stopped on the next command to
+//! the driver.
+//!
+//! Channels are usually `PwmPinUser`s from `virtual_pwm`, so the PWM hardware
+//! can also be shared with kernel users such as the buzzer driver. Note that
+//! `MuxPwm` only runs one pin at a time: starting a second channel on the same
+//! mux is deferred until the first one is stopped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let pwm_pin0 = static_init!(
+//!     capsules::virtual_pwm::PwmPinUser<'static, nrf52::pwm::Pwm>,
+//!     capsules::virtual_pwm::PwmPinUser::new(mux_pwm, nrf5x::pinmux::Pinmux::new(3))
+//! );
+//! pwm_pin0.add_to_mux();
+//!
+//! let pwm_channels = static_init!(
+//!     [(&'static str, &'static dyn kernel::hil::pwm::PwmPin); 1],
+//!     [("led1", pwm_pin0)]
+//! );
+//! let pwm = static_init!(
+//!     capsules::pwm::Pwm<'static>,
+//!     capsules::pwm::Pwm::new(pwm_channels, board_kernel.create_grant(&grant_cap))
+//! );
+//! ```
+//!
+//! Syscall Interface
+//! -----------------
+//!
+//! ### Command
+//!
+//! All PWM operations are synchronous, so this capsule only uses the `command`
+//! and `allow` syscalls.
+//!
+//! #### `command_num`
+//!
+//! - `0`: Return the number of PWM channels on this platform.
+//! - `1`: Start (or update) a PWM output and claim the channel.
+//!   - `arg1`: Bits 0-7 are the channel index, the remaining bits are the
+//!     duty cycle, between 0 and the value returned by command `4`.
+//!   - `arg2`: The frequency in hertz, at most the value returned by command
+//!     `3`.
+//!   - Return: `SUCCESS` if the output was started, `EINVAL` if the channel,
+//!     frequency or duty cycle is out of range, `EBUSY` if another process
+//!     owns the channel.
+//! - `2`: Stop a PWM output and release the channel.
+//!   - `arg1`: The channel index.
+//!   - Return: `SUCCESS` if the output was stopped, `EINVAL` if the channel
+//!     is invalid, `EALREADY` if the channel is not running, `EBUSY` if
+//!     another process owns the channel.
+//! - `3`: Return the maximum frequency in hertz a channel supports.
+//!   - `arg1`: The channel index.
+//! - `4`: Return the value corresponding to a 100% duty cycle on a channel.
+//!   - `arg1`: The channel index.
+//! - `5`: Return the index of the channel whose name is in the buffer shared
+//!   with `allow` `0`.
+//!   - `arg1`: The length of the name.
+//!   - Return: `EINVAL` if no buffer is shared, it is shorter than `arg1`, or
+//!     no channel has that name.
+//!
+//! ### Allow
+//!
+//! - `0`: A buffer holding the name of a channel to look up with command `5`.
+
+use core::cell::Cell;
+use kernel::hil;
+use kernel::{AppId, AppSlice, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Pwm as usize;
+
+/// The largest number of channels a board can expose; ownership is tracked in
+/// a per-process bitmask.
+pub const MAX_CHANNELS: usize = 32;
+
+#[derive(Default)]
+pub struct App {
+    // Bitmask of the channels this process owns.
+    channels: u32,
+    // Name of the channel to look up.
+    name: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct Pwm<'a> {
+    channels: &'a [(&'static str, &'a dyn hil::pwm::PwmPin)],
+    apps: Grant<App>,
+    // Bitmask of the channels started through this driver and not stopped
+    // since, whether or not their owner still exists.
+    running: Cell<u32>,
+}
+
+impl<'a> Pwm<'a> {
+    pub fn new(
+        channels: &'a [(&'static str, &'a dyn hil::pwm::PwmPin)],
+        grant: Grant<App>,
+    ) -> Pwm<'a> {
+        assert!(channels.len() <= MAX_CHANNELS);
+        Pwm {
+            channels: channels,
+            apps: grant,
+            running: Cell::new(0),
+        }
+    }
+
+    // Find the process which currently owns `channel`, if any. Channels owned
+    // by processes that have since exited are free, as their grant is gone.
+    fn owner(&self, channel: usize) -> Option<AppId> {
+        self.apps.iter().find_map(|appiter| {
+            appiter.enter(|app, _| {
+                if app.channels & (1 << channel) != 0 {
+                    Some(app.appid())
+                } else {
+                    None
+                }
+            })
+        })
+    }
+
+    // Stop the outputs that are still running although the process that
+    // started them exited, so they are not left running for nobody and are
+    // stopped before another process claims them.
+    fn stop_abandoned(&self) {
+        let owned = self
+            .apps
+            .iter()
+            .fold(0, |owned, app| owned | app.enter(|app, _| app.channels));
+        let abandoned = self.running.get() & !owned;
+        if abandoned == 0 {
+            return;
+        }
+        for (channel, (_, pin)) in self.channels.iter().enumerate() {
+            if abandoned & (1 << channel) != 0 {
+                pin.stop();
+            }
+        }
+        self.running.set(self.running.get() & owned);
+    }
+
+    fn start(
+        &self,
+        channel: usize,
+        frequency_hz: usize,
+        duty_cycle: usize,
+        appid: AppId,
+    ) -> ReturnCode {
+        let pin = match self.channels.get(channel) {
+            Some((_, pin)) => *pin,
+            None => return ReturnCode::EINVAL,
+        };
+        if frequency_hz == 0
+            || frequency_hz > pin.get_maximum_frequency_hz()
+            || duty_cycle > pin.get_maximum_duty_cycle()
+        {
+            return ReturnCode::EINVAL;
+        }
+        if self.owner(channel).map_or(false, |owner| owner != appid) {
+            return ReturnCode::EBUSY;
+        }
+
+        self.apps
+            .enter(appid, |app, _| {
+                let rcode = pin.start(frequency_hz, duty_cycle);
+                if rcode == ReturnCode::SUCCESS {
+                    app.channels |= 1 << channel;
+                    self.running.set(self.running.get() | 1 << channel);
+                }
+                rcode
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn stop(&self, channel: usize, appid: AppId) -> ReturnCode {
+        let pin = match self.channels.get(channel) {
+            Some((_, pin)) => *pin,
+            None => return ReturnCode::EINVAL,
+        };
+        match self.owner(channel) {
+            None => ReturnCode::EALREADY,
+            Some(owner) if owner != appid => ReturnCode::EBUSY,
+            Some(_) => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.channels &= !(1 << channel);
+                    self.running.set(self.running.get() & !(1 << channel));
+                    pin.stop()
+                })
+                .unwrap_or_else(|err| err.into()),
+        }
+    }
+
+    fn find(&self, name_len: usize, appid: AppId) -> ReturnCode {
+        self.apps
+            .enter(appid, |app, _| {
+                app.name
+                    .as_ref()
+                    .and_then(|name| name.as_ref().get(..name_len))
+                    .and_then(|name| {
+                        self.channels
+                            .iter()
+                            .position(|(channel, _)| channel.as_bytes() == name)
+                    })
+                    .map_or(ReturnCode::EINVAL, |index| ReturnCode::SuccessWithValue {
+                        value: index,
+                    })
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+}
+
+impl Driver for Pwm<'_> {
+    /// Share the name of a channel to look up.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: The name of the channel command `5` looks up.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.name = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Control PWM outputs.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Return the number of channels.
+    /// - `1`: Start a channel. `arg1` holds the channel index in bits 0-7 and
+    ///   the duty cycle above, `arg2` the frequency in hertz.
+    /// - `2`: Stop the channel `arg1`.
+    /// - `3`: Return the maximum frequency of channel `arg1`.
+    /// - `4`: Return the maximum duty cycle of channel `arg1`.
+    /// - `5`: Return the index of the channel named by the first `arg1` bytes
+    ///   of the buffer shared with `allow` `0`.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        self.stop_abandoned();
+
+        match command_num {
+            0 => ReturnCode::SuccessWithValue {
+                value: self.channels.len(),
+            },
+
+            1 => self.start(arg1 & 0xFF, arg2, arg1 >> 8, appid),
+
+            2 => self.stop(arg1, appid),
+
+            3 => self
+                .channels
+                .get(arg1)
+                .map_or(ReturnCode::EINVAL, |(_, pin)| {
+                    ReturnCode::SuccessWithValue {
+                        value: pin.get_maximum_frequency_hz(),
+                    }
+                }),
+
+            4 => self
+                .channels
+                .get(arg1)
+                .map_or(ReturnCode::EINVAL, |(_, pin)| {
+                    ReturnCode::SuccessWithValue {
+                        value: pin.get_maximum_duty_cycle(),
+                    }
+                }),
+
+            5 => self.find(arg1, appid),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "process_mock"))]
+mod tests {
+    extern crate std;
+
+    use super::Pwm;
+    use core::cell::Cell;
+    use kernel::hil::pwm::PwmPin;
+    use kernel::mock::MockKernel;
+    use kernel::{Driver, ReturnCode};
+    use std::boxed::Box;
+
+    #[derive(Default)]
+    struct MockPin {
+        running: Cell<bool>,
+        starts: Cell<usize>,
+    }
+
+    impl PwmPin for MockPin {
+        fn start(&self, _frequency_hz: usize, _duty_cycle: usize) -> ReturnCode {
+            self.running.set(true);
+            self.starts.set(self.starts.get() + 1);
+            ReturnCode::SUCCESS
+        }
+
+        fn stop(&self) -> ReturnCode {
+            self.running.set(false);
+            ReturnCode::SUCCESS
+        }
+
+        fn get_maximum_frequency_hz(&self) -> usize {
+            1_000_000
+        }
+
+        fn get_maximum_duty_cycle(&self) -> usize {
+            100
+        }
+    }
+
+    fn setup() -> (
+        &'static MockKernel,
+        &'static Pwm<'static>,
+        &'static [MockPin; 2],
+    ) {
+        let kernel = MockKernel::new(2);
+        let pins: &'static [MockPin; 2] = Box::leak(Box::new(Default::default()));
+        let channels: &'static [(&'static str, &'static dyn PwmPin)] = Box::leak(Box::new([
+            ("led", &pins[0] as &dyn PwmPin),
+            ("buzzer", &pins[1]),
+        ]));
+        let pwm = Box::leak(Box::new(Pwm::new(channels, kernel.create_grant())));
+        (kernel, pwm, pins)
+    }
+
+    // Channel and duty cycle in `arg1` of the start command.
+    fn channel_duty(channel: usize, duty_cycle: usize) -> usize {
+        channel | duty_cycle << 8
+    }
+
+    #[test]
+    fn test_channel_is_owned_until_stopped() {
+        let (kernel, pwm, pins) = setup();
+        let a = kernel.process(0).appid();
+        let b = kernel.process(1).appid();
+
+        assert_eq!(
+            pwm.command(1, channel_duty(0, 50), 1000, a),
+            ReturnCode::SUCCESS
+        );
+        assert!(pins[0].running.get());
+        // The owner can update its channel, nobody else can touch it.
+        assert_eq!(
+            pwm.command(1, channel_duty(0, 20), 1000, a),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(
+            pwm.command(1, channel_duty(0, 50), 1000, b),
+            ReturnCode::EBUSY
+        );
+        assert_eq!(pwm.command(2, 0, 0, b), ReturnCode::EBUSY);
+        assert_eq!(pins[0].starts.get(), 2);
+        // Other channels are still free.
+        assert_eq!(
+            pwm.command(1, channel_duty(1, 50), 1000, b),
+            ReturnCode::SUCCESS
+        );
+
+        assert_eq!(pwm.command(2, 0, 0, a), ReturnCode::SUCCESS);
+        assert!(!pins[0].running.get());
+        assert_eq!(pwm.command(2, 0, 0, a), ReturnCode::EALREADY);
+        assert_eq!(
+            pwm.command(1, channel_duty(0, 50), 1000, b),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn test_channel_of_exited_process_is_stopped() {
+        let (kernel, pwm, pins) = setup();
+        let a = kernel.process(0);
+        let b = kernel.process(1).appid();
+
+        assert_eq!(
+            pwm.command(1, channel_duty(1, 50), 1000, a.appid()),
+            ReturnCode::SUCCESS
+        );
+        a.terminate();
+        assert!(pins[1].running.get());
+
+        // Any command stops the abandoned output, before it can be claimed.
+        assert_eq!(
+            pwm.command(0, 0, 0, b),
+            ReturnCode::SuccessWithValue { value: 2 }
+        );
+        assert!(!pins[1].running.get());
+        assert_eq!(pwm.command(2, 1, 0, b), ReturnCode::EALREADY);
+        assert_eq!(
+            pwm.command(1, channel_duty(1, 50), 1000, b),
+            ReturnCode::SUCCESS
+        );
+        assert!(pins[1].running.get());
+    }
+
+    #[test]
+    fn test_find_channel_by_name() {
+        let (kernel, pwm, _) = setup();
+        let app = kernel.process(0);
+
+        assert_eq!(pwm.command(5, 6, 0, app.appid()), ReturnCode::EINVAL);
+        let name = app.allow_buffer(Box::leak(Box::new(*b"buzzer..")));
+        assert_eq!(pwm.allow(app.appid(), 0, Some(name)), ReturnCode::SUCCESS);
+        assert_eq!(
+            pwm.command(5, 6, 0, app.appid()),
+            ReturnCode::SuccessWithValue { value: 1 }
+        );
+        assert_eq!(pwm.command(5, 3, 0, app.appid()), ReturnCode::EINVAL);
+        assert_eq!(pwm.command(5, 9, 0, app.appid()), ReturnCode::EINVAL);
+    }
+}
@@ -1285,6 +1285,13 @@ impl<S: spi::SpiMasterDevice> radio::RadioConfig for RF233<'_, S> {
         }
     }
 
+    // The ED measurement of the RF233 is not driven yet.
+    fn energy_detect(&self) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
+
+    fn set_energy_detect_client(&self, _client: &'static dyn radio::EnergyDetectClient) {}
+
     fn get_address(&self) -> u16 {
         self.addr.get()
     }
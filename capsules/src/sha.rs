@@ -0,0 +1,931 @@
+//! Software implementation of the SHA-2 hash functions.
+//!
+//! `ShaSoftware` provides the `Digest` interface on chips without a hashing
+//! peripheral. It computes SHA-256, SHA-384, SHA-512 and HMAC-SHA256, and
+//! keeps the asynchronous semantics of a hardware block: data passed to
+//! `add_data()` is hashed from deferred calls, at most `BYTES_PER_CALL` bytes
+//! at a time so that long inputs do not hold the CPU, and the callbacks are
+//! never issued from within the call which started the operation.
+//!
+//! The digest is selected by one of the `set_mode_*()` functions. Without
+//! one, the hash whose output has the length of the digest type `T` is used,
+//! so a `ShaSoftware<[u8; 64]>` computes SHA-512 by default. After
+//! `hash_done()` the engine starts the next hash in the same mode and with
+//! the same key, until `clear_data()` is called.
+//!
+//! The computation only depends on the length of the data, never on its
+//! value or on the key, so it runs in constant time with respect to them.
+//!
+//! `Sha256Context`, `Sha512Context` and `hmac_sha256()` can also be used
+//! directly by capsules which need to hash small amounts of data
+//! synchronously.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sha = static_init!(
+//!     capsules::sha::ShaSoftware<'static, [u8; 32]>,
+//!     capsules::sha::ShaSoftware::new(dynamic_deferred_caller)
+//! );
+//! sha.initialize_callback_handle(dynamic_deferred_caller.register(sha).unwrap());
+//! ```
+
+use core::cell::Cell;
+use core::mem;
+use kernel::common::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::common::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::common::leasable_buffer::LeasableBuffer;
+use kernel::hil::digest;
+use kernel::hil::digest::DigestType;
+use kernel::ReturnCode;
+
+/// Largest number of bytes hashed in a single deferred call.
+pub const BYTES_PER_CALL: usize = 512;
+
+const SHA256_BLOCK_LEN: usize = 64;
+const SHA512_BLOCK_LEN: usize = 128;
+
+const HMAC_IPAD: u8 = 0x36;
+const HMAC_OPAD: u8 = 0x5c;
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SHA384_INIT: [u64; 8] = [
+    0xcbbb9d5dc1059ed8,
+    0x629a292a367cd507,
+    0x9159015a3070dd17,
+    0x152fecd8f70e5939,
+    0x67332667ffc00b31,
+    0x8eb44a8768581511,
+    0xdb0c2e0d64f98fa7,
+    0x47b5481dbefa4fa4,
+];
+
+const SHA512_INIT: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks(4).enumerate() {
+        w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for i in 0..64 {
+        let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+        let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+        let t1 = v[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA256_K[i])
+            .wrapping_add(w[i]);
+        let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+        let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+        let t2 = s0.wrapping_add(maj);
+        v = [
+            t1.wrapping_add(t2),
+            v[0],
+            v[1],
+            v[2],
+            v[3].wrapping_add(t1),
+            v[4],
+            v[5],
+            v[6],
+        ];
+    }
+    for (h, x) in state.iter_mut().zip(v.iter()) {
+        *h = h.wrapping_add(*x);
+    }
+}
+
+fn sha512_compress(state: &mut [u64; 8], block: &[u8]) {
+    let mut w = [0u64; 80];
+    for (i, word) in block.chunks(8).enumerate() {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(word);
+        w[i] = u64::from_be_bytes(bytes);
+    }
+    for i in 16..80 {
+        let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+        let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for i in 0..80 {
+        let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+        let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+        let t1 = v[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(SHA512_K[i])
+            .wrapping_add(w[i]);
+        let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+        let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+        let t2 = s0.wrapping_add(maj);
+        v = [
+            t1.wrapping_add(t2),
+            v[0],
+            v[1],
+            v[2],
+            v[3].wrapping_add(t1),
+            v[4],
+            v[5],
+            v[6],
+        ];
+    }
+    for (h, x) in state.iter_mut().zip(v.iter()) {
+        *h = h.wrapping_add(*x);
+    }
+}
+
+/// A SHA-256 computation in progress.
+pub struct Sha256Context {
+    state: [u32; 8],
+    block: [u8; SHA256_BLOCK_LEN],
+    block_len: usize,
+    /// Length of the data hashed so far, in bytes.
+    len: u64,
+}
+
+impl Sha256Context {
+    pub fn new() -> Sha256Context {
+        Sha256Context {
+            state: SHA256_INIT,
+            block: [0; SHA256_BLOCK_LEN],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u64);
+        if self.block_len > 0 {
+            let n = data.len().min(SHA256_BLOCK_LEN - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len < SHA256_BLOCK_LEN {
+                return;
+            }
+            sha256_compress(&mut self.state, &self.block);
+            self.block_len = 0;
+        }
+        while data.len() >= SHA256_BLOCK_LEN {
+            sha256_compress(&mut self.state, &data[..SHA256_BLOCK_LEN]);
+            data = &data[SHA256_BLOCK_LEN..];
+        }
+        self.block[..data.len()].copy_from_slice(data);
+        self.block_len = data.len();
+    }
+
+    /// Pads the data and writes the first `out.len()` bytes of the hash, at
+    /// most 32, to `out`. The context is cleared afterwards.
+    pub fn finish(&mut self, out: &mut [u8]) {
+        let bits = self.len.wrapping_mul(8);
+        let mut padding = [0; SHA256_BLOCK_LEN];
+        padding[0] = 0x80;
+        let padding_len = if self.block_len < SHA256_BLOCK_LEN - 8 {
+            SHA256_BLOCK_LEN - 8 - self.block_len
+        } else {
+            2 * SHA256_BLOCK_LEN - 8 - self.block_len
+        };
+        self.update(&padding[..padding_len]);
+        self.update(&bits.to_be_bytes());
+
+        for (bytes, word) in out.chunks_mut(4).zip(self.state.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes()[..bytes.len()]);
+        }
+        self.clear();
+    }
+
+    /// Overwrites the state, which depends on the data hashed.
+    pub fn clear(&mut self) {
+        self.state = [0; 8];
+        self.block = [0; SHA256_BLOCK_LEN];
+        self.block_len = 0;
+        self.len = 0;
+    }
+}
+
+/// A SHA-512 or SHA-384 computation in progress.
+pub struct Sha512Context {
+    state: [u64; 8],
+    block: [u8; SHA512_BLOCK_LEN],
+    block_len: usize,
+    /// Length of the data hashed so far, in bytes.
+    len: u128,
+}
+
+impl Sha512Context {
+    pub fn new() -> Sha512Context {
+        Sha512Context::with_state(SHA512_INIT)
+    }
+
+    /// A SHA-384 computation, whose hash is the first 48 bytes written by
+    /// `finish()`.
+    pub fn new384() -> Sha512Context {
+        Sha512Context::with_state(SHA384_INIT)
+    }
+
+    fn with_state(state: [u64; 8]) -> Sha512Context {
+        Sha512Context {
+            state,
+            block: [0; SHA512_BLOCK_LEN],
+            block_len: 0,
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len = self.len.wrapping_add(data.len() as u128);
+        if self.block_len > 0 {
+            let n = data.len().min(SHA512_BLOCK_LEN - self.block_len);
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+            if self.block_len < SHA512_BLOCK_LEN {
+                return;
+            }
+            sha512_compress(&mut self.state, &self.block);
+            self.block_len = 0;
+        }
+        while data.len() >= SHA512_BLOCK_LEN {
+            sha512_compress(&mut self.state, &data[..SHA512_BLOCK_LEN]);
+            data = &data[SHA512_BLOCK_LEN..];
+        }
+        self.block[..data.len()].copy_from_slice(data);
+        self.block_len = data.len();
+    }
+
+    /// Pads the data and writes the first `out.len()` bytes of the hash, at
+    /// most 64, to `out`. The context is cleared afterwards.
+    pub fn finish(&mut self, out: &mut [u8]) {
+        let bits = self.len.wrapping_mul(8);
+        let mut padding = [0; SHA512_BLOCK_LEN];
+        padding[0] = 0x80;
+        let padding_len = if self.block_len < SHA512_BLOCK_LEN - 16 {
+            SHA512_BLOCK_LEN - 16 - self.block_len
+        } else {
+            2 * SHA512_BLOCK_LEN - 16 - self.block_len
+        };
+        self.update(&padding[..padding_len]);
+        self.update(&bits.to_be_bytes());
+
+        for (bytes, word) in out.chunks_mut(8).zip(self.state.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes()[..bytes.len()]);
+        }
+        self.clear();
+    }
+
+    /// Overwrites the state, which depends on the data hashed.
+    pub fn clear(&mut self) {
+        self.state = [0; 8];
+        self.block = [0; SHA512_BLOCK_LEN];
+        self.block_len = 0;
+        self.len = 0;
+    }
+}
+
+/// Computes the HMAC-SHA256 of the concatenation of `parts` with `key`.
+pub fn hmac_sha256(key: &[u8; 32], parts: &[&[u8]]) -> [u8; 32] {
+    let mut pad = [HMAC_IPAD; SHA256_BLOCK_LEN];
+    pad.iter_mut().zip(key.iter()).for_each(|(p, k)| *p ^= k);
+    let mut inner = [0; 32];
+    let mut ctx = Sha256Context::new();
+    ctx.update(&pad);
+    parts.iter().for_each(|part| ctx.update(part));
+    ctx.finish(&mut inner);
+
+    pad.iter_mut().for_each(|p| *p ^= HMAC_IPAD ^ HMAC_OPAD);
+    let mut out = [0; 32];
+    let mut ctx = Sha256Context::new();
+    ctx.update(&pad);
+    ctx.update(&inner);
+    ctx.finish(&mut out);
+    out
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mode {
+    Sha256,
+    Sha384,
+    Sha512,
+    HmacSha256,
+}
+
+impl Mode {
+    fn output_len(self) -> usize {
+        match self {
+            Mode::Sha256 | Mode::HmacSha256 => 32,
+            Mode::Sha384 => 48,
+            Mode::Sha512 => 64,
+        }
+    }
+
+    /// The plain hash with an output of `len` bytes.
+    fn with_output_len(len: usize) -> Option<Mode> {
+        match len {
+            32 => Some(Mode::Sha256),
+            48 => Some(Mode::Sha384),
+            64 => Some(Mode::Sha512),
+            _ => None,
+        }
+    }
+}
+
+enum Context {
+    Sha256(Sha256Context),
+    /// The inner hash of an HMAC, which was started with the padded key.
+    HmacSha256(Sha256Context),
+    Sha512(Sha512Context),
+}
+
+impl Context {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Context::Sha256(ctx) | Context::HmacSha256(ctx) => ctx.update(data),
+            Context::Sha512(ctx) => ctx.update(data),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Context::Sha256(ctx) | Context::HmacSha256(ctx) => ctx.clear(),
+            Context::Sha512(ctx) => ctx.clear(),
+        }
+    }
+}
+
+pub struct ShaSoftware<'a, T: 'static + DigestType> {
+    client: OptionalCell<&'a dyn digest::Client<'a, T>>,
+    mode: Cell<Option<Mode>>,
+    key: Cell<[u8; 32]>,
+    /// The hash in progress, if any data or a digest was requested since
+    /// the last one completed.
+    context: MapCell<Context>,
+    data: MapCell<LeasableBuffer<'static, u8>>,
+    data_index: Cell<usize>,
+    digest: TakeCell<'static, T>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, T: 'static + DigestType> ShaSoftware<'a, T> {
+    pub fn new(deferred_caller: &'a DynamicDeferredCall) -> ShaSoftware<'a, T> {
+        ShaSoftware {
+            client: OptionalCell::empty(),
+            mode: Cell::new(None),
+            key: Cell::new([0; 32]),
+            context: MapCell::empty(),
+            data: MapCell::empty(),
+            data_index: Cell::new(0),
+            digest: TakeCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    fn schedule(&self) {
+        self.handle.map(|handle| self.deferred_caller.set(*handle));
+    }
+
+    fn busy(&self) -> bool {
+        self.data.is_some() || self.digest.is_some()
+    }
+
+    fn set_mode(&self, mode: Mode, key: &[u8; 32]) -> Result<(), ReturnCode> {
+        if self.busy() {
+            return Err(ReturnCode::EBUSY);
+        }
+        if mode.output_len() != mem::size_of::<T>() {
+            return Err(ReturnCode::ENOSUPPORT);
+        }
+        digest::Digest::clear_data(self);
+        self.mode.set(Some(mode));
+        self.key.set(*key);
+        Ok(())
+    }
+
+    /// The key of the HMAC, XORed with `pad` and padded to a block.
+    fn padded_key(&self, pad: u8) -> [u8; SHA256_BLOCK_LEN] {
+        let mut block = [pad; SHA256_BLOCK_LEN];
+        for (b, k) in block.iter_mut().zip(self.key.get().iter()) {
+            *b ^= *k;
+        }
+        block
+    }
+
+    /// Starts a new hash unless one is in progress, in the mode last set or
+    /// else the one matching the length of `T`.
+    fn start(&self) -> Result<(), ReturnCode> {
+        if self.context.is_some() {
+            return Ok(());
+        }
+        let mode = self
+            .mode
+            .get()
+            .or_else(|| Mode::with_output_len(mem::size_of::<T>()))
+            .ok_or(ReturnCode::ENOSUPPORT)?;
+        self.mode.set(Some(mode));
+        self.context.put(match mode {
+            Mode::Sha256 => Context::Sha256(Sha256Context::new()),
+            Mode::HmacSha256 => {
+                let mut inner = Sha256Context::new();
+                inner.update(&self.padded_key(HMAC_IPAD));
+                Context::HmacSha256(inner)
+            }
+            Mode::Sha384 => Context::Sha512(Sha512Context::new384()),
+            Mode::Sha512 => Context::Sha512(Sha512Context::new()),
+        });
+        Ok(())
+    }
+
+    /// Completes the hash in progress into `digest`.
+    fn finish(&self, digest: &mut T) -> Result<(), ReturnCode> {
+        let mut context = self.context.take().ok_or(ReturnCode::ECANCEL)?;
+        let out = digest.as_mut();
+        match &mut context {
+            Context::Sha256(ctx) => ctx.finish(out),
+            Context::HmacSha256(inner) => {
+                let mut inner_hash = [0; 32];
+                inner.finish(&mut inner_hash);
+                let mut outer = Sha256Context::new();
+                outer.update(&self.padded_key(HMAC_OPAD));
+                outer.update(&inner_hash);
+                outer.finish(out);
+            }
+            Context::Sha512(ctx) => ctx.finish(out),
+        }
+        Ok(())
+    }
+
+    /// Hashes the next part of the pending data. Returns the result once it
+    /// has all been hashed.
+    fn hash_data(&self) -> Option<Result<(), ReturnCode>> {
+        self.data.and_then(|data| {
+            let start = self.data_index.get();
+            let end = data.len().min(start + BYTES_PER_CALL);
+            let hashed = self
+                .context
+                .map(|context| context.update(&data[start..end]))
+                .is_some();
+            self.data_index.set(end);
+            if !hashed {
+                Some(Err(ReturnCode::ECANCEL))
+            } else if end == data.len() {
+                Some(Ok(()))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::Digest<'a, T> for ShaSoftware<'a, T> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<'a, T>) {
+        self.client.set(client);
+    }
+
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<usize, (ReturnCode, &'static mut [u8])> {
+        if self.busy() {
+            return Err((ReturnCode::EBUSY, data.take()));
+        }
+        if let Err(e) = self.start() {
+            return Err((e, data.take()));
+        }
+        let len = data.len();
+        self.data_index.set(0);
+        self.data.put(data);
+        self.schedule();
+        Ok(len)
+    }
+
+    fn run(&'a self, digest: &'static mut T) -> Result<(), (ReturnCode, &'static mut T)> {
+        if self.digest.is_some() {
+            return Err((ReturnCode::EBUSY, digest));
+        }
+        if let Err(e) = self.start() {
+            return Err((e, digest));
+        }
+        self.digest.replace(digest);
+        self.schedule();
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.mode.set(None);
+        self.key.set([0; 32]);
+        self.context.map(|context| context.clear());
+        self.context.take();
+    }
+}
+
+impl<'a, T: 'static + DigestType> DynamicDeferredCallClient for ShaSoftware<'a, T> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        if self.data.is_some() {
+            if let Some(result) = self.hash_data() {
+                let data = self.data.take().unwrap().take();
+                if self.digest.is_some() {
+                    self.schedule();
+                }
+                self.client
+                    .map(move |client| client.add_data_done(result, data));
+            } else {
+                self.schedule();
+            }
+        } else {
+            self.digest.take().map(|digest| {
+                let result = self.finish(digest);
+                self.client
+                    .map(move |client| client.hash_done(result, digest));
+            });
+        }
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::HMACSha256 for ShaSoftware<'a, T> {
+    fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::HmacSha256, key)
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::Sha256 for ShaSoftware<'a, T> {
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Sha256, &[0; 32])
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::Sha384 for ShaSoftware<'a, T> {
+    fn set_mode_sha384(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Sha384, &[0; 32])
+    }
+}
+
+impl<'a, T: 'static + DigestType> digest::Sha512 for ShaSoftware<'a, T> {
+    fn set_mode_sha512(&self) -> Result<(), ReturnCode> {
+        self.set_mode(Mode::Sha512, &[0; 32])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::test::hex::hex;
+    use kernel::common::dynamic_deferred_call::DynamicDeferredCallClientState;
+    use kernel::hil::digest::{Digest, HMACSha256, Sha256};
+    use std::boxed::Box;
+
+    const MSG_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    const MSG_896: &[u8] = b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmn\
+        hijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu";
+
+    fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+        let mut ctx = Sha256Context::new();
+        parts.iter().for_each(|part| ctx.update(part));
+        let mut out = [0; 32];
+        ctx.finish(&mut out);
+        out
+    }
+
+    fn sha512(mut ctx: Sha512Context, parts: &[&[u8]], out: &mut [u8]) {
+        parts.iter().for_each(|part| ctx.update(part));
+        ctx.finish(out);
+    }
+
+    // Test vectors from the NIST examples of FIPS 180-2.
+    #[test]
+    fn sha256_vectors() {
+        let million: &[u8] = &[b'a'; 1000];
+        let vectors: [(&[&[u8]], &str); 5] = [
+            (
+                &[],
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                &[b"abc"],
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                &[MSG_448],
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            // Split across block boundaries
+            (
+                &[
+                    &MSG_896[..1],
+                    &MSG_896[1..63],
+                    &MSG_896[63..64],
+                    &MSG_896[64..],
+                ],
+                "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            ),
+            (
+                &[million; 1000],
+                "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            ),
+        ];
+        for (parts, expected) in vectors.iter() {
+            assert_eq!(&sha256(parts)[..], &hex(expected)[..]);
+        }
+    }
+
+    #[test]
+    fn sha384_sha512_vectors() {
+        let million: &[u8] = &[b'a'; 1000];
+        let mut out = [0; 64];
+        sha512(Sha512Context::new384(), &[b"abc"], &mut out[..48]);
+        assert_eq!(
+            &out[..48],
+            &hex("cb00753f45a35e8bb5a03d699ac65007272c32ab0eded163\
+                 1a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7")[..]
+        );
+        sha512(Sha512Context::new384(), &[MSG_896], &mut out[..48]);
+        assert_eq!(
+            &out[..48],
+            &hex("09330c33f71147e83d192fc782cd1b4753111b173b3b05d2\
+                 2fa08086e3b0f712fcc7c71a557e2db966c3e9fa91746039")[..]
+        );
+        sha512(Sha512Context::new(), &[], &mut out);
+        assert_eq!(
+            &out[..],
+            &hex(
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                 47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            )[..]
+        );
+        sha512(Sha512Context::new(), &[b"a", b"bc"], &mut out);
+        assert_eq!(
+            &out[..],
+            &hex(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )[..]
+        );
+        sha512(
+            Sha512Context::new(),
+            &[&MSG_896[..100], &MSG_896[100..]],
+            &mut out,
+        );
+        assert_eq!(
+            &out[..],
+            &hex(
+                "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+                 501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+            )[..]
+        );
+        sha512(Sha512Context::new(), &[million; 1000], &mut out);
+        assert_eq!(
+            &out[..],
+            &hex(
+                "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb\
+                 de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b"
+            )[..]
+        );
+    }
+
+    struct TestClient<T: 'static> {
+        data: TakeCell<'static, [u8]>,
+        digest: TakeCell<'static, T>,
+        added: Cell<usize>,
+        /// Number of `add_data_done()` callbacks before `hash_done()`.
+        added_before_hash: Cell<Option<usize>>,
+    }
+
+    impl<'a, T: DigestType> digest::Client<'a, T> for TestClient<T> {
+        fn add_data_done(&'a self, result: Result<(), ReturnCode>, data: &'static mut [u8]) {
+            assert_eq!(result, Ok(()));
+            self.added.set(self.added.get() + 1);
+            self.data.replace(data);
+        }
+
+        fn hash_done(&'a self, result: Result<(), ReturnCode>, digest: &'static mut T) {
+            assert_eq!(result, Ok(()));
+            self.added_before_hash.set(Some(self.added.get()));
+            self.digest.replace(digest);
+        }
+    }
+
+    fn setup<T: DigestType>() -> (
+        &'static ShaSoftware<'static, T>,
+        &'static TestClient<T>,
+        DeferredCallHandle,
+    ) {
+        let states = Box::leak(Box::new(<[DynamicDeferredCallClientState; 1]>::default()));
+        let deferred_caller = Box::leak(Box::new(DynamicDeferredCall::new(states)));
+        let sha = Box::leak(Box::new(ShaSoftware::new(deferred_caller)));
+        let handle = deferred_caller.register(sha).unwrap();
+        sha.initialize_callback_handle(handle);
+        let client = Box::leak(Box::new(TestClient {
+            data: TakeCell::empty(),
+            digest: TakeCell::empty(),
+            added: Cell::new(0),
+            added_before_hash: Cell::new(None),
+        }));
+        sha.set_client(client);
+        (sha, client, handle)
+    }
+
+    /// Runs deferred calls until the engine is idle, returning how many it
+    /// took.
+    fn run_calls<T: DigestType>(sha: &ShaSoftware<T>, handle: DeferredCallHandle) -> usize {
+        let mut calls = 0;
+        while sha.busy() {
+            sha.call(handle);
+            calls += 1;
+        }
+        calls
+    }
+
+    #[test]
+    fn hmac_split_over_deferred_calls() {
+        let (sha, client, handle) = setup::<[u8; 32]>();
+        let mut key = [0; 32];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = i as u8;
+        }
+        let data = Box::leak(Box::new([0u8; 1000]));
+        for (i, d) in data.iter_mut().enumerate() {
+            *d = (i * 7 + 3) as u8;
+        }
+        let digest = Box::leak(Box::new([0; 32]));
+
+        assert_eq!(sha.set_mode_hmacsha256(&key), Ok(()));
+        let mut first = LeasableBuffer::new(&mut data[..]);
+        first.slice(..600);
+        assert_eq!(sha.add_data(first), Ok(600));
+        // Only one operation at a time
+        let other = Box::leak(Box::new([0u8; 4]));
+        assert_eq!(
+            sha.add_data(LeasableBuffer::new(other)).unwrap_err().0,
+            ReturnCode::EBUSY
+        );
+        assert_eq!(sha.set_mode_sha256(), Err(ReturnCode::EBUSY));
+        assert_eq!(client.added.get(), 0);
+        assert_eq!(run_calls(sha, handle), 2);
+
+        let mut rest = LeasableBuffer::new(client.data.take().unwrap());
+        rest.slice(600..);
+        assert_eq!(sha.add_data(rest), Ok(400));
+        // The data is hashed before the digest is computed
+        assert!(sha.run(digest).is_ok());
+        assert_eq!(run_calls(sha, handle), 2);
+        assert_eq!(client.added_before_hash.get(), Some(2));
+        assert_eq!(
+            &client.digest.take().unwrap()[..],
+            &hex("13f22d9be5636c710a12699e36a0390622d622f876e5aec2ddd33db4862cfd4f")[..]
+        );
+    }
+
+    #[test]
+    fn mode_follows_digest_length() {
+        let (sha, client, handle) = setup::<[u8; 64]>();
+        assert_eq!(sha.set_mode_sha256(), Err(ReturnCode::ENOSUPPORT));
+        let data = Box::leak(Box::new(*b"abc"));
+        let digest = Box::leak(Box::new([0; 64]));
+
+        assert_eq!(sha.add_data(LeasableBuffer::new(data)), Ok(3));
+        assert!(sha.run(digest).is_ok());
+        run_calls(sha, handle);
+        let digest = client.digest.take().unwrap();
+        assert_eq!(
+            &digest[..],
+            &hex(
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+            )[..]
+        );
+
+        // The next hash starts afresh
+        assert!(sha.run(digest).is_ok());
+        run_calls(sha, handle);
+        assert_eq!(&client.digest.take().unwrap()[..4], &hex("cf83e135")[..]);
+    }
+}
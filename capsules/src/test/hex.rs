@@ -0,0 +1,14 @@
+//! Decodes the hex strings test vectors are written in, for the unit tests
+//! run on the host.
+
+extern crate std;
+
+use std::vec::Vec;
+
+/// Decodes a string of hex digit pairs into bytes.
+pub fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
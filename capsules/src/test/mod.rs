@@ -2,6 +2,8 @@ pub mod aes;
 pub mod aes_ccm;
 pub mod alarm;
 pub mod alarm_edge_cases;
+#[cfg(test)]
+pub mod hex;
 pub mod random_alarm;
 pub mod random_timer;
 pub mod rng;
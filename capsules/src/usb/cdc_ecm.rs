@@ -0,0 +1,964 @@
+//! Ethernet Control Model of the Communications Device Class for USB
+//!
+//! This capsule presents the board to the host as a USB Ethernet adapter,
+//! and to the kernel as an Ethernet MAC (`hil::ethernet::Ethernet`), so that
+//! the IPv6 stack of `capsules::net` can run over the USB cable.
+//!
+//! The host sees two interfaces. The communication interface describes the
+//! adapter and notifies the host of the state of the link on an interrupt
+//! endpoint. The data interface has two alternate settings: the first has no
+//! endpoints, and the second the bulk endpoints which carry the frames. The
+//! link is up while the host selects the second alternate setting, which
+//! Linux and macOS do when the network interface is configured. Windows has
+//! no driver for this class.
+//!
+//! Each frame is one bulk transfer, which ends with a short or zero length
+//! packet. Frames are not checksummed on the bus, and frames shorter than
+//! `MIN_FRAME_LEN` are padded with zeros like on a wire. The host sends every
+//! frame to the device, which filters them in software on their destination
+//! address.
+//!
+//! The adapter has two MAC addresses: the one of the host network interface,
+//! which the host reads from the fourth string of the device as 12
+//! hexadecimal digits, and the one of the device, which `mac_address()`
+//! returns. They must differ. The string is not available when the class is
+//! a function of a composite device, so it must be used on its own.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! static STRINGS: &'static [&str; 4] = &[
+//!     "XYZ Corp.",    // Manufacturer
+//!     "Network",      // Product
+//!     "Serial No. 5", // Serial number
+//!     "02A1B2C3D4E5", // Host MAC address
+//! ];
+//!
+//! let ecm = components::usb_cdc_ecm::UsbCdcEcmComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::cdc_ecm::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915, // Nordic Semiconductor
+//!     0x503a, // lowRISC generic FS USB
+//!     STRINGS,
+//!     kernel::hil::ethernet::MacAddress::new([0x02, 0xa1, 0xb2, 0xc3, 0xd4, 0xe6]),
+//! )
+//! .finalize(components::usb_cdc_ecm_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! ecm.enable();
+//! ecm.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::CdcInterfaceDescriptor;
+use super::descriptors::DescriptorBuffer;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::TakeCell;
+use kernel::common::cells::VolatileCell;
+use kernel::hil;
+use kernel::hil::ethernet::{self, MacAddress, HEADER_LEN, MAX_FRAME_LEN, MIN_FRAME_LEN};
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Identifying number for the endpoint of the notifications.
+const ENDPOINT_NOTIFICATION_NUM: usize = 1;
+/// Identifying number for the endpoint when transferring frames from us to
+/// the host.
+const ENDPOINT_IN_NUM: usize = 2;
+/// Identifying number for the endpoint when transferring frames from the
+/// host to us.
+const ENDPOINT_OUT_NUM: usize = 3;
+
+/// The length of the bulk packets.
+const PACKET_LEN: usize = 64;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Max control packet size of the sam4l USB controller.
+pub const MAX_CTRL_PACKET_SIZE_SAM4L: u8 = 8;
+/// Max control packet size of the nrf52840 USB controller.
+pub const MAX_CTRL_PACKET_SIZE_NRF52840: u8 = 64;
+
+/// The length of the buffer frames from the host are received in.
+pub const RX_BUFFER_LEN: usize = MAX_FRAME_LEN;
+
+/// Number of multicast addresses the software filter can hold.
+pub const MULTICAST_FILTER_SIZE: usize = 8;
+
+/// The index of the string with the MAC address of the host.
+const MAC_ADDRESS_STRING: u8 = 4;
+
+const GET_INTERFACE: u8 = 10;
+const SET_INTERFACE: u8 = 11;
+
+/// The interface which carries the frames.
+const DATA_INTERFACE: u16 = 1;
+
+/// Offset of the Ethernet networking functional descriptor in the
+/// configuration: it follows the configuration and interface descriptors,
+/// and the header and union functional descriptors.
+const ETHERNET_DESCRIPTOR_OFFSET: usize = 9 + 9 + 5 + 5;
+const ETHERNET_DESCRIPTOR_LEN: usize = 13;
+
+/// The bit rate of a full speed device, in bits per second.
+const FULL_SPEED_BIT_RATE: u32 = 12_000_000;
+
+const NETWORK_CONNECTION: u8 = 0x00;
+const CONNECTION_SPEED_CHANGE: u8 = 0x2a;
+
+/// The notifications sent when the link goes up, in order.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Notification {
+    None,
+    SpeedChange,
+    Connected,
+}
+
+pub struct CdcEcm<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; 3],
+
+    client: OptionalCell<&'a dyn ethernet::Client>,
+
+    mac_address: Cell<MacAddress>,
+    multicast: Cell<[Option<MacAddress>; MULTICAST_FILTER_SIZE]>,
+    promiscuous: Cell<bool>,
+
+    /// Whether the host selected the alternate setting with the bulk
+    /// endpoints.
+    link_up: Cell<bool>,
+    /// The next notification to send.
+    notification: Cell<Notification>,
+
+    /// The frame being sent, its length, and the length already sent.
+    tx_frame: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_offset: Cell<usize>,
+    /// Whether the transfer must end with a zero length packet.
+    tx_zlp: Cell<bool>,
+
+    /// The frame being received, and its length so far.
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    /// Whether the frame being received does not fit in the buffer.
+    rx_overflow: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CdcEcm<'a, U> {
+    /// `rx_buffer` must be at least `RX_BUFFER_LEN` bytes long.
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 4],
+        mac_address: MacAddress,
+        rx_buffer: &'static mut [u8],
+    ) -> Self {
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptor_buffers(descriptors::DeviceDescriptor {
+                vendor_id: vendor_id,
+                product_id: product_id,
+                manufacturer_string: 1,
+                product_string: 2,
+                serial_number_string: 3,
+                class: 0x2, // Class: CDC
+                max_packet_size_ep0: max_ctrl_packet_size,
+                ..descriptors::DeviceDescriptor::default()
+            });
+
+        CdcEcm {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [
+                Buffer64::default(),
+                Buffer64::default(),
+                Buffer64::default(),
+            ],
+            client: OptionalCell::empty(),
+            mac_address: Cell::new(mac_address),
+            multicast: Cell::new([None; MULTICAST_FILTER_SIZE]),
+            promiscuous: Cell::new(false),
+            link_up: Cell::new(false),
+            notification: Cell::new(Notification::None),
+            tx_frame: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_offset: Cell::new(0),
+            tx_zlp: Cell::new(false),
+            rx_buffer: TakeCell::new(rx_buffer),
+            rx_len: Cell::new(0),
+            rx_overflow: Cell::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    #[inline]
+    fn buffer(&'a self, i: usize) -> &'a [VolatileCell<u8>; 64] {
+        &self.buffers[i - 1].buf
+    }
+
+    /// Brings the link up or down when the host selects an alternate setting
+    /// of the data interface.
+    fn set_link(&self, up: bool) {
+        if self.link_up.get() == up {
+            return;
+        }
+        self.link_up.set(up);
+        self.rx_len.set(0);
+        self.rx_overflow.set(false);
+        if up {
+            self.notification.set(Notification::SpeedChange);
+            self.controller()
+                .endpoint_resume_in(ENDPOINT_NOTIFICATION_NUM);
+        } else {
+            self.notification.set(Notification::None);
+            self.tx_frame.take().map(|frame| {
+                self.client
+                    .map(move |client| client.transmit_done(frame, ReturnCode::FAIL));
+            });
+        }
+        self.client.map(|client| client.link_status_changed(up));
+    }
+
+    /// Whether a frame with the destination address `dst` passes the
+    /// software address filter.
+    fn accepts(&self, dst: MacAddress) -> bool {
+        self.promiscuous.get()
+            || dst == self.mac_address.get()
+            || dst.is_broadcast()
+            || self.multicast.get().iter().any(|entry| *entry == Some(dst))
+    }
+
+    /// Passes the frame received from the host to the client, if it is
+    /// complete and passes the filter.
+    fn frame_end(&self) {
+        let len = self.rx_len.get();
+        self.rx_len.set(0);
+        if self.rx_overflow.replace(false) || len < HEADER_LEN {
+            return;
+        }
+        self.rx_buffer.map(|buffer| {
+            let mut dst = [0; 6];
+            dst.copy_from_slice(&buffer[..6]);
+            if self.accepts(MacAddress::new(dst)) {
+                self.client
+                    .map(|client| client.frame_received(&buffer[..len]));
+            }
+        });
+    }
+}
+
+/// Creates the descriptors of the device. `create_descriptor_buffers`
+/// cannot describe the alternate settings of the data interface, nor the
+/// Ethernet networking functional descriptor, so they are fixed up here.
+fn descriptor_buffers(
+    device_descriptor: descriptors::DeviceDescriptor,
+) -> (descriptors::DeviceBuffer, DescriptorBuffer) {
+    let data_interface = |alternate_setting: u8| InterfaceDescriptor {
+        interface_number: DATA_INTERFACE as u8,
+        alternate_setting,
+        interface_class: 0x0a,    // CDC data
+        interface_subclass: 0x00, // none
+        interface_protocol: 0x00, // none
+        ..InterfaceDescriptor::default()
+    };
+    let interfaces: &mut [InterfaceDescriptor] = &mut [
+        InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x02,    // CDC communication
+            interface_subclass: 0x06, // Ethernet control model (ECM)
+            interface_protocol: 0x00, // none
+            ..InterfaceDescriptor::default()
+        },
+        data_interface(0),
+        data_interface(1),
+    ];
+
+    let cdc_descriptors: &[CdcInterfaceDescriptor] = &[
+        CdcInterfaceDescriptor {
+            subtype: descriptors::CdcInterfaceDescriptorSubType::Header,
+            field1: 0x10, // CDC
+            field2: 0x01, // CDC
+        },
+        CdcInterfaceDescriptor {
+            subtype: descriptors::CdcInterfaceDescriptorSubType::Union,
+            field1: 0x00, // Interface 0
+            field2: 0x01, // Interface 1
+        },
+    ];
+
+    let endpoints: &[&[EndpointDescriptor]] = &[
+        &[EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_NOTIFICATION_NUM,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: 16,
+            interval: 32,
+        }],
+        &[],
+        &[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_LEN as u16,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: PACKET_LEN as u16,
+                interval: 0,
+            },
+        ],
+    ];
+
+    let (device_buffer, mut other_buffer) = descriptors::create_descriptor_buffers(
+        device_descriptor,
+        descriptors::ConfigurationDescriptor {
+            ..descriptors::ConfigurationDescriptor::default()
+        },
+        interfaces,
+        endpoints,
+        None, // No HID descriptor
+        Some(cdc_descriptors),
+    );
+
+    // The two alternate settings belong to the same data interface.
+    other_buffer.buf[4].set(2);
+
+    // Insert the Ethernet networking functional descriptor after the union
+    // functional descriptor.
+    let max_segment_size = (MAX_FRAME_LEN as u16).to_le_bytes();
+    let ethernet_descriptor: [u8; ETHERNET_DESCRIPTOR_LEN] = [
+        ETHERNET_DESCRIPTOR_LEN as u8,
+        descriptors::DescriptorType::CdcInterface as u8,
+        descriptors::CdcInterfaceDescriptorSubType::EthernetNetworking as u8,
+        MAC_ADDRESS_STRING,
+        0, // No statistics
+        0,
+        0,
+        0,
+        max_segment_size[0],
+        max_segment_size[1],
+        0, // No multicast filters: the device filters in software
+        0,
+        0, // No power filters
+    ];
+    let len = other_buffer.len;
+    for i in (ETHERNET_DESCRIPTOR_OFFSET..len).rev() {
+        other_buffer.buf[i + ETHERNET_DESCRIPTOR_LEN].set(other_buffer.buf[i].get());
+    }
+    for (dest, b) in other_buffer.buf[ETHERNET_DESCRIPTOR_OFFSET..]
+        .iter()
+        .zip(ethernet_descriptor.iter())
+    {
+        dest.set(*b);
+    }
+    other_buffer.len = len + ETHERNET_DESCRIPTOR_LEN;
+    let total_length = (other_buffer.len as u16).to_le_bytes();
+    other_buffer.buf[2].set(total_length[0]);
+    other_buffer.buf[3].set(total_length[1]);
+
+    (device_buffer, other_buffer)
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> ethernet::Ethernet<'a> for CdcEcm<'a, U> {
+    fn set_client(&self, client: &'a dyn ethernet::Client) {
+        self.client.set(client);
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address.get()
+    }
+
+    fn set_mac_address(&self, address: MacAddress) -> ReturnCode {
+        if address.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        self.mac_address.set(address);
+        ReturnCode::SUCCESS
+    }
+
+    fn link_up(&self) -> bool {
+        self.link_up.get()
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if !self.link_up.get() {
+            return Err((ReturnCode::EOFF, frame));
+        }
+        if self.tx_frame.is_some() {
+            return Err((ReturnCode::EBUSY, frame));
+        }
+        if len > MAX_FRAME_LEN || len > frame.len() || len < HEADER_LEN {
+            return Err((ReturnCode::ESIZE, frame));
+        }
+        self.tx_frame.replace(frame);
+        self.tx_len.set(len);
+        self.tx_offset.set(0);
+        self.tx_zlp.set(false);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        Ok(())
+    }
+
+    fn multicast_filter_size(&self) -> usize {
+        MULTICAST_FILTER_SIZE
+    }
+
+    fn add_multicast(&self, address: MacAddress) -> ReturnCode {
+        if !address.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        let mut filter = self.multicast.get();
+        if filter.iter().any(|entry| *entry == Some(address)) {
+            return ReturnCode::SUCCESS;
+        }
+        match filter.iter_mut().find(|entry| entry.is_none()) {
+            Some(entry) => {
+                *entry = Some(address);
+                self.multicast.set(filter);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    fn remove_multicast(&self, address: MacAddress) -> ReturnCode {
+        let mut filter = self.multicast.get();
+        match filter.iter_mut().find(|entry| **entry == Some(address)) {
+            Some(entry) => {
+                *entry = None;
+                self.multicast.set(filter);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn set_promiscuous(&self, promiscuous: bool) {
+        self.promiscuous.set(promiscuous);
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CdcEcm<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for the notifications, and the IN and OUT frames.
+        self.controller().endpoint_set_in_buffer(
+            ENDPOINT_NOTIFICATION_NUM,
+            self.buffer(ENDPOINT_NOTIFICATION_NUM),
+        );
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NOTIFICATION_NUM);
+
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, self.buffer(ENDPOINT_IN_NUM));
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, self.buffer(ENDPOINT_OUT_NUM));
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.set_link(false);
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The device handles the selection of the alternate setting of the
+    /// data interface. The class requests which set the packet filters are
+    /// accepted and ignored, since the device filters frames itself.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        if let Recipient::Interface = setup_data.request_type.recipient() {
+            match setup_data.request_type.request_type() {
+                RequestType::Standard
+                    if setup_data.request_code == SET_INTERFACE
+                        && setup_data.index == DATA_INTERFACE =>
+                {
+                    return match setup_data.value {
+                        0 | 1 => {
+                            self.set_link(setup_data.value == 1);
+                            hil::usb::CtrlSetupResult::Ok
+                        }
+                        _ => hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
+                    };
+                }
+                RequestType::Standard if setup_data.request_code == GET_INTERFACE => {
+                    let alternate_setting =
+                        if setup_data.index == DATA_INTERFACE && self.link_up.get() {
+                            1
+                        } else {
+                            0
+                        };
+                    return self.client_ctrl.ctrl_in_data(
+                        endpoint,
+                        &[alternate_setting],
+                        setup_data.length,
+                    );
+                }
+                RequestType::Class => {
+                    if let TransferDirection::DeviceToHost =
+                        setup_data.request_type.transfer_direction()
+                    {
+                        // The device keeps no statistics.
+                        return hil::usb::CtrlSetupResult::ErrGeneric;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle an IN transaction.
+    ///
+    /// This sends the notifications of a new link, and the packets of the
+    /// frame being transmitted.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Interrupt if endpoint == ENDPOINT_NOTIFICATION_NUM => {
+                let packet = self.buffer(ENDPOINT_NOTIFICATION_NUM);
+                let mut notification = [0; 16];
+                notification[0] = 0xa1; // Class request to the interface
+                let len = match self.notification.get() {
+                    Notification::None => return hil::usb::InResult::Delay,
+                    Notification::SpeedChange => {
+                        notification[1] = CONNECTION_SPEED_CHANGE;
+                        notification[6] = 8;
+                        notification[8..12].copy_from_slice(&FULL_SPEED_BIT_RATE.to_le_bytes());
+                        notification[12..16].copy_from_slice(&FULL_SPEED_BIT_RATE.to_le_bytes());
+                        self.notification.set(Notification::Connected);
+                        16
+                    }
+                    Notification::Connected => {
+                        notification[1] = NETWORK_CONNECTION;
+                        notification[2] = 1; // Connected
+                        self.notification.set(Notification::None);
+                        8
+                    }
+                };
+                for (p, b) in packet.iter().zip(notification[..len].iter()) {
+                    p.set(*b);
+                }
+                hil::usb::InResult::Packet(len)
+            }
+            TransferType::Bulk if endpoint == ENDPOINT_IN_NUM => {
+                let packet = self.buffer(ENDPOINT_IN_NUM);
+                self.tx_frame.map_or(hil::usb::InResult::Delay, |frame| {
+                    let len = self.tx_len.get();
+                    let total = cmp::max(len, MIN_FRAME_LEN);
+                    let offset = self.tx_offset.get();
+                    if offset < total {
+                        let to_send = cmp::min(PACKET_LEN, total - offset);
+                        for (i, p) in packet[..to_send].iter().enumerate() {
+                            // Pad short frames with zeros.
+                            p.set(if offset + i < len {
+                                frame[offset + i]
+                            } else {
+                                0
+                            });
+                        }
+                        self.tx_offset.set(offset + to_send);
+                        // A transfer which ends with a full packet needs a
+                        // zero length packet to end.
+                        self.tx_zlp
+                            .set(offset + to_send == total && to_send == PACKET_LEN);
+                        hil::usb::InResult::Packet(to_send)
+                    } else if self.tx_zlp.get() {
+                        self.tx_zlp.set(false);
+                        hil::usb::InResult::Packet(0)
+                    } else {
+                        hil::usb::InResult::Delay
+                    }
+                })
+            }
+            _ => hil::usb::InResult::Delay,
+        }
+    }
+
+    /// Handle an OUT transaction.
+    ///
+    /// This receives the packets of a frame from the host, which ends with a
+    /// short packet.
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        if let TransferType::Bulk = transfer_type {
+            if endpoint == ENDPOINT_OUT_NUM {
+                let packet_bytes = cmp::min(packet_bytes as usize, PACKET_LEN);
+                let packet = self.buffer(ENDPOINT_OUT_NUM);
+                let offset = self.rx_len.get();
+                self.rx_buffer.map(|buffer| {
+                    if offset + packet_bytes <= buffer.len() {
+                        for (b, p) in buffer[offset..offset + packet_bytes]
+                            .iter_mut()
+                            .zip(packet.iter())
+                        {
+                            *b = p.get();
+                        }
+                    } else {
+                        self.rx_overflow.set(true);
+                    }
+                });
+                self.rx_len.set(offset + packet_bytes);
+                if packet_bytes < PACKET_LEN {
+                    self.frame_end();
+                }
+            }
+        }
+        hil::usb::OutResult::Ok
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        match endpoint {
+            ENDPOINT_NOTIFICATION_NUM => {
+                if self.notification.get() != Notification::None {
+                    self.controller()
+                        .endpoint_resume_in(ENDPOINT_NOTIFICATION_NUM);
+                }
+            }
+            ENDPOINT_IN_NUM => {
+                let total = cmp::max(self.tx_len.get(), MIN_FRAME_LEN);
+                if self.tx_offset.get() < total || self.tx_zlp.get() {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                } else {
+                    self.tx_frame.take().map(|frame| {
+                        self.client
+                            .map(move |client| client.transmit_done(frame, ReturnCode::SUCCESS));
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use ethernet::Ethernet as _;
+    use hil::usb::Client as _;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct TestUsb {
+        resumed_in: Cell<[bool; 4]>,
+    }
+
+    impl TestUsb {
+        /// Whether the IN endpoint was resumed since the last call.
+        fn resumed(&self, endpoint: usize) -> bool {
+            let mut resumed_in = self.resumed_in.get();
+            let resumed = resumed_in[endpoint];
+            resumed_in[endpoint] = false;
+            self.resumed_in.set(resumed_in);
+            resumed
+        }
+    }
+
+    impl<'a> hil::usb::UsbController<'a> for TestUsb {
+        fn set_client(&self, _client: &'a dyn hil::usb::Client<'a>) {}
+        fn endpoint_set_ctrl_buffer(&self, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_in_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_out_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+        fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+        fn endpoint_resume_in(&self, endpoint: usize) {
+            let mut resumed_in = self.resumed_in.get();
+            resumed_in[endpoint] = true;
+            self.resumed_in.set(resumed_in);
+        }
+
+        fn endpoint_resume_out(&self, _endpoint: usize) {}
+    }
+
+    #[derive(Default)]
+    struct TestClient {
+        link_up: Cell<Option<bool>>,
+        transmitted: Cell<Option<ReturnCode>>,
+        received: Cell<usize>,
+    }
+
+    impl ethernet::Client for TestClient {
+        fn transmit_done(&self, _frame: &'static mut [u8], result: ReturnCode) {
+            self.transmitted.set(Some(result));
+        }
+
+        fn frame_received(&self, frame: &[u8]) {
+            assert_eq!(frame[12..14], [0x86, 0xdd]);
+            self.received.set(frame.len());
+        }
+
+        fn link_status_changed(&self, up: bool) {
+            self.link_up.set(Some(up));
+        }
+    }
+
+    type TestEcm = CdcEcm<'static, TestUsb>;
+
+    const MAC_ADDRESS: MacAddress = MacAddress::new([0x02, 0, 0, 0, 0, 0x02]);
+
+    fn setup() -> (&'static TestEcm, &'static TestUsb, &'static TestClient) {
+        let usb = Box::leak(Box::new(TestUsb::default()));
+        let ecm = Box::leak(Box::new(CdcEcm::new(
+            &*usb,
+            MAX_CTRL_PACKET_SIZE_SAM4L,
+            0x1915,
+            0x503a,
+            &["Tock", "Network", "0001", "020000000001"],
+            MAC_ADDRESS,
+            Box::leak(Box::new([0; RX_BUFFER_LEN])),
+        )));
+        let client = Box::leak(Box::new(TestClient::default()));
+        ecm.set_client(client);
+        (ecm, usb, client)
+    }
+
+    /// Runs a control request without data, and returns the data the
+    /// device answered.
+    fn control(ecm: &'static TestEcm, setup: [u8; 8]) -> Option<Vec<u8>> {
+        for (p, b) in ecm.client_ctrl.ctrl_buffer.buf.iter().zip(setup.iter()) {
+            p.set(*b);
+        }
+        if let hil::usb::CtrlSetupResult::Ok = ecm.ctrl_setup(0) {
+        } else {
+            return None;
+        }
+
+        let mut data_in = Vec::new();
+        if setup[0] & 0x80 != 0 {
+            while let hil::usb::CtrlInResult::Packet(len, last) = ecm.ctrl_in(0) {
+                data_in.extend(
+                    ecm.client_ctrl.ctrl_buffer.buf[..len]
+                        .iter()
+                        .map(|b| b.get()),
+                );
+                if last {
+                    break;
+                }
+            }
+        }
+        ecm.ctrl_status(0);
+        ecm.ctrl_status_complete(0);
+        Some(data_in)
+    }
+
+    /// Sends the packets on an IN endpoint until it waits, and returns their
+    /// lengths and contents.
+    fn packets_in(
+        ecm: &'static TestEcm,
+        transfer_type: TransferType,
+        endpoint: usize,
+    ) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        while let hil::usb::InResult::Packet(len) = ecm.packet_in(transfer_type, endpoint) {
+            packets.push(
+                ecm.buffer(endpoint)[..len]
+                    .iter()
+                    .map(|b| b.get())
+                    .collect(),
+            );
+            ecm.packet_transmitted(endpoint);
+        }
+        packets
+    }
+
+    fn receive(ecm: &'static TestEcm, frame: &[u8]) {
+        for packet in frame.chunks(PACKET_LEN) {
+            for (p, b) in ecm.buffer(ENDPOINT_OUT_NUM).iter().zip(packet.iter()) {
+                p.set(*b);
+            }
+            ecm.packet_out(TransferType::Bulk, ENDPOINT_OUT_NUM, packet.len() as u32);
+        }
+        if frame.len() % PACKET_LEN == 0 {
+            ecm.packet_out(TransferType::Bulk, ENDPOINT_OUT_NUM, 0);
+        }
+    }
+
+    #[test]
+    fn descriptors() {
+        let (ecm, _, _) = setup();
+        let configuration = control(ecm, [0x80, 6, 0, 2, 0, 0, 0xff, 0]).unwrap();
+        assert_eq!(configuration.len(), 80);
+        // Total length and number of interfaces
+        assert_eq!(configuration[2..5], [80, 0, 2]);
+        // The communication interface, its header and union, and the
+        // Ethernet networking functional descriptor.
+        assert_eq!(configuration[9..18], [9, 4, 0, 0, 1, 2, 6, 0, 0]);
+        assert_eq!(configuration[23..28], [5, 0x24, 6, 0, 1]);
+        assert_eq!(
+            configuration[28..41],
+            [13, 0x24, 0x0f, 4, 0, 0, 0, 0, 0xea, 0x05, 0, 0, 0]
+        );
+        assert_eq!(configuration[41..48], [7, 5, 0x81, 3, 16, 0, 32]);
+        // The two alternate settings of the data interface.
+        assert_eq!(configuration[48..57], [9, 4, 1, 0, 0, 0x0a, 0, 0, 0]);
+        assert_eq!(configuration[57..66], [9, 4, 1, 1, 2, 0x0a, 0, 0, 0]);
+    }
+
+    #[test]
+    fn link() {
+        let (ecm, usb, client) = setup();
+        assert!(!ecm.link_up());
+
+        // SET_INTERFACE to the alternate setting with the endpoints
+        control(ecm, [0x01, 11, 1, 0, 1, 0, 0, 0]).unwrap();
+        assert!(ecm.link_up());
+        assert_eq!(client.link_up.get(), Some(true));
+        assert_eq!(control(ecm, [0x81, 10, 0, 0, 1, 0, 1, 0]).unwrap(), [1]);
+
+        assert!(usb.resumed(ENDPOINT_NOTIFICATION_NUM));
+        let notifications = packets_in(ecm, TransferType::Interrupt, ENDPOINT_NOTIFICATION_NUM);
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[0][..2], [0xa1, 0x2a]);
+        assert_eq!(notifications[1], [0xa1, 0, 1, 0, 0, 0, 0, 0]);
+
+        // SET_ETHERNET_PACKET_FILTER
+        control(ecm, [0x21, 0x43, 0x0e, 0, 0, 0, 0, 0]).unwrap();
+
+        ecm.bus_reset();
+        assert!(!ecm.link_up());
+        assert_eq!(client.link_up.get(), Some(false));
+    }
+
+    #[test]
+    fn transmit() {
+        let (ecm, usb, client) = setup();
+        let frame = Box::leak(Box::new([0x55; 128]));
+        match ecm.transmit(frame, 128) {
+            Err((ReturnCode::EOFF, _)) => {}
+            _ => panic!("transmitted without a link"),
+        }
+
+        control(ecm, [0x01, 11, 1, 0, 1, 0, 0, 0]).unwrap();
+        let frame = Box::leak(Box::new([0x55; 128]));
+        assert!(ecm.transmit(frame, 128).is_ok());
+        assert!(usb.resumed(ENDPOINT_IN_NUM));
+        let packets = packets_in(ecm, TransferType::Bulk, ENDPOINT_IN_NUM);
+        // Two full packets, and a zero length packet to end the transfer.
+        let lengths: Vec<usize> = packets.iter().map(|p| p.len()).collect();
+        assert_eq!(lengths, [64, 64, 0]);
+        assert_eq!(client.transmitted.get(), Some(ReturnCode::SUCCESS));
+
+        // A short frame is padded.
+        let frame = Box::leak(Box::new([0x55; 20]));
+        assert!(ecm.transmit(frame, 20).is_ok());
+        let packets = packets_in(ecm, TransferType::Bulk, ENDPOINT_IN_NUM);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].len(), MIN_FRAME_LEN);
+        assert_eq!(packets[0][19..21], [0x55, 0]);
+    }
+
+    #[test]
+    fn receive_filter() {
+        let (ecm, _, client) = setup();
+        control(ecm, [0x01, 11, 1, 0, 1, 0, 0, 0]).unwrap();
+
+        let mut frame = [0; 128];
+        frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+
+        // To the device
+        frame[..6].copy_from_slice(&MAC_ADDRESS.bytes());
+        receive(ecm, &frame[..100]);
+        assert_eq!(client.received.replace(0), 100);
+
+        // To another host
+        frame[..6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x03]);
+        receive(ecm, &frame);
+        assert_eq!(client.received.replace(0), 0);
+
+        // To a multicast group, once the device joins it
+        let group = MacAddress::new([0x33, 0x33, 0, 0, 0, 1]);
+        frame[..6].copy_from_slice(&group.bytes());
+        receive(ecm, &frame);
+        assert_eq!(client.received.replace(0), 0);
+        assert_eq!(ecm.add_multicast(group), ReturnCode::SUCCESS);
+        receive(ecm, &frame);
+        assert_eq!(client.received.replace(0), 128);
+    }
+}
@@ -0,0 +1,1116 @@
+//! Composite device for USB
+//!
+//! This capsule lets several USB class drivers share one USB controller, so
+//! that a board can, for example, provide a serial console, a drive and a
+//! security key over a single connection.
+//!
+//! Each class driver is given a `CompositeFunction`, which implements
+//! `hil::usb::UsbController` in place of the hardware. The class drivers
+//! therefore need no changes, and keep the endpoint and interface numbers
+//! they use when they are the only driver of the device:
+//!
+//! ```
+//!    CdcAcm      MassStorage     CtapHid
+//!       |             |             |
+//!       v             v             v
+//!  CompositeFunction CompositeFunction CompositeFunction
+//!       \             |             /
+//!        -------------|-------------
+//!                     v
+//!              CompositeDevice
+//!                     |
+//!                     v
+//!               UsbController
+//! ```
+//!
+//! The composite device assigns each function the next free endpoints of
+//! the controller the first time the function uses an endpoint, and the
+//! next free interface numbers when the device is enabled. It then reads
+//! the configuration descriptor of each function, renumbers its interfaces
+//! and endpoints, and combines them into the configuration descriptor of the
+//! device, with an Interface Association Descriptor for each function that
+//! has more than one interface.
+//!
+//! The composite device answers the standard requests for the device itself.
+//! It passes requests for an interface or an endpoint to the function that
+//! owns it, with the number that the function knows, and likewise the
+//! packets of the endpoints of each function.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let composite = components::usb_composite::UsbCompositeComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::composite::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915, // Nordic Semiconductor
+//!     0x503a, // lowRISC generic FS USB
+//!     strings,
+//! )
+//! .finalize(components::usb_composite_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! let cdc_usb = components::usb_composite::CompositeFunctionComponent::new(composite)
+//!     .finalize(components::composite_function_component_helper!(nrf52840::usbd::Usbd));
+//! let msc_usb = components::usb_composite::CompositeFunctionComponent::new(composite)
+//!     .finalize(components::composite_function_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! // Create the class drivers with `cdc_usb` and `msc_usb` as their USB
+//! // controllers.
+//!
+//! composite.enable();
+//! composite.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::DescriptorType;
+use super::descriptors::Recipient;
+use super::descriptors::SetupData;
+use super::descriptors::StandardRequest;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::TakeCell;
+use kernel::common::cells::VolatileCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Platform-specific packet length for the `SAM4L` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_SAM4L: u8 = 8;
+/// Platform-specific packet length for the `nRF52` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_NRF52840: u8 = 64;
+
+/// Length of the buffer for the configuration descriptor of the device,
+/// which must hold the descriptors of all functions.
+pub const CONFIGURATION_BUFFER_LEN: usize = 256;
+
+/// Number of endpoints of the controller, including the control endpoint.
+const N_ENDPOINTS: usize = 8;
+
+/// Number of endpoint numbers a function can use.
+const N_FUNCTION_ENDPOINTS: usize = 16;
+
+/// Length of the header of a configuration descriptor.
+const CONFIGURATION_HEADER_LEN: usize = 9;
+
+/// Length of an Interface Association Descriptor.
+const IAD_LEN: usize = 8;
+const IAD_TYPE: u8 = 0x0b;
+
+/// Subtypes of CDC functional descriptors that name interfaces.
+const CDC_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_UNION: u8 = 0x06;
+
+/// The transfer the control endpoint is carrying out.
+#[derive(Copy, Clone)]
+enum CtrlState {
+    /// A request the composite device handles with its `ClientCtrl`.
+    Device,
+    /// Sending the configuration descriptor, with the given extent
+    /// remaining to send.
+    Configuration(usize, usize),
+    /// A request handled by the function in `ctrl_function`.
+    Function,
+}
+
+/// A USB device made of the functions of several class drivers.
+pub struct CompositeDevice<'a, U: 'a> {
+    /// The USB hardware controller.
+    controller: &'a U,
+
+    /// Handler for the requests to the device itself.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// The functions of the device, in the order of their interfaces.
+    functions: List<'a, CompositeFunction<'a, U>>,
+
+    /// The next endpoint of the controller to assign to a function.
+    next_endpoint: Cell<usize>,
+
+    /// The combined configuration descriptor, built when the device is
+    /// enabled.
+    configuration: TakeCell<'static, [u8]>,
+    configuration_len: Cell<usize>,
+
+    ctrl_state: Cell<CtrlState>,
+    ctrl_function: OptionalCell<&'a CompositeFunction<'a, U>>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CompositeDevice<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        configuration: &'static mut [u8],
+    ) -> Self {
+        // The configuration descriptor is built from the functions, so the
+        // buffers of the `ClientCtrl` only hold the device descriptor.
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    // Miscellaneous device class using Interface
+                    // Association Descriptors
+                    class: 0xef,
+                    subclass: 0x02,
+                    protocol: 0x01,
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                &mut [],
+                &[],
+                None, // No HID descriptor
+                None, // No CDC descriptor array
+            );
+
+        CompositeDevice {
+            controller,
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            functions: List::new(),
+            next_endpoint: Cell::new(1),
+            configuration: TakeCell::new(configuration),
+            configuration_len: Cell::new(0),
+            ctrl_state: Cell::new(CtrlState::Device),
+            ctrl_function: OptionalCell::empty(),
+        }
+    }
+
+    /// Assign the next free endpoint of the controller.
+    fn allocate_endpoint(&self) -> usize {
+        let endpoint = self.next_endpoint.get();
+        if endpoint >= N_ENDPOINTS {
+            panic!("USB composite device has no free endpoint");
+        }
+        self.next_endpoint.set(endpoint + 1);
+        endpoint
+    }
+
+    /// Find the function that owns an endpoint of the controller, and the
+    /// number of the endpoint for the function.
+    fn endpoint_function(&self, endpoint: usize) -> Option<(&'a CompositeFunction<'a, U>, usize)> {
+        self.functions
+            .iter()
+            .find_map(|function| function.local_endpoint(endpoint).map(|e| (function, e)))
+    }
+
+    /// Find the function that owns an interface of the device.
+    fn interface_function(&self, interface: u8) -> Option<&'a CompositeFunction<'a, U>> {
+        self.functions.iter().find(|function| {
+            let first = function.first_interface.get();
+            interface >= first && interface - first < function.interface_count.get()
+        })
+    }
+
+    /// Read the configuration descriptor of a function, with the control
+    /// transfer the host would use.
+    fn read_function_configuration(
+        &self,
+        function: &'a CompositeFunction<'a, U>,
+        buf: &mut [u8],
+    ) -> usize {
+        let (client, ctrl_buffer) = match function.ctrl_client() {
+            Some(parts) => parts,
+            None => return 0,
+        };
+
+        // GET_DESCRIPTOR(Configuration 0) for as much as the function has
+        let setup = [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0xff];
+        for (dest, b) in ctrl_buffer.iter().zip(setup.iter()) {
+            dest.set(*b);
+        }
+        if !matches!(client.ctrl_setup(0), hil::usb::CtrlSetupResult::Ok) {
+            return 0;
+        }
+
+        let mut len = 0;
+        loop {
+            match client.ctrl_in(0) {
+                hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete) => {
+                    if len + packet_bytes > buf.len() {
+                        panic!("USB composite configuration buffer too small");
+                    }
+                    for (dest, b) in buf[len..len + packet_bytes]
+                        .iter_mut()
+                        .zip(ctrl_buffer.iter())
+                    {
+                        *dest = b.get();
+                    }
+                    len += packet_bytes;
+                    if transfer_complete || packet_bytes == 0 {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        client.ctrl_status(0);
+        client.ctrl_status_complete(0);
+
+        // Trust the descriptor about its length over the transfer.
+        if len >= CONFIGURATION_HEADER_LEN {
+            cmp::min(len, u16::from_le_bytes([buf[2], buf[3]]) as usize)
+        } else {
+            0
+        }
+    }
+
+    /// Combine the configuration descriptors of the functions into the
+    /// configuration descriptor of the device, and assign the interfaces.
+    fn build_configuration(&self) {
+        self.configuration.map(|buf| {
+            let mut len = CONFIGURATION_HEADER_LEN;
+            let mut num_interfaces = 0;
+
+            for function in self.functions.iter() {
+                let function_len = self.read_function_configuration(function, &mut buf[len..]);
+                if function_len == 0 {
+                    continue;
+                }
+                let count = buf[len + 4];
+                function.first_interface.set(num_interfaces);
+                function.interface_count.set(count);
+
+                // Replace the header of the configuration with an Interface
+                // Association Descriptor if the function has several
+                // interfaces, which tells the host to give them to the same
+                // driver. Its class is that of the first interface.
+                let body = len + CONFIGURATION_HEADER_LEN..len + function_len;
+                let header_len = if count > 1 {
+                    let mut class = [0; 3];
+                    let mut offset = body.start;
+                    while offset + 1 < body.end && buf[offset] > 0 {
+                        if buf[offset + 1] == DescriptorType::Interface as u8 {
+                            class.copy_from_slice(&buf[offset + 5..offset + 8]);
+                            break;
+                        }
+                        offset += buf[offset] as usize;
+                    }
+                    buf[len..len + IAD_LEN].copy_from_slice(&[
+                        IAD_LEN as u8,
+                        IAD_TYPE,
+                        num_interfaces,
+                        count,
+                        class[0],
+                        class[1],
+                        class[2],
+                        0,
+                    ]);
+                    IAD_LEN
+                } else {
+                    0
+                };
+                let body_len = body.end - body.start;
+                buf.copy_within(body, len + header_len);
+                let start = len + header_len;
+                len = start + body_len;
+
+                // Renumber the interfaces and endpoints of the function.
+                let mut offset = start;
+                while offset + 2 < len && buf[offset] > 2 {
+                    let end = cmp::min(offset + buf[offset] as usize, len);
+                    let descriptor = &mut buf[offset..end];
+                    match descriptor[1] {
+                        t if t == DescriptorType::Interface as u8 => {
+                            descriptor[2] += num_interfaces;
+                        }
+                        t if t == DescriptorType::Endpoint as u8 => {
+                            let address = descriptor[2];
+                            descriptor[2] = (address & 0x80)
+                                | function.global_endpoint(address as usize & 0x0f) as u8;
+                        }
+                        t if t == DescriptorType::CdcInterface as u8 => match descriptor[2] {
+                            CDC_CALL_MANAGEMENT if descriptor.len() > 4 => {
+                                descriptor[4] += num_interfaces;
+                            }
+                            CDC_UNION => {
+                                for interface in descriptor[3..].iter_mut() {
+                                    *interface += num_interfaces;
+                                }
+                            }
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                    offset = end;
+                }
+
+                num_interfaces += count;
+            }
+
+            let header = descriptors::ConfigurationDescriptor {
+                num_interfaces,
+                related_descriptor_length: len - CONFIGURATION_HEADER_LEN,
+                ..descriptors::ConfigurationDescriptor::default()
+            };
+            let cells: [Cell<u8>; CONFIGURATION_HEADER_LEN] = Default::default();
+            descriptors::Descriptor::write_to(&header, &cells);
+            for (dest, b) in buf.iter_mut().zip(cells.iter()) {
+                *dest = b.get();
+            }
+            self.configuration_len.set(len);
+        });
+    }
+
+    /// Pass a control request to a function, with the interface or endpoint
+    /// number that the function knows.
+    fn function_setup(
+        &self,
+        function: &'a CompositeFunction<'a, U>,
+        index: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let (client, ctrl_buffer) = match function.ctrl_client() {
+            Some(parts) => parts,
+            None => return hil::usb::CtrlSetupResult::ErrGeneric,
+        };
+        for (dest, b) in ctrl_buffer
+            .iter()
+            .zip(self.client_ctrl.ctrl_buffer.buf.iter())
+        {
+            dest.set(b.get());
+        }
+        let index = index.to_le_bytes();
+        ctrl_buffer[4].set(index[0]);
+        ctrl_buffer[5].set(index[1]);
+
+        self.ctrl_state.set(CtrlState::Function);
+        self.ctrl_function.set(function);
+        client.ctrl_setup(0)
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for CompositeDevice<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // The functions set up their endpoints, which assigns them endpoints
+        // of the controller.
+        for function in self.functions.iter() {
+            function.client.map(|client| client.enable());
+        }
+
+        self.build_configuration();
+    }
+
+    fn attach(&'a self) {
+        for function in self.functions.iter() {
+            function.client.map(|client| client.attach());
+        }
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        self.ctrl_state.set(CtrlState::Device);
+        self.ctrl_function.clear();
+        for function in self.functions.iter() {
+            function.client.map(|client| client.bus_reset());
+        }
+
+        // A function can change its descriptors on a bus reset, as a DFU
+        // function does when it enters DFU mode.
+        self.build_configuration();
+    }
+
+    /// Handle a Control Setup transaction.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        // A new request ends any request the host did not finish.
+        self.ctrl_state.set(CtrlState::Device);
+        self.ctrl_function.clear();
+
+        let setup_data = match SetupData::get(&self.client_ctrl.ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        match setup_data.request_type.recipient() {
+            Recipient::Interface => {
+                let interface = setup_data.index as u8;
+                self.interface_function(interface).map_or(
+                    hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
+                    |function| {
+                        let index = (setup_data.index & 0xff00)
+                            | (interface - function.first_interface.get()) as u16;
+                        self.function_setup(function, index)
+                    },
+                )
+            }
+            Recipient::Endpoint => {
+                let address = setup_data.index as usize;
+                self.endpoint_function(address & 0x0f).map_or(
+                    hil::usb::CtrlSetupResult::ErrGeneric,
+                    |(function, local)| {
+                        let index = (setup_data.index & 0xff80) | local as u16;
+                        self.function_setup(function, index)
+                    },
+                )
+            }
+            _ => match setup_data.get_standard_request() {
+                Some(StandardRequest::GetDescriptor {
+                    descriptor_type: DescriptorType::Configuration,
+                    descriptor_index,
+                    requested_length,
+                    ..
+                }) => {
+                    if descriptor_index != 0 {
+                        return hil::usb::CtrlSetupResult::ErrInvalidConfigurationIndex;
+                    }
+                    let end = cmp::min(self.configuration_len.get(), requested_length as usize);
+                    self.ctrl_state.set(CtrlState::Configuration(0, end));
+                    hil::usb::CtrlSetupResult::Ok
+                }
+                _ => self.client_ctrl.ctrl_setup(endpoint),
+            },
+        }
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        match self.ctrl_state.get() {
+            CtrlState::Device => self.client_ctrl.ctrl_in(endpoint),
+            CtrlState::Configuration(start, end) => {
+                let ctrl_buffer = &self.client_ctrl.ctrl_buffer.buf;
+                let packet_bytes = cmp::min(ctrl_buffer.len(), end.saturating_sub(start));
+                self.configuration.map(|buf| {
+                    for (dest, b) in ctrl_buffer
+                        .iter()
+                        .zip(buf[start..start + packet_bytes].iter())
+                    {
+                        dest.set(*b);
+                    }
+                });
+                let start = start + packet_bytes;
+                self.ctrl_state.set(CtrlState::Configuration(start, end));
+                hil::usb::CtrlInResult::Packet(packet_bytes, start >= end)
+            }
+            CtrlState::Function => {
+                self.ctrl_function
+                    .map_or(hil::usb::CtrlInResult::Error, |function| {
+                        let result = function
+                            .client
+                            .map_or(hil::usb::CtrlInResult::Error, |client| client.ctrl_in(0));
+                        if let hil::usb::CtrlInResult::Packet(packet_bytes, _) = result {
+                            function.ctrl_buffer.map(|function_buffer| {
+                                for (dest, b) in self
+                                    .client_ctrl
+                                    .ctrl_buffer
+                                    .buf
+                                    .iter()
+                                    .zip(function_buffer[..packet_bytes].iter())
+                                {
+                                    dest.set(b.get());
+                                }
+                            });
+                        }
+                        result
+                    })
+            }
+        }
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        match self.ctrl_state.get() {
+            CtrlState::Function => {
+                self.ctrl_function
+                    .map_or(hil::usb::CtrlOutResult::Halted, |function| {
+                        function.ctrl_buffer.map(|function_buffer| {
+                            for (dest, b) in function_buffer.iter().zip(
+                                self.client_ctrl.ctrl_buffer.buf[..packet_bytes as usize].iter(),
+                            ) {
+                                dest.set(b.get());
+                            }
+                        });
+                        function
+                            .client
+                            .map_or(hil::usb::CtrlOutResult::Halted, |client| {
+                                client.ctrl_out(0, packet_bytes)
+                            })
+                    })
+            }
+            _ => self.client_ctrl.ctrl_out(endpoint, packet_bytes),
+        }
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        match self.ctrl_state.get() {
+            CtrlState::Function => {
+                self.ctrl_function.map(|function| {
+                    function.client.map(|client| client.ctrl_status(0));
+                });
+            }
+            _ => self.client_ctrl.ctrl_status(endpoint),
+        }
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        match self.ctrl_state.get() {
+            CtrlState::Function => {
+                self.ctrl_function.take().map(|function| {
+                    function.client.map(|client| client.ctrl_status_complete(0));
+                });
+            }
+            _ => self.client_ctrl.ctrl_status_complete(endpoint),
+        }
+        self.ctrl_state.set(CtrlState::Device);
+    }
+
+    /// Handle a Bulk/Interrupt IN transaction.
+    fn packet_in(&'a self, transfer_type: TransferType, endpoint: usize) -> hil::usb::InResult {
+        self.endpoint_function(endpoint)
+            .map_or(hil::usb::InResult::Error, |(function, local)| {
+                function.client.map_or(hil::usb::InResult::Error, |client| {
+                    client.packet_in(transfer_type, local)
+                })
+            })
+    }
+
+    /// Handle a Bulk/Interrupt OUT transaction.
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        self.endpoint_function(endpoint)
+            .map_or(hil::usb::OutResult::Error, |(function, local)| {
+                function
+                    .client
+                    .map_or(hil::usb::OutResult::Error, |client| {
+                        client.packet_out(transfer_type, local, packet_bytes)
+                    })
+            })
+    }
+
+    fn packet_transmitted(&'a self, endpoint: usize) {
+        self.endpoint_function(endpoint).map(|(function, local)| {
+            function
+                .client
+                .map(|client| client.packet_transmitted(local));
+        });
+    }
+}
+
+/// One function of a composite device, which is the USB controller of the
+/// class driver of the function.
+pub struct CompositeFunction<'a, U: 'a> {
+    device: &'a CompositeDevice<'a, U>,
+    client: OptionalCell<&'a dyn hil::usb::Client<'a>>,
+
+    /// The control buffer of the class driver. The composite device copies
+    /// the requests for the function into it.
+    ctrl_buffer: OptionalCell<&'a [VolatileCell<u8>]>,
+
+    /// The endpoint of the controller for each endpoint number of the
+    /// function, or 0 if it has none yet.
+    endpoints: [Cell<usize>; N_FUNCTION_ENDPOINTS],
+
+    first_interface: Cell<u8>,
+    interface_count: Cell<u8>,
+
+    next: ListLink<'a, CompositeFunction<'a, U>>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> CompositeFunction<'a, U> {
+    pub fn new(device: &'a CompositeDevice<'a, U>) -> CompositeFunction<'a, U> {
+        CompositeFunction {
+            device,
+            client: OptionalCell::empty(),
+            ctrl_buffer: OptionalCell::empty(),
+            endpoints: Default::default(),
+            first_interface: Cell::new(0),
+            interface_count: Cell::new(0),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`, and before the device
+    /// is enabled. The interfaces of the functions follow the order in which
+    /// they are set up.
+    pub fn setup(&'a self) {
+        self.device.functions.push_tail(self);
+    }
+
+    /// The class driver of the function and its control buffer, once the
+    /// driver is enabled.
+    fn ctrl_client(&self) -> Option<(&'a dyn hil::usb::Client<'a>, &'a [VolatileCell<u8>])> {
+        self.client
+            .and_then(|client| self.ctrl_buffer.map(|ctrl_buffer| (client, *ctrl_buffer)))
+    }
+
+    /// The endpoint of the controller for an endpoint of the function, which
+    /// is assigned on first use.
+    fn global_endpoint(&self, endpoint: usize) -> usize {
+        let mapping = &self.endpoints[endpoint % N_FUNCTION_ENDPOINTS];
+        if mapping.get() == 0 {
+            mapping.set(self.device.allocate_endpoint());
+        }
+        mapping.get()
+    }
+
+    /// The endpoint of the function for an endpoint of the controller.
+    fn local_endpoint(&self, endpoint: usize) -> Option<usize> {
+        if endpoint == 0 {
+            return None;
+        }
+        self.endpoints.iter().position(|e| e.get() == endpoint)
+    }
+}
+
+impl<'a, U> ListNode<'a, CompositeFunction<'a, U>> for CompositeFunction<'a, U> {
+    fn next(&'a self) -> &'a ListLink<'a, CompositeFunction<'a, U>> {
+        &self.next
+    }
+}
+
+/// The composite device controls the device as a whole: its control
+/// endpoint, its address and whether it is attached. The functions only
+/// control their own endpoints.
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::UsbController<'a> for CompositeFunction<'a, U> {
+    fn set_client(&self, client: &'a dyn hil::usb::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn endpoint_set_ctrl_buffer(&self, buf: &'a [VolatileCell<u8>]) {
+        self.ctrl_buffer.set(buf);
+    }
+
+    fn endpoint_set_in_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.device
+            .controller
+            .endpoint_set_in_buffer(self.global_endpoint(endpoint), buf);
+    }
+
+    fn endpoint_set_out_buffer(&self, endpoint: usize, buf: &'a [VolatileCell<u8>]) {
+        self.device
+            .controller
+            .endpoint_set_out_buffer(self.global_endpoint(endpoint), buf);
+    }
+
+    fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+
+    fn attach(&self) {}
+
+    fn detach(&self) {}
+
+    fn set_address(&self, _addr: u16) {}
+
+    fn enable_address(&self) {}
+
+    fn endpoint_in_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        if endpoint != 0 {
+            self.device
+                .controller
+                .endpoint_in_enable(transfer_type, self.global_endpoint(endpoint));
+        }
+    }
+
+    fn endpoint_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        if endpoint != 0 {
+            self.device
+                .controller
+                .endpoint_out_enable(transfer_type, self.global_endpoint(endpoint));
+        }
+    }
+
+    fn endpoint_in_out_enable(&self, transfer_type: TransferType, endpoint: usize) {
+        if endpoint != 0 {
+            self.device
+                .controller
+                .endpoint_in_out_enable(transfer_type, self.global_endpoint(endpoint));
+        }
+    }
+
+    fn endpoint_resume_in(&self, endpoint: usize) {
+        self.device
+            .controller
+            .endpoint_resume_in(self.global_endpoint(endpoint));
+    }
+
+    fn endpoint_resume_out(&self, endpoint: usize) {
+        self.device
+            .controller
+            .endpoint_resume_out(self.global_endpoint(endpoint));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::usb::descriptors::{
+        Buffer64, CdcInterfaceDescriptor, CdcInterfaceDescriptorSubType, EndpointAddress,
+        EndpointDescriptor, InterfaceDescriptor, TransferDirection,
+    };
+    use hil::usb::Client as _;
+    use hil::usb::UsbController as _;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestUsb {
+        enabled: Cell<u32>,
+        resumed_in: Cell<usize>,
+    }
+
+    impl<'a> hil::usb::UsbController<'a> for TestUsb {
+        fn set_client(&self, _client: &'a dyn hil::usb::Client<'a>) {}
+        fn endpoint_set_ctrl_buffer(&self, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_in_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_out_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+
+        fn endpoint_in_enable(&self, _transfer_type: TransferType, endpoint: usize) {
+            self.enabled.set(self.enabled.get() | 1 << endpoint);
+        }
+
+        fn endpoint_out_enable(&self, _transfer_type: TransferType, endpoint: usize) {
+            self.enabled.set(self.enabled.get() | 1 << endpoint);
+        }
+
+        fn endpoint_in_out_enable(&self, _transfer_type: TransferType, endpoint: usize) {
+            self.enabled.set(self.enabled.get() | 1 << endpoint);
+        }
+
+        fn endpoint_resume_in(&self, endpoint: usize) {
+            self.resumed_in.set(endpoint);
+        }
+
+        fn endpoint_resume_out(&self, _endpoint: usize) {}
+    }
+
+    type Function = CompositeFunction<'static, TestUsb>;
+
+    /// A class driver with the given interfaces and endpoints, which records
+    /// the requests and packets it gets.
+    struct TestFunction {
+        client_ctrl: ClientCtrl<'static, 'static, Function>,
+        buffer: Buffer64,
+        endpoints: &'static [usize],
+        index: Cell<u16>,
+        packet_in: Cell<usize>,
+    }
+
+    impl TestFunction {
+        fn new(
+            usb: &'static Function,
+            interfaces: &mut [InterfaceDescriptor],
+            endpoints: &[&[EndpointDescriptor]],
+            cdc_descriptor: Option<&[CdcInterfaceDescriptor]>,
+            enabled_endpoints: &'static [usize],
+        ) -> &'static TestFunction {
+            let (device_descriptor_buffer, other_descriptor_buffer) =
+                descriptors::create_descriptor_buffers(
+                    descriptors::DeviceDescriptor::default(),
+                    descriptors::ConfigurationDescriptor::default(),
+                    interfaces,
+                    endpoints,
+                    None,
+                    cdc_descriptor,
+                );
+            let function = Box::leak(Box::new(TestFunction {
+                client_ctrl: ClientCtrl::new(
+                    usb,
+                    device_descriptor_buffer,
+                    other_descriptor_buffer,
+                    None,
+                    None,
+                    LANGUAGES,
+                    &[],
+                ),
+                buffer: Buffer64::default(),
+                endpoints: enabled_endpoints,
+                index: Cell::new(0xffff),
+                packet_in: Cell::new(0),
+            }));
+            usb.set_client(function);
+            function
+        }
+    }
+
+    impl hil::usb::Client<'static> for TestFunction {
+        fn enable(&'static self) {
+            self.client_ctrl.enable();
+            for endpoint in self.endpoints {
+                self.client_ctrl
+                    .controller()
+                    .endpoint_set_in_buffer(*endpoint, &self.buffer.buf);
+                self.client_ctrl
+                    .controller()
+                    .endpoint_in_enable(TransferType::Bulk, *endpoint);
+            }
+        }
+
+        fn attach(&'static self) {
+            self.client_ctrl.attach();
+        }
+
+        fn bus_reset(&'static self) {}
+
+        fn ctrl_setup(&'static self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+            SetupData::get(&self.client_ctrl.ctrl_buffer.buf)
+                .map(|setup_data| self.index.set(setup_data.index));
+            self.client_ctrl.ctrl_setup(endpoint)
+        }
+
+        fn ctrl_in(&'static self, endpoint: usize) -> hil::usb::CtrlInResult {
+            self.client_ctrl.ctrl_in(endpoint)
+        }
+
+        fn ctrl_out(&'static self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+            self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+        }
+
+        fn ctrl_status(&'static self, endpoint: usize) {
+            self.client_ctrl.ctrl_status(endpoint)
+        }
+
+        fn ctrl_status_complete(&'static self, endpoint: usize) {
+            self.client_ctrl.ctrl_status_complete(endpoint)
+        }
+
+        fn packet_in(
+            &'static self,
+            _transfer_type: TransferType,
+            endpoint: usize,
+        ) -> hil::usb::InResult {
+            self.packet_in.set(endpoint);
+            hil::usb::InResult::Delay
+        }
+
+        fn packet_out(
+            &'static self,
+            _transfer_type: TransferType,
+            _endpoint: usize,
+            _packet_bytes: u32,
+        ) -> hil::usb::OutResult {
+            hil::usb::OutResult::Ok
+        }
+
+        fn packet_transmitted(&'static self, _endpoint: usize) {}
+    }
+
+    fn endpoint(endpoint: usize, direction: TransferDirection) -> EndpointDescriptor {
+        EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(endpoint, direction),
+            transfer_type: TransferType::Bulk,
+            max_packet_size: 64,
+            interval: 0,
+        }
+    }
+
+    /// A composite device of a serial port like `CdcAcm`, with two interfaces
+    /// and endpoints 2, 3 and 4, and of a function with one interface and
+    /// endpoint 1.
+    fn device() -> (
+        &'static TestUsb,
+        &'static CompositeDevice<'static, TestUsb>,
+        &'static TestFunction,
+        &'static TestFunction,
+    ) {
+        let usb = Box::leak(Box::new(TestUsb {
+            enabled: Cell::new(0),
+            resumed_in: Cell::new(0),
+        }));
+        let configuration = Box::leak(Box::new([0; CONFIGURATION_BUFFER_LEN]));
+        let device = Box::leak(Box::new(CompositeDevice::new(
+            usb,
+            MAX_CTRL_PACKET_SIZE_NRF52840,
+            0x6667,
+            0xabcd,
+            &["", "", ""],
+            configuration,
+        )));
+
+        let serial_usb: &'static Function = Box::leak(Box::new(CompositeFunction::new(device)));
+        serial_usb.setup();
+        let serial = TestFunction::new(
+            serial_usb,
+            &mut [
+                InterfaceDescriptor {
+                    interface_number: 0,
+                    interface_class: 0x02,
+                    interface_subclass: 0x02,
+                    interface_protocol: 0x01,
+                    ..InterfaceDescriptor::default()
+                },
+                InterfaceDescriptor {
+                    interface_number: 1,
+                    interface_class: 0x0a,
+                    interface_subclass: 0,
+                    ..InterfaceDescriptor::default()
+                },
+            ],
+            &[
+                &[endpoint(4, TransferDirection::DeviceToHost)],
+                &[
+                    endpoint(2, TransferDirection::DeviceToHost),
+                    endpoint(3, TransferDirection::HostToDevice),
+                ],
+            ],
+            Some(&[
+                CdcInterfaceDescriptor {
+                    subtype: CdcInterfaceDescriptorSubType::CallManagement,
+                    field1: 0x00,
+                    field2: 0x01,
+                },
+                CdcInterfaceDescriptor {
+                    subtype: CdcInterfaceDescriptorSubType::Union,
+                    field1: 0x00,
+                    field2: 0x01,
+                },
+            ]),
+            &[2, 3],
+        );
+
+        let other_usb: &'static Function = Box::leak(Box::new(CompositeFunction::new(device)));
+        other_usb.setup();
+        let other = TestFunction::new(
+            other_usb,
+            &mut [InterfaceDescriptor::default()],
+            &[&[
+                endpoint(1, TransferDirection::DeviceToHost),
+                endpoint(1, TransferDirection::HostToDevice),
+            ]],
+            None,
+            &[1],
+        );
+
+        device.enable();
+        serial.index.set(0xffff);
+        other.index.set(0xffff);
+        (usb, device, serial, other)
+    }
+
+    fn setup(
+        device: &'static CompositeDevice<'static, TestUsb>,
+        setup: [u8; 8],
+    ) -> hil::usb::CtrlSetupResult {
+        for (dest, b) in device.client_ctrl.ctrl_buffer.buf.iter().zip(setup.iter()) {
+            dest.set(*b);
+        }
+        device.ctrl_setup(0)
+    }
+
+    #[test]
+    fn configuration() {
+        let (usb, device, _, _) = device();
+        // The functions got the endpoints they enabled, in order, after the
+        // control endpoint.
+        assert_eq!(usb.enabled.get(), 0b1111);
+
+        // GET_DESCRIPTOR(Configuration)
+        assert!(matches!(
+            setup(device, [0x80, 0x06, 0x00, 0x02, 0x00, 0x00, 0xff, 0x00]),
+            hil::usb::CtrlSetupResult::Ok
+        ));
+        let mut configuration = Vec::new();
+        loop {
+            match device.ctrl_in(0) {
+                hil::usb::CtrlInResult::Packet(packet_bytes, transfer_complete) => {
+                    configuration.extend(
+                        device.client_ctrl.ctrl_buffer.buf[..packet_bytes]
+                            .iter()
+                            .map(|b| b.get()),
+                    );
+                    if transfer_complete {
+                        break;
+                    }
+                }
+                _ => panic!("no configuration descriptor"),
+            }
+        }
+        device.ctrl_status_complete(0);
+
+        let len = 9 + (8 + 9 + 5 + 5 + 7 + 9 + 7 + 7) + (9 + 7 + 7);
+        assert_eq!(configuration.len(), len);
+        assert_eq!(configuration[..5], [9, 2, len as u8, 0, 3]);
+        // Interface Association Descriptor for the serial port
+        assert_eq!(configuration[9..17], [8, 0x0b, 0, 2, 0x02, 0x02, 0x01, 0]);
+        // Its control interface, the data interface it names and the
+        // endpoints of both.
+        assert_eq!(configuration[17..20], [9, 4, 0]);
+        assert_eq!(configuration[26..31], [5, 0x24, 0x01, 0x00, 1]);
+        assert_eq!(configuration[31..36], [5, 0x24, 0x06, 0, 1]);
+        assert_eq!(configuration[36..39], [7, 5, 0x84]);
+        assert_eq!(configuration[43..46], [9, 4, 1]);
+        assert_eq!(configuration[52..55], [7, 5, 0x81]);
+        assert_eq!(configuration[59..62], [7, 5, 0x02]);
+        // The other function, without an Interface Association Descriptor.
+        assert_eq!(configuration[66..69], [9, 4, 2]);
+        assert_eq!(configuration[75..78], [7, 5, 0x83]);
+        assert_eq!(configuration[82..85], [7, 5, 0x03]);
+    }
+
+    #[test]
+    fn routing() {
+        let (usb, device, serial, other) = device();
+
+        // A class request for interface 2 goes to the other function, as a
+        // request for its interface 0.
+        assert!(matches!(
+            setup(device, [0x21, 0xff, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00]),
+            hil::usb::CtrlSetupResult::Ok
+        ));
+        assert_eq!(other.index.get(), 0);
+        assert_eq!(serial.index.get(), 0xffff);
+        device.ctrl_status(0);
+        device.ctrl_status_complete(0);
+
+        // And a request for interface 1 to the serial port.
+        assert!(matches!(
+            setup(device, [0x21, 0x22, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00]),
+            hil::usb::CtrlSetupResult::Ok
+        ));
+        assert_eq!(serial.index.get(), 1);
+        device.ctrl_status(0);
+        device.ctrl_status_complete(0);
+
+        // There is no interface 3.
+        assert!(matches!(
+            setup(device, [0x21, 0xff, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00]),
+            hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex
+        ));
+
+        // The packets of endpoint 3 of the controller are those of endpoint 1
+        // of the other function, and the other way around.
+        assert!(matches!(
+            device.packet_in(TransferType::Bulk, 3),
+            hil::usb::InResult::Delay
+        ));
+        assert_eq!(other.packet_in.get(), 1);
+        serial.client_ctrl.controller().endpoint_resume_in(2);
+        assert_eq!(usb.resumed_in.get(), 1);
+        assert!(matches!(
+            device.packet_in(TransferType::Bulk, 7),
+            hil::usb::InResult::Error
+        ));
+    }
+}
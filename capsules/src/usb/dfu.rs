@@ -0,0 +1,770 @@
+//! Device Firmware Upgrade class for USB
+//!
+//! This capsule lets a host update the applications or the kernel of the
+//! board over USB with a DFU 1.1 tool such as `dfu-util`.
+//!
+//! The device starts either in runtime mode, in which it only answers the
+//! request to detach, or directly in DFU mode. After a DFU_DETACH, the host
+//! resets the bus, and the device enters DFU mode, in which it presents one
+//! alternate setting of its interface for each `Target`, a region of a
+//! nonvolatile storage:
+//!
+//! - An `Image::Apps` target is the application region of the kernel. The
+//!   image must be a sequence of TBF binaries. The device stops all processes
+//!   before it overwrites their flash, and when the image is complete it
+//!   checks the TBF header of each binary, through the mapping of the region
+//!   in memory, and marks the end of the applications.
+//! - An `Image::Kernel` target is a staging slot, from which a bootloader can
+//!   install a new kernel. The device only checks that the image fits.
+//!
+//! The device writes each block of the download before it answers the next
+//! DFU_GETSTATUS request with the dfuDNLOAD-IDLE state. Uploads are not
+//! supported. If the board provides a reset function, the device resets once
+//! it has reported the end of the manifestation of an image, so that the new
+//! image runs. Otherwise it is manifestation tolerant and returns to the
+//! dfuIDLE state.
+//!
+//! In a composite device, the function only enters DFU mode with the bus
+//! reset if the host tolerates that the other functions remain.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let dfu = components::usb_dfu::UsbDfuComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::dfu::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915, // Nordic Semiconductor
+//!     0x503a, // lowRISC generic FS USB
+//!     strings,
+//!     targets,
+//!     capsules::usb::dfu::Mode::Runtime,
+//!     board_kernel,
+//!     Some(&|| unsafe { cortexm4::scb::reset() }),
+//! )
+//! .finalize(components::usb_dfu_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! dfu.enable();
+//! dfu.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use core::convert::TryInto;
+
+use super::descriptors;
+use super::descriptors::DescriptorBuffer;
+use super::descriptors::DeviceBuffer;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::RequestType;
+use super::descriptors::SetupData;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::Kernel;
+use kernel::ReturnCode;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Platform-specific packet length for the `SAM4L` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_SAM4L: u8 = 8;
+/// Platform-specific packet length for the `nRF52` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_NRF52840: u8 = 64;
+
+/// Largest block of a download, and the length of the buffer of the device.
+pub const TRANSFER_SIZE: usize = 256;
+
+/// Number of targets the device supports.
+pub const MAX_TARGETS: usize = 4;
+
+/// Time the host waits for the bus reset after a DFU_DETACH.
+const DETACH_TIMEOUT_MS: u16 = 1000;
+
+/// Time the host waits before it asks for the status of a write.
+const POLL_TIMEOUT_MS: u32 = 100;
+
+/// DFU class requests
+const DFU_DETACH: u8 = 0;
+const DFU_DNLOAD: u8 = 1;
+const DFU_GETSTATUS: u8 = 3;
+const DFU_CLRSTATUS: u8 = 4;
+const DFU_GETSTATE: u8 = 5;
+const DFU_ABORT: u8 = 6;
+
+/// Standard request to select an alternate setting of an interface.
+const SET_INTERFACE: u8 = 11;
+
+/// DFU functional descriptor
+const FUNCTIONAL_DESCRIPTOR_LEN: usize = 9;
+const FUNCTIONAL_DESCRIPTOR_TYPE: u8 = 0x21;
+const ATTRIBUTE_CAN_DNLOAD: u8 = 1 << 0;
+const ATTRIBUTE_MANIFESTATION_TOLERANT: u8 = 1 << 2;
+const DFU_VERSION: u16 = 0x0110;
+
+/// Length of the beginning of a TBF header, which holds its lengths.
+const TBF_LENGTHS_LEN: usize = 8;
+
+/// The descriptors the device presents.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Mode {
+    /// The device presents an interface which can only detach, and enters DFU
+    /// mode with the bus reset that follows.
+    Runtime,
+    /// The device presents the targets.
+    Dfu,
+}
+
+/// States of the DFU specification.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DnloadSync = 3,
+    DnBusy = 4,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    Error = 10,
+}
+
+/// Status codes of the DFU specification.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Status {
+    Ok = 0x00,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrFirmware = 0x0a,
+    ErrStalledPkt = 0x0f,
+}
+
+/// What a target holds, which decides how the device checks and
+/// manifests an image.
+pub enum Image {
+    /// The application region, which is also mapped at the given slice.
+    Apps(&'static [u8]),
+    /// A staging slot for a kernel.
+    Kernel,
+}
+
+/// A region of a nonvolatile storage to which the host can download an
+/// image.
+pub struct Target<'a> {
+    pub storage: &'a dyn NonvolatileStorage<'static>,
+    pub address: usize,
+    pub length: usize,
+    pub image: Image,
+}
+
+pub struct Dfu<'a, U: 'a, C: ProcessManagementCapability> {
+    /// Handlers of the control endpoint in runtime mode and in DFU mode,
+    /// which hold the descriptors of each mode.
+    runtime_ctrl: ClientCtrl<'a, 'static, U>,
+    dfu_ctrl: ClientCtrl<'a, 'static, U>,
+
+    mode: Cell<Mode>,
+    state: Cell<State>,
+    status: Cell<Status>,
+
+    targets: &'a [Target<'a>],
+    /// The target of the alternate setting the host selected.
+    target: Cell<usize>,
+
+    /// Length of the image written so far.
+    offset: Cell<usize>,
+    /// Length of the block in the buffer.
+    block_len: Cell<usize>,
+    buffer: TakeCell<'static, [u8]>,
+
+    kernel: &'static Kernel,
+    capability: C,
+
+    /// Called once the device has reported the end of the manifestation.
+    reset: Option<&'a (dyn Fn() + 'a)>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, C: ProcessManagementCapability> Dfu<'a, U, C> {
+    /// `strings` are the manufacturer, product and serial number, followed
+    /// by an optional name for each target.
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str],
+        targets: &'a [Target<'a>],
+        mode: Mode,
+        buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        capability: C,
+        reset: Option<&'a (dyn Fn() + 'a)>,
+    ) -> Self {
+        let descriptor_buffers = |protocol, alternate_settings| {
+            descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                protocol,
+                alternate_settings,
+                strings.len(),
+                reset.is_none(),
+            )
+        };
+        let (runtime_device, runtime_other) = descriptor_buffers(1, 1);
+        let (dfu_device, dfu_other) = descriptor_buffers(2, cmp::min(targets.len(), MAX_TARGETS));
+
+        Dfu {
+            runtime_ctrl: ClientCtrl::new(
+                controller,
+                runtime_device,
+                runtime_other,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            dfu_ctrl: ClientCtrl::new(
+                controller, dfu_device, dfu_other, None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES, strings,
+            ),
+            mode: Cell::new(mode),
+            state: Cell::new(match mode {
+                Mode::Runtime => State::AppIdle,
+                Mode::Dfu => State::DfuIdle,
+            }),
+            status: Cell::new(Status::Ok),
+            targets,
+            target: Cell::new(0),
+            offset: Cell::new(0),
+            block_len: Cell::new(0),
+            buffer: TakeCell::new(buffer),
+            kernel,
+            capability,
+            reset,
+        }
+    }
+
+    #[inline]
+    fn client_ctrl(&'a self) -> &'a ClientCtrl<'a, 'static, U> {
+        match self.mode.get() {
+            Mode::Runtime => &self.runtime_ctrl,
+            Mode::Dfu => &self.dfu_ctrl,
+        }
+    }
+
+    #[inline]
+    fn controller(&'a self) -> &'a U {
+        self.client_ctrl().controller()
+    }
+
+    fn error(&self, status: Status) {
+        self.state.set(State::Error);
+        self.status.set(status);
+    }
+
+    /// Switch to the descriptors of DFU mode, which the host reads after the
+    /// bus reset.
+    fn enter_dfu_mode(&'a self) {
+        self.mode.set(Mode::Dfu);
+        self.state.set(State::DfuIdle);
+        self.status.set(Status::Ok);
+        self.controller()
+            .endpoint_set_ctrl_buffer(&self.dfu_ctrl.ctrl_buffer.buf);
+    }
+
+    /// Handle a DFU class request.
+    fn dfu_request(&'a self, endpoint: usize, setup_data: SetupData) -> hil::usb::CtrlSetupResult {
+        let state = self.state.get();
+        match (self.mode.get(), setup_data.request_code) {
+            (Mode::Runtime, DFU_DETACH) => {
+                self.state.set(State::AppDetach);
+                self.client_ctrl().ctrl_setup(endpoint)
+            }
+            (_, DFU_GETSTATUS) => {
+                let poll_timeout = self.update_status().to_le_bytes();
+                let status = [
+                    self.status.get() as u8,
+                    poll_timeout[0],
+                    poll_timeout[1],
+                    poll_timeout[2],
+                    self.state.get() as u8,
+                    0, // No status string
+                ];
+                self.client_ctrl()
+                    .ctrl_in_data(endpoint, &status, setup_data.length)
+            }
+            (_, DFU_GETSTATE) => {
+                self.client_ctrl()
+                    .ctrl_in_data(endpoint, &[state as u8], setup_data.length)
+            }
+            (Mode::Dfu, DFU_DNLOAD) => {
+                if self.start_block(setup_data.length) {
+                    self.client_ctrl().ctrl_setup(endpoint)
+                } else {
+                    hil::usb::CtrlSetupResult::ErrGeneric
+                }
+            }
+            (Mode::Dfu, DFU_CLRSTATUS) if state == State::Error => {
+                self.state.set(State::DfuIdle);
+                self.status.set(Status::Ok);
+                self.client_ctrl().ctrl_setup(endpoint)
+            }
+            (Mode::Dfu, DFU_ABORT)
+                if state == State::DfuIdle
+                    || state == State::DnloadIdle
+                    || state == State::ManifestSync =>
+            {
+                self.state.set(State::DfuIdle);
+                self.client_ctrl().ctrl_setup(endpoint)
+            }
+            (Mode::Dfu, _) => {
+                self.error(Status::ErrStalledPkt);
+                hil::usb::CtrlSetupResult::ErrGeneric
+            }
+            (Mode::Runtime, _) => hil::usb::CtrlSetupResult::ErrGeneric,
+        }
+    }
+
+    /// Prepare to receive a block of `length` bytes of the download, or end
+    /// the download if `length` is zero.
+    fn start_block(&self, length: u16) -> bool {
+        let length = length as usize;
+        match self.state.get() {
+            State::DfuIdle if length > 0 => self.offset.set(0),
+            State::DfuIdle => {
+                self.error(Status::ErrNotDone);
+                return false;
+            }
+            State::DnloadIdle => {}
+            _ => {
+                self.error(Status::ErrStalledPkt);
+                return false;
+            }
+        }
+
+        if length == 0 {
+            self.state.set(State::ManifestSync);
+        } else if length > TRANSFER_SIZE || self.buffer.is_none() {
+            self.error(Status::ErrStalledPkt);
+            return false;
+        } else {
+            self.block_len.set(0);
+            self.state.set(State::DnloadSync);
+        }
+        true
+    }
+
+    /// Write the block in the buffer after the image so far.
+    fn write_block(&self) {
+        let target = match self.targets.get(self.target.get()) {
+            Some(target) => target,
+            None => return self.error(Status::ErrAddress),
+        };
+        let offset = self.offset.get();
+        let length = self.block_len.get();
+        if offset + length > target.length {
+            return self.error(Status::ErrAddress);
+        }
+
+        if offset == 0 {
+            if let Image::Apps(_) = target.image {
+                // Reject anything that does not start like an application
+                // before the applications are stopped and overwritten.
+                let valid = self.buffer.map_or(false, |buffer| {
+                    buffer
+                        .get(..TBF_LENGTHS_LEN)
+                        .and_then(|lengths| lengths.try_into().ok())
+                        .map_or(false, |lengths| {
+                            tock_tbf::parse::parse_tbf_header_lengths(lengths).is_ok()
+                        })
+                });
+                if !valid || length < TBF_LENGTHS_LEN {
+                    return self.error(Status::ErrFile);
+                }
+                self.kernel
+                    .process_each_capability(&self.capability, |process| process.stop());
+            }
+        }
+
+        match self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            target
+                .storage
+                .write(buffer, target.address + offset, length)
+        }) {
+            ReturnCode::SUCCESS => self.state.set(State::DnBusy),
+            _ => self.error(Status::ErrWrite),
+        }
+    }
+
+    /// Advance the state as the host asks for the status, and return how
+    /// long the host should wait before it asks again.
+    fn update_status(&self) -> u32 {
+        match self.state.get() {
+            State::DnloadSync | State::DnBusy => POLL_TIMEOUT_MS,
+            State::ManifestSync => {
+                self.manifest();
+                if self.state.get() == State::Manifest {
+                    POLL_TIMEOUT_MS
+                } else {
+                    0
+                }
+            }
+            State::Manifest => POLL_TIMEOUT_MS,
+            _ => 0,
+        }
+    }
+
+    /// Check the complete image, and for applications mark their end.
+    fn manifest(&self) {
+        let target = match self.targets.get(self.target.get()) {
+            Some(target) => target,
+            None => return self.error(Status::ErrAddress),
+        };
+        let length = self.offset.get();
+
+        if let Image::Apps(flash) = target.image {
+            if !valid_apps(flash, length) {
+                return self.error(Status::ErrFirmware);
+            }
+
+            // Erased flash after the last application ends the list of
+            // applications, so that the kernel does not load the old ones
+            // that follow.
+            if length + TBF_LENGTHS_LEN <= target.length {
+                if let Some(buffer) = self.buffer.take() {
+                    for b in buffer[..TBF_LENGTHS_LEN].iter_mut() {
+                        *b = 0xff;
+                    }
+                    match target
+                        .storage
+                        .write(buffer, target.address + length, TBF_LENGTHS_LEN)
+                    {
+                        ReturnCode::SUCCESS => self.state.set(State::Manifest),
+                        _ => self.error(Status::ErrWrite),
+                    }
+                    return;
+                }
+            }
+        }
+
+        self.manifestation_complete();
+    }
+
+    fn manifestation_complete(&self) {
+        self.offset.set(0);
+        if self.reset.is_some() {
+            self.state.set(State::ManifestWaitReset);
+        } else {
+            self.state.set(State::DfuIdle);
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, C: ProcessManagementCapability> hil::usb::Client<'a>
+    for Dfu<'a, U, C>
+{
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl().enable();
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl().attach();
+    }
+
+    fn bus_reset(&'a self) {
+        match self.state.get() {
+            State::AppDetach => self.enter_dfu_mode(),
+            State::ManifestWaitReset => {
+                self.reset.map(|reset| reset());
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The device handles the DFU class requests, and the selection of the
+    /// target.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = match SetupData::get(&self.client_ctrl().ctrl_buffer.buf) {
+            Some(setup_data) => setup_data,
+            None => return hil::usb::CtrlSetupResult::ErrNoParse,
+        };
+        if let Recipient::Interface = setup_data.request_type.recipient() {
+            match setup_data.request_type.request_type() {
+                RequestType::Class => return self.dfu_request(endpoint, setup_data),
+                RequestType::Standard if setup_data.request_code == SET_INTERFACE => {
+                    let target = setup_data.value as usize;
+                    return match self.mode.get() {
+                        Mode::Runtime if target == 0 => hil::usb::CtrlSetupResult::Ok,
+                        Mode::Dfu if target < cmp::min(self.targets.len(), MAX_TARGETS) => {
+                            self.target.set(target);
+                            hil::usb::CtrlSetupResult::Ok
+                        }
+                        _ => hil::usb::CtrlSetupResult::ErrInvalidInterfaceIndex,
+                    };
+                }
+                _ => {}
+            }
+        }
+        self.client_ctrl().ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl().ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    ///
+    /// The data of a DFU_DNLOAD request is the next block of the image.
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if self.state.get() == State::DnloadSync {
+            let ctrl_buffer = &self.client_ctrl().ctrl_buffer.buf;
+            let packet_bytes = cmp::min(packet_bytes as usize, ctrl_buffer.len());
+            self.buffer.map(|buffer| {
+                let start = self.block_len.get();
+                let end = cmp::min(start + packet_bytes, buffer.len());
+                for (b, packet) in buffer[start..end].iter_mut().zip(ctrl_buffer.iter()) {
+                    *b = packet.get();
+                }
+                self.block_len.set(end);
+            });
+        }
+        self.client_ctrl().ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl().ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl().ctrl_status_complete(endpoint);
+
+        match self.state.get() {
+            // The host sent the whole block.
+            State::DnloadSync => self.write_block(),
+            // The host knows that the manifestation is complete.
+            State::ManifestWaitReset => {
+                self.reset.map(|reset| reset());
+            }
+            _ => {}
+        }
+    }
+
+    fn packet_in(&'a self, _transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        hil::usb::InResult::Error
+    }
+
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        hil::usb::OutResult::Error
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {}
+}
+
+impl<'a, U: hil::usb::UsbController<'a>, C: ProcessManagementCapability>
+    NonvolatileStorageClient<'static> for Dfu<'a, U, C>
+{
+    fn read_done(&self, _buffer: &'static mut [u8], _length: usize) {}
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.buffer.replace(buffer);
+        match self.state.get() {
+            State::DnBusy => {
+                self.offset.set(self.offset.get() + self.block_len.get());
+                self.state.set(State::DnloadIdle);
+            }
+            State::Manifest => self.manifestation_complete(),
+            _ => {}
+        }
+    }
+}
+
+/// Build the descriptors of one mode of the device, with one alternate
+/// setting of the DFU interface for each target.
+fn descriptor_buffers(
+    device_descriptor: descriptors::DeviceDescriptor,
+    protocol: u8,
+    alternate_settings: usize,
+    num_strings: usize,
+    manifestation_tolerant: bool,
+) -> (DeviceBuffer, DescriptorBuffer) {
+    let interface = |alternate_setting: usize| InterfaceDescriptor {
+        interface_number: 0,
+        alternate_setting: alternate_setting as u8,
+        interface_class: 0xfe,    // Application specific
+        interface_subclass: 0x01, // Device firmware upgrade
+        interface_protocol: protocol,
+        // The names of the targets follow the strings of the device.
+        string_index: if protocol == 2 && alternate_setting + 3 < num_strings {
+            (alternate_setting + 4) as u8
+        } else {
+            0
+        },
+        ..InterfaceDescriptor::default()
+    };
+    let mut interfaces = [interface(0), interface(1), interface(2), interface(3)];
+    let endpoints: &[&[EndpointDescriptor]; MAX_TARGETS] = &[&[], &[], &[], &[]];
+
+    let (device_buffer, mut other_buffer) = descriptors::create_descriptor_buffers(
+        device_descriptor,
+        descriptors::ConfigurationDescriptor {
+            ..descriptors::ConfigurationDescriptor::default()
+        },
+        &mut interfaces[..alternate_settings],
+        &endpoints[..alternate_settings],
+        None, // No HID descriptor
+        None, // No CDC descriptor array
+    );
+
+    // The alternate settings belong to a single interface, which the DFU
+    // functional descriptor follows.
+    other_buffer.buf[4].set(1);
+    let attributes = if manifestation_tolerant {
+        ATTRIBUTE_CAN_DNLOAD | ATTRIBUTE_MANIFESTATION_TOLERANT
+    } else {
+        ATTRIBUTE_CAN_DNLOAD
+    };
+    let detach_timeout = DETACH_TIMEOUT_MS.to_le_bytes();
+    let transfer_size = (TRANSFER_SIZE as u16).to_le_bytes();
+    let version = DFU_VERSION.to_le_bytes();
+    let functional_descriptor: [u8; FUNCTIONAL_DESCRIPTOR_LEN] = [
+        FUNCTIONAL_DESCRIPTOR_LEN as u8,
+        FUNCTIONAL_DESCRIPTOR_TYPE,
+        attributes,
+        detach_timeout[0],
+        detach_timeout[1],
+        transfer_size[0],
+        transfer_size[1],
+        version[0],
+        version[1],
+    ];
+    let len = other_buffer.len;
+    for (dest, b) in other_buffer.buf[len..]
+        .iter()
+        .zip(functional_descriptor.iter())
+    {
+        dest.set(*b);
+    }
+    other_buffer.len = len + FUNCTIONAL_DESCRIPTOR_LEN;
+    let total_length = (other_buffer.len as u16).to_le_bytes();
+    other_buffer.buf[2].set(total_length[0]);
+    other_buffer.buf[3].set(total_length[1]);
+
+    (device_buffer, other_buffer)
+}
+
+/// Whether the first `length` bytes of `flash` are a sequence of TBF
+/// binaries with valid headers.
+fn valid_apps(flash: &'static [u8], length: usize) -> bool {
+    let mut offset = 0;
+    while offset < length {
+        let lengths = match flash
+            .get(offset..offset + TBF_LENGTHS_LEN)
+            .and_then(|lengths| lengths.try_into().ok())
+        {
+            Some(lengths) => lengths,
+            None => return false,
+        };
+        let (version, header_length, total_length) =
+            match tock_tbf::parse::parse_tbf_header_lengths(lengths) {
+                Ok(lengths) => lengths,
+                Err(_) => return false,
+            };
+        match flash.get(offset..offset + header_length as usize) {
+            Some(header) => {
+                if tock_tbf::parse::parse_tbf_header(header, version).is_err() {
+                    return false;
+                }
+            }
+            None => return false,
+        }
+        offset += total_length as usize;
+    }
+    offset == length
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// A TBF binary of `length` bytes which only pads, whose header has the
+    /// given checksum.
+    fn padding(length: u32, checksum: Option<u32>) -> Vec<u8> {
+        let mut binary = Vec::new();
+        binary.extend_from_slice(&2u16.to_le_bytes());
+        binary.extend_from_slice(&16u16.to_le_bytes());
+        binary.extend_from_slice(&length.to_le_bytes());
+        binary.extend_from_slice(&0u32.to_le_bytes());
+        let valid = 0x0010_0002 ^ length;
+        binary.extend_from_slice(&checksum.unwrap_or(valid).to_le_bytes());
+        binary.resize(length as usize, 0);
+        binary
+    }
+
+    #[test]
+    fn apps() {
+        let mut image = padding(64, None);
+        image.extend(padding(32, None));
+        image.resize(128, 0xff);
+        let flash: &'static [u8] = Box::leak(image.into_boxed_slice());
+        assert!(valid_apps(flash, 96));
+        assert!(valid_apps(flash, 64));
+        // The image must end with a binary.
+        assert!(!valid_apps(flash, 80));
+        // Erased flash is not an application.
+        assert!(!valid_apps(flash, 128));
+
+        let mut image = padding(64, None);
+        image.extend(padding(32, Some(0)));
+        let flash: &'static [u8] = Box::leak(image.into_boxed_slice());
+        assert!(!valid_apps(flash, 96));
+    }
+
+    #[test]
+    fn descriptors() {
+        let (_, buffer) =
+            descriptor_buffers(descriptors::DeviceDescriptor::default(), 2, 2, 5, false);
+        let bytes: Vec<u8> = buffer.buf[..buffer.len].iter().map(|b| b.get()).collect();
+        assert_eq!(bytes.len(), 9 + 9 + 9 + 9);
+        assert_eq!(bytes[..5], [9, 2, 36, 0, 1]);
+        // Both alternate settings of interface 0, with the names of the
+        // targets.
+        assert_eq!(bytes[9..18], [9, 4, 0, 0, 0, 0xfe, 0x01, 2, 4]);
+        assert_eq!(bytes[18..27], [9, 4, 0, 1, 0, 0xfe, 0x01, 2, 5]);
+        assert_eq!(
+            bytes[27..],
+            [9, 0x21, 0x01, 0xe8, 0x03, 0x00, 0x01, 0x10, 0x01]
+        );
+    }
+}
@@ -0,0 +1,750 @@
+//! Human Interface Device class for USB
+//!
+//! This capsule makes the board an input device of the host, such as a
+//! keyboard, a mouse or a macro pad. The device is described by a report
+//! descriptor, and sends its input reports on an interrupt IN endpoint,
+//! which the host polls every 10 ms.
+//!
+//! `KEYBOARD` and `MOUSE` describe the standard boot keyboard and boot mouse
+//! of the HID specification. Their reports have the same format in the boot
+//! and the report protocols, so BIOSes and operating systems can both use
+//! them:
+//!
+//! - The keyboard report is 8 bytes: the modifier keys (bit 0 is the left
+//!   Control key), a reserved byte and the usage IDs of up to 6 pressed
+//!   keys. The output report is 1 byte, the state of the LEDs (bit 0 is Num
+//!   Lock, bit 1 Caps Lock and bit 2 Scroll Lock).
+//! - The mouse report is 4 bytes: the buttons (bit 0 is the left button),
+//!   and the relative motion along X, Y and of the wheel.
+//!
+//! Other devices provide their own `HidDescriptors`, with a report
+//! descriptor of at most 128 bytes and input reports of at most 64 bytes.
+//! A device which supports the boot interface must send boot reports while
+//! the host selected the boot protocol.
+//!
+//! The device handles the class requests of the HID specification: the host
+//! can read the last input report and the output report, set the output
+//! report, and select the idle rate and the protocol. The device sends a
+//! report each time `send_report()` is called, and never repeats reports:
+//! it only accepts the idle rate of 0 (infinite) that hosts select for
+//! keyboards and mice, and stalls SET_IDLE requests for other rates.
+//! Feature reports and OUT endpoints are not supported.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let (hid, hid_driver) = components::usb_hid::UsbHidComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::hid::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915, // Nordic Semiconductor
+//!     0x503a, // lowRISC generic FS USB
+//!     strings,
+//!     &capsules::usb::hid::KEYBOARD,
+//!     board_kernel,
+//! )
+//! .finalize(components::usb_hid_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! hid.enable();
+//! hid.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::DescriptorType;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::HIDCountryCode;
+use super::descriptors::HIDDescriptor;
+use super::descriptors::HIDSubordinateDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::Recipient;
+use super::descriptors::ReportDescriptor;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::TakeCell;
+use kernel::hil;
+use kernel::hil::usb::TransferType;
+use kernel::hil::usb_hid::{Protocol, ReportClient};
+use kernel::ReturnCode;
+
+/// Identifying number for the endpoint of the input reports.
+const ENDPOINT_NUM: usize = 1;
+
+/// How often the host polls the endpoint, in milliseconds.
+const ENDPOINT_INTERVAL: u8 = 10;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+
+/// Max control packet size of the sam4l USB controller.
+pub const MAX_CTRL_PACKET_SIZE_SAM4L: u8 = 8;
+/// Max control packet size of the nrf52840 USB controller.
+pub const MAX_CTRL_PACKET_SIZE_NRF52840: u8 = 64;
+
+/// The maximum length of the input and output reports.
+pub const MAX_REPORT_LEN: usize = 64;
+
+const REQUEST_GET_REPORT: u8 = 0x01;
+const REQUEST_GET_IDLE: u8 = 0x02;
+const REQUEST_GET_PROTOCOL: u8 = 0x03;
+const REQUEST_SET_REPORT: u8 = 0x09;
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+
+const REPORT_TYPE_INPUT: u8 = 1;
+const REPORT_TYPE_OUTPUT: u8 = 2;
+
+/// The boot interfaces of the HID specification.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BootInterface {
+    None = 0,
+    Keyboard = 1,
+    Mouse = 2,
+}
+
+/// The descriptors of a HID device.
+pub struct HidDescriptors {
+    pub boot_interface: BootInterface,
+    /// The HID descriptor, whose subordinate descriptor is the report
+    /// descriptor.
+    pub hid: HIDDescriptor<'static>,
+    pub report: ReportDescriptor<'static>,
+    /// The length of the input reports.
+    pub input_report_len: usize,
+}
+
+/// The report descriptor of the boot keyboard, from appendix B.1 of the HID
+/// specification.
+pub static KEYBOARD_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute): modifier keys
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant): reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute): LEDs
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant): padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array): pressed keys
+    0xc0, // End Collection
+];
+
+/// The report descriptor of the boot mouse, from appendix B.2 of the HID
+/// specification, with a wheel.
+pub static MOUSE_REPORT_DESCRIPTOR: &'static [u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Buttons)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute): buttons
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant): padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x09, 0x38, //     Usage (Wheel)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x03, //     Report Count (3)
+    0x81, 0x06, //     Input (Data, Variable, Relative): motion
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
+
+/// The boot keyboard.
+pub static KEYBOARD: HidDescriptors = HidDescriptors {
+    boot_interface: BootInterface::Keyboard,
+    hid: HIDDescriptor {
+        hid_class: 0x0111,
+        country_code: HIDCountryCode::NotSupported,
+        sub_descriptors: &[HIDSubordinateDescriptor {
+            typ: DescriptorType::Report,
+            len: KEYBOARD_REPORT_DESCRIPTOR.len() as u16,
+        }],
+    },
+    report: ReportDescriptor {
+        desc: KEYBOARD_REPORT_DESCRIPTOR,
+    },
+    input_report_len: 8,
+};
+
+/// The boot mouse.
+pub static MOUSE: HidDescriptors = HidDescriptors {
+    boot_interface: BootInterface::Mouse,
+    hid: HIDDescriptor {
+        hid_class: 0x0111,
+        country_code: HIDCountryCode::NotSupported,
+        sub_descriptors: &[HIDSubordinateDescriptor {
+            typ: DescriptorType::Report,
+            len: MOUSE_REPORT_DESCRIPTOR.len() as u16,
+        }],
+    },
+    report: ReportDescriptor {
+        desc: MOUSE_REPORT_DESCRIPTOR,
+    },
+    input_report_len: 4,
+};
+
+/// Implementation of the HID class for input devices.
+pub struct Hid<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffer for the IN endpoint.
+    buffer: Buffer64,
+
+    descriptors: &'static HidDescriptors,
+
+    client: OptionalCell<&'a dyn ReportClient>,
+
+    /// The report being sent.
+    report: TakeCell<'static, [u8]>,
+    report_len: Cell<usize>,
+    /// Whether the report is in the endpoint buffer.
+    report_queued: Cell<bool>,
+
+    /// The last input report, which the host can read with GET_REPORT.
+    last_report: Cell<[u8; MAX_REPORT_LEN]>,
+    last_report_len: Cell<usize>,
+
+    /// The output report, which the host sets with SET_REPORT.
+    output: Cell<[u8; MAX_REPORT_LEN]>,
+    output_len: Cell<usize>,
+    /// Whether the control transfer is a SET_REPORT.
+    setting_output: Cell<bool>,
+
+    protocol: Cell<Protocol>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> Hid<'a, U> {
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        descriptors: &'static HidDescriptors,
+    ) -> Self {
+        let boot = descriptors.boot_interface != BootInterface::None;
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x03,                        // HID
+            interface_subclass: if boot { 1 } else { 0 }, // Boot interface
+            interface_protocol: descriptors.boot_interface as u8,
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[EndpointDescriptor {
+            endpoint_address: EndpointAddress::new_const(
+                ENDPOINT_NUM,
+                TransferDirection::DeviceToHost,
+            ),
+            transfer_type: TransferType::Interrupt,
+            max_packet_size: descriptors.input_report_len as u16,
+            interval: ENDPOINT_INTERVAL,
+        }]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                Some(&descriptors.hid),
+                None, // No CDC descriptor array
+            );
+
+        Hid {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                Some(&descriptors.hid),
+                Some(&descriptors.report),
+                LANGUAGES,
+                strings,
+            ),
+            buffer: Buffer64::default(),
+            descriptors,
+            client: OptionalCell::empty(),
+            report: TakeCell::empty(),
+            report_len: Cell::new(0),
+            report_queued: Cell::new(false),
+            last_report: Cell::new([0; MAX_REPORT_LEN]),
+            last_report_len: Cell::new(descriptors.input_report_len),
+            output: Cell::new([0; MAX_REPORT_LEN]),
+            output_len: Cell::new(0),
+            setting_output: Cell::new(false),
+            protocol: Cell::new(Protocol::Report),
+        }
+    }
+
+    #[inline]
+    pub fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    /// Handles a HID class request, or returns `None` to let `ClientCtrl`
+    /// complete it.
+    fn class_request(
+        &'a self,
+        endpoint: usize,
+        setup_data: descriptors::SetupData,
+    ) -> Option<hil::usb::CtrlSetupResult> {
+        let boot = self.descriptors.boot_interface != BootInterface::None;
+        let report_type = (setup_data.value >> 8) as u8;
+        match setup_data.request_code {
+            REQUEST_GET_REPORT => Some(match report_type {
+                REPORT_TYPE_INPUT => {
+                    let report = self.last_report.get();
+                    self.client_ctrl.ctrl_in_data(
+                        endpoint,
+                        &report[..self.last_report_len.get()],
+                        setup_data.length,
+                    )
+                }
+                REPORT_TYPE_OUTPUT => {
+                    let output = self.output.get();
+                    self.client_ctrl.ctrl_in_data(
+                        endpoint,
+                        &output[..self.output_len.get()],
+                        setup_data.length,
+                    )
+                }
+                _ => hil::usb::CtrlSetupResult::ErrGeneric,
+            }),
+            REQUEST_SET_REPORT => {
+                if report_type != REPORT_TYPE_OUTPUT {
+                    return Some(hil::usb::CtrlSetupResult::ErrGeneric);
+                }
+                self.output_len.set(0);
+                self.setting_output.set(true);
+                None
+            }
+            REQUEST_GET_IDLE => Some(self.client_ctrl.ctrl_in_data(
+                endpoint,
+                &[0],
+                setup_data.length,
+            )),
+            // Reports are not repeated, so only an infinite duration is
+            // accepted
+            REQUEST_SET_IDLE if setup_data.value >> 8 == 0 => None,
+            REQUEST_SET_IDLE => Some(hil::usb::CtrlSetupResult::ErrGeneric),
+            REQUEST_GET_PROTOCOL if boot => {
+                let protocol = match self.protocol.get() {
+                    Protocol::Boot => 0,
+                    Protocol::Report => 1,
+                };
+                Some(
+                    self.client_ctrl
+                        .ctrl_in_data(endpoint, &[protocol], setup_data.length),
+                )
+            }
+            REQUEST_SET_PROTOCOL if boot => {
+                self.protocol.set(if setup_data.value == 0 {
+                    Protocol::Boot
+                } else {
+                    Protocol::Report
+                });
+                None
+            }
+            _ => Some(hil::usb::CtrlSetupResult::ErrGeneric),
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb_hid::ReportDevice<'a> for Hid<'a, U> {
+    fn set_client(&self, client: &'a dyn ReportClient) {
+        self.client.set(client);
+    }
+
+    fn send_report(
+        &self,
+        report: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        if self.report.is_some() {
+            return Err((ReturnCode::EBUSY, report));
+        }
+        if len > self.descriptors.input_report_len || len > report.len() {
+            return Err((ReturnCode::ESIZE, report));
+        }
+
+        let mut last_report = [0; MAX_REPORT_LEN];
+        last_report[..len].copy_from_slice(&report[..len]);
+        self.last_report.set(last_report);
+        self.last_report_len.set(len);
+
+        self.report.replace(report);
+        self.report_len.set(len);
+        self.controller().endpoint_resume_in(ENDPOINT_NUM);
+        Ok(())
+    }
+
+    fn protocol(&self) -> Protocol {
+        self.protocol.get()
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for Hid<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup the buffer for the IN endpoint.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_NUM, &self.buffer.buf);
+        self.controller()
+            .endpoint_in_enable(TransferType::Interrupt, ENDPOINT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        // The device starts in the report protocol.
+        self.protocol.set(Protocol::Report);
+        self.setting_output.set(false);
+    }
+
+    /// Handle a Control Setup transaction.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf);
+        if let Some(setup_data) = setup_data {
+            if let (RequestType::Class, Recipient::Interface) = (
+                setup_data.request_type.request_type(),
+                setup_data.request_type.recipient(),
+            ) {
+                if let Some(result) = self.class_request(endpoint, setup_data) {
+                    return result;
+                }
+            }
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction.
+    ///
+    /// This receives the output report of a SET_REPORT request.
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        if self.setting_output.get() {
+            let mut output = self.output.get();
+            let offset = self.output_len.get();
+            let len = cmp::min(packet_bytes as usize, MAX_REPORT_LEN - offset);
+            for (dest, b) in output[offset..offset + len]
+                .iter_mut()
+                .zip(self.client_ctrl.ctrl_buffer.buf.iter())
+            {
+                *dest = b.get();
+            }
+            self.output.set(output);
+            self.output_len.set(offset + len);
+        }
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        if self.setting_output.get() {
+            self.setting_output.set(false);
+            let output = self.output.get();
+            self.client
+                .map(|client| client.output_report(&output[..self.output_len.get()]));
+        }
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle an Interrupt IN transaction.
+    ///
+    /// This sends the report passed to `send_report()` once.
+    fn packet_in(&'a self, transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Interrupt => {
+                if self.report_queued.get() {
+                    return hil::usb::InResult::Delay;
+                }
+                self.report.map_or(hil::usb::InResult::Delay, |report| {
+                    let len = self.report_len.get();
+                    for (p, b) in self.buffer.buf.iter().zip(report[..len].iter()) {
+                        p.set(*b);
+                    }
+                    self.report_queued.set(true);
+                    hil::usb::InResult::Packet(len)
+                })
+            }
+            TransferType::Bulk | TransferType::Control | TransferType::Isochronous => {
+                // Nothing to do for HID.
+                hil::usb::InResult::Delay
+            }
+        }
+    }
+
+    /// Handle an OUT transaction.
+    fn packet_out(
+        &'a self,
+        _transfer_type: TransferType,
+        _endpoint: usize,
+        _packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        // The device has no OUT endpoint.
+        hil::usb::OutResult::Ok
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        self.report_queued.set(false);
+        self.report.take().map(|report| {
+            self.client
+                .map(move |client| client.report_sent(ReturnCode::SUCCESS, report));
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use hil::usb::Client as _;
+    use hil::usb_hid::ReportDevice as _;
+    use kernel::common::cells::VolatileCell;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestUsb {
+        resume_in: Cell<bool>,
+    }
+
+    impl<'a> hil::usb::UsbController<'a> for TestUsb {
+        fn set_client(&self, _client: &'a dyn hil::usb::Client<'a>) {}
+        fn endpoint_set_ctrl_buffer(&self, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_in_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_out_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+        fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+        fn endpoint_resume_in(&self, endpoint: usize) {
+            assert_eq!(endpoint, ENDPOINT_NUM);
+            self.resume_in.set(true);
+        }
+
+        fn endpoint_resume_out(&self, _endpoint: usize) {}
+    }
+
+    #[derive(Default)]
+    struct TestClient {
+        sent: Cell<bool>,
+        output: Cell<Option<u8>>,
+    }
+
+    impl ReportClient for TestClient {
+        fn report_sent(&self, result: ReturnCode, _report: &'static mut [u8]) {
+            assert_eq!(result, ReturnCode::SUCCESS);
+            self.sent.set(true);
+        }
+
+        fn output_report(&self, report: &[u8]) {
+            assert_eq!(report.len(), 1);
+            self.output.set(Some(report[0]));
+        }
+    }
+
+    type TestHid = Hid<'static, TestUsb>;
+
+    fn setup(descriptors: &'static HidDescriptors) -> (&'static TestHid, &'static TestClient) {
+        let usb = Box::leak(Box::new(TestUsb {
+            resume_in: Cell::new(false),
+        }));
+        let hid = Box::leak(Box::new(Hid::new(
+            &*usb,
+            MAX_CTRL_PACKET_SIZE_SAM4L,
+            0x1915,
+            0x503a,
+            &["Tock", "Keyboard", "0001"],
+            descriptors,
+        )));
+        let client = Box::leak(Box::new(TestClient::default()));
+        hid.set_client(client);
+        (hid, client)
+    }
+
+    /// Runs a control request, with the data of `data_out`, and returns the
+    /// data the device answered.
+    fn control(hid: &'static TestHid, setup: [u8; 8], data_out: &[u8]) -> Option<Vec<u8>> {
+        for (p, b) in hid.client_ctrl.ctrl_buffer.buf.iter().zip(setup.iter()) {
+            p.set(*b);
+        }
+        if let hil::usb::CtrlSetupResult::Ok = hid.ctrl_setup(0) {
+        } else {
+            return None;
+        }
+
+        let mut data_in = Vec::new();
+        if setup[0] & 0x80 != 0 {
+            while let hil::usb::CtrlInResult::Packet(len, last) = hid.ctrl_in(0) {
+                data_in.extend(
+                    hid.client_ctrl.ctrl_buffer.buf[..len]
+                        .iter()
+                        .map(|b| b.get()),
+                );
+                if last {
+                    break;
+                }
+            }
+        } else {
+            for packet in data_out.chunks(MAX_CTRL_PACKET_SIZE_SAM4L as usize) {
+                for (p, b) in hid.client_ctrl.ctrl_buffer.buf.iter().zip(packet.iter()) {
+                    p.set(*b);
+                }
+                hid.ctrl_out(0, packet.len() as u32);
+            }
+        }
+        hid.ctrl_status(0);
+        hid.ctrl_status_complete(0);
+        Some(data_in)
+    }
+
+    #[test]
+    fn descriptors() {
+        let (hid, _) = setup(&KEYBOARD);
+        let configuration = control(hid, [0x80, 6, 0, 2, 0, 0, 0xff, 0], &[]).unwrap();
+        // A boot keyboard interface, its HID descriptor and its endpoint.
+        assert_eq!(configuration[9..18], [9, 4, 0, 0, 1, 3, 1, 1, 0]);
+        assert_eq!(
+            configuration[18..27],
+            [9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0]
+        );
+        assert_eq!(configuration[27..], [7, 5, 0x81, 3, 8, 0, 10]);
+
+        let report = control(hid, [0x81, 6, 0, 0x22, 0, 0, 0xff, 0], &[]);
+        assert_eq!(report.unwrap(), KEYBOARD_REPORT_DESCRIPTOR);
+    }
+
+    #[test]
+    fn class_requests() {
+        let (hid, client) = setup(&KEYBOARD);
+
+        // SET_REPORT of the LEDs
+        control(hid, [0x21, 0x09, 0, 2, 0, 0, 1, 0], &[0x02]).unwrap();
+        assert_eq!(client.output.get(), Some(0x02));
+        let output = control(hid, [0xa1, 0x01, 0, 2, 0, 0, 1, 0], &[]);
+        assert_eq!(output.unwrap(), [0x02]);
+
+        // SET_IDLE and GET_IDLE, reports being never repeated
+        control(hid, [0x21, 0x0a, 0, 0, 0, 0, 0, 0], &[]).unwrap();
+        assert!(control(hid, [0x21, 0x0a, 0, 125, 0, 0, 0, 0], &[]).is_none());
+        let idle = control(hid, [0xa1, 0x02, 0, 0, 0, 0, 1, 0], &[]);
+        assert_eq!(idle.unwrap(), [0]);
+
+        // SET_PROTOCOL and GET_PROTOCOL
+        assert_eq!(hid.protocol(), Protocol::Report);
+        control(hid, [0x21, 0x0b, 0, 0, 0, 0, 0, 0], &[]).unwrap();
+        assert_eq!(hid.protocol(), Protocol::Boot);
+        let protocol = control(hid, [0xa1, 0x03, 0, 0, 0, 0, 1, 0], &[]);
+        assert_eq!(protocol.unwrap(), [0]);
+        hid.bus_reset();
+        assert_eq!(hid.protocol(), Protocol::Report);
+
+        // Feature reports are not supported.
+        assert!(control(hid, [0xa1, 0x01, 0, 3, 0, 0, 8, 0], &[]).is_none());
+    }
+
+    #[test]
+    fn reports() {
+        let (hid, client) = setup(&MOUSE);
+        let report = Box::leak(Box::new([1u8, 2, 0xfe, 0, 0, 0, 0, 0]));
+        assert!(hid.send_report(report, 4).is_ok());
+        let (rc, report) = hid
+            .send_report(Box::leak(Box::new([0u8; 4])), 4)
+            .unwrap_err();
+        assert_eq!(rc, ReturnCode::EBUSY);
+
+        if let hil::usb::InResult::Packet(4) = hid.packet_in(TransferType::Interrupt, ENDPOINT_NUM)
+        {
+        } else {
+            panic!("no report");
+        }
+        let sent: Vec<u8> = hid.buffer.buf[..4].iter().map(|b| b.get()).collect();
+        assert_eq!(sent, [1, 2, 0xfe, 0]);
+        // The report is sent once.
+        if let hil::usb::InResult::Packet(_) = hid.packet_in(TransferType::Interrupt, ENDPOINT_NUM)
+        {
+            panic!("report sent twice");
+        }
+        hid.packet_transmitted(ENDPOINT_NUM);
+        assert!(client.sent.get());
+
+        // The host can read the last report.
+        let last = control(hid, [0xa1, 0x01, 0, 1, 0, 0, 8, 0], &[]);
+        assert_eq!(last.unwrap(), [1, 2, 0xfe, 0]);
+
+        // Reports longer than the input report are refused.
+        let (rc, _) = hid.send_report(report, 8).unwrap_err();
+        assert_eq!(rc, ReturnCode::ESIZE);
+    }
+}
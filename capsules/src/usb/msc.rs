@@ -0,0 +1,1270 @@
+//! Mass Storage Class device for USB
+//!
+//! This capsule lets a host use a storage medium of the board as a USB
+//! drive, so files and logs can be copied with the file manager of the host.
+//! It implements the Bulk-Only Transport with the SCSI transparent command
+//! set, which all common hosts support.
+//!
+//! The host sends each command in a Command Block Wrapper on the bulk OUT
+//! endpoint, then the data of the command moves in one direction, and the
+//! device ends the command with a Command Status Wrapper on the bulk IN
+//! endpoint. The device supports the commands hosts need to mount a drive:
+//! TEST UNIT READY, REQUEST SENSE, INQUIRY, MODE SENSE(6), START STOP UNIT,
+//! PREVENT ALLOW MEDIUM REMOVAL, READ CAPACITY(10), READ(10) and WRITE(10).
+//!
+//! The device never stalls its bulk endpoints, because the control endpoint
+//! cannot clear a halt. When a command moves less data than the host
+//! expects, the device pads or discards the rest of the data, which the
+//! Bulk-Only Transport allows, and it ignores invalid Command Block Wrappers.
+//!
+//! The medium is a `BlockStorage`. `NonvolatileBlocks` exposes a region of a
+//! `hil::nonvolatile_storage::NonvolatileStorage`, and `SDCardBlocks` an SD
+//! card of the `sdcard` capsule.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let msc_storage = components::usb_msc::NonvolatileBlocksComponent::new(
+//!     nonvolatile_storage,
+//!     0x40000, // Start address of the drive
+//!     0x1f000, // Length of the drive
+//! )
+//! .finalize(components::nonvolatile_blocks_component_helper!());
+//!
+//! let msc = components::usb_msc::UsbMscComponent::new(
+//!     &nrf52840_peripherals.usbd,
+//!     capsules::usb::msc::MAX_CTRL_PACKET_SIZE_NRF52840,
+//!     0x1915, // Nordic Semiconductor
+//!     0x503a, // lowRISC generic FS USB
+//!     strings,
+//!     msc_storage,
+//! )
+//! .finalize(components::usb_msc_component_helper!(nrf52840::usbd::Usbd));
+//!
+//! msc.enable();
+//! msc.attach();
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use super::descriptors;
+use super::descriptors::Buffer64;
+use super::descriptors::EndpointAddress;
+use super::descriptors::EndpointDescriptor;
+use super::descriptors::InterfaceDescriptor;
+use super::descriptors::RequestType;
+use super::descriptors::TransferDirection;
+use super::usbc_client_ctrl::ClientCtrl;
+use crate::sdcard::{SDCard, SDCardClient};
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::cells::TakeCell;
+use kernel::common::cells::VolatileCell;
+use kernel::hil;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::usb::TransferType;
+use kernel::ReturnCode;
+
+/// Identifying number for the endpoint when transferring data from us to the
+/// host.
+const ENDPOINT_IN_NUM: usize = 1;
+/// Identifying number for the endpoint when transferring data from the host to
+/// us.
+const ENDPOINT_OUT_NUM: usize = 2;
+
+const IN_BUFFER: usize = 0;
+const OUT_BUFFER: usize = 1;
+
+const N_ENDPOINTS: usize = 2;
+
+static LANGUAGES: &'static [u16; 1] = &[
+    0x0409, // English (United States)
+];
+/// Platform-specific packet length for the `SAM4L` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_SAM4L: u8 = 8;
+/// Platform-specific packet length for the `nRF52` USB hardware.
+pub const MAX_CTRL_PACKET_SIZE_NRF52840: u8 = 64;
+
+/// The size of the blocks of the medium, and of the buffer the device needs.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Bulk-Only Mass Storage Reset class request.
+const REQUEST_RESET: u8 = 0xff;
+/// Get Max LUN class request.
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_LEN: usize = 31;
+/// The offset of the command block in the Command Block Wrapper.
+const CBW_CB: usize = 15;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_LEN: usize = 13;
+
+const CSW_PASSED: u8 = 0;
+const CSW_FAILED: u8 = 1;
+const CSW_PHASE_ERROR: u8 = 2;
+
+// SCSI operation codes
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1a;
+const START_STOP_UNIT: u8 = 0x1b;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1e;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2a;
+
+/// The sense key, additional sense code and additional sense code qualifier
+/// reported by REQUEST SENSE for the last failed command.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Sense(u8, u8, u8);
+
+const SENSE_NONE: Sense = Sense(0x00, 0x00, 0x00);
+const SENSE_MEDIUM_NOT_PRESENT: Sense = Sense(0x02, 0x3a, 0x00);
+const SENSE_READ_ERROR: Sense = Sense(0x03, 0x11, 0x00);
+const SENSE_WRITE_ERROR: Sense = Sense(0x03, 0x0c, 0x00);
+const SENSE_INTERNAL_FAILURE: Sense = Sense(0x04, 0x44, 0x00);
+const SENSE_INVALID_COMMAND: Sense = Sense(0x05, 0x20, 0x00);
+const SENSE_LBA_OUT_OF_RANGE: Sense = Sense(0x05, 0x21, 0x00);
+const SENSE_INVALID_FIELD: Sense = Sense(0x05, 0x24, 0x00);
+
+/// A storage medium, accessed in blocks of `BLOCK_SIZE` bytes.
+pub trait BlockStorage<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient);
+
+    /// The number of blocks of the medium, or 0 if there is no medium.
+    fn block_count(&self) -> u32;
+
+    /// Read block `block` into the first `BLOCK_SIZE` bytes of `buffer`.
+    fn read_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode;
+
+    /// Write the first `BLOCK_SIZE` bytes of `buffer` to block `block`.
+    fn write_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode;
+}
+
+/// Client interface for `BlockStorage`.
+pub trait BlockStorageClient {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode);
+}
+
+/// States of the Bulk-Only Transport.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    /// Waiting for a Command Block Wrapper.
+    Command,
+    /// Sending the data of the command to the host.
+    DataIn,
+    /// Receiving the data of the command from the host.
+    DataOut,
+    /// Sending the Command Status Wrapper.
+    Status,
+}
+
+/// The data a command moves, in bytes.
+enum Data {
+    None,
+    In(u32),
+    Out(u32),
+}
+
+/// A block transfer between the host and the medium.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Transfer {
+    None,
+    Read,
+    Write,
+}
+
+/// Implementation of the Mass Storage Class (MSC) Bulk-Only Transport over
+/// USB.
+pub struct MassStorage<'a, U: 'a> {
+    /// Helper USB client library for handling many USB operations.
+    client_ctrl: ClientCtrl<'a, 'static, U>,
+
+    /// 64 byte buffers for each endpoint.
+    buffers: [Buffer64; N_ENDPOINTS],
+
+    /// The medium exposed to the host.
+    storage: &'a dyn BlockStorage<'a>,
+
+    /// The manufacturer and product strings, which INQUIRY also reports.
+    strings: &'static [&'static str; 3],
+
+    state: Cell<State>,
+
+    /// The buffer of the responses and of the blocks.
+    buffer: TakeCell<'static, [u8]>,
+    /// The number of bytes of the buffer to send to the host.
+    buffer_len: Cell<usize>,
+    /// The number of bytes of the buffer that were sent to, or received
+    /// from, the host.
+    buffer_offset: Cell<usize>,
+
+    /// The tag of the command, which the status echoes.
+    tag: Cell<u32>,
+    /// The number of bytes the host expects to move.
+    data_len: Cell<u32>,
+    /// The number of bytes left to move, including padding.
+    data_remaining: Cell<u32>,
+    /// The number of bytes of the command that were moved.
+    data_moved: Cell<u32>,
+    /// The status of the command.
+    status: Cell<u8>,
+    /// Why the last failed command failed.
+    sense: Cell<Sense>,
+
+    transfer: Cell<Transfer>,
+    /// The next block of the transfer.
+    lba: Cell<u32>,
+    /// The number of blocks left to transfer.
+    blocks: Cell<u32>,
+
+    /// Whether the OUT endpoint waits for `endpoint_resume_out()`. We hold
+    /// off the host while the command is processed, so a new command cannot
+    /// arrive before the status is sent.
+    out_delayed: Cell<bool>,
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> MassStorage<'a, U> {
+    /// `buffer` must hold at least `BLOCK_SIZE` bytes.
+    pub fn new(
+        controller: &'a U,
+        max_ctrl_packet_size: u8,
+        vendor_id: u16,
+        product_id: u16,
+        strings: &'static [&'static str; 3],
+        storage: &'a dyn BlockStorage<'a>,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        let interfaces: &mut [InterfaceDescriptor] = &mut [InterfaceDescriptor {
+            interface_number: 0,
+            interface_class: 0x08,    // Mass storage
+            interface_subclass: 0x06, // SCSI transparent command set
+            interface_protocol: 0x50, // Bulk-Only Transport
+            ..InterfaceDescriptor::default()
+        }];
+
+        let endpoints: &[&[EndpointDescriptor]] = &[&[
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_IN_NUM,
+                    TransferDirection::DeviceToHost,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+            EndpointDescriptor {
+                endpoint_address: EndpointAddress::new_const(
+                    ENDPOINT_OUT_NUM,
+                    TransferDirection::HostToDevice,
+                ),
+                transfer_type: TransferType::Bulk,
+                max_packet_size: 64,
+                interval: 0,
+            },
+        ]];
+
+        let (device_descriptor_buffer, other_descriptor_buffer) =
+            descriptors::create_descriptor_buffers(
+                descriptors::DeviceDescriptor {
+                    vendor_id: vendor_id,
+                    product_id: product_id,
+                    manufacturer_string: 1,
+                    product_string: 2,
+                    serial_number_string: 3,
+                    max_packet_size_ep0: max_ctrl_packet_size,
+                    ..descriptors::DeviceDescriptor::default()
+                },
+                descriptors::ConfigurationDescriptor {
+                    ..descriptors::ConfigurationDescriptor::default()
+                },
+                interfaces,
+                endpoints,
+                None, // No HID descriptor
+                None, // No CDC descriptor array
+            );
+
+        MassStorage {
+            client_ctrl: ClientCtrl::new(
+                controller,
+                device_descriptor_buffer,
+                other_descriptor_buffer,
+                None, // No HID descriptor
+                None, // No report descriptor
+                LANGUAGES,
+                strings,
+            ),
+            buffers: [Buffer64::default(), Buffer64::default()],
+            storage,
+            strings,
+            state: Cell::new(State::Command),
+            buffer: TakeCell::new(buffer),
+            buffer_len: Cell::new(0),
+            buffer_offset: Cell::new(0),
+            tag: Cell::new(0),
+            data_len: Cell::new(0),
+            data_remaining: Cell::new(0),
+            data_moved: Cell::new(0),
+            status: Cell::new(CSW_PASSED),
+            sense: Cell::new(SENSE_NONE),
+            transfer: Cell::new(Transfer::None),
+            lba: Cell::new(0),
+            blocks: Cell::new(0),
+            out_delayed: Cell::new(false),
+        }
+    }
+
+    #[inline]
+    pub fn controller(&self) -> &'a U {
+        self.client_ctrl.controller()
+    }
+
+    #[inline]
+    fn buffer(&self, i: usize) -> &[VolatileCell<u8>; 64] {
+        &self.buffers[i].buf
+    }
+
+    fn fail(&self, sense: Sense) {
+        self.status.set(CSW_FAILED);
+        self.sense.set(sense);
+    }
+
+    /// Abandons the command and waits for the next one.
+    fn reset(&self) {
+        self.state.set(State::Command);
+        self.transfer.set(Transfer::None);
+        self.blocks.set(0);
+        self.buffer_len.set(0);
+        self.buffer_offset.set(0);
+    }
+
+    /// Puts the first `allocation_len` bytes of `data` in the buffer, to be
+    /// sent to the host.
+    fn respond(&self, data: &[u8], allocation_len: usize) -> Data {
+        let len = cmp::min(data.len(), allocation_len);
+        self.buffer.map_or_else(
+            || {
+                // The buffer was lost in a failed storage operation
+                self.fail(SENSE_INTERNAL_FAILURE);
+                Data::None
+            },
+            |buffer| {
+                buffer[..len].copy_from_slice(&data[..len]);
+                self.buffer_len.set(len);
+                Data::In(len as u32)
+            },
+        )
+    }
+
+    /// Executes the SCSI command block `cb`, and returns the data the
+    /// command moves.
+    fn execute(&self, cb: &[u8]) -> Data {
+        let block_count = self.storage.block_count();
+        match cb[0] {
+            TEST_UNIT_READY => {
+                if block_count == 0 {
+                    self.fail(SENSE_MEDIUM_NOT_PRESENT);
+                }
+                Data::None
+            }
+            REQUEST_SENSE => {
+                let sense = self.sense.replace(SENSE_NONE);
+                let response = [
+                    0x70, // Current error, fixed format
+                    0x00, sense.0, 0x00, 0x00, 0x00, 0x00, // Additional length
+                    10, 0x00, 0x00, 0x00, 0x00, sense.1, sense.2, 0x00, 0x00, 0x00, 0x00,
+                ];
+                self.respond(&response, cb[4] as usize)
+            }
+            INQUIRY => {
+                if cb[1] & 0x01 != 0 {
+                    // Vital product data pages are not supported
+                    self.fail(SENSE_INVALID_FIELD);
+                    return Data::None;
+                }
+                let mut response = [b' '; 36];
+                response[..8].copy_from_slice(&[
+                    0x00, // Direct access block device
+                    0x80, // Removable medium
+                    0x04, // SPC-2
+                    0x02, // Response data format
+                    31,   // Additional length
+                    0x00, 0x00, 0x00,
+                ]);
+                for (range, string) in [(8..16, self.strings[0]), (16..32, self.strings[1])].iter()
+                {
+                    let field = &mut response[range.clone()];
+                    let len = cmp::min(field.len(), string.len());
+                    field[..len].copy_from_slice(&string.as_bytes()[..len]);
+                }
+                response[32..36].copy_from_slice(b"1.0 ");
+                let allocation_len = u16::from_be_bytes([cb[3], cb[4]]);
+                self.respond(&response, allocation_len as usize)
+            }
+            MODE_SENSE_6 => {
+                // A header without block descriptors or pages, for a medium
+                // that is not write protected
+                self.respond(&[0x03, 0x00, 0x00, 0x00], cb[4] as usize)
+            }
+            START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL => Data::None,
+            READ_CAPACITY_10 => {
+                if block_count == 0 {
+                    self.fail(SENSE_MEDIUM_NOT_PRESENT);
+                    return Data::None;
+                }
+                let mut response = [0; 8];
+                response[..4].copy_from_slice(&(block_count - 1).to_be_bytes());
+                response[4..].copy_from_slice(&(BLOCK_SIZE as u32).to_be_bytes());
+                self.respond(&response, response.len())
+            }
+            READ_10 | WRITE_10 => {
+                let lba = u32::from_be_bytes([cb[2], cb[3], cb[4], cb[5]]);
+                let blocks = u16::from_be_bytes([cb[7], cb[8]]) as u32;
+                if block_count == 0 {
+                    self.fail(SENSE_MEDIUM_NOT_PRESENT);
+                    return Data::None;
+                }
+                if lba
+                    .checked_add(blocks)
+                    .map_or(true, |end| end > block_count)
+                {
+                    self.fail(SENSE_LBA_OUT_OF_RANGE);
+                    return Data::None;
+                }
+                self.lba.set(lba);
+                self.blocks.set(blocks);
+                let len = blocks * BLOCK_SIZE as u32;
+                if cb[0] == READ_10 {
+                    self.transfer.set(Transfer::Read);
+                    Data::In(len)
+                } else {
+                    self.transfer.set(Transfer::Write);
+                    Data::Out(len)
+                }
+            }
+            _ => {
+                self.fail(SENSE_INVALID_COMMAND);
+                Data::None
+            }
+        }
+    }
+
+    /// Handles a Command Block Wrapper of `packet_bytes` bytes, and starts
+    /// the data or status stage of the command.
+    fn command_block(&self, packet_bytes: u32) -> hil::usb::OutResult {
+        if packet_bytes as usize != CBW_LEN {
+            return hil::usb::OutResult::Ok;
+        }
+        let mut cbw = [0; CBW_LEN];
+        for (b, p) in cbw.iter_mut().zip(self.buffer(OUT_BUFFER).iter()) {
+            *b = p.get();
+        }
+        let field = |offset: usize| {
+            u32::from_le_bytes([
+                cbw[offset],
+                cbw[offset + 1],
+                cbw[offset + 2],
+                cbw[offset + 3],
+            ])
+        };
+        if field(0) != CBW_SIGNATURE {
+            return hil::usb::OutResult::Ok;
+        }
+        let data_len = field(8);
+        let host_in = cbw[12] & 0x80 != 0;
+
+        self.tag.set(field(4));
+        self.data_len.set(data_len);
+        self.data_remaining.set(data_len);
+        self.data_moved.set(0);
+        self.status.set(CSW_PASSED);
+        self.reset();
+
+        let (device_len, device_in) = match self.execute(&cbw[CBW_CB..]) {
+            Data::None => (0, host_in),
+            Data::In(len) => (len, true),
+            Data::Out(len) => (len, false),
+        };
+        if device_len > 0 && (data_len < device_len || device_in != host_in) {
+            // The host and the device disagree on the data, so none of it is
+            // valid.
+            self.status.set(CSW_PHASE_ERROR);
+            self.reset();
+        }
+
+        if data_len > 0 && !host_in {
+            self.state.set(State::DataOut);
+            return hil::usb::OutResult::Ok;
+        }
+
+        self.out_delayed.set(true);
+        if data_len == 0 {
+            self.send_status();
+        } else {
+            self.state.set(State::DataIn);
+            if self.transfer.get() == Transfer::Read {
+                self.read_block();
+            } else {
+                self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+            }
+        }
+        hil::usb::OutResult::Delay
+    }
+
+    fn send_status(&self) {
+        self.state.set(State::Status);
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    /// Ends the block transfer after a storage error. The rest of the data is
+    /// padded or discarded.
+    fn transfer_failed(&self, sense: Sense) {
+        self.fail(sense);
+        self.transfer.set(Transfer::None);
+        self.blocks.set(0);
+        self.buffer_len.set(0);
+        self.buffer_offset.set(0);
+    }
+
+    fn read_block(&self) {
+        self.buffer_len.set(0);
+        self.buffer_offset.set(0);
+        let result = self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            self.storage.read_block(buffer, self.lba.get())
+        });
+        if result != ReturnCode::SUCCESS {
+            self.transfer_failed(SENSE_READ_ERROR);
+            self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+        }
+    }
+
+    /// Writes the received block, and returns whether the write started.
+    fn write_block(&self) -> bool {
+        let result = self.buffer.take().map_or(ReturnCode::ENOMEM, |buffer| {
+            self.storage.write_block(buffer, self.lba.get())
+        });
+        if result != ReturnCode::SUCCESS {
+            self.transfer_failed(SENSE_WRITE_ERROR);
+        }
+        result == ReturnCode::SUCCESS
+    }
+
+    /// Accepts the next data from the host, or sends the status if all the
+    /// data was received.
+    fn continue_out(&self) {
+        if self.data_remaining.get() == 0 {
+            self.send_status();
+        } else if self.out_delayed.get() {
+            self.out_delayed.set(false);
+            self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> hil::usb::Client<'a> for MassStorage<'a, U> {
+    fn enable(&'a self) {
+        // Set up the default control endpoint
+        self.client_ctrl.enable();
+
+        // Setup buffers for IN and OUT data transfer.
+        self.controller()
+            .endpoint_set_in_buffer(ENDPOINT_IN_NUM, self.buffer(IN_BUFFER));
+        self.controller()
+            .endpoint_in_enable(TransferType::Bulk, ENDPOINT_IN_NUM);
+
+        self.controller()
+            .endpoint_set_out_buffer(ENDPOINT_OUT_NUM, self.buffer(OUT_BUFFER));
+        self.controller()
+            .endpoint_out_enable(TransferType::Bulk, ENDPOINT_OUT_NUM);
+    }
+
+    fn attach(&'a self) {
+        self.client_ctrl.attach();
+    }
+
+    fn bus_reset(&'a self) {
+        // The controller resets the endpoints, so the OUT endpoint is ready
+        // again.
+        self.reset();
+        self.out_delayed.set(false);
+    }
+
+    /// Handle a Control Setup transaction.
+    ///
+    /// The Bulk-Only Transport defines two class requests: a reset, which
+    /// abandons the current command, and Get Max LUN, to which we answer
+    /// that there is a single logical unit.
+    fn ctrl_setup(&'a self, endpoint: usize) -> hil::usb::CtrlSetupResult {
+        let setup_data = descriptors::SetupData::get(&self.client_ctrl.ctrl_buffer.buf);
+        if let Some(setup_data) = setup_data {
+            if let RequestType::Class = setup_data.request_type.request_type() {
+                match setup_data.request_code {
+                    REQUEST_RESET => {
+                        self.reset();
+                        if self.out_delayed.get() {
+                            self.out_delayed.set(false);
+                            self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+                        }
+                    }
+                    REQUEST_GET_MAX_LUN => {
+                        return self
+                            .client_ctrl
+                            .ctrl_in_data(endpoint, &[0], setup_data.length);
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.client_ctrl.ctrl_setup(endpoint)
+    }
+
+    /// Handle a Control In transaction
+    fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
+        self.client_ctrl.ctrl_in(endpoint)
+    }
+
+    /// Handle a Control Out transaction
+    fn ctrl_out(&'a self, endpoint: usize, packet_bytes: u32) -> hil::usb::CtrlOutResult {
+        self.client_ctrl.ctrl_out(endpoint, packet_bytes)
+    }
+
+    fn ctrl_status(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status(endpoint)
+    }
+
+    /// Handle the completion of a Control transfer
+    fn ctrl_status_complete(&'a self, endpoint: usize) {
+        self.client_ctrl.ctrl_status_complete(endpoint)
+    }
+
+    /// Handle a Bulk IN transaction.
+    ///
+    /// This sends the next packet of the data of the command, from the
+    /// buffer or as padding, or the Command Status Wrapper.
+    fn packet_in(&'a self, transfer_type: TransferType, _endpoint: usize) -> hil::usb::InResult {
+        match transfer_type {
+            TransferType::Bulk => {
+                let packet = self.buffer(IN_BUFFER);
+                match self.state.get() {
+                    State::DataIn => {
+                        let remaining = self.data_remaining.get() as usize;
+                        let offset = self.buffer_offset.get();
+                        let available = self.buffer_len.get() - offset;
+                        let to_send = cmp::min(packet.len(), remaining);
+                        if available > 0 {
+                            let to_send = cmp::min(to_send, available);
+                            self.buffer.map(|buffer| {
+                                for i in 0..to_send {
+                                    packet[i].set(buffer[offset + i]);
+                                }
+                            });
+                            self.buffer_offset.set(offset + to_send);
+                            self.data_moved.set(self.data_moved.get() + to_send as u32);
+                            self.data_remaining.set((remaining - to_send) as u32);
+                            hil::usb::InResult::Packet(to_send)
+                        } else if self.blocks.get() > 0 || to_send == 0 {
+                            // Wait for the next block from the medium
+                            hil::usb::InResult::Delay
+                        } else {
+                            for i in 0..to_send {
+                                packet[i].set(0);
+                            }
+                            self.data_remaining.set((remaining - to_send) as u32);
+                            hil::usb::InResult::Packet(to_send)
+                        }
+                    }
+                    State::Status => {
+                        let residue = self.data_len.get() - self.data_moved.get();
+                        let mut csw = [0; CSW_LEN];
+                        csw[..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+                        csw[4..8].copy_from_slice(&self.tag.get().to_le_bytes());
+                        csw[8..12].copy_from_slice(&residue.to_le_bytes());
+                        csw[12] = self.status.get();
+                        for (p, b) in packet.iter().zip(csw.iter()) {
+                            p.set(*b);
+                        }
+                        hil::usb::InResult::Packet(CSW_LEN)
+                    }
+                    State::Command | State::DataOut => hil::usb::InResult::Delay,
+                }
+            }
+            TransferType::Control | TransferType::Isochronous | TransferType::Interrupt => {
+                // Nothing to do for MSC.
+                hil::usb::InResult::Delay
+            }
+        }
+    }
+
+    /// Handle a Bulk OUT transaction
+    fn packet_out(
+        &'a self,
+        transfer_type: TransferType,
+        _endpoint: usize,
+        packet_bytes: u32,
+    ) -> hil::usb::OutResult {
+        match transfer_type {
+            TransferType::Bulk => match self.state.get() {
+                State::Command => self.command_block(packet_bytes),
+                State::DataOut => {
+                    let received = cmp::min(packet_bytes, self.data_remaining.get());
+                    self.data_remaining
+                        .set(self.data_remaining.get() - received);
+
+                    if self.transfer.get() == Transfer::Write {
+                        let offset = self.buffer_offset.get();
+                        let len = cmp::min(received as usize, BLOCK_SIZE - offset);
+                        let packet = self.buffer(OUT_BUFFER);
+                        self.buffer.map(|buffer| {
+                            for i in 0..len {
+                                buffer[offset + i] = packet[i].get();
+                            }
+                        });
+                        self.buffer_offset.set(offset + len);
+                        if offset + len == BLOCK_SIZE && self.write_block() {
+                            // Hold off the host until the block is written
+                            self.out_delayed.set(true);
+                            return hil::usb::OutResult::Delay;
+                        }
+                    }
+
+                    if self.data_remaining.get() == 0 {
+                        self.out_delayed.set(true);
+                        self.send_status();
+                        hil::usb::OutResult::Delay
+                    } else {
+                        hil::usb::OutResult::Ok
+                    }
+                }
+                State::DataIn | State::Status => {
+                    // The host does not follow the protocol. Wait until it
+                    // gets the status.
+                    self.out_delayed.set(true);
+                    hil::usb::OutResult::Delay
+                }
+            },
+            TransferType::Control | TransferType::Isochronous | TransferType::Interrupt => {
+                // Nothing to do for MSC.
+                hil::usb::OutResult::Ok
+            }
+        }
+    }
+
+    fn packet_transmitted(&'a self, _endpoint: usize) {
+        match self.state.get() {
+            State::DataIn => {
+                if self.data_remaining.get() == 0 {
+                    self.send_status();
+                } else if self.buffer_offset.get() == self.buffer_len.get() && self.blocks.get() > 0
+                {
+                    self.read_block();
+                } else {
+                    self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+                }
+            }
+            State::Status => {
+                self.state.set(State::Command);
+                if self.out_delayed.get() {
+                    self.out_delayed.set(false);
+                    self.controller().endpoint_resume_out(ENDPOINT_OUT_NUM);
+                }
+            }
+            State::Command | State::DataOut => {}
+        }
+    }
+}
+
+impl<'a, U: hil::usb::UsbController<'a>> BlockStorageClient for MassStorage<'a, U> {
+    fn read_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        if self.state.get() != State::DataIn || self.transfer.get() != Transfer::Read {
+            // The host reset the device during the read
+            return;
+        }
+        if result == ReturnCode::SUCCESS {
+            self.buffer_len.set(BLOCK_SIZE);
+            self.lba.set(self.lba.get() + 1);
+            self.blocks.set(self.blocks.get() - 1);
+        } else {
+            self.transfer_failed(SENSE_READ_ERROR);
+        }
+        self.controller().endpoint_resume_in(ENDPOINT_IN_NUM);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], result: ReturnCode) {
+        self.buffer.replace(buffer);
+        if self.state.get() != State::DataOut || self.transfer.get() != Transfer::Write {
+            // The host reset the device during the write
+            return;
+        }
+        if result == ReturnCode::SUCCESS {
+            self.data_moved
+                .set(self.data_moved.get() + BLOCK_SIZE as u32);
+            self.buffer_offset.set(0);
+            self.lba.set(self.lba.get() + 1);
+            self.blocks.set(self.blocks.get() - 1);
+        } else {
+            self.transfer_failed(SENSE_WRITE_ERROR);
+        }
+        self.continue_out();
+    }
+}
+
+/// Exposes `length` bytes of nonvolatile storage from `address` as a
+/// `BlockStorage`.
+pub struct NonvolatileBlocks<'a> {
+    storage: &'a dyn NonvolatileStorage<'static>,
+    address: usize,
+    length: usize,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+}
+
+impl<'a> NonvolatileBlocks<'a> {
+    pub fn new(
+        storage: &'a dyn NonvolatileStorage<'static>,
+        address: usize,
+        length: usize,
+    ) -> NonvolatileBlocks<'a> {
+        NonvolatileBlocks {
+            storage,
+            address,
+            length,
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn block_address(&self, block: u32) -> Option<usize> {
+        if block < self.block_count() {
+            Some(self.address + block as usize * BLOCK_SIZE)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> BlockStorage<'a> for NonvolatileBlocks<'a> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_count(&self) -> u32 {
+        (self.length / BLOCK_SIZE) as u32
+    }
+
+    fn read_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+        self.block_address(block)
+            .map_or(ReturnCode::EINVAL, move |address| {
+                self.storage.read(buffer, address, BLOCK_SIZE)
+            })
+    }
+
+    fn write_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+        self.block_address(block)
+            .map_or(ReturnCode::EINVAL, move |address| {
+                self.storage.write(buffer, address, BLOCK_SIZE)
+            })
+    }
+}
+
+impl NonvolatileStorageClient<'static> for NonvolatileBlocks<'_> {
+    fn read_done(&self, buffer: &'static mut [u8], length: usize) {
+        let result = if length == BLOCK_SIZE {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.client
+            .map(move |client| client.read_done(buffer, result));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], length: usize) {
+        let result = if length == BLOCK_SIZE {
+            ReturnCode::SUCCESS
+        } else {
+            ReturnCode::FAIL
+        };
+        self.client
+            .map(move |client| client.write_done(buffer, result));
+    }
+}
+
+/// Exposes the card of the `sdcard` capsule as a `BlockStorage`.
+///
+/// There is no medium until the card is initialized, and the medium is gone
+/// when the card is removed.
+pub struct SDCardBlocks<'a, A: hil::time::Alarm<'a>> {
+    sdcard: &'a SDCard<'a, A>,
+    block_count: Cell<u32>,
+    /// The operation of the card, whose buffer must be returned if it fails.
+    pending: Cell<Transfer>,
+    client: OptionalCell<&'a dyn BlockStorageClient>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardBlocks<'a, A> {
+    pub fn new(sdcard: &'a SDCard<'a, A>) -> SDCardBlocks<'a, A> {
+        SDCardBlocks {
+            sdcard,
+            block_count: Cell::new(0),
+            pending: Cell::new(Transfer::None),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Initializes the card, and watches for it to be inserted or removed.
+    pub fn initialize(&self) -> ReturnCode {
+        self.sdcard.detect_changes();
+        self.sdcard.initialize()
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> BlockStorage<'a> for SDCardBlocks<'a, A> {
+    fn set_client(&self, client: &'a dyn BlockStorageClient) {
+        self.client.set(client);
+    }
+
+    fn block_count(&self) -> u32 {
+        self.block_count.get()
+    }
+
+    fn read_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+        let result = self.sdcard.read_blocks(buffer, block, 1);
+        if result == ReturnCode::SUCCESS {
+            self.pending.set(Transfer::Read);
+        }
+        result
+    }
+
+    fn write_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+        let result = self.sdcard.write_blocks(buffer, block, 1);
+        if result == ReturnCode::SUCCESS {
+            self.pending.set(Transfer::Write);
+        }
+        result
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SDCardClient for SDCardBlocks<'a, A> {
+    fn card_detection_changed(&self, installed: bool) {
+        self.block_count.set(0);
+        if installed {
+            self.sdcard.initialize();
+        }
+    }
+
+    fn init_done(&self, block_size: u32, total_size: u64) {
+        if block_size as usize == BLOCK_SIZE {
+            let blocks = total_size / BLOCK_SIZE as u64;
+            self.block_count
+                .set(cmp::min(blocks, u32::MAX as u64) as u32);
+        }
+    }
+
+    fn read_done(&self, data: &'static mut [u8], _len: usize) {
+        self.pending.set(Transfer::None);
+        self.client
+            .map(move |client| client.read_done(data, ReturnCode::SUCCESS));
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8]) {
+        self.pending.set(Transfer::None);
+        self.client
+            .map(move |client| client.write_done(buffer, ReturnCode::SUCCESS));
+    }
+
+    fn error(&self, _error: u32) {
+        match self.pending.replace(Transfer::None) {
+            Transfer::Read => {
+                self.sdcard.take_buffer().map(|buffer| {
+                    self.client
+                        .map(move |client| client.read_done(buffer, ReturnCode::FAIL));
+                });
+            }
+            Transfer::Write => {
+                self.sdcard.take_buffer().map(|buffer| {
+                    self.client
+                        .map(move |client| client.write_done(buffer, ReturnCode::FAIL));
+                });
+            }
+            Transfer::None => {
+                // The card could not be initialized
+                self.block_count.set(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use hil::usb::Client as _;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    struct TestUsb {
+        resume_in: Cell<bool>,
+        resume_out: Cell<bool>,
+    }
+
+    impl<'a> hil::usb::UsbController<'a> for TestUsb {
+        fn set_client(&self, _client: &'a dyn hil::usb::Client<'a>) {}
+        fn endpoint_set_ctrl_buffer(&self, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_in_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn endpoint_set_out_buffer(&self, _endpoint: usize, _buf: &'a [VolatileCell<u8>]) {}
+        fn enable_as_device(&self, _speed: hil::usb::DeviceSpeed) {}
+        fn attach(&self) {}
+        fn detach(&self) {}
+        fn set_address(&self, _addr: u16) {}
+        fn enable_address(&self) {}
+        fn endpoint_in_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+        fn endpoint_in_out_enable(&self, _transfer_type: TransferType, _endpoint: usize) {}
+
+        fn endpoint_resume_in(&self, endpoint: usize) {
+            assert_eq!(endpoint, ENDPOINT_IN_NUM);
+            self.resume_in.set(true);
+        }
+
+        fn endpoint_resume_out(&self, endpoint: usize) {
+            assert_eq!(endpoint, ENDPOINT_OUT_NUM);
+            self.resume_out.set(true);
+        }
+    }
+
+    /// A medium of 4 blocks in RAM, whose operations complete when the test
+    /// calls `complete()`.
+    struct TestStorage {
+        data: TakeCell<'static, [u8]>,
+        pending: Cell<Option<(Transfer, u32)>>,
+        buffer: TakeCell<'static, [u8]>,
+        client: OptionalCell<&'static dyn BlockStorageClient>,
+    }
+
+    impl TestStorage {
+        fn complete(&self) {
+            let (transfer, block) = self.pending.take().expect("no storage operation");
+            let buffer = self.buffer.take().unwrap();
+            let offset = block as usize * BLOCK_SIZE;
+            self.data.map(|data| match transfer {
+                Transfer::Read => {
+                    buffer[..BLOCK_SIZE].copy_from_slice(&data[offset..offset + BLOCK_SIZE])
+                }
+                _ => data[offset..offset + BLOCK_SIZE].copy_from_slice(&buffer[..BLOCK_SIZE]),
+            });
+            self.client.map(move |client| match transfer {
+                Transfer::Read => client.read_done(buffer, ReturnCode::SUCCESS),
+                _ => client.write_done(buffer, ReturnCode::SUCCESS),
+            });
+        }
+    }
+
+    impl BlockStorage<'static> for TestStorage {
+        fn set_client(&self, client: &'static dyn BlockStorageClient) {
+            self.client.set(client);
+        }
+
+        fn block_count(&self) -> u32 {
+            4
+        }
+
+        fn read_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+            self.buffer.replace(buffer);
+            self.pending.set(Some((Transfer::Read, block)));
+            ReturnCode::SUCCESS
+        }
+
+        fn write_block(&self, buffer: &'static mut [u8], block: u32) -> ReturnCode {
+            self.buffer.replace(buffer);
+            self.pending.set(Some((Transfer::Write, block)));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    type TestMsc = MassStorage<'static, TestUsb>;
+
+    fn setup() -> (&'static TestUsb, &'static TestStorage, &'static TestMsc) {
+        let usb = Box::leak(Box::new(TestUsb {
+            resume_in: Cell::new(false),
+            resume_out: Cell::new(false),
+        }));
+        let storage = Box::leak(Box::new(TestStorage {
+            data: TakeCell::new(Box::leak(Box::new([0; 4 * BLOCK_SIZE]))),
+            pending: Cell::new(None),
+            buffer: TakeCell::empty(),
+            client: OptionalCell::empty(),
+        }));
+        let msc = Box::leak(Box::new(MassStorage::new(
+            &*usb,
+            MAX_CTRL_PACKET_SIZE_NRF52840,
+            0x1915,
+            0x503a,
+            &["Tock", "USB drive", "0001"],
+            &*storage,
+            Box::leak(Box::new([0; BLOCK_SIZE])),
+        )));
+        storage.set_client(msc);
+        (usb, storage, msc)
+    }
+
+    /// Sends a packet to the OUT endpoint.
+    fn send(msc: &'static TestMsc, packet: &[u8]) -> hil::usb::OutResult {
+        for (p, b) in msc.buffer(OUT_BUFFER).iter().zip(packet.iter()) {
+            p.set(*b);
+        }
+        msc.packet_out(TransferType::Bulk, ENDPOINT_OUT_NUM, packet.len() as u32)
+    }
+
+    fn command(tag: u32, data_len: u32, host_in: bool, cb: &[u8]) -> [u8; CBW_LEN] {
+        let mut cbw = [0; CBW_LEN];
+        cbw[..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&data_len.to_le_bytes());
+        cbw[12] = if host_in { 0x80 } else { 0x00 };
+        cbw[14] = cb.len() as u8;
+        cbw[CBW_CB..CBW_CB + cb.len()].copy_from_slice(cb);
+        cbw
+    }
+
+    /// Receives the packets the device sends on the IN endpoint, completing
+    /// the storage operations.
+    fn receive(usb: &TestUsb, storage: &TestStorage, msc: &'static TestMsc) -> Vec<u8> {
+        let mut received = Vec::new();
+        loop {
+            if storage.pending.get().is_some() {
+                storage.complete();
+            } else if usb.resume_in.replace(false) {
+                match msc.packet_in(TransferType::Bulk, ENDPOINT_IN_NUM) {
+                    hil::usb::InResult::Packet(len) => {
+                        let packet = msc.buffer(IN_BUFFER);
+                        received.extend(packet[..len].iter().map(|p| p.get()));
+                        msc.packet_transmitted(ENDPOINT_IN_NUM);
+                    }
+                    _ => panic!("no packet"),
+                }
+            } else {
+                return received;
+            }
+        }
+    }
+
+    /// Checks the Command Status Wrapper at the end of `received`, and
+    /// returns the data before it.
+    fn status(received: &[u8], tag: u32, residue: u32, status: u8) -> &[u8] {
+        let (data, csw) = received.split_at(received.len() - CSW_LEN);
+        assert_eq!(&csw[..4], &CSW_SIGNATURE.to_le_bytes());
+        assert_eq!(&csw[4..8], &tag.to_le_bytes());
+        assert_eq!(&csw[8..12], &residue.to_le_bytes());
+        assert_eq!(csw[12], status);
+        data
+    }
+
+    #[test]
+    fn commands() {
+        let (usb, storage, msc) = setup();
+
+        let cbw = command(1, 0, false, &[TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        assert!(matches!(send(msc, &cbw), hil::usb::OutResult::Delay));
+        let received = receive(usb, storage, msc);
+        assert!(status(&received, 1, 0, CSW_PASSED).is_empty());
+        assert!(usb.resume_out.replace(false));
+
+        let cbw = command(2, 36, true, &[INQUIRY, 0, 0, 0, 36, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 2, 0, CSW_PASSED);
+        assert_eq!(data.len(), 36);
+        assert_eq!(&data[8..16], b"Tock    ");
+        assert_eq!(&data[16..32], b"USB drive       ");
+
+        let cbw = command(3, 8, true, &[READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 3, 0, CSW_PASSED);
+        assert_eq!(data, &[0, 0, 0, 3, 0, 0, 2, 0]);
+
+        // The host may expect more data than the device has
+        let cbw = command(4, 192, true, &[MODE_SENSE_6, 0, 0x3f, 0, 192, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 4, 188, CSW_PASSED);
+        assert_eq!(data.len(), 192);
+        assert_eq!(&data[..4], &[3, 0, 0, 0]);
+
+        let cbw = command(5, 0, false, &[0xff, 0, 0, 0, 0, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        status(&received, 5, 0, CSW_FAILED);
+
+        let cbw = command(6, 18, true, &[REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 6, 0, CSW_PASSED);
+        assert_eq!((data[2], data[12], data[13]), (0x05, 0x20, 0x00));
+
+        // The data of INQUIRY cannot go to the device
+        let cbw = command(7, 36, false, &[INQUIRY, 0, 0, 0, 36, 0]);
+        assert!(matches!(send(msc, &cbw), hil::usb::OutResult::Ok));
+        assert!(matches!(send(msc, &[0; 32]), hil::usb::OutResult::Ok));
+        assert!(matches!(send(msc, &[0; 4]), hil::usb::OutResult::Delay));
+        let received = receive(usb, storage, msc);
+        status(&received, 7, 36, CSW_PHASE_ERROR);
+
+        // Invalid command blocks wrappers are ignored
+        let mut cbw = command(8, 0, false, &[TEST_UNIT_READY, 0, 0, 0, 0, 0]);
+        cbw[0] = 0;
+        assert!(matches!(send(msc, &cbw), hil::usb::OutResult::Ok));
+        assert!(receive(usb, storage, msc).is_empty());
+    }
+
+    #[test]
+    fn read_write() {
+        let (usb, storage, msc) = setup();
+        let blocks: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i / 3) as u8).collect();
+
+        // Write blocks 2 and 3
+        let cbw = command(1, 1024, false, &[WRITE_10, 0, 0, 0, 0, 2, 0, 0, 2, 0]);
+        assert!(matches!(send(msc, &cbw), hil::usb::OutResult::Ok));
+        for (i, packet) in blocks.chunks(64).enumerate() {
+            let result = send(msc, packet);
+            if i % 8 == 7 {
+                assert!(matches!(result, hil::usb::OutResult::Delay));
+                storage.complete();
+                if i < 15 {
+                    assert!(usb.resume_out.replace(false));
+                }
+            } else {
+                assert!(matches!(result, hil::usb::OutResult::Ok));
+            }
+        }
+        let received = receive(usb, storage, msc);
+        status(&received, 1, 0, CSW_PASSED);
+        storage
+            .data
+            .map(|data| assert_eq!(&data[2 * BLOCK_SIZE..], &blocks[..]));
+        assert!(usb.resume_out.replace(false));
+
+        let cbw = command(2, 1024, true, &[READ_10, 0, 0, 0, 0, 2, 0, 0, 2, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        assert_eq!(status(&received, 2, 0, CSW_PASSED), &blocks[..]);
+
+        // Blocks past the end of the medium are padded
+        let cbw = command(3, 1024, true, &[READ_10, 0, 0, 0, 0, 3, 0, 0, 2, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 3, 1024, CSW_FAILED);
+        assert!(data.len() == 1024 && data.iter().all(|b| *b == 0));
+
+        let cbw = command(4, 18, true, &[REQUEST_SENSE, 0, 0, 0, 18, 0]);
+        send(msc, &cbw);
+        let received = receive(usb, storage, msc);
+        let data = status(&received, 4, 0, CSW_PASSED);
+        assert_eq!((data[2], data[12], data[13]), (0x05, 0x21, 0x00));
+    }
+}
@@ -363,6 +363,24 @@ impl<'a, 'b, U: hil::usb::UsbController<'a>> ClientCtrl<'a, 'b, U> {
         }
     }
 
+    /// Answer a class or vendor request that the client handled itself with
+    /// `data`, of which the host gets at most `requested_length` bytes.
+    pub fn ctrl_in_data(
+        &'a self,
+        endpoint: usize,
+        data: &[u8],
+        requested_length: u16,
+    ) -> hil::usb::CtrlSetupResult {
+        let buf = self.descriptor_buf();
+        let len = min(data.len(), buf.len());
+        for (dest, b) in buf.iter().zip(data[..len].iter()) {
+            dest.set(*b);
+        }
+        let end = min(len, requested_length as usize);
+        self.state[endpoint].set(State::CtrlIn(0, end));
+        hil::usb::CtrlSetupResult::Ok
+    }
+
     /// Handle a Control In transaction
     pub fn ctrl_in(&'a self, endpoint: usize) -> hil::usb::CtrlInResult {
         match self.state[endpoint].get() {
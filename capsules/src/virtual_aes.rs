@@ -0,0 +1,658 @@
+//! Virtualize an AES-128 engine so that several kernel and userspace clients
+//! can share it.
+//!
+//! Each `VirtualAES128` keeps its own key, IV and mode, which the mux loads
+//! into the hardware before each of its `crypt()` operations, so clients do
+//! not see each other's configuration. Every client may have one operation
+//! outstanding; operations are queued and run one after the other.
+//!
+//! Since operations of other clients may run between two `crypt()` calls
+//! of the same message, a `VirtualAES128` does not rely on the hardware to
+//! carry the CBC or CTR state over. It computes the IV the next call
+//! continues from, the last ciphertext block in CBC mode and the incremented
+//! counter in CTR mode, and loads it with the key. Counters are incremented
+//! as 128-bit big-endian integers.
+//!
+//! ECB mode is only available when the hardware implements `AES128ECB` and
+//! `MuxAES128::enable_ecb()` was called. Otherwise, `crypt()` in ECB mode
+//! returns `ENOSUPPORT`.
+//!
+//! `AES128::crypt()` has no way to report errors from the callback, so if a
+//! queued operation fails to start, `crypt_done()` is called with the
+//! buffers unchanged. This does not happen for valid requests.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::virtual_aes::{MuxAES128, VirtualAES128};
+//! # use kernel::hil::symmetric_encryption::AES128;
+//! # use kernel::static_init;
+//! let aes_mux = static_init!(
+//!     MuxAES128<'static, sam4l::aes::Aes>,
+//!     MuxAES128::new(&peripherals.aes)
+//! );
+//! peripherals.aes.set_client(aes_mux);
+//! aes_mux.enable_ecb();
+//!
+//! let aes = static_init!(
+//!     VirtualAES128<'static, sam4l::aes::Aes>,
+//!     VirtualAES128::new(aes_mux)
+//! );
+//! aes.setup();
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::symmetric_encryption;
+use kernel::hil::symmetric_encryption::{
+    AES128Ctr, AES128, AES128CBC, AES128ECB, AES128_BLOCK_SIZE, AES128_KEY_SIZE,
+};
+use kernel::ReturnCode;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Mode {
+    Ecb,
+    Cbc,
+    Ctr,
+}
+
+pub struct MuxAES128<'a, A: AES128<'a> + AES128Ctr + AES128CBC> {
+    aes: &'a A,
+    ecb: OptionalCell<&'a dyn AES128ECB>,
+    clients: List<'a, VirtualAES128<'a, A>>,
+    inflight: OptionalCell<&'a VirtualAES128<'a, A>>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> MuxAES128<'a, A> {
+    pub fn new(aes: &'a A) -> MuxAES128<'a, A> {
+        aes.enable();
+        MuxAES128 {
+            aes: aes,
+            ecb: OptionalCell::empty(),
+            clients: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Loads the configuration of `node` into the hardware and starts its
+    /// operation.
+    fn start(&self, node: &'a VirtualAES128<'a, A>) -> ReturnCode {
+        let res = self.aes.set_key(&node.key.get());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let res = self.aes.set_iv(&node.chain.get());
+        if res != ReturnCode::SUCCESS {
+            return res;
+        }
+        let encrypting = node.encrypting.get();
+        match node.mode.get() {
+            Mode::Ecb => {
+                if self
+                    .ecb
+                    .map(|ecb| ecb.set_mode_aes128ecb(encrypting))
+                    .is_none()
+                {
+                    return ReturnCode::ENOSUPPORT;
+                }
+            }
+            Mode::Cbc => self.aes.set_mode_aes128cbc(encrypting),
+            Mode::Ctr => self.aes.set_mode_aes128ctr(encrypting),
+        }
+        self.aes.start_message();
+
+        let (start, stop) = node.range.get();
+        let dest = match node.dest.take() {
+            Some(dest) => dest,
+            None => return ReturnCode::FAIL,
+        };
+        match self.aes.crypt(node.source.take(), dest, start, stop) {
+            None => ReturnCode::SUCCESS,
+            Some((res, source, dest)) => {
+                node.source.put(source);
+                node.dest.replace(dest);
+                res
+            }
+        }
+    }
+
+    /// Starts the next queued operation, if the hardware is idle.
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let node = match self.clients.iter().find(|node| node.queued.get()) {
+                Some(node) => node,
+                None => return,
+            };
+            node.queued.set(false);
+            self.inflight.set(node);
+            if self.start(node) != ReturnCode::SUCCESS {
+                self.inflight.clear();
+                node.busy.set(false);
+                node.dest.take().map(|dest| {
+                    let source = node.source.take();
+                    node.client
+                        .map(move |client| client.crypt_done(source, dest));
+                });
+            }
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC + AES128ECB> MuxAES128<'a, A> {
+    /// Makes ECB mode available to the clients.
+    pub fn enable_ecb(&self) {
+        self.ecb.set(self.aes);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> symmetric_encryption::Client<'a>
+    for MuxAES128<'a, A>
+{
+    fn crypt_done(&'a self, source: Option<&'a mut [u8]>, dest: &'a mut [u8]) {
+        self.inflight.take().map(move |node| {
+            node.end_op(dest);
+            self.do_next_op();
+            node.busy.set(false);
+            node.client
+                .map(move |client| client.crypt_done(source, dest));
+        });
+    }
+}
+
+pub struct VirtualAES128<'a, A: AES128<'a> + AES128Ctr + AES128CBC> {
+    mux: &'a MuxAES128<'a, A>,
+    next: ListLink<'a, VirtualAES128<'a, A>>,
+    client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    iv: Cell<[u8; AES128_BLOCK_SIZE]>,
+    mode: Cell<Mode>,
+    encrypting: Cell<bool>,
+    /// Whether the next `crypt()` starts a message from `iv`.
+    new_message: Cell<bool>,
+    /// The IV the current operation starts from.
+    chain: Cell<[u8; AES128_BLOCK_SIZE]>,
+    /// The IV the next operation continues from, when it is known before the
+    /// current one completes.
+    next_chain: Cell<[u8; AES128_BLOCK_SIZE]>,
+
+    source: TakeCell<'a, [u8]>,
+    dest: TakeCell<'a, [u8]>,
+    range: Cell<(usize, usize)>,
+    queued: Cell<bool>,
+    busy: Cell<bool>,
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> VirtualAES128<'a, A> {
+    pub fn new(mux: &'a MuxAES128<'a, A>) -> VirtualAES128<'a, A> {
+        VirtualAES128 {
+            mux: mux,
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            iv: Cell::new([0; AES128_BLOCK_SIZE]),
+            mode: Cell::new(Mode::Ctr),
+            encrypting: Cell::new(true),
+            new_message: Cell::new(true),
+            chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            next_chain: Cell::new([0; AES128_BLOCK_SIZE]),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+            range: Cell::new((0, 0)),
+            queued: Cell::new(false),
+            busy: Cell::new(false),
+        }
+    }
+
+    /// Binds itself to the mux, should be called after `static_init!`.
+    pub fn setup(&'a self) {
+        self.mux.clients.push_head(self);
+    }
+
+    fn set_mode(&self, mode: Mode, encrypting: bool) {
+        self.mode.set(mode);
+        self.encrypting.set(encrypting);
+    }
+
+    /// Records the IV the next operation of the message continues from,
+    /// once the current one wrote its output to `dest`.
+    fn end_op(&self, dest: &[u8]) {
+        let (start, stop) = self.range.get();
+        if start == stop {
+            return;
+        }
+        match self.mode.get() {
+            Mode::Ecb => {}
+            Mode::Cbc if self.encrypting.get() => {
+                let mut last = [0; AES128_BLOCK_SIZE];
+                last.copy_from_slice(&dest[stop - AES128_BLOCK_SIZE..stop]);
+                self.chain.set(last);
+            }
+            Mode::Cbc | Mode::Ctr => self.chain.set(self.next_chain.get()),
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> AES128<'a> for VirtualAES128<'a, A> {
+    /// The engine is enabled by the mux.
+    fn enable(&self) {
+        self.mux.aes.enable();
+    }
+
+    /// Does nothing, as other clients may still use the engine.
+    fn disable(&self) {}
+
+    fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+        if iv.len() != AES128_BLOCK_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_iv = [0; AES128_BLOCK_SIZE];
+        new_iv.copy_from_slice(iv);
+        self.iv.set(new_iv);
+        ReturnCode::SUCCESS
+    }
+
+    fn start_message(&self) {
+        if !self.busy.get() {
+            self.new_message.set(true);
+        }
+    }
+
+    fn crypt(
+        &'a self,
+        source: Option<&'a mut [u8]>,
+        dest: &'a mut [u8],
+        start_index: usize,
+        stop_index: usize,
+    ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+        if self.busy.get() {
+            return Some((ReturnCode::EBUSY, source, dest));
+        }
+        let len = match stop_index.checked_sub(start_index) {
+            Some(len) if stop_index <= dest.len() && len % AES128_BLOCK_SIZE == 0 => len,
+            _ => return Some((ReturnCode::EINVAL, source, dest)),
+        };
+        if source.as_ref().map_or(false, |source| source.len() != len) {
+            return Some((ReturnCode::EINVAL, source, dest));
+        }
+        if self.mode.get() == Mode::Ecb && self.mux.ecb.is_none() {
+            return Some((ReturnCode::ENOSUPPORT, source, dest));
+        }
+
+        let new_message = self.new_message.replace(false);
+        if new_message {
+            self.chain.set(self.iv.get());
+        }
+        let blocks = len / AES128_BLOCK_SIZE;
+        match self.mode.get() {
+            Mode::Cbc if !self.encrypting.get() && blocks > 0 => {
+                // The input may be overwritten by the output
+                let input: &[u8] = match source {
+                    Some(ref source) => &source[len - AES128_BLOCK_SIZE..],
+                    None => &dest[stop_index - AES128_BLOCK_SIZE..stop_index],
+                };
+                let mut last = [0; AES128_BLOCK_SIZE];
+                last.copy_from_slice(input);
+                self.next_chain.set(last);
+            }
+            Mode::Ctr => {
+                let counter = u128::from_be_bytes(self.chain.get());
+                self.next_chain
+                    .set(counter.wrapping_add(blocks as u128).to_be_bytes());
+            }
+            _ => {}
+        }
+
+        self.source.put(source);
+        self.dest.replace(dest);
+        self.range.set((start_index, stop_index));
+        self.busy.set(true);
+        if self.mux.inflight.is_some() {
+            self.queued.set(true);
+            return None;
+        }
+
+        self.mux.inflight.set(self);
+        let res = self.mux.start(self);
+        if res == ReturnCode::SUCCESS {
+            None
+        } else {
+            self.mux.inflight.clear();
+            self.busy.set(false);
+            self.new_message.set(new_message);
+            let dest = self.dest.take().unwrap();
+            Some((res, self.source.take(), dest))
+        }
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> AES128Ctr for VirtualAES128<'a, A> {
+    fn set_mode_aes128ctr(&self, encrypting: bool) {
+        self.set_mode(Mode::Ctr, encrypting);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> AES128CBC for VirtualAES128<'a, A> {
+    fn set_mode_aes128cbc(&self, encrypting: bool) {
+        self.set_mode(Mode::Cbc, encrypting);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> AES128ECB for VirtualAES128<'a, A> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.set_mode(Mode::Ecb, encrypting);
+    }
+}
+
+impl<'a, A: AES128<'a> + AES128Ctr + AES128CBC> ListNode<'a, VirtualAES128<'a, A>>
+    for VirtualAES128<'a, A>
+{
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAES128<'a, A>> {
+        &self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    /// An engine with a toy block cipher, which keeps the CBC and CTR state
+    /// across `crypt()` calls like hardware does. Operations complete when
+    /// `complete()` is called.
+    struct ToyAes<'a> {
+        client: OptionalCell<&'a dyn symmetric_encryption::Client<'a>>,
+        key: Cell<[u8; 16]>,
+        iv: Cell<[u8; 16]>,
+        chain: Cell<[u8; 16]>,
+        mode: Cell<(Mode, bool)>,
+        source: TakeCell<'a, [u8]>,
+        dest: TakeCell<'a, [u8]>,
+    }
+
+    impl ToyAes<'_> {
+        fn encrypt(&self, block: [u8; 16]) -> [u8; 16] {
+            let mut out = [0; 16];
+            for i in 0..16 {
+                out[(i + 1) % 16] = block[i] ^ self.key.get()[i];
+            }
+            out
+        }
+
+        fn decrypt(&self, block: [u8; 16]) -> [u8; 16] {
+            let mut out = [0; 16];
+            for i in 0..16 {
+                out[i] = block[(i + 1) % 16] ^ self.key.get()[i];
+            }
+            out
+        }
+
+        fn complete(&self) {
+            let dest = self.dest.take().unwrap();
+            let source = self.source.take();
+            self.client
+                .map(move |client| client.crypt_done(source, dest));
+        }
+    }
+
+    fn xor(a: [u8; 16], b: [u8; 16]) -> [u8; 16] {
+        let mut out = a;
+        out.iter_mut().zip(b.iter()).for_each(|(o, b)| *o ^= *b);
+        out
+    }
+
+    impl<'a> AES128<'a> for ToyAes<'a> {
+        fn enable(&self) {}
+        fn disable(&self) {}
+        fn set_client(&'a self, client: &'a dyn symmetric_encryption::Client<'a>) {
+            self.client.set(client);
+        }
+        fn set_key(&self, key: &[u8]) -> ReturnCode {
+            let mut k = [0; 16];
+            k.copy_from_slice(key);
+            self.key.set(k);
+            ReturnCode::SUCCESS
+        }
+        fn set_iv(&self, iv: &[u8]) -> ReturnCode {
+            let mut v = [0; 16];
+            v.copy_from_slice(iv);
+            self.iv.set(v);
+            ReturnCode::SUCCESS
+        }
+        fn start_message(&self) {
+            self.chain.set(self.iv.get());
+        }
+        fn crypt(
+            &'a self,
+            source: Option<&'a mut [u8]>,
+            dest: &'a mut [u8],
+            start_index: usize,
+            stop_index: usize,
+        ) -> Option<(ReturnCode, Option<&'a mut [u8]>, &'a mut [u8])> {
+            for (i, pos) in (start_index..stop_index).step_by(16).enumerate() {
+                let mut input = [0; 16];
+                match source {
+                    Some(ref source) => input.copy_from_slice(&source[i * 16..i * 16 + 16]),
+                    None => input.copy_from_slice(&dest[pos..pos + 16]),
+                }
+                let chain = self.chain.get();
+                let output = match self.mode.get() {
+                    (Mode::Ecb, true) => self.encrypt(input),
+                    (Mode::Ecb, false) => self.decrypt(input),
+                    (Mode::Cbc, true) => {
+                        let out = self.encrypt(xor(input, chain));
+                        self.chain.set(out);
+                        out
+                    }
+                    (Mode::Cbc, false) => {
+                        self.chain.set(input);
+                        xor(self.decrypt(input), chain)
+                    }
+                    (Mode::Ctr, _) => {
+                        let counter = u128::from_be_bytes(chain).wrapping_add(1);
+                        self.chain.set(counter.to_be_bytes());
+                        xor(input, self.encrypt(chain))
+                    }
+                };
+                dest[pos..pos + 16].copy_from_slice(&output);
+            }
+            self.source.put(source);
+            self.dest.replace(dest);
+            None
+        }
+    }
+
+    impl AES128Ctr for ToyAes<'_> {
+        fn set_mode_aes128ctr(&self, encrypting: bool) {
+            self.mode.set((Mode::Ctr, encrypting));
+        }
+    }
+
+    impl AES128CBC for ToyAes<'_> {
+        fn set_mode_aes128cbc(&self, encrypting: bool) {
+            self.mode.set((Mode::Cbc, encrypting));
+        }
+    }
+
+    impl AES128ECB for ToyAes<'_> {
+        fn set_mode_aes128ecb(&self, encrypting: bool) {
+            self.mode.set((Mode::Ecb, encrypting));
+        }
+    }
+
+    struct TestClient {
+        done: TakeCell<'static, [u8]>,
+    }
+
+    impl symmetric_encryption::Client<'static> for TestClient {
+        fn crypt_done(&self, _source: Option<&'static mut [u8]>, dest: &'static mut [u8]) {
+            self.done.replace(dest);
+        }
+    }
+
+    type Virtual = VirtualAES128<'static, ToyAes<'static>>;
+
+    fn setup(
+        clients: usize,
+    ) -> (
+        &'static ToyAes<'static>,
+        Vec<(&'static Virtual, &'static TestClient)>,
+    ) {
+        let hw: &'static ToyAes = Box::leak(Box::new(ToyAes {
+            client: OptionalCell::empty(),
+            key: Cell::new([0; 16]),
+            iv: Cell::new([0; 16]),
+            chain: Cell::new([0; 16]),
+            mode: Cell::new((Mode::Ctr, true)),
+            source: TakeCell::empty(),
+            dest: TakeCell::empty(),
+        }));
+        let mux: &'static MuxAES128<ToyAes> = Box::leak(Box::new(MuxAES128::new(hw)));
+        hw.set_client(mux);
+        let clients = (0..clients)
+            .map(move |_| {
+                let aes: &'static Virtual = Box::leak(Box::new(VirtualAES128::new(mux)));
+                aes.setup();
+                let client: &'static TestClient = Box::leak(Box::new(TestClient {
+                    done: TakeCell::empty(),
+                }));
+                aes.set_client(client);
+                (aes, client)
+            })
+            .collect();
+        (hw, clients)
+    }
+
+    fn buffer(data: &[u8]) -> &'static mut [u8] {
+        Box::leak(data.to_vec().into_boxed_slice())
+    }
+
+    const KEYS: [[u8; 16]; 2] = [[0x11; 16], [0x5a; 16]];
+    const IVS: [[u8; 16]; 2] = [
+        [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xfe],
+        [7; 16],
+    ];
+
+    fn configure(aes: &Virtual, i: usize) {
+        assert_eq!(aes.set_key(&KEYS[i]), ReturnCode::SUCCESS);
+        assert_eq!(aes.set_iv(&IVS[i]), ReturnCode::SUCCESS);
+        if i == 0 {
+            aes.set_mode_aes128ctr(true);
+        } else {
+            aes.set_mode_aes128cbc(true);
+        }
+        aes.start_message();
+    }
+
+    #[test]
+    fn interleaved_messages() {
+        let messages: Vec<Vec<u8>> = (0..2)
+            .map(|i| (0..64).map(|b| (b * 3 + i * 100) as u8).collect())
+            .collect();
+
+        // Each message in one operation
+        let (hw, clients) = setup(1);
+        let (aes, client) = clients[0];
+        let mut expected = Vec::new();
+        for i in 0..2 {
+            configure(aes, i);
+            assert!(aes.crypt(None, buffer(&messages[i]), 0, 64).is_none());
+            hw.complete();
+            expected.push(client.done.take().unwrap().to_vec());
+        }
+
+        // Both messages in two operations each, sharing the engine
+        let (hw, clients) = setup(2);
+        for (i, (aes, _)) in clients.iter().enumerate() {
+            configure(aes, i);
+        }
+        let first = |i: usize| buffer(&messages[i][..32]);
+        let second = |i: usize| buffer(&messages[i][32..]);
+        let (aes0, client0) = clients[0];
+        let (aes1, client1) = clients[1];
+
+        assert!(aes0.crypt(None, first(0), 0, 32).is_none());
+        assert!(aes1
+            .crypt(Some(first(1)), buffer(&[0; 32]), 0, 32)
+            .is_none());
+        // Only one operation per client
+        assert_eq!(
+            aes0.crypt(None, second(0), 0, 32).unwrap().0,
+            ReturnCode::EBUSY
+        );
+        hw.complete();
+        let mut out0 = client0.done.take().unwrap().to_vec();
+        assert!(aes0.crypt(None, second(0), 0, 32).is_none());
+        hw.complete();
+        let mut out1 = client1.done.take().unwrap().to_vec();
+        assert!(aes1.crypt(None, second(1), 0, 32).is_none());
+        hw.complete();
+        out0.extend_from_slice(client0.done.take().unwrap());
+        hw.complete();
+        out1.extend_from_slice(client1.done.take().unwrap());
+
+        assert_eq!(out0, expected[0]);
+        assert_eq!(out1, expected[1]);
+
+        // CBC decryption in place, continuing from the input blocks
+        aes1.set_mode_aes128cbc(false);
+        aes1.start_message();
+        let ciphertext = buffer(&expected[1]);
+        assert!(aes1.crypt(None, ciphertext, 0, 48).is_none());
+        hw.complete();
+        let ciphertext = client1.done.take().unwrap();
+        assert!(aes1.crypt(None, ciphertext, 48, 64).is_none());
+        hw.complete();
+        assert_eq!(client1.done.take().unwrap(), &messages[1][..]);
+    }
+
+    #[test]
+    fn ecb_and_invalid_requests() {
+        let (hw, clients) = setup(1);
+        let (aes, client) = clients[0];
+        configure(aes, 0);
+        assert_eq!(
+            aes.crypt(None, buffer(&[0; 20]), 0, 20).unwrap().0,
+            ReturnCode::EINVAL
+        );
+        assert_eq!(
+            aes.crypt(Some(buffer(&[0; 32])), buffer(&[0; 16]), 0, 16)
+                .unwrap()
+                .0,
+            ReturnCode::EINVAL
+        );
+
+        aes.set_mode_aes128ecb(true);
+        assert_eq!(
+            aes.crypt(None, buffer(&[0; 16]), 0, 16).unwrap().0,
+            ReturnCode::ENOSUPPORT
+        );
+        aes.mux.enable_ecb();
+        assert!(aes.crypt(None, buffer(&[1; 16]), 0, 16).is_none());
+        hw.complete();
+        let block = client.done.take().unwrap();
+        aes.set_mode_aes128ecb(false);
+        assert!(aes.crypt(None, block, 0, 16).is_none());
+        hw.complete();
+        assert_eq!(client.done.take().unwrap(), &[1; 16][..]);
+    }
+}
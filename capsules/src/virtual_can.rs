@@ -0,0 +1,487 @@
+//! Virtualize a CAN controller.
+//!
+//! `MuxCan` provides shared access to a single CAN controller for multiple
+//! users. `CanDevice` provides access for a single client, and implements
+//! `hil::can::Can` itself so clients do not need to know whether they are
+//! virtualized.
+//!
+//! Each `CanDevice` has its own set of up to `FILTERS_PER_DEVICE` acceptance
+//! filters. The mux programs the union of the filters of all enabled devices
+//! into the controller, so frames nobody is interested in are still discarded
+//! in hardware. If the union does not fit in the controller, the controller is
+//! set to accept every frame and the mux does all of the filtering in
+//! software. A received frame is delivered to every enabled device with a
+//! matching filter.
+//!
+//! Transmissions are serialized: each device can have one frame outstanding,
+//! and the mux hands them to the controller one at a time. Changes of the
+//! controller error state are reported to every device.
+//!
+//! The controller is enabled when the first device is enabled and disabled
+//! once no device is enabled anymore. The bit rate is shared by all devices,
+//! and can only be changed while the controller is disabled.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::{hil, static_init};
+//! # use capsules::virtual_can::{CanDevice, MuxCan};
+//!
+//! let can_mux = static_init!(MuxCan<'static>, MuxCan::new(&peripherals.can1));
+//! hil::can::Can::set_client(&peripherals.can1, can_mux);
+//! can_mux.set_bitrate(500_000);
+//!
+//! let can_device = static_init!(CanDevice<'static>, CanDevice::new(can_mux));
+//! can_device.add_to_mux();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::can::{self, ErrorState, Filter, Frame, Id};
+use kernel::ReturnCode;
+
+/// Number of acceptance filters each `CanDevice` supports.
+pub const FILTERS_PER_DEVICE: usize = 4;
+
+/// Largest number of hardware filters the mux programs. Controllers with
+/// more filters only have this many used.
+const MAX_HW_FILTERS: usize = 16;
+
+pub struct MuxCan<'a> {
+    can: &'a dyn can::Can<'a>,
+    devices: List<'a, CanDevice<'a>>,
+    inflight: OptionalCell<&'a CanDevice<'a>>,
+}
+
+impl<'a> MuxCan<'a> {
+    pub const fn new(can: &'a dyn can::Can<'a>) -> MuxCan<'a> {
+        MuxCan {
+            can: can,
+            devices: List::new(),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    /// Set the bit rate of the bus. Fails with `EBUSY` while any device is
+    /// enabled.
+    pub fn set_bitrate(&self, bitrate: u32) -> ReturnCode {
+        self.can.set_bitrate(bitrate)
+    }
+
+    /// Turn the controller on or off depending on whether any device is
+    /// enabled, and program the filters of the enabled devices.
+    fn update(&self) -> ReturnCode {
+        let any_enabled = self.devices.iter().any(|device| device.enabled.get());
+        if !any_enabled {
+            self.inflight.clear();
+            return if self.can.is_enabled() {
+                self.can.disable()
+            } else {
+                ReturnCode::SUCCESS
+            };
+        }
+
+        let rcode = self.update_filters();
+        if rcode != ReturnCode::SUCCESS {
+            return rcode;
+        }
+        if self.can.is_enabled() {
+            ReturnCode::SUCCESS
+        } else {
+            self.can.enable()
+        }
+    }
+
+    fn update_filters(&self) -> ReturnCode {
+        let hw_count = core::cmp::min(self.can.filter_count(), MAX_HW_FILTERS);
+        let mut union = [Filter::any(Id::Standard(0)); MAX_HW_FILTERS];
+        let mut count = 0;
+        let mut overflow = false;
+
+        for device in self.devices.iter().filter(|device| device.enabled.get()) {
+            for filter in device.filters.get().iter().filter_map(|filter| *filter) {
+                if union[..count].contains(&filter) {
+                    continue;
+                }
+                if count == hw_count {
+                    overflow = true;
+                    break;
+                }
+                union[count] = filter;
+                count += 1;
+            }
+        }
+
+        if overflow {
+            // Let everything through; `frame_received` filters per device.
+            let all = [Filter::any(Id::Standard(0)), Filter::any(Id::Extended(0))];
+            self.can
+                .set_filters(&all[..core::cmp::min(hw_count, all.len())])
+        } else {
+            self.can.set_filters(&union[..count])
+        }
+    }
+
+    /// Hand queued frames to the controller until one is accepted. Frames the
+    /// controller refuses are completed immediately with the error.
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let node = match self.devices.iter().find(|node| node.tx.is_some()) {
+                Some(node) => node,
+                None => return,
+            };
+            node.tx.take().map(|frame| {
+                let rcode = self.can.send(&frame);
+                if rcode == ReturnCode::SUCCESS {
+                    self.inflight.set(node);
+                } else {
+                    node.client.map(|client| client.transmit_complete(rcode));
+                }
+            });
+        }
+    }
+
+    fn send(&self, device: &'a CanDevice<'a>, frame: &Frame) -> ReturnCode {
+        if self.inflight.is_none() {
+            let rcode = self.can.send(frame);
+            if rcode == ReturnCode::SUCCESS {
+                self.inflight.set(device);
+            }
+            rcode
+        } else {
+            device.tx.set(*frame);
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl can::Client for MuxCan<'_> {
+    fn transmit_complete(&self, result: ReturnCode) {
+        self.inflight.take().map(|device| {
+            device.client.map(|client| client.transmit_complete(result));
+        });
+        self.do_next_op();
+    }
+
+    fn frame_received(&self, frame: &Frame) {
+        for device in self.devices.iter() {
+            if device.enabled.get() && device.accepts(frame.id) {
+                device.client.map(|client| client.frame_received(frame));
+            }
+        }
+    }
+
+    fn error_state_changed(&self, state: ErrorState) {
+        for device in self.devices.iter() {
+            device
+                .client
+                .map(|client| client.error_state_changed(state));
+        }
+        if state == ErrorState::BusOff {
+            // The controller stops transmitting; fail everything queued
+            // rather than leaving it pending until the bus recovers.
+            for device in self.devices.iter() {
+                device.tx.take().map(|_| {
+                    device
+                        .client
+                        .map(|client| client.transmit_complete(ReturnCode::FAIL));
+                });
+            }
+        }
+    }
+}
+
+pub struct CanDevice<'a> {
+    mux: &'a MuxCan<'a>,
+    enabled: Cell<bool>,
+    filters: Cell<[Option<Filter>; FILTERS_PER_DEVICE]>,
+    tx: OptionalCell<Frame>,
+    client: OptionalCell<&'a dyn can::Client>,
+    next: ListLink<'a, CanDevice<'a>>,
+}
+
+impl<'a> CanDevice<'a> {
+    pub const fn new(mux: &'a MuxCan<'a>) -> CanDevice<'a> {
+        CanDevice {
+            mux: mux,
+            enabled: Cell::new(false),
+            filters: Cell::new([None; FILTERS_PER_DEVICE]),
+            tx: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+            next: ListLink::empty(),
+        }
+    }
+
+    /// Must be called right after `static_init!()`.
+    pub fn add_to_mux(&'a self) {
+        self.mux.devices.push_head(self);
+    }
+
+    fn accepts(&self, id: Id) -> bool {
+        self.filters
+            .get()
+            .iter()
+            .filter_map(|filter| *filter)
+            .any(|filter| filter.matches(id))
+    }
+
+    fn is_transmitting(&self) -> bool {
+        self.tx.is_some()
+            || self
+                .mux
+                .inflight
+                .map_or(false, |inflight| core::ptr::eq(*inflight, self))
+    }
+}
+
+impl<'a> ListNode<'a, CanDevice<'a>> for CanDevice<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, CanDevice<'a>> {
+        &self.next
+    }
+}
+
+impl<'a> can::Can<'a> for CanDevice<'a> {
+    fn set_client(&self, client: &'a dyn can::Client) {
+        self.client.set(client);
+    }
+
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode {
+        self.mux.set_bitrate(bitrate)
+    }
+
+    fn enable(&self) -> ReturnCode {
+        self.enabled.set(true);
+        self.mux.update()
+    }
+
+    /// Frames queued by this device that have not been handed to the
+    /// controller yet are dropped without a callback.
+    fn disable(&self) -> ReturnCode {
+        self.enabled.set(false);
+        self.tx.clear();
+        self.mux.update()
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn send(&self, frame: &Frame) -> ReturnCode {
+        if !frame.is_valid() {
+            ReturnCode::EINVAL
+        } else if !self.enabled.get() || self.mux.can.error_state() == ErrorState::BusOff {
+            ReturnCode::EOFF
+        } else if self.is_transmitting() {
+            ReturnCode::EBUSY
+        } else {
+            // The mux keeps `'a` references to its devices, look ours up in
+            // the list rather than requiring `&'a self` here.
+            self.mux
+                .devices
+                .iter()
+                .find(|device| core::ptr::eq(*device, self))
+                .map_or(ReturnCode::EOFF, |device| self.mux.send(device, frame))
+        }
+    }
+
+    fn filter_count(&self) -> usize {
+        FILTERS_PER_DEVICE
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode {
+        if filters.len() > FILTERS_PER_DEVICE {
+            return ReturnCode::ESIZE;
+        }
+        let mut new = [None; FILTERS_PER_DEVICE];
+        for (slot, filter) in new.iter_mut().zip(filters.iter()) {
+            *slot = Some(*filter);
+        }
+        self.filters.set(new);
+        if self.enabled.get() {
+            self.mux.update_filters()
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+
+    fn error_state(&self) -> ErrorState {
+        self.mux.can.error_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CanDevice, MuxCan};
+    use crate::can_loopback::CanLoopback;
+    use core::cell::Cell;
+    use kernel::common::dynamic_deferred_call::DynamicDeferredCall;
+    use kernel::hil::can::{self, Can, ErrorState, Filter, Frame, Id};
+    use kernel::ReturnCode;
+
+    #[derive(Default)]
+    struct TestClient {
+        sent: Cell<Option<ReturnCode>>,
+        received: Cell<usize>,
+        last: Cell<Option<Frame>>,
+        state: Cell<Option<ErrorState>>,
+    }
+
+    impl can::Client for TestClient {
+        fn transmit_complete(&self, result: ReturnCode) {
+            self.sent.set(Some(result));
+        }
+
+        fn frame_received(&self, frame: &Frame) {
+            self.received.set(self.received.get() + 1);
+            self.last.set(Some(*frame));
+        }
+
+        fn error_state_changed(&self, state: ErrorState) {
+            self.state.set(Some(state));
+        }
+    }
+
+    fn frame(id: u16) -> Frame {
+        Frame::new(Id::Standard(id), &[id as u8, 0xAA]).unwrap()
+    }
+
+    #[test]
+    fn test_filters_route_frames() {
+        let ddc = DynamicDeferredCall::new(&[]);
+        let loopback = CanLoopback::new(&ddc);
+        let mux = MuxCan::new(&loopback);
+        loopback.set_client(&mux);
+        let (dev_a, dev_b) = (CanDevice::new(&mux), CanDevice::new(&mux));
+        dev_a.add_to_mux();
+        dev_b.add_to_mux();
+        let (client_a, client_b) = (TestClient::default(), TestClient::default());
+        dev_a.set_client(&client_a);
+        dev_b.set_client(&client_b);
+
+        dev_a.set_filters(&[Filter::exact(Id::Standard(0x100))]);
+        dev_b.set_filters(&[Filter {
+            id: Id::Standard(0x200),
+            mask: 0x700,
+        }]);
+        assert_eq!(dev_a.enable(), ReturnCode::SUCCESS);
+        assert_eq!(dev_b.enable(), ReturnCode::SUCCESS);
+        assert!(loopback.is_enabled());
+        assert_eq!(loopback.hardware_filters(), 2);
+
+        assert_eq!(dev_a.send(&frame(0x234)), ReturnCode::SUCCESS);
+        assert_eq!(dev_a.send(&frame(0x100)), ReturnCode::EBUSY);
+        loopback.service();
+        assert_eq!(client_a.sent.get(), Some(ReturnCode::SUCCESS));
+        assert_eq!(client_a.received.get(), 0);
+        assert_eq!(client_b.received.get(), 1);
+        assert_eq!(client_b.last.get(), Some(frame(0x234)));
+
+        // Frames matching no filter are dropped by the "hardware".
+        assert_eq!(dev_b.send(&frame(0x300)), ReturnCode::SUCCESS);
+        loopback.service();
+        assert_eq!(client_b.sent.get(), Some(ReturnCode::SUCCESS));
+        assert_eq!(client_a.received.get() + client_b.received.get(), 1);
+        assert_eq!(loopback.dropped(), 1);
+    }
+
+    #[test]
+    fn test_filter_overflow_falls_back_to_software() {
+        let ddc = DynamicDeferredCall::new(&[]);
+        let loopback = CanLoopback::new(&ddc);
+        let mux = MuxCan::new(&loopback);
+        loopback.set_client(&mux);
+        let (dev_a, dev_b) = (CanDevice::new(&mux), CanDevice::new(&mux));
+        dev_a.add_to_mux();
+        dev_b.add_to_mux();
+        let (client_a, client_b) = (TestClient::default(), TestClient::default());
+        dev_a.set_client(&client_a);
+        dev_b.set_client(&client_b);
+
+        let exact = |id| Filter::exact(Id::Standard(id));
+        dev_a.set_filters(&[exact(0x1), exact(0x2), exact(0x3), exact(0x4)]);
+        dev_b.set_filters(&[exact(0x5), exact(0x6), exact(0x7)]);
+        dev_a.enable();
+        dev_b.enable();
+        // Seven filters do not fit in the loopback controller.
+        assert_eq!(loopback.hardware_filters(), 2);
+
+        dev_a.send(&frame(0x6));
+        loopback.service();
+        assert_eq!(client_a.received.get(), 0);
+        assert_eq!(client_b.received.get(), 1);
+        assert_eq!(loopback.dropped(), 0);
+
+        // Disabling a device shrinks the union back into hardware.
+        dev_b.disable();
+        assert_eq!(loopback.hardware_filters(), 4);
+        dev_a.disable();
+        assert!(!loopback.is_enabled());
+    }
+
+    #[test]
+    fn test_transmissions_are_serialized() {
+        let ddc = DynamicDeferredCall::new(&[]);
+        let loopback = CanLoopback::new(&ddc);
+        let mux = MuxCan::new(&loopback);
+        loopback.set_client(&mux);
+        let (dev_a, dev_b) = (CanDevice::new(&mux), CanDevice::new(&mux));
+        dev_a.add_to_mux();
+        dev_b.add_to_mux();
+        let (client_a, client_b) = (TestClient::default(), TestClient::default());
+        dev_a.set_client(&client_a);
+        dev_b.set_client(&client_b);
+        dev_a.set_filters(&[Filter::any(Id::Extended(0))]);
+        dev_a.enable();
+        dev_b.enable();
+
+        let extended = Frame::new(Id::Extended(0x1234_5678), &[1, 2, 3]).unwrap();
+        assert_eq!(dev_b.send(&frame(0x10)), ReturnCode::SUCCESS);
+        assert_eq!(dev_b.send(&extended), ReturnCode::EBUSY);
+        assert_eq!(dev_a.send(&extended), ReturnCode::SUCCESS);
+
+        loopback.service();
+        assert_eq!(client_b.sent.get(), Some(ReturnCode::SUCCESS));
+        assert_eq!(client_a.sent.get(), None);
+        // The queued frame was handed to the controller on completion.
+        loopback.service();
+        assert_eq!(client_a.sent.get(), Some(ReturnCode::SUCCESS));
+        assert_eq!(client_a.last.get(), Some(extended));
+        assert_eq!(client_a.last.get().unwrap().data(), &[1, 2, 3]);
+    }
+
+    #[test]
+    fn test_bus_off_is_broadcast() {
+        let ddc = DynamicDeferredCall::new(&[]);
+        let loopback = CanLoopback::new(&ddc);
+        let mux = MuxCan::new(&loopback);
+        loopback.set_client(&mux);
+        let (dev_a, dev_b) = (CanDevice::new(&mux), CanDevice::new(&mux));
+        dev_a.add_to_mux();
+        dev_b.add_to_mux();
+        let (client_a, client_b) = (TestClient::default(), TestClient::default());
+        dev_a.set_client(&client_a);
+        dev_b.set_client(&client_b);
+        dev_a.enable();
+        dev_b.enable();
+
+        dev_a.send(&frame(0x1));
+        dev_b.send(&frame(0x2));
+        loopback.set_error_state(ErrorState::BusOff);
+
+        assert_eq!(client_a.state.get(), Some(ErrorState::BusOff));
+        assert_eq!(client_b.state.get(), Some(ErrorState::BusOff));
+        assert_eq!(client_a.sent.get(), Some(ReturnCode::FAIL));
+        assert_eq!(client_b.sent.get(), Some(ReturnCode::FAIL));
+        assert_eq!(dev_a.send(&frame(0x1)), ReturnCode::EOFF);
+
+        // Restarting the controller recovers from bus-off.
+        dev_a.disable();
+        dev_b.disable();
+        dev_a.enable();
+        assert_eq!(dev_a.error_state(), ErrorState::Active);
+        assert_eq!(dev_a.send(&frame(0x1)), ReturnCode::SUCCESS);
+    }
+}
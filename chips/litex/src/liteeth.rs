@@ -3,6 +3,11 @@
 //! The hardware source and any documentation can be found in the
 //! [LiteEth Git
 //! repository](https://github.com/enjoy-digital/liteeth).
+//!
+//! LiteEth has no address filter of its own, so frames are filtered in
+//! software against the configured MAC address, the broadcast address and
+//! a small multicast table. The PHY is not managed by this driver, and the
+//! link is reported as up once the MAC is initialized.
 
 use crate::event_manager::LiteXEventManager;
 use crate::litex_registers::{LiteXSoCRegisterConfiguration, Read, Write};
@@ -11,6 +16,7 @@ use core::slice;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::common::StaticRef;
 use kernel::debug;
+use kernel::hil::ethernet::{self, MacAddress};
 use kernel::ReturnCode;
 
 // Both events have the same index since they are located on different
@@ -18,6 +24,9 @@ use kernel::ReturnCode;
 const LITEETH_TX_EVENT: usize = 0;
 const LITEETH_RX_EVENT: usize = 0;
 
+/// Number of multicast addresses the software filter can hold.
+pub const MULTICAST_FILTER_SIZE: usize = 8;
+
 type LiteEthRXEV<'a, R> = LiteXEventManager<
     'a,
     u8,
@@ -80,11 +89,6 @@ impl<R: LiteXSoCRegisterConfiguration> LiteEthMacRegisters<R> {
     }
 }
 
-pub trait LiteEthClient {
-    fn tx_done(&self, rc: ReturnCode, packet_buffer: &'static mut [u8]);
-    fn rx_packet(&self, packet: &'static mut [u8], len: usize);
-}
-
 pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     mac_regs: StaticRef<LiteEthMacRegisters<R>>,
     mac_memory_base: usize,
@@ -92,10 +96,13 @@ pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     slot_size: usize,
     rx_slots: usize,
     tx_slots: usize,
-    client: OptionalCell<&'a dyn LiteEthClient>,
+    client: OptionalCell<&'a dyn ethernet::Client>,
     tx_packet: TakeCell<'static, [u8]>,
     rx_buffer: TakeCell<'static, [u8]>,
     initialized: Cell<bool>,
+    mac_address: Cell<MacAddress>,
+    multicast: Cell<[Option<MacAddress>; MULTICAST_FILTER_SIZE]>,
+    promiscuous: Cell<bool>,
 }
 
 impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
@@ -107,6 +114,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         rx_slots: usize,
         tx_slots: usize,
         rx_buffer: &'static mut [u8],
+        mac_address: MacAddress,
     ) -> LiteEth<'a, R> {
         LiteEth {
             mac_regs,
@@ -119,13 +127,12 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
             tx_packet: TakeCell::empty(),
             rx_buffer: TakeCell::new(rx_buffer),
             initialized: Cell::new(false),
+            mac_address: Cell::new(mac_address),
+            multicast: Cell::new([None; MULTICAST_FILTER_SIZE]),
+            promiscuous: Cell::new(false),
         }
     }
 
-    pub fn set_client(&self, client: &'a dyn LiteEthClient) {
-        self.client.set(client);
-    }
-
     pub fn initialize(&self) {
         // Sanity check the memory parameters
         //
@@ -173,42 +180,26 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         ))
     }
 
-    pub fn return_rx_buffer(&self, rx_buffer: &'static mut [u8]) {
-        // Assert that we won't overwrite a buffer
-        assert!(
-            self.rx_buffer.is_none(),
-            "LiteEth: return RX buffer while one is registered"
-        );
-
-        // Put the buffer back
-        self.rx_buffer.replace(rx_buffer);
-
-        // In case we received a packet RX interrupt but couldn't
-        // handle it due to the missing buffer, reenable RX interrupts
-        self.mac_regs.rx_ev().enable_event(LITEETH_RX_EVENT);
+    /// Whether a frame with the destination address `dst` passes the
+    /// software address filter.
+    fn accepts(&self, dst: MacAddress) -> bool {
+        self.promiscuous.get()
+            || dst == self.mac_address.get()
+            || dst.is_broadcast()
+            || self.multicast.get().iter().any(|entry| *entry == Some(dst))
     }
 
     fn rx_interrupt(&self) {
-        // Check whether we have a buffer to read the packet into. If
-        // not, we must disable, but not clear the event and enable it
-        // again as soon as we get the buffer back from the client
-        if self.rx_buffer.is_none() {
-            self.mac_regs.rx_ev().disable_event(LITEETH_RX_EVENT);
-        } else {
-            // Get the buffer first to be able to check the length
-            let rx_buffer = self.rx_buffer.take().unwrap();
-
+        self.rx_buffer.take().map(|rx_buffer| {
             // Get the frame length. If it exceeds the length of the
-            // rx_buffer, discard the packet, put the buffer back
+            // rx_buffer or is too short to hold a header, discard the
+            // packet
             let pkt_len = self.mac_regs.rx_length.get() as usize;
-            if pkt_len > rx_buffer.len() {
+            if pkt_len > rx_buffer.len() || pkt_len < ethernet::HEADER_LEN {
                 debug!("LiteEth: discarding ethernet packet with len {}", pkt_len);
 
                 // Acknowledge the interrupt so that the HW may use the slot again
                 self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
-
-                // Replace the buffer
-                self.rx_buffer.replace(rx_buffer);
             } else {
                 // Obtain the packet slot id
                 let slot_id: usize = self.mac_regs.rx_slot.get().into();
@@ -226,18 +217,23 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
                 // so that the slot is ready for use again
                 self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
 
-                self.client
-                    .map(move |client| client.rx_packet(rx_buffer, pkt_len));
+                let mut dst = [0; 6];
+                dst.copy_from_slice(&rx_buffer[0..6]);
+                if self.accepts(MacAddress::new(dst)) {
+                    self.client
+                        .map(|client| client.frame_received(&rx_buffer[..pkt_len]));
+                }
             }
-        }
+            self.rx_buffer.replace(rx_buffer);
+        });
     }
 
     /// Transmit an ethernet packet over the interface
     ///
     /// For now this will only use a single slot on the interface and
     /// is therefore blocking. A client must wait until a callback to
-    /// `tx_done` prior to sending a new packet.
-    pub fn transmit(
+    /// `transmit_done` prior to sending a new packet.
+    fn transmit_packet(
         &self,
         packet: &'static mut [u8],
         len: usize,
@@ -246,17 +242,25 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
             return Err((ReturnCode::EINVAL, packet));
         }
 
+        if !self.initialized.get() {
+            return Err((ReturnCode::EOFF, packet));
+        }
+
         if self.tx_packet.is_some() {
             return Err((ReturnCode::EBUSY, packet));
         }
 
         let slot = unsafe { self.get_slot_buffer(true, 0) }.expect("LiteEth: no TX slot");
-        if slot.len() < len {
+        let wire_len = core::cmp::max(len, ethernet::MIN_FRAME_LEN);
+        if slot.len() < wire_len {
             return Err((ReturnCode::ESIZE, packet));
         }
 
-        // Copy the packet into the slot HW buffer
+        // Copy the packet into the slot HW buffer, padding runt frames
         slot[..len].copy_from_slice(&packet[..len]);
+        for byte in slot[len..wire_len].iter_mut() {
+            *byte = 0;
+        }
 
         // Put the currently transmitting packet into the designated
         // TakeCell
@@ -264,7 +268,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
 
         // Set the slot and packet length
         self.mac_regs.tx_slot.set(0);
-        self.mac_regs.tx_length.set(len as u16);
+        self.mac_regs.tx_length.set(wire_len as u16);
 
         // Wait for the device to be ready to transmit
         while self.mac_regs.tx_ready.get() == 0 {}
@@ -292,7 +296,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
             .take()
             .expect("LiteEth: TakeCell empty in tx callback");
         self.client
-            .map(move |client| client.tx_done(ReturnCode::SUCCESS, packet));
+            .map(move |client| client.transmit_done(packet, ReturnCode::SUCCESS));
     }
 
     pub fn service_interrupt(&self) {
@@ -309,3 +313,71 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         }
     }
 }
+
+impl<'a, R: LiteXSoCRegisterConfiguration> ethernet::Ethernet<'a> for LiteEth<'a, R> {
+    fn set_client(&self, client: &'a dyn ethernet::Client) {
+        self.client.set(client);
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address.get()
+    }
+
+    fn set_mac_address(&self, address: MacAddress) -> ReturnCode {
+        if address.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        self.mac_address.set(address);
+        ReturnCode::SUCCESS
+    }
+
+    fn link_up(&self) -> bool {
+        self.initialized.get()
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])> {
+        self.transmit_packet(frame, len)
+    }
+
+    fn multicast_filter_size(&self) -> usize {
+        MULTICAST_FILTER_SIZE
+    }
+
+    fn add_multicast(&self, address: MacAddress) -> ReturnCode {
+        if !address.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        let mut filter = self.multicast.get();
+        if filter.iter().any(|entry| *entry == Some(address)) {
+            return ReturnCode::SUCCESS;
+        }
+        match filter.iter_mut().find(|entry| entry.is_none()) {
+            Some(entry) => {
+                *entry = Some(address);
+                self.multicast.set(filter);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    fn remove_multicast(&self, address: MacAddress) -> ReturnCode {
+        let mut filter = self.multicast.get();
+        match filter.iter_mut().find(|entry| **entry == Some(address)) {
+            Some(entry) => {
+                *entry = None;
+                self.multicast.set(filter);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn set_promiscuous(&self, promiscuous: bool) {
+        self.promiscuous.set(promiscuous);
+    }
+}
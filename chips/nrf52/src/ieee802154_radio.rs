@@ -28,6 +28,11 @@ pub const IEEE802154_BACKOFF_PERIOD: usize = 320; //microseconds = 20 symbols
 pub const IEEE802154_ACK_TIME: usize = 512; //microseconds = 32 symbols
 pub const IEEE802154_MAX_POLLING_ATTEMPTS: u8 = 4;
 pub const IEEE802154_MIN_BE: u8 = 3;
+/// Number of additional 128 us energy samples in an energy detection, whose
+/// maximum is reported
+pub const IEEE802154_ED_COUNT: u32 = 15;
+/// Factor from an energy sample to the IEEE 802.15.4 ED level
+pub const IEEE802154_ED_RSSISCALE: u32 = 4;
 pub const IEEE802154_MAX_BE: u8 = 5;
 pub const RAM_LEN_BITS: usize = 8;
 pub const RAM_S1_BITS: usize = 0;
@@ -143,8 +148,12 @@ struct RadioRegisters {
     /// Stop the bit counter
     /// - Address: 0x020 - 0x024
     task_bcstop: WriteOnly<u32, Task::Register>,
-    /// Reserved
-    _reserved1: [u32; 2],
+    /// Start the energy detect measurement used in IEEE 802.15.4 mode
+    /// - Address: 0x024 - 0x028
+    task_edstart: WriteOnly<u32, Task::Register>,
+    /// Stop the energy detect measurement
+    /// - Address: 0x028 - 0x02c
+    task_edstop: WriteOnly<u32, Task::Register>,
     /// Stop the bit counter
     /// - Address: 0x02c - 0x030
     task_ccastart: WriteOnly<u32, Task::Register>,
@@ -193,8 +202,12 @@ struct RadioRegisters {
     /// IEEE 802.15.4 length field received
     /// - Address: 0x138 - 0x13c
     event_framestart: ReadWrite<u32, Event::Register>,
-    /// Reserved
-    _reserved5: [u32; 2],
+    /// Sampling of energy detection complete
+    /// - Address: 0x13c - 0x140
+    event_edend: ReadWrite<u32, Event::Register>,
+    /// The sampling of energy detection has stopped
+    /// - Address: 0x140 - 0x144
+    event_edstopped: ReadWrite<u32, Event::Register>,
     /// Wireless medium in idle - clear to send
     /// - Address: 0x144-0x148
     event_ccaidle: ReadWrite<u32, Event::Register>,
@@ -321,7 +334,13 @@ struct RadioRegisters {
     /// - Address: 0x650 - 0x654
     modecnf0: ReadWrite<u32, RadioModeConfig::Register>,
     /// Reserved
-    _reserved16: [u32; 6],
+    _reserved16: [u32; 4],
+    /// IEEE 802.15.4 energy detect loop count
+    /// - Address: 0x664 - 0x668
+    edcnt: ReadWrite<u32, EnergyDetectCount::Register>,
+    /// IEEE 802.15.4 energy detect level
+    /// - Address: 0x668 - 0x66C
+    edsample: ReadOnly<u32, EnergyDetectSample::Register>,
     /// Clear Channel Assesment (CCA) control register
     /// - Address: 0x66C - 0x670
     ccactrl: ReadWrite<u32, CCAControl::Register>,
@@ -388,6 +407,8 @@ register_bitfields! [u32,
         CRCERROR OFFSET(13) NUMBITS(1),
         /// CCAIDLE event
         FRAMESTART OFFSET(14) NUMBITS(1),
+        /// EDEND event
+        EDEND OFFSET(15) NUMBITS(1),
         /// CCAIDLE event
         CCAIDLE OFFSET(17) NUMBITS(1),
         /// CCABUSY event
@@ -639,6 +660,17 @@ register_bitfields! [u32,
         CCACORRTHRESH OFFSET(16) NUMBITS(8) [],
         CCACORRCNT OFFSET(24) NUMBITS(8) []
     ],
+    /// Energy detect loop count register
+    EnergyDetectCount [
+        /// Number of iterations to perform an ED scan, whose maximum is
+        /// kept in EDSAMPLE
+        EDCNT OFFSET(0) NUMBITS(21)
+    ],
+    /// Energy detect level register
+    EnergyDetectSample [
+        /// Energy level of the last ED scan
+        EDLVL OFFSET(0) NUMBITS(8)
+    ],
     /// Radio mode configuration register
     RadioModeConfig [
         /// Radio ramp-up time
@@ -663,6 +695,7 @@ pub struct Radio<'p> {
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'static dyn radio::RxClient>,
     tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    ed_client: OptionalCell<&'static dyn radio::EnergyDetectClient>,
     tx_buf: TakeCell<'static, [u8]>,
     rx_buf: TakeCell<'static, [u8]>,
     addr: Cell<u16>,
@@ -673,6 +706,7 @@ pub struct Radio<'p> {
     random_nonce: Cell<u32>,
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
+    energy_detecting: Cell<bool>,
     timer0: OptionalCell<&'p crate::timer::TimerAlarm<'p>>,
     ppi: &'p crate::ppi::Ppi,
 }
@@ -684,6 +718,7 @@ impl<'p> Radio<'p> {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            ed_client: OptionalCell::empty(),
             tx_buf: TakeCell::empty(),
             rx_buf: TakeCell::empty(),
             addr: Cell::new(0),
@@ -694,6 +729,7 @@ impl<'p> Radio<'p> {
             random_nonce: Cell::new(0xDEADBEEF),
             channel: Cell::new(RadioChannel::DataChannel26),
             transmitting: Cell::new(false),
+            energy_detecting: Cell::new(false),
             timer0: OptionalCell::empty(),
             ppi,
         }
@@ -765,6 +801,15 @@ impl<'p> Radio<'p> {
         buffer
     }
 
+    // Energy detection needs the receiver ramped up but not receiving.
+    fn start_energy_detect(&self) {
+        self.registers.event_edend.write(Event::READY::CLEAR);
+        self.registers
+            .edcnt
+            .write(EnergyDetectCount::EDCNT.val(IEEE802154_ED_COUNT));
+        self.registers.task_edstart.write(Task::ENABLE::SET);
+    }
+
     // TODO: Theres an additional step for 802154 rx/tx handling
     #[inline(never)]
     pub fn handle_interrupt(&self) {
@@ -780,11 +825,31 @@ impl<'p> Radio<'p> {
                     self.ppi.disable(ppi::Channel::CH21::SET);
                 }
                 self.registers.task_ccastart.write(Task::ENABLE::SET);
+            } else if self.energy_detecting.get()
+                && self.registers.state.get() == nrf5x::constants::RADIO_STATE_RXIDLE
+            {
+                self.start_energy_detect();
             } else {
                 self.registers.task_start.write(Task::ENABLE::SET);
             }
         }
 
+        if self.registers.event_edend.is_set(Event::READY) {
+            self.registers.event_edend.write(Event::READY::CLEAR);
+            if self.energy_detecting.get() {
+                self.energy_detecting.set(false);
+                let sample = self.registers.edsample.read(EnergyDetectSample::EDLVL);
+                let level = (sample * IEEE802154_ED_RSSISCALE).min(255) as u8;
+                self.ed_client
+                    .map(|client| client.energy_detect_done(level, ReturnCode::SUCCESS));
+                // Resume receiving unless the client asked for another
+                // measurement
+                if !self.energy_detecting.get() {
+                    self.registers.task_start.write(Task::ENABLE::SET);
+                }
+            }
+        }
+
         if self.registers.event_framestart.is_set(Event::READY) {
             self.registers.event_framestart.write(Event::READY::CLEAR);
         }
@@ -891,7 +956,8 @@ impl<'p> Radio<'p> {
                 + Interrupt::CCAIDLE::SET
                 + Interrupt::CCABUSY::SET
                 + Interrupt::END::SET
-                + Interrupt::FRAMESTART::SET,
+                + Interrupt::FRAMESTART::SET
+                + Interrupt::EDEND::SET,
         );
     }
 
@@ -1119,6 +1185,34 @@ impl<'p> kernel::hil::radio::RadioConfig for Radio<'p> {
         }
     }
 
+    fn energy_detect(&self) -> ReturnCode {
+        if self.transmitting.get() || self.energy_detecting.get() {
+            return ReturnCode::EBUSY;
+        }
+        match self.registers.state.get() {
+            nrf5x::constants::RADIO_STATE_RX => {
+                self.energy_detecting.set(true);
+                self.registers.task_stop.write(Task::ENABLE::SET);
+                self.start_energy_detect();
+            }
+            nrf5x::constants::RADIO_STATE_RXIDLE => {
+                self.energy_detecting.set(true);
+                self.start_energy_detect();
+            }
+            // Started by the READY event once the receiver has ramped up
+            nrf5x::constants::RADIO_STATE_RXRU => {
+                self.energy_detecting.set(true);
+            }
+            nrf5x::constants::RADIO_STATE_DISABLE => return ReturnCode::EOFF,
+            _ => return ReturnCode::EBUSY,
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
     fn set_tx_power(&self, tx_power: i8) -> ReturnCode {
         // Convert u8 to TxPower
         match nrf5x::constants::TxPower::try_from(tx_power as u8) {
@@ -1152,7 +1246,7 @@ impl<'p> kernel::hil::radio::RadioData for Radio<'p> {
         buf: &'static mut [u8],
         frame_len: usize,
     ) -> (ReturnCode, Option<&'static mut [u8]>) {
-        if self.tx_buf.is_some() || self.transmitting.get() {
+        if self.tx_buf.is_some() || self.transmitting.get() || self.energy_detecting.get() {
             return (ReturnCode::EBUSY, Some(buf));
         } else if radio::PSDU_OFFSET + frame_len >= buf.len() {
             // Not enough room for CRC
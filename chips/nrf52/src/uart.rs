@@ -539,3 +539,57 @@ impl<'a> uart::Receive<'a> for Uarte<'a> {
         }
     }
 }
+
+#[cfg(all(test, feature = "register_mock"))]
+mod tests {
+    use super::{Uarte, UarteRegisters, UARTE_BASE};
+    use kernel::common::registers::mock::{Access, MockRegisters};
+    use kernel::hil::uart::{self, Configure};
+    use kernel::ReturnCode;
+    use nrf5x::pinmux::Pinmux;
+
+    #[test]
+    fn test_initialize() {
+        let mock = MockRegisters::for_block(&*UARTE_BASE as *const UarteRegisters);
+        let uarte = Uarte::new();
+
+        unsafe {
+            uarte.initialize(Pinmux::new(6), Pinmux::new(8), None, Some(Pinmux::new(5)));
+        }
+
+        let regs = &*UARTE_BASE;
+        assert_eq!(
+            mock.trace(),
+            [
+                Access::Write(mock.offset_of(&regs.pseltxd), 6),
+                Access::Write(mock.offset_of(&regs.pselrxd), 8),
+                Access::Write(mock.offset_of(&regs.pselcts), 1 << 31),
+                Access::Write(mock.offset_of(&regs.pselrts), 5),
+                Access::Write(mock.offset_of(&regs.event_endtx), 0),
+                Access::Write(mock.offset_of(&regs.enable), 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_configure_baud_rate() {
+        let mock = MockRegisters::for_block(&*UARTE_BASE as *const UarteRegisters);
+        let uarte = Uarte::new();
+        let mut params = uart::Parameters {
+            baud_rate: 9600,
+            width: uart::Width::Eight,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        };
+
+        assert_eq!(uarte.configure(params), ReturnCode::SUCCESS);
+        assert_eq!(mock.get(mock.offset_of(&UARTE_BASE.baudrate)), 0x00275000);
+
+        // Unsupported settings are rejected without touching the hardware.
+        mock.take_trace();
+        params.parity = uart::Parity::Even;
+        assert_eq!(uarte.configure(params), ReturnCode::ENOSUPPORT);
+        assert!(mock.trace().is_empty());
+    }
+}
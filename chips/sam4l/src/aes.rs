@@ -503,3 +503,9 @@ impl hil::symmetric_encryption::AES128CBC for Aes<'_> {
         self.set_mode(encrypting, ConfidentialityMode::CBC);
     }
 }
+
+impl hil::symmetric_encryption::AES128ECB for Aes<'_> {
+    fn set_mode_aes128ecb(&self, encrypting: bool) {
+        self.set_mode(encrypting, ConfidentialityMode::ECB);
+    }
+}
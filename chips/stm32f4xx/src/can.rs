@@ -0,0 +1,815 @@
+//! bxCAN controller.
+//!
+//! Only CAN1 is supported. It uses transmit mailbox 0 for the single
+//! outstanding frame of `hil::can::Can`, and receives through FIFO 0. Each of
+//! the 14 filter banks assigned to CAN1 holds one 32-bit mask filter.
+//!
+//! The bit timing assumes that PCLK1 runs at 16 MHz, and places the sample
+//! point at 87.5% of the bit.
+//!
+//! Automatic bus-off recovery is disabled: once the controller went bus-off it
+//! has to be disabled and enabled again.
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil::can::{self, ErrorState, Filter, Frame, Id};
+use kernel::ClockInterface;
+use kernel::ReturnCode;
+
+use crate::rcc;
+
+/// Transmit mailbox
+#[repr(C)]
+struct TxMailbox {
+    /// Identifier register
+    tir: ReadWrite<u32, TIR::Register>,
+    /// Data length control and time stamp register
+    tdtr: ReadWrite<u32, TDTR::Register>,
+    /// Data low register
+    tdlr: ReadWrite<u32>,
+    /// Data high register
+    tdhr: ReadWrite<u32>,
+}
+
+/// Receive FIFO mailbox
+#[repr(C)]
+struct RxMailbox {
+    /// Identifier register
+    rir: ReadOnly<u32, RIR::Register>,
+    /// Data length control and time stamp register
+    rdtr: ReadOnly<u32, RDTR::Register>,
+    /// Data low register
+    rdlr: ReadOnly<u32>,
+    /// Data high register
+    rdhr: ReadOnly<u32>,
+}
+
+/// Filter bank
+#[repr(C)]
+struct FilterBank {
+    /// Filter identifier
+    fr1: ReadWrite<u32>,
+    /// Filter mask
+    fr2: ReadWrite<u32>,
+}
+
+/// Basic extended CAN controller
+#[repr(C)]
+pub struct CanRegisters {
+    /// Master control register
+    mcr: ReadWrite<u32, MCR::Register>,
+    /// Master status register
+    msr: ReadWrite<u32, MSR::Register>,
+    /// Transmit status register
+    tsr: ReadWrite<u32, TSR::Register>,
+    /// Receive FIFO 0 register
+    rf0r: ReadWrite<u32, RFR::Register>,
+    /// Receive FIFO 1 register
+    rf1r: ReadWrite<u32, RFR::Register>,
+    /// Interrupt enable register
+    ier: ReadWrite<u32, IER::Register>,
+    /// Error status register
+    esr: ReadWrite<u32, ESR::Register>,
+    /// Bit timing register
+    btr: ReadWrite<u32, BTR::Register>,
+    _reserved0: [u32; 88],
+    /// Transmit mailboxes
+    tx: [TxMailbox; 3],
+    /// Receive FIFO mailboxes
+    rx: [RxMailbox; 2],
+    _reserved1: [u32; 12],
+    /// Filter master register
+    fmr: ReadWrite<u32, FMR::Register>,
+    /// Filter mode register
+    fm1r: ReadWrite<u32>,
+    _reserved2: u32,
+    /// Filter scale register
+    fs1r: ReadWrite<u32>,
+    _reserved3: u32,
+    /// Filter FIFO assignment register
+    ffa1r: ReadWrite<u32>,
+    _reserved4: u32,
+    /// Filter activation register
+    fa1r: ReadWrite<u32>,
+    _reserved5: [u32; 8],
+    /// Filter banks
+    banks: [FilterBank; 28],
+}
+
+register_bitfields![u32,
+    MCR [
+        /// Debug freeze
+        DBF OFFSET(16) NUMBITS(1) [],
+        /// bxCAN software master reset
+        RESET OFFSET(15) NUMBITS(1) [],
+        /// Time triggered communication mode
+        TTCM OFFSET(7) NUMBITS(1) [],
+        /// Automatic bus-off management
+        ABOM OFFSET(6) NUMBITS(1) [],
+        /// Automatic wakeup mode
+        AWUM OFFSET(5) NUMBITS(1) [],
+        /// No automatic retransmission
+        NART OFFSET(4) NUMBITS(1) [],
+        /// Receive FIFO locked mode
+        RFLM OFFSET(3) NUMBITS(1) [],
+        /// Transmit FIFO priority
+        TXFP OFFSET(2) NUMBITS(1) [],
+        /// Sleep mode request
+        SLEEP OFFSET(1) NUMBITS(1) [],
+        /// Initialization request
+        INRQ OFFSET(0) NUMBITS(1) []
+    ],
+    MSR [
+        /// CAN Rx signal
+        RX OFFSET(11) NUMBITS(1) [],
+        /// Last sample point
+        SAMP OFFSET(10) NUMBITS(1) [],
+        /// Receive mode
+        RXM OFFSET(9) NUMBITS(1) [],
+        /// Transmit mode
+        TXM OFFSET(8) NUMBITS(1) [],
+        /// Sleep acknowledge interrupt
+        SLAKI OFFSET(4) NUMBITS(1) [],
+        /// Wakeup interrupt
+        WKUI OFFSET(3) NUMBITS(1) [],
+        /// Error interrupt
+        ERRI OFFSET(2) NUMBITS(1) [],
+        /// Sleep acknowledge
+        SLAK OFFSET(1) NUMBITS(1) [],
+        /// Initialization acknowledge
+        INAK OFFSET(0) NUMBITS(1) []
+    ],
+    TSR [
+        /// Transmit mailbox 2 empty
+        TME2 OFFSET(28) NUMBITS(1) [],
+        /// Transmit mailbox 1 empty
+        TME1 OFFSET(27) NUMBITS(1) [],
+        /// Transmit mailbox 0 empty
+        TME0 OFFSET(26) NUMBITS(1) [],
+        /// Mailbox code
+        CODE OFFSET(24) NUMBITS(2) [],
+        /// Abort request for mailbox 0
+        ABRQ0 OFFSET(7) NUMBITS(1) [],
+        /// Transmission error of mailbox 0
+        TERR0 OFFSET(3) NUMBITS(1) [],
+        /// Arbitration lost for mailbox 0
+        ALST0 OFFSET(2) NUMBITS(1) [],
+        /// Transmission OK of mailbox 0
+        TXOK0 OFFSET(1) NUMBITS(1) [],
+        /// Request completed mailbox 0
+        RQCP0 OFFSET(0) NUMBITS(1) []
+    ],
+    RFR [
+        /// Release FIFO output mailbox
+        RFOM OFFSET(5) NUMBITS(1) [],
+        /// FIFO overrun
+        FOVR OFFSET(4) NUMBITS(1) [],
+        /// FIFO full
+        FULL OFFSET(3) NUMBITS(1) [],
+        /// FIFO message pending
+        FMP OFFSET(0) NUMBITS(2) []
+    ],
+    IER [
+        /// Sleep interrupt enable
+        SLKIE OFFSET(17) NUMBITS(1) [],
+        /// Wakeup interrupt enable
+        WKUIE OFFSET(16) NUMBITS(1) [],
+        /// Error interrupt enable
+        ERRIE OFFSET(15) NUMBITS(1) [],
+        /// Last error code interrupt enable
+        LECIE OFFSET(11) NUMBITS(1) [],
+        /// Bus-off interrupt enable
+        BOFIE OFFSET(10) NUMBITS(1) [],
+        /// Error passive interrupt enable
+        EPVIE OFFSET(9) NUMBITS(1) [],
+        /// Error warning interrupt enable
+        EWGIE OFFSET(8) NUMBITS(1) [],
+        /// FIFO 1 overrun interrupt enable
+        FOVIE1 OFFSET(6) NUMBITS(1) [],
+        /// FIFO 1 full interrupt enable
+        FFIE1 OFFSET(5) NUMBITS(1) [],
+        /// FIFO 1 message pending interrupt enable
+        FMPIE1 OFFSET(4) NUMBITS(1) [],
+        /// FIFO 0 overrun interrupt enable
+        FOVIE0 OFFSET(3) NUMBITS(1) [],
+        /// FIFO 0 full interrupt enable
+        FFIE0 OFFSET(2) NUMBITS(1) [],
+        /// FIFO 0 message pending interrupt enable
+        FMPIE0 OFFSET(1) NUMBITS(1) [],
+        /// Transmit mailbox empty interrupt enable
+        TMEIE OFFSET(0) NUMBITS(1) []
+    ],
+    ESR [
+        /// Receive error counter
+        REC OFFSET(24) NUMBITS(8) [],
+        /// Transmit error counter
+        TEC OFFSET(16) NUMBITS(8) [],
+        /// Last error code
+        LEC OFFSET(4) NUMBITS(3) [],
+        /// Bus-off flag
+        BOFF OFFSET(2) NUMBITS(1) [],
+        /// Error passive flag
+        EPVF OFFSET(1) NUMBITS(1) [],
+        /// Error warning flag
+        EWGF OFFSET(0) NUMBITS(1) []
+    ],
+    BTR [
+        /// Silent mode (debug)
+        SILM OFFSET(31) NUMBITS(1) [],
+        /// Loop back mode (debug)
+        LBKM OFFSET(30) NUMBITS(1) [],
+        /// Resynchronization jump width
+        SJW OFFSET(24) NUMBITS(2) [],
+        /// Time segment 2
+        TS2 OFFSET(20) NUMBITS(3) [],
+        /// Time segment 1
+        TS1 OFFSET(16) NUMBITS(4) [],
+        /// Baud rate prescaler
+        BRP OFFSET(0) NUMBITS(10) []
+    ],
+    TIR [
+        /// Standard identifier or extended identifier
+        STID OFFSET(21) NUMBITS(11) [],
+        /// Extended identifier
+        EXID OFFSET(3) NUMBITS(18) [],
+        /// Identifier extension
+        IDE OFFSET(2) NUMBITS(1) [],
+        /// Remote transmission request
+        RTR OFFSET(1) NUMBITS(1) [],
+        /// Transmit mailbox request
+        TXRQ OFFSET(0) NUMBITS(1) []
+    ],
+    TDTR [
+        /// Message time stamp
+        TIME OFFSET(16) NUMBITS(16) [],
+        /// Transmit global time
+        TGT OFFSET(8) NUMBITS(1) [],
+        /// Data length code
+        DLC OFFSET(0) NUMBITS(4) []
+    ],
+    RIR [
+        /// Standard identifier or extended identifier
+        STID OFFSET(21) NUMBITS(11) [],
+        /// Extended identifier
+        EXID OFFSET(3) NUMBITS(18) [],
+        /// Identifier extension
+        IDE OFFSET(2) NUMBITS(1) [],
+        /// Remote transmission request
+        RTR OFFSET(1) NUMBITS(1) []
+    ],
+    RDTR [
+        /// Message time stamp
+        TIME OFFSET(16) NUMBITS(16) [],
+        /// Filter match index
+        FMI OFFSET(8) NUMBITS(8) [],
+        /// Data length code
+        DLC OFFSET(0) NUMBITS(4) []
+    ],
+    FMR [
+        /// CAN2 start bank
+        CAN2SB OFFSET(8) NUMBITS(6) [],
+        /// Filter initialization mode
+        FINIT OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+pub const CAN1_BASE: StaticRef<CanRegisters> =
+    unsafe { StaticRef::new(0x40006400 as *const CanRegisters) };
+
+/// Filter banks assigned to CAN1 after reset.
+const CAN1_FILTER_BANKS: usize = 14;
+
+/// PCLK1 frequency the bit timing is computed from.
+const PCLK1_HZ: u32 = 16_000_000;
+
+/// Iterations to wait for the controller to acknowledge a mode change. Leaving
+/// initialization mode requires 11 recessive bits on the bus, so this fails
+/// if the transceiver is not connected.
+const MODE_CHANGE_TIMEOUT: usize = 100_000;
+
+pub struct Can<'a> {
+    registers: StaticRef<CanRegisters>,
+    clock: CanClock<'a>,
+    client: OptionalCell<&'a dyn can::Client>,
+    enabled: Cell<bool>,
+    transmitting: Cell<bool>,
+    btr: Cell<u32>,
+    filters: Cell<[Option<Filter>; CAN1_FILTER_BANKS]>,
+    error_state: Cell<ErrorState>,
+}
+
+impl<'a> Can<'a> {
+    pub const fn new_can1(rcc: &'a rcc::Rcc) -> Can<'a> {
+        Can {
+            registers: CAN1_BASE,
+            clock: CanClock(rcc::PeripheralClock::new(
+                rcc::PeripheralClockType::APB1(rcc::PCLK1::CAN1),
+                rcc,
+            )),
+            client: OptionalCell::empty(),
+            enabled: Cell::new(false),
+            transmitting: Cell::new(false),
+            // Not configured yet; `enable` falls back to 125 kbit/s.
+            btr: Cell::new(0),
+            filters: Cell::new([None; CAN1_FILTER_BANKS]),
+            error_state: Cell::new(ErrorState::Active),
+        }
+    }
+
+    pub fn is_enabled_clock(&self) -> bool {
+        self.clock.is_enabled()
+    }
+
+    pub fn enable_clock(&self) {
+        self.clock.enable();
+    }
+
+    pub fn disable_clock(&self) {
+        self.clock.disable();
+    }
+
+    /// Compute the bit timing register for `bitrate`, using 16 or 8 time
+    /// quanta per bit.
+    fn bit_timing(bitrate: u32) -> Option<u32> {
+        // (time quanta per bit, TS1, TS2), sampling at 87.5%
+        for &(quanta, ts1, ts2) in [(16, 13, 2), (8, 5, 2)].iter() {
+            let rate = bitrate.checked_mul(quanta)?;
+            if rate == 0 || PCLK1_HZ % rate != 0 {
+                continue;
+            }
+            let prescaler = PCLK1_HZ / rate;
+            if prescaler == 0 || prescaler > 1024 {
+                continue;
+            }
+            return Some(
+                (BTR::BRP.val(prescaler - 1)
+                    + BTR::TS1.val(ts1 - 1)
+                    + BTR::TS2.val(ts2 - 1)
+                    + BTR::SJW.val(0))
+                .value,
+            );
+        }
+        None
+    }
+
+    fn wait_for_init_ack(&self, set: bool) -> bool {
+        for _ in 0..MODE_CHANGE_TIMEOUT {
+            if self.registers.msr.is_set(MSR::INAK) == set {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn program_filters(&self) {
+        let regs = &*self.registers;
+        let mask = (1 << CAN1_FILTER_BANKS) - 1;
+
+        regs.fmr.modify(FMR::FINIT::SET);
+        regs.fa1r.set(regs.fa1r.get() & !mask);
+        for (bank, filter) in self.filters.get().iter().enumerate() {
+            let filter = match filter {
+                Some(filter) => filter,
+                None => continue,
+            };
+            let bit = 1 << bank;
+            // One 32-bit filter in identifier/mask mode, assigned to FIFO 0.
+            regs.fs1r.set(regs.fs1r.get() | bit);
+            regs.fm1r.set(regs.fm1r.get() & !bit);
+            regs.ffa1r.set(regs.ffa1r.get() & !bit);
+            // The IDE bit is always compared, so filters only match
+            // identifiers of their own kind.
+            let (id, mask) = match filter.id {
+                Id::Standard(id) => (
+                    TIR::STID.val(id as u32).value,
+                    TIR::STID.val(filter.mask & 0x7FF).value + TIR::IDE::SET.value,
+                ),
+                // The extended identifier spans both STID and EXID.
+                Id::Extended(id) => (
+                    (id << 3) | TIR::IDE::SET.value,
+                    ((filter.mask & 0x1FFF_FFFF) << 3) | TIR::IDE::SET.value,
+                ),
+            };
+            regs.banks[bank].fr1.set(id);
+            regs.banks[bank].fr2.set(mask);
+            regs.fa1r.set(regs.fa1r.get() | bit);
+        }
+        regs.fmr.modify(FMR::FINIT::CLEAR);
+    }
+
+    fn read_error_state(&self) -> ErrorState {
+        let esr = self.registers.esr.extract();
+        if esr.is_set(ESR::BOFF) {
+            ErrorState::BusOff
+        } else if esr.is_set(ESR::EPVF) {
+            ErrorState::Passive
+        } else {
+            ErrorState::Active
+        }
+    }
+
+    /// Report a change of the fault confinement state to the client. The
+    /// controller only interrupts when entering the error-passive or bus-off
+    /// state, so this is also checked after every transmission.
+    fn update_error_state(&self) {
+        let state = self.read_error_state();
+        if self.error_state.replace(state) != state {
+            if state == ErrorState::BusOff && self.transmitting.get() {
+                // The frame will not go out anymore; the transmit interrupt
+                // reports the failure.
+                self.registers.tsr.write(TSR::ABRQ0::SET);
+            }
+            self.client.map(|client| client.error_state_changed(state));
+        }
+    }
+
+    /// Transmit mailbox empty interrupt.
+    pub fn handle_transmit_interrupt(&self) {
+        let tsr = self.registers.tsr.extract();
+        if !tsr.is_set(TSR::RQCP0) {
+            return;
+        }
+        // Also clears TXOK0, ALST0 and TERR0.
+        self.registers.tsr.write(TSR::RQCP0::SET);
+        if self.transmitting.replace(false) {
+            let result = if tsr.is_set(TSR::TXOK0) {
+                ReturnCode::SUCCESS
+            } else {
+                ReturnCode::FAIL
+            };
+            self.client.map(|client| client.transmit_complete(result));
+        }
+        self.update_error_state();
+    }
+
+    /// Receive FIFO 0 interrupt.
+    pub fn handle_fifo0_interrupt(&self) {
+        let regs = &*self.registers;
+        if regs.rf0r.is_set(RFR::FOVR) {
+            regs.rf0r.write(RFR::FOVR::SET);
+        }
+        while regs.rf0r.read(RFR::FMP) > 0 {
+            let mailbox = &regs.rx[0];
+            let rir = mailbox.rir.extract();
+            let id = if rir.is_set(RIR::IDE) {
+                Id::Extended(rir.read(RIR::EXID) | (rir.read(RIR::STID) << 18))
+            } else {
+                Id::Standard(rir.read(RIR::STID) as u16)
+            };
+            let dlc = core::cmp::min(mailbox.rdtr.read(RDTR::DLC), 8) as u8;
+            let mut data = [0; 8];
+            data[..4].copy_from_slice(&mailbox.rdlr.get().to_le_bytes());
+            data[4..].copy_from_slice(&mailbox.rdhr.get().to_le_bytes());
+            regs.rf0r.write(RFR::RFOM::SET);
+
+            let frame = Frame {
+                id: id,
+                remote: rir.is_set(RIR::RTR),
+                dlc: dlc,
+                data: data,
+            };
+            self.client.map(|client| client.frame_received(&frame));
+        }
+    }
+
+    /// Status change and error interrupt.
+    pub fn handle_error_interrupt(&self) {
+        self.registers.msr.write(MSR::ERRI::SET);
+        self.update_error_state();
+    }
+}
+
+impl<'a> can::Can<'a> for Can<'a> {
+    fn set_client(&self, client: &'a dyn can::Client) {
+        self.client.set(client);
+    }
+
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EBUSY;
+        }
+        match Self::bit_timing(bitrate) {
+            Some(btr) => {
+                self.btr.set(btr);
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::EINVAL,
+        }
+    }
+
+    fn enable(&self) -> ReturnCode {
+        if self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        let regs = &*self.registers;
+        self.enable_clock();
+
+        regs.mcr.write(MCR::INRQ::SET);
+        if !self.wait_for_init_ack(true) {
+            self.disable_clock();
+            return ReturnCode::FAIL;
+        }
+        if self.btr.get() == 0 {
+            self.btr.set(Self::bit_timing(125_000).unwrap_or(0));
+        }
+        regs.btr.set(self.btr.get());
+        self.program_filters();
+        regs.ier.write(
+            IER::TMEIE::SET
+                + IER::FMPIE0::SET
+                + IER::FOVIE0::SET
+                + IER::EPVIE::SET
+                + IER::BOFIE::SET
+                + IER::ERRIE::SET,
+        );
+
+        // Leave initialization mode; ABOM is clear so bus-off is permanent.
+        regs.mcr.write(MCR::INRQ::CLEAR);
+        if !self.wait_for_init_ack(false) {
+            regs.mcr.write(MCR::SLEEP::SET);
+            self.disable_clock();
+            return ReturnCode::FAIL;
+        }
+        self.error_state.set(ErrorState::Active);
+        self.enabled.set(true);
+        ReturnCode::SUCCESS
+    }
+
+    fn disable(&self) -> ReturnCode {
+        if !self.enabled.get() {
+            return ReturnCode::EALREADY;
+        }
+        let regs = &*self.registers;
+        regs.ier.set(0);
+        if self.transmitting.get() {
+            regs.tsr.write(TSR::ABRQ0::SET);
+        }
+        regs.mcr.write(MCR::INRQ::SET);
+        self.wait_for_init_ack(true);
+        regs.mcr.write(MCR::SLEEP::SET);
+        self.disable_clock();
+        self.enabled.set(false);
+
+        if self.transmitting.replace(false) {
+            self.client
+                .map(|client| client.transmit_complete(ReturnCode::ECANCEL));
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+
+    fn send(&self, frame: &Frame) -> ReturnCode {
+        if !frame.is_valid() {
+            return ReturnCode::EINVAL;
+        }
+        if !self.enabled.get() || self.error_state.get() == ErrorState::BusOff {
+            return ReturnCode::EOFF;
+        }
+        let regs = &*self.registers;
+        if self.transmitting.get() || !regs.tsr.is_set(TSR::TME0) {
+            return ReturnCode::EBUSY;
+        }
+
+        let mailbox = &regs.tx[0];
+        let id = match frame.id {
+            Id::Standard(id) => TIR::STID.val(id as u32),
+            Id::Extended(id) => TIR::EXID.val(id) + TIR::STID.val(id >> 18) + TIR::IDE::SET,
+        };
+        let rtr = if frame.remote {
+            TIR::RTR::SET
+        } else {
+            TIR::RTR::CLEAR
+        };
+        mailbox.tdtr.write(TDTR::DLC.val(frame.dlc as u32));
+        mailbox.tdlr.set(u32::from_le_bytes([
+            frame.data[0],
+            frame.data[1],
+            frame.data[2],
+            frame.data[3],
+        ]));
+        mailbox.tdhr.set(u32::from_le_bytes([
+            frame.data[4],
+            frame.data[5],
+            frame.data[6],
+            frame.data[7],
+        ]));
+        self.transmitting.set(true);
+        mailbox.tir.write(id + rtr + TIR::TXRQ::SET);
+        ReturnCode::SUCCESS
+    }
+
+    fn filter_count(&self) -> usize {
+        CAN1_FILTER_BANKS
+    }
+
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode {
+        if filters.len() > CAN1_FILTER_BANKS {
+            return ReturnCode::ESIZE;
+        }
+        let mut new = [None; CAN1_FILTER_BANKS];
+        for (slot, filter) in new.iter_mut().zip(filters.iter()) {
+            *slot = Some(*filter);
+        }
+        self.filters.set(new);
+        if !self.enabled.get() {
+            // Programmed by `enable()`.
+            return ReturnCode::SUCCESS;
+        }
+
+        // The filters are only changed in initialization mode. Entering it
+        // waits for the frame on the bus to complete, and a pending
+        // transmission request is kept until the mode is left again.
+        let regs = &*self.registers;
+        regs.mcr.write(MCR::INRQ::SET);
+        if !self.wait_for_init_ack(true) {
+            regs.mcr.write(MCR::INRQ::CLEAR);
+            return ReturnCode::FAIL;
+        }
+        self.program_filters();
+        regs.mcr.write(MCR::INRQ::CLEAR);
+        if !self.wait_for_init_ack(false) {
+            return ReturnCode::FAIL;
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn error_state(&self) -> ErrorState {
+        self.error_state.get()
+    }
+}
+
+struct CanClock<'a>(rcc::PeripheralClock<'a>);
+
+impl ClockInterface for CanClock<'_> {
+    fn is_enabled(&self) -> bool {
+        self.0.is_enabled()
+    }
+
+    fn enable(&self) {
+        self.0.enable();
+    }
+
+    fn disable(&self) {
+        self.0.disable();
+    }
+}
+
+#[cfg(all(test, feature = "register_mock"))]
+mod tests {
+    use super::{Can, CanRegisters, CAN1_BASE};
+    use crate::rcc::Rcc;
+    use core::cell::Cell;
+    use kernel::common::registers::mock::{Access, MockRegisters};
+    use kernel::hil::can::{self, Can as _, ErrorState, Filter, Frame, Id};
+    use kernel::ReturnCode;
+
+    // MSR, TSR and RF0R offsets.
+    const MSR: usize = 0x04;
+    const TSR: usize = 0x08;
+    const RF0R: usize = 0x0C;
+
+    #[derive(Default)]
+    struct TestClient {
+        sent: Cell<Option<ReturnCode>>,
+        received: Cell<Option<Frame>>,
+    }
+
+    impl can::Client for TestClient {
+        fn transmit_complete(&self, result: ReturnCode) {
+            self.sent.set(Some(result));
+        }
+
+        fn frame_received(&self, frame: &Frame) {
+            self.received.set(Some(*frame));
+        }
+
+        fn error_state_changed(&self, _state: ErrorState) {}
+    }
+
+    fn mock_rcc() -> MockRegisters {
+        MockRegisters::new(0x4002_3800, 0x90)
+    }
+
+    #[test]
+    fn test_bit_timing() {
+        // 500 kbit/s: prescaler 2, TS1 13 tq, TS2 2 tq.
+        assert_eq!(Can::bit_timing(500_000), Some(0x001C_0001));
+        // 1 Mbit/s: prescaler 1.
+        assert_eq!(Can::bit_timing(1_000_000), Some(0x001C_0000));
+        assert_eq!(Can::bit_timing(800_000), None);
+        assert_eq!(Can::bit_timing(0), None);
+    }
+
+    #[test]
+    fn test_enable_and_filters() {
+        let _rcc_mock = mock_rcc();
+        let mock = MockRegisters::for_block(&*CAN1_BASE as *const CanRegisters);
+        let rcc = Rcc::new();
+        let can = Can::new_can1(&rcc);
+
+        assert_eq!(can.set_bitrate(500_000), ReturnCode::SUCCESS);
+        can.set_filters(&[
+            Filter::exact(Id::Standard(0x123)),
+            Filter::any(Id::Extended(0)),
+        ]);
+        // INAK is set once initialization is requested, and clears when
+        // leaving it.
+        mock.push_read(MSR, 0x1);
+        mock.push_read(MSR, 0x0);
+        assert_eq!(can.enable(), ReturnCode::SUCCESS);
+
+        assert_eq!(mock.get(0x1C), 0x001C_0001);
+        assert_eq!(mock.get(0x21C) & 0x3FFF, 0b11);
+        assert_eq!(mock.get(0x20C) & 0x3FFF, 0b11);
+        assert_eq!(mock.get(0x240), 0x123 << 21);
+        assert_eq!(mock.get(0x244), (0x7FF << 21) | (1 << 2));
+        assert_eq!(mock.get(0x248), 1 << 2);
+        assert_eq!(mock.get(0x24C), 1 << 2);
+        // Initialization and filter init mode were left.
+        assert_eq!(mock.get(0x00) & 0x1, 0);
+        assert_eq!(mock.get(0x200) & 0x1, 0);
+        assert_eq!(can.set_bitrate(250_000), ReturnCode::EBUSY);
+    }
+
+    #[test]
+    fn test_filters_changed_in_init_mode() {
+        let _rcc_mock = mock_rcc();
+        let mock = MockRegisters::for_block(&*CAN1_BASE as *const CanRegisters);
+        let rcc = Rcc::new();
+        let can = Can::new_can1(&rcc);
+
+        mock.push_read(MSR, 0x1);
+        mock.push_read(MSR, 0x0);
+        assert_eq!(can.enable(), ReturnCode::SUCCESS);
+
+        mock.take_trace();
+        mock.push_read(MSR, 0x1);
+        mock.push_read(MSR, 0x0);
+        assert_eq!(
+            can.set_filters(&[Filter::exact(Id::Standard(0x123))]),
+            ReturnCode::SUCCESS
+        );
+        let trace = mock.take_trace();
+        let position = |access| trace.iter().position(|a| *a == access).unwrap();
+        // Initialization mode is entered before filter init mode, and left
+        // after it.
+        let init_ack = position(Access::Read(MSR, 0x1));
+        assert!(position(Access::Write(0x00, 0x1)) < init_ack);
+        assert!(init_ack < position(Access::Write(0x200, 0x1)));
+        assert!(position(Access::Write(0x200, 0x0)) < position(Access::Write(0x00, 0x0)));
+        assert_eq!(mock.get(0x240), 0x123 << 21);
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        let _rcc_mock = mock_rcc();
+        let mock = MockRegisters::for_block(&*CAN1_BASE as *const CanRegisters);
+        let rcc = Rcc::new();
+        let can = Can::new_can1(&rcc);
+        let client = TestClient::default();
+        can.set_client(&client);
+
+        mock.push_read(MSR, 0x1);
+        mock.push_read(MSR, 0x0);
+        assert_eq!(can.enable(), ReturnCode::SUCCESS);
+
+        // Mailbox 0 is empty.
+        mock.set(TSR, 1 << 26);
+        let frame = Frame::new(Id::Extended(0x1ABC_DEF0), &[1, 2, 3, 4, 5]).unwrap();
+        mock.take_trace();
+        assert_eq!(can.send(&frame), ReturnCode::SUCCESS);
+        assert_eq!(can.send(&frame), ReturnCode::EBUSY);
+        assert_eq!(
+            mock.take_trace()[1..],
+            [
+                Access::Write(0x184, 5),
+                Access::Write(0x188, 0x0403_0201),
+                Access::Write(0x18C, 0x05),
+                Access::Write(0x180, (0x1ABC_DEF0 << 3) | (1 << 2) | 1),
+            ]
+        );
+
+        // Request completed, transmission OK.
+        mock.set(TSR, (1 << 26) | 0b11);
+        can.handle_transmit_interrupt();
+        assert_eq!(client.sent.get(), Some(ReturnCode::SUCCESS));
+
+        // One standard remote frame pending in FIFO 0.
+        mock.set(0x1B0, (0x42 << 21) | (1 << 1));
+        mock.set(0x1B4, 2);
+        mock.push_read(RF0R, 1);
+        mock.push_read(RF0R, 1);
+        mock.push_read(RF0R, 0);
+        can.handle_fifo0_interrupt();
+        assert_eq!(
+            client.received.get(),
+            Some(Frame::new_remote(Id::Standard(0x42), 2).unwrap())
+        );
+        assert!(mock.trace().contains(&Access::Write(RF0R, 1 << 5)));
+    }
+}
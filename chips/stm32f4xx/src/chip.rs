@@ -20,6 +20,7 @@ pub struct Stm32f4xx<'a, I: InterruptService<DeferredCallTask> + 'a> {
 
 pub struct Stm32f4xxDefaultPeripherals<'a> {
     pub adc1: crate::adc::Adc<'a>,
+    pub can1: crate::can::Can<'a>,
     pub dma_streams: [crate::dma1::Stream<'a>; 8],
     pub exti: &'a crate::exti::Exti<'a>,
     pub i2c1: crate::i2c::I2C<'a>,
@@ -39,6 +40,7 @@ impl<'a> Stm32f4xxDefaultPeripherals<'a> {
     ) -> Self {
         Self {
             adc1: crate::adc::Adc::new(rcc),
+            can1: crate::can::Can::new_can1(rcc),
             dma_streams: crate::dma1::new_dma1_stream(dma),
             exti,
             i2c1: crate::i2c::I2C::new(rcc),
@@ -99,6 +101,10 @@ impl<'a> InterruptService<DeferredCallTask> for Stm32f4xxDefaultPeripherals<'a>
 
             nvic::ADC => self.adc1.handle_interrupt(),
 
+            nvic::CAN1_TX => self.can1.handle_transmit_interrupt(),
+            nvic::CAN1_RX0 => self.can1.handle_fifo0_interrupt(),
+            nvic::CAN1_SCE => self.can1.handle_error_interrupt(),
+
             nvic::I2C1_EV => self.i2c1.handle_event(),
             nvic::I2C1_ER => self.i2c1.handle_error(),
 
@@ -964,6 +964,20 @@ impl Rcc {
         self.registers.apb1enr.modify(APB1ENR::USART3EN::CLEAR)
     }
 
+    // CAN1 clock
+
+    fn is_enabled_can1_clock(&self) -> bool {
+        self.registers.apb1enr.is_set(APB1ENR::CAN1EN)
+    }
+
+    fn enable_can1_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::CAN1EN::SET)
+    }
+
+    fn disable_can1_clock(&self) {
+        self.registers.apb1enr.modify(APB1ENR::CAN1EN::CLEAR)
+    }
+
     // ADC1 clock
 
     fn is_enabled_adc1_clock(&self) -> bool {
@@ -1060,6 +1074,7 @@ pub enum PCLK1 {
     USART3,
     SPI3,
     I2C1,
+    CAN1,
 }
 
 /// Peripherals clocked by PCLK2
@@ -1105,6 +1120,7 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::USART3 => self.rcc.is_enabled_usart3_clock(),
                 PCLK1::I2C1 => self.rcc.is_enabled_i2c1_clock(),
                 PCLK1::SPI3 => self.rcc.is_enabled_spi3_clock(),
+                PCLK1::CAN1 => self.rcc.is_enabled_can1_clock(),
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => self.rcc.is_enabled_adc1_clock(),
@@ -1171,6 +1187,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::SPI3 => {
                     self.rcc.enable_spi3_clock();
                 }
+                PCLK1::CAN1 => {
+                    self.rcc.enable_can1_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => {
@@ -1241,6 +1260,9 @@ impl<'a> ClockInterface for PeripheralClock<'a> {
                 PCLK1::SPI3 => {
                     self.rcc.disable_spi3_clock();
                 }
+                PCLK1::CAN1 => {
+                    self.rcc.disable_can1_clock();
+                }
             },
             PeripheralClockType::APB2(ref v) => match v {
                 PCLK2::ADC1 => {
@@ -555,3 +555,58 @@ impl ClockInterface for UsartClock<'_> {
         self.0.disable();
     }
 }
+
+#[cfg(all(test, feature = "register_mock"))]
+mod tests {
+    use super::{Usart, UsartRegisters, USART2_BASE};
+    use crate::rcc::Rcc;
+    use kernel::common::registers::mock::{Access, MockRegisters};
+    use kernel::hil::uart::{self, Configure};
+    use kernel::ReturnCode;
+
+    #[test]
+    fn test_send_byte_waits_for_txe() {
+        let mock = MockRegisters::for_block(&*USART2_BASE as *const UsartRegisters);
+        let rcc = Rcc::new();
+        let usart = Usart::new_usart2(&rcc);
+
+        // TXE is clear for two polls before the data register empties.
+        mock.push_read(0x00, 0x00);
+        mock.push_read(0x00, 0x00);
+        mock.push_read(0x00, 1 << 7);
+
+        usart.send_byte(b'A');
+
+        assert_eq!(
+            mock.trace(),
+            [
+                Access::Read(0x00, 0x00),
+                Access::Read(0x00, 0x00),
+                Access::Read(0x00, 1 << 7),
+                Access::Write(0x04, 0x41),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_configure() {
+        let mock = MockRegisters::for_block(&*USART2_BASE as *const UsartRegisters);
+        let rcc = Rcc::new();
+        let usart = Usart::new_usart2(&rcc);
+
+        let rcode = usart.configure(uart::Parameters {
+            baud_rate: 115200,
+            width: uart::Width::Eight,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+
+        assert_eq!(rcode, ReturnCode::SUCCESS);
+        // BRR = 8.6875 at 16 MHz for 115200 baud.
+        assert_eq!(mock.get(0x08), 0x8B);
+        // UE, TE and RE are set, M and PCE are clear.
+        assert_eq!(mock.get(0x0C), (1 << 13) | (1 << 3) | (1 << 2));
+        assert_eq!(mock.pending_reads(), 0);
+    }
+}
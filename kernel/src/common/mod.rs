@@ -13,6 +13,9 @@ pub mod registers {
     pub use tock_registers::registers::{Aliased, ReadOnly, ReadWrite, WriteOnly};
     pub use tock_registers::registers::{Field, FieldValue, LocalRegisterCopy};
     pub use tock_registers::{register_bitfields, register_structs};
+
+    #[cfg(feature = "register_mock")]
+    pub use tock_registers::mock;
 }
 
 pub mod deferred_call;
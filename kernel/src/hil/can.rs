@@ -0,0 +1,219 @@
+//! Interface for CAN (Controller Area Network) controllers.
+//!
+//! This interface covers classic CAN 2.0 controllers: data and remote frames
+//! with 11-bit (CAN 2.0A, "standard") or 29-bit (CAN 2.0B, "extended")
+//! identifiers and up to 8 bytes of payload. CAN FD is not supported.
+//!
+//! A controller is configured with `set_bitrate()` and a set of acceptance
+//! filters while it is disabled, and then joins the bus with `enable()`. Frames
+//! that pass at least one of the acceptance filters are handed to the client
+//! with `frame_received()`. Only one transmission can be outstanding at a time;
+//! its completion is signalled with `transmit_complete()`.
+//!
+//! Controllers track the error counters defined by the CAN specification, and
+//! report transitions between the error-active, error-passive and bus-off
+//! states with `error_state_changed()`. A controller that went bus-off stops
+//! transmitting and receiving until it is disabled and enabled again.
+
+use crate::returncode::ReturnCode;
+
+/// Largest 11-bit (standard) identifier.
+pub const STANDARD_ID_MAX: u16 = 0x7FF;
+/// Largest 29-bit (extended) identifier.
+pub const EXTENDED_ID_MAX: u32 = 0x1FFF_FFFF;
+
+/// Frame identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Id {
+    /// 11-bit identifier (CAN 2.0A).
+    Standard(u16),
+    /// 29-bit identifier (CAN 2.0B).
+    Extended(u32),
+}
+
+impl Id {
+    /// Whether the identifier fits in its number of bits.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Id::Standard(id) => id <= STANDARD_ID_MAX,
+            Id::Extended(id) => id <= EXTENDED_ID_MAX,
+        }
+    }
+
+    /// The raw identifier, without its kind.
+    pub fn raw(&self) -> u32 {
+        match *self {
+            Id::Standard(id) => id as u32,
+            Id::Extended(id) => id,
+        }
+    }
+
+    pub fn is_extended(&self) -> bool {
+        match *self {
+            Id::Standard(_) => false,
+            Id::Extended(_) => true,
+        }
+    }
+}
+
+/// A classic CAN frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub id: Id,
+    /// Remote transmission request: the frame carries no data and `dlc` is
+    /// the length of the data requested.
+    pub remote: bool,
+    /// Data length code, between 0 and 8.
+    pub dlc: u8,
+    /// Payload; only the first `dlc` bytes are meaningful.
+    pub data: [u8; 8],
+}
+
+impl Frame {
+    /// Create a data frame. Returns `None` if the identifier is out of range
+    /// or `data` is longer than 8 bytes.
+    pub fn new(id: Id, data: &[u8]) -> Option<Frame> {
+        if !id.is_valid() || data.len() > 8 {
+            return None;
+        }
+        let mut frame = Frame {
+            id: id,
+            remote: false,
+            dlc: data.len() as u8,
+            data: [0; 8],
+        };
+        frame.data[..data.len()].copy_from_slice(data);
+        Some(frame)
+    }
+
+    /// Create a remote frame requesting `dlc` bytes. Returns `None` if the
+    /// identifier or the length is out of range.
+    pub fn new_remote(id: Id, dlc: u8) -> Option<Frame> {
+        if !id.is_valid() || dlc > 8 {
+            return None;
+        }
+        Some(Frame {
+            id: id,
+            remote: true,
+            dlc: dlc,
+            data: [0; 8],
+        })
+    }
+
+    /// The payload of a data frame. Always empty for remote frames.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..(self.dlc.min(8) as usize)]
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.id.is_valid() && self.dlc <= 8
+    }
+}
+
+/// An acceptance filter.
+///
+/// A frame passes the filter if its identifier is of the same kind as `id`
+/// and matches `id` on every bit set in `mask`. A mask of 0 therefore accepts
+/// every frame of that kind.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    pub id: Id,
+    pub mask: u32,
+}
+
+impl Filter {
+    /// Filter accepting exactly one identifier.
+    pub fn exact(id: Id) -> Filter {
+        Filter {
+            id: id,
+            mask: match id {
+                Id::Standard(_) => STANDARD_ID_MAX as u32,
+                Id::Extended(_) => EXTENDED_ID_MAX,
+            },
+        }
+    }
+
+    /// Filter accepting every frame with an identifier of the same kind as
+    /// `id`.
+    pub fn any(id: Id) -> Filter {
+        Filter { id: id, mask: 0 }
+    }
+
+    pub fn matches(&self, id: Id) -> bool {
+        self.id.is_extended() == id.is_extended() && (self.id.raw() ^ id.raw()) & self.mask == 0
+    }
+}
+
+/// Fault confinement state of a controller, as defined by the CAN
+/// specification.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorState {
+    /// Both error counters are below 128; the node takes part in the bus
+    /// normally.
+    Active,
+    /// One of the error counters reached 128; the node may only signal
+    /// errors passively.
+    Passive,
+    /// The transmit error counter exceeded 255; the node is disconnected
+    /// from the bus.
+    BusOff,
+}
+
+pub trait Can<'a> {
+    /// Set the client to be used for callbacks.
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// Set the bus bit rate in bits per second. Must be called while the
+    /// controller is disabled.
+    ///
+    /// Returns `EINVAL` if the rate cannot be generated from the controller
+    /// clock, `EBUSY` if the controller is enabled.
+    fn set_bitrate(&self, bitrate: u32) -> ReturnCode;
+
+    /// Join the bus. Receiving starts immediately.
+    fn enable(&self) -> ReturnCode;
+
+    /// Leave the bus. An outstanding transmission is aborted, and is
+    /// completed with `ECANCEL`.
+    fn disable(&self) -> ReturnCode;
+
+    fn is_enabled(&self) -> bool;
+
+    /// Queue a frame for transmission.
+    ///
+    /// Returns `EBUSY` if a transmission is already outstanding, `EOFF` if
+    /// the controller is disabled or bus-off and `EINVAL` for an invalid
+    /// frame. On `SUCCESS`, `transmit_complete()` will be called once the
+    /// frame has been acknowledged or the transmission failed.
+    fn send(&self, frame: &Frame) -> ReturnCode;
+
+    /// The number of acceptance filters the controller supports.
+    fn filter_count(&self) -> usize;
+
+    /// Replace the acceptance filters. Frames matching none of the filters
+    /// are discarded by the controller; an empty slice discards every frame.
+    ///
+    /// Returns `ESIZE` if more than `filter_count()` filters are given.
+    fn set_filters(&self, filters: &[Filter]) -> ReturnCode;
+
+    /// The current fault confinement state.
+    fn error_state(&self) -> ErrorState;
+}
+
+pub trait Client {
+    /// The outstanding transmission finished. `result` is `SUCCESS` if the
+    /// frame was acknowledged by another node, `ECANCEL` if it was aborted
+    /// and `FAIL` if the controller gave up, for example after going
+    /// bus-off.
+    fn transmit_complete(&self, result: ReturnCode);
+
+    /// A frame passing the acceptance filters was received.
+    fn frame_received(&self, frame: &Frame);
+
+    /// The fault confinement state of the controller changed.
+    fn error_state_changed(&self, state: ErrorState);
+}
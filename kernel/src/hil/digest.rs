@@ -8,6 +8,8 @@ use crate::returncode::ReturnCode;
 pub trait DigestType: Eq + Copy + Clone + Sized + AsRef<[u8]> + AsMut<[u8]> {}
 
 impl DigestType for [u8; 32] {}
+impl DigestType for [u8; 48] {}
+impl DigestType for [u8; 64] {}
 
 /// Implement this trait and use `set_client()` in order to receive callbacks.
 pub trait Client<'a, T: DigestType> {
@@ -70,3 +72,18 @@ pub trait HMACSha256 {
     /// The key used for the HMAC is passed to this function.
     fn set_mode_hmacsha256(&self, key: &[u8; 32]) -> Result<(), ReturnCode>;
 }
+
+pub trait Sha256 {
+    /// Call before `Digest::run()` to perform Sha256
+    fn set_mode_sha256(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha384 {
+    /// Call before `Digest::run()` to perform Sha384
+    fn set_mode_sha384(&self) -> Result<(), ReturnCode>;
+}
+
+pub trait Sha512 {
+    /// Call before `Digest::run()` to perform Sha512
+    fn set_mode_sha512(&self) -> Result<(), ReturnCode>;
+}
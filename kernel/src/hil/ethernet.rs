@@ -0,0 +1,117 @@
+//! Interface for Ethernet MACs.
+//!
+//! Frames are exchanged with the MAC as raw Ethernet II frames, starting
+//! with the destination address and ending with the payload: the preamble,
+//! start frame delimiter and frame check sequence are handled by the MAC.
+//! Frames shorter than `MIN_FRAME_LEN` are padded by the MAC before they are
+//! sent.
+//!
+//! Only one transmission can be outstanding at a time; the frame buffer is
+//! handed back to the client with `transmit_done()`. Received frames are
+//! passed to the client with `frame_received()` and must be copied out
+//! before the callback returns.
+//!
+//! A MAC accepts frames addressed to its own address, to the broadcast
+//! address and to the multicast addresses added to its filter with
+//! `add_multicast()`, unless it is put into promiscuous mode, in which case
+//! every frame is received. MACs without hardware address filtering implement
+//! the filter in software.
+
+use crate::returncode::ReturnCode;
+
+/// Length of the Ethernet II header: destination, source and EtherType.
+pub const HEADER_LEN: usize = 14;
+/// Shortest frame on the wire, excluding the frame check sequence.
+pub const MIN_FRAME_LEN: usize = 60;
+/// Longest frame with a 1500 byte payload, excluding the frame check
+/// sequence.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + 1500;
+
+/// EtherType values of the protocols Tock knows about.
+pub mod ethertype {
+    pub const IPV4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+    pub const IPV6: u16 = 0x86DD;
+}
+
+/// A 48-bit IEEE 802 MAC address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    pub const fn new(bytes: [u8; 6]) -> MacAddress {
+        MacAddress(bytes)
+    }
+
+    pub fn bytes(&self) -> [u8; 6] {
+        self.0
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddress::BROADCAST
+    }
+
+    /// Group addresses have the least significant bit of the first octet
+    /// set. This includes the broadcast address.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+}
+
+pub trait Ethernet<'a> {
+    fn set_client(&self, client: &'a dyn Client);
+
+    /// The address frames are received on and which the MAC reports as its
+    /// own.
+    fn mac_address(&self) -> MacAddress;
+
+    /// Change the address of the MAC. Returns EINVAL for group addresses.
+    fn set_mac_address(&self, address: MacAddress) -> ReturnCode;
+
+    /// Whether the link is up. MACs which cannot query their PHY report the
+    /// link as up once they are initialized.
+    fn link_up(&self) -> bool;
+
+    /// Send the first `len` bytes of `frame`, which must start with the
+    /// Ethernet header. Returns EBUSY if a transmission is outstanding,
+    /// ESIZE if `len` exceeds what the MAC can send and EOFF if the link is
+    /// down.
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// Number of multicast addresses the filter can hold.
+    fn multicast_filter_size(&self) -> usize;
+
+    /// Receive frames sent to the multicast address `address`. Adding an
+    /// address which is already in the filter succeeds. Returns EINVAL if the
+    /// address is not a multicast address, and ENOMEM if the filter is full.
+    fn add_multicast(&self, address: MacAddress) -> ReturnCode;
+
+    /// Stop receiving frames sent to `address`. Returns EINVAL if the address
+    /// is not in the filter.
+    fn remove_multicast(&self, address: MacAddress) -> ReturnCode;
+
+    /// Receive all frames regardless of their destination.
+    fn set_promiscuous(&self, promiscuous: bool);
+}
+
+pub trait Client {
+    /// The transmission of `frame` finished.
+    fn transmit_done(&self, frame: &'static mut [u8], result: ReturnCode);
+
+    /// A frame passed the address filter. `frame` starts with the Ethernet
+    /// header and does not include the frame check sequence.
+    fn frame_received(&self, frame: &[u8]);
+
+    /// The link went up or down.
+    fn link_status_changed(&self, up: bool);
+}
@@ -4,11 +4,13 @@ pub mod adc;
 pub mod analog_comparator;
 pub mod ble_advertising;
 pub mod bus8080;
+pub mod can;
 pub mod crc;
 pub mod dac;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
+pub mod ethernet;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
@@ -17,6 +19,7 @@ pub mod kv_system;
 pub mod led;
 pub mod log;
 pub mod nonvolatile_storage;
+pub mod public_key_crypto;
 pub mod pwm;
 pub mod radio;
 pub mod rng;
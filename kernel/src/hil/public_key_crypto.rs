@@ -0,0 +1,112 @@
+//! Interface for public-key signatures.
+//!
+//! An implementation provides one signature algorithm, and may implement
+//! any of signing, verification and key generation. Keys and signatures are
+//! byte strings in the encoding of the algorithm, for instance `r | s` for
+//! ECDSA. Functions return `EINVAL` when a key does not have the length the
+//! algorithm expects, or when a buffer for a key or a signature is shorter
+//! than it; only the start of longer buffers is used.
+//!
+//! Like `hil::digest`, operations are asynchronous and the buffers passed to
+//! them are handed back to the client with the result, or with the error
+//! if the operation could not start. Messages are passed as a
+//! `LeasableBuffer`, of which the active range is signed or verified, and
+//! the whole buffer is handed back.
+
+use crate::common::leasable_buffer::LeasableBuffer;
+use crate::returncode::ReturnCode;
+
+/// Implement this trait and use `set_sign_client()` in order to receive
+/// signatures.
+pub trait ClientSign<'a> {
+    /// Called when a signature is computed. On error or success `message`
+    /// and `signature` are the buffers passed to `sign()`, and on success
+    /// `signature` holds the signature.
+    fn sign_done(
+        &'a self,
+        result: Result<(), ReturnCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    );
+}
+
+/// Implement this trait and use `set_verify_client()` in order to receive
+/// the result of verifications.
+pub trait ClientVerify<'a> {
+    /// Called when a signature is verified. `result` is `Ok(true)` if the
+    /// signature is valid and `Ok(false)` if it is not. `message` and
+    /// `signature` are the buffers passed to `verify()`.
+    fn verify_done(
+        &'a self,
+        result: Result<bool, ReturnCode>,
+        message: &'static mut [u8],
+        signature: &'static mut [u8],
+    );
+}
+
+/// Implement this trait and use `set_key_generate_client()` in order to
+/// receive generated keys.
+pub trait ClientKeyGenerate<'a> {
+    /// Called when a key pair is generated. On error or success
+    /// `private_key` and `public_key` are the buffers passed to
+    /// `generate()`, and on success they hold the new keys.
+    fn generate_done(
+        &'a self,
+        result: Result<(), ReturnCode>,
+        private_key: &'static mut [u8],
+        public_key: &'static mut [u8],
+    );
+}
+
+/// Signs messages with a private key.
+pub trait SignatureSign<'a> {
+    fn set_sign_client(&'a self, client: &'a dyn ClientSign<'a>);
+
+    /// Sets the private key used by `sign()`. Returns `EINVAL` if it is not
+    /// a valid key.
+    fn set_private_key(&self, key: &[u8]) -> Result<(), ReturnCode>;
+
+    /// Clears the private key, so that it does not remain in memory.
+    fn clear_private_key(&self);
+
+    /// Signs `message`, and writes the signature to `signature`. Only one
+    /// operation runs at a time: `EBUSY` is returned while another one is
+    /// in progress, and `ERESERVE` if no private key was set.
+    fn sign(
+        &'a self,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])>;
+}
+
+/// Verifies signatures with a public key.
+pub trait SignatureVerify<'a> {
+    fn set_verify_client(&'a self, client: &'a dyn ClientVerify<'a>);
+
+    /// Sets the public key used by `verify()`. Returns `EINVAL` if it is
+    /// not a valid key.
+    fn set_public_key(&self, key: &[u8]) -> Result<(), ReturnCode>;
+
+    /// Verifies that `signature` is a signature of `message`. Only one
+    /// operation runs at a time: `EBUSY` is returned while another one is
+    /// in progress, and `ERESERVE` if no public key was set.
+    fn verify(
+        &'a self,
+        message: LeasableBuffer<'static, u8>,
+        signature: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])>;
+}
+
+/// Generates key pairs.
+pub trait KeyGenerate<'a> {
+    fn set_key_generate_client(&'a self, client: &'a dyn ClientKeyGenerate<'a>);
+
+    /// Generates a new key pair, and writes it to `private_key` and
+    /// `public_key`. The keys are not set as the keys of `SignatureSign`
+    /// or `SignatureVerify`.
+    fn generate(
+        &'a self,
+        private_key: &'static mut [u8],
+        public_key: &'static mut [u8],
+    ) -> Result<(), (ReturnCode, &'static mut [u8], &'static mut [u8])>;
+}
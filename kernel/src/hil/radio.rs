@@ -30,6 +30,13 @@ pub trait PowerClient {
     fn changed(&self, on: bool);
 }
 
+pub trait EnergyDetectClient {
+    /// Reports the energy measured on the current channel, as an IEEE
+    /// 802.15.4 ED level from 0 (at most 10 dB above the receiver
+    /// sensitivity) to 255 (strongest).
+    fn energy_detect_done(&self, level: u8, result: ReturnCode);
+}
+
 /// These constants are used for interacting with the SPI buffer, which contains
 /// a 1-byte SPI command, a 1-byte PHY header, and then the 802.15.4 frame. In
 /// theory, the number of extra bytes in front of the frame can depend on the
@@ -98,6 +105,12 @@ pub trait RadioConfig {
     fn set_pan(&self, id: u16);
     fn set_tx_power(&self, power: i8) -> ReturnCode;
     fn set_channel(&self, chan: u8) -> ReturnCode;
+
+    /// Measures the energy on the current channel, as used by channel
+    /// scans. The result is reported to the energy detection client.
+    /// Returns ENOSUPPORT if the radio cannot measure it.
+    fn energy_detect(&self) -> ReturnCode;
+    fn set_energy_detect_client(&self, client: &'static dyn EnergyDetectClient);
 }
 
 pub trait RadioData {
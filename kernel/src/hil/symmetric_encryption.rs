@@ -135,3 +135,42 @@ pub trait AES128CCM<'a> {
         encrypting: bool,
     ) -> (ReturnCode, Option<&'static mut [u8]>);
 }
+
+pub trait GCMClient {
+    /// `res` is SUCCESS if the encryption/decryption process succeeded. This
+    /// does not mean that the message has been verified in the case of
+    /// decryption.
+    /// If we are encrypting: `tag_is_valid` is `true` iff `res` is SUCCESS.
+    /// If we are decrypting: `tag_is_valid` is `true` iff `res` is SUCCESS and the
+    /// message authentication tag is valid.
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool);
+}
+
+pub const GCM_IV_LENGTH: usize = 12;
+pub const GCM_TAG_LENGTH: usize = 16;
+
+pub trait AES128GCM<'a> {
+    /// Set the client instance which will receive `crypt_done()` callbacks
+    fn set_client(&'a self, client: &'a dyn GCMClient);
+
+    /// Set the key to be used for GCM encryption
+    fn set_key(&self, key: &[u8]) -> ReturnCode;
+
+    /// Set the IV (length GCM_IV_LENGTH) to be used for GCM encryption
+    fn set_iv(&self, iv: &[u8]) -> ReturnCode;
+
+    /// Try to begin the encryption/decryption process
+    ///
+    /// `buf[a_off..m_off]` is authenticated, `buf[m_off..m_off + m_len]` is
+    /// encrypted or decrypted in place, and the GCM_TAG_LENGTH bytes after
+    /// it receive the tag when encrypting, or hold the tag to check when
+    /// decrypting.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>);
+}
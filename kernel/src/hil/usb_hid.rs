@@ -101,3 +101,45 @@ pub trait UsbHid<'a, T: UsbHidType> {
     /// indicate a result of `ECANCEL`.
     fn receive_cancel(&'a self) -> Result<&'static mut T, ReturnCode>;
 }
+
+/// The protocols of a HID device which supports the boot interface, such as
+/// a keyboard or a mouse. BIOSes and bootloaders select the boot protocol,
+/// in which the reports have the fixed format of the HID specification,
+/// while operating systems use the reports of the report descriptor.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    Boot,
+    Report,
+}
+
+/// Implement this trait and use `set_client()` in order to receive the
+/// callbacks of a `ReportDevice`.
+pub trait ReportClient {
+    /// Called when the report passed to `send_report()` has been sent, or
+    /// could not be sent.
+    fn report_sent(&self, result: ReturnCode, report: &'static mut [u8]);
+
+    /// Called when the host sets an output report, such as the state of the
+    /// LEDs of a keyboard.
+    fn output_report(&self, report: &[u8]);
+}
+
+/// A HID device which sends the input reports of its report descriptor to
+/// the host, such as a keyboard or a mouse.
+pub trait ReportDevice<'a> {
+    fn set_client(&self, client: &'a dyn ReportClient);
+
+    /// Sends the first `len` bytes of `report` as an input report, which the
+    /// host gets when it next polls the device.
+    ///
+    /// Returns `EBUSY` while another report is sent, and `ESIZE` if `len` is
+    /// longer than the input report of the device.
+    fn send_report(
+        &self,
+        report: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ReturnCode, &'static mut [u8])>;
+
+    /// The protocol the host selected.
+    fn protocol(&self) -> Protocol;
+}
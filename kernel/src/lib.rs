@@ -98,6 +98,9 @@ pub mod introspection;
 pub mod ipc;
 pub mod syscall;
 
+#[cfg(feature = "process_mock")]
+pub mod mock;
+
 mod callback;
 mod config;
 mod driver;
@@ -0,0 +1,370 @@
+//! Mock processes for host-side unit tests of capsules.
+//!
+//! When the `process_mock` feature is enabled, [`MockKernel`] creates a
+//! `Kernel` whose processes array is filled with [`MockProcess`]es. They have
+//! no code or MPU configuration, but they do support what a capsule sees of a
+//! process: `AppId`s, grant regions, callbacks and `AppSlice`s. A capsule can
+//! therefore be driven through its `Driver` implementation on the host:
+//!
+//! ```rust,ignore
+//! use kernel::mock::MockKernel;
+//!
+//! let kernel = MockKernel::new(2);
+//! let driver = Driver::new(kernel.create_grant());
+//! let app = kernel.process(0);
+//!
+//! driver.subscribe(0, Some(app.callback(DRIVER_NUM, 0)), app.appid());
+//! driver.command(1, 0, 0, app.appid());
+//! assert_eq!(app.take_callbacks(), [(0, ReturnCode::SUCCESS as usize, 0, 0)]);
+//!
+//! // The process exits, releasing its grant region.
+//! app.terminate();
+//! ```
+//!
+//! Everything is leaked, so each test should create its own `MockKernel`.
+//!
+//! This module requires `std` and is only intended for host-side testing;
+//! never enable `process_mock` for a kernel build.
+
+extern crate std;
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::ptr::{self, NonNull};
+use std::alloc::{alloc_zeroed, Layout};
+use std::boxed::Box;
+use std::vec::Vec;
+
+use crate::callback::{AppId, Callback, CallbackId};
+use crate::capabilities;
+use crate::grant::Grant;
+use crate::mem::{AppSlice, Shared};
+use crate::platform::mpu;
+use crate::process::{Error, FunctionCallSource, ProcessType, State, Task};
+use crate::returncode::ReturnCode;
+use crate::sched::Kernel;
+use crate::syscall::{self, Syscall};
+
+/// A kernel with a fixed set of mock processes.
+pub struct MockKernel {
+    kernel: &'static Kernel,
+    processes: &'static [MockProcess],
+}
+
+impl MockKernel {
+    /// Create a kernel with `num_processes` running processes.
+    pub fn new(num_processes: usize) -> &'static MockKernel {
+        let processes: &'static [MockProcess] = Box::leak(
+            (0..num_processes)
+                .map(MockProcess::new)
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+        );
+        let entries: &'static [Option<&'static dyn ProcessType>] = Box::leak(
+            processes
+                .iter()
+                .map(|p| Some(p as &'static dyn ProcessType))
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+        );
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(entries)));
+        for process in processes {
+            process.kernel.set(Some(kernel));
+            process.identifier.set(kernel.create_process_identifier());
+        }
+        Box::leak(Box::new(MockKernel { kernel, processes }))
+    }
+
+    /// The kernel the processes belong to.
+    pub fn kernel(&self) -> &'static Kernel {
+        self.kernel
+    }
+
+    /// Create a grant, as a board does with `Kernel::create_grant`.
+    pub fn create_grant<T: Default>(&self) -> Grant<T> {
+        struct MockCapability;
+        unsafe impl capabilities::MemoryAllocationCapability for MockCapability {}
+        self.kernel.create_grant(&MockCapability)
+    }
+
+    /// The process at `index` in the processes array.
+    pub fn process(&self, index: usize) -> &'static MockProcess {
+        &self.processes[index]
+    }
+}
+
+/// Stands in for the capabilities a board passes to the networking capsules.
+pub struct MockNetworkCapability;
+
+unsafe impl capabilities::UdpDriverCapability for MockNetworkCapability {}
+unsafe impl capabilities::CreatePortTableCapability for MockNetworkCapability {}
+unsafe impl capabilities::NetworkCapabilityCreationCapability for MockNetworkCapability {}
+
+/// A process which only has the state capsules interact with.
+pub struct MockProcess {
+    kernel: Cell<Option<&'static Kernel>>,
+    index: usize,
+    identifier: Cell<usize>,
+    active: Cell<bool>,
+    grant_pointers: RefCell<Vec<*mut u8>>,
+    callbacks: RefCell<Vec<(usize, usize, usize, usize)>>,
+}
+
+impl MockProcess {
+    fn new(index: usize) -> MockProcess {
+        MockProcess {
+            kernel: Cell::new(None),
+            index: index,
+            identifier: Cell::new(0),
+            active: Cell::new(true),
+            grant_pointers: RefCell::new(Vec::new()),
+            callbacks: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn kernel(&self) -> &'static Kernel {
+        self.kernel
+            .get()
+            .expect("process not added to a MockKernel")
+    }
+
+    /// The current `AppId` of this process. It changes when the process is
+    /// restarted.
+    pub fn appid(&self) -> AppId {
+        AppId::new(self.kernel(), self.identifier.get(), self.index)
+    }
+
+    /// A callback for `subscribe_num` of driver `driver_num`, which records
+    /// its invocations in this process.
+    pub fn callback(&self, driver_num: usize, subscribe_num: usize) -> Callback {
+        Callback::new(
+            self.appid(),
+            CallbackId {
+                driver_num,
+                subscribe_num,
+            },
+            0,
+            NonNull::dangling(),
+        )
+    }
+
+    /// Return the callbacks scheduled for this process since the last call,
+    /// in order, as `(subscribe_num, r0, r1, r2)`.
+    pub fn take_callbacks(&self) -> Vec<(usize, usize, usize, usize)> {
+        self.callbacks.replace(Vec::new())
+    }
+
+    /// Share `buffer` with the kernel, as the `allow` syscall does.
+    pub fn allow_buffer(&self, buffer: &'static mut [u8]) -> AppSlice<Shared, u8> {
+        let len = buffer.len();
+        let ptr = NonNull::new(buffer.as_mut_ptr()).unwrap();
+        unsafe { AppSlice::new(ptr, len, self.appid()) }
+    }
+
+    /// End the process, as if it exited or faulted without being restarted.
+    /// Its grant regions and pending callbacks are dropped, but its `AppId`
+    /// keeps referring to it.
+    pub fn terminate(&self) {
+        self.active.set(false);
+        self.grant_pointers.borrow_mut().clear();
+        self.callbacks.borrow_mut().clear();
+    }
+
+    /// Restart the process with a new `AppId` and empty grant regions.
+    pub fn restart(&self) {
+        self.terminate();
+        self.identifier
+            .set(self.kernel().create_process_identifier());
+        self.active.set(true);
+    }
+}
+
+impl ProcessType for MockProcess {
+    fn appid(&self) -> AppId {
+        MockProcess::appid(self)
+    }
+
+    fn enqueue_task(&self, task: Task) -> bool {
+        if !self.active.get() {
+            return false;
+        }
+        match task {
+            Task::FunctionCall(call) => match call.source {
+                FunctionCallSource::Driver(id) => {
+                    self.callbacks.borrow_mut().push((
+                        id.subscribe_num,
+                        call.argument0,
+                        call.argument1,
+                        call.argument2,
+                    ));
+                    true
+                }
+                FunctionCallSource::Kernel => false,
+            },
+            Task::IPC(_) => false,
+        }
+    }
+
+    fn ready(&self) -> bool {
+        false
+    }
+
+    fn dequeue_task(&self) -> Option<Task> {
+        None
+    }
+
+    fn remove_pending_callbacks(&self, callback_id: CallbackId) {
+        self.callbacks
+            .borrow_mut()
+            .retain(|&(subscribe_num, _, _, _)| subscribe_num != callback_id.subscribe_num);
+    }
+
+    fn get_state(&self) -> State {
+        if self.active.get() {
+            State::Yielded
+        } else {
+            State::StoppedFaulted
+        }
+    }
+
+    fn set_yielded_state(&self) {}
+
+    fn stop(&self) {}
+
+    fn resume(&self) {}
+
+    fn set_fault_state(&self) {
+        self.terminate();
+    }
+
+    fn get_restart_count(&self) -> usize {
+        0
+    }
+
+    fn get_process_name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn brk(&self, _new_break: *const u8) -> Result<*const u8, Error> {
+        Err(Error::AddressOutOfBounds)
+    }
+
+    fn sbrk(&self, _increment: isize) -> Result<*const u8, Error> {
+        Err(Error::AddressOutOfBounds)
+    }
+
+    fn mem_start(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn mem_end(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn flash_start(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn flash_end(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn kernel_memory_break(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn number_writeable_flash_regions(&self) -> usize {
+        0
+    }
+
+    fn get_writeable_flash_region(&self, _region_index: usize) -> (u32, u32) {
+        (0, 0)
+    }
+
+    fn update_stack_start_pointer(&self, _stack_pointer: *const u8) {}
+
+    fn update_heap_start_pointer(&self, _heap_pointer: *const u8) {}
+
+    fn allow(
+        &self,
+        _buf_start_addr: *const u8,
+        _size: usize,
+    ) -> Result<Option<AppSlice<Shared, u8>>, ReturnCode> {
+        Err(ReturnCode::ENOSUPPORT)
+    }
+
+    fn flash_non_protected_start(&self) -> *const u8 {
+        ptr::null()
+    }
+
+    fn setup_mpu(&self) {}
+
+    fn add_mpu_region(
+        &self,
+        _unallocated_memory_start: *const u8,
+        _unallocated_memory_size: usize,
+        _min_region_size: usize,
+    ) -> Option<mpu::Region> {
+        None
+    }
+
+    fn alloc(&self, size: usize, align: usize) -> Option<NonNull<u8>> {
+        if !self.active.get() {
+            return None;
+        }
+        // Grant memory lives as long as the test, like the rest of the mock.
+        let layout = Layout::from_size_align(size.max(1), align).ok()?;
+        NonNull::new(unsafe { alloc_zeroed(layout) })
+    }
+
+    unsafe fn free(&self, _: *mut u8) {}
+
+    fn get_grant_ptr(&self, grant_num: usize) -> Option<*mut u8> {
+        if !self.active.get() || grant_num >= self.kernel().get_grant_count_and_finalize() {
+            return None;
+        }
+        Some(
+            self.grant_pointers
+                .borrow()
+                .get(grant_num)
+                .copied()
+                .unwrap_or(ptr::null_mut()),
+        )
+    }
+
+    unsafe fn set_grant_ptr(&self, grant_num: usize, grant_ptr: *mut u8) {
+        let mut pointers = self.grant_pointers.borrow_mut();
+        if pointers.len() <= grant_num {
+            pointers.resize(grant_num + 1, ptr::null_mut());
+        }
+        pointers[grant_num] = grant_ptr;
+    }
+
+    unsafe fn set_syscall_return_value(&self, _return_value: isize) {}
+
+    unsafe fn set_process_function(&self, _callback: crate::process::FunctionCall) {}
+
+    unsafe fn switch_to(&self) -> Option<syscall::ContextSwitchReason> {
+        None
+    }
+
+    unsafe fn print_memory_map(&self, _writer: &mut dyn Write) {}
+
+    unsafe fn print_full_process(&self, _writer: &mut dyn Write) {}
+
+    fn debug_syscall_count(&self) -> usize {
+        0
+    }
+
+    fn debug_dropped_callback_count(&self) -> usize {
+        0
+    }
+
+    fn debug_timeslice_expiration_count(&self) -> usize {
+        0
+    }
+
+    fn debug_timeslice_expired(&self) {}
+
+    fn debug_syscall_called(&self, _last_syscall: Syscall) {}
+}
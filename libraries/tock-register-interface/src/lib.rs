@@ -7,3 +7,6 @@
 
 pub mod macros;
 pub mod registers;
+
+#[cfg(feature = "register_mock")]
+pub mod mock;
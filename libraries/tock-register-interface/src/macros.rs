@@ -122,6 +122,16 @@ macro_rules! register_bitmasks {
                     }
                 }
             }
+
+            /// Name of the enumerated value `v` corresponds to, if any.
+            pub fn value_name(v: $valtype) -> Option<&'static str> {
+                Value::try_from(v).map(|value| match value {
+                    $(
+                        $(#[$inner])*
+                        Value::$valname => stringify!($valname),
+                    )*
+                })
+            }
         }
     };
     {
@@ -166,6 +176,11 @@ macro_rules! register_bitmasks {
                     Option::None
                 }
             }
+
+            /// Name of the enumerated value `v` corresponds to, if any.
+            pub fn value_name(_v: $valtype) -> Option<&'static str> {
+                Option::None
+            }
         }
     };
 }
@@ -192,11 +207,74 @@ macro_rules! register_bitfields {
                 use $crate::registers::Field;
 
                 $crate::register_bitmasks!( $valtype, Register, $fields );
+
+                $crate::register_debug_info!( $valtype, $reg, $fields );
             }
         )*
     }
 }
 
+/// Helper macro implementing `RegisterDebugInfo` for the fields of a register.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! register_debug_info {
+    {
+        // BITFIELD_NAME OFFSET(x)
+        $valtype:ident, $reg:ident, [
+            $( $(#[$inner:meta])* $field:ident OFFSET($offset:expr)),+ $(,)?
+        ]
+    } => {
+        $crate::register_debug_info!(@impl $valtype, $reg, $($field),+);
+    };
+    {
+        // BITFIELD_NAME OFFSET
+        $valtype:ident, $reg:ident, [
+            $( $(#[$inner:meta])* $field:ident $offset:expr ),+ $(,)?
+        ]
+    } => {
+        $crate::register_debug_info!(@impl $valtype, $reg, $($field),+);
+    };
+    {
+        // BITFIELD_NAME OFFSET(x) NUMBITS(y)
+        $valtype:ident, $reg:ident, [
+            $( $(#[$inner:meta])* $field:ident OFFSET($offset:expr) NUMBITS($numbits:expr) ),+ $(,)?
+        ]
+    } => {
+        $crate::register_debug_info!(@impl $valtype, $reg, $($field),+);
+    };
+    {
+        // BITFIELD_NAME OFFSET(x) NUMBITS(y) []
+        $valtype:ident, $reg:ident, [
+            $( $(#[$inner:meta])* $field:ident OFFSET($offset:expr) NUMBITS($numbits:expr)
+               $values:tt ),+ $(,)?
+        ]
+    } => {
+        $crate::register_debug_info!(@impl $valtype, $reg, $($field),+);
+    };
+    {
+        @impl $valtype:ident, $reg:ident, $($field:ident),+
+    } => {
+        impl $crate::registers::RegisterDebugInfo<$valtype> for Register {
+            fn name() -> &'static str {
+                stringify!($reg)
+            }
+
+            fn fields() -> &'static [$crate::registers::FieldDebugInfo<$valtype, Register>] {
+                const FIELDS: &[$crate::registers::FieldDebugInfo<$valtype, Register>] = &[
+                    $(
+                        $crate::registers::FieldDebugInfo {
+                            name: stringify!($field),
+                            field: $field,
+                            value_name: $field::value_name,
+                        },
+                    )+
+                ];
+                FIELDS
+            }
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! register_fields {
     // Macro entry point.
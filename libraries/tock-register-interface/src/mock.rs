@@ -0,0 +1,367 @@
+//! Recording register backend for host-side unit tests.
+//!
+//! When the `register_mock` feature is enabled, every access made through a
+//! `ReadWrite`, `ReadOnly`, `WriteOnly` or `Aliased` register first checks
+//! whether its address falls inside a [`MockRegisters`] block installed on the
+//! current thread. If it does, the access never touches memory: writes update
+//! the block's shadow values, reads return either a scripted value or the last
+//! value written, and both are appended to an ordered access trace. Accesses
+//! outside of any installed block fall through to the usual volatile memory
+//! operations.
+//!
+//! This allows a chip driver that hard-codes its MMIO base address in a
+//! `StaticRef` to be exercised on the host without modification:
+//!
+//! ```rust,ignore
+//! use tock_registers::mock::{Access, MockRegisters};
+//!
+//! let mock = MockRegisters::new(0x4000_4400, 0x1C);
+//! // The first status read reports "busy", the second "ready".
+//! mock.push_read(0x00, 0x00);
+//! mock.push_read(0x00, 0x80);
+//!
+//! usart.send_byte(b'A');
+//!
+//! assert_eq!(
+//!     mock.trace(),
+//!     [
+//!         Access::Read(0x00, 0x00),
+//!         Access::Read(0x00, 0x80),
+//!         Access::Write(0x04, 0x41),
+//!     ]
+//! );
+//! ```
+//!
+//! Mock blocks are registered per thread, so tests running in parallel do not
+//! observe each other's accesses. The block is uninstalled when the
+//! `MockRegisters` handle is dropped.
+//!
+//! This module requires `std` and is only intended for host-side testing;
+//! never enable `register_mock` for a kernel build.
+
+extern crate std;
+
+use core::mem::size_of;
+use core::ptr;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// A single register access observed by a [`MockRegisters`] block.
+///
+/// The first member is the offset of the register from the base of the
+/// block, the second is the value read or written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read(usize, u128),
+    Write(usize, u128),
+}
+
+struct MockState {
+    base: usize,
+    size: usize,
+    values: BTreeMap<usize, u128>,
+    scripted: BTreeMap<usize, VecDeque<u128>>,
+    trace: Vec<Access>,
+}
+
+impl MockState {
+    fn contains(&self, address: usize, width: usize) -> bool {
+        address >= self.base && address + width <= self.base + self.size
+    }
+}
+
+std::thread_local! {
+    static INSTALLED: RefCell<Vec<Rc<RefCell<MockState>>>> = RefCell::new(Vec::new());
+}
+
+/// A block of mocked registers covering `size` bytes starting at `base`.
+pub struct MockRegisters {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockRegisters {
+    /// Install a mock block on the current thread.
+    ///
+    /// Panics if the block overlaps with another block already installed on
+    /// this thread.
+    pub fn new(base: usize, size: usize) -> MockRegisters {
+        let state = Rc::new(RefCell::new(MockState {
+            base: base,
+            size: size,
+            values: BTreeMap::new(),
+            scripted: BTreeMap::new(),
+            trace: Vec::new(),
+        }));
+        INSTALLED.with(|installed| {
+            let mut installed = installed.borrow_mut();
+            for other in installed.iter() {
+                let other = other.borrow();
+                if base < other.base + other.size && other.base < base + size {
+                    panic!(
+                        "mock register block {:#x}+{:#x} overlaps {:#x}+{:#x}",
+                        base, size, other.base, other.size
+                    );
+                }
+            }
+            installed.push(state.clone());
+        });
+        MockRegisters { state: state }
+    }
+
+    /// Install a mock block sized to cover the register struct `S` located
+    /// at `base`.
+    pub fn for_block<S>(base: *const S) -> MockRegisters {
+        MockRegisters::new(base as usize, size_of::<S>())
+    }
+
+    /// Offset of `register` from the base of this block.
+    ///
+    /// Useful to refer to a field of a register struct instead of repeating
+    /// its offset from the datasheet. Only the address of `register` is used;
+    /// it is never dereferenced.
+    pub fn offset_of<R>(&self, register: &R) -> usize {
+        register as *const R as usize - self.state.borrow().base
+    }
+
+    /// Queue a value to be returned by the next read of the register at
+    /// `offset`. Queued values are consumed in order; once the queue is empty
+    /// reads return the last value written or set.
+    pub fn push_read(&self, offset: usize, value: u128) {
+        self.state
+            .borrow_mut()
+            .scripted
+            .entry(offset)
+            .or_insert_with(VecDeque::new)
+            .push_back(value);
+    }
+
+    /// Set the current value of the register at `offset` without recording
+    /// an access.
+    pub fn set(&self, offset: usize, value: u128) {
+        self.state.borrow_mut().values.insert(offset, value);
+    }
+
+    /// Current value of the register at `offset`, ignoring any scripted
+    /// reads. Registers that were never written read as zero.
+    pub fn get(&self, offset: usize) -> u128 {
+        self.state
+            .borrow()
+            .values
+            .get(&offset)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// A copy of all accesses made so far, in order.
+    pub fn trace(&self) -> Vec<Access> {
+        self.state.borrow().trace.clone()
+    }
+
+    /// Return and clear the accesses made so far.
+    pub fn take_trace(&self) -> Vec<Access> {
+        core::mem::replace(&mut self.state.borrow_mut().trace, Vec::new())
+    }
+
+    /// Number of scripted reads that have not been consumed yet.
+    pub fn pending_reads(&self) -> usize {
+        self.state
+            .borrow()
+            .scripted
+            .values()
+            .map(|queue| queue.len())
+            .sum()
+    }
+}
+
+impl Drop for MockRegisters {
+    fn drop(&mut self) {
+        // The thread-local may already be gone if the handle is dropped
+        // during thread teardown, in which case there is nothing to remove.
+        let _ = INSTALLED.try_with(|installed| {
+            installed
+                .borrow_mut()
+                .retain(|state| !Rc::ptr_eq(state, &self.state));
+        });
+    }
+}
+
+fn find(address: usize, width: usize) -> Option<Rc<RefCell<MockState>>> {
+    INSTALLED
+        .try_with(|installed| {
+            installed
+                .borrow()
+                .iter()
+                .find(|state| state.borrow().contains(address, width))
+                .cloned()
+        })
+        .ok()
+        .flatten()
+}
+
+// Registers are generic over `IntLike`, which offers no conversion to a
+// common integer type. Go through the little-endian byte representation
+// instead, which works for any register width up to `u128`.
+fn to_u128<T>(value: T) -> u128 {
+    let width = size_of::<T>();
+    let mut bytes = [0u8; 16];
+    unsafe { ptr::write_unaligned(bytes.as_mut_ptr() as *mut T, value) };
+    if cfg!(target_endian = "big") {
+        bytes[..width].reverse();
+    }
+    u128::from_le_bytes(bytes)
+}
+
+fn from_u128<T>(value: u128) -> T {
+    let width = size_of::<T>();
+    let mut bytes = value.to_le_bytes();
+    if cfg!(target_endian = "big") {
+        bytes[..width].reverse();
+    }
+    unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+/// Perform a read against an installed mock block, if one covers `address`.
+pub(crate) fn read<T>(address: *const T) -> Option<T> {
+    let width = size_of::<T>();
+    find(address as usize, width).map(|state| {
+        let mut state = state.borrow_mut();
+        let offset = address as usize - state.base;
+        let scripted = state
+            .scripted
+            .get_mut(&offset)
+            .and_then(|queue| queue.pop_front());
+        let value = match scripted {
+            Some(value) => value,
+            None => state.values.get(&offset).copied().unwrap_or(0),
+        };
+        // Truncate to the register width so the trace matches what the
+        // driver actually observed.
+        let value = to_u128(from_u128::<T>(value));
+        state.trace.push(Access::Read(offset, value));
+        from_u128(value)
+    })
+}
+
+/// Perform a write against an installed mock block, if one covers `address`.
+/// Returns `false` if the access should go to memory instead.
+pub(crate) fn write<T>(address: *mut T, value: T) -> bool {
+    let width = size_of::<T>();
+    find(address as usize, width).map_or(false, |state| {
+        let mut state = state.borrow_mut();
+        let offset = address as usize - state.base;
+        let value = to_u128(value);
+        state.values.insert(offset, value);
+        state.trace.push(Access::Write(offset, value));
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, MockRegisters};
+    use crate::registers::{ReadOnly, ReadWrite, WriteOnly};
+    use crate::{register_bitfields, register_structs};
+
+    register_bitfields![u32,
+        Control [
+            ENABLE OFFSET(0) NUMBITS(1) [],
+            MODE OFFSET(4) NUMBITS(2) [
+                Idle = 0,
+                Tx = 1,
+                Rx = 2
+            ]
+        ],
+        Status [
+            READY OFFSET(0) NUMBITS(1) []
+        ]
+    ];
+
+    register_structs! {
+        Registers {
+            (0x00 => control: ReadWrite<u32, Control::Register>),
+            (0x04 => status: ReadOnly<u32, Status::Register>),
+            (0x08 => data: WriteOnly<u8>),
+            (0x09 => _reserved),
+            (0x0C => scratch: ReadWrite<u32>),
+            (0x10 => @END),
+        }
+    }
+
+    // Never dereferenced: all accesses are served by the mock.
+    const BASE: usize = 0x4000_0000;
+
+    fn registers() -> &'static Registers {
+        unsafe { &*(BASE as *const Registers) }
+    }
+
+    #[test]
+    fn test_write_and_modify_are_traced() {
+        let mock = MockRegisters::for_block(BASE as *const Registers);
+        let regs = registers();
+
+        regs.control.write(Control::ENABLE::SET);
+        regs.control.modify(Control::MODE::Rx);
+
+        assert_eq!(
+            mock.trace(),
+            [
+                Access::Write(0x00, 0x01),
+                Access::Read(0x00, 0x01),
+                Access::Write(0x00, 0x21),
+            ]
+        );
+        assert_eq!(mock.get(mock.offset_of(&regs.control)), 0x21);
+    }
+
+    #[test]
+    fn test_scripted_reads() {
+        let mock = MockRegisters::for_block(BASE as *const Registers);
+        let regs = registers();
+
+        mock.set(0x04, 0x1);
+        mock.push_read(0x04, 0x0);
+        mock.push_read(0x04, 0x0);
+        assert_eq!(mock.pending_reads(), 2);
+
+        assert!(!regs.status.is_set(Status::READY));
+        assert!(!regs.status.is_set(Status::READY));
+        assert!(regs.status.is_set(Status::READY));
+        assert_eq!(mock.pending_reads(), 0);
+        assert_eq!(mock.take_trace().len(), 3);
+        assert!(mock.trace().is_empty());
+    }
+
+    #[test]
+    fn test_narrow_register() {
+        let mock = MockRegisters::for_block(BASE as *const Registers);
+        let regs = registers();
+
+        regs.data.set(0xA5);
+        mock.push_read(0x0C, 0x1_0000_0003);
+        assert_eq!(regs.scratch.get(), 0x3);
+
+        assert_eq!(
+            mock.trace(),
+            [Access::Write(0x08, 0xA5), Access::Read(0x0C, 0x3)]
+        );
+    }
+
+    #[test]
+    fn test_uninstalled_on_drop() {
+        {
+            let _mock = MockRegisters::for_block(BASE as *const Registers);
+        }
+        // A second block over the same range can only be installed if the
+        // first one was removed.
+        let _mock = MockRegisters::for_block(BASE as *const Registers);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_overlap_panics() {
+        let _first = MockRegisters::new(BASE, 0x10);
+        let _second = MockRegisters::new(BASE + 0x8, 0x10);
+    }
+}
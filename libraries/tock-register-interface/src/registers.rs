@@ -101,6 +101,37 @@ pub trait RegisterLongName {}
 
 impl RegisterLongName for () {}
 
+/// Description of a single register field, used to decode register values
+/// when debugging.
+pub struct FieldDebugInfo<T: IntLike, R: RegisterLongName> {
+    /// Name of the field.
+    pub name: &'static str,
+    /// Location of the field in the register.
+    pub field: Field<T, R>,
+    /// Name of the enumerated value a field value corresponds to, if any.
+    pub value_name: fn(T) -> Option<&'static str>,
+}
+
+/// Names and fields of a register.
+/// Implemented inside register_bitfields! macro for each register.
+pub trait RegisterDebugInfo<T: IntLike + 'static>: RegisterLongName + Sized + 'static {
+    /// Name of the register.
+    fn name() -> &'static str;
+
+    /// All fields of the register, in declaration order.
+    fn fields() -> &'static [FieldDebugInfo<T, Self>];
+}
+
+impl<T: IntLike + 'static> RegisterDebugInfo<T> for () {
+    fn name() -> &'static str {
+        ""
+    }
+
+    fn fields() -> &'static [FieldDebugInfo<T, Self>] {
+        &[]
+    }
+}
+
 /// Conversion of raw register value into enumerated values member.
 /// Implemented inside register_bitfields! macro for each bit field.
 pub trait TryFromValue<V> {
@@ -109,6 +140,36 @@ pub trait TryFromValue<V> {
     fn try_from(v: V) -> Option<Self::EnumType>;
 }
 
+/// Volatile read of a memory-mapped register.
+///
+/// With the `register_mock` feature enabled, the read is served by a mock
+/// register block covering the address, if one is installed.
+#[inline]
+unsafe fn read_register<T: IntLike>(ptr: *const T) -> T {
+    #[cfg(feature = "register_mock")]
+    {
+        if let Some(value) = crate::mock::read(ptr) {
+            return value;
+        }
+    }
+    ::core::ptr::read_volatile(ptr)
+}
+
+/// Volatile write of a memory-mapped register.
+///
+/// With the `register_mock` feature enabled, the write is served by a mock
+/// register block covering the address, if one is installed.
+#[inline]
+unsafe fn write_register<T: IntLike>(ptr: *mut T, value: T) {
+    #[cfg(feature = "register_mock")]
+    {
+        if crate::mock::write(ptr, value) {
+            return;
+        }
+    }
+    ::core::ptr::write_volatile(ptr, value)
+}
+
 /// Read/Write registers.
 // To successfully alias this structure onto hardware registers in memory, this
 // struct must be exactly the size of the `T`.
@@ -153,13 +214,13 @@ impl<T: IntLike, R: RegisterLongName> ReadWrite<T, R> {
     #[inline]
     /// Get the raw register value
     pub fn get(&self) -> T {
-        unsafe { ::core::ptr::read_volatile(self.value.get()) }
+        unsafe { read_register(self.value.get()) }
     }
 
     #[inline]
     /// Set the raw register value
     pub fn set(&self, value: T) {
-        unsafe { ::core::ptr::write_volatile(self.value.get(), value) }
+        unsafe { write_register(self.value.get(), value) }
     }
 
     #[inline]
@@ -222,7 +283,7 @@ impl<T: IntLike, R: RegisterLongName> ReadOnly<T, R> {
     #[inline]
     /// Get the raw register value
     pub fn get(&self) -> T {
-        unsafe { ::core::ptr::read_volatile(&self.value) }
+        unsafe { read_register(&self.value) }
     }
 
     #[inline]
@@ -266,7 +327,7 @@ impl<T: IntLike, R: RegisterLongName> WriteOnly<T, R> {
     #[inline]
     /// Set the raw register value
     pub fn set(&self, value: T) {
-        unsafe { ::core::ptr::write_volatile(self.value.get(), value) }
+        unsafe { write_register(self.value.get(), value) }
     }
 
     #[inline]
@@ -280,13 +341,13 @@ impl<T: IntLike, R: RegisterLongName, W: RegisterLongName> Aliased<T, R, W> {
     #[inline]
     /// Get the raw register value
     pub fn get(&self) -> T {
-        unsafe { ::core::ptr::read_volatile(self.value.get()) }
+        unsafe { read_register(self.value.get()) }
     }
 
     #[inline]
     /// Set the raw register value
     pub fn set(&self, value: T) {
-        unsafe { ::core::ptr::write_volatile(self.value.get(), value) }
+        unsafe { write_register(self.value.get(), value) }
     }
 
     #[inline]
@@ -419,12 +480,154 @@ impl<T: IntLike, R: RegisterLongName> LocalRegisterCopy<T, R> {
     }
 }
 
+impl<T: IntLike + 'static, R: RegisterDebugInfo<T>> LocalRegisterCopy<T, R> {
+    /// Compare against another copy of the same register. The result prints
+    /// only the fields that differ between the two values when formatted
+    /// with `{:?}`.
+    #[inline]
+    pub fn diff(&self, new: LocalRegisterCopy<T, R>) -> RegisterDiff<T, R> {
+        RegisterDiff {
+            old: self.value,
+            new: new.value,
+            associated_register: PhantomData,
+        }
+    }
+}
+
+// Prints a field value by its enumerated name if it has one, or in hex
+// otherwise.
+struct FieldValueDebug<T> {
+    value: T,
+    name: Option<&'static str>,
+}
+
+impl<T: fmt::LowerHex> fmt::Debug for FieldValueDebug<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}({:#x})", name, self.value),
+            None => write!(f, "{:#x}", self.value),
+        }
+    }
+}
+
+impl<T: IntLike + 'static, R: RegisterDebugInfo<T>> FieldDebugInfo<T, R> {
+    fn decode(&self, val: T) -> FieldValueDebug<T> {
+        let value = (val & (self.field.mask << self.field.shift)) >> self.field.shift;
+        FieldValueDebug {
+            value: value,
+            name: (self.value_name)(value),
+        }
+    }
+}
+
+// Bits of `val` that are not covered by any field of the register.
+fn unnamed_bits<T: IntLike + 'static, R: RegisterDebugInfo<T>>(val: T) -> T {
+    let mut covered = T::zero();
+    for info in R::fields() {
+        covered |= info.field.mask << info.field.shift;
+    }
+    val & !covered
+}
+
+#[cfg(not(feature = "register_debug"))]
 impl<T: IntLike + fmt::Debug, R: RegisterLongName> fmt::Debug for LocalRegisterCopy<T, R> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self.value)
     }
 }
 
+/// Decodes every named field of the register. Registers without fields
+/// print their raw value.
+///
+/// Only with the `register_debug` feature, as it narrows the bounds of the
+/// impl to registers declared with `register_bitfields!`.
+#[cfg(feature = "register_debug")]
+impl<T: IntLike + fmt::Debug + fmt::LowerHex + 'static, R: RegisterDebugInfo<T>> fmt::Debug
+    for LocalRegisterCopy<T, R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if R::fields().is_empty() {
+            return write!(f, "{:?}", self.value);
+        }
+
+        let mut s = f.debug_struct(R::name());
+        for info in R::fields() {
+            s.field(info.name, &info.decode(self.value));
+        }
+        let unnamed = unnamed_bits::<T, R>(self.value);
+        if unnamed != T::zero() {
+            s.field(
+                "_",
+                &FieldValueDebug {
+                    value: unnamed,
+                    name: None,
+                },
+            );
+        }
+        s.finish()
+    }
+}
+
+/// Field-by-field difference between two values of a register.
+///
+/// Created with `LocalRegisterCopy::diff()`.
+#[derive(Copy, Clone)]
+pub struct RegisterDiff<T: IntLike, R: RegisterLongName> {
+    old: T,
+    new: T,
+    associated_register: PhantomData<R>,
+}
+
+impl<T: IntLike + 'static, R: RegisterDebugInfo<T>> RegisterDiff<T, R> {
+    /// Whether any bits changed.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.old == self.new
+    }
+}
+
+// Prints a changed value as `old -> new`.
+struct ChangeDebug<T>(FieldValueDebug<T>, FieldValueDebug<T>);
+
+impl<T: fmt::LowerHex> fmt::Debug for ChangeDebug<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} -> {:?}", self.0, self.1)
+    }
+}
+
+impl<T: IntLike + fmt::LowerHex + 'static, R: RegisterDebugInfo<T>> fmt::Debug
+    for RegisterDiff<T, R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct(R::name());
+        for info in R::fields() {
+            let old = info.decode(self.old);
+            let new = info.decode(self.new);
+            if old.value != new.value {
+                s.field(info.name, &ChangeDebug(old, new));
+            }
+        }
+        let old = unnamed_bits::<T, R>(self.old);
+        let new = unnamed_bits::<T, R>(self.new);
+        if old != new {
+            s.field(
+                "_",
+                &ChangeDebug(
+                    FieldValueDebug {
+                        value: old,
+                        name: None,
+                    },
+                    FieldValueDebug {
+                        value: new,
+                        name: None,
+                    },
+                ),
+            );
+        }
+        s.finish()
+    }
+}
+
 macro_rules! From_impl_for {
     ($type:ty) => {
         impl<R: RegisterLongName> From<LocalRegisterCopy<$type, R>> for $type {
@@ -929,5 +1132,72 @@ mod tests {
         }
     }
 
+    mod debug {
+        extern crate std;
+        use super::super::LocalRegisterCopy;
+        use crate::register_bitfields;
+        use std::format;
+
+        register_bitfields![u32,
+            Status [
+                TXCOMPLETE  OFFSET(0) NUMBITS(1) [],
+                MODE        OFFSET(4) NUMBITS(3) [
+                    FullDuplex = 0,
+                    HalfDuplex = 1,
+                    Loopback = 2
+                ],
+                ERRORCOUNT  OFFSET(8) NUMBITS(4) []
+            ]
+        ];
+
+        #[test]
+        #[cfg(not(feature = "register_debug"))]
+        fn test_debug_raw() {
+            let status = LocalRegisterCopy::<u32, Status::Register>::new(0x321);
+            assert_eq!(format!("{:?}", status), "801");
+        }
+
+        #[test]
+        #[cfg(feature = "register_debug")]
+        fn test_debug_fields() {
+            let status = LocalRegisterCopy::<u32, Status::Register>::new(0x321);
+            assert_eq!(
+                format!("{:?}", status),
+                "Status { TXCOMPLETE: 0x1, MODE: Loopback(0x2), ERRORCOUNT: 0x3 }"
+            );
+
+            // Values without an enumerated name and bits outside of any field
+            // are printed in hex.
+            let status = LocalRegisterCopy::<u32, Status::Register>::new(0x8000_0070);
+            assert_eq!(
+                format!("{:?}", status),
+                "Status { TXCOMPLETE: 0x0, MODE: 0x7, ERRORCOUNT: 0x0, _: 0x80000000 }"
+            );
+        }
+
+        #[test]
+        fn test_debug_no_fields() {
+            let raw = LocalRegisterCopy::<u32>::new(42);
+            assert_eq!(format!("{:?}", raw), "42");
+        }
+
+        #[test]
+        fn test_diff() {
+            let old = LocalRegisterCopy::<u32, Status::Register>::new(0x301);
+            let new = LocalRegisterCopy::<u32, Status::Register>::new(0x1311);
+
+            let diff = old.diff(new);
+            assert!(!diff.is_empty());
+            assert_eq!(
+                format!("{:?}", diff),
+                "Status { MODE: FullDuplex(0x0) -> HalfDuplex(0x1), _: 0x0 -> 0x1000 }"
+            );
+
+            let diff = old.diff(old);
+            assert!(diff.is_empty());
+            assert_eq!(format!("{:?}", diff), "Status");
+        }
+    }
+
     // TODO: More unit tests here.
 }